@@ -0,0 +1,647 @@
+// Recent activity feed
+//
+// A consolidated, persisted list of what this node has been doing: files
+// published, downloads finished or failed, peers served, payments sent and
+// received, and relay reservations/circuits. It backs the "recent activity"
+// view on the home screen.
+//
+// Like the transfer log, the feed is fed from the unified event bus and stored
+// in SQLite, newest first. Serving a file and sending a payment are not
+// reported by any service on their own, so those paths publish an
+// `ActivityEvent` on the bus through `publish`. Only the newest `MAX_ENTRIES`
+// rows are kept.
+//
+// Downloads are recognised the way the transfer log recognises them, so those
+// reported only by the file transfer service (headless) show up too. A
+// download reported by more than one service keeps a single row.
+
+use crate::dht::DhtEvent;
+use crate::event_bus::{self, EventEnvelope, EventPayload, EventSeverity, EventSource};
+use crate::transfer_log::{self, TransferDirection, TransferOutcome};
+use directories::ProjectDirs;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Upper bound for a single page of results
+pub const MAX_PAGE_SIZE: u32 = 200;
+/// Rows kept; older activity is dropped as new rows arrive
+pub const MAX_ENTRIES: u32 = 10_000;
+
+const DEFAULT_PAGE_SIZE: u32 = 20;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ActivityKind {
+    Upload,
+    Download,
+    DownloadFailed,
+    PeerServed,
+    PaymentSent,
+    PaymentReceived,
+    Relay,
+}
+
+impl ActivityKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ActivityKind::Upload => "upload",
+            ActivityKind::Download => "download",
+            ActivityKind::DownloadFailed => "download_failed",
+            ActivityKind::PeerServed => "peer_served",
+            ActivityKind::PaymentSent => "payment_sent",
+            ActivityKind::PaymentReceived => "payment_received",
+            ActivityKind::Relay => "relay",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "upload" => Some(ActivityKind::Upload),
+            "download" => Some(ActivityKind::Download),
+            "download_failed" => Some(ActivityKind::DownloadFailed),
+            "peer_served" => Some(ActivityKind::PeerServed),
+            "payment_sent" => Some(ActivityKind::PaymentSent),
+            "payment_received" => Some(ActivityKind::PaymentReceived),
+            "relay" => Some(ActivityKind::Relay),
+            _ => None,
+        }
+    }
+}
+
+/// Activity that no service reports on the bus by itself
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ActivityEvent {
+    /// A file was sent to a peer in full
+    PeerServed {
+        peer_id: String,
+        file_hash: String,
+        file_name: String,
+        bytes: u64,
+    },
+    PaymentSent {
+        peer_id: String,
+        file_hash: String,
+        file_name: String,
+        amount: f64,
+        transaction_hash: String,
+        /// Download the payment was made for, when known
+        #[serde(skip_serializing_if = "Option::is_none")]
+        transfer_id: Option<String>,
+    },
+}
+
+/// Publish an activity event on the unified event bus, correlated with its
+/// transfer when one is known and with the file otherwise
+pub fn publish(event: ActivityEvent) -> u64 {
+    let correlation_id = match &event {
+        ActivityEvent::PeerServed { file_hash, .. } => file_hash.clone(),
+        ActivityEvent::PaymentSent {
+            file_hash,
+            transfer_id,
+            ..
+        } => transfer_id.clone().unwrap_or_else(|| file_hash.clone()),
+    };
+    event_bus::global().publish(
+        EventSource::Activity,
+        EventSeverity::Info,
+        Some(correlation_id),
+        EventPayload::Activity(event),
+    )
+}
+
+/// One item of the feed
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ActivityEntry {
+    /// Row ID assigned by the database (0 before insertion)
+    #[serde(default)]
+    pub id: i64,
+    pub kind: ActivityKind,
+    /// Download the entry belongs to, when known
+    #[serde(default)]
+    pub transfer_id: Option<String>,
+    pub file_hash: Option<String>,
+    pub file_name: Option<String>,
+    /// Remote peer involved, if any
+    pub peer_id: Option<String>,
+    pub bytes: u64,
+    /// Payment amount
+    pub amount: Option<f64>,
+    /// Failure reason, relay status and similar
+    pub detail: Option<String>,
+    /// Unix timestamp in milliseconds
+    pub occurred_at: u64,
+}
+
+impl ActivityEntry {
+    fn new(kind: ActivityKind, occurred_at: u64) -> Self {
+        Self {
+            id: 0,
+            kind,
+            transfer_id: None,
+            file_hash: None,
+            file_name: None,
+            peer_id: None,
+            bytes: 0,
+            amount: None,
+            detail: None,
+            occurred_at,
+        }
+    }
+}
+
+/// Filters for `ActivityFeed::query`; all fields are optional
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ActivityQuery {
+    /// Only these kinds; all kinds when empty
+    pub kinds: Vec<ActivityKind>,
+    /// Inclusive lower bound, Unix ms
+    pub since: Option<u64>,
+    /// Exclusive upper bound, Unix ms
+    pub until: Option<u64>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+/// A page of results, newest first
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActivityPage {
+    pub entries: Vec<ActivityEntry>,
+    /// Number of rows matching the filters, ignoring pagination
+    pub total: u64,
+    pub limit: u32,
+    pub offset: u32,
+}
+
+pub struct ActivityFeed {
+    conn: Mutex<Connection>,
+}
+
+impl ActivityFeed {
+    /// Open (or create) the feed at `path`
+    pub fn open(path: &Path) -> Result<Self, String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create activity feed directory: {}", e))?;
+        }
+        let conn =
+            Connection::open(path).map_err(|e| format!("Failed to open activity feed: {}", e))?;
+        Self::with_connection(conn)
+    }
+
+    /// Open the feed in the application data directory
+    pub fn open_default() -> Result<Self, String> {
+        let proj_dirs = ProjectDirs::from("com", "chiral-network", "chiral-network")
+            .ok_or("Failed to get project directories")?;
+        Self::open(&proj_dirs.data_dir().join("activity.sqlite3"))
+    }
+
+    pub fn open_in_memory() -> Result<Self, String> {
+        let conn = Connection::open_in_memory()
+            .map_err(|e| format!("Failed to open activity feed: {}", e))?;
+        Self::with_connection(conn)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self, String> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS activity (
+                 id          INTEGER PRIMARY KEY AUTOINCREMENT,
+                 kind        TEXT NOT NULL,
+                 file_hash   TEXT,
+                 file_name   TEXT,
+                 peer_id     TEXT,
+                 bytes       INTEGER NOT NULL DEFAULT 0,
+                 amount      REAL,
+                 detail      TEXT,
+                 occurred_at INTEGER NOT NULL,
+                 transfer_id TEXT
+             );
+             CREATE INDEX IF NOT EXISTS idx_activity_occurred_at
+                 ON activity (occurred_at);",
+        )
+        .map_err(|e| format!("Failed to initialize activity feed schema: {}", e))?;
+        // Feeds created before downloads were merged lack the column
+        let has_transfer_id = conn
+            .prepare("SELECT transfer_id FROM activity LIMIT 0")
+            .is_ok();
+        if !has_transfer_id {
+            conn.execute("ALTER TABLE activity ADD COLUMN transfer_id TEXT", [])
+                .map_err(|e| format!("Failed to migrate activity feed schema: {}", e))?;
+        }
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_activity_transfer_id ON activity (transfer_id)",
+            [],
+        )
+        .map_err(|e| format!("Failed to initialize activity feed schema: {}", e))?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Append an entry, drop rows beyond `MAX_ENTRIES`, and return its row ID.
+    /// An entry of a kind already recorded for its transfer is merged into
+    /// that row instead, filling in what it left empty.
+    pub fn record(&self, entry: &ActivityEntry) -> Result<i64, String> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "Activity feed lock poisoned".to_string())?;
+        if let Some(transfer_id) = &entry.transfer_id {
+            let existing: Option<i64> = conn
+                .query_row(
+                    "SELECT id FROM activity WHERE transfer_id = ?1 AND kind = ?2",
+                    params![transfer_id, entry.kind.as_str()],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(|e| format!("Failed to read activity entry: {}", e))?;
+            if let Some(id) = existing {
+                conn.execute(
+                    "UPDATE activity SET
+                         file_hash = COALESCE(file_hash, ?2),
+                         file_name = COALESCE(file_name, ?3),
+                         peer_id = COALESCE(peer_id, ?4),
+                         bytes = MAX(bytes, ?5),
+                         detail = COALESCE(detail, ?6)
+                     WHERE id = ?1",
+                    params![
+                        id,
+                        entry.file_hash,
+                        entry.file_name,
+                        entry.peer_id,
+                        entry.bytes as i64,
+                        entry.detail,
+                    ],
+                )
+                .map_err(|e| format!("Failed to write activity entry: {}", e))?;
+                return Ok(id);
+            }
+        }
+        conn.execute(
+            "INSERT INTO activity
+                 (kind, file_hash, file_name, peer_id, bytes, amount, detail, occurred_at, transfer_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                entry.kind.as_str(),
+                entry.file_hash,
+                entry.file_name,
+                entry.peer_id,
+                entry.bytes as i64,
+                entry.amount,
+                entry.detail,
+                entry.occurred_at as i64,
+                entry.transfer_id,
+            ],
+        )
+        .map_err(|e| format!("Failed to write activity entry: {}", e))?;
+        let id = conn.last_insert_rowid();
+        conn.execute(
+            "DELETE FROM activity WHERE id <= ?1",
+            params![id - MAX_ENTRIES as i64],
+        )
+        .map_err(|e| format!("Failed to trim activity feed: {}", e))?;
+        Ok(id)
+    }
+
+    /// Filtered, paginated query, newest entries first
+    pub fn query(&self, query: &ActivityQuery) -> Result<ActivityPage, String> {
+        let mut clauses: Vec<String> = Vec::new();
+        let mut values: Vec<rusqlite::types::Value> = Vec::new();
+
+        if !query.kinds.is_empty() {
+            let placeholders = vec!["?"; query.kinds.len()].join(", ");
+            clauses.push(format!("kind IN ({})", placeholders));
+            values.extend(query.kinds.iter().map(|k| k.as_str().to_string().into()));
+        }
+        if let Some(since) = query.since {
+            clauses.push("occurred_at >= ?".to_string());
+            values.push((since as i64).into());
+        }
+        if let Some(until) = query.until {
+            clauses.push("occurred_at < ?".to_string());
+            values.push((until as i64).into());
+        }
+
+        let where_sql = if clauses.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", clauses.join(" AND "))
+        };
+        let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+        let offset = query.offset.unwrap_or(0);
+
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "Activity feed lock poisoned".to_string())?;
+
+        let total: i64 = conn
+            .query_row(
+                &format!("SELECT COUNT(*) FROM activity{}", where_sql),
+                params_from_iter(values.iter()),
+                |row| row.get(0),
+            )
+            .map_err(|e| format!("Failed to count activity entries: {}", e))?;
+
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT id, kind, file_hash, file_name, peer_id, bytes, amount, detail, occurred_at, transfer_id
+                 FROM activity{}
+                 ORDER BY occurred_at DESC, id DESC
+                 LIMIT {} OFFSET {}",
+                where_sql, limit, offset
+            ))
+            .map_err(|e| format!("Failed to prepare activity query: {}", e))?;
+
+        let entries = stmt
+            .query_map(params_from_iter(values.iter()), |row| {
+                let kind: String = row.get(1)?;
+                Ok(ActivityEntry {
+                    id: row.get(0)?,
+                    kind: ActivityKind::parse(&kind).unwrap_or(ActivityKind::Relay),
+                    transfer_id: row.get(9)?,
+                    file_hash: row.get(2)?,
+                    file_name: row.get(3)?,
+                    peer_id: row.get(4)?,
+                    bytes: row.get::<_, i64>(5)? as u64,
+                    amount: row.get(6)?,
+                    detail: row.get(7)?,
+                    occurred_at: row.get::<_, i64>(8)? as u64,
+                })
+            })
+            .map_err(|e| format!("Failed to query activity feed: {}", e))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Failed to read activity row: {}", e))?;
+
+        Ok(ActivityPage {
+            entries,
+            total: total as u64,
+            limit,
+            offset,
+        })
+    }
+}
+
+/// Map a bus envelope to a feed entry if it is activity worth showing
+pub fn entry_from_envelope(envelope: &EventEnvelope) -> Option<ActivityEntry> {
+    let at = envelope.timestamp;
+    let entry = match &envelope.payload {
+        EventPayload::Dht(DhtEvent::PublishedFile(metadata)) => ActivityEntry {
+            file_hash: Some(metadata.merkle_root.clone()),
+            file_name: Some(metadata.file_name.clone()),
+            bytes: metadata.file_size,
+            ..ActivityEntry::new(ActivityKind::Upload, at)
+        },
+        EventPayload::Activity(ActivityEvent::PeerServed {
+            peer_id,
+            file_hash,
+            file_name,
+            bytes,
+        }) => ActivityEntry {
+            file_hash: Some(file_hash.clone()),
+            file_name: Some(file_name.clone()),
+            peer_id: Some(peer_id.clone()),
+            bytes: *bytes,
+            ..ActivityEntry::new(ActivityKind::PeerServed, at)
+        },
+        EventPayload::Activity(ActivityEvent::PaymentSent {
+            peer_id,
+            file_hash,
+            file_name,
+            amount,
+            transaction_hash,
+            ..
+        }) => ActivityEntry {
+            file_hash: Some(file_hash.clone()),
+            file_name: Some(file_name.clone()),
+            peer_id: Some(peer_id.clone()),
+            amount: Some(*amount),
+            detail: Some(transaction_hash.clone()),
+            ..ActivityEntry::new(ActivityKind::PaymentSent, at)
+        },
+        EventPayload::Dht(DhtEvent::PaymentNotificationReceived { from_peer, payload }) => {
+            let text = |key: &str| payload.get(key).and_then(|v| v.as_str()).map(String::from);
+            ActivityEntry {
+                file_hash: text("file_hash"),
+                file_name: text("file_name"),
+                peer_id: Some(from_peer.clone()),
+                amount: payload.get("amount").and_then(|v| v.as_f64()),
+                detail: text("transaction_hash"),
+                ..ActivityEntry::new(ActivityKind::PaymentReceived, at)
+            }
+        }
+        EventPayload::Dht(DhtEvent::ProxyStatus {
+            address,
+            status,
+            error,
+            ..
+        }) if envelope.source == EventSource::Relay => ActivityEntry {
+            peer_id: Some(address.clone()),
+            detail: Some(match error {
+                Some(error) => format!("{}: {}", status, error),
+                None => status.clone(),
+            }),
+            ..ActivityEntry::new(ActivityKind::Relay, at)
+        },
+        EventPayload::Dht(DhtEvent::ReputationEvent {
+            peer_id,
+            event_type,
+            ..
+        }) if envelope.source == EventSource::Relay => ActivityEntry {
+            peer_id: Some(peer_id.clone()),
+            detail: Some(event_type.clone()),
+            ..ActivityEntry::new(ActivityKind::Relay, at)
+        },
+        _ => return download_entry(envelope),
+    };
+    Some(entry)
+}
+
+/// A finished or failed download, as the transfer log sees it
+fn download_entry(envelope: &EventEnvelope) -> Option<ActivityEntry> {
+    let logged = transfer_log::entry_from_envelope(envelope)?;
+    let kind = match (logged.direction, logged.outcome) {
+        (TransferDirection::Download, TransferOutcome::Completed) => ActivityKind::Download,
+        (TransferDirection::Download, TransferOutcome::Failed) => ActivityKind::DownloadFailed,
+        _ => return None,
+    };
+    Some(ActivityEntry {
+        transfer_id: logged.transfer_id,
+        file_hash: logged.file_hash,
+        file_name: logged.file_name,
+        peer_id: logged.peer,
+        bytes: logged.bytes,
+        detail: logged.error,
+        ..ActivityEntry::new(kind, envelope.timestamp)
+    })
+}
+
+/// Record activity from the unified event bus into `feed` until the bus closes
+pub async fn run_recorder(feed: Arc<ActivityFeed>) {
+    event_bus::record_events("Activity feed", entry_from_envelope, move |entry| {
+        feed.record(entry)
+    })
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn envelope(source: EventSource, payload: EventPayload, timestamp: u64) -> EventEnvelope {
+        EventEnvelope {
+            id: timestamp,
+            source,
+            severity: EventSeverity::Info,
+            correlation_id: None,
+            timestamp,
+            error: None,
+            payload,
+        }
+    }
+
+    #[test]
+    fn maps_bus_events_and_paginates_by_kind() {
+        let served = envelope(
+            EventSource::Activity,
+            EventPayload::Activity(ActivityEvent::PeerServed {
+                peer_id: "peer-a".into(),
+                file_hash: "hash-1".into(),
+                file_name: "a.bin".into(),
+                bytes: 42,
+            }),
+            1,
+        );
+        let entry = entry_from_envelope(&served).unwrap();
+        assert_eq!(entry.kind, ActivityKind::PeerServed);
+        assert_eq!((entry.peer_id.as_deref(), entry.bytes), (Some("peer-a"), 42));
+
+        let relay_status = DhtEvent::ProxyStatus {
+            id: "r".into(),
+            address: "/ip4/1.2.3.4/tcp/1".into(),
+            status: "relay_reservation_accepted".into(),
+            latency_ms: None,
+            error: None,
+        };
+        let relay = envelope(EventSource::Relay, EventPayload::Dht(relay_status.clone()), 2);
+        assert_eq!(entry_from_envelope(&relay).unwrap().kind, ActivityKind::Relay);
+        // Plain proxy status updates are not relay activity
+        let proxy = envelope(EventSource::Dht, EventPayload::Dht(relay_status), 3);
+        assert!(entry_from_envelope(&proxy).is_none());
+
+        let feed = ActivityFeed::open_in_memory().unwrap();
+        for at in 1..=6 {
+            let kind = if at % 2 == 0 {
+                ActivityKind::PeerServed
+            } else {
+                ActivityKind::Download
+            };
+            feed.record(&ActivityEntry::new(kind, at)).unwrap();
+        }
+        feed.record(&ActivityEntry::new(ActivityKind::Relay, 7)).unwrap();
+
+        let page = feed
+            .query(&ActivityQuery {
+                kinds: vec![ActivityKind::PeerServed, ActivityKind::Relay],
+                limit: Some(2),
+                offset: Some(1),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(page.total, 4);
+        assert_eq!(
+            page.entries.iter().map(|e| e.occurred_at).collect::<Vec<_>>(),
+            vec![6, 4]
+        );
+        assert_eq!(feed.query(&ActivityQuery::default()).unwrap().total, 7);
+    }
+
+    #[test]
+    fn downloads_reported_only_by_the_transfer_service_are_recorded_once() {
+        use crate::file_transfer::{progress, FileTransferEvent};
+        use crate::transfer_events::{TransferCompletedEvent, TransferEvent};
+
+        let downloaded = envelope(
+            EventSource::FileTransfer,
+            EventPayload::FileTransfer(FileTransferEvent::FileDownloaded {
+                transfer_id: "t-1".into(),
+                file_path: "/missing/out/a.bin".into(),
+            }),
+            1,
+        );
+        let completed = envelope(
+            EventSource::Transfer,
+            EventPayload::Transfer(TransferEvent::Completed(TransferCompletedEvent {
+                transfer_id: "t-1".into(),
+                file_hash: "hash-1".into(),
+                file_name: "/missing/out/a.bin".into(),
+                file_size: 42,
+                output_path: "/missing/out/a.bin".into(),
+                completed_at: 2,
+                duration_seconds: 1,
+                average_speed_bps: 0.0,
+                total_chunks: 1,
+                sources_used: Vec::new(),
+            })),
+            2,
+        );
+        let upload_failed = envelope(
+            EventSource::FileTransfer,
+            EventPayload::FileTransfer(FileTransferEvent::Error {
+                transfer_id: "t-2".into(),
+                direction: progress::TransferDirection::Upload,
+                code: crate::error_codes::ErrorCode::WriteFailed,
+                message: "disk full".into(),
+            }),
+            3,
+        );
+        assert!(entry_from_envelope(&upload_failed).is_none());
+
+        let feed = ActivityFeed::open_in_memory().unwrap();
+        for envelope in [&downloaded, &completed] {
+            feed.record(&entry_from_envelope(envelope).unwrap())
+                .unwrap();
+        }
+        let page = feed.query(&ActivityQuery::default()).unwrap();
+        assert_eq!(page.total, 1);
+        let entry = &page.entries[0];
+        assert_eq!(entry.kind, ActivityKind::Download);
+        assert_eq!(entry.file_hash.as_deref(), Some("hash-1"));
+        assert_eq!(
+            (entry.file_name.as_deref(), entry.bytes),
+            (Some("a.bin"), 42)
+        );
+    }
+
+    #[test]
+    fn payments_are_correlated_with_their_transfer() {
+        let mut rx = event_bus::global().subscribe();
+        let payment = |transfer_id: Option<&str>| ActivityEvent::PaymentSent {
+            peer_id: "seeder".into(),
+            file_hash: "hash-pay".into(),
+            file_name: "a.bin".into(),
+            amount: 1.5,
+            transaction_hash: "0xabc".into(),
+            transfer_id: transfer_id.map(String::from),
+        };
+        let with_transfer = publish(payment(Some("transfer-7")));
+        let without_transfer = publish(payment(None));
+
+        let mut correlations = Vec::new();
+        while let Ok(envelope) = rx.try_recv() {
+            if envelope.id == with_transfer || envelope.id == without_transfer {
+                correlations.push(envelope.correlation_id);
+            }
+        }
+        assert_eq!(
+            correlations,
+            vec![Some("transfer-7".to_string()), Some("hash-pay".to_string())]
+        );
+    }
+}
@@ -0,0 +1,423 @@
+// Node health alerts
+//
+// Users configure thresholds for conditions that need attention (low disk
+// space, a high download failure rate, no known relays, no peers for a while).
+// A monitor samples the node periodically and publishes an `Alert` event on
+// the unified event bus when a condition trips and again when it clears, so
+// each alert is reported once per episode instead of on every sample.
+
+use crate::event_bus::{self, EventPayload, EventSeverity, EventSource};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{info, warn};
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(30);
+
+const BYTES_PER_GB: f64 = 1024.0 * 1024.0 * 1024.0;
+
+/// User-configurable alert thresholds; `None` disables the alert
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AlertThresholds {
+    /// Alert when free space in the download directory drops below this
+    pub min_free_disk_gb: Option<f64>,
+    /// Alert when the share of downloads that failed for good in the last
+    /// minute exceeds this percentage
+    pub max_download_failure_rate_percent: Option<f64>,
+    /// Downloads that must finish in the window before the failure rate is
+    /// considered
+    pub min_download_attempts: u64,
+    /// Alert when the node knows no relay-capable peers
+    pub alert_on_no_relays: bool,
+    /// Alert when the node has had no connected peers for this long
+    pub no_peers_minutes: Option<u64>,
+}
+
+impl Default for AlertThresholds {
+    fn default() -> Self {
+        Self {
+            min_free_disk_gb: Some(5.0),
+            max_download_failure_rate_percent: Some(50.0),
+            min_download_attempts: 5,
+            alert_on_no_relays: true,
+            no_peers_minutes: Some(5),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertKind {
+    LowDiskSpace,
+    HighDownloadFailureRate,
+    NoRelays,
+    NoPeers,
+}
+
+impl AlertKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertKind::LowDiskSpace => "low_disk_space",
+            AlertKind::HighDownloadFailureRate => "high_download_failure_rate",
+            AlertKind::NoRelays => "no_relays",
+            AlertKind::NoPeers => "no_peers",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertState {
+    Triggered,
+    Cleared,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertEvent {
+    pub kind: AlertKind,
+    pub state: AlertState,
+    pub message: String,
+    /// Observed value at the time of the transition
+    pub value: f64,
+    pub threshold: f64,
+    /// Unix timestamp (seconds)
+    pub timestamp: u64,
+}
+
+/// Node state observed by one monitor tick. `None` means the value could not
+/// be read (e.g. the DHT is not running); alerts depending on it keep their
+/// current state.
+#[derive(Debug, Clone, Default)]
+pub struct HealthSample {
+    pub free_disk_bytes: Option<u64>,
+    /// Downloads that finished, one per transfer however often it retried
+    pub downloads_finished: u64,
+    /// Of which failed after their last retry
+    pub download_failures: u64,
+    pub relay_count: Option<usize>,
+    pub peer_count: Option<usize>,
+}
+
+#[derive(Default)]
+struct MonitorState {
+    active: HashMap<AlertKind, AlertEvent>,
+    no_peers_since: Option<u64>,
+}
+
+/// Evaluated condition for one alert kind
+struct Condition {
+    kind: AlertKind,
+    tripped: bool,
+    value: f64,
+    threshold: f64,
+    message: String,
+}
+
+pub struct AlertMonitor {
+    thresholds: Mutex<AlertThresholds>,
+    state: Mutex<MonitorState>,
+    persist_path: Option<PathBuf>,
+}
+
+impl AlertMonitor {
+    pub fn new(thresholds: AlertThresholds) -> Self {
+        Self {
+            thresholds: Mutex::new(thresholds),
+            state: Mutex::new(MonitorState::default()),
+            persist_path: None,
+        }
+    }
+
+    /// Monitor whose thresholds are loaded from and saved to `path`
+    pub fn with_persistence(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let thresholds = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|raw| serde_json::from_str::<AlertThresholds>(&raw).ok())
+            .unwrap_or_default();
+        Self {
+            persist_path: Some(path),
+            ..Self::new(thresholds)
+        }
+    }
+
+    pub fn thresholds(&self) -> AlertThresholds {
+        self.thresholds
+            .lock()
+            .map(|t| t.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn set_thresholds(&self, thresholds: AlertThresholds) -> Result<(), String> {
+        if let Some(path) = &self.persist_path {
+            let json = serde_json::to_string_pretty(&thresholds)
+                .map_err(|e| format!("Failed to serialize alert thresholds: {}", e))?;
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| format!("Failed to create config directory: {}", e))?;
+            }
+            std::fs::write(path, json)
+                .map_err(|e| format!("Failed to save alert thresholds: {}", e))?;
+        }
+        let mut current = self
+            .thresholds
+            .lock()
+            .map_err(|_| "Alert thresholds lock poisoned".to_string())?;
+        *current = thresholds;
+        Ok(())
+    }
+
+    /// Alerts currently in the triggered state
+    pub fn active_alerts(&self) -> Vec<AlertEvent> {
+        let state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return Vec::new(),
+        };
+        let mut alerts: Vec<AlertEvent> = state.active.values().cloned().collect();
+        alerts.sort_by_key(|a| a.timestamp);
+        alerts
+    }
+
+    /// Evaluate `sample` taken at `now` (Unix seconds) and return the alerts
+    /// that changed state
+    pub fn evaluate(&self, sample: &HealthSample, now: u64) -> Vec<AlertEvent> {
+        let thresholds = self.thresholds();
+        let Ok(mut state) = self.state.lock() else {
+            return Vec::new();
+        };
+
+        match sample.peer_count {
+            Some(0) => {
+                state.no_peers_since.get_or_insert(now);
+            }
+            Some(_) => state.no_peers_since = None,
+            None => {}
+        }
+
+        let mut conditions = Vec::new();
+        let mut disabled = Vec::new();
+
+        match (thresholds.min_free_disk_gb, sample.free_disk_bytes) {
+            (Some(min_gb), Some(free)) => {
+                let free_gb = free as f64 / BYTES_PER_GB;
+                conditions.push(Condition {
+                    kind: AlertKind::LowDiskSpace,
+                    tripped: free_gb < min_gb,
+                    value: free_gb,
+                    threshold: min_gb,
+                    message: format!("Free disk space is {:.1} GB (minimum {:.1} GB)", free_gb, min_gb),
+                });
+            }
+            (None, _) => disabled.push(AlertKind::LowDiskSpace),
+            _ => {}
+        }
+
+        match thresholds.max_download_failure_rate_percent {
+            Some(max_rate) => {
+                let rate = if sample.downloads_finished == 0 {
+                    0.0
+                } else {
+                    sample.download_failures as f64 * 100.0 / sample.downloads_finished as f64
+                };
+                conditions.push(Condition {
+                    kind: AlertKind::HighDownloadFailureRate,
+                    tripped: sample.downloads_finished >= thresholds.min_download_attempts
+                        && rate > max_rate,
+                    value: rate,
+                    threshold: max_rate,
+                    message: format!(
+                        "{:.0}% of downloads failed in the last minute (limit {:.0}%)",
+                        rate, max_rate
+                    ),
+                });
+            }
+            None => disabled.push(AlertKind::HighDownloadFailureRate),
+        }
+
+        match (thresholds.alert_on_no_relays, sample.relay_count) {
+            (true, Some(relays)) => conditions.push(Condition {
+                kind: AlertKind::NoRelays,
+                tripped: relays == 0,
+                value: relays as f64,
+                threshold: 0.0,
+                message: "No relay nodes are known".to_string(),
+            }),
+            (false, _) => disabled.push(AlertKind::NoRelays),
+            _ => {}
+        }
+
+        match (thresholds.no_peers_minutes, sample.peer_count) {
+            (Some(minutes), Some(_)) => {
+                let without_peers_secs = state
+                    .no_peers_since
+                    .map(|since| now.saturating_sub(since))
+                    .unwrap_or(0);
+                conditions.push(Condition {
+                    kind: AlertKind::NoPeers,
+                    tripped: state.no_peers_since.is_some() && without_peers_secs >= minutes * 60,
+                    value: (without_peers_secs / 60) as f64,
+                    threshold: minutes as f64,
+                    message: format!("No connected peers for {} minutes", without_peers_secs / 60),
+                });
+            }
+            (None, _) => disabled.push(AlertKind::NoPeers),
+            _ => {}
+        }
+
+        let mut changed = Vec::new();
+        for condition in conditions {
+            let active = state.active.contains_key(&condition.kind);
+            if condition.tripped == active {
+                continue;
+            }
+            let event = AlertEvent {
+                kind: condition.kind,
+                state: if condition.tripped {
+                    AlertState::Triggered
+                } else {
+                    AlertState::Cleared
+                },
+                message: condition.message,
+                value: condition.value,
+                threshold: condition.threshold,
+                timestamp: now,
+            };
+            if condition.tripped {
+                state.active.insert(condition.kind, event.clone());
+            } else {
+                state.active.remove(&condition.kind);
+            }
+            changed.push(event);
+        }
+
+        // Disabling an alert clears it if it was active
+        for kind in disabled {
+            if let Some(previous) = state.active.remove(&kind) {
+                changed.push(AlertEvent {
+                    state: AlertState::Cleared,
+                    message: format!("{} alert disabled", kind.as_str()),
+                    timestamp: now,
+                    ..previous
+                });
+            }
+        }
+
+        changed
+    }
+
+    /// Sample the node every 30 seconds with `sampler` and publish transitions
+    /// on the event bus
+    pub fn spawn_monitor<F, Fut>(self: Arc<Self>, sampler: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = HealthSample> + Send,
+    {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+            loop {
+                interval.tick().await;
+                let sample = sampler().await;
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                for alert in self.evaluate(&sample, now) {
+                    match alert.state {
+                        AlertState::Triggered => warn!("Alert triggered: {}", alert.message),
+                        AlertState::Cleared => info!("Alert cleared: {}", alert.kind.as_str()),
+                    }
+                    publish(alert);
+                }
+            }
+        });
+    }
+}
+
+/// Publish an alert transition on the unified event bus
+pub fn publish(alert: AlertEvent) -> u64 {
+    let severity = match alert.state {
+        AlertState::Triggered => EventSeverity::Warning,
+        AlertState::Cleared => EventSeverity::Info,
+    };
+    event_bus::global().publish(
+        EventSource::Alert,
+        severity,
+        Some(alert.kind.as_str().to_string()),
+        EventPayload::Alert(alert),
+    )
+}
+
+/// Free space available on the filesystem holding `path`
+pub fn free_disk_bytes(path: &Path) -> Option<u64> {
+    // The directory may not exist yet; fall back to the nearest ancestor
+    let existing = path.ancestors().find(|p| p.exists())?;
+    fs2::available_space(existing).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alerts_fire_once_per_episode_and_clear() {
+        let monitor = AlertMonitor::new(AlertThresholds::default());
+        let healthy = HealthSample {
+            free_disk_bytes: Some(100 * BYTES_PER_GB as u64),
+            downloads_finished: 10,
+            download_failures: 1,
+            relay_count: Some(2),
+            peer_count: Some(3),
+        };
+        assert!(monitor.evaluate(&healthy, 1_000).is_empty());
+
+        let degraded = HealthSample {
+            free_disk_bytes: Some(BYTES_PER_GB as u64),
+            download_failures: 8,
+            relay_count: Some(0),
+            peer_count: Some(0),
+            ..healthy.clone()
+        };
+        let tripped = monitor.evaluate(&degraded, 1_030);
+        let mut kinds: Vec<_> = tripped.iter().map(|a| a.kind.as_str()).collect();
+        kinds.sort();
+        assert_eq!(kinds, vec!["high_download_failure_rate", "low_disk_space", "no_relays"]);
+        assert!(tripped.iter().all(|a| a.state == AlertState::Triggered));
+
+        // Same condition again: no duplicate events; no peers for 5 minutes trips
+        assert!(monitor.evaluate(&degraded, 1_100).is_empty());
+        let no_peers = monitor.evaluate(&degraded, 1_030 + 5 * 60);
+        assert_eq!(no_peers.len(), 1);
+        assert_eq!(no_peers[0].kind, AlertKind::NoPeers);
+        assert_eq!(monitor.active_alerts().len(), 4);
+
+        let cleared = monitor.evaluate(&healthy, 2_000);
+        assert_eq!(cleared.len(), 4);
+        assert!(cleared.iter().all(|a| a.state == AlertState::Cleared));
+        assert!(monitor.active_alerts().is_empty());
+    }
+
+    #[test]
+    fn disabling_a_threshold_clears_its_alert() {
+        let monitor = AlertMonitor::new(AlertThresholds::default());
+        let sample = HealthSample {
+            relay_count: Some(0),
+            ..HealthSample::default()
+        };
+        assert_eq!(monitor.evaluate(&sample, 10).len(), 1);
+
+        monitor
+            .set_thresholds(AlertThresholds {
+                alert_on_no_relays: false,
+                ..AlertThresholds::default()
+            })
+            .unwrap();
+        let cleared = monitor.evaluate(&sample, 20);
+        assert_eq!(cleared.len(), 1);
+        assert_eq!(cleared[0].state, AlertState::Cleared);
+    }
+}
@@ -102,6 +102,9 @@ struct Inner {
     upload_bytes_used: u64,
     download_bytes_used: u64,
     stats_last_reset: Instant,
+    // Monotonic counters (never reset) for time-series sampling
+    upload_bytes_total: u64,
+    download_bytes_total: u64,
 }
 
 impl BandwidthController {
@@ -114,6 +117,8 @@ impl BandwidthController {
                 upload_bytes_used: 0,
                 download_bytes_used: 0,
                 stats_last_reset: Instant::now(),
+                upload_bytes_total: 0,
+                download_bytes_total: 0,
             }),
             event_bus: None,
             app_handle: Mutex::new(None),
@@ -129,6 +134,8 @@ impl BandwidthController {
                 upload_bytes_used: 0,
                 download_bytes_used: 0,
                 stats_last_reset: Instant::now(),
+                upload_bytes_total: 0,
+                download_bytes_total: 0,
             }),
             event_bus: Some(event_bus),
             app_handle: Mutex::new(None),
@@ -252,8 +259,14 @@ impl BandwidthController {
         {
             let mut inner = self.inner.lock().await;
             match direction {
-                Direction::Upload => inner.upload_bytes_used += bytes as u64,
-                Direction::Download => inner.download_bytes_used += bytes as u64,
+                Direction::Upload => {
+                    inner.upload_bytes_used += bytes as u64;
+                    inner.upload_bytes_total += bytes as u64;
+                }
+                Direction::Download => {
+                    inner.download_bytes_used += bytes as u64;
+                    inner.download_bytes_total += bytes as u64;
+                }
             }
         }
         
@@ -287,6 +300,12 @@ impl BandwidthController {
         (upload, download, period)
     }
     
+    /// Total (upload, download) bytes since startup; unaffected by `get_and_reset_usage`
+    pub async fn lifetime_totals(&self) -> (u64, u64) {
+        let inner = self.inner.lock().await;
+        (inner.upload_bytes_total, inner.download_bytes_total)
+    }
+
     /// Emit current usage statistics event
     pub async fn emit_usage_stats(&self) {
         let (upload_bytes, download_bytes, period) = self.get_and_reset_usage().await;
@@ -0,0 +1,267 @@
+// Bandwidth usage time-series
+//
+// A sampler reads the bandwidth controller's monotonic byte counters once a
+// minute and records the delta into a ring of per-minute buckets. Minutes are
+// rolled up into hourly and daily buckets, which are persisted to disk so
+// long-range graphs survive restarts. The minute ring is in-memory only.
+
+use crate::bandwidth::BandwidthController;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+/// Per-minute buckets kept in memory (3 hours)
+pub const MINUTE_BUCKETS: usize = 180;
+/// Hourly rollups kept on disk (30 days)
+pub const HOURLY_BUCKETS: usize = 24 * 30;
+/// Daily rollups kept on disk (1 year)
+pub const DAILY_BUCKETS: usize = 365;
+
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(60);
+
+const MINUTE_SECS: u64 = 60;
+const HOUR_SECS: u64 = 60 * 60;
+const DAY_SECS: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BandwidthResolution {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl BandwidthResolution {
+    fn bucket_secs(&self) -> u64 {
+        match self {
+            BandwidthResolution::Minute => MINUTE_SECS,
+            BandwidthResolution::Hour => HOUR_SECS,
+            BandwidthResolution::Day => DAY_SECS,
+        }
+    }
+}
+
+/// Bytes transferred during one bucket
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct BandwidthBucket {
+    /// Unix timestamp (seconds) of the bucket start
+    pub start: u64,
+    pub upload_bytes: u64,
+    pub download_bytes: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PersistedRollups {
+    hourly: VecDeque<BandwidthBucket>,
+    daily: VecDeque<BandwidthBucket>,
+}
+
+#[derive(Debug, Default)]
+struct Series {
+    minutes: VecDeque<BandwidthBucket>,
+    rollups: PersistedRollups,
+    last_totals: Option<(u64, u64)>,
+}
+
+/// Add `bytes` to the bucket containing `now`, appending a new bucket if needed
+fn accumulate(
+    ring: &mut VecDeque<BandwidthBucket>,
+    capacity: usize,
+    bucket_secs: u64,
+    now: u64,
+    upload_bytes: u64,
+    download_bytes: u64,
+) {
+    let start = now - now % bucket_secs;
+    match ring.back_mut() {
+        Some(last) if last.start == start => {
+            last.upload_bytes = last.upload_bytes.saturating_add(upload_bytes);
+            last.download_bytes = last.download_bytes.saturating_add(download_bytes);
+        }
+        _ => {
+            if ring.len() == capacity {
+                ring.pop_front();
+            }
+            ring.push_back(BandwidthBucket {
+                start,
+                upload_bytes,
+                download_bytes,
+            });
+        }
+    }
+}
+
+pub struct BandwidthHistory {
+    series: Mutex<Series>,
+    persist_path: Option<PathBuf>,
+}
+
+impl BandwidthHistory {
+    /// In-memory history; rollups are not persisted
+    pub fn new() -> Self {
+        Self {
+            series: Mutex::new(Series::default()),
+            persist_path: None,
+        }
+    }
+
+    /// History whose hourly/daily rollups are loaded from and saved to `path`
+    pub fn with_persistence(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let rollups = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|raw| serde_json::from_str::<PersistedRollups>(&raw).ok())
+            .unwrap_or_default();
+        Self {
+            series: Mutex::new(Series {
+                rollups,
+                ..Series::default()
+            }),
+            persist_path: Some(path),
+        }
+    }
+
+    /// Record the controller's lifetime counters observed at `now` (Unix seconds).
+    /// The first observation only establishes the baseline.
+    pub async fn record_totals(&self, now: u64, upload_total: u64, download_total: u64) {
+        let mut series = self.series.lock().await;
+        let Some((last_up, last_down)) =
+            series.last_totals.replace((upload_total, download_total))
+        else {
+            return;
+        };
+        let upload = upload_total.saturating_sub(last_up);
+        let download = download_total.saturating_sub(last_down);
+
+        accumulate(&mut series.minutes, MINUTE_BUCKETS, MINUTE_SECS, now, upload, download);
+        accumulate(
+            &mut series.rollups.hourly,
+            HOURLY_BUCKETS,
+            HOUR_SECS,
+            now,
+            upload,
+            download,
+        );
+        accumulate(
+            &mut series.rollups.daily,
+            DAILY_BUCKETS,
+            DAY_SECS,
+            now,
+            upload,
+            download,
+        );
+
+        if let Some(path) = &self.persist_path {
+            match serde_json::to_string(&series.rollups) {
+                Ok(json) => {
+                    // The data directory does not exist yet on a fresh profile
+                    if let Some(parent) = path.parent() {
+                        if let Err(e) = tokio::fs::create_dir_all(parent).await {
+                            warn!("Failed to create bandwidth history directory: {}", e);
+                        }
+                    }
+                    if let Err(e) = tokio::fs::write(path, json).await {
+                        warn!("Failed to persist bandwidth history: {}", e);
+                    }
+                }
+                Err(e) => warn!("Failed to serialize bandwidth history: {}", e),
+            }
+        }
+    }
+
+    /// Buckets at `resolution` whose start lies in `[since, until)`, oldest first
+    pub async fn series(
+        &self,
+        resolution: BandwidthResolution,
+        since: Option<u64>,
+        until: Option<u64>,
+    ) -> Vec<BandwidthBucket> {
+        let series = self.series.lock().await;
+        let ring = match resolution {
+            BandwidthResolution::Minute => &series.minutes,
+            BandwidthResolution::Hour => &series.rollups.hourly,
+            BandwidthResolution::Day => &series.rollups.daily,
+        };
+        // Include the bucket that contains `since`
+        let since = since.map(|s| s - s % resolution.bucket_secs());
+        ring.iter()
+            .filter(|b| since.map_or(true, |s| b.start >= s))
+            .filter(|b| until.map_or(true, |u| b.start < u))
+            .copied()
+            .collect()
+    }
+
+    /// Sample `controller` every minute for the lifetime of the process
+    pub fn spawn_sampler(self: Arc<Self>, controller: Arc<BandwidthController>) {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+            loop {
+                interval.tick().await;
+                let (upload, download) = controller.lifetime_totals().await;
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                self.record_totals(now, upload, download).await;
+            }
+        });
+    }
+}
+
+impl Default for BandwidthHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn deltas_are_bucketed_and_rolled_up() {
+        let history = BandwidthHistory::new();
+        let base = 10 * DAY_SECS;
+
+        history.record_totals(base, 100, 1_000).await; // baseline
+        history.record_totals(base + 60, 150, 1_500).await;
+        history.record_totals(base + 120, 250, 1_500).await;
+        history.record_totals(base + HOUR_SECS, 250, 4_500).await;
+
+        let minutes = history.series(BandwidthResolution::Minute, None, None).await;
+        assert_eq!(minutes.len(), 3);
+        assert_eq!(minutes[0].upload_bytes, 50);
+        assert_eq!(minutes[1].upload_bytes, 100);
+        assert_eq!(minutes[2].download_bytes, 3_000);
+
+        let hours = history.series(BandwidthResolution::Hour, None, None).await;
+        assert_eq!(hours.len(), 2);
+        assert_eq!(hours[0].upload_bytes, 150);
+        assert_eq!(hours[0].download_bytes, 500);
+
+        let days = history.series(BandwidthResolution::Day, None, None).await;
+        assert_eq!(days.len(), 1);
+        assert_eq!(days[0].download_bytes, 3_500);
+
+        let windowed = history
+            .series(BandwidthResolution::Minute, Some(base + 90), Some(base + HOUR_SECS))
+            .await;
+        assert_eq!(windowed.len(), 2);
+        assert_eq!(windowed[0].start, base + 60);
+    }
+
+    #[tokio::test]
+    async fn rollups_are_saved_into_a_missing_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fresh").join("bandwidth_history.json");
+        let history = BandwidthHistory::with_persistence(&path);
+        history.record_totals(DAY_SECS, 0, 0).await;
+        history.record_totals(DAY_SECS + 60, 10, 20).await;
+        assert!(path.exists());
+    }
+}
@@ -0,0 +1,179 @@
+// Batch uploads
+//
+// Selecting a folder or dozens of files in the UI used to fire one upload
+// command per file, each with its own progress and error toast. A batch runs
+// the same per-file upload path sequentially and reports one aggregate
+// progress stream (files and bytes done) and one summary at the end, listing
+// the published id or error of every file.
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::Duration;
+
+/// Event carrying `BatchUploadProgress` after every file
+pub const PROGRESS_EVENT: &str = "batch_upload_progress";
+
+/// Upper bound on files in one batch
+pub const MAX_BATCH_FILES: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchUploadProgress {
+    pub batch_id: String,
+    pub total_files: usize,
+    pub completed_files: usize,
+    pub failed_files: usize,
+    pub processed_bytes: u64,
+    pub total_bytes: u64,
+    /// File being uploaded, `None` once the batch is done
+    pub current_file: Option<String>,
+}
+
+/// Outcome for one file of a batch
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchUploadItem {
+    pub path: String,
+    pub file_name: String,
+    pub file_size: u64,
+    /// Id the file is published under (merkle root, info hash or ed2k
+    /// hash), set when the upload succeeded
+    pub file_hash: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchUploadSummary {
+    pub batch_id: String,
+    pub total_files: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub total_bytes: u64,
+    pub uploaded_bytes: u64,
+    pub duration_ms: u64,
+    /// One entry per file, in upload order
+    pub items: Vec<BatchUploadItem>,
+}
+
+/// Progress bookkeeping for a running batch
+pub struct BatchUpload {
+    batch_id: String,
+    total_files: usize,
+    total_bytes: u64,
+    items: Vec<BatchUploadItem>,
+}
+
+pub fn file_name_of(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(path)
+        .to_string()
+}
+
+/// Drop empty and repeated paths, keeping the first occurrence of each
+pub fn dedup_paths(paths: Vec<String>) -> Vec<String> {
+    let mut seen = std::collections::HashSet::new();
+    paths
+        .into_iter()
+        .map(|path| path.trim().to_string())
+        .filter(|path| !path.is_empty() && seen.insert(path.clone()))
+        .collect()
+}
+
+impl BatchUpload {
+    /// `files` are the paths of the batch with their sizes on disk
+    pub fn new(batch_id: String, files: &[(String, u64)]) -> Self {
+        Self {
+            batch_id,
+            total_files: files.len(),
+            total_bytes: files.iter().map(|(_, size)| size).sum(),
+            items: Vec::with_capacity(files.len()),
+        }
+    }
+
+    pub fn progress(&self, current_file: Option<&str>) -> BatchUploadProgress {
+        BatchUploadProgress {
+            batch_id: self.batch_id.clone(),
+            total_files: self.total_files,
+            completed_files: self.items.iter().filter(|i| i.error.is_none()).count(),
+            failed_files: self.items.iter().filter(|i| i.error.is_some()).count(),
+            processed_bytes: self.items.iter().map(|i| i.file_size).sum(),
+            total_bytes: self.total_bytes,
+            current_file: current_file.map(file_name_of),
+        }
+    }
+
+    pub fn record(&mut self, path: &str, file_size: u64, result: Result<String, String>) {
+        let (file_hash, error) = match result {
+            Ok(hash) => (Some(hash), None),
+            Err(e) => (None, Some(e)),
+        };
+        self.items.push(BatchUploadItem {
+            path: path.to_string(),
+            file_name: file_name_of(path),
+            file_size,
+            file_hash,
+            error,
+        });
+    }
+
+    pub fn finish(self, elapsed: Duration) -> BatchUploadSummary {
+        let succeeded = self.items.iter().filter(|i| i.error.is_none()).count();
+        BatchUploadSummary {
+            succeeded,
+            failed: self.items.len() - succeeded,
+            total_files: self.total_files,
+            total_bytes: self.total_bytes,
+            uploaded_bytes: self
+                .items
+                .iter()
+                .filter(|i| i.error.is_none())
+                .map(|i| i.file_size)
+                .sum(),
+            duration_ms: elapsed.as_millis() as u64,
+            batch_id: self.batch_id,
+            items: self.items,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregates_progress_and_summary() {
+        let paths = dedup_paths(vec![
+            "/data/a.bin".into(),
+            " /data/b.bin ".into(),
+            "/data/a.bin".into(),
+            "".into(),
+        ]);
+        assert_eq!(paths, vec!["/data/a.bin", "/data/b.bin"]);
+
+        let files = vec![(paths[0].clone(), 100), (paths[1].clone(), 50)];
+        let mut batch = BatchUpload::new("batch-1".into(), &files);
+        let progress = batch.progress(Some("/data/a.bin"));
+        assert_eq!(progress.total_bytes, 150);
+        assert_eq!(progress.current_file.as_deref(), Some("a.bin"));
+
+        batch.record("/data/a.bin", 100, Ok("hash-a".into()));
+        batch.record(
+            "/data/b.bin",
+            50,
+            Err("Failed to open file for hashing".into()),
+        );
+        let progress = batch.progress(None);
+        assert_eq!((progress.completed_files, progress.failed_files), (1, 1));
+        assert_eq!(progress.processed_bytes, 150);
+
+        let summary = batch.finish(Duration::from_millis(1500));
+        assert_eq!((summary.succeeded, summary.failed), (1, 1));
+        assert_eq!(summary.uploaded_bytes, 100);
+        assert_eq!(summary.items[0].file_hash.as_deref(), Some("hash-a"));
+        assert_eq!(summary.items[1].file_name, "b.bin");
+        assert_eq!(summary.duration_ms, 1500);
+    }
+}
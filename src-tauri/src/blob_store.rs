@@ -0,0 +1,1510 @@
+// Content-addressed blob store
+//
+// Stored file contents live as one file per hash directly under the storage
+// directory, next to the `<hash>.meta` sidecars that `local_library` reads.
+// `blobs.index.json` records the size, store time and pin state of every
+// blob so listings and usage totals don't have to stat the whole directory.
+// Pinned blobs are never deleted to make room (`evict_to`) and never
+// expire. The index is reconciled with the directory on open: entries whose
+// file vanished are dropped and blob files written by older versions (which
+// had no index) are adopted.
+//
+// Writes go to a temporary file first and are renamed into place, so a
+// crash never leaves a truncated blob under its final name.
+//
+// The index sits behind a read-write lock. Lookups and reads, which is what
+// serving and downloading many files at once mostly does, share it; only
+// changes to the index wait for each other.
+//
+// Blobs can be encrypted at rest so a seeding machine keeps no plaintext
+// copies of what it shares. The key is derived from the user's account (see
+// `keystore::derive_storage_key`) and only held in memory while unlocked. A
+// sealed blob is AES-256-GCM encrypted in 64 KiB segments, each with its own
+// nonce and tag, so `read_range` only decrypts the segments it touches.
+//
+// With deduplication on, blob contents go to the `ChunkStore` under `dedup/`
+// instead, so data shared between stored files is kept once. Sealing takes
+// precedence: encrypted segments never repeat, so sealed blobs stay whole
+// files. Code that opens `path(hash)` directly only sees blobs stored as
+// plain files.
+//
+// A blob can also be linked instead of stored: the index points at the
+// user's own file, which is read where it is so sharing a large library
+// doesn't copy it. Linked blobs don't count towards the store's size, are
+// never sealed, deduplicated or evicted, and removing one only forgets the
+// link. The file's size and modification time are recorded when it is
+// linked; once either changes the contents no longer match the hash, and
+// reads fail rather than serve them.
+
+use crate::chunk_store::{ChunkStore, DedupStats};
+use crate::encryption::FileEncryption;
+use crate::error_codes::{io_error, ErrorCode, ServiceError};
+use crate::persisted;
+use crate::runtime_env::{Clock, FileIo, RuntimeEnv};
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use bytes::BytesMut;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use tokio::sync::RwLock;
+
+pub const INDEX_FILE: &str = "blobs.index.json";
+/// Directory of the deduplicated chunk store, under the storage directory
+pub const DEDUP_DIR: &str = "dedup";
+const TMP_SUFFIX: &str = ".blobtmp";
+
+const SEAL_MAGIC: &[u8; 8] = b"CHRLSEAL";
+/// Magic, nonce prefix and plaintext length
+const SEAL_HEADER_LEN: usize = 24;
+/// Plaintext bytes per separately authenticated segment
+const SEAL_SEGMENT: usize = 64 * 1024;
+const SEAL_TAG_LEN: usize = 16;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct BlobEntry {
+    pub hash: String,
+    pub size: u64,
+    /// Unix seconds when the blob was written or adopted
+    pub stored_at: u64,
+    /// Encrypted at rest; `size` is still the plaintext size
+    #[serde(default)]
+    pub sealed: bool,
+    /// Kept when storage is trimmed or garbage collected
+    #[serde(default)]
+    pub pinned: bool,
+    /// Unix seconds after which the blob is garbage collected
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// Kept as shared chunks in the dedup store rather than as a file
+    #[serde(default)]
+    pub deduplicated: bool,
+    /// Read from the user's file instead of a copy in the store
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub linked: Option<LinkedFile>,
+}
+
+/// A file outside the store that a blob is read from
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct LinkedFile {
+    pub path: PathBuf,
+    /// Modification time when the file was linked, Unix milliseconds
+    pub modified_ms: u64,
+}
+
+impl LinkedFile {
+    /// Current state of the file at `path`, with its size
+    pub async fn stat(path: &Path) -> Result<(Self, u64), String> {
+        let meta = tokio::fs::metadata(path)
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if !meta.is_file() {
+            return Err(format!("{} is not a file", path.display()));
+        }
+        let modified_ms = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+        Ok((
+            Self {
+                path: path.to_path_buf(),
+                modified_ms,
+            },
+            meta.len(),
+        ))
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct BlobIndex {
+    blobs: BTreeMap<String, BlobEntry>,
+    /// New blobs are sealed
+    #[serde(default)]
+    encrypt_at_rest: bool,
+    /// Fingerprint of the key the blobs are sealed with
+    #[serde(default)]
+    key_fingerprint: Option<String>,
+    /// New unsealed blobs go to the chunk store
+    #[serde(default)]
+    deduplicate: bool,
+    /// Ids blobs are published under, e.g. a merkle root, to their keys
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    aliases: BTreeMap<String, String>,
+}
+
+pub struct BlobStore {
+    root: PathBuf,
+    index: RwLock<BlobIndex>,
+    chunks: ChunkStore,
+    fs: Arc<dyn FileIo>,
+    clock: Arc<dyn Clock>,
+    /// Key for sealed blobs while the store is unlocked
+    key: std::sync::Mutex<Option<[u8; 32]>>,
+}
+
+/// Keys become file names, so only allow characters that can't escape `root`
+pub fn is_valid_key(hash: &str) -> bool {
+    !hash.is_empty()
+        && hash.len() <= 128
+        && hash
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Whether the file at `path` has other names, e.g. from an older merge of
+/// duplicate files
+#[cfg(unix)]
+async fn is_hard_linked(path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    tokio::fs::metadata(path)
+        .await
+        .is_ok_and(|meta| meta.nlink() > 1)
+}
+
+#[cfg(not(unix))]
+async fn is_hard_linked(_path: &Path) -> bool {
+    false
+}
+
+fn check_key(hash: &str) -> Result<(), String> {
+    if is_valid_key(hash) {
+        Ok(())
+    } else {
+        Err(format!("Invalid blob key: {:?}", hash))
+    }
+}
+
+fn segment_nonce(prefix: &[u8], index: usize) -> Result<[u8; 12], String> {
+    let index = u32::try_from(index).map_err(|_| "Blob is too large to encrypt".to_string())?;
+    let mut nonce = [0u8; 12];
+    nonce[..8].copy_from_slice(prefix);
+    nonce[8..].copy_from_slice(&index.to_be_bytes());
+    Ok(nonce)
+}
+
+/// Encrypt `data` as a header followed by sealed segments. The header is
+/// authenticated with every segment, so segments can't be moved between
+/// blobs and the length can't be altered.
+fn seal(key: &[u8; 32], data: &[u8]) -> Result<Vec<u8>, String> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let mut prefix = [0u8; 8];
+    rand::thread_rng().fill_bytes(&mut prefix);
+    let mut header = Vec::with_capacity(SEAL_HEADER_LEN);
+    header.extend_from_slice(SEAL_MAGIC);
+    header.extend_from_slice(&prefix);
+    header.extend_from_slice(&(data.len() as u64).to_be_bytes());
+
+    let segments = data.len().div_ceil(SEAL_SEGMENT);
+    let mut sealed = Vec::with_capacity(SEAL_HEADER_LEN + data.len() + segments * SEAL_TAG_LEN);
+    sealed.extend_from_slice(&header);
+    for (index, segment) in data.chunks(SEAL_SEGMENT).enumerate() {
+        let nonce = segment_nonce(&prefix, index)?;
+        let payload = Payload {
+            msg: segment,
+            aad: &header,
+        };
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|e| format!("Failed to encrypt blob: {}", e))?;
+        sealed.extend_from_slice(&ciphertext);
+    }
+    Ok(sealed)
+}
+
+/// Plaintext length recorded in a sealed blob's header
+fn sealed_len(header: &[u8]) -> Result<u64, String> {
+    if header.len() < SEAL_HEADER_LEN || &header[..8] != SEAL_MAGIC {
+        return Err("Blob is not encrypted at rest".to_string());
+    }
+    let mut len = [0u8; 8];
+    len.copy_from_slice(&header[16..SEAL_HEADER_LEN]);
+    Ok(u64::from_be_bytes(len))
+}
+
+/// Decrypt consecutive sealed segments starting at segment `first`
+fn open_segments(
+    key: &[u8; 32],
+    header: &[u8],
+    first: usize,
+    ciphertext: &[u8],
+) -> Result<Vec<u8>, String> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let mut plaintext = Vec::with_capacity(ciphertext.len());
+    for (i, segment) in ciphertext.chunks(SEAL_SEGMENT + SEAL_TAG_LEN).enumerate() {
+        let nonce = segment_nonce(&header[8..16], first + i)?;
+        let payload = Payload {
+            msg: segment,
+            aad: &header[..SEAL_HEADER_LEN],
+        };
+        let opened = cipher
+            .decrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|_| "Encrypted blob failed authentication".to_string())?;
+        plaintext.extend_from_slice(&opened);
+    }
+    Ok(plaintext)
+}
+
+fn unseal(key: &[u8; 32], sealed: &[u8]) -> Result<Vec<u8>, String> {
+    let len = sealed_len(sealed)?;
+    let plaintext = open_segments(key, sealed, 0, &sealed[SEAL_HEADER_LEN..])?;
+    if plaintext.len() as u64 != len {
+        return Err("Encrypted blob is truncated".to_string());
+    }
+    Ok(plaintext)
+}
+
+impl BlobStore {
+    pub async fn open(root: impl Into<PathBuf>) -> Result<Self, String> {
+        Self::open_with_env(root, &RuntimeEnv::system()).await
+    }
+
+    /// Open with the filesystem and clock of `env`
+    pub async fn open_with_env(root: impl Into<PathBuf>, env: &RuntimeEnv) -> Result<Self, String> {
+        let root = root.into();
+        tokio::fs::create_dir_all(&root)
+            .await
+            .map_err(|e| format!("Failed to create blob store directory: {}", e))?;
+
+        // An unreadable index is rebuilt from the files by `reconcile`
+        let index: BlobIndex = persisted::load_async(&root.join(INDEX_FILE)).await;
+
+        let store = Self {
+            chunks: ChunkStore::open(root.join(DEDUP_DIR)).await?,
+            root,
+            index: RwLock::new(index),
+            fs: env.fs.clone(),
+            clock: env.clock.clone(),
+            key: std::sync::Mutex::new(None),
+        };
+        store.reconcile().await?;
+        Ok(store)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Where the blob for `hash` lives, whether or not it exists
+    pub fn path(&self, hash: &str) -> PathBuf {
+        self.root.join(hash)
+    }
+
+    /// Write `data` under `hash`, replacing any previous blob
+    pub async fn put(&self, hash: &str, data: &[u8]) -> Result<BlobEntry, String> {
+        check_key(hash)?;
+        let (sealed, deduplicated) = {
+            let index = self.index.read().await;
+            let deduplicated = index.deduplicate && !index.encrypt_at_rest;
+            (index.encrypt_at_rest, deduplicated)
+        };
+        if deduplicated {
+            self.chunks.put(hash, data).await?;
+            self.remove_file(hash).await?;
+        } else {
+            if sealed {
+                let stored = seal(&self.required_key()?, data)?;
+                self.write_file(hash, &stored).await?;
+            } else {
+                self.write_file(hash, data).await?;
+            }
+            self.chunks.remove(hash).await?;
+        }
+
+        self.record(BlobEntry {
+            hash: hash.to_string(),
+            size: data.len() as u64,
+            stored_at: self.clock.now_secs(),
+            sealed,
+            pinned: false,
+            expires_at: None,
+            deduplicated,
+            linked: None,
+        })
+        .await
+    }
+
+    /// Move an already written file into the store under `hash`
+    pub async fn put_file(&self, hash: &str, source: &Path) -> Result<BlobEntry, String> {
+        check_key(hash)?;
+        let rewritten = {
+            let index = self.index.read().await;
+            index.encrypt_at_rest || index.deduplicate
+        };
+        if rewritten {
+            // The plaintext must not be moved in as it is, or it is split
+            // into chunks
+            let data = tokio::fs::read(source).await.map_err(io_error(
+                ErrorCode::ReadFailed,
+                "Failed to read blob source",
+            ))?;
+            let entry = self.put(hash, &data).await?;
+            let _ = tokio::fs::remove_file(source).await;
+            return Ok(entry);
+        }
+        let size = tokio::fs::metadata(source)
+            .await
+            .map_err(io_error(
+                ErrorCode::ReadFailed,
+                "Failed to read blob source",
+            ))?
+            .len();
+        if tokio::fs::rename(source, self.path(hash)).await.is_err() {
+            // Different filesystem: fall back to copying
+            tokio::fs::copy(source, self.path(hash))
+                .await
+                .map_err(io_error(
+                    ErrorCode::WriteFailed,
+                    "Failed to copy blob into place",
+                ))?;
+            let _ = tokio::fs::remove_file(source).await;
+        }
+        self.chunks.remove(hash).await?;
+
+        self.record(BlobEntry {
+            hash: hash.to_string(),
+            size,
+            stored_at: self.clock.now_secs(),
+            sealed: false,
+            pinned: false,
+            expires_at: None,
+            deduplicated: false,
+            linked: None,
+        })
+        .await
+    }
+
+    /// Index the file at `linked.path` as the blob `hash` without copying
+    /// it. `linked` and `size` are the file's state from before it was
+    /// hashed; if it changed since, the hash may not match and the link is
+    /// refused. A copy already stored under `hash` is deleted.
+    pub async fn link(
+        &self,
+        hash: &str,
+        linked: LinkedFile,
+        size: u64,
+    ) -> Result<BlobEntry, String> {
+        check_key(hash)?;
+        let (now, now_size) = LinkedFile::stat(&linked.path).await?;
+        if now != linked || now_size != size {
+            return Err(format!(
+                "{} changed while it was being indexed",
+                linked.path.display()
+            ));
+        }
+        self.remove_file(hash).await?;
+        self.chunks.remove(hash).await?;
+        self.record(BlobEntry {
+            hash: hash.to_string(),
+            size,
+            stored_at: self.clock.now_secs(),
+            sealed: false,
+            pinned: false,
+            expires_at: None,
+            deduplicated: false,
+            linked: Some(linked),
+        })
+        .await
+    }
+
+    /// The linked file of `hash` if it still holds what was indexed
+    async fn linked_source(&self, hash: &str) -> Result<Option<PathBuf>, String> {
+        let Some((linked, size)) = self
+            .entry(hash)
+            .await
+            .and_then(|entry| Some((entry.linked?, entry.size)))
+        else {
+            return Ok(None);
+        };
+        match LinkedFile::stat(&linked.path).await {
+            Ok((now, now_size)) if now == linked && now_size == size => Ok(Some(linked.path)),
+            Ok(_) => Err(format!(
+                "Shared file {} has changed since it was indexed",
+                linked.path.display()
+            )),
+            Err(e) => Err(format!("Shared file is unavailable: {}", e)),
+        }
+    }
+
+    /// Index a freshly written blob. Rewriting a pinned blob keeps the pin;
+    /// an expiry has to be set again.
+    async fn record(&self, mut entry: BlobEntry) -> Result<BlobEntry, String> {
+        let mut index = self.index.write().await;
+        entry.pinned = index.blobs.get(&entry.hash).is_some_and(|old| old.pinned);
+        index.blobs.insert(entry.hash.clone(), entry.clone());
+        self.persist(&index).await?;
+        Ok(entry)
+    }
+
+    /// Contents of the blob, or `None` if it isn't stored
+    pub async fn get(&self, hash: &str) -> Result<Option<Vec<u8>>, String> {
+        check_key(hash)?;
+        if self.chunks.contains(hash).await {
+            return self.chunks.get(hash).await;
+        }
+        if let Some(path) = self.linked_source(hash).await? {
+            return self.fs.read(&path).await.map(Some).map_err(io_error(
+                ErrorCode::ReadFailed,
+                "Failed to read shared file",
+            ));
+        }
+        let data = match self.fs.read(&self.path(hash)).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            read => read.map_err(io_error(ErrorCode::ReadFailed, "Failed to read blob"))?,
+        };
+        if self.is_sealed(hash).await {
+            return unseal(&self.required_key()?, &data).map(Some);
+        }
+        Ok(Some(data))
+    }
+
+    /// `len` bytes of the blob starting at `offset`, or `None` if it isn't
+    /// stored. Reads past the end are an error.
+    pub async fn read_range(
+        &self,
+        hash: &str,
+        offset: u64,
+        len: usize,
+    ) -> Result<Option<Vec<u8>>, String> {
+        use tokio::io::{AsyncReadExt, AsyncSeekExt};
+        check_key(hash)?;
+        if self.chunks.contains(hash).await {
+            return self.chunks.read_range(hash, offset, len).await;
+        }
+        let path = match self.linked_source(hash).await? {
+            Some(path) => path,
+            None => self.path(hash),
+        };
+        let mut file = match tokio::fs::File::open(path).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            read => read.map_err(io_error(ErrorCode::ReadFailed, "Failed to read blob"))?,
+        };
+        if !self.is_sealed(hash).await {
+            let mut buf = vec![0u8; len];
+            file.seek(std::io::SeekFrom::Start(offset))
+                .await
+                .map_err(io_error(ErrorCode::ReadFailed, "Failed to read blob"))?;
+            file.read_exact(&mut buf)
+                .await
+                .map_err(io_error(ErrorCode::ReadFailed, "Failed to read blob"))?;
+            return Ok(Some(buf));
+        }
+
+        // Decrypt only the segments covering the range
+        let key = self.required_key()?;
+        let mut header = [0u8; SEAL_HEADER_LEN];
+        file.read_exact(&mut header)
+            .await
+            .map_err(io_error(ErrorCode::ReadFailed, "Failed to read blob"))?;
+        let total = sealed_len(&header)?;
+        let end = offset.saturating_add(len as u64);
+        if end > total {
+            return Err(format!(
+                "Failed to read blob: range ends at {}, blob has {} bytes",
+                end, total
+            ));
+        }
+        if len == 0 {
+            return Ok(Some(Vec::new()));
+        }
+        let first = (offset / SEAL_SEGMENT as u64) as usize;
+        let last = ((end - 1) / SEAL_SEGMENT as u64) as usize;
+        let last_len = (total - (last * SEAL_SEGMENT) as u64).min(SEAL_SEGMENT as u64) as usize;
+        let sealed_segment = SEAL_SEGMENT + SEAL_TAG_LEN;
+        let mut ciphertext = vec![0u8; (last - first) * sealed_segment + last_len + SEAL_TAG_LEN];
+        file.seek(std::io::SeekFrom::Start(
+            (SEAL_HEADER_LEN + first * sealed_segment) as u64,
+        ))
+        .await
+        .map_err(io_error(ErrorCode::ReadFailed, "Failed to read blob"))?;
+        file.read_exact(&mut ciphertext)
+            .await
+            .map_err(io_error(ErrorCode::ReadFailed, "Failed to read blob"))?;
+        let plaintext = open_segments(&key, &header, first, &ciphertext)?;
+        let start = (offset - (first * SEAL_SEGMENT) as u64) as usize;
+        Ok(Some(plaintext[start..start + len].to_vec()))
+    }
+
+    /// Like `read_range`, but into `buf` so the caller can reuse one buffer
+    /// for many reads. False if the blob isn't stored.
+    pub async fn read_range_into(
+        &self,
+        hash: &str,
+        offset: u64,
+        len: usize,
+        buf: &mut BytesMut,
+    ) -> Result<bool, String> {
+        use tokio::io::{AsyncReadExt, AsyncSeekExt};
+        check_key(hash)?;
+        buf.clear();
+        if self.chunks.contains(hash).await || self.is_sealed(hash).await {
+            // Assembled from chunks or decrypted, so there is a copy anyway
+            let Some(data) = self.read_range(hash, offset, len).await? else {
+                return Ok(false);
+            };
+            buf.extend_from_slice(&data);
+            return Ok(true);
+        }
+        let path = match self.linked_source(hash).await? {
+            Some(path) => path,
+            None => self.path(hash),
+        };
+        let mut file = match tokio::fs::File::open(path).await {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+            read => read.map_err(io_error(ErrorCode::ReadFailed, "Failed to read blob"))?,
+        };
+        buf.resize(len, 0);
+        file.seek(std::io::SeekFrom::Start(offset))
+            .await
+            .map_err(io_error(ErrorCode::ReadFailed, "Failed to read blob"))?;
+        file.read_exact(&mut buf[..])
+            .await
+            .map_err(io_error(ErrorCode::ReadFailed, "Failed to read blob"))?;
+        Ok(true)
+    }
+
+    pub async fn contains(&self, hash: &str) -> bool {
+        is_valid_key(hash)
+            && (self.chunks.contains(hash).await
+                || self.linked_source(hash).await.is_ok_and(|p| p.is_some())
+                || tokio::fs::try_exists(self.path(hash))
+                    .await
+                    .unwrap_or(false))
+    }
+
+    pub async fn entry(&self, hash: &str) -> Option<BlobEntry> {
+        self.index.read().await.blobs.get(hash).cloned()
+    }
+
+    /// Record that the stored blob `hash` is published as `id`. The alias
+    /// goes away with the blob.
+    pub async fn add_alias(&self, id: &str, hash: &str) -> Result<(), String> {
+        check_key(hash)?;
+        let mut index = self.index.write().await;
+        if !index.blobs.contains_key(hash) {
+            return Err(format!("Blob {} is not stored", hash));
+        }
+        if id == hash || index.aliases.get(id).is_some_and(|h| h == hash) {
+            return Ok(());
+        }
+        index.aliases.insert(id.to_string(), hash.to_string());
+        self.persist(&index).await
+    }
+
+    /// Key of the blob published as `id`, if one was recorded
+    pub async fn resolve_alias(&self, id: &str) -> Option<String> {
+        self.index.read().await.aliases.get(id).cloned()
+    }
+
+    /// Ids the blob `hash` was recorded as published under
+    pub async fn aliases_of(&self, hash: &str) -> Vec<String> {
+        let index = self.index.read().await;
+        index
+            .aliases
+            .iter()
+            .filter(|(_, key)| *key == hash)
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// Delete the blob; returns whether it existed. Chunks it shares with
+    /// other blobs are kept, and a linked file is left alone.
+    pub async fn remove(&self, hash: &str) -> Result<bool, String> {
+        check_key(hash)?;
+        let linked = self.entry(hash).await.is_some_and(|e| e.linked.is_some());
+        let existed = linked | self.remove_file(hash).await? | self.chunks.remove(hash).await?;
+        let mut index = self.index.write().await;
+        let aliases = index.aliases.len();
+        index.aliases.retain(|_, key| key != hash);
+        if index.blobs.remove(hash).is_some() || existed || index.aliases.len() != aliases {
+            self.persist(&index).await?;
+        }
+        Ok(existed)
+    }
+
+    /// Move the blob out of the store to `dest` as it is kept, so that
+    /// [`restore`](Self::restore) can put it back: a sealed blob stays
+    /// sealed and a deduplicated one is written out whole, sharing nothing
+    /// with other blobs. Nothing is written for a linked file. Returns the
+    /// removed entry, or `None` if the blob isn't stored.
+    pub async fn take(&self, hash: &str, dest: &Path) -> Result<Option<BlobEntry>, String> {
+        check_key(hash)?;
+        let Some(entry) = self.entry(hash).await else {
+            return Ok(None);
+        };
+        if entry.deduplicated {
+            let data = self
+                .chunks
+                .get(hash)
+                .await?
+                .ok_or_else(|| format!("Chunks of blob {} are missing", hash))?;
+            self.fs
+                .write(dest, &data)
+                .await
+                .map_err(io_error(ErrorCode::WriteFailed, "Failed to move blob out"))?;
+        } else if entry.linked.is_none()
+            && (is_hard_linked(&self.path(hash)).await
+                || tokio::fs::rename(self.path(hash), dest).await.is_err())
+        {
+            // A file hard-linked from another key must not be erased with
+            // the trash entry, and renames fail across filesystems
+            tokio::fs::copy(self.path(hash), dest)
+                .await
+                .map_err(io_error(ErrorCode::WriteFailed, "Failed to move blob out"))?;
+        }
+        self.remove(hash).await?;
+        Ok(Some(entry))
+    }
+
+    /// Put back a blob moved out with [`take`](Self::take) from `source`,
+    /// stored the way new blobs currently are and with its pin and expiry.
+    /// A linked blob is linked again if its file is unchanged.
+    pub async fn restore(&self, entry: &BlobEntry, source: &Path) -> Result<BlobEntry, String> {
+        if let Some(linked) = &entry.linked {
+            self.link(&entry.hash, linked.clone(), entry.size).await?;
+        } else {
+            let data = tokio::fs::read(source).await.map_err(io_error(
+                ErrorCode::ReadFailed,
+                "Failed to read trashed blob",
+            ))?;
+            let data = if entry.sealed {
+                unseal(&self.required_key()?, &data)?
+            } else {
+                data
+            };
+            self.put(&entry.hash, &data).await?;
+            let _ = tokio::fs::remove_file(source).await;
+        }
+        self.set_pinned(&entry.hash, entry.pinned).await?;
+        self.set_expiry(&entry.hash, entry.expires_at)
+            .await?
+            .ok_or_else(|| format!("Blob {} vanished while it was restored", entry.hash))
+    }
+
+    pub async fn list(&self) -> Vec<BlobEntry> {
+        self.index.read().await.blobs.values().cloned().collect()
+    }
+
+    /// Bytes of the blobs kept in the store; linked files don't count
+    pub async fn total_bytes(&self) -> u64 {
+        self.index
+            .read()
+            .await
+            .blobs
+            .values()
+            .filter(|b| b.linked.is_none())
+            .map(|b| b.size)
+            .sum()
+    }
+
+    /// Pin or unpin a stored blob. Returns its updated entry, or `None` if
+    /// it isn't stored.
+    pub async fn set_pinned(&self, hash: &str, pinned: bool) -> Result<Option<BlobEntry>, String> {
+        check_key(hash)?;
+        let mut index = self.index.write().await;
+        let Some(entry) = index.blobs.get_mut(hash) else {
+            return Ok(None);
+        };
+        if entry.pinned == pinned {
+            return Ok(Some(entry.clone()));
+        }
+        entry.pinned = pinned;
+        let entry = entry.clone();
+        self.persist(&index).await?;
+        Ok(Some(entry))
+    }
+
+    /// Set or clear when a stored blob expires. Returns its updated entry,
+    /// or `None` if it isn't stored.
+    pub async fn set_expiry(
+        &self,
+        hash: &str,
+        expires_at: Option<u64>,
+    ) -> Result<Option<BlobEntry>, String> {
+        check_key(hash)?;
+        let mut index = self.index.write().await;
+        let Some(entry) = index.blobs.get_mut(hash) else {
+            return Ok(None);
+        };
+        entry.expires_at = expires_at;
+        let entry = entry.clone();
+        self.persist(&index).await?;
+        Ok(Some(entry))
+    }
+
+    /// Unpinned blobs whose expiry is at or before `now` (Unix seconds)
+    pub async fn expired(&self, now: u64) -> Vec<BlobEntry> {
+        self.index
+            .read()
+            .await
+            .blobs
+            .values()
+            .filter(|entry| !entry.pinned && entry.expires_at.is_some_and(|at| at <= now))
+            .cloned()
+            .collect()
+    }
+
+    /// Unpinned blobs, oldest first, that would have to go for at most
+    /// `max_bytes` to be stored. Linked files are never counted in.
+    pub async fn eviction_candidates(&self, max_bytes: u64) -> Vec<BlobEntry> {
+        let mut candidates: Vec<BlobEntry> = {
+            let index = self.index.read().await;
+            index
+                .blobs
+                .values()
+                .filter(|entry| !entry.pinned && entry.linked.is_none())
+                .cloned()
+                .collect()
+        };
+        candidates.sort_by_key(|entry| entry.stored_at);
+
+        let mut total = self.total_bytes().await;
+        candidates
+            .into_iter()
+            .take_while(|entry| {
+                let over = total > max_bytes;
+                total = total.saturating_sub(entry.size);
+                over
+            })
+            .collect()
+    }
+
+    /// Delete unpinned blobs, oldest first, until at most `max_bytes` are
+    /// stored. Pinned blobs are never deleted, so the store may stay over
+    /// the limit. Returns the deleted entries.
+    pub async fn evict_to(&self, max_bytes: u64) -> Result<Vec<BlobEntry>, String> {
+        let mut evicted = Vec::new();
+        for entry in self.eviction_candidates(max_bytes).await {
+            // Pinned in the meantime
+            if self.entry(&entry.hash).await.is_none_or(|e| e.pinned) {
+                continue;
+            }
+            self.remove(&entry.hash).await?;
+            evicted.push(entry);
+        }
+        Ok(evicted)
+    }
+
+    /// Hold the key for sealed blobs, e.g. when the user logs in. A key
+    /// other than the one the blobs were sealed with is refused.
+    pub async fn unlock(&self, key: [u8; 32]) -> Result<(), String> {
+        let fingerprint = FileEncryption::generate_key_fingerprint(&key);
+        let index = self.index.read().await;
+        if index
+            .key_fingerprint
+            .as_ref()
+            .is_some_and(|expected| *expected != fingerprint)
+        {
+            return Err("This key does not unlock the blob store".to_string());
+        }
+        *self.key.lock().unwrap() = Some(key);
+        Ok(())
+    }
+
+    /// Forget the key; sealed blobs can't be read until the next unlock
+    pub fn lock_key(&self) {
+        *self.key.lock().unwrap() = None;
+    }
+
+    /// Whether sealed blobs can be read
+    pub fn is_unlocked(&self) -> bool {
+        self.key.lock().unwrap().is_some()
+    }
+
+    pub async fn encrypts_at_rest(&self) -> bool {
+        self.index.read().await.encrypt_at_rest
+    }
+
+    /// Turn encryption at rest on or off, converting every stored blob.
+    /// Needs the store to be unlocked. Blobs are converted one at a time,
+    /// so reads and writes of other blobs carry on meanwhile. Returns how
+    /// many blobs were converted.
+    pub async fn set_encrypt_at_rest(&self, enabled: bool) -> Result<usize, String> {
+        let key = self.required_key()?;
+        let pending: Vec<String> = {
+            let mut index = self.index.write().await;
+            index.encrypt_at_rest = enabled;
+            if enabled {
+                index.key_fingerprint = Some(FileEncryption::generate_key_fingerprint(&key));
+            }
+            index
+                .blobs
+                .values()
+                .filter(|entry| entry.sealed != enabled && entry.linked.is_none())
+                .map(|entry| entry.hash.clone())
+                .collect()
+        };
+
+        let mut converted = 0;
+        let mut result = Ok(());
+        for hash in pending {
+            let mut index = self.index.write().await;
+            // Switched back by another call; that one converts the rest
+            if index.encrypt_at_rest != enabled {
+                break;
+            }
+            // Removed, replaced or converted in the meantime
+            if index
+                .blobs
+                .get(&hash)
+                .is_none_or(|entry| entry.sealed == enabled || entry.linked.is_some())
+            {
+                continue;
+            }
+            let deduplicate = index.deduplicate;
+            // Unsealed blobs go back to the chunk store if deduplication is on
+            let step = async {
+                if enabled {
+                    let raw = self.read_unsealed(&hash).await?;
+                    self.write_file(&hash, &seal(&key, &raw)?).await?;
+                    self.chunks.remove(&hash).await?;
+                    return Ok(false);
+                }
+                let raw = self
+                    .fs
+                    .read(&self.path(&hash))
+                    .await
+                    .map_err(io_error(ErrorCode::ReadFailed, "Failed to read blob"))?;
+                let data = unseal(&key, &raw)?;
+                if deduplicate {
+                    self.chunks.put(&hash, &data).await?;
+                    self.remove_file(&hash).await?;
+                } else {
+                    self.write_file(&hash, &data).await?;
+                }
+                Ok::<bool, String>(deduplicate)
+            };
+            let deduplicated = match step.await {
+                Ok(deduplicated) => deduplicated,
+                Err(e) => {
+                    result = Err(format!("Failed to convert blob {}: {}", hash, e));
+                    break;
+                }
+            };
+            if let Some(entry) = index.blobs.get_mut(&hash) {
+                entry.sealed = enabled;
+                entry.deduplicated = deduplicated;
+            }
+            converted += 1;
+            // Saved per blob so a crash midway leaves the index agreeing
+            // with the files
+            if let Err(e) = self.persist(&index).await {
+                result = Err(e);
+                break;
+            }
+        }
+
+        let mut index = self.index.write().await;
+        if result.is_ok() && !enabled && !index.encrypt_at_rest {
+            index.key_fingerprint = None;
+        }
+        // Record the blobs converted so far even if one failed
+        self.persist(&index).await?;
+        result.map(|()| converted)
+    }
+
+    pub async fn deduplicates(&self) -> bool {
+        self.index.read().await.deduplicate
+    }
+
+    /// Turn deduplication on or off, moving every unsealed blob into the
+    /// chunk store or back out to a file of its own. Returns how many blobs
+    /// were moved.
+    pub async fn set_deduplicate(&self, enabled: bool) -> Result<usize, String> {
+        let mut index = self.index.write().await;
+        index.deduplicate = enabled;
+        let pending: Vec<String> = index
+            .blobs
+            .values()
+            .filter(|entry| {
+                !entry.sealed && entry.deduplicated != enabled && entry.linked.is_none()
+            })
+            .map(|entry| entry.hash.clone())
+            .collect();
+
+        let mut moved = 0;
+        let mut result = Ok(());
+        for hash in pending {
+            let step = async {
+                let data = self.read_unsealed(&hash).await?;
+                if enabled {
+                    self.chunks.put(&hash, &data).await?;
+                    self.remove_file(&hash).await?;
+                } else {
+                    self.write_file(&hash, &data).await?;
+                    self.chunks.remove(&hash).await?;
+                }
+                Ok::<(), String>(())
+            };
+            if let Err(e) = step.await {
+                result = Err(format!("Failed to move blob {}: {}", hash, e));
+                break;
+            }
+            if let Some(entry) = index.blobs.get_mut(&hash) {
+                entry.deduplicated = enabled;
+            }
+            moved += 1;
+        }
+        // Record the blobs moved so far even if one failed
+        self.persist(&index).await?;
+        result.map(|()| moved)
+    }
+
+    /// Keep the blob as refcounted chunks in the chunk store whatever the
+    /// dedup setting, so identical blobs are stored once and deleting or
+    /// overwriting one never touches another. Sealed and linked blobs stay
+    /// as they are. Returns whether the blob moved.
+    pub async fn share_chunks(&self, hash: &str) -> Result<bool, String> {
+        check_key(hash)?;
+        let mut index = self.index.write().await;
+        let Some(entry) = index.blobs.get(hash) else {
+            return Err(format!("Blob {} is not stored", hash));
+        };
+        if entry.sealed || entry.deduplicated || entry.linked.is_some() {
+            return Ok(false);
+        }
+        let data = self.read_unsealed(hash).await?;
+        self.chunks.put(hash, &data).await?;
+        self.remove_file(hash).await?;
+        if let Some(entry) = index.blobs.get_mut(hash) {
+            entry.deduplicated = true;
+        }
+        self.persist(&index).await?;
+        Ok(true)
+    }
+
+    /// How much the chunk store saves
+    pub async fn dedup_stats(&self) -> DedupStats {
+        self.chunks.stats().await
+    }
+
+    /// Contents of an unsealed blob wherever it is kept
+    async fn read_unsealed(&self, hash: &str) -> Result<Vec<u8>, String> {
+        if let Some(data) = self.chunks.get(hash).await? {
+            return Ok(data);
+        }
+        self.fs
+            .read(&self.path(hash))
+            .await
+            .map_err(io_error(ErrorCode::ReadFailed, "Failed to read blob"))
+    }
+
+    /// Delete the blob's own file; returns whether there was one
+    async fn remove_file(&self, hash: &str) -> Result<bool, String> {
+        match tokio::fs::remove_file(self.path(hash)).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => {
+                Err(ServiceError::io(ErrorCode::WriteFailed, "Failed to remove blob", &e).into())
+            }
+        }
+    }
+
+    async fn is_sealed(&self, hash: &str) -> bool {
+        self.index
+            .read()
+            .await
+            .blobs
+            .get(hash)
+            .is_some_and(|entry| entry.sealed)
+    }
+
+    fn required_key(&self) -> Result<[u8; 32], String> {
+        self.key.lock().unwrap().ok_or_else(|| {
+            ServiceError::new(
+                ErrorCode::Unauthorized,
+                "Stored files are encrypted at rest; log in to unlock them",
+            )
+            .into()
+        })
+    }
+
+    /// Write through a temporary file renamed into place
+    async fn write_file(&self, hash: &str, data: &[u8]) -> Result<(), String> {
+        let tmp = self.root.join(format!("{}{}", hash, TMP_SUFFIX));
+        self.fs
+            .write(&tmp, data)
+            .await
+            .map_err(io_error(ErrorCode::WriteFailed, "Failed to write blob"))?;
+        if let Err(e) = tokio::fs::rename(&tmp, self.path(hash)).await {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return Err(ServiceError::io(
+                ErrorCode::WriteFailed,
+                "Failed to move blob into place",
+                &e,
+            )
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Bring the index in line with the directory contents
+    async fn reconcile(&self) -> Result<(), String> {
+        let mut on_disk = BTreeMap::new();
+        let mut dir = tokio::fs::read_dir(&self.root)
+            .await
+            .map_err(|e| format!("Failed to read blob store directory: {}", e))?;
+        while let Some(item) = dir
+            .next_entry()
+            .await
+            .map_err(|e| format!("Failed to read directory entry: {}", e))?
+        {
+            let Some(name) = item.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            if name.ends_with(TMP_SUFFIX) {
+                // Left over from an interrupted put
+                let _ = tokio::fs::remove_file(item.path()).await;
+                continue;
+            }
+            if !is_valid_key(&name) {
+                continue;
+            }
+            match item.metadata().await {
+                Ok(meta) if meta.is_file() => {
+                    // Sealed blobs are recognised by their header, which
+                    // also records their plaintext size
+                    let sealed_size = match self.read_header(&name).await {
+                        Some(header) => sealed_len(&header).ok(),
+                        None => None,
+                    };
+                    on_disk.insert(name, (meta.len(), sealed_size));
+                }
+                _ => {}
+            }
+        }
+
+        // A blob both in the chunk store and in a file was being moved when
+        // the app stopped; the copies hold the same contents
+        let chunked = self.chunks.files().await;
+        for hash in chunked.keys() {
+            if on_disk.remove(hash).is_some() {
+                self.remove_file(hash).await?;
+            }
+        }
+
+        let mut index = self.index.write().await;
+        let before = index.blobs.clone();
+        // Linked files may only be unavailable for now, e.g. on a drive that
+        // isn't mounted, so their entries stay
+        index.blobs.retain(|hash, entry| {
+            entry.linked.is_some() || on_disk.contains_key(hash) || chunked.contains_key(hash)
+        });
+        for (hash, size) in chunked {
+            let entry = index.blobs.entry(hash.clone()).or_insert(BlobEntry {
+                hash,
+                size,
+                stored_at: self.clock.now_secs(),
+                sealed: false,
+                pinned: false,
+                expires_at: None,
+                deduplicated: true,
+                linked: None,
+            });
+            entry.size = size;
+            entry.sealed = false;
+            entry.deduplicated = true;
+            entry.linked = None;
+        }
+        for (hash, (size, sealed_size)) in on_disk {
+            // The file decides whether a blob is sealed, in case the app
+            // stopped while converting it
+            if let Some(entry) = index.blobs.get_mut(&hash) {
+                entry.size = sealed_size.unwrap_or(size);
+                entry.sealed = sealed_size.is_some();
+                entry.deduplicated = false;
+                entry.linked = None;
+                continue;
+            }
+            index.blobs.insert(
+                hash.clone(),
+                BlobEntry {
+                    hash,
+                    size: sealed_size.unwrap_or(size),
+                    stored_at: self.clock.now_secs(),
+                    sealed: sealed_size.is_some(),
+                    pinned: false,
+                    expires_at: None,
+                    deduplicated: false,
+                    linked: None,
+                },
+            );
+        }
+        let dangling: Vec<String> = index
+            .aliases
+            .iter()
+            .filter(|(_, hash)| !index.blobs.contains_key(*hash))
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &dangling {
+            index.aliases.remove(id);
+        }
+        if index.blobs != before || !dangling.is_empty() {
+            self.persist(&index).await?;
+        }
+        Ok(())
+    }
+
+    async fn read_header(&self, hash: &str) -> Option<[u8; SEAL_HEADER_LEN]> {
+        use tokio::io::AsyncReadExt;
+        let mut file = tokio::fs::File::open(self.path(hash)).await.ok()?;
+        let mut header = [0u8; SEAL_HEADER_LEN];
+        file.read_exact(&mut header).await.ok()?;
+        Some(header)
+    }
+
+    async fn persist(&self, index: &BlobIndex) -> Result<(), String> {
+        persisted::save_async(&self.root.join(INDEX_FILE), index).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn put_get_remove_round_trip_survives_reopen() {
+        let dir = tempdir().unwrap();
+        let store = BlobStore::open(dir.path()).await.unwrap();
+
+        store.put("abc123", b"hello").await.unwrap();
+        assert_eq!(
+            store.get("abc123").await.unwrap().as_deref(),
+            Some(&b"hello"[..])
+        );
+        assert_eq!(store.get("missing").await.unwrap(), None);
+        assert_eq!(
+            store.read_range("abc123", 1, 3).await.unwrap().as_deref(),
+            Some(&b"ell"[..])
+        );
+        assert!(store.read_range("abc123", 4, 3).await.is_err());
+        let mut buf = BytesMut::from(&b"stale"[..]);
+        let found = store.read_range_into("abc123", 2, 3, &mut buf).await;
+        assert_eq!((found, &buf[..]), (Ok(true), &b"llo"[..]));
+        let found = store.read_range_into("missing", 0, 1, &mut buf).await;
+        assert_eq!(found, Ok(false));
+        assert_eq!(store.total_bytes().await, 5);
+
+        drop(store);
+        let reopened = BlobStore::open(dir.path()).await.unwrap();
+        assert_eq!(reopened.entry("abc123").await.map(|e| e.size), Some(5));
+
+        assert!(reopened.remove("abc123").await.unwrap());
+        assert!(!reopened.remove("abc123").await.unwrap());
+        assert!(reopened.list().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn reconcile_adopts_legacy_blobs_and_drops_missing_ones() {
+        let dir = tempdir().unwrap();
+        let store = BlobStore::open(dir.path()).await.unwrap();
+        store.put("gone", b"x").await.unwrap();
+        drop(store);
+
+        std::fs::remove_file(dir.path().join("gone")).unwrap();
+        std::fs::write(dir.path().join("legacy"), b"old data").unwrap();
+        std::fs::write(dir.path().join("legacy.meta"), b"{}").unwrap();
+        std::fs::write(dir.path().join("half.blobtmp"), b"partial").unwrap();
+
+        let store = BlobStore::open(dir.path()).await.unwrap();
+        let hashes: Vec<String> = store.list().await.into_iter().map(|e| e.hash).collect();
+        assert_eq!(hashes, vec!["legacy".to_string()]);
+        assert!(!dir.path().join("half.blobtmp").exists());
+    }
+
+    #[tokio::test]
+    async fn sealed_blobs_hold_no_plaintext_and_need_the_key() {
+        let dir = tempdir().unwrap();
+        let store = BlobStore::open(dir.path()).await.unwrap();
+        let data: Vec<u8> = (0..SEAL_SEGMENT * 2 + 100).map(|i| i as u8).collect();
+        store.put("before", b"plain before").await.unwrap();
+        assert!(store.set_encrypt_at_rest(true).await.is_err());
+
+        store.unlock([7u8; 32]).await.unwrap();
+        assert_eq!(store.set_encrypt_at_rest(true).await.unwrap(), 1);
+        store.put("big", &data).await.unwrap();
+        let on_disk = std::fs::read(dir.path().join("before")).unwrap();
+        assert!(!on_disk.windows(5).any(|w| w == b"plain"));
+        assert_eq!(
+            store.entry("big").await.map(|e| e.size),
+            Some(data.len() as u64)
+        );
+
+        let offset = SEAL_SEGMENT as u64 - 10;
+        assert_eq!(
+            store.read_range("big", offset, 30).await.unwrap().unwrap(),
+            data[SEAL_SEGMENT - 10..SEAL_SEGMENT + 20]
+        );
+        assert!(store
+            .read_range("big", data.len() as u64 - 1, 2)
+            .await
+            .is_err());
+
+        drop(store);
+        let reopened = BlobStore::open(dir.path()).await.unwrap();
+        assert!(reopened.get("big").await.is_err());
+        assert!(reopened.unlock([8u8; 32]).await.is_err());
+        reopened.unlock([7u8; 32]).await.unwrap();
+        assert_eq!(reopened.get("big").await.unwrap(), Some(data));
+
+        assert_eq!(reopened.set_encrypt_at_rest(false).await.unwrap(), 2);
+        assert_eq!(
+            std::fs::read(dir.path().join("before")).unwrap(),
+            b"plain before"
+        );
+    }
+
+    #[tokio::test]
+    async fn a_toggle_interrupted_midway_leaves_every_blob_readable() {
+        let dir = tempdir().unwrap();
+        let store = BlobStore::open(dir.path()).await.unwrap();
+        store.put("first", b"first contents").await.unwrap();
+        store.put("second", b"second contents").await.unwrap();
+        store.unlock([7u8; 32]).await.unwrap();
+        assert_eq!(store.set_encrypt_at_rest(true).await.unwrap(), 2);
+
+        // The app stopped after writing one blob back out in plain but
+        // before the index recorded it
+        store.write_file("first", b"first contents").await.unwrap();
+        drop(store);
+
+        let reopened = BlobStore::open(dir.path()).await.unwrap();
+        let first = reopened.entry("first").await.unwrap();
+        assert_eq!((first.sealed, first.size), (false, 14));
+        assert_eq!(
+            reopened.get("first").await.unwrap().as_deref(),
+            Some(&b"first contents"[..])
+        );
+        assert!(reopened.entry("second").await.unwrap().sealed);
+        reopened.unlock([7u8; 32]).await.unwrap();
+        assert_eq!(reopened.set_encrypt_at_rest(false).await.unwrap(), 1);
+        assert_eq!(
+            reopened.get("second").await.unwrap().as_deref(),
+            Some(&b"second contents"[..])
+        );
+    }
+
+    #[tokio::test]
+    async fn eviction_skips_pinned_blobs() {
+        let dir = tempdir().unwrap();
+        let store = BlobStore::open(dir.path()).await.unwrap();
+        for hash in ["old", "pinned", "new"] {
+            store.put(hash, b"0123456789").await.unwrap();
+        }
+        {
+            // Spread the write times so the eviction order is known
+            let mut index = store.index.write().await;
+            for (i, hash) in ["pinned", "old", "new"].iter().enumerate() {
+                index.blobs.get_mut(*hash).unwrap().stored_at = i as u64;
+            }
+        }
+        let pinned = store.set_pinned("pinned", true).await.unwrap();
+        assert!(pinned.is_some_and(|e| e.pinned));
+        assert!(store.set_pinned("missing", true).await.unwrap().is_none());
+        // Rewriting keeps the pin
+        store.put("pinned", b"0123456789").await.unwrap();
+
+        let evicted: Vec<String> = store
+            .evict_to(15)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.hash)
+            .collect();
+        assert_eq!(evicted, vec!["old".to_string(), "new".to_string()]);
+        assert!(store.evict_to(0).await.unwrap().is_empty());
+        assert!(store.contains("pinned").await);
+
+        drop(store);
+        let reopened = BlobStore::open(dir.path()).await.unwrap();
+        assert!(reopened.entry("pinned").await.unwrap().pinned);
+        reopened.set_pinned("pinned", false).await.unwrap();
+        assert_eq!(reopened.evict_to(0).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn lookups_do_not_wait_for_each_other() {
+        let dir = tempdir().unwrap();
+        let store = BlobStore::open(dir.path()).await.unwrap();
+        store.put("a", b"0123456789").await.unwrap();
+
+        let reader = store.index.read().await;
+        let lookups = async {
+            let entry = store.entry("a").await;
+            let range = store.read_range("a", 2, 3).await.unwrap();
+            (entry, range, store.list().await.len())
+        };
+        let (entry, range, listed) = tokio::time::timeout(Duration::from_secs(5), lookups)
+            .await
+            .expect("lookups wait for another reader");
+        assert_eq!(entry.map(|e| e.size), Some(10));
+        assert_eq!(range.as_deref(), Some(&b"234"[..]));
+        assert_eq!(listed, 1);
+
+        let pinning = store.set_pinned("a", true);
+        tokio::pin!(pinning);
+        let blocked = tokio::time::timeout(Duration::from_millis(50), &mut pinning).await;
+        assert!(blocked.is_err(), "index changes wait for readers");
+        drop(reader);
+        assert!(pinning.await.unwrap().is_some_and(|e| e.pinned));
+    }
+
+    #[tokio::test]
+    async fn expiry_survives_reopen_and_spares_pinned_blobs() {
+        let dir = tempdir().unwrap();
+        let store = BlobStore::open(dir.path()).await.unwrap();
+        for hash in ["temp", "kept", "forever"] {
+            store.put(hash, b"x").await.unwrap();
+        }
+        store.set_expiry("temp", Some(100)).await.unwrap();
+        store.set_expiry("kept", Some(100)).await.unwrap();
+        store.set_pinned("kept", true).await.unwrap();
+        assert!(store
+            .set_expiry("missing", Some(1))
+            .await
+            .unwrap()
+            .is_none());
+
+        drop(store);
+        let store = BlobStore::open(dir.path()).await.unwrap();
+        assert!(store.expired(99).await.is_empty());
+        let expired: Vec<String> = store
+            .expired(100)
+            .await
+            .into_iter()
+            .map(|e| e.hash)
+            .collect();
+        assert_eq!(expired, vec!["temp".to_string()]);
+
+        // Storing the blob again makes it permanent
+        store.put("temp", b"x").await.unwrap();
+        assert!(store.expired(100).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn deduplicated_blobs_share_chunks_and_move_back_out() {
+        let dir = tempdir().unwrap();
+        let store = BlobStore::open(dir.path()).await.unwrap();
+        let mut seed = 1u64;
+        let data: Vec<u8> = (0..1_000_000)
+            .map(|_| {
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                (seed >> 33) as u8
+            })
+            .collect();
+        let mut copy = data.clone();
+        copy.extend_from_slice(b"trailer");
+        store.put("plain", &data).await.unwrap();
+        assert_eq!(store.set_deduplicate(true).await.unwrap(), 1);
+        assert!(!dir.path().join("plain").exists());
+        store.put("copy", &copy).await.unwrap();
+        assert!(store.entry("copy").await.unwrap().deduplicated);
+        let stats = store.dedup_stats().await;
+        assert_eq!(stats.logical_bytes, (data.len() + copy.len()) as u64);
+        assert!(stats.stored_bytes < stats.logical_bytes * 3 / 4);
+
+        assert!(store.remove("plain").await.unwrap());
+        assert!(!store.contains("plain").await);
+        assert_eq!(store.get("copy").await.unwrap(), Some(copy.clone()));
+        assert_eq!(
+            store.read_range("copy", 999_998, 4).await.unwrap().unwrap(),
+            copy[999_998..1_000_002]
+        );
+
+        drop(store);
+        let store = BlobStore::open(dir.path()).await.unwrap();
+        assert!(store.deduplicates().await);
+        assert_eq!(
+            store.entry("copy").await.map(|e| e.size),
+            Some(copy.len() as u64)
+        );
+        assert_eq!(store.set_deduplicate(false).await.unwrap(), 1);
+        assert_eq!(std::fs::read(dir.path().join("copy")).unwrap(), copy);
+        assert_eq!(store.dedup_stats().await.chunks, 0);
+    }
+
+    #[tokio::test]
+    async fn linked_blobs_are_read_in_place_and_never_deleted() {
+        let dir = tempdir().unwrap();
+        let library = tempdir().unwrap();
+        let source = library.path().join("movie.mkv");
+        std::fs::write(&source, b"feature film").unwrap();
+        let store = BlobStore::open(dir.path()).await.unwrap();
+
+        let (linked, size) = LinkedFile::stat(&source).await.unwrap();
+        std::fs::write(&source, b"feature film, director's cut").unwrap();
+        assert!(store.link("movie", linked, size).await.is_err());
+        let (linked, size) = LinkedFile::stat(&source).await.unwrap();
+        store.link("movie", linked, size).await.unwrap();
+        assert!(store.contains("movie").await);
+        assert!(!dir.path().join("movie").exists());
+        assert_eq!(
+            store.read_range("movie", 14, 8).await.unwrap().as_deref(),
+            Some(&b"director"[..])
+        );
+        assert_eq!(store.total_bytes().await, 0);
+        assert!(store.evict_to(0).await.unwrap().is_empty());
+
+        drop(store);
+        let store = BlobStore::open(dir.path()).await.unwrap();
+        assert_eq!(store.get("movie").await.unwrap().map(|d| d.len()), Some(28));
+
+        // An edited file no longer matches its hash
+        std::fs::write(&source, b"remastered").unwrap();
+        assert!(store.get("movie").await.is_err());
+        assert!(!store.contains("movie").await);
+        assert!(store.remove("movie").await.unwrap());
+        assert!(source.exists());
+        assert!(store.entry("movie").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn taken_blobs_come_back_with_their_pin_and_drop_their_aliases() {
+        let dir = tempdir().unwrap();
+        let out = tempdir().unwrap();
+        let store = BlobStore::open(dir.path()).await.unwrap();
+        store.set_deduplicate(true).await.unwrap();
+        store.put("abc123", b"shared bytes").await.unwrap();
+        store.set_pinned("abc123", true).await.unwrap();
+        store.add_alias("root1", "abc123").await.unwrap();
+        assert!(store.add_alias("root2", "missing").await.is_err());
+        assert_eq!(
+            store.resolve_alias("root1").await.as_deref(),
+            Some("abc123")
+        );
+
+        let dest = out.path().join("abc123");
+        let entry = store.take("abc123", &dest).await.unwrap().unwrap();
+        assert!(entry.deduplicated && entry.pinned);
+        assert_eq!(std::fs::read(&dest).unwrap(), b"shared bytes");
+        assert!(store.entry("abc123").await.is_none());
+        assert_eq!(store.resolve_alias("root1").await, None);
+        assert_eq!(store.take("abc123", &dest).await.unwrap(), None);
+
+        store.set_deduplicate(false).await.unwrap();
+        let restored = store.restore(&entry, &dest).await.unwrap();
+        assert!(restored.pinned && !restored.deduplicated);
+        assert!(!dest.exists());
+        assert_eq!(
+            store.get("abc123").await.unwrap().as_deref(),
+            Some(&b"shared bytes"[..])
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_keys_that_could_escape_the_root() {
+        let dir = tempdir().unwrap();
+        let store = BlobStore::open(dir.path()).await.unwrap();
+        assert!(store.put("../evil", b"x").await.is_err());
+        assert!(store.get("a/b").await.is_err());
+        assert!(!store.contains("..").await);
+    }
+}
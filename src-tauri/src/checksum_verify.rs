@@ -0,0 +1,317 @@
+// Verification against external checksum files
+//
+// Software is usually released with a SHA256SUMS, MD5SUMS or B3SUMS file next
+// to the artifacts. Users who redistribute a release over Chiral can check a
+// downloaded copy against that file, or against a digest pasted from the
+// upstream release page. Both the GNU (`<digest>  <name>`, `*` for binary
+// mode) and BSD (`SHA256 (<name>) = <digest>`) line formats are accepted;
+// comments and the armor of clearsigned files are skipped. The signature
+// itself is not checked.
+//
+// A 64-character digest can be SHA-256 or BLAKE3; unless the algorithm is
+// known from the line, the file name or the caller, both are computed and
+// whichever matches is reported.
+
+use md5::Md5;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumAlgorithm {
+    Sha256,
+    Md5,
+    Blake3,
+}
+
+/// Where the expected checksums come from
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "camelCase")]
+pub enum ChecksumSource {
+    /// Path to a checksum file
+    File(String),
+    /// Pasted checksum file contents or a bare digest
+    Text(String),
+}
+
+/// One line of a checksum file
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChecksumEntry {
+    pub digest: String,
+    /// `None` for a bare digest
+    pub name: Option<String>,
+    /// Known from a BSD-style tag
+    pub algorithm: Option<ChecksumAlgorithm>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChecksumReport {
+    pub file_path: String,
+    pub algorithm: ChecksumAlgorithm,
+    pub expected: String,
+    pub actual: String,
+    pub matches: bool,
+    /// Name of the checksum file entry compared against
+    pub entry_name: Option<String>,
+}
+
+impl ChecksumAlgorithm {
+    fn from_tag(tag: &str) -> Option<Self> {
+        match tag.to_ascii_uppercase().replace('-', "").as_str() {
+            "SHA256" => Some(Self::Sha256),
+            "MD5" => Some(Self::Md5),
+            "BLAKE3" | "B3" => Some(Self::Blake3),
+            _ => None,
+        }
+    }
+
+    /// Guess from a checksum file name such as `SHA256SUMS` or `app.b3`
+    pub fn from_file_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        if name.contains("sha256") {
+            Some(Self::Sha256)
+        } else if name.contains("md5") {
+            Some(Self::Md5)
+        } else if name.contains("blake3") || name.contains("b3sum") || name.ends_with(".b3") {
+            Some(Self::Blake3)
+        } else {
+            None
+        }
+    }
+
+    fn digest_len(self) -> usize {
+        match self {
+            Self::Md5 => 32,
+            Self::Sha256 | Self::Blake3 => 64,
+        }
+    }
+}
+
+fn is_hex_digest(s: &str) -> bool {
+    (s.len() == 32 || s.len() == 64) && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn parse_line(line: &str) -> Option<ChecksumEntry> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    // BSD: `SHA256 (name) = digest`
+    if let Some((tag, rest)) = line.split_once(" (") {
+        if let Some((name, digest)) = rest.rsplit_once(") = ") {
+            let algorithm = ChecksumAlgorithm::from_tag(tag.trim())?;
+            let digest = digest.trim();
+            return (digest.len() == algorithm.digest_len() && is_hex_digest(digest)).then(|| {
+                ChecksumEntry {
+                    digest: digest.to_ascii_lowercase(),
+                    name: Some(name.to_string()),
+                    algorithm: Some(algorithm),
+                }
+            });
+        }
+    }
+    // GNU: `digest  name` or `digest *name`, or a bare digest
+    let (digest, name) = match line.split_once(char::is_whitespace) {
+        Some((digest, name)) => {
+            let name = name.trim_start();
+            let name = name.strip_prefix('*').unwrap_or(name);
+            (digest, (!name.is_empty()).then(|| name.to_string()))
+        }
+        None => (line, None),
+    };
+    is_hex_digest(digest).then(|| ChecksumEntry {
+        digest: digest.to_ascii_lowercase(),
+        name,
+        algorithm: None,
+    })
+}
+
+/// Entries of a checksum file; lines that are not checksums are skipped
+pub fn parse_checksums(contents: &str) -> Vec<ChecksumEntry> {
+    contents.lines().filter_map(parse_line).collect()
+}
+
+/// The entry for `file_name`: an exact name match, else a path ending in
+/// it, else the only entry when there is just one
+pub fn find_entry<'a>(entries: &'a [ChecksumEntry], file_name: &str) -> Option<&'a ChecksumEntry> {
+    let base = |name: &str| name.rsplit(['/', '\\']).next().unwrap_or(name).to_string();
+    entries
+        .iter()
+        .find(|e| e.name.as_deref() == Some(file_name))
+        .or_else(|| {
+            entries
+                .iter()
+                .find(|e| e.name.as_deref().map(base).as_deref() == Some(file_name))
+        })
+        .or_else(|| match entries {
+            [only] => Some(only),
+            _ => None,
+        })
+}
+
+/// Hex digests of `path` for `algorithms`, computed in one pass
+pub fn hash_file(
+    path: &Path,
+    algorithms: &[ChecksumAlgorithm],
+) -> Result<Vec<(ChecksumAlgorithm, String)>, String> {
+    let mut file = std::fs::File::open(path).map_err(|e| format!("Failed to open file: {}", e))?;
+    let mut sha256 = Sha256::new();
+    let mut md5 = Md5::new();
+    let mut blake3 = blake3::Hasher::new();
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .map_err(|e| format!("Failed to read file: {}", e))?;
+        if n == 0 {
+            break;
+        }
+        for algorithm in algorithms {
+            match algorithm {
+                ChecksumAlgorithm::Sha256 => sha256.update(&buf[..n]),
+                ChecksumAlgorithm::Md5 => md5.update(&buf[..n]),
+                ChecksumAlgorithm::Blake3 => {
+                    blake3.update(&buf[..n]);
+                }
+            }
+        }
+    }
+    let sha256 = format!("{:x}", sha256.finalize());
+    let md5 = format!("{:x}", md5.finalize());
+    let blake3 = blake3.finalize().to_hex().to_string();
+    Ok(algorithms
+        .iter()
+        .map(|algorithm| {
+            let digest = match algorithm {
+                ChecksumAlgorithm::Sha256 => sha256.clone(),
+                ChecksumAlgorithm::Md5 => md5.clone(),
+                ChecksumAlgorithm::Blake3 => blake3.clone(),
+            };
+            (*algorithm, digest)
+        })
+        .collect())
+}
+
+/// Check `file_path` against the entry for it in `source`. `algorithm`
+/// overrides what the checksum file or digest length suggests.
+pub fn verify(
+    file_path: &Path,
+    source: &ChecksumSource,
+    algorithm: Option<ChecksumAlgorithm>,
+) -> Result<ChecksumReport, String> {
+    let (contents, source_name) = match source {
+        ChecksumSource::File(path) => (
+            std::fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read checksum file: {}", e))?,
+            Path::new(path)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string()),
+        ),
+        ChecksumSource::Text(text) => (text.clone(), None),
+    };
+    let entries = parse_checksums(&contents);
+    if entries.is_empty() {
+        return Err("No checksums found".to_string());
+    }
+    let file_name = file_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let entry = find_entry(&entries, &file_name)
+        .ok_or_else(|| format!("No checksum listed for {}", file_name))?;
+
+    let candidates = match algorithm.or(entry.algorithm).or_else(|| {
+        source_name
+            .as_deref()
+            .and_then(ChecksumAlgorithm::from_file_name)
+    }) {
+        Some(algorithm) => vec![algorithm],
+        None if entry.digest.len() == 32 => vec![ChecksumAlgorithm::Md5],
+        None => vec![ChecksumAlgorithm::Sha256, ChecksumAlgorithm::Blake3],
+    };
+    if candidates
+        .iter()
+        .any(|a| a.digest_len() != entry.digest.len())
+    {
+        return Err(format!(
+            "A {}-character digest cannot be {:?}",
+            entry.digest.len(),
+            candidates[0]
+        ));
+    }
+
+    let digests = hash_file(file_path, &candidates)?;
+    let (algorithm, actual) = digests
+        .iter()
+        .find(|(_, digest)| *digest == entry.digest)
+        .unwrap_or(&digests[0])
+        .clone();
+    Ok(ChecksumReport {
+        file_path: file_path.to_string_lossy().to_string(),
+        algorithm,
+        matches: actual == entry.digest,
+        expected: entry.digest.clone(),
+        actual,
+        entry_name: entry.name.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_formats_and_verifies() {
+        let sums = "\
+-----BEGIN PGP SIGNED MESSAGE-----
+Hash: SHA256
+
+# release 1.2
+d41d8cd98f00b204e9800998ecf8427e  empty.txt
+2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824 *dist/hello.txt
+BLAKE3 (hello.txt) = ea8f163db38682925e4491c5e58d4bb3506ef8c14eb78a86e908c5624a67200f
+";
+        let entries = parse_checksums(sums);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].name.as_deref(), Some("dist/hello.txt"));
+        assert_eq!(entries[2].algorithm, Some(ChecksumAlgorithm::Blake3));
+        assert_eq!(find_entry(&entries, "hello.txt"), Some(&entries[2]));
+        assert_eq!(find_entry(&entries, "other.txt"), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hello.txt");
+        std::fs::write(&path, "hello").unwrap();
+
+        // BSD line for the exact name wins; BLAKE3 is taken from its tag
+        let report = verify(&path, &ChecksumSource::Text(sums.into()), None).unwrap();
+        assert_eq!(report.algorithm, ChecksumAlgorithm::Blake3);
+        assert!(report.matches);
+
+        // A bare 64-character digest is tried as SHA-256 and BLAKE3
+        let sha = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        let report = verify(&path, &ChecksumSource::Text(sha.into()), None).unwrap();
+        assert_eq!(report.algorithm, ChecksumAlgorithm::Sha256);
+        assert!(report.matches);
+
+        let md5 = "5d41402abc4b2a76b9719d911017c592";
+        let report = verify(&path, &ChecksumSource::Text(md5.to_uppercase()), None).unwrap();
+        assert!(report.matches);
+
+        let report = verify(
+            &path,
+            &ChecksumSource::Text(format!("{}  hello.txt", "0".repeat(64))),
+            None,
+        )
+        .unwrap();
+        assert!(!report.matches);
+        assert!(verify(
+            &path,
+            &ChecksumSource::Text(md5.into()),
+            Some(ChecksumAlgorithm::Sha256)
+        )
+        .is_err());
+    }
+}
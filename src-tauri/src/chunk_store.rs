@@ -0,0 +1,482 @@
+// Deduplicated chunk store
+//
+// Blob contents can be kept as content-defined chunks instead of one file per
+// blob. Chunk boundaries are picked by a rolling gear hash over the data, so
+// an insertion or deletion only changes the chunks around it, and files that
+// share long runs of bytes share most of their chunks. Every chunk is stored
+// once, named by the SHA-256 of its contents, and `index.json` records the
+// chunks each file is made of (its recipe).
+//
+// Reference counts are derived from the recipes when the store is opened and
+// kept up to date as files come and go. A chunk file is only deleted once no
+// recipe refers to it, so removing one file never takes data that another
+// file still uses. Chunks are written before the recipe that needs them is
+// recorded and deleted only after the recipe is gone, so a crash can leave
+// unreferenced chunk files behind but never a recipe with missing chunks.
+// Those leftovers are swept when the store is opened.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use tokio::sync::RwLock;
+use tracing::warn;
+
+const INDEX_FILE: &str = "index.json";
+const TMP_SUFFIX: &str = ".chunktmp";
+
+/// No boundary is looked for before this many bytes
+pub const MIN_CHUNK: usize = 16 * 1024;
+/// A chunk is cut here if no boundary was found
+pub const MAX_CHUNK: usize = 256 * 1024;
+/// A boundary is where the top 16 bits of the gear hash are zero, giving
+/// chunks of 64 KiB past the minimum on average
+const CUT_MASK: u64 = 0xFFFF << 48;
+
+/// Random but fixed per-byte values of the gear hash (splitmix64 output).
+/// Changing them moves every boundary, which only costs deduplication
+/// between chunks written before and after.
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+static GEAR: [u64; 256] = gear_table();
+
+/// Length of the chunk at the start of `data`
+fn next_cut(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK {
+        return data.len();
+    }
+    let end = data.len().min(MAX_CHUNK);
+    let mut hash = 0u64;
+    for (i, byte) in data[..end].iter().enumerate().skip(MIN_CHUNK) {
+        hash = (hash << 1).wrapping_add(GEAR[*byte as usize]);
+        if hash & CUT_MASK == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
+
+/// Split `data` into content-defined chunks
+pub fn split(data: &[u8]) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let (chunk, tail) = rest.split_at(next_cut(rest));
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
+
+/// One chunk of a recipe
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ChunkRef {
+    /// SHA-256 of the chunk, hex
+    pub hash: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ChunkIndex {
+    /// Chunks of every stored file, in order
+    files: BTreeMap<String, Vec<ChunkRef>>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ChunkCount {
+    size: u64,
+    refs: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DedupStats {
+    pub files: usize,
+    pub chunks: usize,
+    /// Combined size of the stored files
+    pub logical_bytes: u64,
+    /// Bytes the chunks actually take up
+    pub stored_bytes: u64,
+}
+
+struct State {
+    index: ChunkIndex,
+    /// Recipe entries referring to each chunk
+    counts: BTreeMap<String, ChunkCount>,
+}
+
+pub struct ChunkStore {
+    dir: PathBuf,
+    state: RwLock<State>,
+}
+
+fn count_refs(index: &ChunkIndex) -> BTreeMap<String, ChunkCount> {
+    let mut counts: BTreeMap<String, ChunkCount> = BTreeMap::new();
+    for chunk in index.files.values().flatten() {
+        counts
+            .entry(chunk.hash.clone())
+            .or_insert(ChunkCount {
+                size: chunk.size,
+                refs: 0,
+            })
+            .refs += 1;
+    }
+    counts
+}
+
+impl ChunkStore {
+    pub async fn open(dir: impl Into<PathBuf>) -> Result<Self, String> {
+        let dir = dir.into();
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .map_err(|e| format!("Failed to create chunk store directory: {}", e))?;
+        let mut index: ChunkIndex = match tokio::fs::read_to_string(dir.join(INDEX_FILE)).await {
+            Ok(raw) => serde_json::from_str(&raw).unwrap_or_else(|e| {
+                warn!("Chunk index is unreadable, starting empty: {}", e);
+                ChunkIndex::default()
+            }),
+            Err(_) => ChunkIndex::default(),
+        };
+
+        let on_disk = Self::chunk_files(&dir).await?;
+        // A recipe whose chunks are gone can't be read back
+        let before = index.files.len();
+        index.files.retain(|key, chunks| {
+            let complete = chunks.iter().all(|chunk| on_disk.contains(&chunk.hash));
+            if !complete {
+                warn!("Dropping deduplicated file {} with missing chunks", key);
+            }
+            complete
+        });
+        let changed = index.files.len() != before;
+        let counts = count_refs(&index);
+        for name in on_disk.iter().filter(|name| !counts.contains_key(*name)) {
+            let _ = tokio::fs::remove_file(dir.join(name)).await;
+        }
+
+        let store = Self {
+            dir,
+            state: RwLock::new(State { index, counts }),
+        };
+        if changed {
+            let state = store.state.read().await;
+            store.persist(&state.index).await?;
+        }
+        Ok(store)
+    }
+
+    /// Names of the chunk files in `dir`, removing interrupted writes
+    async fn chunk_files(dir: &Path) -> Result<HashSet<String>, String> {
+        let mut names = HashSet::new();
+        let mut entries = tokio::fs::read_dir(dir)
+            .await
+            .map_err(|e| format!("Failed to read chunk store directory: {}", e))?;
+        while let Some(item) = entries
+            .next_entry()
+            .await
+            .map_err(|e| format!("Failed to read directory entry: {}", e))?
+        {
+            let Some(name) = item.file_name().to_str().map(str::to_string) else {
+                continue;
+            };
+            if name.ends_with(TMP_SUFFIX) {
+                let _ = tokio::fs::remove_file(item.path()).await;
+            } else if name != INDEX_FILE && !name.ends_with(".tmp") {
+                names.insert(name);
+            }
+        }
+        Ok(names)
+    }
+
+    fn chunk_path(&self, hash: &str) -> PathBuf {
+        self.dir.join(hash)
+    }
+
+    /// Store `data` under `key`, replacing what was stored under it before.
+    /// Returns how many bytes of new chunks had to be written.
+    pub async fn put(&self, key: &str, data: &[u8]) -> Result<u64, String> {
+        let mut state = self.state.write().await;
+        let mut recipe = Vec::new();
+        let mut written = HashSet::new();
+        let mut new_bytes = 0;
+        for chunk in split(data) {
+            let hash = hex::encode(Sha256::digest(chunk));
+            if !state.counts.contains_key(&hash) && written.insert(hash.clone()) {
+                self.write_chunk(&hash, chunk).await?;
+                new_bytes += chunk.len() as u64;
+            }
+            recipe.push(ChunkRef {
+                hash,
+                size: chunk.len() as u64,
+            });
+        }
+
+        // Count the new references before dropping the old ones so chunks
+        // shared by both versions survive
+        for chunk in &recipe {
+            state
+                .counts
+                .entry(chunk.hash.clone())
+                .or_insert(ChunkCount {
+                    size: chunk.size,
+                    refs: 0,
+                })
+                .refs += 1;
+        }
+        let old = state.index.files.insert(key.to_string(), recipe);
+        let unused = Self::release(&mut state, old.unwrap_or_default());
+        self.persist(&state.index).await?;
+        self.delete_chunks(&unused).await;
+        Ok(new_bytes)
+    }
+
+    /// Contents of the file, or `None` if it isn't stored
+    pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        let Some(recipe) = self.recipe(key).await else {
+            return Ok(None);
+        };
+        let mut data = Vec::with_capacity(recipe.iter().map(|c| c.size as usize).sum());
+        for chunk in &recipe {
+            data.extend_from_slice(&self.read_chunk(&chunk.hash).await?);
+        }
+        Ok(Some(data))
+    }
+
+    /// `len` bytes of the file starting at `offset`, or `None` if it isn't
+    /// stored. Reads past the end are an error.
+    pub async fn read_range(
+        &self,
+        key: &str,
+        offset: u64,
+        len: usize,
+    ) -> Result<Option<Vec<u8>>, String> {
+        let Some(recipe) = self.recipe(key).await else {
+            return Ok(None);
+        };
+        let total: u64 = recipe.iter().map(|c| c.size).sum();
+        let end = offset.saturating_add(len as u64);
+        if end > total {
+            return Err(format!(
+                "Failed to read blob: range ends at {}, blob has {} bytes",
+                end, total
+            ));
+        }
+        let mut data = Vec::with_capacity(len);
+        let mut chunk_start = 0u64;
+        for chunk in &recipe {
+            let chunk_end = chunk_start + chunk.size;
+            if chunk_end > offset && chunk_start < end {
+                let bytes = self.read_chunk(&chunk.hash).await?;
+                let from = offset.saturating_sub(chunk_start) as usize;
+                let to = (end.min(chunk_end) - chunk_start) as usize;
+                data.extend_from_slice(&bytes[from..to]);
+            }
+            chunk_start = chunk_end;
+        }
+        Ok(Some(data))
+    }
+
+    pub async fn contains(&self, key: &str) -> bool {
+        self.state.read().await.index.files.contains_key(key)
+    }
+
+    /// Every stored file with its size
+    pub async fn files(&self) -> BTreeMap<String, u64> {
+        self.state
+            .read()
+            .await
+            .index
+            .files
+            .iter()
+            .map(|(key, chunks)| (key.clone(), chunks.iter().map(|c| c.size).sum()))
+            .collect()
+    }
+
+    /// Drop the file, deleting the chunks nothing else refers to. Returns
+    /// whether it was stored.
+    pub async fn remove(&self, key: &str) -> Result<bool, String> {
+        let mut state = self.state.write().await;
+        let Some(recipe) = state.index.files.remove(key) else {
+            return Ok(false);
+        };
+        let unused = Self::release(&mut state, recipe);
+        self.persist(&state.index).await?;
+        self.delete_chunks(&unused).await;
+        Ok(true)
+    }
+
+    pub async fn stats(&self) -> DedupStats {
+        let state = self.state.read().await;
+        DedupStats {
+            files: state.index.files.len(),
+            chunks: state.counts.len(),
+            logical_bytes: state.index.files.values().flatten().map(|c| c.size).sum(),
+            stored_bytes: state.counts.values().map(|c| c.size).sum(),
+        }
+    }
+
+    async fn recipe(&self, key: &str) -> Option<Vec<ChunkRef>> {
+        self.state.read().await.index.files.get(key).cloned()
+    }
+
+    /// Drop one reference per recipe entry. Returns the chunks no longer
+    /// referenced at all.
+    fn release(state: &mut State, recipe: Vec<ChunkRef>) -> Vec<String> {
+        let mut unused = Vec::new();
+        for chunk in recipe {
+            if let Some(count) = state.counts.get_mut(&chunk.hash) {
+                count.refs -= 1;
+                if count.refs == 0 {
+                    state.counts.remove(&chunk.hash);
+                    unused.push(chunk.hash);
+                }
+            }
+        }
+        unused
+    }
+
+    async fn delete_chunks(&self, hashes: &[String]) {
+        for hash in hashes {
+            if let Err(e) = tokio::fs::remove_file(self.chunk_path(hash)).await {
+                // Swept on the next open
+                warn!("Failed to delete unused chunk {}: {}", hash, e);
+            }
+        }
+    }
+
+    async fn read_chunk(&self, hash: &str) -> Result<Vec<u8>, String> {
+        tokio::fs::read(self.chunk_path(hash))
+            .await
+            .map_err(|e| format!("Failed to read chunk {}: {}", hash, e))
+    }
+
+    async fn write_chunk(&self, hash: &str, data: &[u8]) -> Result<(), String> {
+        let tmp = self.dir.join(format!("{}{}", hash, TMP_SUFFIX));
+        tokio::fs::write(&tmp, data)
+            .await
+            .map_err(|e| format!("Failed to write chunk: {}", e))?;
+        if let Err(e) = tokio::fs::rename(&tmp, self.chunk_path(hash)).await {
+            let _ = tokio::fs::remove_file(&tmp).await;
+            return Err(format!("Failed to move chunk into place: {}", e));
+        }
+        Ok(())
+    }
+
+    async fn persist(&self, index: &ChunkIndex) -> Result<(), String> {
+        let json = serde_json::to_vec(index)
+            .map_err(|e| format!("Failed to serialize chunk index: {}", e))?;
+        let tmp = self.dir.join(format!("{}.tmp", INDEX_FILE));
+        tokio::fs::write(&tmp, json)
+            .await
+            .map_err(|e| format!("Failed to write chunk index: {}", e))?;
+        tokio::fs::rename(&tmp, self.dir.join(INDEX_FILE))
+            .await
+            .map_err(|e| format!("Failed to write chunk index: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    /// Bytes that don't repeat, so every chunk is distinct
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state
+                    .wrapping_mul(6364136223846793005)
+                    .wrapping_add(1442695040888963407);
+                (state >> 33) as u8
+            })
+            .collect()
+    }
+
+    fn chunk_file_count(dir: &Path) -> usize {
+        std::fs::read_dir(dir)
+            .unwrap()
+            .filter(|e| e.as_ref().unwrap().file_name() != INDEX_FILE)
+            .count()
+    }
+
+    #[test]
+    fn boundaries_follow_content_not_offsets() {
+        let data = noise(2 * 1024 * 1024, 1);
+        let chunks = split(&data);
+        assert!(chunks.len() > 4);
+        assert!(chunks.iter().all(|c| c.len() <= MAX_CHUNK));
+        assert!(chunks[..chunks.len() - 1]
+            .iter()
+            .all(|c| c.len() >= MIN_CHUNK));
+        assert_eq!(chunks.concat(), data);
+
+        // Shifting the data by a few bytes keeps most boundaries
+        let mut shifted = b"prefix".to_vec();
+        shifted.extend_from_slice(&data);
+        let before: HashSet<&[u8]> = chunks.into_iter().collect();
+        let shared = split(&shifted)
+            .into_iter()
+            .filter(|c| before.contains(c))
+            .count();
+        assert!(shared + 1 >= before.len());
+        assert!(split(&[]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn shared_chunks_are_stored_once_and_outlive_one_file() {
+        let dir = tempdir().unwrap();
+        let store = ChunkStore::open(dir.path()).await.unwrap();
+        let original = noise(1024 * 1024, 7);
+        let mut edited = original.clone();
+        edited.splice(500_000..500_000, b"inserted".iter().copied());
+
+        let first = store.put("original", &original).await.unwrap();
+        assert_eq!(first, original.len() as u64);
+        let second = store.put("edited", &edited).await.unwrap();
+        assert!(second < edited.len() as u64 / 2);
+        let stats = store.stats().await;
+        assert_eq!(stats.files, 2);
+        assert_eq!(stats.logical_bytes, (original.len() + edited.len()) as u64);
+        assert_eq!(stats.stored_bytes, first + second);
+        assert_eq!(chunk_file_count(dir.path()), stats.chunks);
+
+        assert!(store.remove("original").await.unwrap());
+        assert!(!store.remove("original").await.unwrap());
+        assert_eq!(store.get("original").await.unwrap(), None);
+        assert_eq!(store.get("edited").await.unwrap(), Some(edited.clone()));
+        assert_eq!(
+            store.read_range("edited", 499_990, 30).await.unwrap(),
+            Some(edited[499_990..500_020].to_vec())
+        );
+        assert!(store
+            .read_range("edited", edited.len() as u64, 1)
+            .await
+            .is_err());
+        let stats = store.stats().await;
+        assert_eq!(stats.stored_bytes, edited.len() as u64);
+        assert_eq!(chunk_file_count(dir.path()), stats.chunks);
+
+        // Counts are rebuilt from the recipes and stray chunks swept
+        std::fs::write(dir.path().join("stray"), b"orphan").unwrap();
+        drop(store);
+        let store = ChunkStore::open(dir.path()).await.unwrap();
+        assert_eq!(store.stats().await, stats);
+        assert!(!dir.path().join("stray").exists());
+        assert!(store.remove("edited").await.unwrap());
+        assert_eq!(chunk_file_count(dir.path()), 0);
+    }
+}
@@ -0,0 +1,422 @@
+// Content type detection and basic metadata extraction
+//
+// At upload time the file's type is detected from its leading bytes rather
+// than trusting the extension, and a few cheap properties are read from the
+// container headers: image dimensions, audio duration and document titles.
+// The result is stored in the file manifest (and the MIME type on the DHT
+// metadata) so listings can show it and searches can filter by type without
+// downloading the file.
+//
+// Only the first and last `SAMPLE_SIZE` bytes are read. Anything that cannot
+// be determined from them is left as None.
+
+use serde::{Deserialize, Serialize};
+use std::io::SeekFrom;
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+const SAMPLE_SIZE: usize = 64 * 1024;
+const MAX_TITLE_LEN: usize = 200;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ContentInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_secs: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Preview assets generated for the file, fetched under its published id
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preview: Option<crate::media_preview::PreviewRef>,
+}
+
+/// MIME type from a file's leading bytes
+pub fn detect_mime(header: &[u8]) -> Option<&'static str> {
+    let starts = |magic: &[u8]| header.starts_with(magic);
+    let at = |offset: usize, magic: &[u8]| header.get(offset..offset + magic.len()) == Some(magic);
+
+    let mime = if starts(b"\xFF\xD8\xFF") {
+        "image/jpeg"
+    } else if starts(b"\x89PNG\r\n\x1A\n") {
+        "image/png"
+    } else if starts(b"GIF87a") || starts(b"GIF89a") {
+        "image/gif"
+    } else if starts(b"RIFF") && at(8, b"WEBP") {
+        "image/webp"
+    } else if starts(b"RIFF") && at(8, b"WAVE") {
+        "audio/wav"
+    } else if starts(b"RIFF") && at(8, b"AVI ") {
+        "video/x-msvideo"
+    } else if starts(b"BM") && header.len() >= 26 && at(14, &[40, 0, 0, 0]) {
+        "image/bmp"
+    } else if starts(b"II*\0") || starts(b"MM\0*") {
+        "image/tiff"
+    } else if starts(b"%PDF-") {
+        "application/pdf"
+    } else if starts(b"fLaC") {
+        "audio/flac"
+    } else if starts(b"OggS") {
+        "audio/ogg"
+    } else if starts(b"ID3") || is_mpeg_frame(header) {
+        "audio/mpeg"
+    } else if at(4, b"ftyp") {
+        match header.get(8..12) {
+            Some(b"qt  ") => "video/quicktime",
+            Some(b"M4A ") | Some(b"M4B ") => "audio/mp4",
+            _ => "video/mp4",
+        }
+    } else if starts(b"\x1A\x45\xDF\xA3") {
+        if contains(header, b"webm") {
+            "video/webm"
+        } else {
+            "video/x-matroska"
+        }
+    } else if starts(b"PK\x03\x04") {
+        "application/zip"
+    } else if starts(b"\x1F\x8B") {
+        "application/gzip"
+    } else if starts(b"7z\xBC\xAF\x27\x1C") {
+        "application/x-7z-compressed"
+    } else if starts(b"Rar!\x1A\x07") {
+        "application/x-rar-compressed"
+    } else if header.get(257..262) == Some(&b"ustar"[..]) {
+        "application/x-tar"
+    } else {
+        return detect_text(header);
+    };
+    Some(mime)
+}
+
+fn detect_text(header: &[u8]) -> Option<&'static str> {
+    if header.is_empty() || header.contains(&0) {
+        return None;
+    }
+    let text = String::from_utf8_lossy(header);
+    let lower = text.trim_start_matches('\u{FEFF}').trim_start().to_ascii_lowercase();
+    if lower.starts_with("<!doctype html") || lower.starts_with("<html") {
+        Some("text/html")
+    } else if lower.starts_with("<svg") || (lower.starts_with("<?xml") && lower.contains("<svg")) {
+        Some("image/svg+xml")
+    } else if lower.starts_with("<?xml") {
+        Some("application/xml")
+    } else if std::str::from_utf8(header).is_ok() {
+        Some("text/plain")
+    } else {
+        None
+    }
+}
+
+/// MPEG audio frame sync: 11 set bits
+fn is_mpeg_frame(b: &[u8]) -> bool {
+    b.len() >= 2 && b[0] == 0xFF && b[1] & 0xE0 == 0xE0
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn u16_be(b: &[u8], at: usize) -> Option<u32> {
+    Some(u16::from_be_bytes(b.get(at..at + 2)?.try_into().ok()?) as u32)
+}
+
+fn u16_le(b: &[u8], at: usize) -> Option<u32> {
+    Some(u16::from_le_bytes(b.get(at..at + 2)?.try_into().ok()?) as u32)
+}
+
+fn u32_be(b: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(b.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn u32_le(b: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(b.get(at..at + 4)?.try_into().ok()?))
+}
+
+/// Width and height from an image header
+fn image_dimensions(mime: &str, b: &[u8]) -> Option<(u32, u32)> {
+    match mime {
+        "image/png" => Some((u32_be(b, 16)?, u32_be(b, 20)?)),
+        "image/gif" => Some((u16_le(b, 6)?, u16_le(b, 8)?)),
+        "image/bmp" => {
+            let width = u32_le(b, 18)? as i32;
+            let height = u32_le(b, 22)? as i32;
+            Some((width.unsigned_abs(), height.unsigned_abs()))
+        }
+        "image/webp" => match b.get(12..16)? {
+            b"VP8 " => Some((u16_le(b, 26)? & 0x3FFF, u16_le(b, 28)? & 0x3FFF)),
+            b"VP8L" => {
+                let bits = u32_le(b, 21)?;
+                Some(((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1))
+            }
+            b"VP8X" => {
+                let w = u32_le(b, 24)? & 0xFF_FFFF;
+                let h = u32_le(b, 27)? & 0xFF_FFFF;
+                Some((w + 1, h + 1))
+            }
+            _ => None,
+        },
+        "image/jpeg" => jpeg_dimensions(b),
+        _ => None,
+    }
+}
+
+/// Walk JPEG segments until a start-of-frame marker
+fn jpeg_dimensions(b: &[u8]) -> Option<(u32, u32)> {
+    let mut i = 2;
+    while i + 9 < b.len() {
+        if b[i] != 0xFF {
+            return None;
+        }
+        let marker = b[i + 1];
+        let is_sof = matches!(marker, 0xC0..=0xCF) && !matches!(marker, 0xC4 | 0xC8 | 0xCC);
+        if is_sof {
+            return Some((u16_be(b, i + 7)?, u16_be(b, i + 5)?));
+        }
+        i += 2 + u16_be(b, i + 2)? as usize;
+    }
+    None
+}
+
+/// Duration of an audio file from its header and total size
+fn audio_duration(mime: &str, b: &[u8], file_size: u64) -> Option<f64> {
+    match mime {
+        "audio/wav" => {
+            let fmt = find(b, b"fmt ")?;
+            let byte_rate = u32_le(b, fmt + 16)?;
+            let data_size = match find(b, b"data") {
+                Some(data) => u32_le(b, data + 4)? as u64,
+                None => file_size,
+            };
+            (byte_rate > 0).then(|| data_size as f64 / byte_rate as f64)
+        }
+        "audio/flac" => {
+            // STREAMINFO is the first metadata block: 20-bit sample rate,
+            // then 36-bit total sample count
+            let info = b.get(18..26)?;
+            let sample_rate =
+                ((info[0] as u32) << 12) | ((info[1] as u32) << 4) | (info[2] >> 4) as u32;
+            let total_samples = (((info[3] & 0x0F) as u64) << 32)
+                | u32::from_be_bytes(info[4..8].try_into().ok()?) as u64;
+            (sample_rate > 0 && total_samples > 0)
+                .then(|| total_samples as f64 / sample_rate as f64)
+        }
+        "audio/mpeg" => mp3_duration(b, file_size),
+        _ => None,
+    }
+}
+
+/// Estimate from the first frame's bitrate (exact for constant-bitrate files)
+fn mp3_duration(b: &[u8], file_size: u64) -> Option<f64> {
+    let mut start = 0usize;
+    if b.starts_with(b"ID3") {
+        let tag = b.get(6..10)?;
+        let size = tag.iter().fold(0usize, |acc, byte| (acc << 7) | (*byte & 0x7F) as usize);
+        start = 10 + size;
+    }
+    let frame = b.get(start..start + 4)?;
+    if !is_mpeg_frame(frame) {
+        return None;
+    }
+    // MPEG-1 Layer III bitrates, kbps
+    const BITRATES: [u32; 16] = [
+        0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 0,
+    ];
+    let is_mpeg1_layer3 = frame[1] & 0x18 == 0x18 && frame[1] & 0x06 == 0x02;
+    if !is_mpeg1_layer3 {
+        return None;
+    }
+    let kbps = BITRATES[(frame[2] >> 4) as usize];
+    let audio_bytes = file_size.saturating_sub(start as u64);
+    (kbps > 0).then(|| audio_bytes as f64 * 8.0 / (kbps as f64 * 1000.0))
+}
+
+/// Title of a PDF (Info dictionary) or HTML document
+fn document_title(mime: &str, sample: &[u8]) -> Option<String> {
+    let title = match mime {
+        "text/html" => {
+            let text = String::from_utf8_lossy(sample);
+            let lower = text.to_ascii_lowercase();
+            let open = lower.find("<title")?;
+            let start = open + lower[open..].find('>')? + 1;
+            let end = start + lower[start..].find("</title")?;
+            text[start..end].to_string()
+        }
+        "application/pdf" => {
+            let at = find(sample, b"/Title")? + b"/Title".len();
+            let rest = &sample[at..];
+            let open = rest.iter().position(|c| !c.is_ascii_whitespace())?;
+            if rest[open] != b'(' {
+                // Hex and indirect titles are not decoded
+                return None;
+            }
+            let mut depth = 0;
+            let mut title = Vec::new();
+            let mut escaped = false;
+            for &c in &rest[open + 1..] {
+                match c {
+                    _ if escaped => {
+                        title.push(c);
+                        escaped = false;
+                    }
+                    b'\\' => escaped = true,
+                    b'(' => {
+                        depth += 1;
+                        title.push(c);
+                    }
+                    b')' if depth == 0 => break,
+                    b')' => {
+                        depth -= 1;
+                        title.push(c);
+                    }
+                    _ => title.push(c),
+                }
+            }
+            String::from_utf8_lossy(&title).into_owned()
+        }
+        _ => return None,
+    };
+    let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!title.is_empty()).then(|| title.chars().take(MAX_TITLE_LEN).collect())
+}
+
+/// Whether `mime_type` matches a search filter: a full type ("video/mp4") or
+/// just the top-level type ("video")
+pub fn matches_type_filter(mime_type: Option<&str>, filter: &str) -> bool {
+    let Some(mime_type) = mime_type else {
+        return false;
+    };
+    let filter = filter.trim().to_ascii_lowercase();
+    let mime_type = mime_type.to_ascii_lowercase();
+    if filter.contains('/') {
+        mime_type == filter
+    } else {
+        mime_type.split('/').next() == Some(filter.as_str())
+    }
+}
+
+/// Lowercased extension of a file name, without the dot
+pub fn extension(file_name: &str) -> Option<String> {
+    Path::new(file_name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .filter(|ext| !ext.is_empty())
+        .map(str::to_ascii_lowercase)
+}
+
+/// When the file at `path` was created, Unix seconds. Falls back to the
+/// modification time where the filesystem does not record creation.
+pub async fn created_at(path: &Path) -> Option<u64> {
+    let meta = tokio::fs::metadata(path).await.ok()?;
+    let created = meta.created().or_else(|_| meta.modified()).ok()?;
+    created
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()
+        .map(|since| since.as_secs())
+}
+
+/// Content info from the start and end of a file
+pub fn analyze(head: &[u8], tail: &[u8], file_size: u64) -> ContentInfo {
+    let Some(mime) = detect_mime(head) else {
+        return ContentInfo::default();
+    };
+    let mut info = ContentInfo {
+        mime_type: Some(mime.to_string()),
+        ..ContentInfo::default()
+    };
+    if let Some((width, height)) = image_dimensions(mime, head) {
+        info.width = Some(width);
+        info.height = Some(height);
+    }
+    info.duration_secs = audio_duration(mime, head, file_size);
+    // PDF info dictionaries usually sit near the end of the file
+    info.title = document_title(mime, head).or_else(|| document_title(mime, tail));
+    info
+}
+
+/// Detect the type of the file at `path` and extract what metadata its
+/// headers allow
+pub async fn extract(path: &Path) -> Result<ContentInfo, String> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| format!("Failed to open file: {}", e))?;
+    let file_size = file
+        .metadata()
+        .await
+        .map_err(|e| format!("Failed to read file metadata: {}", e))?
+        .len();
+
+    let mut head = Vec::with_capacity(SAMPLE_SIZE);
+    (&mut file)
+        .take(SAMPLE_SIZE as u64)
+        .read_to_end(&mut head)
+        .await
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+
+    let mut tail = Vec::new();
+    if file_size > (SAMPLE_SIZE * 2) as u64 {
+        file.seek(SeekFrom::End(-(SAMPLE_SIZE as i64)))
+            .await
+            .map_err(|e| format!("Failed to seek file: {}", e))?;
+        file.read_to_end(&mut tail)
+            .await
+            .map_err(|e| format!("Failed to read file: {}", e))?;
+    }
+    Ok(analyze(&head, &tail, file_size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_types_and_extracts_metadata() {
+        let mut png = b"\x89PNG\r\n\x1A\n\0\0\0\x0DIHDR".to_vec();
+        png.extend(640u32.to_be_bytes());
+        png.extend(480u32.to_be_bytes());
+        let info = analyze(&png, &[], png.len() as u64);
+        assert_eq!(info.mime_type.as_deref(), Some("image/png"));
+        assert_eq!((info.width, info.height), (Some(640), Some(480)));
+
+        // 1 second of 8kHz mono 8-bit audio
+        let mut wav = b"RIFF\0\0\0\0WAVEfmt \x10\0\0\0\x01\0\x01\0".to_vec();
+        wav.extend(8000u32.to_le_bytes()); // sample rate
+        wav.extend(8000u32.to_le_bytes()); // byte rate
+        wav.extend(b"\x01\0\x08\0data");
+        wav.extend(8000u32.to_le_bytes());
+        let info = analyze(&wav, &[], wav.len() as u64 + 8000);
+        assert_eq!(info.mime_type.as_deref(), Some("audio/wav"));
+        assert_eq!(info.duration_secs, Some(1.0));
+
+        let pdf = b"%PDF-1.4\n1 0 obj << /Title (Annual \\(draft\\) Report) >> endobj";
+        let info = analyze(pdf, &[], pdf.len() as u64);
+        assert_eq!(info.mime_type.as_deref(), Some("application/pdf"));
+        assert_eq!(info.title.as_deref(), Some("Annual (draft) Report"));
+
+        let html = b"<!DOCTYPE html><html><head><TITLE>\n  Hello   page </TITLE>";
+        assert_eq!(analyze(html, &[], 0).title.as_deref(), Some("Hello page"));
+
+        // The extension does not matter, only the bytes
+        assert_eq!(detect_mime(b"PK\x03\x04rest"), Some("application/zip"));
+        assert_eq!(detect_mime(b"plain notes"), Some("text/plain"));
+        assert_eq!(detect_mime(b"\0\x01\x02binary"), None);
+
+        assert!(matches_type_filter(Some("video/mp4"), "Video"));
+        assert!(matches_type_filter(Some("video/mp4"), "video/mp4"));
+        assert!(!matches_type_filter(Some("video/webm"), "video/mp4"));
+        assert!(!matches_type_filter(None, "image"));
+
+        assert_eq!(extension("Report.PDF").as_deref(), Some("pdf"));
+        assert_eq!(extension("archive.tar.gz").as_deref(), Some("gz"));
+        assert_eq!(extension(".env"), None);
+        assert_eq!(extension("README"), None);
+    }
+}
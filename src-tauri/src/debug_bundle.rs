@@ -0,0 +1,373 @@
+// Debug bundle export
+//
+// Collects the diagnostics a bug report needs (redacted settings, recent logs,
+// metrics and network summaries, version info) into one zip archive. Every
+// bundle carries a `manifest.json` listing what was included and what was
+// deliberately left out, so users can check what they are about to share.
+//
+// Secrets never enter the bundle: every entry passes through `redact_json`
+// or `redact_text` on the way in, and keystores, wallets and stored file
+// contents are never read. Besides values of sensitive-looking keys, that
+// strips private keys, filesystem paths and peer IDs from all text. Peer
+// IDs become short pseudonyms, so lines about the same peer can still be
+// matched up.
+
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::io::{Seek, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+const REDACTED: &str = "[redacted]";
+const REDACTED_PATH: &str = "[path]";
+
+/// Protocols a multiaddr can start with, so addresses aren't mistaken for
+/// file paths
+const MULTIADDR_PROTOCOLS: &[&str] = &["ip4", "ip6", "dns", "dns4", "dns6", "dnsaddr", "p2p"];
+
+/// Normalized key fragments whose values are replaced by `redact_json`
+const SENSITIVE_KEY_PARTS: &[&str] = &[
+    "privatekey",
+    "password",
+    "passphrase",
+    "secret",
+    "mnemonic",
+    "seedphrase",
+    "token",
+    "apikey",
+    "credential",
+];
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct VersionInfo {
+    pub app_version: String,
+    pub os: String,
+    pub arch: String,
+    pub family: String,
+}
+
+impl VersionInfo {
+    pub fn current() -> Self {
+        Self {
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            family: std::env::consts::FAMILY.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IncludedEntry {
+    /// Path inside the archive
+    pub path: String,
+    pub description: String,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExcludedEntry {
+    pub item: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DebugBundleManifest {
+    /// Where the bundle was written; empty until `write_to` succeeds
+    pub path: String,
+    /// Unix timestamp (seconds)
+    pub created_at: u64,
+    pub version: VersionInfo,
+    pub included: Vec<IncludedEntry>,
+    pub excluded: Vec<ExcludedEntry>,
+}
+
+/// Accumulates bundle entries in memory, then writes them as one zip
+pub struct DebugBundleBuilder {
+    entries: Vec<(String, Vec<u8>)>,
+    manifest: DebugBundleManifest,
+}
+
+/// Replace the values of sensitive-looking keys anywhere in `value`, and
+/// pass every other string, object keys included, through `redact_text`
+pub fn redact_json(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            let entries = std::mem::take(map);
+            for (key, mut child) in entries {
+                let normalized: String = key
+                    .chars()
+                    .filter(|c| c.is_ascii_alphanumeric())
+                    .map(|c| c.to_ascii_lowercase())
+                    .collect();
+                if SENSITIVE_KEY_PARTS.iter().any(|part| normalized.contains(part)) {
+                    child = Value::String(REDACTED.to_string());
+                } else {
+                    redact_json(&mut child);
+                }
+                map.insert(redact_text(&key), child);
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_json),
+        Value::String(text) => *text = redact_text(text),
+        _ => {}
+    }
+}
+
+/// Strip private keys and filesystem paths from free text and replace peer
+/// IDs by pseudonyms
+pub fn redact_text(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let at_boundary = out.chars().last().map_or(true, |prev| {
+            !prev.is_alphanumeric() && prev != '/' && prev != '\\'
+        });
+        if at_boundary {
+            if let Some(len) = path_len(rest) {
+                out.push_str(REDACTED_PATH);
+                rest = &rest[len..];
+                continue;
+            }
+        }
+        if c.is_ascii_alphanumeric() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len());
+            let (word, tail) = rest.split_at(len);
+            redact_word(word, &mut out);
+            rest = tail;
+            continue;
+        }
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    out
+}
+
+/// Length of the file path `text` starts with, if it starts with one
+fn path_len(text: &str) -> Option<usize> {
+    let end = text
+        .find(|c: char| c.is_whitespace() || "\"'`,;()[]{}<>".contains(c))
+        .unwrap_or(text.len());
+    let candidate = &text[..end];
+    let bytes = candidate.as_bytes();
+    let is_path = if let Some(unix) = candidate.strip_prefix('/') {
+        let first = unix.split('/').next().unwrap_or_default();
+        unix.len() > 1 && !first.is_empty() && !MULTIADDR_PROTOCOLS.contains(&first)
+    } else if candidate.starts_with("~/") {
+        true
+    } else {
+        bytes.len() > 3
+            && bytes[0].is_ascii_alphabetic()
+            && bytes[1] == b':'
+            && (bytes[2] == b'\\' || bytes[2] == b'/')
+    };
+    is_path.then_some(end)
+}
+
+fn redact_word(word: &str, out: &mut String) {
+    let is_hex = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_hexdigit());
+    let is_base58 = word
+        .bytes()
+        .all(|b| b.is_ascii_alphanumeric() && !b"0IOl".contains(&b));
+    if word.len() == 66 && word.starts_with("0x") && is_hex(&word[2..]) {
+        // Private keys; a 64 hex digit value without the prefix is far more
+        // likely a content hash and is kept
+        out.push_str(REDACTED);
+    } else if is_base58
+        && ((word.starts_with("12D3KooW") && word.len() >= 50)
+            || (word.starts_with("Qm") && word.len() == 46))
+    {
+        let digest = Sha256::digest(word.as_bytes());
+        out.push_str("peer-");
+        out.push_str(&hex::encode(&digest[..4]));
+    } else {
+        out.push_str(word);
+    }
+}
+
+impl DebugBundleBuilder {
+    pub fn new(version: VersionInfo) -> Self {
+        Self {
+            entries: Vec::new(),
+            manifest: DebugBundleManifest {
+                path: String::new(),
+                created_at: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+                version,
+                included: Vec::new(),
+                excluded: Vec::new(),
+            },
+        }
+    }
+
+    /// Add `contents` after passing it through `redact_text`
+    pub fn add_text(&mut self, path: &str, description: &str, contents: &str) {
+        self.add_bytes(path, description, redact_text(contents).into_bytes());
+    }
+
+    /// Add `value` as pretty-printed JSON after passing it through
+    /// `redact_json`; serialization failures are recorded as exclusions
+    /// rather than aborting the bundle
+    pub fn add_json<T: Serialize>(&mut self, path: &str, description: &str, value: &T) {
+        let bytes = serde_json::to_value(value).and_then(|mut value| {
+            redact_json(&mut value);
+            serde_json::to_vec_pretty(&value)
+        });
+        match bytes {
+            Ok(bytes) => self.add_bytes(path, description, bytes),
+            Err(e) => self.exclude(path, &format!("Failed to serialize: {}", e)),
+        }
+    }
+
+    pub fn exclude(&mut self, item: &str, reason: &str) {
+        self.manifest.excluded.push(ExcludedEntry {
+            item: item.to_string(),
+            reason: reason.to_string(),
+        });
+    }
+
+    fn add_bytes(&mut self, path: &str, description: &str, bytes: Vec<u8>) {
+        self.manifest.included.push(IncludedEntry {
+            path: path.to_string(),
+            description: description.to_string(),
+            bytes: bytes.len() as u64,
+        });
+        self.entries.push((path.to_string(), bytes));
+    }
+
+    /// Write all entries plus the manifest into `writer` as a zip archive
+    pub fn write_zip<W: Write + Seek>(
+        &self,
+        writer: W,
+        manifest: &DebugBundleManifest,
+    ) -> Result<(), String> {
+        let mut zip = ZipWriter::new(writer);
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+        let manifest_json = serde_json::to_vec_pretty(manifest)
+            .map_err(|e| format!("Failed to serialize bundle manifest: {}", e))?;
+        let files = std::iter::once((MANIFEST_FILE, manifest_json.as_slice()))
+            .chain(self.entries.iter().map(|(p, b)| (p.as_str(), b.as_slice())));
+        for (path, bytes) in files {
+            zip.start_file(path, options)
+                .map_err(|e| format!("Failed to add {} to bundle: {}", path, e))?;
+            zip.write_all(bytes)
+                .map_err(|e| format!("Failed to write {} to bundle: {}", path, e))?;
+        }
+        zip.finish()
+            .map_err(|e| format!("Failed to finish bundle: {}", e))?;
+        Ok(())
+    }
+
+    /// Write the bundle to `path`, creating parent directories as needed
+    pub fn write_to(self, path: &Path) -> Result<DebugBundleManifest, String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create bundle directory: {}", e))?;
+        }
+        let mut manifest = self.manifest.clone();
+        manifest.path = path.to_string_lossy().to_string();
+
+        let file = std::fs::File::create(path)
+            .map_err(|e| format!("Failed to create bundle file: {}", e))?;
+        self.write_zip(file, &manifest)?;
+        Ok(manifest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+
+    #[test]
+    fn settings_are_redacted_recursively() {
+        let mut settings = serde_json::json!({
+            "storagePath": "/data",
+            "privateKey": "0xabc",
+            "proxy": { "authToken": "t", "port": 8080 },
+            "accounts": [{ "wallet_password": "p", "address": "0x1" }]
+        });
+        redact_json(&mut settings);
+        assert_eq!(settings["storagePath"], REDACTED_PATH);
+        assert_eq!(settings["privateKey"], REDACTED);
+        assert_eq!(settings["proxy"]["authToken"], REDACTED);
+        assert_eq!(settings["proxy"]["port"], 8080);
+        assert_eq!(settings["accounts"][0]["wallet_password"], REDACTED);
+        assert_eq!(settings["accounts"][0]["address"], "0x1");
+    }
+
+    #[test]
+    fn text_is_stripped_of_keys_paths_and_peer_ids() {
+        let peer = "12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDEp";
+        let key = format!("0x{}", "ab".repeat(32));
+        let hash = "cd".repeat(32);
+        let line = format!(
+            "peer {peer} sent {hash} to /home/alice/Downloads/report.pdf via \
+             /ip4/1.2.3.4/tcp/4001/p2p/{peer} (key={key}, dir=C:\\Users\\alice)"
+        );
+        let redacted = redact_text(&line);
+        let pseudonym = redacted.split(' ').nth(1).unwrap().to_string();
+        assert!(pseudonym.starts_with("peer-"));
+        assert_eq!(
+            redacted,
+            format!(
+                "peer {pseudonym} sent {hash} to [path] via \
+                 /ip4/1.2.3.4/tcp/4001/p2p/{pseudonym} (key=[redacted], dir=[path])"
+            )
+        );
+
+        let mut events = serde_json::json!({});
+        events[peer] = serde_json::json!({ "outputPath": "~/x.bin" });
+        redact_json(&mut events);
+        assert_eq!(events[&pseudonym]["outputPath"], REDACTED_PATH);
+    }
+
+    #[test]
+    fn bundle_contains_manifest_and_entries() {
+        let mut builder = DebugBundleBuilder::new(VersionInfo::current());
+        builder.add_text("logs/recent.log", "Recent log lines", "line one\n");
+        builder.add_json(
+            "config/settings.json",
+            "Settings",
+            serde_json::json!({ "secretKey": "s" }),
+        );
+        builder.exclude("keystore", "Contains private keys");
+
+        let mut buffer = Cursor::new(Vec::new());
+        builder.write_zip(&mut buffer, &builder.manifest).unwrap();
+
+        let mut archive = zip::ZipArchive::new(buffer).unwrap();
+        assert_eq!(archive.len(), 3);
+        let mut manifest = String::new();
+        archive
+            .by_name(MANIFEST_FILE)
+            .unwrap()
+            .read_to_string(&mut manifest)
+            .unwrap();
+        let manifest: Value = serde_json::from_str(&manifest).unwrap();
+        assert_eq!(manifest["included"].as_array().unwrap().len(), 2);
+        assert_eq!(manifest["excluded"][0]["item"], "keystore");
+
+        let mut settings = String::new();
+        archive
+            .by_name("config/settings.json")
+            .unwrap()
+            .read_to_string(&mut settings)
+            .unwrap();
+        assert!(!settings.contains("\"s\""));
+    }
+}
@@ -0,0 +1,271 @@
+// Per-hash demand statistics
+//
+// Counts incoming download requests per file hash, including requests for
+// content we do not have, so seeders can see which files are worth keeping
+// pinned and which are the first candidates for eviction. Serving paths call
+// `global().record(..)` once per request (WebRTC file requests, HTTP metadata
+// lookups).
+//
+// Memory is bounded: at most `MAX_TRACKED_HASHES` hashes are kept (the least
+// recently requested is dropped first) and unique requesters are only counted
+// up to `MAX_REQUESTERS_PER_HASH`.
+
+use crate::runtime_env::{Clock, SystemClock};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+pub const MAX_TRACKED_HASHES: usize = 10_000;
+pub const MAX_REQUESTERS_PER_HASH: usize = 256;
+
+static GLOBAL_DEMAND: Lazy<DemandTracker> = Lazy::new(DemandTracker::new);
+
+/// The process-wide demand tracker
+pub fn global() -> &'static DemandTracker {
+    &GLOBAL_DEMAND
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct HashDemand {
+    pub file_hash: String,
+    pub requests: u64,
+    /// Requests we could not serve (file not stored locally)
+    pub unserved: u64,
+    /// Distinct requesting peers, capped at `MAX_REQUESTERS_PER_HASH`
+    pub unique_requesters: usize,
+    /// Unix timestamps (seconds)
+    pub first_requested_at: u64,
+    pub last_requested_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DemandReport {
+    pub most_requested: Vec<HashDemand>,
+    /// Locally stored files ordered from least to most requested
+    pub eviction_candidates: Vec<HashDemand>,
+    pub tracked_hashes: usize,
+    pub total_requests: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct DemandEntry {
+    requests: u64,
+    unserved: u64,
+    requesters: HashSet<String>,
+    first_requested_at: u64,
+    last_requested_at: u64,
+}
+
+pub struct DemandTracker {
+    entries: Mutex<HashMap<String, DemandEntry>>,
+    persist_path: Mutex<Option<PathBuf>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl DemandEntry {
+    fn summary(&self, file_hash: &str) -> HashDemand {
+        HashDemand {
+            file_hash: file_hash.to_string(),
+            requests: self.requests,
+            unserved: self.unserved,
+            unique_requesters: self.requesters.len(),
+            first_requested_at: self.first_requested_at,
+            last_requested_at: self.last_requested_at,
+        }
+    }
+}
+
+impl DemandTracker {
+    pub fn new() -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            persist_path: Mutex::new(None),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Read the time from `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Load counts saved at `path` and save there from now on
+    pub fn load(&self, path: impl AsRef<Path>) {
+        let path = path.as_ref().to_path_buf();
+        let saved = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|raw| serde_json::from_str::<HashMap<String, DemandEntry>>(&raw).ok())
+            .unwrap_or_default();
+        if let Ok(mut entries) = self.entries.lock() {
+            for (hash, entry) in saved {
+                entries.entry(hash).or_insert(entry);
+            }
+        }
+        if let Ok(mut persist_path) = self.persist_path.lock() {
+            *persist_path = Some(path);
+        }
+    }
+
+    /// Write the counts to the path given to `load`; a no-op without one
+    pub fn save(&self) -> Result<(), String> {
+        let Some(path) = self.persist_path.lock().ok().and_then(|p| p.clone()) else {
+            return Ok(());
+        };
+        let json = {
+            let entries = self
+                .entries
+                .lock()
+                .map_err(|_| "Demand statistics lock poisoned".to_string())?;
+            serde_json::to_string(&*entries)
+                .map_err(|e| format!("Failed to serialize demand statistics: {}", e))?
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create data directory: {}", e))?;
+        }
+        std::fs::write(&path, json)
+            .map_err(|e| format!("Failed to save demand statistics: {}", e))
+    }
+
+    /// Count one request for `file_hash`. `served` is false when the content
+    /// was not available locally.
+    pub fn record(&self, file_hash: &str, requester: Option<&str>, served: bool) {
+        self.record_at(file_hash, requester, served, self.clock.now_secs());
+    }
+
+    fn record_at(&self, file_hash: &str, requester: Option<&str>, served: bool, now: u64) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        if !entries.contains_key(file_hash) && entries.len() >= MAX_TRACKED_HASHES {
+            let stalest = entries
+                .iter()
+                .min_by_key(|(_, e)| e.last_requested_at)
+                .map(|(hash, _)| hash.clone());
+            if let Some(hash) = stalest {
+                entries.remove(&hash);
+            }
+        }
+
+        let entry = entries.entry(file_hash.to_string()).or_insert_with(|| DemandEntry {
+            first_requested_at: now,
+            ..DemandEntry::default()
+        });
+        entry.requests += 1;
+        if !served {
+            entry.unserved += 1;
+        }
+        entry.last_requested_at = now;
+        if let Some(peer) = requester {
+            if entry.requesters.len() < MAX_REQUESTERS_PER_HASH {
+                entry.requesters.insert(peer.to_string());
+            }
+        }
+    }
+
+    pub fn get(&self, file_hash: &str) -> Option<HashDemand> {
+        let entries = self.entries.lock().ok()?;
+        entries.get(file_hash).map(|e| e.summary(file_hash))
+    }
+
+    /// Hashes with the most requests, most recent first on ties
+    pub fn most_requested(&self, limit: usize) -> Vec<HashDemand> {
+        let Ok(entries) = self.entries.lock() else {
+            return Vec::new();
+        };
+        let mut all: Vec<HashDemand> = entries.iter().map(|(h, e)| e.summary(h)).collect();
+        all.sort_by(|a, b| {
+            b.requests
+                .cmp(&a.requests)
+                .then(b.last_requested_at.cmp(&a.last_requested_at))
+        });
+        all.truncate(limit);
+        all
+    }
+
+    /// `stored` hashes ordered from least to most requested; never-requested
+    /// files come first, then those requested longest ago on ties
+    pub fn eviction_candidates(&self, stored: &[String], limit: usize) -> Vec<HashDemand> {
+        let Ok(entries) = self.entries.lock() else {
+            return Vec::new();
+        };
+        let mut candidates: Vec<HashDemand> = stored
+            .iter()
+            .map(|hash| match entries.get(hash) {
+                Some(entry) => entry.summary(hash),
+                None => DemandEntry::default().summary(hash),
+            })
+            .collect();
+        candidates.sort_by(|a, b| {
+            a.requests
+                .cmp(&b.requests)
+                .then(a.last_requested_at.cmp(&b.last_requested_at))
+        });
+        candidates.truncate(limit);
+        candidates
+    }
+
+    /// Most requested hashes plus eviction candidates among `stored`
+    pub fn report(&self, stored: &[String], limit: usize) -> DemandReport {
+        let (tracked_hashes, total_requests) = self
+            .entries
+            .lock()
+            .map(|e| (e.len(), e.values().map(|entry| entry.requests).sum::<u64>()))
+            .unwrap_or_default();
+        DemandReport {
+            most_requested: self.most_requested(limit),
+            eviction_candidates: self.eviction_candidates(stored, limit),
+            tracked_hashes,
+            total_requests,
+        }
+    }
+}
+
+impl Default for DemandTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranks_popular_and_unpopular_hashes() {
+        let tracker = DemandTracker::new();
+        tracker.record_at("popular", Some("peer-a"), true, 100);
+        tracker.record_at("popular", Some("peer-b"), true, 200);
+        tracker.record_at("popular", Some("peer-a"), true, 300);
+        tracker.record_at("missing", Some("peer-c"), false, 150);
+        tracker.record_at("stale", None, true, 50);
+
+        let popular = tracker.get("popular").unwrap();
+        assert_eq!(popular.requests, 3);
+        assert_eq!(popular.unique_requesters, 2);
+        assert_eq!(popular.first_requested_at, 100);
+        assert_eq!(popular.last_requested_at, 300);
+        assert_eq!(tracker.get("missing").unwrap().unserved, 1);
+
+        let top: Vec<String> = tracker.most_requested(2).into_iter().map(|d| d.file_hash).collect();
+        assert_eq!(top, vec!["popular", "missing"]);
+
+        let stored = vec!["popular".to_string(), "stale".to_string(), "never".to_string()];
+        let evict: Vec<String> = tracker
+            .eviction_candidates(&stored, 10)
+            .into_iter()
+            .map(|d| d.file_hash)
+            .collect();
+        assert_eq!(evict, vec!["never", "stale", "popular"]);
+
+        let report = tracker.report(&stored, 10);
+        assert_eq!(report.tracked_hashes, 3);
+        assert_eq!(report.total_requests, 5);
+    }
+}
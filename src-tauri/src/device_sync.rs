@@ -0,0 +1,353 @@
+// Encrypted cross-device sync bundles
+//
+// Moving to a second device used to mean re-importing every account and
+// re-entering settings, subscriptions and friends by hand. A sync bundle
+// carries all of them in one file: the keystore accounts (still encrypted
+// with their own account passwords), the settings, the publisher
+// subscriptions, the trusted peers and which stored files are pinned. The whole bundle is additionally
+// sealed with AES-256-GCM under a key derived from a passphrase, so it can
+// be sent over any channel, ChiralDrop included.
+//
+// Importing merges rather than replaces: accounts and peers are added or
+// updated, settings are taken key by key, and settings that only make sense
+// on the exporting device (the download folder, missing auto-download
+// folders) are left alone. Pins are applied to the files this device also
+// stores; the rest are reported so they can be downloaded.
+
+use crate::keystore::{EncryptedKeystore, Keystore};
+use crate::settings::SettingsStore;
+use crate::subscriptions::{Subscription, SubscriptionLimits, Subscriptions};
+use crate::trusted_peers::{TrustedPeer, TrustedPeerSettings, TrustedPeers};
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+use base64::{engine::general_purpose, Engine as _};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+
+const FORMAT: &str = "chiral-sync";
+pub const BUNDLE_VERSION: u32 = 1;
+const PBKDF2_ITERATIONS: u32 = 100_000;
+/// Bounds on the iteration count a bundle may ask for, so a crafted file
+/// cannot stall the import
+const MIN_PBKDF2_ITERATIONS: u32 = 10_000;
+const MAX_PBKDF2_ITERATIONS: u32 = 10_000_000;
+/// Settings tied to the exporting device, never imported
+const DEVICE_SETTINGS: &[&str] = &["storagePath", "schemaVersion"];
+pub const MIN_PASSPHRASE_LEN: usize = 8;
+
+/// Everything a sync bundle carries
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SyncBundle {
+    pub exported_at: u64,
+    pub accounts: Vec<EncryptedKeystore>,
+    /// Full settings object as stored
+    pub settings: Option<Value>,
+    pub subscriptions: Vec<Subscription>,
+    pub subscription_limits: Option<SubscriptionLimits>,
+    pub trusted_peers: Vec<TrustedPeer>,
+    pub trusted_peer_settings: Option<TrustedPeerSettings>,
+    /// Hashes of the files pinned in blob storage
+    pub pinned_files: Vec<String>,
+}
+
+/// On-disk form of a bundle
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SealedBundle {
+    format: String,
+    version: u32,
+    iterations: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportSummary {
+    pub accounts: usize,
+    pub settings: bool,
+    pub subscriptions: usize,
+    pub trusted_peers: usize,
+    pub pinned_files: usize,
+    /// Pinned on the exporting device but not stored here
+    pub missing_pins: Vec<String>,
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, iterations, &mut key);
+    key
+}
+
+fn decode(field: &str, value: &str) -> Result<Vec<u8>, String> {
+    general_purpose::STANDARD
+        .decode(value)
+        .map_err(|e| format!("Invalid bundle {}: {}", field, e))
+}
+
+/// Serialize and encrypt `bundle` under `passphrase`
+pub fn seal(bundle: &SyncBundle, passphrase: &str) -> Result<String, String> {
+    if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+        return Err(format!(
+            "Passphrase must be at least {} characters",
+            MIN_PASSPHRASE_LEN
+        ));
+    }
+    let plaintext =
+        serde_json::to_vec(bundle).map_err(|e| format!("Failed to serialize bundle: {}", e))?;
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let key = derive_key(passphrase, &salt, PBKDF2_ITERATIONS);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext.as_ref())
+        .map_err(|e| format!("Encryption failed: {}", e))?;
+    serde_json::to_string_pretty(&SealedBundle {
+        format: FORMAT.to_string(),
+        version: BUNDLE_VERSION,
+        iterations: PBKDF2_ITERATIONS,
+        salt: general_purpose::STANDARD.encode(salt),
+        nonce: general_purpose::STANDARD.encode(nonce),
+        ciphertext: general_purpose::STANDARD.encode(ciphertext),
+    })
+    .map_err(|e| format!("Failed to serialize bundle: {}", e))
+}
+
+/// Decrypt a sealed bundle; a wrong passphrase and a damaged file are
+/// indistinguishable
+pub fn open(sealed: &str, passphrase: &str) -> Result<SyncBundle, String> {
+    let sealed: SealedBundle =
+        serde_json::from_str(sealed).map_err(|_| "Not a Chiral sync bundle".to_string())?;
+    if sealed.format != FORMAT {
+        return Err("Not a Chiral sync bundle".to_string());
+    }
+    if sealed.version > BUNDLE_VERSION {
+        return Err(format!(
+            "Sync bundle version {} is newer than this app supports",
+            sealed.version
+        ));
+    }
+    if !(MIN_PBKDF2_ITERATIONS..=MAX_PBKDF2_ITERATIONS).contains(&sealed.iterations) {
+        return Err(format!(
+            "Unsupported bundle key derivation ({} iterations)",
+            sealed.iterations
+        ));
+    }
+    let nonce = decode("nonce", &sealed.nonce)?;
+    if nonce.len() != 12 {
+        return Err("Invalid bundle nonce".to_string());
+    }
+    let key = derive_key(
+        passphrase,
+        &decode("salt", &sealed.salt)?,
+        sealed.iterations,
+    );
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    let plaintext = cipher
+        .decrypt(
+            Nonce::from_slice(&nonce),
+            decode("ciphertext", &sealed.ciphertext)?.as_ref(),
+        )
+        .map_err(|_| "Wrong passphrase or damaged bundle".to_string())?;
+    serde_json::from_slice(&plaintext).map_err(|e| format!("Failed to parse bundle: {}", e))
+}
+
+/// Collect the current accounts, settings, subscriptions and trusted peers,
+/// along with `pinned_files`
+pub fn collect(
+    settings: &SettingsStore,
+    subscriptions: &Subscriptions,
+    trusted_peers: &TrustedPeers,
+    pinned_files: Vec<String>,
+    exported_at: u64,
+) -> Result<SyncBundle, String> {
+    let subscription_state = subscriptions.state();
+    Ok(SyncBundle {
+        exported_at,
+        accounts: Keystore::load()?.accounts,
+        settings: Some(
+            serde_json::to_value(settings.get())
+                .map_err(|e| format!("Failed to serialize settings: {}", e))?,
+        ),
+        subscriptions: subscription_state.subscriptions,
+        subscription_limits: Some(subscription_state.limits),
+        trusted_peers: trusted_peers.list(),
+        trusted_peer_settings: Some(trusted_peers.settings()),
+        pinned_files,
+    })
+}
+
+/// Add `imported` accounts to `keystore`, replacing those with the same
+/// address. File keys only the local copy of an account holds are kept.
+/// Returns how many were imported.
+pub fn merge_accounts(keystore: &mut Keystore, imported: Vec<EncryptedKeystore>) -> usize {
+    let count = imported.len();
+    for mut account in imported {
+        if let Some(index) = keystore
+            .accounts
+            .iter()
+            .position(|a| a.address == account.address)
+        {
+            let local = keystore.accounts.remove(index);
+            for (file_hash, key) in local.file_encryption_keys {
+                account.file_encryption_keys.entry(file_hash).or_insert(key);
+            }
+        }
+        keystore.accounts.push(account);
+    }
+    count
+}
+
+/// This device's settings with each key of `imported` applied on top,
+/// except the ones tied to the exporting device
+pub fn merge_settings(mut imported: Value, local: &SettingsStore) -> Result<Value, String> {
+    crate::settings::migrate(&mut imported);
+    let Value::Object(imported) = imported else {
+        return Err("Bundle settings must be a JSON object".to_string());
+    };
+    let Ok(Value::Object(mut merged)) = serde_json::to_value(local.get()) else {
+        return Err("Failed to serialize settings".to_string());
+    };
+    for (key, value) in imported {
+        if !DEVICE_SETTINGS.contains(&key.as_str()) {
+            merged.insert(key, value);
+        }
+    }
+    Ok(Value::Object(merged))
+}
+
+/// Merge `bundle` into this device's stores. Pins are left to the caller,
+/// which owns the blob store.
+pub fn apply(
+    bundle: SyncBundle,
+    settings: &SettingsStore,
+    subscriptions: &Subscriptions,
+    trusted_peers: &TrustedPeers,
+) -> Result<ImportSummary, String> {
+    let mut summary = ImportSummary::default();
+    if !bundle.accounts.is_empty() {
+        let mut keystore = Keystore::load()?;
+        summary.accounts = merge_accounts(&mut keystore, bundle.accounts);
+        keystore.save()?;
+    }
+    if let Some(value) = bundle.settings {
+        settings.replace(merge_settings(value, settings)?)?;
+        summary.settings = true;
+    }
+    for mut subscription in bundle.subscriptions {
+        if subscription
+            .download_dir
+            .as_deref()
+            .is_some_and(|dir| !std::path::Path::new(dir).is_dir())
+        {
+            subscription.download_dir = None;
+        }
+        subscriptions.subscribe(subscription)?;
+        summary.subscriptions += 1;
+    }
+    if let Some(limits) = bundle.subscription_limits {
+        subscriptions.set_limits(limits)?;
+    }
+    for peer in bundle.trusted_peers {
+        trusted_peers.add(&peer.peer_id, peer.label)?;
+        summary.trusted_peers += 1;
+    }
+    if let Some(peer_settings) = bundle.trusted_peer_settings {
+        trusted_peers.set_settings(peer_settings)?;
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::keystore::EncryptedFileKey;
+    use libp2p::PeerId;
+
+    #[test]
+    fn sealed_bundle_round_trips_and_merges() {
+        let peers = TrustedPeers::new();
+        let friend = PeerId::random().to_string();
+        peers.add(&friend, Some("Laptop".into())).unwrap();
+        let bundle = SyncBundle {
+            exported_at: 7,
+            settings: Some(serde_json::json!({"storagePath": "/home/a/dl", "port": 4001})),
+            trusted_peers: peers.list(),
+            pinned_files: vec!["ab".repeat(32)],
+            ..Default::default()
+        };
+
+        assert!(seal(&bundle, "short").is_err());
+        let sealed = seal(&bundle, "correct horse battery").unwrap();
+        assert!(!sealed.contains(&friend));
+        assert!(open(&sealed, "wrong passphrase").is_err());
+        assert!(open("{}", "correct horse battery").is_err());
+        let opened = open(&sealed, "correct horse battery").unwrap();
+        assert_eq!(opened.exported_at, 7);
+        assert_eq!(opened.trusted_peers, peers.list());
+        assert_eq!(opened.pinned_files, bundle.pinned_files);
+
+        let mut tampered: Value = serde_json::from_str(&sealed).unwrap();
+        tampered["iterations"] = Value::from(u32::MAX);
+        assert!(open(&tampered.to_string(), "correct horse battery").is_err());
+
+        let local = SettingsStore::new();
+        local
+            .update(serde_json::json!({"maxStorageSize": 250, "theme": "dark"}))
+            .unwrap();
+        let merged = merge_settings(opened.settings.clone().unwrap(), &local).unwrap();
+        assert_eq!(merged["storagePath"], "");
+        assert_eq!(merged["port"], 4001);
+        assert_eq!(merged["maxStorageSize"], 250);
+        assert_eq!(merged["theme"], "dark");
+
+        let other = TrustedPeers::new();
+        let summary = apply(opened, &local, &Subscriptions::new(), &other).unwrap();
+        assert_eq!(summary.trusted_peers, 1);
+        assert!(summary.settings);
+        assert!(other.is_trusted(&friend));
+        assert_eq!(local.get().port, 4001);
+
+        let account = |address: &str, key: &str| EncryptedKeystore {
+            address: address.into(),
+            encrypted_private_key: key.into(),
+            salt: String::new(),
+            iv: String::new(),
+            encrypted_two_fa_secret: None,
+            two_fa_iv: None,
+            file_encryption_keys: Default::default(),
+        };
+        let file_key = |hash: &str| EncryptedFileKey {
+            encrypted_key: String::new(),
+            key_iv: String::new(),
+            file_hash: hash.into(),
+            created_at: 0,
+        };
+        let mut old = account("0xa", "old");
+        old.file_encryption_keys
+            .insert("local-file".into(), file_key("local"));
+        let mut new = account("0xa", "new");
+        new.file_encryption_keys
+            .insert("synced-file".into(), file_key("synced"));
+        let mut keystore = Keystore::new();
+        keystore.accounts.push(old);
+        keystore.accounts.push(account("0xb", "kept"));
+        let merged = merge_accounts(&mut keystore, vec![new]);
+        assert_eq!(merged, 1);
+        assert_eq!(keystore.accounts.len(), 2);
+        let a = keystore
+            .accounts
+            .iter()
+            .find(|a| a.address == "0xa")
+            .unwrap();
+        assert_eq!(a.encrypted_private_key, "new");
+        assert_eq!(a.file_encryption_keys.len(), 2);
+    }
+}
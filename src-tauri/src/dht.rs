@@ -1,10 +1,21 @@
+pub mod connection_stats;
+pub mod availability;
+pub mod chunk_have;
+pub mod diagnostics;
+pub mod dial_failures;
 pub mod models;
+pub mod publisher_feed;
+pub mod query_trace;
+pub mod relay_gossip;
+pub mod reservations;
+pub mod topology;
 // pub mod protocol;
 pub use self::models::*;
 use bon::Builder;
 use rand::seq::SliceRandom;
 // use self::protocol::*;
 use crate::config::CHAIN_ID;
+use crate::error_codes::{ErrorCode, ServiceError};
 use crate::download_source::HttpSourceInfo;
 use crate::encryption::EncryptedAesKeyBundle;
 use serde_bytes;
@@ -37,6 +48,40 @@ fn deserialize_cids_from_json(value: &serde_json::Value) -> Option<Vec<Cid>> {
     None
 }
 
+/// JSON stored in a file's DHT record.
+///
+/// Goes through serde for the camelCase field names and CID serialization, then adds the
+/// snake_case aliases that older readers and debug tooling still look for.
+fn file_record_json(metadata: &FileMetadata) -> serde_json::Value {
+    let mut dht_metadata =
+        serde_json::to_value(metadata).unwrap_or_else(|_| serde_json::json!({}));
+    if let Some(obj) = dht_metadata.as_object_mut() {
+        obj.insert("file_hash".to_string(), serde_json::json!(metadata.merkle_root));
+        obj.insert("merkle_root".to_string(), serde_json::json!(metadata.merkle_root));
+        obj.insert("file_name".to_string(), serde_json::json!(metadata.file_name));
+        obj.insert("file_size".to_string(), serde_json::json!(metadata.file_size));
+        obj.insert("created_at".to_string(), serde_json::json!(metadata.created_at));
+        obj.insert("mime_type".to_string(), serde_json::json!(metadata.mime_type));
+        obj.insert("is_encrypted".to_string(), serde_json::json!(metadata.is_encrypted));
+        obj.insert(
+            "encryption_method".to_string(),
+            serde_json::json!(metadata.encryption_method),
+        );
+        obj.insert("key_fingerprint".to_string(), serde_json::json!(metadata.key_fingerprint));
+        obj.insert("parent_hash".to_string(), serde_json::json!(metadata.parent_hash));
+        obj.insert(
+            "encrypted_key_bundle".to_string(),
+            serde_json::json!(metadata.encrypted_key_bundle),
+        );
+        obj.insert("info_hash".to_string(), serde_json::json!(metadata.info_hash));
+        obj.insert(
+            "uploader_address".to_string(),
+            serde_json::json!(metadata.uploader_address),
+        );
+    }
+    dht_metadata
+}
+
 /// Merges two FileMetadata instances for the same file uploaded via different protocols.
 /// This preserves all protocol-specific information while keeping the most recent common fields.
 fn merge_file_metadata(
@@ -204,6 +249,155 @@ impl rr::Codec for KeyRequestCodec {
         write_framed(io, data).await
     }
 }
+
+// ------ Chunk-Have Protocol Implementation ------
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkSwarmProtocol;
+
+impl AsRef<str> for ChunkSwarmProtocol {
+    fn as_ref(&self) -> &str {
+        "/chiral/chunk-have/1.0.0"
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ChunkSwarmCodec;
+
+#[async_trait::async_trait]
+impl rr::Codec for ChunkSwarmCodec {
+    type Protocol = ChunkSwarmProtocol;
+    type Request = ChunkSwarmRequest;
+    type Response = ChunkSwarmResponse;
+
+    async fn read_request<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+    ) -> std::io::Result<Self::Request>
+    where
+        T: FAsyncRead + Unpin + Send,
+    {
+        let data = read_framed(io).await?;
+        serde_json::from_slice(&data)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+    ) -> std::io::Result<Self::Response>
+    where
+        T: FAsyncRead + Unpin + Send,
+    {
+        let data = read_framed(io).await?;
+        serde_json::from_slice(&data)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+        request: Self::Request,
+    ) -> std::io::Result<()>
+    where
+        T: FAsyncWrite + Unpin + Send,
+    {
+        let data = serde_json::to_vec(&request)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        write_framed(io, data).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+        response: Self::Response,
+    ) -> std::io::Result<()>
+    where
+        T: FAsyncWrite + Unpin + Send,
+    {
+        let data = serde_json::to_vec(&response)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        write_framed(io, data).await
+    }
+}
+
+// ------ File Transfer Protocol Implementation ------
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileTransferProtocol;
+
+impl AsRef<str> for FileTransferProtocol {
+    fn as_ref(&self) -> &str {
+        crate::file_transfer::protocol::PROTOCOL_NAME
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct FileTransferCodec;
+
+#[async_trait::async_trait]
+impl rr::Codec for FileTransferCodec {
+    type Protocol = FileTransferProtocol;
+    type Request = TransferRequest;
+    type Response = TransferResponse;
+
+    async fn read_request<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+    ) -> std::io::Result<Self::Request>
+    where
+        T: FAsyncRead + Unpin + Send,
+    {
+        let data = read_framed(io).await?;
+        serde_json::from_slice(&data)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    async fn read_response<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+    ) -> std::io::Result<Self::Response>
+    where
+        T: FAsyncRead + Unpin + Send,
+    {
+        let data = read_framed(io).await?;
+        serde_json::from_slice(&data)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    async fn write_request<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+        request: Self::Request,
+    ) -> std::io::Result<()>
+    where
+        T: FAsyncWrite + Unpin + Send,
+    {
+        let data = serde_json::to_vec(&request)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        write_framed(io, data).await
+    }
+
+    async fn write_response<T>(
+        &mut self,
+        _: &Self::Protocol,
+        io: &mut T,
+        response: Self::Response,
+    ) -> std::io::Result<()>
+    where
+        T: FAsyncWrite + Unpin + Send,
+    {
+        let data = serde_json::to_vec(&response)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        write_framed(io, data).await
+    }
+}
+
 use async_std::fs;
 use async_std::path::Path;
 use async_trait::async_trait;
@@ -237,6 +431,12 @@ use tokio::sync::{mpsc, oneshot, Mutex};
 use tokio_util::compat::TokioAsyncReadCompatExt;
 use tracing::{debug, error, info, trace, warn};
 
+use self::chunk_have::{ChunkHolder, ChunkSwarm, ChunkSwarmRequest, ChunkSwarmResponse};
+use self::connection_stats::{ConnectionStatsTracker, PeerConnectionStats};
+use self::dial_failures::{DialFailureLog, PeerDialFailures};
+use self::query_trace::{QueryTrace, QueryTracer};
+use self::reservations::{ReservationManager, ReservationState};
+use self::topology::{BucketSummary, NetworkTopology, RoutingTopology};
 use crate::manager::Sha256Hasher;
 use crate::peer_selection::{PeerMetrics, PeerSelectionService, SelectionStrategy};
 use crate::reputation::{TransactionVerdict, VerdictOutcome};
@@ -248,7 +448,10 @@ use std::pin::Pin;
 use std::task::{Context, Poll};
 
 // Import the missing types
-use crate::file_transfer::FileTransferService;
+use crate::file_transfer::chunking::ChunkManifest;
+use crate::file_transfer::protocol::{TransferRequest, TransferResponse};
+use crate::file_transfer::swarm::ChunkFetcher;
+use crate::file_transfer::{FileRequest, FileResponse, FileTransferService};
 use crate::manager::ChunkManager;
 use std::error::Error;
 
@@ -267,7 +470,7 @@ use libp2p::{
         // FIXED E0432: ListenerEvent is removed, only import what is available.
         transport::{Boxed, DialOpts, ListenerId, Transport, TransportError, TransportEvent},
     },
-    dcutr,
+    dcutr, gossipsub,
     identify::{self, Event as IdentifyEvent},
     identity,
     kad::{
@@ -301,12 +504,15 @@ struct DhtBehaviour {
     proxy_rr: rr::Behaviour<ProxyCodec>,
     webrtc_signaling_rr: rr::Behaviour<WebRTCSignalingCodec>,
     key_request: rr::Behaviour<KeyRequestCodec>,
+    chunk_swarm: rr::Behaviour<ChunkSwarmCodec>,
+    file_transfer: rr::Behaviour<FileTransferCodec>,
     autonat_client: toggle::Toggle<v2::client::Behaviour>,
     autonat_server: toggle::Toggle<v2::server::Behaviour>,
     relay_client: relay::client::Behaviour,
     relay_server: toggle::Toggle<relay::Behaviour>,
     dcutr: toggle::Toggle<dcutr::Behaviour>,
     upnp: toggle::Toggle<upnp::tokio::Behaviour>,
+    relay_gossip: gossipsub::Behaviour,
 }
 #[derive(Debug)]
 pub enum DhtCommand {
@@ -342,6 +548,18 @@ pub enum DhtCommand {
         payload: Vec<u8>,
         tx: oneshot::Sender<Result<Vec<u8>, String>>,
     },
+    /// Reserve with these relays (addresses ending in their peer ID) and
+    /// give up reservations with relays dropped from the previous set
+    SetRelayCandidates(Vec<String>),
+    /// Relays the user blocked: they are disconnected, their reservations
+    /// given up, and they are never reserved with or dialled through
+    SetBlockedRelays(HashSet<PeerId>),
+    /// Ask a relay for a reservation only to see whether it grants one; the
+    /// reservation is given back as soon as it is accepted
+    ProbeRelayReservation {
+        relay_addr: Multiaddr,
+        sender: oneshot::Sender<Result<(), String>>,
+    },
     Shutdown(oneshot::Sender<()>),
     StopPublish(String),
     GetProviders {
@@ -395,6 +613,34 @@ pub enum DhtCommand {
         auto_recover: bool,
         sender: oneshot::Sender<DhtHealthStatus>,
     },
+    /// Snapshot the routing table and known relays for the topology view
+    GetRoutingTopology {
+        sender: oneshot::Sender<RoutingTopology>,
+    },
+    /// Put the metadata record and provider record for an already published
+    /// file again, without touching the cache or emitting `PublishedFile`
+    ReannounceFile {
+        metadata: FileMetadata,
+        sender: oneshot::Sender<Result<(), String>>,
+    },
+    /// Tell a swarm member which chunks of a file we have
+    AnnounceChunkHaves {
+        peer: PeerId,
+        announcement: self::chunk_have::HaveAnnouncement,
+    },
+    /// Fetch one chunk from a peer that announced it
+    FetchSwarmChunk {
+        peer: PeerId,
+        file_hash: String,
+        chunk_id: u32,
+        sender: oneshot::Sender<Result<Vec<u8>, String>>,
+    },
+    /// Ask a peer for a stored file, its manifest or one of its chunks
+    SendTransferRequest {
+        peer: PeerId,
+        request: TransferRequest,
+        sender: oneshot::Sender<Result<TransferResponse, String>>,
+    },
 }
 
 /// Health status of the DHT network
@@ -480,6 +726,28 @@ pub enum DhtEvent {
         from_peer: String,
         payload: serde_json::Value,
     },
+    /// A relay announced itself on the relay gossip topic
+    RelayAnnounced {
+        announcement: relay_gossip::RelayAnnouncement,
+        /// Peer that passed the announcement on to us
+        via: String,
+    },
+    /// One of our relay reservations changed state
+    RelayReservation(ReservationState),
+    /// A DCUtR attempt to upgrade a relayed connection finished
+    HolePunch {
+        peer_id: String,
+        /// Relay the connection went through, if still known
+        relay_peer_id: Option<String>,
+        success: bool,
+    },
+    /// Use of a relay this node goes through since the last report
+    RelayUsage {
+        relay_peer_id: String,
+        circuits_opened: u64,
+        bytes: u64,
+        failures: u64,
+    },
 }
 
 struct RelayState {
@@ -1356,12 +1624,28 @@ async fn run_dht_node(
     >,
     is_bootstrap: bool,
     enable_autorelay: bool,
-    relay_candidates: HashSet<String>,
+    mut relay_candidates: HashSet<String>,
     chunk_size: usize,
     bootstrap_peer_ids: HashSet<PeerId>,
     pure_client_mode: bool,
     force_server_mode: bool,
+    connection_stats: ConnectionStatsTracker,
+    query_tracer: QueryTracer,
+    chunk_swarm: ChunkSwarm,
+    dial_failures: DialFailureLog,
+    relay_capacity: relay_gossip::RelayCapacity,
+    reservations: ReservationManager,
 ) {
+    // Chunk fetches sent to swarm members, answered by their chunk-have behaviour
+    let mut pending_chunk_fetches: HashMap<
+        rr::OutboundRequestId,
+        oneshot::Sender<Result<Vec<u8>, String>>,
+    > = HashMap::new();
+    // File transfer requests awaiting the provider's answer
+    let mut pending_transfer_requests: HashMap<
+        rr::OutboundRequestId,
+        oneshot::Sender<Result<TransferResponse, String>>,
+    > = HashMap::new();
     // Track peers that support relay (discovered via identify protocol)
     let relay_capable_peers: Arc<Mutex<HashMap<PeerId, Vec<Multiaddr>>>> =
         Arc::new(Mutex::new(HashMap::new()));
@@ -1374,6 +1658,27 @@ async fn run_dht_node(
         tokio::time::interval(Duration::from_secs(24 * 60 * 60)) // 24 hours if disabled
     };
     relay_discovery_interval.tick().await;
+    // Announce ourselves as a relay while the relay server is running
+    let mut relay_announce_interval = tokio::time::interval(relay_gossip::ANNOUNCE_INTERVAL);
+    relay_announce_interval.tick().await;
+    // Renew reservations libp2p let lapse and retry failed ones
+    let mut reservation_interval = tokio::time::interval(Duration::from_secs(60));
+    reservation_interval.tick().await;
+    // Report the bytes carried by each relay we use
+    let mut relay_usage_interval = tokio::time::interval(Duration::from_secs(60));
+    relay_usage_interval.tick().await;
+    // Reservation probes by relay, with the listener opened for the probe
+    // (none when a real reservation with that relay was already underway)
+    let mut reservation_probes: HashMap<
+        PeerId,
+        (Option<ListenerId>, oneshot::Sender<Result<(), String>>),
+    > = HashMap::new();
+    // File transfer requests are served off the swarm loop, which sends the
+    // responses as they come back
+    let (transfer_reply_tx, mut transfer_reply_rx) = mpsc::unbounded_channel::<(
+        libp2p::request_response::ResponseChannel<TransferResponse>,
+        TransferResponse,
+    )>();
     // Periodic bootstrap interval
 
     /// Creates a proper circuit relay address for connecting through a relay peer
@@ -1443,6 +1748,8 @@ async fn run_dht_node(
     let mut shutdown_ack: Option<oneshot::Sender<()>> = None;
     let mut ping_failures: HashMap<PeerId, u8> = HashMap::new();
     let mut relay_blacklist: HashSet<PeerId> = HashSet::new();
+    // Relays the user blocked, see `DhtCommand::SetBlockedRelays`
+    let mut blocked_relays: HashSet<PeerId> = HashSet::new();
     let mut relay_cooldown: HashMap<PeerId, Instant> = HashMap::new();
     let mut last_tried_relay: Option<PeerId> = None;
 
@@ -1489,6 +1796,45 @@ async fn run_dht_node(
                                 info!("🔍 Periodic relay discovery started (QueryId: {:?})", query_id);
                             }
 
+                            _ = reservation_interval.tick(), if !is_bootstrap => {
+                                for (relay, listen_addr) in reservations.due(unix_timestamp()) {
+                                    if blocked_relays.contains(&relay) {
+                                        for listener in reservations.release(&relay) {
+                                            swarm.remove_listener(listener);
+                                        }
+                                        continue;
+                                    }
+                                    match swarm.listen_on(listen_addr.clone()) {
+                                        Ok(listener) => {
+                                            debug!("Requesting relay reservation with {} again", relay);
+                                            let state = reservations.requested(relay, &listen_addr, listener, unix_timestamp());
+                                            let _ = event_tx.send(DhtEvent::RelayReservation(state)).await;
+                                        }
+                                        Err(e) => warn!("Failed to request relay reservation with {}: {}", relay, e),
+                                    }
+                                }
+                            }
+
+                            _ = relay_usage_interval.tick(), if !is_bootstrap => {
+                                for (relay, bytes) in connection_stats.take_relay_bytes() {
+                                    let _ = event_tx.send(relay_usage(relay, 0, bytes, 0)).await;
+                                }
+                            }
+
+                            _ = relay_announce_interval.tick() => {
+                                let capacity = relay_gossip::RelayCapacity {
+                                    active_circuits: connection_stats.open_relay_circuits() as u32,
+                                    ..relay_capacity
+                                };
+                                publish_relay_announcement(&mut swarm, capacity);
+                            }
+
+                            Some((channel, response)) = transfer_reply_rx.recv() => {
+                                swarm.behaviour_mut().file_transfer
+                                    .send_response(channel, response)
+                                    .unwrap_or_else(|e| debug!("Failed to send file transfer response: {e:?}"));
+                            }
+
                             cmd = cmd_rx.recv() => {
                                 match cmd {
                                     Some(DhtCommand::Shutdown(ack)) => {
@@ -1521,33 +1867,8 @@ async fn run_dht_node(
                 cache.insert(merged_metadata.merkle_root.clone(), merged_metadata.clone());
             }
 
-            // 4. Create the JSON for DHT storage
-            //
-            // IMPORTANT:
-            // We MUST go through serde for FileMetadata here to ensure:
-            // - field renames (merkleRoot/fileName/...) are correct
-            // - CID custom serialization runs (cids -> Vec<String>)
-            //
-            // Then we add a few legacy/extra fields for backwards compatibility.
-            let mut dht_metadata = serde_json::to_value(&merged_metadata).unwrap_or_else(|_| serde_json::json!({}));
-            if let Some(obj) = dht_metadata.as_object_mut() {
-                // Keep legacy fields used by older readers / debug tooling.
-                obj.insert("file_hash".to_string(), serde_json::json!(merged_metadata.merkle_root));
-
-                // Some historical paths used snake_case keys; add them as aliases so mixed networks can interop.
-                obj.insert("merkle_root".to_string(), serde_json::json!(merged_metadata.merkle_root));
-                obj.insert("file_name".to_string(), serde_json::json!(merged_metadata.file_name));
-                obj.insert("file_size".to_string(), serde_json::json!(merged_metadata.file_size));
-                obj.insert("created_at".to_string(), serde_json::json!(merged_metadata.created_at));
-                obj.insert("mime_type".to_string(), serde_json::json!(merged_metadata.mime_type));
-                obj.insert("is_encrypted".to_string(), serde_json::json!(merged_metadata.is_encrypted));
-                obj.insert("encryption_method".to_string(), serde_json::json!(merged_metadata.encryption_method));
-                obj.insert("key_fingerprint".to_string(), serde_json::json!(merged_metadata.key_fingerprint));
-                obj.insert("parent_hash".to_string(), serde_json::json!(merged_metadata.parent_hash));
-                obj.insert("encrypted_key_bundle".to_string(), serde_json::json!(merged_metadata.encrypted_key_bundle));
-                obj.insert("info_hash".to_string(), serde_json::json!(merged_metadata.info_hash));
-                obj.insert("uploader_address".to_string(), serde_json::json!(merged_metadata.uploader_address));
-            }
+            // 4. Create the JSON for DHT storage (serde + legacy aliases)
+            let dht_metadata = file_record_json(&merged_metadata);
 
             let record_key = kad::RecordKey::new(&merged_metadata.merkle_root.as_bytes());
 
@@ -1970,6 +2291,7 @@ async fn run_dht_node(
                                     Some(DhtCommand::SearchPeersByInfohash { info_hash, sender }) => {
                                         let key = kad::RecordKey::new(&info_hash.as_bytes());
                                         let query_id = swarm.behaviour_mut().kademlia.get_providers(key);
+                                        query_tracer.query_started(&query_id, "get_providers", info_hash.as_bytes());
                                         info!("Searching for torrent providers (info_hash): {} (query: {:?})", info_hash, query_id);
 
                                         get_providers_queries.lock().await.insert(query_id, (info_hash.clone(), std::time::Instant::now()));
@@ -2222,8 +2544,10 @@ async fn run_dht_node(
                                                 if !is_bootstrap & should_request {
                                                     if let Some(relay_addr) = build_relay_listen_addr(&multiaddr) {
                                                         match swarm.listen_on(relay_addr.clone()) {
-                                                            Ok(_) => {
+                                                            Ok(listener) => {
                                                                 info!("Requested relay reservation via {}", relay_addr);
+                                                                let state = reservations.requested(peer_id, &relay_addr, listener, unix_timestamp());
+                                                                let _ = event_tx.send(DhtEvent::RelayReservation(state)).await;
                                                                 let _ = event_tx
                                                                     .send(DhtEvent::ProxyStatus {
                                                                         id: peer_id.to_string(),
@@ -2317,13 +2641,112 @@ async fn run_dht_node(
                                         let _ = tx.send(count);
                                     }
                                     Some(DhtCommand::Echo { peer, payload, tx }) => {
+                                        connection_stats.request_sent(&peer, payload.len());
                                         let id = swarm.behaviour_mut().proxy_rr.send_request(&peer, EchoRequest(payload));
                                         pending_echo.lock().await.insert(id, PendingEcho { peer, tx });
                                     }
+                                    Some(DhtCommand::SetRelayCandidates(candidates)) => {
+                                        let candidates: HashSet<String> = candidates
+                                            .into_iter()
+                                            .filter(|c| c.parse::<Multiaddr>().is_ok_and(|a| ma_plausibly_reachable(&a)))
+                                            .filter(|c| !peer_id_from_multiaddr_str(c).is_some_and(|relay| blocked_relays.contains(&relay)))
+                                            .collect();
+                                        for dropped in relay_candidates.difference(&candidates) {
+                                            let Some(relay) = peer_id_from_multiaddr_str(dropped) else {
+                                                continue;
+                                            };
+                                            for listener in reservations.release(&relay) {
+                                                swarm.remove_listener(listener);
+                                            }
+                                            info!("Gave up the reservation with relay {}", relay);
+                                        }
+                                        if enable_autorelay && !is_bootstrap {
+                                            for candidate in &candidates {
+                                                let Some(relay) = peer_id_from_multiaddr_str(candidate) else {
+                                                    continue;
+                                                };
+                                                if reservations.is_tracked(&relay) {
+                                                    continue;
+                                                }
+                                                let Ok(addr) = candidate.parse::<Multiaddr>() else {
+                                                    continue;
+                                                };
+                                                let listen_addr = addr.with(Protocol::P2pCircuit);
+                                                match swarm.listen_on(listen_addr.clone()) {
+                                                    Ok(listener) => {
+                                                        info!("Requesting a reservation with selected relay {}", relay);
+                                                        let state = reservations.requested(relay, &listen_addr, listener, unix_timestamp());
+                                                        let _ = event_tx.send(DhtEvent::RelayReservation(state)).await;
+                                                    }
+                                                    Err(e) => warn!("Failed to request relay reservation with {}: {}", relay, e),
+                                                }
+                                            }
+                                        }
+                                        relay_candidates = candidates;
+                                    }
+                                    Some(DhtCommand::SetBlockedRelays(blocked)) => {
+                                        for relay in blocked.difference(&blocked_relays) {
+                                            for listener in reservations.release(relay) {
+                                                swarm.remove_listener(listener);
+                                            }
+                                            relay_capable_peers.lock().await.remove(relay);
+                                            if swarm.is_connected(relay) {
+                                                let _ = swarm.disconnect_peer_id(*relay);
+                                            }
+                                            info!("Dropped blocked relay {}", relay);
+                                        }
+                                        relay_candidates.retain(|c| {
+                                            !peer_id_from_multiaddr_str(c).is_some_and(|relay| blocked.contains(&relay))
+                                        });
+                                        blocked_relays = blocked;
+                                    }
+                                    Some(DhtCommand::ProbeRelayReservation { relay_addr, sender }) => {
+                                        // Give back reservations of probes nobody waits for any more
+                                        reservation_probes.retain(|_, (listener, tx)| {
+                                            if !tx.is_closed() {
+                                                return true;
+                                            }
+                                            if let Some(listener) = listener {
+                                                swarm.remove_listener(*listener);
+                                            }
+                                            false
+                                        });
+                                        let relay = relay_addr.iter().find_map(|p| match p {
+                                            Protocol::P2p(id) => Some(id),
+                                            _ => None,
+                                        });
+                                        let Some(relay) = relay else {
+                                            let _ = sender.send(Err(format!("Relay address {} has no peer ID", relay_addr)));
+                                            continue;
+                                        };
+                                        if blocked_relays.contains(&relay) {
+                                            let _ = sender.send(Err(format!("Relay {} is blocked", relay)));
+                                            continue;
+                                        }
+                                        let (ready, pending) = {
+                                            let mgr = proxy_mgr.lock().await;
+                                            (mgr.relay_ready.contains(&relay), mgr.has_relay_request(&relay))
+                                        };
+                                        if ready {
+                                            let _ = sender.send(Ok(()));
+                                        } else if pending {
+                                            reservation_probes.insert(relay, (None, sender));
+                                        } else {
+                                            match swarm.listen_on(relay_addr.with(Protocol::P2pCircuit)) {
+                                                Ok(listener) => {
+                                                    reservation_probes.insert(relay, (Some(listener), sender));
+                                                }
+                                                Err(e) => {
+                                                    let _ = sender.send(Err(format!("Failed to request a reservation: {}", e)));
+                                                }
+                                            }
+                                        }
+                                    }
                                     Some(DhtCommand::GetProviders { file_hash, sender }) => {
                                         // Query provider records for this file hash
                                         let key = kad::RecordKey::new(&file_hash.as_bytes());
                                         let query_id = swarm.behaviour_mut().kademlia.get_providers(key);
+                                        query_tracer.query_started(&query_id, "get_providers", file_hash.as_bytes());
                                         info!("Querying providers for file: {} (query_id: {:?})", file_hash, query_id);
 
                                         // Store the query_id -> (file_hash, start_time) mapping for error handling and timeout detection
@@ -2337,6 +2760,7 @@ async fn run_dht_node(
                                         pending_provider_queries.lock().await.insert(file_hash, pending_query);
                                     }
                                     Some(DhtCommand::SendWebRTCOffer { peer, offer_request, sender }) => {
+                                        connection_stats.request_sent(&peer, offer_request.offer_sdp.len());
                                         let id = swarm.behaviour_mut().webrtc_signaling_rr.send_request(&peer, offer_request);
                                         pending_webrtc_offers.lock().await.insert(id, sender);
                                     }
@@ -2362,6 +2786,8 @@ async fn run_dht_node(
                                             recipient_public_key: recipient_pk_bytes,
                                         };
 
+                                        connection_stats.request_sent(&seeder, key_request.merkle_root.len() + key_request.recipient_public_key.len());
+
                                         // Send the request using the key_request behavior
                                         let request_id = swarm.behaviour_mut().key_request.send_request(&seeder, key_request);
 
@@ -2370,6 +2796,24 @@ async fn run_dht_node(
 
                                         info!("Sent key request to seeder {} for file {} (request_id: {:?})", seeder, merkle_root, request_id);
                                     }
+                                    Some(DhtCommand::AnnounceChunkHaves { peer, announcement }) => {
+                                        let request = ChunkSwarmRequest::Have(announcement);
+                                        connection_stats.request_sent(&peer, request.approx_len());
+                                        swarm.behaviour_mut().chunk_swarm.send_request(&peer, request);
+                                    }
+                                    Some(DhtCommand::FetchSwarmChunk { peer, file_hash, chunk_id, sender }) => {
+                                        connection_stats.request_sent(&peer, file_hash.len() + 4);
+                                        let request_id = swarm.behaviour_mut().chunk_swarm.send_request(
+                                            &peer,
+                                            ChunkSwarmRequest::Fetch { file_hash, chunk_id },
+                                        );
+                                        pending_chunk_fetches.insert(request_id, sender);
+                                    }
+                                    Some(DhtCommand::SendTransferRequest { peer, request, sender }) => {
+                                        connection_stats.request_sent(&peer, request.approx_len());
+                                        let request_id = swarm.behaviour_mut().file_transfer.send_request(&peer, request);
+                                        pending_transfer_requests.insert(request_id, sender);
+                                    }
                                     Some(DhtCommand::AnnounceTorrent { info_hash }) => {
                                         let key = kad::RecordKey::new(&info_hash);
                                         match swarm.behaviour_mut().kademlia.start_providing(key) {
@@ -2523,6 +2967,53 @@ async fn run_dht_node(
 
                                         let _ = sender.send(addresses_map);
                                     }
+                                    Some(DhtCommand::GetRoutingTopology { sender }) => {
+                                        let buckets: Vec<BucketSummary> = swarm
+                                            .behaviour_mut()
+                                            .kademlia
+                                            .kbuckets()
+                                            .map(|bucket| {
+                                                let peers: Vec<String> = bucket
+                                                    .iter()
+                                                    .map(|entry| entry.node.key.preimage().to_string())
+                                                    .collect();
+                                                BucketSummary {
+                                                    index: bucket.range().0.ilog2().unwrap_or(0),
+                                                    peer_count: peers.len(),
+                                                    peers,
+                                                }
+                                            })
+                                            .collect();
+
+                                        let capable: std::collections::BTreeMap<PeerId, Vec<Multiaddr>> = relay_capable_peers
+                                            .lock()
+                                            .await
+                                            .iter()
+                                            .map(|(id, addrs)| (*id, addrs.clone()))
+                                            .collect();
+                                        let connected = connected_peers.lock().await.clone();
+                                        let active_relay = metrics.lock().await.active_relay_peer_id.clone();
+                                        let relays = {
+                                            let pm = proxy_mgr.lock().await;
+                                            topology::relay_nodes(
+                                                &capable,
+                                                &pm.relay_ready,
+                                                &pm.relay_pending,
+                                                &connected,
+                                                active_relay.as_deref(),
+                                            )
+                                        };
+
+                                        let _ = sender.send(RoutingTopology { relays, buckets });
+                                    }
+                                    Some(DhtCommand::ReannounceFile { metadata, sender }) => {
+                                        let _ = sender.send(reannounce_file_records(
+                                            &mut swarm,
+                                            &metadata,
+                                            peer_id,
+                                            connected_peers.lock().await.len(),
+                                        ));
+                                    }
                                     None => {
                                         info!("DHT command channel closed; shutting down node task");
                                         break 'outer;
@@ -2532,6 +3023,7 @@ async fn run_dht_node(
                             event = swarm.next() => if let Some(event) = event {
                                 match event {
                                     SwarmEvent::Behaviour(DhtBehaviourEvent::Kademlia(kad_event)) => {
+                                        query_tracer.observe(&kad_event);
                                         handle_kademlia_event(
                                             kad_event,
                                             &mut swarm,
@@ -2557,10 +3049,12 @@ async fn run_dht_node(
                                             metrics.clone(),
                                             enable_autorelay,
                                             &relay_candidates,
+                                            &blocked_relays,
                                             &proxy_mgr,
                                             &peer_selection,
                                             relay_capable_peers.clone(),
                                             &peer_id,
+                                            &reservations,
                                         )
                                         .await;
                                     }
@@ -2571,8 +3065,25 @@ async fn run_dht_node(
                                     }
                                     SwarmEvent::Behaviour(DhtBehaviourEvent::RelayClient(relay_event)) if !is_bootstrap => {
                                         match relay_event {
-                                            RelayClientEvent::ReservationReqAccepted { relay_peer_id, .. } => {
+                                            RelayClientEvent::ReservationReqAccepted { relay_peer_id, .. }
+                                                if matches!(reservation_probes.get(&relay_peer_id), Some((Some(_), _))) =>
+                                            {
+                                                debug!("Relay {} accepted a probe reservation", relay_peer_id);
+                                                if let Some((Some(listener), tx)) = reservation_probes.remove(&relay_peer_id) {
+                                                    let _ = tx.send(Ok(()));
+                                                    swarm.remove_listener(listener);
+                                                }
+                                            }
+                                            RelayClientEvent::ReservationReqAccepted { relay_peer_id, renewal, .. } => {
                                                 info!("✅ Relay reservation accepted from {}", relay_peer_id);
+                                                if let Some((_, tx)) = reservation_probes.remove(&relay_peer_id) {
+                                                    let _ = tx.send(Ok(()));
+                                                }
+                                                let (state, superseded) = reservations.accepted(relay_peer_id, renewal, unix_timestamp());
+                                                if let Some(listener) = superseded {
+                                                    swarm.remove_listener(listener);
+                                                }
+                                                let _ = event_tx.send(DhtEvent::RelayReservation(state)).await;
                                                 let mut mgr = proxy_mgr.lock().await;
                                                 let newly_ready = mgr.mark_relay_ready(relay_peer_id);
                                                 drop(mgr);
@@ -2718,6 +3229,7 @@ async fn run_dht_node(
                                             }
                                             RelayEvent::CircuitReqAccepted { src_peer_id, dst_peer_id, .. } => {
                                                 info!("🔁 Relay server: Established circuit from {} to {}", src_peer_id, dst_peer_id);
+                                                connection_stats.relay_circuit_opened(src_peer_id, dst_peer_id);
                                                 let _ = event_tx
                                                     .send(DhtEvent::Info(format!(
                                                         "Relaying traffic from {} to {}",
@@ -2743,6 +3255,7 @@ async fn run_dht_node(
                                             }
                                             RelayEvent::CircuitClosed { src_peer_id, dst_peer_id, .. } => {
                                                 debug!("🔁 Relay server: Circuit closed between {} and {}", src_peer_id, dst_peer_id);
+                                                connection_stats.relay_circuit_closed(src_peer_id, dst_peer_id);
 
                                                 // Emit reputation event
                                                 let _ = event_tx
@@ -3017,25 +3530,18 @@ async fn run_dht_node(
 
                 if active_download.is_complete() {
                     info!("🎉 Download complete for file {}! Finalizing...", file_hash);
-                    // Flush and finalize the file
+                    // Flush the file; it is verified and moved into place off the event loop
                     match active_download.finalize() {
-                        Ok(_) => {
-                            info!("Successfully finalized file {}", file_hash);
-                        }
+                        Ok(staged) => completed_downloads.push((
+                            staged,
+                            active_download.metadata.clone(),
+                            active_download.final_file_path.clone(),
+                        )),
                         Err(e) => {
                             error!("Failed to finalize file {}: {}", file_hash, e);
                             break;
                         }
                     }
-
-                    // Create completed metadata with the correct absolute path
-                    let mut completed_metadata = active_download.metadata.clone();
-                    completed_metadata.download_path = Some(
-                        active_download.final_file_path
-                            .to_string_lossy()
-                            .to_string()
-                    );
-                    completed_downloads.push(completed_metadata);
                 }
                                                             break;
                                                         }
@@ -3048,16 +3554,15 @@ async fn run_dht_node(
 
                                                 // Send completion events for finished downloads
                                              // Send completion events for finished downloads
-                                                for metadata in completed_downloads {
-                                                    info!("Emitting DownloadedFile event for: {}", metadata.merkle_root);
-
-                                                    if let Err(e) = event_tx.send(DhtEvent::DownloadedFile(metadata.clone())).await {
-                                                        error!("Failed to send DownloadedFile event: {}", e);
-                                                    }
-
-                                                    // Just remove from active downloads - file is already finalized
+                                                for (staged, metadata, destination) in completed_downloads {
                                                     info!("Removing from active_downloads...");
                                                     active_downloads.lock().await.remove(&metadata.merkle_root);
+                                                    tokio::spawn(complete_bitswap_download(
+                                                        staged,
+                                                        metadata,
+                                                        destination,
+                                                        event_tx.clone(),
+                                                    ));
                                                 }
                                             }
                                         }
@@ -3081,40 +3586,36 @@ async fn run_dht_node(
                                                         // Check if download is still complete with remaining chunks
                                                         if active_download.is_complete() {
                                                             info!("File {} completed despite failed chunk", file_hash);
-                                                            // Flush and finalize the file
+                                                            // Flush the file; it is verified and moved into place off the event loop
                                                             match active_download.finalize() {
-                                                                Ok(_) => {
-                                                                    info!("Successfully finalized file {}", file_hash);
-                                                                }
+                                                                Ok(staged) => completed_downloads.push((
+                                                                    staged,
+                                                                    active_download.metadata.clone(),
+                                                                    active_download.final_file_path.clone(),
+                                                                )),
                                                                 Err(e) => {
                                                                     error!("Failed to finalize file {}: {}", file_hash, e);
                                                                     continue;
                                                                 }
                                                             }
-
-                                                            // Create completed metadata with the correct absolute path
-                                                            let mut completed_metadata = active_download.metadata.clone();
-                                                            completed_metadata.download_path = Some(
-                                                                active_download.final_file_path
-                                                                    .to_string_lossy()
-                                                                    .to_string()
-                                                            );
-                                                            completed_downloads.push(completed_metadata);
                                                         }
                                                     }
                                                 }
 
                                                 // Remove completed downloads from active downloads
-                                                for metadata in &completed_downloads {
+                                                for (_, metadata, _) in &completed_downloads {
                                                     active_downloads_guard.remove(&metadata.merkle_root);
                                                 }
 
-                                                // Send completion events for finished downloads
-                                                for metadata in completed_downloads {
-                                                    info!("Emitting DownloadedFile event for: {} (after chunk failure)", metadata.merkle_root);
-                                                    if let Err(e) = event_tx.send(DhtEvent::DownloadedFile(metadata)).await {
-                                                        error!("Failed to send DownloadedFile event: {}", e);
-                                                    }
+                                                // Verify finished downloads and send their completion events
+                                                for (staged, metadata, destination) in completed_downloads {
+                                                    info!("Completing {} (after chunk failure)", metadata.merkle_root);
+                                                    tokio::spawn(complete_bitswap_download(
+                                                        staged,
+                                                        metadata,
+                                                        destination,
+                                                        event_tx.clone(),
+                                                    ));
                                                 }
                                             }
 
@@ -3187,11 +3688,24 @@ async fn run_dht_node(
                                         debug!(?ev, "AutoNAT server event");
                                     }
                                     SwarmEvent::Behaviour(DhtBehaviourEvent::Dcutr(ev)) if !is_bootstrap => {
-                                        handle_dcutr_event(ev, &metrics, &event_tx).await;
+                                        handle_dcutr_event(ev, &metrics, &connection_stats, &event_tx).await;
                                     }
                                     SwarmEvent::Behaviour(DhtBehaviourEvent::Upnp(upnp_event)) => {
                                         handle_upnp_event(upnp_event, &mut swarm, &event_tx).await;
                                     }
+                                    SwarmEvent::Behaviour(DhtBehaviourEvent::RelayGossip(gossipsub::Event::Message { propagation_source, message, .. })) => {
+                                        match relay_gossip::decode(&message.data, message.source.as_ref(), unix_timestamp()) {
+                                            Ok(announcement) => {
+                                                let _ = event_tx
+                                                    .send(DhtEvent::RelayAnnounced {
+                                                        announcement,
+                                                        via: propagation_source.to_string(),
+                                                    })
+                                                    .await;
+                                            }
+                                            Err(e) => debug!("Ignoring relay announcement: {}", e),
+                                        }
+                                    }
                                     SwarmEvent::ExternalAddrConfirmed { address, .. } if !is_bootstrap => {
                                         handle_external_addr_confirmed(&mut swarm, &address, &metrics, &event_tx, &proxy_mgr, &pending_provider_registrations, pure_client_mode, force_server_mode)
                                             .await;
@@ -3200,7 +3714,10 @@ async fn run_dht_node(
                                         handle_external_addr_expired(&address, &metrics, &event_tx, &proxy_mgr)
                                             .await;
                                     }
-                                    SwarmEvent::ConnectionEstablished { peer_id, endpoint, num_established, .. } => {
+                                    SwarmEvent::ConnectionEstablished { peer_id, connection_id, endpoint, num_established, .. } => {
+                                        if let Some(relay) = connection_stats.connection_established(connection_id, peer_id, &endpoint) {
+                                            let _ = event_tx.send(relay_usage(relay, 1, 0, 0)).await;
+                                        }
                                         let remote_addr = endpoint.get_remote_address().clone();
                                         let is_relay = remote_addr.iter().any(|p| matches!(p, Protocol::P2pCircuit));
 
@@ -3245,7 +3762,8 @@ async fn run_dht_node(
                                             })
                                             .await;
                                     }
-                                    SwarmEvent::ConnectionClosed { peer_id, cause, .. } => {
+                                    SwarmEvent::ConnectionClosed { peer_id, connection_id, cause, .. } => {
+                                        connection_stats.connection_closed(connection_id, peer_id);
                                         warn!("❌ DISCONNECTED from peer: {}", peer_id);
                                         warn!("   Cause: {:?}", cause);
                                         swarm.behaviour_mut().kademlia.remove_peer(&peer_id);
@@ -3296,6 +3814,10 @@ async fn run_dht_node(
                                         }
                                     }
                                     SwarmEvent::OutgoingConnectionError { peer_id, error, .. } => {
+                                        dial_failures.record_dial_error(peer_id, &error);
+                                        for relay in dial_failures::failed_relays(&error) {
+                                            let _ = event_tx.send(relay_usage(relay, 0, 0, 1)).await;
+                                        }
                                         if let Ok(mut m) = metrics.try_lock() {
                                             m.last_error = Some(error.to_string());
                                             m.last_error_at = Some(SystemTime::now());
@@ -3401,6 +3923,7 @@ async fn run_dht_node(
                                     }
                                     SwarmEvent::Behaviour(DhtBehaviourEvent::ProxyRr(ev)) if !is_bootstrap => {
                                         use libp2p::request_response::{Event as RREvent, Message};
+                                        connection_stats.observe_rr_event(&ev, |r| r.0.len(), |r| r.0.len());
                                         match ev {
                                             RREvent::Message { peer, message } => match message {
                                                 // Echo server
@@ -3441,6 +3964,7 @@ async fn run_dht_node(
                                                     }).await;
 
                                                     // 3) Echo response
+                                                    connection_stats.response_sent(&peer, data.len());
                                                     swarm.behaviour_mut().proxy_rr
                                                         .send_response(channel, EchoResponse(data))
                                                         .unwrap_or_else(|e| error!("send_response failed: {e:?}"));
@@ -3503,6 +4027,7 @@ async fn run_dht_node(
                                     }
                                     SwarmEvent::Behaviour(DhtBehaviourEvent::WebrtcSignalingRr(ev)) if !is_bootstrap => {
                                         use libp2p::request_response::{Event as RREvent, Message};
+                                        connection_stats.observe_rr_event(&ev, |r| r.offer_sdp.len(), |r| r.answer_sdp.len());
                                         match ev {
                                             RREvent::Message { peer, message } => match message {
                                                 // WebRTC offer request
@@ -3567,6 +4092,15 @@ async fn run_dht_node(
                                     }
                                     SwarmEvent::Behaviour(DhtBehaviourEvent::KeyRequest(ev)) => {
                                         use libp2p::request_response::{Event as RREvent, Message};
+                                        connection_stats.observe_rr_event(
+                                            &ev,
+                                            |r| r.merkle_root.len() + r.recipient_public_key.len(),
+                                            |r| {
+                                                r.encrypted_bundle.as_ref().map_or(0, |b| {
+                                                    b.ephemeral_public_key.len() + b.encrypted_key.len() + b.nonce.len()
+                                                }) + r.error.as_ref().map_or(0, |e| e.len())
+                                            },
+                                        );
                                         match ev {
                                             // Incoming key request (we're the seeder)
                                             RREvent::Message { peer, message } => match message {
@@ -3660,7 +4194,145 @@ async fn run_dht_node(
                                             RREvent::ResponseSent { .. } => {}
                                         }
                                     }
-                                    SwarmEvent::ListenerClosed { reason, .. } if !is_bootstrap => {
+                                    SwarmEvent::Behaviour(DhtBehaviourEvent::ChunkSwarm(ev)) => {
+                                        use libp2p::request_response::{Event as RREvent, Message};
+                                        connection_stats.observe_rr_event(
+                                            &ev,
+                                            ChunkSwarmRequest::approx_len,
+                                            ChunkSwarmResponse::approx_len,
+                                        );
+                                        match ev {
+                                            RREvent::Message { peer, message } => match message {
+                                                Message::Request { request, channel, .. } => {
+                                                    let response = match request {
+                                                        ChunkSwarmRequest::Have(announcement) => {
+                                                            let file_hash = announcement.file_hash.clone();
+                                                            let seeding = file_metadata_cache.lock().await.contains_key(&file_hash);
+                                                            // Only track swarms we take part in
+                                                            if seeding || chunk_swarm.is_member(&file_hash) {
+                                                                chunk_swarm.record(peer, &announcement, &peer_id);
+                                                            }
+                                                            ChunkSwarmResponse::Have(chunk_swarm.announcement(&file_hash, &peer, seeding))
+                                                        }
+                                                        ChunkSwarmRequest::Fetch { file_hash, chunk_id } => {
+                                                            let data = if chunk_swarm.has_local_chunk(&file_hash, chunk_id) {
+                                                                tokio::fs::read(chunk_have::chunk_path(&file_hash, chunk_id))
+                                                                    .await
+                                                                    .map_err(|e| warn!("Failed to read swarm chunk {} of {}: {}", chunk_id, file_hash, e))
+                                                                    .ok()
+                                                                    .map(|bytes| chunk_have::encode_chunk(&bytes))
+                                                            } else {
+                                                                None
+                                                            };
+                                                            ChunkSwarmResponse::Chunk { file_hash, chunk_id, data }
+                                                        }
+                                                    };
+                                                    swarm.behaviour_mut().chunk_swarm
+                                                        .send_response(channel, response)
+                                                        .unwrap_or_else(|e| debug!("Failed to send chunk-have response: {e:?}"));
+                                                }
+                                                Message::Response { request_id, response } => match response {
+                                                    ChunkSwarmResponse::Have(announcement) => {
+                                                        // Replies can arrive after the download ended
+                                                        if chunk_swarm.is_member(&announcement.file_hash) {
+                                                            chunk_swarm.record(peer, &announcement, &peer_id);
+                                                        }
+                                                    }
+                                                    ChunkSwarmResponse::Chunk { file_hash, chunk_id, data } => {
+                                                        if let Some(tx) = pending_chunk_fetches.remove(&request_id) {
+                                                            let result = match data {
+                                                                Some(encoded) => chunk_have::decode_chunk(&encoded),
+                                                                None => Err(format!("Peer {} does not serve chunk {} of {}", peer, chunk_id, file_hash)),
+                                                            };
+                                                            let _ = tx.send(result);
+                                                        }
+                                                    }
+                                                },
+                                            },
+                                            RREvent::OutboundFailure { peer, request_id, error, .. } => {
+                                                debug!("Chunk-have request to {} failed: {error:?}", peer);
+                                                if let Some(tx) = pending_chunk_fetches.remove(&request_id) {
+                                                    let _ = tx.send(Err(format!("Outbound failure: {error:?}")));
+                                                }
+                                            }
+                                            RREvent::InboundFailure { error, .. } => {
+                                                debug!("Chunk-have inbound failure: {error:?}");
+                                            }
+                                            RREvent::ResponseSent { .. } => {}
+                                        }
+                                    }
+                                    SwarmEvent::Behaviour(DhtBehaviourEvent::FileTransfer(ev)) => {
+                                        use libp2p::request_response::{Event as RREvent, Message};
+                                        connection_stats.observe_rr_event(
+                                            &ev,
+                                            TransferRequest::approx_len,
+                                            TransferResponse::approx_len,
+                                        );
+                                        match ev {
+                                            RREvent::Message { peer, message } => match message {
+                                                Message::Request { request, channel, .. } => {
+                                                    debug!("File transfer request from {} for {}", peer, request.file_hash());
+                                                    match &file_transfer_service {
+                                                        Some(ft_service) => {
+                                                            // Reading and hashing blobs must not stall the
+                                                            // swarm; uploads share the WebRTC upload slots,
+                                                            // trusted peers first
+                                                            let ft_service = ft_service.clone();
+                                                            let reply_tx = transfer_reply_tx.clone();
+                                                            tokio::spawn(async move {
+                                                                let peer = peer.to_string();
+                                                                let _slot = crate::trusted_peers::global()
+                                                                    .acquire_upload_slot(&peer)
+                                                                    .await;
+                                                                let response = ft_service.serve_request(&peer, request).await;
+                                                                let _ = reply_tx.send((channel, response));
+                                                            });
+                                                        }
+                                                        None => {
+                                                            let response = TransferResponse::Error {
+                                                                code: ErrorCode::ServiceUnavailable,
+                                                                message: "File transfer service is not running".to_string(),
+                                                            };
+                                                            swarm.behaviour_mut().file_transfer
+                                                                .send_response(channel, response)
+                                                                .unwrap_or_else(|e| debug!("Failed to send file transfer response: {e:?}"));
+                                                        }
+                                                    }
+                                                }
+                                                Message::Response { request_id, response } => {
+                                                    if let Some(tx) = pending_transfer_requests.remove(&request_id) {
+                                                        let _ = tx.send(Ok(response));
+                                                    }
+                                                }
+                                            },
+                                            RREvent::OutboundFailure { peer, request_id, error, .. } => {
+                                                debug!("File transfer request to {} failed: {error:?}", peer);
+                                                if let Some(tx) = pending_transfer_requests.remove(&request_id) {
+                                                    let _ = tx.send(Err(format!("Outbound failure: {error:?}")));
+                                                }
+                                            }
+                                            RREvent::InboundFailure { error, .. } => {
+                                                debug!("File transfer inbound failure: {error:?}");
+                                            }
+                                            RREvent::ResponseSent { .. } => {}
+                                        }
+                                    }
+                                    SwarmEvent::ListenerClosed { listener_id, reason, .. } if !is_bootstrap => {
+                                        let probed = reservation_probes
+                                            .iter()
+                                            .find(|(_, (listener, _))| *listener == Some(listener_id))
+                                            .map(|(relay, _)| *relay);
+                                        if let Some((_, tx)) = probed.and_then(|relay| reservation_probes.remove(&relay)) {
+                                            let _ = tx.send(Err(format!("Reservation not granted: {:?}", reason)));
+                                        }
+                                        let error = reason.as_ref().err().map(|e| e.to_string());
+                                        let failed = error.is_some();
+                                        if let Some(state) = reservations.listener_closed(listener_id, error, unix_timestamp()) {
+                                            if let Some(relay) = state.relay_peer_id.parse().ok().filter(|_| failed) {
+                                                let _ = event_tx.send(relay_usage(relay, 0, 0, 1)).await;
+                                            }
+                                            let _ = event_tx.send(DhtEvent::RelayReservation(state)).await;
+                                        }
                                         if !is_bootstrap{
                                         if reason.is_ok() {
                                             trace!("ListenerClosed Ok; ignoring");
@@ -3764,65 +4436,70 @@ async fn run_dht_node(
 
                             // Get file data from file transfer service
                             if let Some(ft_service) = &file_transfer_service {
-                                match ft_service.get_file_data(&file_hash).await {
-                                    Some(file_data) => {
-                                        // Calculate chunk boundaries
-                                        let start = (chunk_index as usize) * chunk_size;
-                                        let end = (start + chunk_size).min(file_data.len());
-
-                                        if start < file_data.len() {
-                                            let chunk_data = file_data[start..end].to_vec();
-
-                                            // Calculate total chunks
-                                            let total_chunks = ((file_data.len() + chunk_size - 1)
-                                                / chunk_size)
-                                                as u32;
-
-                                            // Calculate checksum
-                                            let checksum = {
-                                                use sha2::{Digest, Sha256};
-                                                let mut hasher = Sha256::new();
-                                                hasher.update(&chunk_data);
-                                                format!("{:x}", hasher.finalize())
-                                            };
+                                // Only the requested chunk is read from storage
+                                let file_size = ft_service.stored_file_size(&file_hash).await;
+                                let chunk = ft_service
+                                    .read_file_chunk(&file_hash, chunk_index as u64, chunk_size)
+                                    .await;
+                                match (file_size, chunk) {
+                                    (Some(file_size), Ok(Some(chunk_data))) => {
+                                        let total_chunks =
+                                            file_size.div_ceil(chunk_size as u64) as u32;
+
+                                        // Calculate checksum
+                                        let checksum = {
+                                            use sha2::{Digest, Sha256};
+                                            let mut hasher = Sha256::new();
+                                            hasher.update(&chunk_data);
+                                            format!("{:x}", hasher.finalize())
+                                        };
 
-                                            // Create chunk struct
-                                            let chunk = crate::webrtc_service::FileChunk {
-                                                file_hash: file_hash.clone(),
-                                                file_name: metadata.file_name.clone(),
-                                                chunk_index,
-                                                total_chunks,
-                                                data: chunk_data,
-                                                checksum,
-                                                encrypted_key_bundle: metadata
-                                                    .encrypted_key_bundle
-                                                    .clone(),
-                                            };
+                                        // Create chunk struct
+                                        let chunk = crate::webrtc_service::FileChunk {
+                                            file_hash: file_hash.clone(),
+                                            file_name: metadata.file_name.clone(),
+                                            chunk_index,
+                                            total_chunks,
+                                            data: chunk_data,
+                                            checksum,
+                                            encrypted_key_bundle: metadata
+                                                .encrypted_key_bundle
+                                                .clone(),
+                                        };
 
-                                            // Send chunk to peer
-                                            if let Err(e) =
-                                                webrtc.send_file_chunk(peer_id.clone(), chunk).await
-                                            {
-                                                error!(
-                                                    "Failed to send chunk {} to {}: {}",
-                                                    chunk_index, peer_id, e
-                                                );
-                                            } else {
-                                                info!(
-                                                    "✅ Sent chunk {} to peer {}",
-                                                    chunk_index, peer_id
-                                                );
-                                            }
+                                        // Send chunk to peer
+                                        let chunk_len = chunk.data.len() as u64;
+                                        if let Err(e) =
+                                            webrtc.send_file_chunk(peer_id.clone(), chunk).await
+                                        {
+                                            error!(
+                                                "Failed to send chunk {} to {}: {}",
+                                                chunk_index, peer_id, e
+                                            );
                                         } else {
-                                            warn!(
-                                                "Chunk index {} out of bounds for file {}",
-                                                chunk_index, file_hash
+                                            info!(
+                                                "✅ Sent chunk {} to peer {}",
+                                                chunk_index, peer_id
                                             );
+                                            ft_service
+                                                .record_served(&file_hash, &peer_id, chunk_len);
                                         }
                                     }
-                                    None => {
+                                    (None, _) => {
                                         warn!("File data not found for {}", file_hash);
                                     }
+                                    (Some(_), Ok(None)) => {
+                                        warn!(
+                                            "Chunk index {} out of bounds for file {}",
+                                            chunk_index, file_hash
+                                        );
+                                    }
+                                    (Some(_), Err(e)) => {
+                                        warn!(
+                                            "Failed to read chunk {} of {}: {}",
+                                            chunk_index, file_hash, e
+                                        );
+                                    }
                                 }
                             } else {
                                 warn!("FileTransferService not available to serve chunks");
@@ -4774,10 +5451,12 @@ async fn handle_identify_event(
     metrics: Arc<Mutex<DhtMetrics>>,
     enable_autorelay: bool,
     relay_candidates: &HashSet<String>,
+    blocked_relays: &HashSet<PeerId>,
     proxy_mgr: &ProxyMgr,
     peer_selection: &Arc<Mutex<PeerSelectionService>>,
     relay_capable_peers: Arc<Mutex<HashMap<PeerId, Vec<Multiaddr>>>>,
     local_peer_id: &PeerId,
+    reservations: &ReservationManager,
 ) {
     match event {
         IdentifyEvent::Received { peer_id, info, .. } => {
@@ -4840,7 +5519,9 @@ async fn handle_identify_event(
                 .iter()
                 .any(|p| p.as_ref() == hop_proto);
 
-            if supports_relay {
+            if supports_relay && blocked_relays.contains(&peer_id) {
+                debug!("Not using blocked relay {}", peer_id);
+            } else if supports_relay {
                 // Store this peer as relay-capable with its listen addresses
                 let reachable_addrs: Vec<Multiaddr> = info
                     .listen_addrs
@@ -4886,8 +5567,15 @@ async fn handle_identify_event(
                         .with(Protocol::P2pCircuit);
 
                     match swarm.listen_on(relay_addr.clone()) {
-                        Ok(_) => {
+                        Ok(listener) => {
                             info!("Success: Listening on relay address {}: {}", i + 1, addr);
+                            let state = reservations.requested(
+                                peer_id,
+                                &relay_addr,
+                                listener,
+                                unix_timestamp(),
+                            );
+                            let _ = event_tx.send(DhtEvent::RelayReservation(state)).await;
 
                             // Don't manually advertise here - NewListenAddr event will handle it
                             // This prevents creating nested relay circuits
@@ -5151,21 +5839,41 @@ async fn handle_autonat_client_event(
         .await;
 }
 
+fn relay_usage(relay: PeerId, circuits_opened: u64, bytes: u64, failures: u64) -> DhtEvent {
+    DhtEvent::RelayUsage {
+        relay_peer_id: relay.to_string(),
+        circuits_opened,
+        bytes,
+        failures,
+    }
+}
+
 async fn handle_dcutr_event(
     event: dcutr::Event,
     metrics: &Arc<Mutex<DhtMetrics>>,
+    connection_stats: &ConnectionStatsTracker,
     event_tx: &mpsc::Sender<DhtEvent>,
 ) {
-    let mut metrics_guard = metrics.lock().await;
-    // if !metrics_guard.dcutr_enabled {
-    //     return;
-    // }
-
     let dcutr::Event {
         remote_peer_id,
         result,
     } = event;
 
+    // The relayed connection is still open when the attempt is reported
+    let _ = event_tx
+        .send(DhtEvent::HolePunch {
+            peer_id: remote_peer_id.to_string(),
+            relay_peer_id: connection_stats
+                .relay_of(&remote_peer_id)
+                .map(|relay| relay.to_string()),
+            success: result.is_ok(),
+        })
+        .await;
+
+    let mut metrics_guard = metrics.lock().await;
+    // if !metrics_guard.dcutr_enabled {
+    //     return;
+    // }
     metrics_guard.dcutr_hole_punch_attempts += 1;
 
     match result {
@@ -5237,6 +5945,46 @@ async fn handle_dcutr_event(
     }
 }
 
+/// Publish a relay announcement if the relay server is running and we have
+/// a public address to announce
+fn publish_relay_announcement(
+    swarm: &mut Swarm<DhtBehaviour>,
+    capacity: relay_gossip::RelayCapacity,
+) {
+    if swarm.behaviour().relay_server.as_ref().is_none() {
+        return;
+    }
+    let reachable: Vec<Multiaddr> = swarm
+        .external_addresses()
+        .filter(|addr| ma_plausibly_reachable(addr))
+        .cloned()
+        .collect();
+    let announcement = relay_gossip::announcement(
+        swarm.local_peer_id(),
+        &reachable,
+        capacity,
+        unix_timestamp(),
+    );
+    let Some(mut announcement) = announcement else {
+        debug!("No reachable address to announce as a relay yet");
+        return;
+    };
+    announcement.region = crate::relay_selector::local_region();
+    let data = relay_gossip::encode(&announcement);
+    match swarm
+        .behaviour_mut()
+        .relay_gossip
+        .publish(relay_gossip::topic(), data)
+    {
+        Ok(_) => debug!(
+            "Announced relay at {} address(es)",
+            announcement.addresses.len()
+        ),
+        // Expected while no peer has joined the topic yet
+        Err(e) => debug!("Relay announcement not published: {}", e),
+    }
+}
+
 async fn handle_upnp_event(
     event: upnp::Event,
     swarm: &mut Swarm<DhtBehaviour>,
@@ -5327,6 +6075,45 @@ async fn flush_pending_providers(
     }
 }
 
+/// Re-put the metadata record (and info-hash index) of a published file and
+/// renew its provider record. Errors are the ones Kademlia reports locally;
+/// replication to remote peers is not awaited.
+fn reannounce_file_records(
+    swarm: &mut Swarm<DhtBehaviour>,
+    metadata: &FileMetadata,
+    peer_id: PeerId,
+    connected_peers: usize,
+) -> Result<(), String> {
+    if connected_peers == 0 {
+        return Err("No connected peers".to_string());
+    }
+    let value = serde_json::to_vec(&file_record_json(metadata))
+        .map_err(|e| format!("Failed to serialize DHT metadata: {}", e))?;
+    let record_key = kad::RecordKey::new(&metadata.merkle_root.as_bytes());
+    let record = Record {
+        key: record_key.clone(),
+        value,
+        publisher: Some(peer_id),
+        expires: None,
+    };
+    let kademlia = &mut swarm.behaviour_mut().kademlia;
+    kademlia
+        .put_record(record, kad::Quorum::One)
+        .map_err(|e| format!("Failed to put metadata record: {}", e))?;
+    kademlia
+        .start_providing(record_key)
+        .map_err(|e| format!("Failed to start providing: {}", e))?;
+    if let Some(info_hash) = &metadata.info_hash {
+        let index_key = format!("{}{}", INFO_HASH_PREFIX, info_hash);
+        let index_record = Record::new(
+            index_key.as_bytes().to_vec(),
+            metadata.merkle_root.as_bytes().to_vec(),
+        );
+        let _ = kademlia.put_record(index_record, kad::Quorum::One);
+    }
+    Ok(())
+}
+
 async fn handle_external_addr_confirmed(
     swarm: &mut Swarm<DhtBehaviour>,
     addr: &Multiaddr,
@@ -5624,7 +6411,14 @@ pub struct DhtService {
     active_downloads: Arc<Mutex<HashMap<String, Arc<Mutex<ActiveDownload>>>>>,
     get_providers_queries: Arc<Mutex<HashMap<kad::QueryId, (String, std::time::Instant)>>>,
     chunk_size: usize,
+    connection_stats: ConnectionStatsTracker,
+    query_tracer: QueryTracer,
+    chunk_swarm: ChunkSwarm,
+    dial_failures: DialFailureLog,
+    reservations: ReservationManager,
+    bootstrap_nodes: Vec<String>,
 }
+use crate::download_staging::StagedFile;
 use memmap2::MmapMut;
 use std::fs::OpenOptions;
 
@@ -5632,8 +6426,9 @@ use std::fs::OpenOptions;
 struct ActiveDownload {
     metadata: FileMetadata,
     queries: HashMap<beetswap::QueryId, u32>,
-    temp_file_path: PathBuf,  // Path with .tmp suffix
-    final_file_path: PathBuf, // Final path without .tmp
+    // Written in the staging area; removed with the last clone unless finalized
+    staged: Arc<std::sync::Mutex<Option<StagedFile<'static>>>>,
+    final_file_path: PathBuf,
     mmap: Arc<std::sync::Mutex<MmapMut>>,
     received_chunks: Arc<std::sync::Mutex<HashSet<u32>>>,
     total_chunks: u32,
@@ -5653,18 +6448,18 @@ impl ActiveDownload {
         // download_path is already the complete file path
         let final_file_path = download_path.clone();
 
-        // Create temp file by replacing extension with .tmp
-        let mut temp_file_path = download_path.clone();
-        temp_file_path.set_extension("tmp");
+        let staged = crate::download_staging::global()
+            .stage(&metadata.merkle_root, total_size)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
 
-        info!("Creating temp file at: {:?}", temp_file_path);
-        info!("Will rename to: {:?} when complete", final_file_path);
+        info!("Staging download at: {:?}", staged.path());
+        info!("Will move to: {:?} when verified", final_file_path);
 
         let file = OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
-            .open(&temp_file_path)?;
+            .open(staged.path())?;
 
         file.set_len(total_size)?;
 
@@ -5673,7 +6468,7 @@ impl ActiveDownload {
         Ok(Self {
             metadata,
             queries,
-            temp_file_path,
+            staged: Arc::new(std::sync::Mutex::new(Some(staged))),
             final_file_path,
             mmap: Arc::new(std::sync::Mutex::new(mmap)),
             received_chunks: Arc::new(std::sync::Mutex::new(HashSet::new())),
@@ -5756,40 +6551,17 @@ impl ActiveDownload {
         Ok(mmap.to_vec())
     }
 
-    /// Finalize the download by renaming .tmp file to final filename
-    fn finalize(&self) -> std::io::Result<()> {
-        // First, flush to ensure all data is written
+    /// Flush the download and hand over its staged file, which still has to
+    /// be verified before it is moved to `final_file_path`
+    fn finalize(&self) -> std::io::Result<StagedFile<'static>> {
         self.flush()?;
-
-        // Drop the mmap to release the file handle
-        if let Ok(mmap_guard) = self.mmap.lock() {
-            drop(mmap_guard);
-        }
-
-        info!(
-            "Renaming {:?} to {:?}",
-            self.temp_file_path, self.final_file_path
-        );
-
-        // Rename the temp file to the final file
-        std::fs::rename(&self.temp_file_path, &self.final_file_path)?;
-
-        info!("Successfully finalized file: {:?}", self.final_file_path);
-        Ok(())
-    }
-
-    /// Clean up temp file (only call if download fails/is cancelled)
-    fn cleanup(&self) {
-        if self.temp_file_path.exists() {
-            if let Err(e) = std::fs::remove_file(&self.temp_file_path) {
-                error!(
-                    "Failed to cleanup temp file {:?}: {}",
-                    self.temp_file_path, e
-                );
-            } else {
-                info!("Cleaned up temp file: {:?}", self.temp_file_path);
-            }
-        }
+        self.staged
+            .lock()
+            .ok()
+            .and_then(|mut staged| staged.take())
+            .ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::Other, "Download was already finalized")
+            })
     }
 
     fn progress(&self) -> f32 {
@@ -5814,7 +6586,7 @@ impl Clone for ActiveDownload {
         Self {
             metadata: self.metadata.clone(),
             queries: self.queries.clone(),
-            temp_file_path: self.temp_file_path.clone(),
+            staged: Arc::clone(&self.staged),
             final_file_path: self.final_file_path.clone(),
             mmap: Arc::clone(&self.mmap),
             received_chunks: Arc::clone(&self.received_chunks),
@@ -5824,11 +6596,36 @@ impl Clone for ActiveDownload {
     }
 }
 
-impl Drop for ActiveDownload {
-    fn drop(&mut self) {
-        // Only cleanup temp file if this is the last reference and file wasn't finalized
-        if Arc::strong_count(&self.mmap) == 1 {
-            self.cleanup();
+/// Verify a finished Bitswap download against the id it was published under,
+/// move it into place and report it
+async fn complete_bitswap_download(
+    staged: StagedFile<'static>,
+    mut metadata: FileMetadata,
+    destination: PathBuf,
+    event_tx: mpsc::Sender<DhtEvent>,
+) {
+    // Encrypted files arrive as published, so only plaintext is checked by id
+    let verification = crate::download_staging::Verification {
+        expected_size: Some(metadata.file_size),
+        published_id: (!metadata.is_encrypted).then(|| metadata.merkle_root.clone()),
+        ..Default::default()
+    };
+    match staged.commit(&destination, &verification).await {
+        Ok(path) => {
+            info!("Successfully finalized file {}", metadata.merkle_root);
+            metadata.download_path = Some(path.to_string_lossy().to_string());
+            if let Err(e) = event_tx.send(DhtEvent::DownloadedFile(metadata)).await {
+                error!("Failed to send DownloadedFile event: {}", e);
+            }
+        }
+        Err(e) => {
+            error!("Failed to finalize file {}: {}", metadata.merkle_root, e);
+            let _ = event_tx
+                .send(DhtEvent::Error(format!(
+                    "Download of {} failed: {}",
+                    metadata.merkle_root, e
+                )))
+                .await;
         }
     }
 }
@@ -6034,7 +6831,15 @@ impl DhtService {
 
         let key_request_protocols =
             std::iter::once((KeyRequestProtocol, rr::ProtocolSupport::Full));
-        let key_request = rr::Behaviour::new(key_request_protocols, rr_cfg);
+        let key_request = rr::Behaviour::new(key_request_protocols, rr_cfg.clone());
+
+        let chunk_swarm_protocols =
+            std::iter::once((ChunkSwarmProtocol, rr::ProtocolSupport::Full));
+        let chunk_swarm = rr::Behaviour::new(chunk_swarm_protocols, rr_cfg.clone());
+
+        let file_transfer_protocols =
+            std::iter::once((FileTransferProtocol, rr::ProtocolSupport::Full));
+        let file_transfer = rr::Behaviour::new(file_transfer_protocols, rr_cfg);
 
         let probe_interval = autonat_probe_interval;
         let autonat_client_behaviour = if enable_autonat {
@@ -6070,7 +6875,9 @@ impl DhtService {
         let dcutr_toggle = toggle::Toggle::from(Some(dcutr::Behaviour::new(local_peer_id)));
 
         // Relay server configuration
-        // Relay server configuration
+        let relay_config = relay::Config::default();
+        // Advertised in relay announcements
+        let relay_capacity = relay_gossip::RelayCapacity::of(&relay_config);
         // Enable relay server if explicitly requested OR if AutoNAT is enabled (to allow auto-relay on public IP)
         let relay_server_behaviour = if enable_relay_server || enable_autonat {
             if enable_relay_server {
@@ -6078,10 +6885,7 @@ impl DhtService {
             } else {
                 info!("🔁 Relay server initialized (standby) - will be advertised if public IP is detected");
             }
-            Some(relay::Behaviour::new(
-                local_peer_id,
-                relay::Config::default(),
-            ))
+            Some(relay::Behaviour::new(local_peer_id, relay_config))
         } else {
             None
         };
@@ -6096,6 +6900,12 @@ impl DhtService {
             None
         };
         let upnp_toggle = toggle::Toggle::from(upnp_behaviour);
+
+        // Signed relay announcements; every node listens, relays also publish
+        let mut relay_gossip = relay_gossip::behaviour(&local_key)?;
+        relay_gossip
+            .subscribe(&relay_gossip::topic())
+            .map_err(|e| format!("Failed to subscribe to relay announcements: {}", e))?;
         let bootstrap_set: HashSet<String> = bootstrap_nodes.iter().cloned().collect();
         let mut autonat_targets: HashSet<String> = if enable_autonat && !autonat_servers.is_empty()
         {
@@ -6181,12 +6991,15 @@ impl DhtService {
                     proxy_rr,
                     webrtc_signaling_rr,
                     key_request,
+                    chunk_swarm,
+                    file_transfer,
                     autonat_client: autonat_client_toggle,
                     autonat_server: autonat_server_toggle,
                     relay_client: relay_client_behaviour,
                     relay_server: relay_server_toggle,
                     dcutr: dcutr_toggle,
                     upnp: upnp_toggle,
+                    relay_gossip,
                 }
             })?
             .with_swarm_config(
@@ -6327,8 +7140,25 @@ impl DhtService {
         }
 
         let (cmd_tx, cmd_rx) = mpsc::channel(100);
-        let (event_tx, event_rx) = mpsc::channel(100);
+        let (event_tx, mut node_event_rx) = mpsc::channel::<DhtEvent>(100);
+        let (service_event_tx, event_rx) = mpsc::channel(100);
         let connected_peers = Arc::new(Mutex::new(HashSet::new()));
+        let connection_stats = ConnectionStatsTracker::new();
+        let query_tracer = QueryTracer::new();
+        let chunk_swarm = ChunkSwarm::new();
+        let dial_failures = DialFailureLog::new();
+        let reservations = ReservationManager::new();
+
+        // Mirror node events (including relay reservations/circuits) onto the
+        // unified event bus before they reach drain_events consumers.
+        tokio::spawn(async move {
+            while let Some(event) = node_event_rx.recv().await {
+                crate::event_bus::global().publish_dht(&event);
+                if service_event_tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+        });
         let metrics = Arc::new(Mutex::new(DhtMetrics::default()));
         let pending_echo = Arc::new(Mutex::new(HashMap::new()));
         let pending_searches = Arc::new(Mutex::new(HashMap::new()));
@@ -6416,6 +7246,12 @@ impl DhtService {
             bootstrap_peer_ids,
             pure_client_mode,
             force_server_mode,
+            connection_stats.clone(),
+            query_tracer.clone(),
+            chunk_swarm.clone(),
+            dial_failures.clone(),
+            relay_capacity,
+            reservations.clone(),
         ));
 
         Ok(DhtService {
@@ -6443,6 +7279,12 @@ impl DhtService {
             active_downloads,
             get_providers_queries: get_providers_queries_local,
             chunk_size,
+            connection_stats,
+            query_tracer,
+            chunk_swarm,
+            dial_failures,
+            reservations,
+            bootstrap_nodes,
         })
     }
 
@@ -6627,6 +7469,28 @@ impl DhtService {
 
         self.publish_file(sanitized, ftp_sources).await
     }
+    /// Announce a file this node already published again: its metadata
+    /// record, provider record and info-hash index
+    pub async fn reannounce_file(&self, mut metadata: FileMetadata) -> Result<(), String> {
+        // The cached copy of a freshly uploaded file may still carry its bytes
+        metadata.file_data.clear();
+        let (tx, rx) = oneshot::channel();
+        self.cmd_tx
+            .send(DhtCommand::ReannounceFile {
+                metadata,
+                sender: tx,
+            })
+            .await
+            .map_err(|e| format!("Failed to send re-announce command: {}", e))?;
+        rx.await
+            .map_err(|e| format!("Re-announce response error: {}", e))?
+    }
+
+    /// Cached metadata for `file_hash`, if this node published or discovered it
+    pub async fn cached_file_metadata(&self, file_hash: &str) -> Option<FileMetadata> {
+        self.file_metadata_cache.lock().await.get(file_hash).cloned()
+    }
+
     /// List all known FileMetadata (from cache, i.e., locally published or discovered)
     pub async fn get_all_file_metadata(&self) -> Result<Vec<FileMetadata>, String> {
         let cache = self.file_metadata_cache.lock().await;
@@ -6861,6 +7725,27 @@ impl DhtService {
             .map_err(|e| e.to_string())
     }
 
+    /// Stop using `relays`, see `DhtCommand::SetBlockedRelays`
+    pub async fn set_blocked_relays(&self, relays: &[String]) -> Result<(), String> {
+        let relays = relays
+            .iter()
+            .filter_map(|relay| relay.parse::<PeerId>().ok())
+            .collect();
+        self.cmd_tx
+            .send(DhtCommand::SetBlockedRelays(relays))
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    /// Use `relays` (dial addresses ending in their peer ID) for our
+    /// reservations from now on, see `DhtCommand::SetRelayCandidates`
+    pub async fn set_relay_candidates(&self, relays: Vec<String>) -> Result<(), String> {
+        self.cmd_tx
+            .send(DhtCommand::SetRelayCandidates(relays))
+            .await
+            .map_err(|e| e.to_string())
+    }
+
     pub async fn connect_to_peer_by_id(&self, peer_id: String) -> Result<(), String> {
         let peer_id: PeerId = peer_id
             .parse()
@@ -6975,6 +7860,322 @@ impl DhtService {
             .collect()
     }
 
+    /// Transport, direction, age, traffic and relay status per connected peer
+    pub fn get_peer_connection_stats(&self) -> Vec<PeerConnectionStats> {
+        self.connection_stats.snapshot()
+    }
+
+    /// Distinct peers seen and relay time served since the node started
+    pub fn session_network_totals(&self) -> connection_stats::SessionNetworkTotals {
+        self.connection_stats.session_totals()
+    }
+
+    /// Peer connect/disconnect rates, session lengths and the `limit` peers
+    /// that disconnected most often
+    pub fn peer_churn_stats(&self, limit: usize) -> Option<connection_stats::ChurnStats> {
+        self.connection_stats.churn_stats(limit)
+    }
+
+    /// Turn per-hop tracing of Kademlia queries on or off. Traces already
+    /// recorded are kept.
+    pub fn set_query_tracing(&self, enabled: bool) {
+        self.query_tracer.set_enabled(enabled);
+    }
+
+    pub fn query_tracing_enabled(&self) -> bool {
+        self.query_tracer.is_enabled()
+    }
+
+    /// Full trace of one query, by the ID shown in logs (e.g. "QueryId(12)")
+    pub fn query_trace(&self, query_id: &str) -> Option<QueryTrace> {
+        self.query_tracer.trace(query_id)
+    }
+
+    /// Most recently traced queries, newest first, without their hops
+    pub fn recent_query_traces(&self, limit: usize) -> Vec<QueryTrace> {
+        self.query_tracer.recent(limit)
+    }
+
+    /// Start exchanging chunk-have announcements for a download, beginning
+    /// with `seeders`; the swarm reveals the other members from there
+    pub async fn join_chunk_swarm(
+        &self,
+        file_hash: &str,
+        total_chunks: u32,
+        chunks: &[u32],
+        seeders: &[String],
+    ) {
+        let seeders: Vec<PeerId> = seeders.iter().filter_map(|p| p.parse().ok()).collect();
+        self.chunk_swarm.join(file_hash, total_chunks, chunks, &seeders);
+        self.announce_chunk_haves().await;
+    }
+
+    pub fn leave_chunk_swarm(&self, file_hash: &str) {
+        self.chunk_swarm.leave(file_hash);
+    }
+
+    /// Make a chunk stored on disk available to the swarm; it is announced
+    /// with the next round
+    pub fn add_swarm_chunk(&self, file_hash: &str, chunk_id: u32) -> bool {
+        self.chunk_swarm.add_local_chunk(file_hash, chunk_id)
+    }
+
+    /// Peers that announced `chunk_id`, partial holders first
+    pub fn swarm_chunk_holders(&self, file_hash: &str, chunk_id: u32) -> Vec<String> {
+        self.chunk_swarm
+            .holders(file_hash, chunk_id)
+            .iter()
+            .map(PeerId::to_string)
+            .collect()
+    }
+
+    /// Chunks announced by each peer that has part of `file_hash`
+    pub fn swarm_partial_holdings(&self, file_hash: &str) -> Vec<Vec<u32>> {
+        self.chunk_swarm.partial_holdings(file_hash)
+    }
+
+    pub fn chunk_swarm_members(&self, file_hash: &str) -> Vec<ChunkHolder> {
+        self.chunk_swarm.holders_summary(file_hash)
+    }
+
+    /// Send our haves to every live swarm member. Run every
+    /// `chunk_have::ANNOUNCE_INTERVAL`; returns the number of announcements.
+    pub async fn announce_chunk_haves(&self) -> usize {
+        let due = self.chunk_swarm.due_announcements();
+        let count = due.len();
+        for (peer, announcement) in due {
+            if self
+                .cmd_tx
+                .send(DhtCommand::AnnounceChunkHaves { peer, announcement })
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+        count
+    }
+
+    /// Fetch one chunk from a swarm member that announced it. The caller
+    /// verifies the data against the chunk hash.
+    pub async fn fetch_swarm_chunk(
+        &self,
+        peer_id: &str,
+        file_hash: &str,
+        chunk_id: u32,
+    ) -> Result<Vec<u8>, String> {
+        let peer: PeerId = peer_id
+            .parse()
+            .map_err(|e| format!("Invalid peer ID: {}", e))?;
+        let (tx, rx) = oneshot::channel();
+        self.cmd_tx
+            .send(DhtCommand::FetchSwarmChunk {
+                peer,
+                file_hash: file_hash.to_string(),
+                chunk_id,
+                sender: tx,
+            })
+            .await
+            .map_err(|e| format!("Failed to request swarm chunk: {}", e))?;
+        tokio::time::timeout(Duration::from_secs(30), rx)
+            .await
+            .map_err(|_| "Swarm chunk request timed out".to_string())?
+            .map_err(|e| format!("Swarm chunk response error: {}", e))?
+    }
+
+    /// Send a file transfer request to `peer_id`. `Error` responses come back
+    /// as `Err` carrying the provider's error code.
+    pub async fn send_transfer_request(
+        &self,
+        peer_id: &str,
+        request: TransferRequest,
+    ) -> Result<TransferResponse, String> {
+        let peer: PeerId = peer_id
+            .parse()
+            .map_err(|e| format!("Invalid peer ID: {}", e))?;
+        let (tx, rx) = oneshot::channel();
+        self.cmd_tx
+            .send(DhtCommand::SendTransferRequest {
+                peer,
+                request,
+                sender: tx,
+            })
+            .await
+            .map_err(|e| format!("Failed to send file transfer request: {}", e))?;
+        tokio::time::timeout(Duration::from_secs(30), rx)
+            .await
+            .map_err(|_| {
+                String::from(ServiceError::new(
+                    ErrorCode::Timeout,
+                    "File transfer request timed out",
+                ))
+            })?
+            .map_err(|e| format!("File transfer response error: {}", e))??
+            .into_result()
+    }
+
+    /// Fetch a whole stored file from a peer. Only files up to
+    /// `protocol::MAX_WHOLE_FILE_BYTES` are served this way.
+    pub async fn request_file(
+        &self,
+        peer_id: &str,
+        file_hash: &str,
+    ) -> Result<FileResponse, String> {
+        let request = TransferRequest::File(FileRequest {
+            file_hash: file_hash.to_string(),
+        });
+        match self.send_transfer_request(peer_id, request).await? {
+            TransferResponse::File(file) => Ok(file),
+            other => Err(format!("Unexpected file transfer response: {:?}", other)),
+        }
+    }
+
+    /// Categorized dial failures for one peer, or for the `limit` peers that
+    /// failed most recently
+    pub fn dial_failures(&self, peer_id: Option<&str>, limit: usize) -> Vec<PeerDialFailures> {
+        match peer_id {
+            Some(peer_id) => self.dial_failures.for_peer(peer_id).into_iter().collect(),
+            None => self.dial_failures.recent(limit),
+        }
+    }
+
+    /// Our relay reservations, active ones first
+    pub fn relay_reservations(&self) -> Vec<ReservationState> {
+        self.reservations.snapshot()
+    }
+
+    /// The local node's view of the network as one document: connected peers,
+    /// known relays, bootstrap node status and the routing table buckets
+    pub async fn network_topology(&self) -> Result<NetworkTopology, String> {
+        let (tx, rx) = oneshot::channel();
+        self.cmd_tx
+            .send(DhtCommand::GetRoutingTopology { sender: tx })
+            .await
+            .map_err(|e| format!("Failed to request routing topology: {}", e))?;
+        let routing = tokio::time::timeout(Duration::from_secs(5), rx)
+            .await
+            .map_err(|_| {
+                ServiceError::new(ErrorCode::Timeout, "Routing topology request timed out")
+            })?
+            .map_err(|e| format!("Routing topology response error: {}", e))?;
+
+        let connected = self.connected_peers.lock().await.clone();
+        let (listen_addrs, reachability) = {
+            let metrics = self.metrics.lock().await;
+            (metrics.listen_addrs.clone(), metrics.reachability_state)
+        };
+
+        Ok(NetworkTopology {
+            local_peer_id: self.peer_id.clone(),
+            listen_addrs,
+            reachability,
+            peers: self.connection_stats.snapshot(),
+            relays: routing.relays,
+            bootstrap_nodes: topology::bootstrap_nodes_status(&self.bootstrap_nodes, &connected),
+            routing_table_size: routing.buckets.iter().map(|b| b.peer_count).sum(),
+            buckets: routing.buckets,
+            generated_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        })
+    }
+
+    /// Pass/warn/fail report on port reachability, NAT layering, bootstrap
+    /// connectivity and DHT lookup latency
+    pub async fn run_diagnostics(&self) -> diagnostics::DiagnosticsReport {
+        let metrics = self.metrics_snapshot().await;
+        let connected = self.connected_peers.lock().await.clone();
+        let peers = self.connection_stats.snapshot();
+
+        // A lookup for a key nobody stores walks the closest peers until the
+        // query gives up, which is the path every search takes
+        let key = format!("chiral-diagnostics-{:016x}", rand::random::<u64>());
+        let started = Instant::now();
+        let lookup = match tokio::time::timeout(
+            diagnostics::LOOKUP_TIMEOUT,
+            self.get_dht_value(key),
+        )
+        .await
+        {
+            Ok(Ok(_)) => Ok(started.elapsed()),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(format!(
+                "no answer within {} seconds",
+                diagnostics::LOOKUP_TIMEOUT.as_secs()
+            )),
+        };
+
+        let checks = vec![
+            diagnostics::check_port_reachability(
+                metrics.reachability,
+                metrics.autonat_enabled,
+                &peers,
+            ),
+            diagnostics::check_nat(&metrics.listen_addrs, &metrics.observed_addrs),
+            diagnostics::check_bootstrap(&topology::bootstrap_nodes_status(
+                &self.bootstrap_nodes,
+                &connected,
+            )),
+            diagnostics::check_dht_lookup(connected.len(), lookup),
+        ];
+        diagnostics::DiagnosticsReport::new(
+            checks,
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        )
+    }
+
+    /// Who besides this node can serve `file_hash`, how they can be reached,
+    /// and whether it is safe to stop providing it
+    pub async fn file_availability(&self, file_hash: &str) -> availability::AvailabilityReport {
+        let provider_records = self.get_seeders_for_file(file_hash).await;
+        let metadata_seeders = self
+            .cached_file_metadata(file_hash)
+            .await
+            .map(|metadata| metadata.seeders)
+            .unwrap_or_default();
+
+        let peer_ids: Vec<PeerId> = provider_records
+            .iter()
+            .chain(metadata_seeders.iter())
+            .filter_map(|id| id.parse().ok())
+            .collect();
+        let (tx, rx) = oneshot::channel();
+        let addresses = if self
+            .cmd_tx
+            .send(DhtCommand::GetPeerAddresses {
+                peer_ids,
+                sender: tx,
+            })
+            .await
+            .is_ok()
+        {
+            rx.await
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(peer_id, addrs)| (peer_id.to_string(), addrs))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+
+        availability::build_report(
+            file_hash,
+            &self.peer_id,
+            &provider_records,
+            &metadata_seeders,
+            &self.connection_stats.snapshot(),
+            &addresses,
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        )
+    }
+
     /// Trigger a re-bootstrap to discover new peers
     /// Returns the number of new peers discovered
     pub async fn re_bootstrap(&self) -> Result<usize, String> {
@@ -7105,6 +8306,24 @@ impl DhtService {
             .map_err(|e| format!("Echo response error: {}", e))?
     }
 
+    /// Whether the relay at `relay_addr` (which must end in `/p2p/<id>`)
+    /// grants a reservation. Waits until it answers; callers bound the wait.
+    pub async fn probe_relay_reservation(&self, relay_addr: &str) -> Result<(), String> {
+        let relay_addr: Multiaddr = relay_addr
+            .parse()
+            .map_err(|e| format!("Invalid relay address: {e}"))?;
+        let (tx, rx) = oneshot::channel();
+        self.cmd_tx
+            .send(DhtCommand::ProbeRelayReservation {
+                relay_addr,
+                sender: tx,
+            })
+            .await
+            .map_err(|e| format!("Failed to send relay probe command: {e}"))?;
+        rx.await
+            .map_err(|e| format!("Relay probe response error: {}", e))?
+    }
+
     pub async fn update_privacy_proxy_targets(&self, addresses: Vec<String>) -> Result<(), String> {
         self.cmd_tx
             .send(DhtCommand::SetPrivacyProxies { addresses })
@@ -7792,20 +9011,13 @@ impl DhtService {
         chunk_index: usize,
     ) -> Result<Vec<u8>, String> {
         if let Some(ft_service) = &self.file_transfer_service {
-            let file_data = ft_service
-                .get_file_data(file_root_hex)
-                .await
-                .ok_or_else(|| format!("File data not found for root {}", file_root_hex))?;
-
-            let chunk_size = self.chunk_size();
-            let start = chunk_index * chunk_size;
-            let end = (start + chunk_size).min(file_data.len());
-
-            if start >= file_data.len() {
-                return Err(format!("Chunk index {} is out of bounds", chunk_index));
+            if ft_service.stored_file_size(file_root_hex).await.is_none() {
+                return Err(format!("File data not found for root {}", file_root_hex));
             }
-
-            Ok(file_data[start..end].to_vec())
+            ft_service
+                .read_file_chunk(file_root_hex, chunk_index as u64, self.chunk_size())
+                .await?
+                .ok_or_else(|| format!("Chunk index {} is out of bounds", chunk_index))
         } else {
             Err("FileTransferService is not available".to_string())
         }
@@ -7932,6 +9144,90 @@ async fn synchronous_search_by_infohash(
     Err("Synchronous info_hash search not fully implemented.".to_string())
 }
 
+/// Lets `FileTransferService` fetch manifests and chunks from other nodes
+/// over the file transfer protocol. Holds the DHT weakly so a stopped node
+/// is not kept alive by the file transfer service.
+pub struct DhtChunkFetcher {
+    dht: std::sync::Weak<DhtService>,
+}
+
+impl DhtChunkFetcher {
+    pub fn new(dht: &Arc<DhtService>) -> Self {
+        Self {
+            dht: Arc::downgrade(dht),
+        }
+    }
+
+    fn dht(&self) -> Result<Arc<DhtService>, String> {
+        self.dht.upgrade().ok_or_else(|| {
+            String::from(ServiceError::new(
+                ErrorCode::ServiceUnavailable,
+                "DHT is not running",
+            ))
+        })
+    }
+}
+
+#[async_trait]
+impl ChunkFetcher for DhtChunkFetcher {
+    async fn fetch_manifest(
+        &self,
+        provider: &str,
+        file_hash: &str,
+    ) -> Result<ChunkManifest, String> {
+        let request = TransferRequest::Manifest {
+            file_hash: file_hash.to_string(),
+        };
+        match self.dht()?.send_transfer_request(provider, request).await? {
+            TransferResponse::Manifest(manifest) => Ok(manifest),
+            _ => Err(format!(
+                "{} answered a manifest request with something else",
+                provider
+            )),
+        }
+    }
+
+    async fn fetch_chunk(
+        &self,
+        provider: &str,
+        file_hash: &str,
+        index: u32,
+    ) -> Result<Vec<u8>, String> {
+        let request = TransferRequest::Chunk {
+            file_hash: file_hash.to_string(),
+            index,
+        };
+        match self.dht()?.send_transfer_request(provider, request).await? {
+            TransferResponse::Chunk {
+                file_hash: got_hash,
+                index: got_index,
+                data,
+            } if got_hash == file_hash && got_index == index => Ok(data),
+            _ => Err(format!(
+                "{} answered chunk {} with something else",
+                provider, index
+            )),
+        }
+    }
+
+    async fn stop_providing(&self, file_hash: &str) -> Result<(), String> {
+        self.dht()?
+            .stop_publishing_file(file_hash.to_string())
+            .await
+    }
+
+    async fn find_providers(&self, file_hash: &str) -> Result<Vec<String>, String> {
+        let dht = self.dht()?;
+        let local = dht.get_peer_id().await;
+        Ok(dht
+            .get_seeders_for_file(file_hash)
+            .await
+            .into_iter()
+            .filter(|peer| *peer != local)
+            .collect())
+    }
+}
+
 impl DhtService {
     pub async fn search_by_infohash(
         &self,
@@ -7975,6 +9271,45 @@ impl DhtService {
             .map_err(|e| e.to_string())?;
         receiver.await.map_err(|e| e.to_string())?
     }
+
+    /// Verified publisher feed of `peer_id`, if it has published one
+    pub async fn fetch_publisher_feed(
+        &self,
+        peer_id: &str,
+    ) -> Result<Option<publisher_feed::PublisherFeed>, String> {
+        let Some(raw) = self.get_dht_value(publisher_feed::feed_key(peer_id)).await? else {
+            return Ok(None);
+        };
+        let feed: publisher_feed::PublisherFeed = serde_json::from_slice(&raw)
+            .map_err(|e| format!("Failed to parse publisher feed: {}", e))?;
+        feed.verify(peer_id)?;
+        Ok(Some(feed))
+    }
+
+    /// Add `entry` to this node's signed publisher feed
+    pub async fn publish_feed_entry(&self, entry: publisher_feed::FeedEntry) -> Result<(), String> {
+        let previous = match self.fetch_publisher_feed(&self.peer_id).await {
+            Ok(feed) => feed,
+            Err(e) => {
+                warn!("Replacing unreadable publisher feed: {}", e);
+                None
+            }
+        };
+        let (seq, entries) = match previous {
+            Some(feed) => (
+                feed.seq + 1,
+                publisher_feed::merge_entries(&feed.entries, entry),
+            ),
+            None => (1, vec![entry]),
+        };
+        let keypair = identity::Keypair::ed25519_from_bytes(*self.ed25519_secret_key)
+            .map_err(|e| format!("Failed to load identity key: {}", e))?;
+        let feed = publisher_feed::PublisherFeed::signed(&keypair, seq, entries)?;
+        let value = serde_json::to_vec(&feed)
+            .map_err(|e| format!("Failed to serialize publisher feed: {}", e))?;
+        self.put_dht_value(publisher_feed::feed_key(&self.peer_id), value)
+            .await
+    }
 }
 
 impl DhtService {
@@ -0,0 +1,257 @@
+// Per-file network availability
+//
+// Answers "if I unpin this, can anyone else still serve it?" for one hash.
+// Providers come from the DHT provider records plus the seeders listed in the
+// file's metadata record; each one other than us is classified by how we can
+// reach it: a direct connection (or, when not connected, a public non-relay
+// address), a relay circuit only, or unknown. The counts are folded into a
+// grade. Like the diagnostics checks these are pure functions over snapshots;
+// `DhtService::file_availability` gathers the inputs.
+
+use super::connection_stats::PeerConnectionStats;
+use super::diagnostics::is_public;
+use libp2p::multiaddr::Protocol;
+use libp2p::Multiaddr;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderReachability {
+    Direct,
+    RelayOnly,
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum AvailabilityGrade {
+    /// Nobody else provides the file
+    Unavailable,
+    /// Other providers exist but none is known to be reachable
+    Poor,
+    /// Reachable through a single provider or only through relays
+    Fair,
+    Good,
+    Excellent,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderInfo {
+    pub peer_id: String,
+    pub reachability: ProviderReachability,
+    /// Currently connected to us
+    pub connected: bool,
+    /// Listed in the DHT provider records (otherwise only in the metadata)
+    pub in_provider_records: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AvailabilityReport {
+    pub file_hash: String,
+    /// Whether this node is among the providers
+    pub self_providing: bool,
+    /// Providers other than this node
+    pub providers: Vec<ProviderInfo>,
+    pub direct_count: usize,
+    pub relay_only_count: usize,
+    pub grade: AvailabilityGrade,
+    /// Someone else can still serve the file if this node stops
+    pub safe_to_unpin: bool,
+    pub generated_at: u64,
+}
+
+fn is_relay_address(addr: &Multiaddr) -> bool {
+    addr.iter().any(|p| matches!(p, Protocol::P2pCircuit))
+}
+
+fn has_public_ip(addr: &Multiaddr) -> bool {
+    addr.iter().any(|p| match p {
+        Protocol::Ip4(ip) => is_public(&ip.into()),
+        Protocol::Ip6(ip) => is_public(&ip.into()),
+        // DNS names are resolved by the dialer; assume they point somewhere public
+        Protocol::Dns(_) | Protocol::Dns4(_) | Protocol::Dns6(_) | Protocol::Dnsaddr(_) => true,
+        _ => false,
+    })
+}
+
+/// How `peer_id` can be reached, from its connection (if any) or its known
+/// addresses
+pub fn classify(
+    connection: Option<&PeerConnectionStats>,
+    addrs: &[Multiaddr],
+) -> ProviderReachability {
+    if let Some(stats) = connection {
+        // `relayed` is set when any connection is a circuit; a second
+        // connection next to it is the direct one hole punching opened
+        return if stats.relayed && stats.connection_count <= 1 {
+            ProviderReachability::RelayOnly
+        } else {
+            ProviderReachability::Direct
+        };
+    }
+    if addrs
+        .iter()
+        .any(|a| !is_relay_address(a) && has_public_ip(a))
+    {
+        ProviderReachability::Direct
+    } else if addrs.iter().any(is_relay_address) {
+        ProviderReachability::RelayOnly
+    } else {
+        ProviderReachability::Unknown
+    }
+}
+
+pub fn grade(providers: &[ProviderInfo]) -> AvailabilityGrade {
+    let direct = providers
+        .iter()
+        .filter(|p| p.reachability == ProviderReachability::Direct)
+        .count();
+    let reachable = providers
+        .iter()
+        .filter(|p| p.reachability != ProviderReachability::Unknown)
+        .count();
+    match (providers.len(), reachable, direct) {
+        (0, _, _) => AvailabilityGrade::Unavailable,
+        (_, 0, _) => AvailabilityGrade::Poor,
+        (_, _, d) if d >= 3 => AvailabilityGrade::Excellent,
+        (_, r, d) if d >= 1 && r >= 2 => AvailabilityGrade::Good,
+        _ => AvailabilityGrade::Fair,
+    }
+}
+
+/// Build the report from the provider records, the metadata seeders, our
+/// connections and the addresses known for each provider
+pub fn build_report(
+    file_hash: &str,
+    local_peer_id: &str,
+    provider_records: &[String],
+    metadata_seeders: &[String],
+    connections: &[PeerConnectionStats],
+    addresses: &HashMap<String, Vec<Multiaddr>>,
+    generated_at: u64,
+) -> AvailabilityReport {
+    let recorded: BTreeSet<&str> = provider_records.iter().map(String::as_str).collect();
+    let candidates: BTreeSet<&str> = recorded
+        .iter()
+        .copied()
+        .chain(metadata_seeders.iter().map(String::as_str))
+        .collect();
+
+    let providers: Vec<ProviderInfo> = candidates
+        .iter()
+        .filter(|peer| **peer != local_peer_id)
+        .map(|peer| {
+            let connection = connections.iter().find(|c| c.peer_id == *peer);
+            let addrs = addresses.get(*peer).map(Vec::as_slice).unwrap_or(&[]);
+            ProviderInfo {
+                peer_id: peer.to_string(),
+                reachability: classify(connection, addrs),
+                connected: connection.is_some(),
+                in_provider_records: recorded.contains(peer),
+            }
+        })
+        .collect();
+
+    let grade = grade(&providers);
+    AvailabilityReport {
+        file_hash: file_hash.to_string(),
+        self_providing: candidates.contains(local_peer_id),
+        direct_count: providers
+            .iter()
+            .filter(|p| p.reachability == ProviderReachability::Direct)
+            .count(),
+        relay_only_count: providers
+            .iter()
+            .filter(|p| p.reachability == ProviderReachability::RelayOnly)
+            .count(),
+        safe_to_unpin: grade >= AvailabilityGrade::Fair,
+        grade,
+        providers,
+        generated_at,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::connection_stats::ConnectionDirection;
+    use super::*;
+
+    fn connection(peer_id: &str, relayed: bool, connection_count: usize) -> PeerConnectionStats {
+        PeerConnectionStats {
+            peer_id: peer_id.to_string(),
+            remote_address: String::new(),
+            transport: "tcp".to_string(),
+            direction: ConnectionDirection::Outbound,
+            connected_since: 0,
+            duration_secs: 0,
+            bytes_in: 0,
+            bytes_out: 0,
+            open_streams: 0,
+            relayed,
+            connection_count,
+        }
+    }
+
+    #[test]
+    fn classifies_providers_and_grades() {
+        let connections = vec![
+            connection("relayed", true, 1),
+            connection("punched", true, 2),
+        ];
+        let mut addresses = HashMap::new();
+        addresses.insert(
+            "offline".to_string(),
+            vec!["/ip4/8.8.4.4/tcp/4001".parse().unwrap()],
+        );
+        addresses.insert(
+            "behind-nat".to_string(),
+            vec!["/ip4/192.168.1.5/tcp/4001".parse().unwrap()],
+        );
+
+        let report = build_report(
+            "hash",
+            "me",
+            &["me".into(), "relayed".into(), "offline".into()],
+            &["punched".into(), "behind-nat".into()],
+            &connections,
+            &addresses,
+            0,
+        );
+        assert!(report.self_providing);
+        assert_eq!(report.providers.len(), 4);
+        let reach = |id: &str| {
+            report
+                .providers
+                .iter()
+                .find(|p| p.peer_id == id)
+                .map(|p| p.reachability)
+                .unwrap()
+        };
+        assert_eq!(reach("relayed"), ProviderReachability::RelayOnly);
+        assert_eq!(reach("punched"), ProviderReachability::Direct);
+        assert_eq!(reach("offline"), ProviderReachability::Direct);
+        assert_eq!(reach("behind-nat"), ProviderReachability::Unknown);
+        assert_eq!((report.direct_count, report.relay_only_count), (2, 1));
+        assert_eq!(report.grade, AvailabilityGrade::Good);
+        assert!(report.safe_to_unpin);
+
+        let alone = build_report("hash", "me", &["me".into()], &[], &[], &HashMap::new(), 0);
+        assert_eq!(alone.grade, AvailabilityGrade::Unavailable);
+        assert!(!alone.safe_to_unpin);
+
+        let unreachable = build_report(
+            "hash",
+            "me",
+            &["behind-nat".into()],
+            &[],
+            &[],
+            &addresses,
+            0,
+        );
+        assert_eq!(unreachable.grade, AvailabilityGrade::Poor);
+        assert!(!unreachable.safe_to_unpin);
+    }
+}
@@ -0,0 +1,443 @@
+// Chunk-have announcements for active download swarms
+//
+// While a download is running, this node tells the other participants which
+// chunks it has verified and stored, and they answer with theirs. Each
+// announcement also lists the other swarm members the sender knows about, so a
+// late joiner that only reaches an original seeder learns about the peers
+// that are downloading the same file and can fetch chunks from them. Seeders
+// keep the table too: they record whoever announces to them and hand that list
+// back to the next peer.
+//
+// Only chunks this node announced as its own are served, and only while it is
+// in the swarm. Remote entries expire when a peer stops announcing.
+
+use crate::file_transfer::ranges::{from_ranges, to_ranges};
+use base64::{engine::general_purpose, Engine as _};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often haves are sent to the other swarm members
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(10);
+/// Remote haves not refreshed for this long are dropped
+const PEER_EXPIRY: Duration = Duration::from_secs(60);
+/// Swarm members passed along in one announcement
+const MAX_SHARED_PEERS: usize = 20;
+/// Upper bound on chunk IDs accepted from files we are not downloading
+const MAX_CHUNKS: u32 = 1 << 20;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct HaveAnnouncement {
+    pub file_hash: String,
+    pub total_chunks: u32,
+    /// Half-open `[start, end)` ranges of chunk IDs the sender can serve
+    pub chunks: Vec<[u32; 2]>,
+    /// The sender has the whole file
+    pub complete: bool,
+    /// Other swarm members known to the sender
+    #[serde(default)]
+    pub peers: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ChunkSwarmRequest {
+    Have(HaveAnnouncement),
+    Fetch { file_hash: String, chunk_id: u32 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ChunkSwarmResponse {
+    Have(HaveAnnouncement),
+    Chunk {
+        file_hash: String,
+        chunk_id: u32,
+        /// Base64 chunk data; `None` when the chunk is not served
+        data: Option<String>,
+    },
+}
+
+impl ChunkSwarmRequest {
+    /// Rough wire size, for connection stats
+    pub fn approx_len(&self) -> usize {
+        match self {
+            ChunkSwarmRequest::Have(have) => have.file_hash.len() + have.chunks.len() * 8,
+            ChunkSwarmRequest::Fetch { file_hash, .. } => file_hash.len() + 4,
+        }
+    }
+}
+
+impl ChunkSwarmResponse {
+    /// Rough wire size, for connection stats
+    pub fn approx_len(&self) -> usize {
+        match self {
+            ChunkSwarmResponse::Have(have) => have.file_hash.len() + have.chunks.len() * 8,
+            ChunkSwarmResponse::Chunk {
+                file_hash, data, ..
+            } => file_hash.len() + data.as_ref().map_or(0, String::len),
+        }
+    }
+}
+
+pub fn encode_chunk(data: &[u8]) -> String {
+    general_purpose::STANDARD.encode(data)
+}
+
+pub fn decode_chunk(encoded: &str) -> Result<Vec<u8>, String> {
+    general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|e| format!("Invalid chunk data: {}", e))
+}
+
+/// What one remote peer can serve for a file
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChunkHolder {
+    pub peer_id: String,
+    pub complete: bool,
+    pub chunk_count: usize,
+}
+
+struct LocalHaves {
+    total_chunks: u32,
+    chunks: BTreeSet<u32>,
+}
+
+struct RemoteHaves {
+    chunks: BTreeSet<u32>,
+    complete: bool,
+    seen: Instant,
+}
+
+#[derive(Default)]
+struct SwarmState {
+    /// Files this node is downloading, keyed by file hash
+    local: HashMap<String, LocalHaves>,
+    /// Haves announced by other peers, keyed by file hash
+    remote: HashMap<String, HashMap<PeerId, RemoteHaves>>,
+}
+
+/// Shared between the swarm loop and `DhtService`
+#[derive(Clone, Default)]
+pub struct ChunkSwarm {
+    state: Arc<Mutex<SwarmState>>,
+}
+
+/// Where the multi-source downloader stores a verified chunk
+pub fn chunk_path(file_hash: &str, chunk_id: u32) -> PathBuf {
+    PathBuf::from("./chunks")
+        .join(file_hash)
+        .join(format!("chunk_{}.dat", chunk_id))
+}
+
+impl SwarmState {
+    fn live_peers(&self, file_hash: &str, now: Instant) -> Vec<PeerId> {
+        self.remote
+            .get(file_hash)
+            .map(|peers| {
+                peers
+                    .iter()
+                    .filter(|(_, haves)| now.duration_since(haves.seen) < PEER_EXPIRY)
+                    .map(|(peer, _)| *peer)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl ChunkSwarm {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn with_state<T>(&self, f: impl FnOnce(&mut SwarmState) -> T) -> T {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        f(&mut state)
+    }
+
+    /// Start announcing `chunks` of `file_hash`; `seeders` are the first
+    /// peers told about it
+    pub fn join(&self, file_hash: &str, total_chunks: u32, chunks: &[u32], seeders: &[PeerId]) {
+        let now = Instant::now();
+        self.with_state(|state| {
+            state.local.insert(
+                file_hash.to_string(),
+                LocalHaves {
+                    total_chunks,
+                    chunks: chunks.iter().copied().collect(),
+                },
+            );
+            let peers = state.remote.entry(file_hash.to_string()).or_default();
+            for seeder in seeders {
+                peers.entry(*seeder).or_insert_with(|| RemoteHaves {
+                    chunks: BTreeSet::new(),
+                    complete: true,
+                    seen: now,
+                });
+            }
+        });
+    }
+
+    pub fn leave(&self, file_hash: &str) {
+        self.with_state(|state| {
+            state.local.remove(file_hash);
+            state.remote.remove(file_hash);
+        });
+    }
+
+    /// Record a newly stored chunk; false when not in the swarm for the file
+    pub fn add_local_chunk(&self, file_hash: &str, chunk_id: u32) -> bool {
+        self.with_state(|state| match state.local.get_mut(file_hash) {
+            Some(local) if chunk_id < local.total_chunks => {
+                local.chunks.insert(chunk_id);
+                true
+            }
+            _ => false,
+        })
+    }
+
+    /// Whether this node is downloading `file_hash`
+    pub fn is_member(&self, file_hash: &str) -> bool {
+        self.with_state(|state| state.local.contains_key(file_hash))
+    }
+
+    pub fn has_local_chunk(&self, file_hash: &str, chunk_id: u32) -> bool {
+        self.with_state(|state| {
+            state
+                .local
+                .get(file_hash)
+                .is_some_and(|local| local.chunks.contains(&chunk_id))
+        })
+    }
+
+    /// Our haves for `file_hash`, listing the members other than `to`.
+    /// `seeding` marks a file this node shares in full without downloading it.
+    pub fn announcement(&self, file_hash: &str, to: &PeerId, seeding: bool) -> HaveAnnouncement {
+        let now = Instant::now();
+        self.with_state(|state| {
+            let peers = state
+                .live_peers(file_hash, now)
+                .into_iter()
+                .filter(|peer| peer != to)
+                .take(MAX_SHARED_PEERS)
+                .map(|peer| peer.to_string())
+                .collect();
+            match state.local.get(file_hash) {
+                Some(local) => HaveAnnouncement {
+                    file_hash: file_hash.to_string(),
+                    total_chunks: local.total_chunks,
+                    chunks: to_ranges(&local.chunks),
+                    complete: local.chunks.len() as u32 >= local.total_chunks,
+                    peers,
+                },
+                None => HaveAnnouncement {
+                    file_hash: file_hash.to_string(),
+                    total_chunks: 0,
+                    chunks: Vec::new(),
+                    complete: seeding,
+                    peers,
+                },
+            }
+        })
+    }
+
+    /// Store the haves `peer` announced. Members it passes along are added
+    /// with no chunks so the next round announces to them too.
+    pub fn record(&self, peer: PeerId, announcement: &HaveAnnouncement, local_peer: &PeerId) {
+        let now = Instant::now();
+        self.with_state(|state| {
+            let local = state.local.get(&announcement.file_hash);
+            let downloading = local.is_some();
+            // Ranges come from the network; never expand past the chunk count
+            let limit = local.map_or(announcement.total_chunks.min(MAX_CHUNKS), |l| {
+                l.total_chunks
+            });
+            let ranges: Vec<[u32; 2]> = announcement
+                .chunks
+                .iter()
+                .map(|[start, end]| [*start.min(&limit), *end.min(&limit)])
+                .collect();
+            let chunks = from_ranges(&ranges);
+            let peers = state
+                .remote
+                .entry(announcement.file_hash.clone())
+                .or_default();
+            peers.insert(
+                peer,
+                RemoteHaves {
+                    chunks,
+                    complete: announcement.complete,
+                    seen: now,
+                },
+            );
+            if !downloading {
+                return;
+            }
+            for other in announcement.peers.iter().take(MAX_SHARED_PEERS) {
+                let Ok(other) = other.parse::<PeerId>() else {
+                    continue;
+                };
+                if other != *local_peer {
+                    peers.entry(other).or_insert_with(|| RemoteHaves {
+                        chunks: BTreeSet::new(),
+                        complete: false,
+                        seen: now,
+                    });
+                }
+            }
+        });
+    }
+
+    /// Announcements to send this round, one per live member of every swarm
+    /// this node is downloading in. Expired members are dropped.
+    pub fn due_announcements(&self) -> Vec<(PeerId, HaveAnnouncement)> {
+        let now = Instant::now();
+        let targets: Vec<(String, Vec<PeerId>)> = self.with_state(|state| {
+            for peers in state.remote.values_mut() {
+                peers.retain(|_, haves| now.duration_since(haves.seen) < PEER_EXPIRY);
+            }
+            state.remote.retain(|_, peers| !peers.is_empty());
+            state
+                .local
+                .keys()
+                .map(|file_hash| (file_hash.clone(), state.live_peers(file_hash, now)))
+                .collect()
+        });
+        targets
+            .into_iter()
+            .flat_map(|(file_hash, peers)| {
+                peers
+                    .into_iter()
+                    .map(|peer| (peer, self.announcement(&file_hash, &peer, false)))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Peers that announced `chunk_id`, partial holders with the fewest
+    /// chunks first so complete seeders are kept for what nobody else has.
+    /// Ties are ordered by peer ID so the choice is stable.
+    pub fn holders(&self, file_hash: &str, chunk_id: u32) -> Vec<PeerId> {
+        let now = Instant::now();
+        self.with_state(|state| {
+            let Some(peers) = state.remote.get(file_hash) else {
+                return Vec::new();
+            };
+            let mut holders: Vec<(&PeerId, &RemoteHaves)> = peers
+                .iter()
+                .filter(|(_, haves)| now.duration_since(haves.seen) < PEER_EXPIRY)
+                .filter(|(_, haves)| haves.complete || haves.chunks.contains(&chunk_id))
+                .collect();
+            holders.sort_by_key(|(peer, haves)| (haves.complete, haves.chunks.len(), **peer));
+            holders.into_iter().map(|(peer, _)| *peer).collect()
+        })
+    }
+
+    /// Chunks announced by each partial holder of `file_hash`, for
+    /// availability counts. Complete peers are left out; they are counted as
+    /// whole-file sources already.
+    pub fn partial_holdings(&self, file_hash: &str) -> Vec<Vec<u32>> {
+        let now = Instant::now();
+        self.with_state(|state| {
+            state
+                .remote
+                .get(file_hash)
+                .map(|peers| {
+                    peers
+                        .values()
+                        .filter(|haves| now.duration_since(haves.seen) < PEER_EXPIRY)
+                        .filter(|haves| !haves.complete && !haves.chunks.is_empty())
+                        .map(|haves| haves.chunks.iter().copied().collect())
+                        .collect()
+                })
+                .unwrap_or_default()
+        })
+    }
+
+    pub fn holders_summary(&self, file_hash: &str) -> Vec<ChunkHolder> {
+        let now = Instant::now();
+        self.with_state(|state| {
+            state
+                .remote
+                .get(file_hash)
+                .map(|peers| {
+                    peers
+                        .iter()
+                        .filter(|(_, haves)| now.duration_since(haves.seen) < PEER_EXPIRY)
+                        .map(|(peer, haves)| ChunkHolder {
+                            peer_id: peer.to_string(),
+                            complete: haves.complete,
+                            chunk_count: haves.chunks.len(),
+                        })
+                        .collect()
+                })
+                .unwrap_or_default()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_haves_and_shares_members() {
+        let me = PeerId::random();
+        let seeder = PeerId::random();
+        let early = PeerId::random();
+        let late = PeerId::random();
+        let swarm = ChunkSwarm::new();
+
+        swarm.join("file", 8, &[0, 1], &[seeder]);
+        assert!(swarm.add_local_chunk("file", 2));
+        assert!(!swarm.add_local_chunk("file", 8));
+        assert!(!swarm.add_local_chunk("other", 0));
+        assert!(swarm.has_local_chunk("file", 2));
+
+        let due = swarm.due_announcements();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].0, seeder);
+        assert_eq!(due[0].1.chunks, vec![[0, 3]]);
+        assert!(!due[0].1.complete);
+
+        // The seeder answers with its haves and another downloader it knows
+        swarm.record(
+            seeder,
+            &HaveAnnouncement {
+                file_hash: "file".into(),
+                total_chunks: 8,
+                chunks: vec![[0, 8]],
+                complete: true,
+                peers: vec![early.to_string(), me.to_string()],
+            },
+            &me,
+        );
+        swarm.record(
+            early,
+            &HaveAnnouncement {
+                file_hash: "file".into(),
+                total_chunks: 8,
+                chunks: vec![[4, 6]],
+                complete: false,
+                peers: Vec::new(),
+            },
+            &me,
+        );
+        assert_eq!(swarm.holders("file", 5), vec![early, seeder]);
+        assert_eq!(swarm.holders("file", 7), vec![seeder]);
+        assert_eq!(swarm.partial_holdings("file"), vec![vec![4, 5]]);
+
+        // A late joiner asking us hears about both other members
+        let reply = swarm.announcement("file", &late, false);
+        assert_eq!(reply.peers.len(), 2);
+        assert!(!reply.peers.contains(&late.to_string()));
+
+        swarm.leave("file");
+        assert!(swarm.holders("file", 5).is_empty());
+        assert!(!swarm.has_local_chunk("file", 0));
+        assert!(swarm.due_announcements().is_empty());
+    }
+}
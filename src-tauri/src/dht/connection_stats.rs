@@ -0,0 +1,738 @@
+// Per-peer connection statistics
+//
+// The swarm loop records every established/closed connection here together
+// with the request-response traffic it carries, so the UI can show transport,
+// direction, age, traffic and relay status for each connected peer.
+//
+// libp2p does not expose per-connection byte counters, so `bytes_in`/`bytes_out`
+// count the application payload of request-response exchanges (proxy echo,
+// WebRTC signaling, key requests). Bitswap blocks are not attributed per peer.
+// `open_streams` is the number of request-response exchanges currently in
+// flight with the peer.
+//
+// The tracker also keeps session-wide totals (distinct peers seen, time spent
+// relaying circuits for others) that outlive individual connections, and a
+// connect/disconnect history per peer for churn statistics. A peer session
+// runs from the first open connection to the peer until the last one closes.
+// Traffic with peers reached only through a relay is also added up per relay,
+// for the relay registry's usage metrics.
+
+use libp2p::core::ConnectedPoint;
+use libp2p::multiaddr::Protocol;
+use libp2p::request_response;
+use libp2p::swarm::ConnectionId;
+use libp2p::{Multiaddr, PeerId};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Window over which churn rates are computed
+const CHURN_WINDOW: Duration = Duration::from_secs(60 * 60);
+/// Shortest window churn rates are extrapolated from, so the first minutes
+/// after startup don't report huge rates
+const MIN_CHURN_WINDOW: Duration = Duration::from_secs(60);
+/// Finished sessions shorter than this count as short (flapping peers)
+const SHORT_SESSION: Duration = Duration::from_secs(30);
+const MAX_CHURN_EVENTS: usize = 10_000;
+/// Disconnected peers whose session history is kept; beyond this the ones
+/// gone longest are folded into the totals and forgotten
+const MAX_PEER_SESSIONS: usize = 1_000;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionDirection {
+    Inbound,
+    Outbound,
+}
+
+#[derive(Debug, Clone)]
+struct ConnectionRecord {
+    peer_id: PeerId,
+    remote_address: Multiaddr,
+    transport: String,
+    direction: ConnectionDirection,
+    relayed: bool,
+    /// Relay the circuit goes through, for relayed connections
+    relay: Option<PeerId>,
+    established_at: Instant,
+    established_unix: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct TrafficCounters {
+    bytes_in: u64,
+    bytes_out: u64,
+    open_streams: u32,
+}
+
+/// Statistics for one connected peer, reported by `get_peer_connection_stats`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerConnectionStats {
+    pub peer_id: String,
+    pub remote_address: String,
+    /// Transport of the oldest open connection ("tcp", "quic", "websocket", ...)
+    pub transport: String,
+    pub direction: ConnectionDirection,
+    /// Unix timestamp (seconds) when the oldest open connection was established
+    pub connected_since: u64,
+    pub duration_secs: u64,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub open_streams: u32,
+    /// True if any open connection to the peer goes through a relay circuit
+    pub relayed: bool,
+    /// True if any open connection to the peer is direct, e.g. after a
+    /// hole punch upgraded a relayed one
+    pub direct: bool,
+    /// Relay of a relayed connection to the peer
+    pub relay_peer_id: Option<String>,
+    pub connection_count: usize,
+}
+
+/// Totals accumulated since the swarm started
+#[derive(Debug, Clone, Copy, Default, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionNetworkTotals {
+    pub unique_peers: usize,
+    /// Seconds of relay circuits served for other peers, open ones included
+    pub relay_circuit_secs: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+struct PeerSessions {
+    connects: u64,
+    disconnects: u64,
+    short_sessions: u64,
+    closed_session_secs: u64,
+    last_session_secs: Option<u64>,
+    current_start: Option<Instant>,
+    ended_at: Option<Instant>,
+}
+
+/// Connect/disconnect history of one peer
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerChurnEntry {
+    pub peer_id: String,
+    pub connects: u64,
+    pub disconnects: u64,
+    pub short_sessions: u64,
+    /// Mean length of finished sessions
+    pub avg_session_secs: Option<f64>,
+    pub last_session_secs: Option<u64>,
+    pub connected: bool,
+}
+
+/// Peer churn since the swarm started, reported by `get_peer_churn_stats`
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChurnStats {
+    /// Length of the window the `*_in_window` counts cover
+    pub window_secs: u64,
+    pub connects_in_window: u64,
+    pub disconnects_in_window: u64,
+    /// Disconnects per connected peer per hour, extrapolated from the window
+    pub churn_rate_per_hour: f64,
+    pub total_connects: u64,
+    pub total_disconnects: u64,
+    /// Mean length of finished peer sessions
+    pub avg_session_secs: Option<f64>,
+    /// Finished sessions shorter than 30 seconds
+    pub short_sessions: u64,
+    pub connected_peers: usize,
+    /// Peers with the most disconnects, most first
+    pub flappiest_peers: Vec<PeerChurnEntry>,
+}
+
+#[derive(Default)]
+struct TrackerState {
+    connections: HashMap<ConnectionId, ConnectionRecord>,
+    traffic: HashMap<PeerId, TrafficCounters>,
+    peers_seen: HashSet<PeerId>,
+    /// Open relay circuits keyed by (source, destination)
+    relay_circuits: HashMap<(PeerId, PeerId), Vec<Instant>>,
+    closed_relay_secs: u64,
+    /// Bytes carried by each relay we use since `take_relay_bytes`
+    relay_bytes: HashMap<PeerId, u64>,
+    peer_sessions: HashMap<PeerId, PeerSessions>,
+    /// Counts of peers evicted from `peer_sessions`, still part of the totals
+    evicted_sessions: PeerSessions,
+    /// Recent peer session starts (`true`) and ends within `CHURN_WINDOW`
+    churn_events: VecDeque<(Instant, bool)>,
+    first_churn_event: Option<Instant>,
+}
+
+/// Shared between the swarm loop (writer) and `DhtService` (reader)
+#[derive(Clone, Default)]
+pub struct ConnectionStatsTracker {
+    state: Arc<Mutex<TrackerState>>,
+}
+
+/// Short name of the transport `addr` runs over; circuit addresses report "relay"
+pub fn transport_name(addr: &Multiaddr) -> String {
+    // The last transport component wins, so circuit addresses report "relay"
+    let mut name = "unknown";
+    for protocol in addr.iter() {
+        name = match protocol {
+            Protocol::Tcp(_) => "tcp",
+            Protocol::QuicV1 | Protocol::Quic => "quic",
+            Protocol::Ws(_) | Protocol::Wss(_) => "websocket",
+            Protocol::WebRTCDirect | Protocol::WebRTC => "webrtc",
+            Protocol::WebTransport => "webtransport",
+            Protocol::P2pCircuit => "relay",
+            _ => continue,
+        };
+    }
+    name.to_string()
+}
+
+/// Relay peer of a circuit address (`.../p2p/<relay>/p2p-circuit/...`)
+pub fn circuit_relay(addr: &Multiaddr) -> Option<PeerId> {
+    let mut last_peer = None;
+    for protocol in addr.iter() {
+        match protocol {
+            Protocol::P2p(peer) => last_peer = Some(peer),
+            Protocol::P2pCircuit => return last_peer,
+            _ => {}
+        }
+    }
+    None
+}
+
+impl ConnectionStatsTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the relay the connection goes through, if any
+    pub fn connection_established(
+        &self,
+        connection_id: ConnectionId,
+        peer_id: PeerId,
+        endpoint: &ConnectedPoint,
+    ) -> Option<PeerId> {
+        let remote_address = endpoint.get_remote_address().clone();
+        let direction = if endpoint.is_dialer() {
+            ConnectionDirection::Outbound
+        } else {
+            ConnectionDirection::Inbound
+        };
+        // Inbound circuits name the relay in our listen address
+        let relay = match endpoint {
+            ConnectedPoint::Listener { local_addr, .. } => {
+                circuit_relay(&remote_address).or_else(|| circuit_relay(local_addr))
+            }
+            ConnectedPoint::Dialer { .. } => circuit_relay(&remote_address),
+        };
+        let record = ConnectionRecord {
+            peer_id,
+            transport: transport_name(&remote_address),
+            relayed: endpoint.is_relayed()
+                || remote_address.iter().any(|p| matches!(p, Protocol::P2pCircuit))
+                || relay.is_some(),
+            relay,
+            remote_address,
+            direction,
+            established_at: Instant::now(),
+            established_unix: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+        if let Ok(mut state) = self.state.lock() {
+            let now = record.established_at;
+            let first = !state.connections.values().any(|c| c.peer_id == peer_id);
+            state.connections.insert(connection_id, record);
+            state.traffic.entry(peer_id).or_default();
+            state.peers_seen.insert(peer_id);
+            if first {
+                state.peer_session_started(peer_id, now);
+            }
+        }
+        relay
+    }
+
+    pub fn connection_closed(&self, connection_id: ConnectionId, peer_id: PeerId) {
+        if let Ok(mut state) = self.state.lock() {
+            state.connections.remove(&connection_id);
+            if !state.connections.values().any(|c| c.peer_id == peer_id) {
+                state.traffic.remove(&peer_id);
+                state.peer_session_ended(peer_id, Instant::now());
+            }
+        }
+    }
+
+    /// Churn rates, session lengths and the `limit` flappiest peers
+    pub fn churn_stats(&self, limit: usize) -> Option<ChurnStats> {
+        let state = self.state.lock().ok()?;
+        Some(state.churn_stats(Instant::now(), limit))
+    }
+
+    /// Our relay server accepted a circuit from `src` to `dst`
+    pub fn relay_circuit_opened(&self, src: PeerId, dst: PeerId) {
+        if let Ok(mut state) = self.state.lock() {
+            state
+                .relay_circuits
+                .entry((src, dst))
+                .or_default()
+                .push(Instant::now());
+        }
+    }
+
+    pub fn relay_circuit_closed(&self, src: PeerId, dst: PeerId) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        // Entries are removed once empty, so an existing entry has a circuit
+        let Some(open) = state.relay_circuits.get_mut(&(src, dst)) else {
+            return;
+        };
+        let opened = open.remove(0);
+        if open.is_empty() {
+            state.relay_circuits.remove(&(src, dst));
+        }
+        state.closed_relay_secs += opened.elapsed().as_secs();
+    }
+
+    /// Relay an open relayed connection to `peer` goes through
+    pub fn relay_of(&self, peer: &PeerId) -> Option<PeerId> {
+        let state = self.state.lock().ok()?;
+        state
+            .connections
+            .values()
+            .filter(|c| c.peer_id == *peer)
+            .find_map(|c| c.relay)
+    }
+
+    /// Bytes each relay carried for us since the last call, which resets
+    /// them
+    pub fn take_relay_bytes(&self) -> Vec<(PeerId, u64)> {
+        let Ok(mut state) = self.state.lock() else {
+            return Vec::new();
+        };
+        state.relay_bytes.drain().collect()
+    }
+
+    /// Relay circuits our relay server is carrying right now
+    pub fn open_relay_circuits(&self) -> usize {
+        let Ok(state) = self.state.lock() else {
+            return 0;
+        };
+        state.relay_circuits.values().map(Vec::len).sum()
+    }
+
+    pub fn session_totals(&self) -> SessionNetworkTotals {
+        let Ok(state) = self.state.lock() else {
+            return SessionNetworkTotals::default();
+        };
+        let open_secs: u64 = state
+            .relay_circuits
+            .values()
+            .flatten()
+            .map(|opened| opened.elapsed().as_secs())
+            .sum();
+        SessionNetworkTotals {
+            unique_peers: state.peers_seen.len(),
+            relay_circuit_secs: state.closed_relay_secs + open_secs,
+        }
+    }
+
+    /// We sent a request of `bytes` to `peer`, opening an exchange
+    pub fn request_sent(&self, peer: &PeerId, bytes: usize) {
+        self.with_counters(peer, |c| {
+            c.open_streams = c.open_streams.saturating_add(1);
+            c.bytes_out = c.bytes_out.saturating_add(bytes as u64);
+        });
+    }
+
+    /// We answered a request from `peer` with `bytes` of payload
+    pub fn response_sent(&self, peer: &PeerId, bytes: usize) {
+        self.with_counters(peer, |c| {
+            c.bytes_out = c.bytes_out.saturating_add(bytes as u64);
+        });
+    }
+
+    /// Account a request-response event. `request_len`/`response_len` give
+    /// the payload size of the protocol's messages.
+    pub fn observe_rr_event<Req, Resp>(
+        &self,
+        event: &request_response::Event<Req, Resp>,
+        request_len: impl Fn(&Req) -> usize,
+        response_len: impl Fn(&Resp) -> usize,
+    ) {
+        match event {
+            request_response::Event::Message { peer, message } => match message {
+                request_response::Message::Request { request, .. } => {
+                    let bytes = request_len(request) as u64;
+                    self.with_counters(peer, |c| {
+                        c.open_streams = c.open_streams.saturating_add(1);
+                        c.bytes_in = c.bytes_in.saturating_add(bytes);
+                    });
+                }
+                request_response::Message::Response { response, .. } => {
+                    let bytes = response_len(response) as u64;
+                    self.with_counters(peer, |c| {
+                        c.open_streams = c.open_streams.saturating_sub(1);
+                        c.bytes_in = c.bytes_in.saturating_add(bytes);
+                    });
+                }
+            },
+            request_response::Event::OutboundFailure { peer, .. }
+            | request_response::Event::InboundFailure { peer, .. }
+            | request_response::Event::ResponseSent { peer, .. } => {
+                self.with_counters(peer, |c| {
+                    c.open_streams = c.open_streams.saturating_sub(1);
+                });
+            }
+        }
+    }
+
+    fn with_counters(&self, peer: &PeerId, f: impl FnOnce(&mut TrafficCounters)) {
+        if let Ok(mut guard) = self.state.lock() {
+            let state = &mut *guard;
+            // Only track peers with an open connection; late events for a
+            // peer that already disconnected are dropped.
+            let Some(counters) = state.traffic.get_mut(peer) else {
+                return;
+            };
+            let before = counters.bytes_in + counters.bytes_out;
+            f(counters);
+            let moved = (counters.bytes_in + counters.bytes_out).saturating_sub(before);
+            // Traffic goes through the relay only while there is no direct
+            // connection
+            let mut connections = state.connections.values().filter(|c| c.peer_id == *peer);
+            let relay = connections.clone().find_map(|c| c.relay);
+            if let Some(relay) = relay.filter(|_| moved > 0 && connections.all(|c| c.relayed)) {
+                *state.relay_bytes.entry(relay).or_default() += moved;
+            }
+        }
+    }
+
+    /// One entry per connected peer, longest-connected first
+    pub fn snapshot(&self) -> Vec<PeerConnectionStats> {
+        let state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return Vec::new(),
+        };
+
+        let mut by_peer: HashMap<PeerId, Vec<&ConnectionRecord>> = HashMap::new();
+        for record in state.connections.values() {
+            by_peer.entry(record.peer_id).or_default().push(record);
+        }
+
+        let mut stats: Vec<PeerConnectionStats> = by_peer
+            .into_iter()
+            .filter_map(|(peer_id, records)| {
+                let oldest = records.iter().min_by_key(|r| r.established_at)?;
+                let traffic = state.traffic.get(&peer_id).copied().unwrap_or_default();
+                Some(PeerConnectionStats {
+                    peer_id: peer_id.to_string(),
+                    remote_address: oldest.remote_address.to_string(),
+                    transport: oldest.transport.clone(),
+                    direction: oldest.direction,
+                    connected_since: oldest.established_unix,
+                    duration_secs: oldest.established_at.elapsed().as_secs(),
+                    bytes_in: traffic.bytes_in,
+                    bytes_out: traffic.bytes_out,
+                    open_streams: traffic.open_streams,
+                    relayed: records.iter().any(|r| r.relayed),
+                    direct: records.iter().any(|r| !r.relayed),
+                    relay_peer_id: records
+                        .iter()
+                        .find_map(|r| r.relay)
+                        .map(|relay| relay.to_string()),
+                    connection_count: records.len(),
+                })
+            })
+            .collect();
+        stats.sort_by(|a, b| {
+            a.connected_since
+                .cmp(&b.connected_since)
+                .then_with(|| a.peer_id.cmp(&b.peer_id))
+        });
+        stats
+    }
+}
+
+impl TrackerState {
+    fn record_churn_event(&mut self, now: Instant, connected: bool) {
+        self.first_churn_event.get_or_insert(now);
+        self.churn_events.push_back((now, connected));
+        while self.churn_events.len() > MAX_CHURN_EVENTS
+            || self
+                .churn_events
+                .front()
+                .is_some_and(|(at, _)| now.saturating_duration_since(*at) > CHURN_WINDOW)
+        {
+            self.churn_events.pop_front();
+        }
+    }
+
+    fn peer_session_started(&mut self, peer_id: PeerId, now: Instant) {
+        let sessions = self.peer_sessions.entry(peer_id).or_default();
+        sessions.connects += 1;
+        sessions.current_start = Some(now);
+        self.record_churn_event(now, true);
+    }
+
+    fn peer_session_ended(&mut self, peer_id: PeerId, now: Instant) {
+        let Some(sessions) = self.peer_sessions.get_mut(&peer_id) else {
+            return;
+        };
+        let Some(started) = sessions.current_start.take() else {
+            return;
+        };
+        let length = now.saturating_duration_since(started);
+        sessions.disconnects += 1;
+        sessions.closed_session_secs += length.as_secs();
+        sessions.last_session_secs = Some(length.as_secs());
+        if length < SHORT_SESSION {
+            sessions.short_sessions += 1;
+        }
+        sessions.ended_at = Some(now);
+        self.record_churn_event(now, false);
+        self.evict_peer_sessions();
+    }
+
+    /// Keep `peer_sessions` to `MAX_PEER_SESSIONS` by dropping the peers
+    /// that disconnected longest ago. Connected peers are never dropped.
+    fn evict_peer_sessions(&mut self) {
+        while self.peer_sessions.len() > MAX_PEER_SESSIONS {
+            let Some(oldest) = self
+                .peer_sessions
+                .iter()
+                .filter_map(|(peer_id, s)| {
+                    s.current_start.is_none().then_some((s.ended_at, *peer_id))
+                })
+                .min()
+                .map(|(_, peer_id)| peer_id)
+            else {
+                return;
+            };
+            if let Some(gone) = self.peer_sessions.remove(&oldest) {
+                let evicted = &mut self.evicted_sessions;
+                evicted.connects += gone.connects;
+                evicted.disconnects += gone.disconnects;
+                evicted.short_sessions += gone.short_sessions;
+                evicted.closed_session_secs += gone.closed_session_secs;
+            }
+        }
+    }
+
+    fn churn_stats(&self, now: Instant, limit: usize) -> ChurnStats {
+        let window_start = now.checked_sub(CHURN_WINDOW);
+        let in_window = |at: &Instant| window_start.map_or(true, |start| *at >= start);
+        let (connects_in_window, disconnects_in_window) = self
+            .churn_events
+            .iter()
+            .filter(|(at, _)| in_window(at))
+            .fold((0u64, 0u64), |(up, down), (_, connected)| {
+                if *connected {
+                    (up + 1, down)
+                } else {
+                    (up, down + 1)
+                }
+            });
+
+        let observed = self
+            .first_churn_event
+            .map(|first| now.saturating_duration_since(first))
+            .unwrap_or_default()
+            .clamp(MIN_CHURN_WINDOW, CHURN_WINDOW);
+        let connected_peers = self
+            .peer_sessions
+            .values()
+            .filter(|s| s.current_start.is_some())
+            .count();
+        let churn_rate_per_hour = disconnects_in_window as f64
+            / connected_peers.max(1) as f64
+            * (3600.0 / observed.as_secs_f64());
+
+        let mut total_connects = 0;
+        let mut total_disconnects = 0;
+        let mut short_sessions = 0;
+        let mut closed_secs = 0;
+        for sessions in self
+            .peer_sessions
+            .values()
+            .chain(std::iter::once(&self.evicted_sessions))
+        {
+            total_connects += sessions.connects;
+            total_disconnects += sessions.disconnects;
+            short_sessions += sessions.short_sessions;
+            closed_secs += sessions.closed_session_secs;
+        }
+
+        let mut flappiest_peers: Vec<PeerChurnEntry> = self
+            .peer_sessions
+            .iter()
+            .filter(|(_, s)| s.disconnects > 0)
+            .map(|(peer_id, s)| PeerChurnEntry {
+                peer_id: peer_id.to_string(),
+                connects: s.connects,
+                disconnects: s.disconnects,
+                short_sessions: s.short_sessions,
+                avg_session_secs: average_secs(s.closed_session_secs, s.disconnects),
+                last_session_secs: s.last_session_secs,
+                connected: s.current_start.is_some(),
+            })
+            .collect();
+        flappiest_peers.sort_by(|a, b| {
+            b.disconnects
+                .cmp(&a.disconnects)
+                .then_with(|| a.peer_id.cmp(&b.peer_id))
+        });
+        flappiest_peers.truncate(limit);
+
+        ChurnStats {
+            window_secs: observed.as_secs(),
+            connects_in_window,
+            disconnects_in_window,
+            churn_rate_per_hour,
+            total_connects,
+            total_disconnects,
+            avg_session_secs: average_secs(closed_secs, total_disconnects),
+            short_sessions,
+            connected_peers,
+            flappiest_peers,
+        }
+    }
+}
+
+fn average_secs(total_secs: u64, count: u64) -> Option<f64> {
+    (count > 0).then(|| total_secs as f64 / count as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::core::transport::PortUse;
+    use libp2p::core::Endpoint;
+
+    #[test]
+    fn tracks_connections_and_traffic_per_peer() {
+        let tracker = ConnectionStatsTracker::new();
+        let peer = PeerId::random();
+        let relay = PeerId::random();
+        let direct: Multiaddr = "/ip4/1.2.3.4/tcp/4001".parse().unwrap();
+        let relayed: Multiaddr = format!("/ip4/5.6.7.8/udp/4001/quic-v1/p2p/{}/p2p-circuit", relay)
+            .parse()
+            .unwrap();
+
+        let first = ConnectionId::new_unchecked(1);
+        let second = ConnectionId::new_unchecked(2);
+        tracker.connection_established(
+            first,
+            peer,
+            &ConnectedPoint::Dialer {
+                address: direct,
+                role_override: Endpoint::Dialer,
+                port_use: PortUse::Reuse,
+            },
+        );
+        tracker.connection_established(
+            second,
+            peer,
+            &ConnectedPoint::Listener {
+                local_addr: "/ip4/0.0.0.0/tcp/4001".parse().unwrap(),
+                send_back_addr: relayed,
+            },
+        );
+
+        tracker.request_sent(&peer, 100);
+        tracker.response_sent(&peer, 40);
+        tracker.request_sent(&PeerId::random(), 1_000); // not connected, ignored
+
+        let stats = tracker.snapshot();
+        assert_eq!(stats.len(), 1);
+        let peer_stats = &stats[0];
+        assert_eq!(peer_stats.transport, "tcp");
+        assert_eq!(peer_stats.direction, ConnectionDirection::Outbound);
+        assert!(peer_stats.relayed);
+        assert!(peer_stats.direct);
+        assert_eq!(peer_stats.relay_peer_id, Some(relay.to_string()));
+        assert_eq!(tracker.relay_of(&peer), Some(relay));
+        assert_eq!(peer_stats.connection_count, 2);
+        assert_eq!(peer_stats.bytes_out, 140);
+        assert_eq!(peer_stats.bytes_in, 0);
+        assert_eq!(peer_stats.open_streams, 1);
+        // The direct connection carried that traffic
+        assert!(tracker.take_relay_bytes().is_empty());
+
+        tracker.connection_closed(first, peer);
+        assert_eq!(tracker.snapshot()[0].transport, "relay");
+        assert!(!tracker.snapshot()[0].direct);
+        tracker.response_sent(&peer, 50);
+        assert_eq!(tracker.take_relay_bytes(), vec![(relay, 50)]);
+        assert!(tracker.take_relay_bytes().is_empty());
+        tracker.connection_closed(second, peer);
+        assert!(tracker.snapshot().is_empty());
+
+        let dst = PeerId::random();
+        tracker.relay_circuit_opened(peer, dst);
+        let totals = tracker.session_totals();
+        assert_eq!(totals.unique_peers, 1);
+        assert_eq!(totals.relay_circuit_secs, 0);
+        assert_eq!(tracker.open_relay_circuits(), 1);
+        tracker.relay_circuit_closed(peer, dst);
+        assert_eq!(tracker.open_relay_circuits(), 0);
+    }
+
+    #[test]
+    fn churn_counts_peer_sessions_not_connections() {
+        let mut state = TrackerState::default();
+        let flappy = PeerId::random();
+        let stable = PeerId::random();
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        state.peer_session_started(stable, at(0));
+        for round in 0..3 {
+            state.peer_session_started(flappy, at(round * 100));
+            state.peer_session_ended(flappy, at(round * 100 + 10));
+        }
+        // A second close without a new session is ignored
+        state.peer_session_ended(flappy, at(400));
+        state.peer_session_started(flappy, at(500));
+        state.peer_session_ended(flappy, at(620));
+
+        let stats = state.churn_stats(at(900), 5);
+        assert_eq!(stats.total_connects, 5);
+        assert_eq!(stats.total_disconnects, 4);
+        assert_eq!(stats.short_sessions, 3);
+        assert_eq!(stats.avg_session_secs, Some(37.5));
+        assert_eq!(stats.connected_peers, 1);
+        assert_eq!(stats.window_secs, 900);
+        assert_eq!(stats.churn_rate_per_hour, 16.0);
+        assert_eq!(stats.flappiest_peers.len(), 1);
+        let entry = &stats.flappiest_peers[0];
+        assert_eq!(entry.peer_id, flappy.to_string());
+        assert_eq!(entry.last_session_secs, Some(120));
+        assert!(!entry.connected);
+
+        let later = state.churn_stats(at(900) + CHURN_WINDOW, 5);
+        assert_eq!(later.disconnects_in_window, 0);
+        assert_eq!(later.total_disconnects, 4);
+    }
+
+    #[test]
+    fn departed_peers_are_evicted_but_still_counted() {
+        let mut state = TrackerState::default();
+        let start = Instant::now();
+        let stable = PeerId::random();
+        state.peer_session_started(stable, start);
+        for i in 0..MAX_PEER_SESSIONS as u64 + 10 {
+            let peer = PeerId::random();
+            state.peer_session_started(peer, start + Duration::from_secs(i));
+            state.peer_session_ended(peer, start + Duration::from_secs(i + 1));
+        }
+        assert_eq!(state.peer_sessions.len(), MAX_PEER_SESSIONS);
+        assert!(state.peer_sessions.contains_key(&stable));
+
+        let stats = state.churn_stats(start + Duration::from_secs(2_000), 5);
+        assert_eq!(stats.total_disconnects, MAX_PEER_SESSIONS as u64 + 10);
+        assert_eq!(stats.connected_peers, 1);
+    }
+}
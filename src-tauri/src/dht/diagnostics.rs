@@ -0,0 +1,383 @@
+// Network diagnostics
+//
+// One pass/warn/fail report answering "why can't peers reach me?": whether
+// the listen port is reachable from outside (AutoNAT, or an inbound direct
+// connection from a public peer), whether we sit behind more than one NAT or a
+// carrier-grade NAT, whether the bootstrap nodes are connected, and how long a
+// DHT lookup takes. The checks are pure functions over snapshots so they can
+// be tested without a swarm; `DhtService::run_diagnostics` gathers the inputs.
+
+use super::connection_stats::{ConnectionDirection, PeerConnectionStats};
+use super::topology::{BootstrapNode, BootstrapStatus};
+use super::NatReachabilityState;
+use libp2p::multiaddr::Protocol;
+use libp2p::Multiaddr;
+use serde::Serialize;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+
+/// Lookups slower than this are reported as a warning
+pub const SLOW_LOOKUP: Duration = Duration::from_secs(5);
+/// Lookups are abandoned (and fail) after this long
+pub const LOOKUP_TIMEOUT: Duration = Duration::from_secs(20);
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticCheck {
+    /// Stable identifier ("port_reachability", "nat", "bootstrap", "dht_lookup")
+    pub id: String,
+    pub status: CheckStatus,
+    pub summary: String,
+    /// What to do about a warning or failure
+    pub suggestion: Option<String>,
+}
+
+impl DiagnosticCheck {
+    fn new(id: &str, status: CheckStatus, summary: impl Into<String>) -> Self {
+        Self {
+            id: id.to_string(),
+            status,
+            summary: summary.into(),
+            suggestion: None,
+        }
+    }
+
+    fn suggest(mut self, suggestion: impl Into<String>) -> Self {
+        self.suggestion = Some(suggestion.into());
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DiagnosticsReport {
+    /// Worst status among the checks
+    pub status: CheckStatus,
+    pub checks: Vec<DiagnosticCheck>,
+    /// Unix timestamp (seconds)
+    pub generated_at: u64,
+}
+
+impl DiagnosticsReport {
+    pub fn new(checks: Vec<DiagnosticCheck>, generated_at: u64) -> Self {
+        let status = checks
+            .iter()
+            .map(|c| c.status)
+            .max()
+            .unwrap_or(CheckStatus::Pass);
+        Self {
+            status,
+            checks,
+            generated_at,
+        }
+    }
+}
+
+fn ip_of(addr: &str) -> Option<IpAddr> {
+    addr.parse::<Multiaddr>()
+        .ok()?
+        .iter()
+        .find_map(|p| match p {
+            Protocol::Ip4(ip) => Some(IpAddr::V4(ip)),
+            Protocol::Ip6(ip) => Some(IpAddr::V6(ip)),
+            _ => None,
+        })
+}
+
+/// 100.64.0.0/10, handed out by carrier-grade NAT
+fn is_shared_address(ip: &Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    a == 100 && (64..128).contains(&b)
+}
+
+pub(super) fn is_public(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || is_shared_address(v4))
+        }
+        IpAddr::V6(v6) => {
+            let unique_local = (v6.segments()[0] & 0xfe00) == 0xfc00;
+            let link_local = (v6.segments()[0] & 0xffc0) == 0xfe80;
+            !(v6.is_loopback() || v6.is_unspecified() || unique_local || link_local)
+        }
+    }
+}
+
+/// Is the listen port reachable from outside?
+pub fn check_port_reachability(
+    reachability: NatReachabilityState,
+    autonat_enabled: bool,
+    peers: &[PeerConnectionStats],
+) -> DiagnosticCheck {
+    const ID: &str = "port_reachability";
+    if reachability == NatReachabilityState::Public {
+        return DiagnosticCheck::new(
+            ID,
+            CheckStatus::Pass,
+            "AutoNAT confirmed the listen port is reachable",
+        );
+    }
+    // A direct inbound connection from a public address proves it as well
+    let inbound = peers.iter().find(|p| {
+        p.direction == ConnectionDirection::Inbound
+            && !p.relayed
+            && ip_of(&p.remote_address).is_some_and(|ip| is_public(&ip))
+    });
+    if let Some(peer) = inbound {
+        return DiagnosticCheck::new(
+            ID,
+            CheckStatus::Pass,
+            format!(
+                "Accepted a direct inbound connection from {}",
+                peer.remote_address
+            ),
+        );
+    }
+    match reachability {
+        NatReachabilityState::Private => DiagnosticCheck::new(
+            ID,
+            CheckStatus::Fail,
+            "AutoNAT probes could not reach the listen port",
+        )
+        .suggest(
+            "Forward the listen port on your router or enable UPnP; until then peers \
+             reach you only through relays",
+        ),
+        _ if !autonat_enabled => DiagnosticCheck::new(
+            ID,
+            CheckStatus::Warn,
+            "AutoNAT is disabled and no direct inbound connection has been seen",
+        )
+        .suggest("Enable AutoNAT in network settings to test reachability"),
+        _ => DiagnosticCheck::new(
+            ID,
+            CheckStatus::Warn,
+            "Reachability has not been determined yet",
+        )
+        .suggest("Run the diagnostics again once AutoNAT has probed the listen port"),
+    }
+}
+
+/// Detect double NAT and carrier-grade NAT from the addresses peers observe
+/// us at, compared with our own listen addresses
+pub fn check_nat(listen_addrs: &[String], observed_addrs: &[String]) -> DiagnosticCheck {
+    const ID: &str = "nat";
+    let local: Vec<IpAddr> = listen_addrs.iter().filter_map(|a| ip_of(a)).collect();
+    let mut observed: Vec<IpAddr> = observed_addrs
+        .iter()
+        .filter_map(|a| ip_of(a))
+        .filter(|ip| !ip.is_loopback() && !local.contains(ip))
+        .collect();
+    observed.sort();
+    observed.dedup();
+
+    if let Some(IpAddr::V4(shared)) = observed
+        .iter()
+        .find(|ip| matches!(ip, IpAddr::V4(v4) if is_shared_address(v4)))
+    {
+        return DiagnosticCheck::new(
+            ID,
+            CheckStatus::Fail,
+            format!("Carrier-grade NAT detected (shared address {})", shared),
+        )
+        .suggest(
+            "Port forwarding cannot work behind CGNAT; ask your ISP for a public IPv4 \
+             address or rely on relays and IPv6",
+        );
+    }
+    if let Some(private) = observed.iter().find(|ip| !is_public(ip)) {
+        return DiagnosticCheck::new(
+            ID,
+            CheckStatus::Warn,
+            format!(
+                "Double NAT detected: the next router sees this node at private address {}",
+                private
+            ),
+        )
+        .suggest(
+            "Put the inner router in bridge mode, or forward the listen port on both routers",
+        );
+    }
+    let public: Vec<&IpAddr> = observed.iter().filter(|ip| is_public(ip)).collect();
+    let public_v4: Vec<&&IpAddr> = public.iter().filter(|ip| ip.is_ipv4()).collect();
+    if public_v4.len() > 1 {
+        let list: Vec<String> = public_v4.iter().map(|ip| ip.to_string()).collect();
+        return DiagnosticCheck::new(
+            ID,
+            CheckStatus::Warn,
+            format!("Peers see several external addresses ({})", list.join(", ")),
+        )
+        .suggest(
+            "The NAT may be symmetric or load-balanced, which makes hole punching unreliable",
+        );
+    }
+    if local.iter().any(is_public) {
+        return DiagnosticCheck::new(
+            ID,
+            CheckStatus::Pass,
+            "Listening directly on a public address",
+        );
+    }
+    match public.first() {
+        Some(external) => DiagnosticCheck::new(
+            ID,
+            CheckStatus::Pass,
+            format!("Single NAT layer (external address {})", external),
+        ),
+        None => DiagnosticCheck::new(ID, CheckStatus::Warn, "No external address observed yet")
+            .suggest("Connect to more peers so they can report the address they see"),
+    }
+}
+
+/// Are the configured bootstrap nodes connected?
+pub fn check_bootstrap(nodes: &[BootstrapNode]) -> DiagnosticCheck {
+    const ID: &str = "bootstrap";
+    if nodes.is_empty() {
+        return DiagnosticCheck::new(ID, CheckStatus::Warn, "No bootstrap nodes configured")
+            .suggest("Add bootstrap nodes in network settings");
+    }
+    let connected = nodes
+        .iter()
+        .filter(|n| n.status == BootstrapStatus::Connected)
+        .count();
+    let unreachable: Vec<&str> = nodes
+        .iter()
+        .filter(|n| n.status != BootstrapStatus::Connected)
+        .map(|n| n.address.as_str())
+        .collect();
+    let summary = format!("{} of {} bootstrap nodes connected", connected, nodes.len());
+    if unreachable.is_empty() {
+        DiagnosticCheck::new(ID, CheckStatus::Pass, summary)
+    } else if connected > 0 {
+        DiagnosticCheck::new(ID, CheckStatus::Warn, summary)
+            .suggest(format!("Not connected: {}", unreachable.join(", ")))
+    } else {
+        DiagnosticCheck::new(ID, CheckStatus::Fail, summary)
+            .suggest("Check that outbound connections are not blocked by a firewall or proxy")
+    }
+}
+
+/// How long a DHT lookup took, or why it failed
+pub fn check_dht_lookup(peer_count: usize, lookup: Result<Duration, String>) -> DiagnosticCheck {
+    const ID: &str = "dht_lookup";
+    if peer_count == 0 {
+        return DiagnosticCheck::new(ID, CheckStatus::Fail, "No connected peers to query")
+            .suggest("Connect to the network (check the bootstrap nodes) before searching");
+    }
+    match lookup {
+        Ok(elapsed) if elapsed < SLOW_LOOKUP => DiagnosticCheck::new(
+            ID,
+            CheckStatus::Pass,
+            format!("DHT lookup completed in {} ms", elapsed.as_millis()),
+        ),
+        Ok(elapsed) => DiagnosticCheck::new(
+            ID,
+            CheckStatus::Warn,
+            format!("DHT lookup was slow ({} ms)", elapsed.as_millis()),
+        )
+        .suggest("Searches will be slow; more or closer peers usually help"),
+        Err(e) => DiagnosticCheck::new(ID, CheckStatus::Fail, format!("DHT lookup failed: {}", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(
+        remote_address: &str,
+        direction: ConnectionDirection,
+        relayed: bool,
+    ) -> PeerConnectionStats {
+        PeerConnectionStats {
+            peer_id: "peer".into(),
+            remote_address: remote_address.into(),
+            transport: "tcp".into(),
+            direction,
+            connected_since: 0,
+            duration_secs: 0,
+            bytes_in: 0,
+            bytes_out: 0,
+            open_streams: 0,
+            relayed,
+            connection_count: 1,
+        }
+    }
+
+    #[test]
+    fn classifies_reachability_nat_and_bootstrap() {
+        let lan_peer = peer(
+            "/ip4/192.168.1.7/tcp/4001",
+            ConnectionDirection::Inbound,
+            false,
+        );
+        let public_peer = peer("/ip4/8.8.8.8/tcp/4001", ConnectionDirection::Inbound, false);
+        let unknown = NatReachabilityState::Unknown;
+        assert_eq!(
+            check_port_reachability(unknown, true, &[lan_peer.clone()]).status,
+            CheckStatus::Warn
+        );
+        assert_eq!(
+            check_port_reachability(unknown, true, &[lan_peer, public_peer]).status,
+            CheckStatus::Pass
+        );
+        assert_eq!(
+            check_port_reachability(NatReachabilityState::Private, true, &[]).status,
+            CheckStatus::Fail
+        );
+
+        let listen = vec!["/ip4/192.168.1.5/tcp/4001".to_string()];
+        let nat = |observed: &[&str]| {
+            let observed: Vec<String> = observed.iter().map(|s| s.to_string()).collect();
+            check_nat(&listen, &observed).status
+        };
+        assert_eq!(nat(&["/ip4/203.0.113.9/tcp/4001"]), CheckStatus::Pass);
+        assert_eq!(nat(&["/ip4/100.70.1.2/tcp/4001"]), CheckStatus::Fail);
+        assert_eq!(nat(&["/ip4/10.0.0.2/tcp/4001"]), CheckStatus::Warn);
+        // Our own LAN address reported by a LAN peer is not a second NAT
+        assert_eq!(
+            nat(&["/ip4/192.168.1.5/tcp/4001", "/ip4/203.0.113.9/tcp/1"]),
+            CheckStatus::Pass
+        );
+        assert_eq!(
+            nat(&["/ip4/203.0.113.9/tcp/1", "/ip4/198.51.100.4/tcp/1"]),
+            CheckStatus::Warn
+        );
+        assert_eq!(nat(&[]), CheckStatus::Warn);
+
+        let node = |status| BootstrapNode {
+            address: "/ip4/1.2.3.4/tcp/4001".into(),
+            peer_id: None,
+            status,
+        };
+        let report = DiagnosticsReport::new(
+            vec![
+                check_bootstrap(&[
+                    node(BootstrapStatus::Connected),
+                    node(BootstrapStatus::Disconnected),
+                ]),
+                check_dht_lookup(3, Ok(Duration::from_millis(300))),
+            ],
+            0,
+        );
+        assert_eq!(report.checks[0].status, CheckStatus::Warn);
+        assert_eq!(report.checks[1].status, CheckStatus::Pass);
+        assert_eq!(report.status, CheckStatus::Warn);
+        assert_eq!(
+            check_dht_lookup(0, Ok(Duration::ZERO)).status,
+            CheckStatus::Fail
+        );
+    }
+}
@@ -0,0 +1,314 @@
+// Failed dial diagnostics
+//
+// Every `OutgoingConnectionError` is broken down per attempted address and
+// sorted into a reason category (timeout, refused, unreachable, wrong peer ID,
+// unsupported transport, ...) so the UI can show why a peer cannot be reached
+// instead of one opaque error string. The most recent failures are kept per
+// peer; dials to bare addresses are filed under "unknown".
+
+use super::connection_stats::circuit_relay;
+use crate::runtime_env::{Clock, SystemClock};
+use libp2p::core::transport::TransportError;
+use libp2p::swarm::DialError;
+use libp2p::{Multiaddr, PeerId};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/// Peers whose failures are retained
+const MAX_PEERS: usize = 500;
+const MAX_FAILURES_PER_PEER: usize = 20;
+const UNKNOWN_PEER: &str = "unknown";
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum DialFailureReason {
+    Timeout,
+    Refused,
+    AddressUnreachable,
+    WrongPeerId,
+    TransportUnsupported,
+    /// Rejected locally (connection limits, dial conditions, own peer ID)
+    Denied,
+    NoAddresses,
+    Aborted,
+    Other,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DialFailure {
+    pub address: Option<String>,
+    pub reason: DialFailureReason,
+    pub detail: String,
+    /// Unix timestamp (seconds)
+    pub failed_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PeerDialFailures {
+    pub peer_id: String,
+    pub total_failures: u64,
+    pub last_failure_at: u64,
+    pub by_reason: HashMap<DialFailureReason, u64>,
+    /// Most recent failures, newest first
+    pub recent: Vec<DialFailure>,
+}
+
+#[derive(Debug, Default)]
+struct PeerEntry {
+    total_failures: u64,
+    last_failure_at: u64,
+    by_reason: HashMap<DialFailureReason, u64>,
+    recent: VecDeque<DialFailure>,
+}
+
+/// Shared between the swarm loop (writer) and `DhtService` (reader)
+#[derive(Clone)]
+pub struct DialFailureLog {
+    peers: Arc<Mutex<HashMap<String, PeerEntry>>>,
+    clock: Arc<dyn Clock>,
+}
+
+/// Error message including every source in the chain
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        message.push_str(": ");
+        message.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    message
+}
+
+fn classify_io_error(error: &io::Error) -> DialFailureReason {
+    match error.kind() {
+        io::ErrorKind::TimedOut => return DialFailureReason::Timeout,
+        io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset => {
+            return DialFailureReason::Refused
+        }
+        io::ErrorKind::AddrNotAvailable => return DialFailureReason::AddressUnreachable,
+        _ => {}
+    }
+    // Transports wrap the underlying error, so fall back to the message
+    let message = error_chain(error).to_lowercase();
+    if message.contains("timed out") || message.contains("timeout") {
+        DialFailureReason::Timeout
+    } else if message.contains("refused") || message.contains("reset by peer") {
+        DialFailureReason::Refused
+    } else if message.contains("unreachable") || message.contains("no route") {
+        DialFailureReason::AddressUnreachable
+    } else if message.contains("peer id mismatch") || message.contains("wrong peer") {
+        DialFailureReason::WrongPeerId
+    } else {
+        DialFailureReason::Other
+    }
+}
+
+/// Split a dial error into one `(address, reason, detail)` per attempted
+/// address, or a single entry without an address when no address was tried
+pub fn classify_dial_error(
+    error: &DialError,
+) -> Vec<(Option<Multiaddr>, DialFailureReason, String)> {
+    let reason = match error {
+        DialError::Transport(attempts) => {
+            return attempts
+                .iter()
+                .map(|(address, error)| {
+                    let reason = match error {
+                        TransportError::MultiaddrNotSupported(_) => {
+                            DialFailureReason::TransportUnsupported
+                        }
+                        TransportError::Other(io_error) => classify_io_error(io_error),
+                    };
+                    (Some(address.clone()), reason, error_chain(error))
+                })
+                .collect();
+        }
+        DialError::WrongPeerId { .. } => DialFailureReason::WrongPeerId,
+        DialError::LocalPeerId { .. }
+        | DialError::DialPeerConditionFalse(_)
+        | DialError::Denied { .. } => DialFailureReason::Denied,
+        DialError::NoAddresses => DialFailureReason::NoAddresses,
+        DialError::Aborted => DialFailureReason::Aborted,
+    };
+    vec![(None, reason, error_chain(error))]
+}
+
+/// Relays of the circuit addresses a failed dial tried, once each
+pub fn failed_relays(error: &DialError) -> Vec<PeerId> {
+    let DialError::Transport(attempts) = error else {
+        return Vec::new();
+    };
+    let mut relays: Vec<PeerId> = Vec::new();
+    for relay in attempts
+        .iter()
+        .filter_map(|(address, _)| circuit_relay(address))
+    {
+        if !relays.contains(&relay) {
+            relays.push(relay);
+        }
+    }
+    relays
+}
+
+impl Default for DialFailureLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DialFailureLog {
+    pub fn new() -> Self {
+        Self {
+            peers: Arc::new(Mutex::new(HashMap::new())),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Read the time from `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Record a failed dial to `peer_id` (None for dials to a bare address)
+    pub fn record_dial_error(&self, peer_id: Option<PeerId>, error: &DialError) {
+        let peer = peer_id.map_or_else(|| UNKNOWN_PEER.to_string(), |p| p.to_string());
+        self.record(&peer, classify_dial_error(error), self.clock.now_secs());
+    }
+
+    fn record(
+        &self,
+        peer: &str,
+        failures: Vec<(Option<Multiaddr>, DialFailureReason, String)>,
+        now: u64,
+    ) {
+        let Ok(mut peers) = self.peers.lock() else {
+            return;
+        };
+        if !peers.contains_key(peer) && peers.len() >= MAX_PEERS {
+            let stalest = peers
+                .iter()
+                .min_by_key(|(_, e)| e.last_failure_at)
+                .map(|(id, _)| id.clone());
+            if let Some(id) = stalest {
+                peers.remove(&id);
+            }
+        }
+
+        let entry = peers.entry(peer.to_string()).or_default();
+        entry.last_failure_at = now;
+        for (address, reason, detail) in failures {
+            entry.total_failures += 1;
+            *entry.by_reason.entry(reason).or_insert(0) += 1;
+            entry.recent.push_front(DialFailure {
+                address: address.map(|a| a.to_string()),
+                reason,
+                detail,
+                failed_at: now,
+            });
+        }
+        entry.recent.truncate(MAX_FAILURES_PER_PEER);
+    }
+
+    /// Failures for one peer ("unknown" for dials without a peer ID)
+    pub fn for_peer(&self, peer_id: &str) -> Option<PeerDialFailures> {
+        let peers = self.peers.lock().ok()?;
+        peers.get(peer_id).map(|e| summarize(peer_id, e))
+    }
+
+    /// Peers with the most recent failures first
+    pub fn recent(&self, limit: usize) -> Vec<PeerDialFailures> {
+        let Ok(peers) = self.peers.lock() else {
+            return Vec::new();
+        };
+        let mut all: Vec<PeerDialFailures> =
+            peers.iter().map(|(id, e)| summarize(id, e)).collect();
+        all.sort_by(|a, b| b.last_failure_at.cmp(&a.last_failure_at));
+        all.truncate(limit);
+        all
+    }
+}
+
+fn summarize(peer_id: &str, entry: &PeerEntry) -> PeerDialFailures {
+    PeerDialFailures {
+        peer_id: peer_id.to_string(),
+        total_failures: entry.total_failures,
+        last_failure_at: entry.last_failure_at,
+        by_reason: entry.by_reason.clone(),
+        recent: entry.recent.iter().cloned().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classifies_each_attempted_address() {
+        let tcp: Multiaddr = "/ip4/10.0.0.1/tcp/4001".parse().unwrap();
+        let quic: Multiaddr = "/ip4/10.0.0.1/udp/4001/quic-v1".parse().unwrap();
+        let lan: Multiaddr = "/ip4/192.168.1.5/tcp/4001".parse().unwrap();
+        let error = DialError::Transport(vec![
+            (
+                tcp.clone(),
+                TransportError::Other(io::Error::new(io::ErrorKind::TimedOut, "dial timed out")),
+            ),
+            (quic.clone(), TransportError::MultiaddrNotSupported(quic.clone())),
+            (
+                lan,
+                TransportError::Other(io::Error::other("Connection refused (os error 111)")),
+            ),
+        ]);
+        let reasons: Vec<DialFailureReason> =
+            classify_dial_error(&error).into_iter().map(|(_, r, _)| r).collect();
+        assert!(failed_relays(&error).is_empty());
+        assert_eq!(
+            reasons,
+            vec![
+                DialFailureReason::Timeout,
+                DialFailureReason::TransportUnsupported,
+                DialFailureReason::Refused,
+            ]
+        );
+
+        let log = DialFailureLog::new();
+        let peer = PeerId::random();
+        log.record_dial_error(Some(peer), &error);
+        log.record_dial_error(None, &DialError::NoAddresses);
+
+        let failures = log.for_peer(&peer.to_string()).unwrap();
+        assert_eq!(failures.total_failures, 3);
+        assert_eq!(failures.by_reason[&DialFailureReason::Timeout], 1);
+        assert_eq!(failures.recent.len(), 3);
+        assert!(failures.recent.iter().any(|f| f.address == Some(tcp.to_string())));
+
+        let unknown = log.for_peer(UNKNOWN_PEER).unwrap();
+        assert_eq!(unknown.recent[0].reason, DialFailureReason::NoAddresses);
+        assert_eq!(log.recent(10).len(), 2);
+    }
+
+    #[test]
+    fn circuit_dials_name_their_relay() {
+        let relay = PeerId::random();
+        let circuit: Multiaddr = format!(
+            "/ip4/203.0.113.7/tcp/4001/p2p/{}/p2p-circuit/p2p/{}",
+            relay,
+            PeerId::random()
+        )
+        .parse()
+        .unwrap();
+        let refused = || TransportError::Other(io::Error::other("Connection refused"));
+        let error = DialError::Transport(vec![
+            (circuit.clone(), refused()),
+            (circuit, refused()),
+            ("/ip4/10.0.0.1/tcp/4001".parse().unwrap(), refused()),
+        ]);
+        assert_eq!(failed_relays(&error), vec![relay]);
+        assert!(failed_relays(&DialError::NoAddresses).is_empty());
+    }
+}
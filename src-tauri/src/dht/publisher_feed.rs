@@ -0,0 +1,148 @@
+// Signed publisher feeds
+//
+// Every node keeps one DHT record at `publisher_feed:<peer_id>` listing the
+// files it published most recently. The record is signed with the node's
+// libp2p identity key and carries the public key, so a subscriber can check
+// that the feed really comes from the peer it subscribed to: anyone can write
+// to any key in the DHT, but only the owner of the peer ID can produce a feed
+// that verifies against it.
+
+use libp2p::identity::{Keypair, PublicKey};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+
+pub const FEED_KEY_PREFIX: &str = "publisher_feed:";
+/// Entries kept in a feed, newest first
+pub const MAX_FEED_ENTRIES: usize = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FeedEntry {
+    pub file_hash: String,
+    pub file_name: String,
+    pub file_size: u64,
+    pub price: f64,
+    /// Unix timestamp (seconds)
+    pub published_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PublisherFeed {
+    pub publisher: String,
+    /// Protobuf-encoded libp2p public key, hex
+    pub public_key: String,
+    /// Incremented on every update
+    pub seq: u64,
+    pub entries: Vec<FeedEntry>,
+    /// Signature over publisher, seq and entries, hex
+    pub signature: String,
+}
+
+pub fn feed_key(peer_id: &str) -> String {
+    format!("{}{}", FEED_KEY_PREFIX, peer_id)
+}
+
+fn signing_payload(publisher: &str, seq: u64, entries: &[FeedEntry]) -> Result<Vec<u8>, String> {
+    serde_json::to_vec(&(FEED_KEY_PREFIX, publisher, seq, entries))
+        .map_err(|e| format!("Failed to serialize publisher feed: {}", e))
+}
+
+/// `entry` followed by the entries of `previous` it does not replace, capped
+/// at `MAX_FEED_ENTRIES`
+pub fn merge_entries(previous: &[FeedEntry], entry: FeedEntry) -> Vec<FeedEntry> {
+    let mut entries = vec![entry];
+    entries.extend(
+        previous
+            .iter()
+            .filter(|e| e.file_hash != entries[0].file_hash)
+            .cloned(),
+    );
+    entries.truncate(MAX_FEED_ENTRIES);
+    entries
+}
+
+impl PublisherFeed {
+    pub fn signed(keypair: &Keypair, seq: u64, entries: Vec<FeedEntry>) -> Result<Self, String> {
+        let public_key = keypair.public();
+        let publisher = public_key.to_peer_id().to_string();
+        let signature = keypair
+            .sign(&signing_payload(&publisher, seq, &entries)?)
+            .map_err(|e| format!("Failed to sign publisher feed: {}", e))?;
+        Ok(Self {
+            publisher,
+            public_key: hex::encode(public_key.encode_protobuf()),
+            seq,
+            entries,
+            signature: hex::encode(signature),
+        })
+    }
+
+    /// Check that the feed is signed by `expected_publisher`
+    pub fn verify(&self, expected_publisher: &str) -> Result<(), String> {
+        if self.publisher != expected_publisher {
+            return Err(format!(
+                "Feed is for {}, expected {}",
+                self.publisher, expected_publisher
+            ));
+        }
+        let key_bytes =
+            hex::decode(&self.public_key).map_err(|e| format!("Invalid feed public key: {}", e))?;
+        let public_key = PublicKey::try_decode_protobuf(&key_bytes)
+            .map_err(|e| format!("Invalid feed public key: {}", e))?;
+        let expected: PeerId = expected_publisher
+            .parse()
+            .map_err(|e| format!("Invalid publisher peer ID: {}", e))?;
+        if public_key.to_peer_id() != expected {
+            return Err("Feed public key does not belong to the publisher".to_string());
+        }
+        let signature =
+            hex::decode(&self.signature).map_err(|e| format!("Invalid feed signature: {}", e))?;
+        let payload = signing_payload(&self.publisher, self.seq, &self.entries)?;
+        if !public_key.verify(&payload, &signature) {
+            return Err("Feed signature does not verify".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(hash: &str, published_at: u64) -> FeedEntry {
+        FeedEntry {
+            file_hash: hash.to_string(),
+            file_name: format!("{}.bin", hash),
+            file_size: 10,
+            price: 0.0,
+            published_at,
+        }
+    }
+
+    #[test]
+    fn signs_verifies_and_merges() {
+        let keypair = Keypair::generate_ed25519();
+        let publisher = keypair.public().to_peer_id().to_string();
+
+        let entries = merge_entries(&[entry("a", 1), entry("b", 2)], entry("a", 3));
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].published_at, 3);
+
+        let feed = PublisherFeed::signed(&keypair, 7, entries).unwrap();
+        feed.verify(&publisher).unwrap();
+
+        let mut tampered = feed.clone();
+        tampered.entries[0].file_hash = "evil".to_string();
+        assert!(tampered.verify(&publisher).is_err());
+
+        // A feed signed by someone else claiming the publisher's ID
+        let impostor = Keypair::generate_ed25519();
+        let mut forged = PublisherFeed::signed(&impostor, 8, vec![entry("x", 4)]).unwrap();
+        forged.publisher = publisher.clone();
+        assert!(forged.verify(&publisher).is_err());
+
+        let other = impostor.public().to_peer_id().to_string();
+        assert!(feed.verify(&other).is_err());
+    }
+}
@@ -0,0 +1,452 @@
+// DHT query tracing
+//
+// When enabled, the swarm loop hands every Kademlia `OutboundQueryProgressed`
+// event to a `QueryTracer`, which records one hop per progress step: time
+// since the query started and since the previous step, the requests issued in
+// between and how many succeeded or failed, the peers the step reported, and
+// how close (log2 XOR distance) the closest reported peer is to the target.
+// Traces of the most recent queries are kept per query ID so slow provider
+// lookups can be inspected after the fact.
+//
+// libp2p-kad does not say which peer each request went to, so request counts
+// come from the query's `QueryStats` and peers from the results of each step.
+// Tracing is off by default.
+
+use libp2p::kad::{self, QueryResult};
+use libp2p::PeerId;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Queries whose traces are retained
+const MAX_TRACES: usize = 200;
+const MAX_HOPS_PER_TRACE: usize = 256;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryHop {
+    /// 1-based progress step
+    pub step: usize,
+    pub elapsed_ms: u64,
+    pub since_previous_ms: u64,
+    /// Requests issued since the previous step
+    pub requests: u32,
+    pub successes: u32,
+    pub failures: u32,
+    /// Providers, records' origins or closest peers reported by this step
+    pub peers: Vec<String>,
+    /// log2 XOR distance of the closest reported peer to the target
+    pub closest_distance: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QueryTrace {
+    pub query_id: String,
+    /// "get_providers", "get_closest_peers", "get_record", ...
+    pub kind: String,
+    /// File hash, peer ID or hex key being looked up
+    pub key: Option<String>,
+    /// Unix timestamp (milliseconds)
+    pub started_at: u64,
+    pub finished: bool,
+    pub duration_ms: Option<u64>,
+    pub total_requests: u32,
+    pub total_successes: u32,
+    pub total_failures: u32,
+    pub providers_found: usize,
+    /// Closest distance reached over the whole query
+    pub closest_distance: Option<u32>,
+    pub error: Option<String>,
+    pub hops: Vec<QueryHop>,
+}
+
+/// One progress event, reduced to what the tracer records
+#[derive(Debug, Clone, Default)]
+pub struct QueryProgress {
+    pub kind: &'static str,
+    pub key: Option<Vec<u8>>,
+    pub peers: Vec<PeerId>,
+    pub providers: usize,
+    pub error: Option<String>,
+    /// Cumulative counts from the query's `QueryStats`
+    pub requests: u32,
+    pub successes: u32,
+    pub failures: u32,
+    pub elapsed: Option<Duration>,
+    pub last: bool,
+}
+
+struct ActiveTrace {
+    trace: QueryTrace,
+    target: Option<kad::KBucketKey<Vec<u8>>>,
+    started: Instant,
+    last_step_at: Instant,
+    last_counts: (u32, u32, u32),
+}
+
+#[derive(Default)]
+struct TracerState {
+    traces: HashMap<String, ActiveTrace>,
+    /// Query IDs oldest first, for eviction
+    order: VecDeque<String>,
+}
+
+/// Shared between the swarm loop (writer) and `DhtService` (reader)
+#[derive(Clone, Default)]
+pub struct QueryTracer {
+    enabled: Arc<AtomicBool>,
+    state: Arc<Mutex<TracerState>>,
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Human-readable form of a Kademlia key: peer ID, UTF-8 hash or hex
+fn key_label(key: &[u8]) -> String {
+    if let Ok(peer_id) = PeerId::from_bytes(key) {
+        return peer_id.to_string();
+    }
+    match std::str::from_utf8(key) {
+        Ok(text) => text.to_string(),
+        Err(_) => hex::encode(key),
+    }
+}
+
+fn query_label(id: &kad::QueryId) -> String {
+    format!("{:?}", id)
+}
+
+/// Counts since the previous step; stats that went backwards are taken as-is
+fn since(current: u32, previous: u32) -> u32 {
+    current.checked_sub(previous).unwrap_or(current)
+}
+
+impl QueryProgress {
+    /// Extract the traced fields from a Kademlia progress event
+    pub fn from_event(
+        result: &QueryResult,
+        stats: &kad::QueryStats,
+        step: &kad::ProgressStep,
+    ) -> Self {
+        let mut progress = QueryProgress {
+            requests: stats.num_requests(),
+            successes: stats.num_successes(),
+            failures: stats.num_failures(),
+            elapsed: stats.duration(),
+            last: step.last(),
+            ..QueryProgress::default()
+        };
+        match result {
+            QueryResult::GetProviders(Ok(kad::GetProvidersOk::FoundProviders {
+                key,
+                providers,
+            })) => {
+                progress.kind = "get_providers";
+                progress.key = Some(key.to_vec());
+                progress.providers = providers.len();
+                progress.peers = providers.iter().copied().collect();
+            }
+            QueryResult::GetProviders(Ok(
+                kad::GetProvidersOk::FinishedWithNoAdditionalRecord { closest_peers },
+            )) => {
+                progress.kind = "get_providers";
+                progress.peers = closest_peers.clone();
+            }
+            QueryResult::GetProviders(Err(e)) => {
+                progress.kind = "get_providers";
+                progress.key = Some(e.key().to_vec());
+                progress.error = Some(format!("{:?}", e));
+            }
+            QueryResult::GetClosestPeers(Ok(ok)) => {
+                progress.kind = "get_closest_peers";
+                progress.key = Some(ok.key.clone());
+                progress.peers = ok.peers.iter().map(|p| p.peer_id).collect();
+            }
+            QueryResult::GetClosestPeers(Err(e)) => {
+                progress.kind = "get_closest_peers";
+                progress.key = Some(e.key().clone());
+                progress.error = Some(format!("{:?}", e));
+            }
+            QueryResult::GetRecord(Ok(kad::GetRecordOk::FoundRecord(record))) => {
+                progress.kind = "get_record";
+                progress.key = Some(record.record.key.to_vec());
+                progress.peers = record.peer.into_iter().collect();
+            }
+            QueryResult::GetRecord(Ok(_)) => progress.kind = "get_record",
+            QueryResult::GetRecord(Err(e)) => {
+                progress.kind = "get_record";
+                progress.key = Some(e.key().to_vec());
+                progress.error = Some(format!("{:?}", e));
+            }
+            QueryResult::PutRecord(Ok(ok)) => {
+                progress.kind = "put_record";
+                progress.key = Some(ok.key.to_vec());
+            }
+            QueryResult::PutRecord(Err(e)) => {
+                progress.kind = "put_record";
+                progress.key = Some(e.key().to_vec());
+                progress.error = Some(format!("{:?}", e));
+            }
+            QueryResult::StartProviding(Ok(ok)) => {
+                progress.kind = "start_providing";
+                progress.key = Some(ok.key.to_vec());
+            }
+            QueryResult::StartProviding(Err(e)) => {
+                progress.kind = "start_providing";
+                progress.key = Some(e.key().to_vec());
+                progress.error = Some(format!("{:?}", e));
+            }
+            QueryResult::Bootstrap(result) => {
+                progress.kind = "bootstrap";
+                if let Err(e) = result {
+                    progress.error = Some(format!("{:?}", e));
+                }
+            }
+            _ => progress.kind = "other",
+        }
+        progress
+    }
+}
+
+impl QueryTracer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Register a query as it is issued so its trace carries a readable key
+    /// even if no progress step reports one
+    pub fn query_started(&self, id: &kad::QueryId, kind: &'static str, key: &[u8]) {
+        if !self.is_enabled() {
+            return;
+        }
+        self.start_trace(query_label(id), kind, Some(key.to_vec()), Instant::now());
+    }
+
+    /// Record a Kademlia event; only `OutboundQueryProgressed` is traced
+    pub fn observe(&self, event: &kad::Event) {
+        if !self.is_enabled() {
+            return;
+        }
+        if let kad::Event::OutboundQueryProgressed {
+            id,
+            result,
+            stats,
+            step,
+        } = event
+        {
+            let progress = QueryProgress::from_event(result, stats, step);
+            self.record(query_label(id), progress, Instant::now());
+        }
+    }
+
+    /// Trace of one query, by its ID as formatted in logs ("QueryId(12)")
+    pub fn trace(&self, query_id: &str) -> Option<QueryTrace> {
+        let state = self.state.lock().ok()?;
+        state.traces.get(query_id).map(|t| t.trace.clone())
+    }
+
+    /// Most recent traces first, without their hops
+    pub fn recent(&self, limit: usize) -> Vec<QueryTrace> {
+        let Ok(state) = self.state.lock() else {
+            return Vec::new();
+        };
+        state
+            .order
+            .iter()
+            .rev()
+            .filter_map(|id| state.traces.get(id))
+            .take(limit)
+            .map(|t| QueryTrace {
+                hops: Vec::new(),
+                ..t.trace.clone()
+            })
+            .collect()
+    }
+
+    fn start_trace(
+        &self,
+        query_id: String,
+        kind: &'static str,
+        key: Option<Vec<u8>>,
+        now: Instant,
+    ) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        if state.traces.contains_key(&query_id) {
+            return;
+        }
+        while state.order.len() >= MAX_TRACES {
+            if let Some(oldest) = state.order.pop_front() {
+                state.traces.remove(&oldest);
+            }
+        }
+        let trace = QueryTrace {
+            query_id: query_id.clone(),
+            kind: kind.to_string(),
+            key: key.as_deref().map(key_label),
+            started_at: unix_millis(),
+            finished: false,
+            duration_ms: None,
+            total_requests: 0,
+            total_successes: 0,
+            total_failures: 0,
+            providers_found: 0,
+            closest_distance: None,
+            error: None,
+            hops: Vec::new(),
+        };
+        state.order.push_back(query_id.clone());
+        state.traces.insert(
+            query_id,
+            ActiveTrace {
+                trace,
+                target: key.map(kad::KBucketKey::new),
+                started: now,
+                last_step_at: now,
+                last_counts: (0, 0, 0),
+            },
+        );
+    }
+
+    fn record(&self, query_id: String, progress: QueryProgress, now: Instant) {
+        // Queries issued without `query_started` are traced from their first
+        // step, backdated by the elapsed time the stats report
+        let started = progress
+            .elapsed
+            .and_then(|elapsed| now.checked_sub(elapsed))
+            .unwrap_or(now);
+        self.start_trace(query_id.clone(), progress.kind, progress.key.clone(), started);
+
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let Some(active) = state.traces.get_mut(&query_id) else {
+            return;
+        };
+        if active.target.is_none() {
+            if let Some(key) = &progress.key {
+                active.trace.key = Some(key_label(key));
+                active.target = Some(kad::KBucketKey::new(key.clone()));
+            }
+        }
+
+        let closest_distance = active.target.as_ref().and_then(|target| {
+            progress
+                .peers
+                .iter()
+                .filter_map(|peer| target.distance(&kad::KBucketKey::from(*peer)).ilog2())
+                .min()
+        });
+        let (requests, successes, failures) = active.last_counts;
+        let elapsed = progress
+            .elapsed
+            .unwrap_or_else(|| now.saturating_duration_since(active.started));
+        let since_previous = now.saturating_duration_since(active.last_step_at);
+        let hop = QueryHop {
+            step: active.trace.hops.len() + 1,
+            elapsed_ms: elapsed.as_millis() as u64,
+            since_previous_ms: since_previous.as_millis() as u64,
+            requests: since(progress.requests, requests),
+            successes: since(progress.successes, successes),
+            failures: since(progress.failures, failures),
+            peers: progress.peers.iter().map(|p| p.to_string()).collect(),
+            closest_distance,
+        };
+
+        let trace = &mut active.trace;
+        trace.total_requests += hop.requests;
+        trace.total_successes += hop.successes;
+        trace.total_failures += hop.failures;
+        trace.providers_found += progress.providers;
+        if let Some(distance) = closest_distance {
+            let closest = trace.closest_distance.map_or(distance, |d| d.min(distance));
+            trace.closest_distance = Some(closest);
+        }
+        if progress.error.is_some() {
+            trace.error = progress.error;
+        }
+        if progress.last {
+            trace.finished = true;
+            trace.duration_ms = Some(hop.elapsed_ms);
+        }
+        if trace.hops.len() < MAX_HOPS_PER_TRACE {
+            trace.hops.push(hop);
+        }
+        active.last_step_at = now;
+        active.last_counts = (progress.requests, progress.successes, progress.failures);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_hops_with_timing_and_convergence() {
+        let tracer = QueryTracer::new();
+        let start = Instant::now();
+        let id = "QueryId(7)".to_string();
+        assert!(!tracer.is_enabled(), "tracing is off by default");
+        tracer.start_trace(id.clone(), "get_providers", Some(b"file-hash".to_vec()), start);
+        let provider = PeerId::random();
+        tracer.record(
+            id.clone(),
+            QueryProgress {
+                kind: "get_providers",
+                key: Some(b"file-hash".to_vec()),
+                peers: vec![provider],
+                providers: 1,
+                requests: 6,
+                successes: 4,
+                failures: 1,
+                elapsed: Some(Duration::from_millis(1_500)),
+                ..QueryProgress::default()
+            },
+            start + Duration::from_millis(1_500),
+        );
+        tracer.record(
+            id.clone(),
+            QueryProgress {
+                kind: "get_providers",
+                peers: vec![PeerId::random(), PeerId::random()],
+                requests: 10,
+                successes: 7,
+                failures: 3,
+                elapsed: Some(Duration::from_millis(31_500)),
+                last: true,
+                ..QueryProgress::default()
+            },
+            start + Duration::from_millis(31_500),
+        );
+
+        let trace = tracer.trace(&id).unwrap();
+        assert_eq!(trace.key.as_deref(), Some("file-hash"));
+        assert!(trace.finished);
+        assert_eq!(trace.duration_ms, Some(31_500));
+        assert_eq!(trace.providers_found, 1);
+        assert_eq!((trace.total_requests, trace.total_failures), (10, 3));
+        assert_eq!(trace.hops.len(), 2);
+        assert_eq!(trace.hops[0].peers, vec![provider.to_string()]);
+        assert_eq!(trace.hops[1].since_previous_ms, 30_000);
+        assert_eq!(trace.hops[1].requests, 4);
+        assert!(trace.hops.iter().all(|h| h.closest_distance.is_some()));
+        assert_eq!(tracer.recent(5)[0].query_id, id);
+        assert!(tracer.recent(5)[0].hops.is_empty());
+    }
+}
@@ -0,0 +1,221 @@
+// Relay announcements over gossipsub
+//
+// Nodes that run a relay server and are publicly reachable publish a
+// `RelayAnnouncement` on the `chiral/relays/1` topic every few minutes, so
+// other nodes learn about relays as they come up instead of only through
+// provider queries and their own reservations. Each announcement also carries
+// the relay's limits and how many circuits it is carrying, so nodes can steer
+// away from relays that are close to full. Gossipsub signs every message
+// with the publisher's key and drops the ones whose signature does not
+// verify. On top of that an announcement is only accepted if it names the
+// peer that signed it, is recent, and lists a few parseable addresses.
+
+use libp2p::{gossipsub, identity, Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+pub const TOPIC: &str = "chiral/relays/1";
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Announcements further than this from the local clock are ignored
+pub const MAX_ANNOUNCEMENT_AGE_SECS: u64 = 15 * 60;
+pub const MAX_ANNOUNCED_ADDRESSES: usize = 8;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayAnnouncement {
+    pub peer_id: String,
+    /// Addresses the relay can be dialled at
+    pub addresses: Vec<String>,
+    /// Unix seconds
+    pub announced_at: u64,
+    /// Missing from announcements of older nodes
+    #[serde(default)]
+    pub capacity: Option<RelayCapacity>,
+    /// Country code of the relay, see `relay_selector::local_region`
+    #[serde(default)]
+    pub region: Option<String>,
+}
+
+/// Limits a relay enforces and the load it carries
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RelayCapacity {
+    /// Circuits the relay carries at once
+    pub max_circuits: u32,
+    /// Bytes relayed per circuit before it is closed
+    pub max_circuit_bytes: u64,
+    /// Circuits open when the announcement was made
+    pub active_circuits: u32,
+}
+
+impl RelayCapacity {
+    /// Limits of a relay server running with `config`, carrying nothing yet
+    pub fn of(config: &libp2p::relay::Config) -> Self {
+        Self {
+            max_circuits: config.max_circuits.try_into().unwrap_or(u32::MAX),
+            max_circuit_bytes: config.max_circuit_bytes,
+            active_circuits: 0,
+        }
+    }
+
+    /// Share of the circuits still free, from 0.0 (full) to 1.0 (idle).
+    /// `None` if the relay did not say how many it carries.
+    pub fn spare(&self) -> Option<f64> {
+        if self.max_circuits == 0 {
+            return None;
+        }
+        let free = self.max_circuits.saturating_sub(self.active_circuits);
+        Some(free as f64 / self.max_circuits as f64)
+    }
+}
+
+pub fn topic() -> gossipsub::IdentTopic {
+    gossipsub::IdentTopic::new(TOPIC)
+}
+
+/// Gossipsub behaviour that signs what this node publishes and only passes
+/// on messages with a valid signature
+pub fn behaviour(key: &identity::Keypair) -> Result<gossipsub::Behaviour, String> {
+    let config = gossipsub::ConfigBuilder::default()
+        .validation_mode(gossipsub::ValidationMode::Strict)
+        .build()
+        .map_err(|e| format!("Invalid gossipsub config: {}", e))?;
+    gossipsub::Behaviour::new(gossipsub::MessageAuthenticity::Signed(key.clone()), config)
+        .map_err(|e| format!("Failed to create gossipsub behaviour: {}", e))
+}
+
+/// What `peer_id` publishes about itself at `now`. `None` without
+/// addresses worth announcing.
+pub fn announcement<'a>(
+    peer_id: &PeerId,
+    addresses: impl IntoIterator<Item = &'a Multiaddr>,
+    capacity: RelayCapacity,
+    now: u64,
+) -> Option<RelayAnnouncement> {
+    let mut announced: Vec<String> = Vec::new();
+    for address in addresses {
+        let address = address.to_string();
+        if !announced.contains(&address) {
+            announced.push(address);
+        }
+    }
+    announced.truncate(MAX_ANNOUNCED_ADDRESSES);
+    if announced.is_empty() {
+        return None;
+    }
+    Some(RelayAnnouncement {
+        peer_id: peer_id.to_string(),
+        addresses: announced,
+        announced_at: now,
+        capacity: Some(capacity),
+        region: None,
+    })
+}
+
+pub fn encode(announcement: &RelayAnnouncement) -> Vec<u8> {
+    serde_json::to_vec(announcement).unwrap_or_default()
+}
+
+/// Check an announcement signed by `source` and received at `now`
+pub fn decode(data: &[u8], source: Option<&PeerId>, now: u64) -> Result<RelayAnnouncement, String> {
+    let mut announcement: RelayAnnouncement =
+        serde_json::from_slice(data).map_err(|e| format!("Malformed relay announcement: {}", e))?;
+    let source = source.ok_or("Relay announcement is not signed")?;
+    if announcement.peer_id != source.to_string() {
+        return Err(format!(
+            "Relay announcement for {} was signed by {}",
+            announcement.peer_id, source
+        ));
+    }
+    if now.abs_diff(announcement.announced_at) > MAX_ANNOUNCEMENT_AGE_SECS {
+        return Err(format!(
+            "Relay announcement from {} is out of date",
+            announcement.peer_id
+        ));
+    }
+    if announcement.addresses.is_empty() || announcement.addresses.len() > MAX_ANNOUNCED_ADDRESSES {
+        return Err(format!(
+            "Relay announcement from {} lists {} addresses",
+            announcement.peer_id,
+            announcement.addresses.len()
+        ));
+    }
+    if let Some(bad) = announcement
+        .addresses
+        .iter()
+        .find(|address| address.parse::<Multiaddr>().is_err())
+    {
+        return Err(format!("Relay announcement has a bad address: {}", bad));
+    }
+    // A region that is not a country code is dropped, not held against it
+    announcement.region = announcement
+        .region
+        .filter(|region| region.len() == 2 && region.chars().all(|c| c.is_ascii_uppercase()));
+    Ok(announcement)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn announcements_round_trip_for_their_signer_only() {
+        let relay = PeerId::random();
+        let addresses: Vec<Multiaddr> = vec![
+            "/ip4/203.0.113.7/tcp/4001".parse().unwrap(),
+            "/ip4/203.0.113.7/tcp/4001".parse().unwrap(),
+        ];
+        let capacity = RelayCapacity {
+            max_circuits: 16,
+            max_circuit_bytes: 1 << 17,
+            active_circuits: 12,
+        };
+        let announced = announcement(&relay, &addresses, capacity, 1_000).unwrap();
+        assert_eq!(announced.addresses.len(), 1);
+        assert_eq!(announced.capacity.and_then(|c| c.spare()), Some(0.25));
+        assert!(announcement(&relay, &[], capacity, 1_000).is_none());
+
+        let data = encode(&announced);
+        assert_eq!(decode(&data, Some(&relay), 1_300), Ok(announced));
+        assert!(decode(&data, Some(&PeerId::random()), 1_000).is_err());
+        assert!(decode(&data, None, 1_000).is_err());
+    }
+
+    #[test]
+    fn stale_or_malformed_announcements_are_refused() {
+        let relay = PeerId::random();
+        let stale = RelayAnnouncement {
+            peer_id: relay.to_string(),
+            addresses: vec!["/ip4/203.0.113.7/tcp/4001".to_string()],
+            announced_at: 0,
+            capacity: None,
+            region: Some("somewhere".to_string()),
+        };
+        let now = MAX_ANNOUNCEMENT_AGE_SECS + 1;
+        assert!(decode(&encode(&stale), Some(&relay), now).is_err());
+
+        let garbled = RelayAnnouncement {
+            addresses: vec!["not an address".to_string()],
+            ..stale.clone()
+        };
+        assert!(decode(&encode(&garbled), Some(&relay), 0).is_err());
+        let empty = RelayAnnouncement {
+            addresses: Vec::new(),
+            ..stale.clone()
+        };
+        assert!(decode(&encode(&empty), Some(&relay), 0).is_err());
+        assert!(decode(b"{", Some(&relay), 0).is_err());
+
+        // Announcements from before capacity was reported still decode
+        let legacy = format!(
+            r#"{{"peerId":"{}","addresses":["/ip4/203.0.113.7/tcp/4001"],"announcedAt":5}}"#,
+            relay
+        );
+        let decoded = decode(legacy.as_bytes(), Some(&relay), 0).unwrap();
+        assert_eq!(decoded.capacity, None);
+        // Only the odd region is dropped
+        let decoded = decode(&encode(&stale), Some(&relay), 0).unwrap();
+        assert_eq!(decoded.region, None);
+        assert_eq!(RelayCapacity::default().spare(), None);
+    }
+}
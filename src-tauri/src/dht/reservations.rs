@@ -0,0 +1,329 @@
+// Relay reservation lifecycle
+//
+// A circuit relay v2 reservation is what lets peers reach this node through a
+// relay. It is requested by listening on `<relay>/p2p-circuit` and lasts for
+// the relay's reservation duration (an hour unless the relay is configured
+// otherwise); libp2p renews it on its own three quarters of the way through.
+// This module keeps the state of every reservation, per relay, so the UI can
+// show it and so the swarm loop can step in when libp2p does not: a
+// reservation that gets close to expiry without a renewal is requested again,
+// and one whose listener closed is retried with exponential backoff until it
+// has failed `MAX_CONSECUTIVE_FAILURES` times in a row.
+//
+// Relays do not tell clients when a reservation expires, so expiry is
+// estimated from `RESERVATION_TTL_SECS`.
+
+use libp2p::core::transport::ListenerId;
+use libp2p::{Multiaddr, PeerId};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Default reservation duration of circuit relay v2 servers
+pub const RESERVATION_TTL_SECS: u64 = 60 * 60;
+/// Renew this long before the estimated expiry if libp2p has not
+pub const RENEW_MARGIN_SECS: u64 = 5 * 60;
+pub const MAX_CONSECUTIVE_FAILURES: u32 = 5;
+const RETRY_BASE_SECS: u64 = 30;
+const RETRY_MAX_SECS: u64 = 30 * 60;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReservationStatus {
+    /// Requested, no answer yet
+    Pending,
+    Active,
+    /// The listener closed; see `retry_at`
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ReservationState {
+    pub relay_peer_id: String,
+    /// The `/p2p-circuit` address listened on
+    pub listen_addr: String,
+    pub status: ReservationStatus,
+    /// Unix seconds, as all times here
+    pub requested_at: u64,
+    pub accepted_at: Option<u64>,
+    /// Estimated, see `RESERVATION_TTL_SECS`
+    pub expires_at: Option<u64>,
+    pub renewals: u64,
+    pub failures: u64,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    /// Next attempt after a failure; `None` once the relay is given up on
+    pub retry_at: Option<u64>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    state: ReservationState,
+    listen_addr: Multiaddr,
+    listener: Option<ListenerId>,
+    /// Listener of the reservation being replaced, closed once the new one
+    /// is accepted
+    superseded: Option<ListenerId>,
+}
+
+impl Entry {
+    fn new(relay: PeerId, listen_addr: Multiaddr, now: u64) -> Self {
+        Self {
+            state: ReservationState {
+                relay_peer_id: relay.to_string(),
+                listen_addr: listen_addr.to_string(),
+                status: ReservationStatus::Pending,
+                requested_at: now,
+                accepted_at: None,
+                expires_at: None,
+                renewals: 0,
+                failures: 0,
+                consecutive_failures: 0,
+                last_error: None,
+                retry_at: None,
+            },
+            listen_addr,
+            listener: None,
+            superseded: None,
+        }
+    }
+}
+
+/// Shared between the swarm loop (writer) and `DhtService` (reader)
+#[derive(Clone, Default)]
+pub struct ReservationManager {
+    relays: Arc<Mutex<HashMap<PeerId, Entry>>>,
+}
+
+fn retry_delay(consecutive_failures: u32) -> u64 {
+    let doublings = consecutive_failures.saturating_sub(1).min(16);
+    (RETRY_BASE_SECS << doublings).min(RETRY_MAX_SECS)
+}
+
+impl ReservationManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A reservation with `relay` was requested by listening on
+    /// `listen_addr`
+    pub fn requested(
+        &self,
+        relay: PeerId,
+        listen_addr: &Multiaddr,
+        listener: ListenerId,
+        now: u64,
+    ) -> ReservationState {
+        let mut relays = self.relays.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = relays
+            .entry(relay)
+            .or_insert_with(|| Entry::new(relay, listen_addr.clone(), now));
+        // An active reservation keeps serving until its replacement is in
+        if entry.state.status == ReservationStatus::Active {
+            entry.superseded = entry.listener.or(entry.superseded);
+        } else {
+            entry.state.status = ReservationStatus::Pending;
+        }
+        entry.listener = Some(listener);
+        entry.listen_addr = listen_addr.clone();
+        entry.state.listen_addr = listen_addr.to_string();
+        entry.state.requested_at = now;
+        entry.state.retry_at = None;
+        entry.state.clone()
+    }
+
+    /// `relay` accepted (or renewed) our reservation. Returns the new state
+    /// and the listener of a replaced reservation, to be closed.
+    pub fn accepted(
+        &self,
+        relay: PeerId,
+        renewal: bool,
+        now: u64,
+    ) -> (ReservationState, Option<ListenerId>) {
+        let mut relays = self.relays.lock().unwrap_or_else(|e| e.into_inner());
+        // Reservations made outside our bookkeeping still get tracked
+        let entry = relays
+            .entry(relay)
+            .or_insert_with(|| Entry::new(relay, Multiaddr::empty(), now));
+        entry.state.status = ReservationStatus::Active;
+        entry.state.accepted_at = Some(now);
+        entry.state.expires_at = Some(now + RESERVATION_TTL_SECS);
+        entry.state.consecutive_failures = 0;
+        entry.state.retry_at = None;
+        if renewal {
+            entry.state.renewals += 1;
+        }
+        (entry.state.clone(), entry.superseded.take())
+    }
+
+    /// A listener closed. If it carried a reservation, that reservation
+    /// failed (`error` is `None` for a clean close) and its new state is
+    /// returned.
+    pub fn listener_closed(
+        &self,
+        listener: ListenerId,
+        error: Option<String>,
+        now: u64,
+    ) -> Option<ReservationState> {
+        let mut relays = self.relays.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = relays
+            .values_mut()
+            .find(|entry| entry.listener == Some(listener))?;
+        entry.listener = None;
+        entry.state.status = ReservationStatus::Failed;
+        entry.state.failures += 1;
+        entry.state.consecutive_failures += 1;
+        entry.state.expires_at = None;
+        entry.state.last_error = Some(error.unwrap_or_else(|| "Relay listener closed".to_string()));
+        entry.state.retry_at = (entry.state.consecutive_failures < MAX_CONSECUTIVE_FAILURES)
+            .then(|| now + retry_delay(entry.state.consecutive_failures));
+        Some(entry.state.clone())
+    }
+
+    /// Relays whose reservation should be requested again now, with the
+    /// address to listen on: failed ones whose retry time has come and
+    /// active ones near expiry that libp2p did not renew
+    pub fn due(&self, now: u64) -> Vec<(PeerId, Multiaddr)> {
+        let relays = self.relays.lock().unwrap_or_else(|e| e.into_inner());
+        relays
+            .iter()
+            .filter(|(_, entry)| !entry.listen_addr.is_empty())
+            .filter(|(_, entry)| match entry.state.status {
+                ReservationStatus::Pending => false,
+                // Not while a replacement is already underway
+                ReservationStatus::Active => {
+                    entry.superseded.is_none()
+                        && entry
+                            .state
+                            .expires_at
+                            .is_some_and(|expires| now + RENEW_MARGIN_SECS >= expires)
+                }
+                ReservationStatus::Failed => entry.state.retry_at.is_some_and(|at| now >= at),
+            })
+            .map(|(relay, entry)| (*relay, entry.listen_addr.clone()))
+            .collect()
+    }
+
+    pub fn is_tracked(&self, relay: &PeerId) -> bool {
+        let relays = self.relays.lock().unwrap_or_else(|e| e.into_inner());
+        relays.contains_key(relay)
+    }
+
+    /// Stop tracking the reservation with `relay`. Returns its listeners,
+    /// to be closed so the relay drops the reservation.
+    pub fn release(&self, relay: &PeerId) -> Vec<ListenerId> {
+        let mut relays = self.relays.lock().unwrap_or_else(|e| e.into_inner());
+        relays
+            .remove(relay)
+            .map(|entry| entry.listener.into_iter().chain(entry.superseded).collect())
+            .unwrap_or_default()
+    }
+
+    /// Every tracked reservation, active ones first
+    pub fn snapshot(&self) -> Vec<ReservationState> {
+        let relays = self.relays.lock().unwrap_or_else(|e| e.into_inner());
+        let mut states: Vec<ReservationState> =
+            relays.values().map(|entry| entry.state.clone()).collect();
+        states.sort_by(|a, b| {
+            let rank = |s: &ReservationState| match s.status {
+                ReservationStatus::Active => 0,
+                ReservationStatus::Pending => 1,
+                ReservationStatus::Failed => 2,
+            };
+            rank(a)
+                .cmp(&rank(b))
+                .then_with(|| a.relay_peer_id.cmp(&b.relay_peer_id))
+        });
+        states
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn circuit(relay: &PeerId) -> Multiaddr {
+        format!("/ip4/203.0.113.7/tcp/4001/p2p/{}/p2p-circuit", relay)
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn reservations_renew_before_expiry_and_replace_their_listener() {
+        let manager = ReservationManager::new();
+        let relay = PeerId::random();
+        let first = ListenerId::next();
+        let state = manager.requested(relay, &circuit(&relay), first, 0);
+        assert_eq!(state.status, ReservationStatus::Pending);
+        assert!(manager.due(0).is_empty());
+
+        let (state, superseded) = manager.accepted(relay, false, 10);
+        assert_eq!(state.expires_at, Some(10 + RESERVATION_TTL_SECS));
+        assert_eq!(superseded, None);
+        // libp2p renewed it in time
+        let (state, _) = manager.accepted(relay, true, 2_000);
+        assert_eq!(state.renewals, 1);
+        let expires = 2_000 + RESERVATION_TTL_SECS;
+        assert!(manager.due(expires - RENEW_MARGIN_SECS - 1).is_empty());
+
+        // It did not renew again, so we do
+        let due = manager.due(expires - RENEW_MARGIN_SECS);
+        assert_eq!(due, vec![(relay, circuit(&relay))]);
+        let second = ListenerId::next();
+        let state = manager.requested(relay, &circuit(&relay), second, expires - 60);
+        assert_eq!(state.status, ReservationStatus::Active);
+        assert!(manager.due(expires - 60).is_empty());
+        let (_, superseded) = manager.accepted(relay, false, expires - 50);
+        assert_eq!(superseded, Some(first));
+        // The replaced listener closing is not a failure
+        assert_eq!(manager.listener_closed(first, None, expires), None);
+        assert_eq!(manager.snapshot()[0].status, ReservationStatus::Active);
+    }
+
+    #[test]
+    fn released_reservations_hand_back_their_listeners() {
+        let manager = ReservationManager::new();
+        let relay = PeerId::random();
+        let (first, second) = (ListenerId::next(), ListenerId::next());
+        manager.requested(relay, &circuit(&relay), first, 0);
+        manager.accepted(relay, false, 10);
+        manager.requested(relay, &circuit(&relay), second, 20);
+        assert!(manager.is_tracked(&relay));
+
+        assert_eq!(manager.release(&relay), vec![second, first]);
+        assert!(!manager.is_tracked(&relay));
+        assert!(manager.release(&relay).is_empty());
+        // Closing the listeners afterwards is no failure
+        assert_eq!(manager.listener_closed(second, None, 30), None);
+        assert!(manager.snapshot().is_empty());
+    }
+
+    #[test]
+    fn failed_reservations_back_off_and_are_given_up() {
+        let manager = ReservationManager::new();
+        let relay = PeerId::random();
+        let mut now = 0;
+        for attempt in 1..=MAX_CONSECUTIVE_FAILURES {
+            let listener = ListenerId::next();
+            manager.requested(relay, &circuit(&relay), listener, now);
+            let state = manager
+                .listener_closed(listener, Some("no reservation".to_string()), now)
+                .unwrap();
+            assert_eq!(state.status, ReservationStatus::Failed);
+            assert_eq!(state.consecutive_failures, attempt);
+            if attempt == MAX_CONSECUTIVE_FAILURES {
+                assert_eq!(state.retry_at, None);
+                break;
+            }
+            let retry_at = state.retry_at.unwrap();
+            assert_eq!(retry_at - now, retry_delay(attempt));
+            assert!(manager.due(retry_at - 1).is_empty());
+            assert_eq!(manager.due(retry_at).len(), 1);
+            now = retry_at;
+        }
+        assert!(manager.due(u64::MAX / 2).is_empty());
+        assert_eq!(retry_delay(1), RETRY_BASE_SECS);
+        assert_eq!(retry_delay(30), RETRY_MAX_SECS);
+    }
+}
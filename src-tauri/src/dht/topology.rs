@@ -0,0 +1,194 @@
+// Network topology snapshot
+//
+// One document describing the local node's view of the network: who we are
+// connected to, which relays we know and how healthy they are, the state of
+// every configured bootstrap node, and how the Kademlia routing table is
+// filled. The frontend renders it as a live graph centred on the local node.
+
+use super::connection_stats::PeerConnectionStats;
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RelayReservation {
+    /// Reservation accepted; we are reachable through this relay
+    Active,
+    /// Reservation requested, not yet accepted
+    Pending,
+    /// Relay-capable peer without a reservation
+    None,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayNode {
+    pub peer_id: String,
+    pub addresses: Vec<String>,
+    pub reservation: RelayReservation,
+    pub connected: bool,
+    /// The relay currently used for our advertised circuit address
+    pub is_active_relay: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BootstrapStatus {
+    Connected,
+    Disconnected,
+    /// Address could not be parsed into a peer ID
+    Invalid,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BootstrapNode {
+    pub address: String,
+    pub peer_id: Option<String>,
+    pub status: BootstrapStatus,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BucketSummary {
+    /// log2 of the lower bound of the bucket's XOR distance range
+    pub index: u32,
+    pub peer_count: usize,
+    pub peers: Vec<String>,
+}
+
+/// Part of the topology only the swarm task can read (routing table, relays)
+#[derive(Debug, Clone, Default)]
+pub struct RoutingTopology {
+    pub relays: Vec<RelayNode>,
+    pub buckets: Vec<BucketSummary>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetworkTopology {
+    pub local_peer_id: String,
+    pub listen_addrs: Vec<String>,
+    pub reachability: super::NatReachabilityState,
+    pub peers: Vec<PeerConnectionStats>,
+    pub relays: Vec<RelayNode>,
+    pub bootstrap_nodes: Vec<BootstrapNode>,
+    pub buckets: Vec<BucketSummary>,
+    /// Total number of peers across all buckets
+    pub routing_table_size: usize,
+    /// Unix timestamp (seconds) when the snapshot was taken
+    pub generated_at: u64,
+}
+
+/// Status of each configured bootstrap address given the connected peer set
+pub fn bootstrap_nodes_status(
+    bootstrap_nodes: &[String],
+    connected: &HashSet<PeerId>,
+) -> Vec<BootstrapNode> {
+    bootstrap_nodes
+        .iter()
+        .map(|address| {
+            let peer_id = address.parse::<Multiaddr>().ok().and_then(|ma| {
+                ma.iter().find_map(|p| match p {
+                    Protocol::P2p(peer_id) => Some(peer_id),
+                    _ => None,
+                })
+            });
+            let status = match &peer_id {
+                Some(id) if connected.contains(id) => BootstrapStatus::Connected,
+                Some(_) => BootstrapStatus::Disconnected,
+                None => BootstrapStatus::Invalid,
+            };
+            BootstrapNode {
+                address: address.clone(),
+                peer_id: peer_id.map(|id| id.to_string()),
+                status,
+            }
+        })
+        .collect()
+}
+
+/// Merge the relay sources known to the swarm task into one list
+pub fn relay_nodes(
+    capable: &BTreeMap<PeerId, Vec<Multiaddr>>,
+    ready: &HashSet<PeerId>,
+    pending: &HashSet<PeerId>,
+    connected: &HashSet<PeerId>,
+    active_relay: Option<&str>,
+) -> Vec<RelayNode> {
+    let mut peers: BTreeMap<PeerId, Vec<String>> = capable
+        .iter()
+        .map(|(id, addrs)| (*id, addrs.iter().map(|a| a.to_string()).collect()))
+        .collect();
+    for id in ready.iter().chain(pending.iter()) {
+        peers.entry(*id).or_default();
+    }
+
+    peers
+        .into_iter()
+        .map(|(id, addresses)| {
+            let reservation = if ready.contains(&id) {
+                RelayReservation::Active
+            } else if pending.contains(&id) {
+                RelayReservation::Pending
+            } else {
+                RelayReservation::None
+            };
+            let peer_id = id.to_string();
+            RelayNode {
+                is_active_relay: active_relay == Some(peer_id.as_str()),
+                peer_id,
+                addresses,
+                reservation,
+                connected: connected.contains(&id),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bootstrap_and_relay_status_reflect_connections() {
+        let up = PeerId::random();
+        let down = PeerId::random();
+        let connected: HashSet<PeerId> = [up].into_iter().collect();
+
+        let nodes = bootstrap_nodes_status(
+            &[
+                format!("/ip4/1.2.3.4/tcp/4001/p2p/{}", up),
+                format!("/ip4/1.2.3.5/tcp/4001/p2p/{}", down),
+                "not-a-multiaddr".to_string(),
+            ],
+            &connected,
+        );
+        let statuses: Vec<_> = nodes.iter().map(|n| n.status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                BootstrapStatus::Connected,
+                BootstrapStatus::Disconnected,
+                BootstrapStatus::Invalid
+            ]
+        );
+
+        let capable: BTreeMap<PeerId, Vec<Multiaddr>> =
+            [(up, vec!["/ip4/1.2.3.4/tcp/4001".parse().unwrap()])].into_iter().collect();
+        let ready: HashSet<PeerId> = [up].into_iter().collect();
+        let pending: HashSet<PeerId> = [down].into_iter().collect();
+        let relays = relay_nodes(&capable, &ready, &pending, &connected, Some(&up.to_string()));
+
+        assert_eq!(relays.len(), 2);
+        let active = relays.iter().find(|r| r.peer_id == up.to_string()).unwrap();
+        assert_eq!(active.reservation, RelayReservation::Active);
+        assert!(active.connected && active.is_active_relay);
+        assert_eq!(active.addresses.len(), 1);
+        let waiting = relays.iter().find(|r| r.peer_id == down.to_string()).unwrap();
+        assert_eq!(waiting.reservation, RelayReservation::Pending);
+        assert!(!waiting.connected);
+    }
+}
@@ -0,0 +1,574 @@
+// Directory manifests and selective downloads
+//
+// A folder shared with a batch upload is published as a manifest listing the
+// relative path, merkle root and size of every file. The merkle root is the
+// id the file's metadata is announced under, so each entry is downloaded like
+// any other published file. The manifest is stored in the DHT under its own
+// SHA-256, so whoever has the ID can check it was not altered. Downloading a
+// manifest takes a selection of paths, directory prefixes (`data/train/`) or
+// glob patterns (`**/*.csv`); only the matching entries are fetched and each
+// reports its own progress.
+
+use crate::event_bus::{self, EventPayload};
+use crate::file_transfer::progress::TransferDirection;
+use crate::file_transfer::FileTransferEvent;
+use glob::{MatchOptions, Pattern};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Component, Path, PathBuf};
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tracing::warn;
+
+/// Event carrying `DirectoryEntryProgress` whenever an entry changes
+pub const PROGRESS_EVENT: &str = "directory_download_progress";
+
+/// Upper bound on entries in one manifest
+pub const MAX_ENTRIES: usize = 100_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectoryEntry {
+    /// Relative to the manifest root, `/`-separated
+    pub path: String,
+    /// Id the file is downloaded by: the merkle root its metadata is
+    /// announced under, or the content hash in a signed folder manifest
+    pub file_hash: String,
+    pub file_size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectoryManifest {
+    pub name: String,
+    pub created_at: u64,
+    /// Sorted by path
+    pub entries: Vec<DirectoryEntry>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum EntryStatus {
+    Pending,
+    Downloading,
+    Done,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectoryEntryProgress {
+    pub manifest_id: String,
+    pub path: String,
+    pub file_hash: String,
+    pub status: EntryStatus,
+    pub downloaded_bytes: u64,
+    pub total_bytes: u64,
+    pub error: Option<String>,
+}
+
+/// What a selective download will fetch
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectoryDownloadPlan {
+    pub manifest_id: String,
+    pub output_dir: String,
+    pub entries: Vec<DirectoryEntryProgress>,
+    pub skipped_entries: usize,
+    pub total_bytes: u64,
+    pub skipped_bytes: u64,
+}
+
+pub fn dht_key(manifest_id: &str) -> String {
+    format!("directory_manifest:{}", manifest_id)
+}
+
+/// `path` as a `/`-separated relative path, refusing anything that could
+/// escape the output directory
+pub fn normalize_relative(path: &str) -> Result<String, String> {
+    let mut parts = Vec::new();
+    for component in Path::new(&path.replace('\\', "/")).components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy().to_string()),
+            Component::CurDir => {}
+            _ => return Err(format!("Invalid manifest path: {}", path)),
+        }
+    }
+    if parts.is_empty() {
+        return Err(format!("Invalid manifest path: {}", path));
+    }
+    Ok(parts.join("/"))
+}
+
+impl DirectoryManifest {
+    /// Manifest of `files` (absolute path, hash, size), all under `root`
+    pub fn new(
+        name: String,
+        root: &Path,
+        files: Vec<(PathBuf, String, u64)>,
+        created_at: u64,
+    ) -> Result<Self, String> {
+        if files.is_empty() {
+            return Err("A directory manifest needs at least one file".to_string());
+        }
+        let mut entries = files
+            .into_iter()
+            .map(|(path, file_hash, file_size)| {
+                let relative = path
+                    .strip_prefix(root)
+                    .map_err(|_| format!("{} is not inside {}", path.display(), root.display()))?;
+                Ok(DirectoryEntry {
+                    path: normalize_relative(&relative.to_string_lossy())?,
+                    file_hash,
+                    file_size,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        let manifest = Self {
+            name,
+            created_at,
+            entries,
+        };
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    /// Check the entry count and that paths are unique and stay inside the
+    /// root
+    pub fn validate(&self) -> Result<(), String> {
+        if self.entries.len() > MAX_ENTRIES {
+            return Err(format!(
+                "Too many entries in manifest ({}, at most {})",
+                self.entries.len(),
+                MAX_ENTRIES
+            ));
+        }
+        let mut seen = std::collections::HashSet::new();
+        for entry in &self.entries {
+            if normalize_relative(&entry.path)? != entry.path {
+                return Err(format!("Invalid manifest path: {}", entry.path));
+            }
+            if !seen.insert(entry.path.as_str()) {
+                return Err(format!("Duplicate manifest path: {}", entry.path));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        serde_json::to_vec(self).map_err(|e| format!("Failed to serialize manifest: {}", e))
+    }
+
+    /// Hex SHA-256 of the serialized manifest
+    pub fn id(&self) -> Result<String, String> {
+        Ok(hex::encode(Sha256::digest(self.to_bytes()?)))
+    }
+
+    /// Parse a manifest fetched from the DHT and check it hashes to `manifest_id`
+    pub fn from_dht(raw: &[u8], manifest_id: &str) -> Result<Self, String> {
+        if !hex::encode(Sha256::digest(raw)).eq_ignore_ascii_case(manifest_id) {
+            return Err("Directory manifest does not match its ID".to_string());
+        }
+        let manifest: Self = serde_json::from_slice(raw)
+            .map_err(|e| format!("Failed to parse directory manifest: {}", e))?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.entries.iter().map(|e| e.file_size).sum()
+    }
+
+    /// Entries matching any of `patterns`: exact paths, directory prefixes
+    /// or globs. No patterns selects everything; a pattern matching nothing
+    /// is an error so typos do not silently download less.
+    pub fn select(&self, patterns: &[String]) -> Result<Vec<&DirectoryEntry>, String> {
+        if patterns.iter().all(|p| p.trim().is_empty()) {
+            return Ok(self.entries.iter().collect());
+        }
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        let mut selected = vec![false; self.entries.len()];
+        for raw in patterns.iter().map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            let prefix = raw.trim_start_matches("./").trim_matches('/');
+            let glob =
+                Pattern::new(prefix).map_err(|e| format!("Invalid pattern {}: {}", raw, e))?;
+            let mut matched = false;
+            for (entry, selected) in self.entries.iter().zip(selected.iter_mut()) {
+                let hit = prefix.is_empty()
+                    || entry.path == prefix
+                    || entry
+                        .path
+                        .strip_prefix(prefix)
+                        .is_some_and(|rest| rest.starts_with('/'))
+                    || glob.matches_with(&entry.path, options);
+                if hit {
+                    *selected = true;
+                    matched = true;
+                }
+            }
+            if !matched {
+                return Err(format!("No manifest entries match {}", raw));
+            }
+        }
+        Ok(self
+            .entries
+            .iter()
+            .zip(selected)
+            .filter_map(|(entry, selected)| selected.then_some(entry))
+            .collect())
+    }
+
+    /// Plan downloading the entries matching `patterns` into `output_dir`
+    pub fn plan(
+        &self,
+        manifest_id: &str,
+        output_dir: &str,
+        patterns: &[String],
+    ) -> Result<DirectoryDownloadPlan, String> {
+        let selected = self.select(patterns)?;
+        let total_bytes: u64 = selected.iter().map(|e| e.file_size).sum();
+        Ok(DirectoryDownloadPlan {
+            manifest_id: manifest_id.to_string(),
+            output_dir: output_dir.to_string(),
+            skipped_entries: self.entries.len() - selected.len(),
+            skipped_bytes: self.total_bytes() - total_bytes,
+            total_bytes,
+            entries: selected
+                .into_iter()
+                .map(|entry| DirectoryEntryProgress {
+                    manifest_id: manifest_id.to_string(),
+                    path: entry.path.clone(),
+                    file_hash: entry.file_hash.clone(),
+                    status: EntryStatus::Pending,
+                    downloaded_bytes: 0,
+                    total_bytes: entry.file_size,
+                    error: None,
+                })
+                .collect(),
+        })
+    }
+}
+
+impl DirectoryDownloadPlan {
+    /// Where the entry at `path` is written
+    pub fn output_path(&self, path: &str) -> PathBuf {
+        path.split('/')
+            .fold(PathBuf::from(&self.output_dir), |dir, part| dir.join(part))
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.entries
+            .iter()
+            .all(|e| matches!(e.status, EntryStatus::Done | EntryStatus::Failed))
+    }
+
+    /// Apply an update to every unfinished entry for `file_hash` and return
+    /// the entries that changed
+    pub fn update(
+        &mut self,
+        file_hash: &str,
+        status: EntryStatus,
+        downloaded_bytes: Option<u64>,
+        error: Option<&str>,
+    ) -> Vec<DirectoryEntryProgress> {
+        let mut changed = Vec::new();
+        for entry in self.entries.iter_mut().filter(|e| {
+            e.file_hash == file_hash && !matches!(e.status, EntryStatus::Done | EntryStatus::Failed)
+        }) {
+            let bytes = match status {
+                EntryStatus::Done => entry.total_bytes,
+                _ => downloaded_bytes.unwrap_or(entry.downloaded_bytes),
+            };
+            if entry.status == status && entry.downloaded_bytes == bytes {
+                continue;
+            }
+            entry.status = status;
+            entry.downloaded_bytes = bytes.min(entry.total_bytes);
+            entry.error = error.map(str::to_string);
+            changed.push(entry.clone());
+        }
+        changed
+    }
+
+    /// Mark the entry at `path` failed, e.g. when copying a duplicate fails
+    pub fn fail_entry(&mut self, path: &str, error: &str) -> Option<DirectoryEntryProgress> {
+        let entry = self.entries.iter_mut().find(|e| e.path == path)?;
+        entry.status = EntryStatus::Failed;
+        entry.error = Some(error.to_string());
+        Some(entry.clone())
+    }
+}
+
+/// Download the entries of `plan` and follow their file transfer events on
+/// the global bus until every entry is done or failed. `start` begins the
+/// download of one hash into the given path and returns its transfer id;
+/// each distinct hash is downloaded once and the other paths sharing it get
+/// a copy. `report` receives the entries each step changed.
+pub async fn run_plan<S, Fut, R>(
+    mut plan: DirectoryDownloadPlan,
+    mut start: S,
+    mut report: R,
+) -> DirectoryDownloadPlan
+where
+    S: FnMut(String, PathBuf) -> Fut,
+    Fut: Future<Output = Result<String, String>>,
+    R: FnMut(Vec<DirectoryEntryProgress>),
+{
+    // Subscribed before any download starts so no event is missed
+    let mut rx = event_bus::global().subscribe();
+    let mut targets: Vec<(String, PathBuf)> = Vec::new();
+    for entry in &plan.entries {
+        if !targets.iter().any(|(hash, _)| *hash == entry.file_hash) {
+            targets.push((entry.file_hash.clone(), plan.output_path(&entry.path)));
+        }
+    }
+
+    // Transfer id -> hash; events are only matched once their id is known
+    let mut transfers: HashMap<String, String> = HashMap::new();
+    for (file_hash, output) in targets {
+        let changed = match start(file_hash.clone(), output).await {
+            Ok(transfer_id) => {
+                transfers.insert(transfer_id, file_hash.clone());
+                plan.update(&file_hash, EntryStatus::Downloading, None, None)
+            }
+            Err(e) => {
+                warn!("Directory entry {} failed to start: {}", file_hash, e);
+                plan.update(&file_hash, EntryStatus::Failed, None, Some(&e))
+            }
+        };
+        report(changed);
+        loop {
+            match rx.try_recv() {
+                Ok(envelope) => report(apply_event(&mut plan, &transfers, &envelope.payload).await),
+                Err(TryRecvError::Lagged(skipped)) => {
+                    warn!("Directory download missed {} events", skipped)
+                }
+                Err(_) => break,
+            }
+        }
+    }
+
+    while !plan.is_finished() {
+        match rx.recv().await {
+            Ok(envelope) => report(apply_event(&mut plan, &transfers, &envelope.payload).await),
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("Directory download missed {} events", skipped)
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+    plan
+}
+
+/// Apply one bus event to the entries of the transfer it belongs to
+async fn apply_event(
+    plan: &mut DirectoryDownloadPlan,
+    transfers: &HashMap<String, String>,
+    payload: &EventPayload,
+) -> Vec<DirectoryEntryProgress> {
+    let EventPayload::FileTransfer(event) = payload else {
+        return Vec::new();
+    };
+    let Some(file_hash) = event.transfer_id().and_then(|id| transfers.get(id)) else {
+        return Vec::new();
+    };
+    match event {
+        FileTransferEvent::Progress(p) if p.direction == TransferDirection::Download => plan
+            .update(
+                file_hash,
+                EntryStatus::Downloading,
+                Some(p.bytes_done),
+                None,
+            ),
+        FileTransferEvent::FileDownloaded { file_path, .. } => {
+            let source = PathBuf::from(file_path);
+            let mut changed = Vec::new();
+            for progress in plan.update(file_hash, EntryStatus::Done, None, None) {
+                let target = plan.output_path(&progress.path);
+                if target != source {
+                    if let Err(e) = tokio::fs::copy(&source, &target).await {
+                        let error = format!("Failed to copy file: {}", e);
+                        changed.extend(plan.fail_entry(&progress.path, &error));
+                        continue;
+                    }
+                }
+                changed.push(progress);
+            }
+            changed
+        }
+        FileTransferEvent::Error { message, .. } => {
+            plan.update(file_hash, EntryStatus::Failed, None, Some(message))
+        }
+        FileTransferEvent::FileNotFound { .. } => {
+            plan.update(file_hash, EntryStatus::Failed, None, Some("File not found"))
+        }
+        FileTransferEvent::Cancelled { .. } => plan.update(
+            file_hash,
+            EntryStatus::Failed,
+            None,
+            Some("Download canceled"),
+        ),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selects_paths_prefixes_and_globs() {
+        let root = Path::new("/data/set");
+        let file = |path: &str, hash: &str, size| (root.join(path), hash.to_string(), size);
+        let manifest = DirectoryManifest::new(
+            "set".into(),
+            root,
+            vec![
+                file("train/a.csv", "h1", 10),
+                file("train/deep/b.csv", "h2", 20),
+                file("test/c.csv", "h3", 30),
+                file("README.md", "h4", 5),
+                file("copy/a.csv", "h1", 10),
+            ],
+            1,
+        )
+        .unwrap();
+        assert!(DirectoryManifest::new(
+            "bad".into(),
+            root,
+            vec![(PathBuf::from("/etc/passwd"), "h".into(), 1)],
+            1
+        )
+        .is_err());
+        assert!(normalize_relative("../escape").is_err());
+        assert_eq!(normalize_relative("./a\\b").unwrap(), "a/b");
+
+        let id = manifest.id().unwrap();
+        let raw = manifest.to_bytes().unwrap();
+        assert_eq!(DirectoryManifest::from_dht(&raw, &id).unwrap(), manifest);
+        assert!(DirectoryManifest::from_dht(&raw, &"0".repeat(64)).is_err());
+
+        let paths = |patterns: &[&str]| {
+            let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+            manifest
+                .select(&patterns)
+                .map(|s| s.iter().map(|e| e.path.clone()).collect::<Vec<_>>())
+        };
+        assert_eq!(paths(&[]).unwrap().len(), 5);
+        assert_eq!(
+            paths(&["train/"]).unwrap(),
+            vec!["train/a.csv", "train/deep/b.csv"]
+        );
+        assert_eq!(paths(&["*.md"]).unwrap(), vec!["README.md"]);
+        assert_eq!(paths(&["train/*.csv"]).unwrap(), vec!["train/a.csv"]);
+        assert_eq!(paths(&["**/*.csv"]).unwrap().len(), 4);
+        assert!(paths(&["trian/"]).is_err());
+
+        let mut plan = manifest
+            .plan(&id, "/out", &["train".into(), "copy/a.csv".into()])
+            .unwrap();
+        assert_eq!(plan.entries.len(), 3);
+        assert_eq!((plan.skipped_entries, plan.skipped_bytes), (2, 35));
+        assert_eq!(
+            plan.output_path("train/deep/b.csv"),
+            Path::new("/out/train/deep/b.csv")
+        );
+        // Both paths sharing a hash follow the same download
+        assert_eq!(
+            plan.update("h1", EntryStatus::Downloading, Some(4), None)
+                .len(),
+            2
+        );
+        assert_eq!(plan.update("h1", EntryStatus::Done, None, None).len(), 2);
+        assert!(plan
+            .update("h1", EntryStatus::Failed, None, Some("late"))
+            .is_empty());
+        assert!(!plan.is_finished());
+        plan.update("h2", EntryStatus::Failed, None, Some("no seeders"));
+        assert!(plan.is_finished());
+    }
+
+    #[tokio::test]
+    async fn runs_a_manifest_until_every_entry_finishes() {
+        use crate::file_transfer::FileTransferService;
+        use crate::runtime_env::RuntimeEnv;
+        use std::sync::Arc;
+        use tokio::sync::Mutex;
+
+        let storage = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let service = FileTransferService::new_with_env(
+            storage.path().to_path_buf(),
+            false,
+            Arc::new(Mutex::new(crate::keystore::Keystore::new())),
+            None,
+            RuntimeEnv::deterministic(7),
+        )
+        .await
+        .unwrap();
+        let mut files = Vec::new();
+        for (path, data) in [
+            ("a/x.txt", "shared"),
+            ("b/x.txt", "shared"),
+            ("c.txt", "own"),
+        ] {
+            let hash = FileTransferService::calculate_file_hash(data.as_bytes());
+            service
+                .store_file_data(hash.clone(), path.into(), data.as_bytes().to_vec())
+                .await;
+            files.push((Path::new("/set").join(path), hash, data.len() as u64));
+        }
+        let missing = FileTransferService::calculate_file_hash(b"nobody has this");
+        files.push((Path::new("/set/gone.txt").into(), missing, 4));
+        let manifest = DirectoryManifest::new("set".into(), Path::new("/set"), files, 1).unwrap();
+        let output_dir = out.path().to_string_lossy().into_owned();
+        let plan = manifest.plan("id", &output_dir, &[]).unwrap();
+        for dir in ["a", "b"] {
+            std::fs::create_dir_all(out.path().join(dir)).unwrap();
+        }
+
+        let service = &service;
+        let mut reported = Vec::new();
+        let plan = tokio::time::timeout(
+            std::time::Duration::from_secs(10),
+            run_plan(
+                plan,
+                move |hash, output| {
+                    let output = output.to_string_lossy().into_owned();
+                    service.download_file_with_account(hash, output, None, None)
+                },
+                |changed| reported.extend(changed),
+            ),
+        )
+        .await
+        .expect("every entry finishes");
+
+        assert!(plan.is_finished());
+        let status = |path: &str| {
+            let entry = plan.entries.iter().find(|e| e.path == path).unwrap();
+            (entry.status, entry.downloaded_bytes)
+        };
+        assert_eq!(status("a/x.txt"), (EntryStatus::Done, 6));
+        assert_eq!(status("b/x.txt"), (EntryStatus::Done, 6));
+        assert_eq!(status("c.txt"), (EntryStatus::Done, 3));
+        assert_eq!(status("gone.txt").0, EntryStatus::Failed);
+        for (path, data) in [
+            ("a/x.txt", "shared"),
+            ("b/x.txt", "shared"),
+            ("c.txt", "own"),
+        ] {
+            assert_eq!(
+                std::fs::read_to_string(out.path().join(path)).unwrap(),
+                data
+            );
+        }
+        assert!(reported
+            .iter()
+            .any(|e| e.path == "b/x.txt" && e.status == EntryStatus::Done));
+    }
+}
@@ -433,12 +433,26 @@ impl DownloadScheduler {
                 }
             });
 
-            // Perform the download with progress tracking
-            match ftp_client::download_from_ftp_with_progress(
-                &info_clone,
-                &output_path_clone,
-                progress_callback
-            ).await {
+            // Download into the staging area; the file only reaches the output
+            // path once it matches the hash it was requested by
+            let result = async {
+                let staged = crate::download_staging::global().stage(&file_hash_clone, 0)?;
+                let bytes = ftp_client::download_from_ftp_with_progress(
+                    &info_clone,
+                    staged.path(),
+                    progress_callback
+                ).await.map_err(|e| e.to_string())?;
+                let verification = crate::download_staging::Verification {
+                    expected_size: Some(bytes),
+                    published_id: Some(file_hash_clone.clone()),
+                    ..Default::default()
+                };
+                staged.commit(&output_path_clone, &verification).await?;
+                Ok::<u64, String>(bytes)
+            }
+            .await;
+
+            match result {
                 Ok(bytes) => {
                     let duration_seconds = start_time.elapsed().as_secs_f64();
                     let average_speed = if duration_seconds > 0.0 {
@@ -0,0 +1,483 @@
+// Staged downloads
+//
+// Downloads are assembled in a staging directory and only moved to the
+// requested output path once complete and verified, so an interrupted or
+// corrupted transfer never leaves a half-written file at the destination.
+//
+// Staging space is reserved up front for the expected size. A reservation is
+// refused when it would push the in-flight total over the configured quota or
+// leave less than `min_free_bytes` on the staging volume. Moving into place
+// is a rename when staging and destination share a filesystem; otherwise the
+// file is copied next to the destination under a hidden name and renamed
+// from there, which is still atomic for anyone watching the destination.
+//
+// Staged files are removed when their `StagedFile` is dropped without being
+// committed; leftovers from a crash are purged at startup.
+//
+// A download is checked against the id it was published under, not against
+// a hash of what was just written: that id is either the content hash (bare
+// hex SHA-256 or a multihash) or the merkle root over 256 KiB chunks that
+// WebRTC and multi-source publishing announce. A 64-hex id may be either, so
+// both are tried.
+
+use crate::file_transfer::hashing::HashAlgorithm;
+use crate::persisted::Persisted;
+use directories::ProjectDirs;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+
+/// Staged files older than this are leftovers from an interrupted session
+pub const STALE_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+const STAGED_EXTENSION: &str = "staged";
+
+/// Chunk size merkle roots of published files are computed over
+const MERKLE_CHUNK_SIZE: usize = 256 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct StagingConfig {
+    /// Upper bound on bytes reserved by in-flight downloads; 0 disables it
+    pub quota_bytes: u64,
+    /// Free space that must remain on the staging volume
+    pub min_free_bytes: u64,
+}
+
+impl Default for StagingConfig {
+    fn default() -> Self {
+        Self {
+            quota_bytes: 50 * 1024 * 1024 * 1024,
+            min_free_bytes: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StagingStatus {
+    pub root: String,
+    pub reserved_bytes: u64,
+    pub active_downloads: usize,
+    pub available_bytes: Option<u64>,
+    pub config: StagingConfig,
+}
+
+/// What a staged file must match before it is moved into place
+#[derive(Debug, Clone, Default)]
+pub struct Verification {
+    pub expected_size: Option<u64>,
+    /// Hex SHA-256 of the complete content
+    pub expected_sha256: Option<String>,
+    /// Id the file was published under: its content hash or merkle root
+    pub published_id: Option<String>,
+}
+
+pub struct StagingArea {
+    root: PathBuf,
+    config: Persisted<StagingConfig>,
+    /// Expected sizes of the files currently staged
+    reservations: Mutex<Vec<u64>>,
+}
+
+static GLOBAL_STAGING: Lazy<StagingArea> = Lazy::new(default_area);
+
+fn default_area() -> StagingArea {
+    match ProjectDirs::from("com", "chiral-network", "chiral-network") {
+        Some(dirs) => StagingArea::with_persistence(
+            dirs.cache_dir().join("staging"),
+            dirs.config_dir().join("download_staging.json"),
+        ),
+        None => StagingArea::new(std::env::temp_dir().join("chiral-staging")),
+    }
+}
+
+/// The process-wide staging area
+pub fn global() -> &'static StagingArea {
+    &GLOBAL_STAGING
+}
+
+fn sanitize(file_hash: &str) -> String {
+    file_hash
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .take(64)
+        .collect()
+}
+
+/// Whether the file at `path` is the content published as `id`, either by
+/// content hash or by merkle root
+pub async fn matches_published_id(path: &Path, id: &str) -> Result<bool, String> {
+    use crate::file_transfer::hashing::ContentHash;
+    use crate::manager::Sha256Hasher;
+    use rs_merkle::{Hasher, MerkleTree};
+
+    let path = path.to_path_buf();
+    let id = id.trim().to_ascii_lowercase();
+    let content_hash = ContentHash::parse(&id);
+    let merkle_root = hex::decode(&id).ok().filter(|bytes| bytes.len() == 32);
+    if content_hash.is_none() && merkle_root.is_none() {
+        return Err(format!("Unrecognized published id: {}", id));
+    }
+    tokio::task::spawn_blocking(move || {
+        use std::io::Read;
+        let mut file = std::fs::File::open(&path)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        let mut hasher = content_hash.map(|hash| hash.algorithm.hasher());
+        let mut leaves = Vec::new();
+        let mut buffer = vec![0u8; MERKLE_CHUNK_SIZE];
+        loop {
+            // Fill whole chunks so leaves line up with the publisher's
+            let mut filled = 0;
+            while filled < buffer.len() {
+                let n = file
+                    .read(&mut buffer[filled..])
+                    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                if n == 0 {
+                    break;
+                }
+                filled += n;
+            }
+            if filled == 0 {
+                break;
+            }
+            if let Some(hasher) = hasher.as_mut() {
+                hasher.update(&buffer[..filled]);
+            }
+            if merkle_root.is_some() {
+                leaves.push(Sha256Hasher::hash(&buffer[..filled]));
+            }
+            if filled < buffer.len() {
+                break;
+            }
+        }
+        if let (Some(hasher), Some(expected)) = (hasher, content_hash) {
+            if hasher.finish() == expected {
+                return Ok(true);
+            }
+        }
+        Ok(merkle_root.is_some_and(|expected| {
+            MerkleTree::<Sha256Hasher>::from_leaves(&leaves)
+                .root()
+                .is_some_and(|root| root.as_slice() == expected.as_slice())
+        }))
+    })
+    .await
+    .map_err(|e| format!("Hashing task failed: {}", e))?
+}
+
+impl StagingArea {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            config: Persisted::in_memory(),
+            reservations: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Staging area whose config is loaded from and saved to `config_path`
+    pub fn with_persistence(root: impl Into<PathBuf>, config_path: impl AsRef<Path>) -> Self {
+        Self {
+            root: root.into(),
+            config: Persisted::with_persistence(config_path),
+            reservations: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn config(&self) -> StagingConfig {
+        self.config.get()
+    }
+
+    pub fn set_config(&self, config: StagingConfig) -> Result<(), String> {
+        self.config.set(config)
+    }
+
+    pub fn status(&self) -> StagingStatus {
+        let reservations = self
+            .reservations
+            .lock()
+            .map(|r| r.clone())
+            .unwrap_or_default();
+        StagingStatus {
+            root: self.root.to_string_lossy().to_string(),
+            reserved_bytes: reservations.iter().sum(),
+            active_downloads: reservations.len(),
+            available_bytes: fs2::available_space(&self.root).ok(),
+            config: self.config(),
+        }
+    }
+
+    /// Reserve space for a download of `expected_size` bytes and return the
+    /// file to write it to
+    pub fn stage(&self, file_hash: &str, expected_size: u64) -> Result<StagedFile<'_>, String> {
+        std::fs::create_dir_all(&self.root)
+            .map_err(|e| format!("Failed to create staging directory: {}", e))?;
+        let config = self.config();
+        let mut reservations = self
+            .reservations
+            .lock()
+            .map_err(|_| "Staging reservations lock poisoned".to_string())?;
+
+        let reserved: u64 = reservations.iter().sum();
+        if config.quota_bytes > 0 && reserved + expected_size > config.quota_bytes {
+            return Err(format!(
+                "Download staging quota exceeded: {} bytes in use, {} needed, quota {}",
+                reserved, expected_size, config.quota_bytes
+            ));
+        }
+        let available = fs2::available_space(&self.root)
+            .map_err(|e| format!("Failed to check free space: {}", e))?;
+        let needed = reserved + expected_size + config.min_free_bytes;
+        if available < needed {
+            return Err(format!(
+                "Not enough disk space to stage download: need {} bytes, {} available",
+                needed, available
+            ));
+        }
+
+        reservations.push(expected_size);
+        let path = self.root.join(format!(
+            "{}-{:08x}.{}",
+            sanitize(file_hash),
+            rand::random::<u32>(),
+            STAGED_EXTENSION
+        ));
+        Ok(StagedFile {
+            area: self,
+            path,
+            reserved: expected_size,
+            finished: false,
+        })
+    }
+
+    fn release(&self, bytes: u64) {
+        if let Ok(mut reservations) = self.reservations.lock() {
+            if let Some(pos) = reservations.iter().position(|r| *r == bytes) {
+                reservations.swap_remove(pos);
+            }
+        }
+    }
+
+    /// Delete staged files last modified before `max_age` ago; returns how many
+    pub fn purge_stale(&self, max_age: Duration) -> usize {
+        let Ok(entries) = std::fs::read_dir(&self.root) else {
+            return 0;
+        };
+        let cutoff = SystemTime::now()
+            .checked_sub(max_age)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        let mut removed = 0;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(STAGED_EXTENSION) {
+                continue;
+            }
+            let stale = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .map(|modified| modified < cutoff)
+                .unwrap_or(false);
+            if stale && std::fs::remove_file(&path).is_ok() {
+                removed += 1;
+            }
+        }
+        removed
+    }
+}
+
+/// A download being written in the staging area
+pub struct StagedFile<'a> {
+    area: &'a StagingArea,
+    path: PathBuf,
+    reserved: u64,
+    finished: bool,
+}
+
+impl std::fmt::Debug for StagedFile<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StagedFile")
+            .field("path", &self.path)
+            .field("reserved", &self.reserved)
+            .finish()
+    }
+}
+
+impl StagedFile<'_> {
+    /// Where the download must be written
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Verify the staged file and move it to `destination`
+    pub async fn commit(
+        mut self,
+        destination: &Path,
+        verification: &Verification,
+    ) -> Result<PathBuf, String> {
+        let size = tokio::fs::metadata(&self.path)
+            .await
+            .map_err(|e| format!("Staged download is missing: {}", e))?
+            .len();
+        if let Some(expected) = verification.expected_size {
+            if size != expected {
+                return Err(format!(
+                    "Download incomplete: {} of {} bytes",
+                    size, expected
+                ));
+            }
+        }
+        if let Some(expected) = &verification.expected_sha256 {
+            let actual = HashAlgorithm::Sha256
+                .hash_file(&self.path)
+                .await?
+                .to_string();
+            if !actual.eq_ignore_ascii_case(expected) {
+                return Err(format!(
+                    "Download verification failed: expected {}, got {}",
+                    expected, actual
+                ));
+            }
+        }
+        if let Some(id) = &verification.published_id {
+            if !matches_published_id(&self.path, id).await? {
+                return Err(format!(
+                    "Download verification failed: content does not match {}",
+                    id
+                ));
+            }
+        }
+
+        if let Some(parent) = destination.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| format!("Failed to create output directory: {}", e))?;
+        }
+        if tokio::fs::rename(&self.path, destination).await.is_err() {
+            // Different filesystem: copy beside the destination, then rename
+            let file_name = destination
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let sibling = destination.with_file_name(format!(".{}.chiral-part", file_name));
+            let moved = async {
+                tokio::fs::copy(&self.path, &sibling).await?;
+                tokio::fs::rename(&sibling, destination).await
+            }
+            .await;
+            if let Err(e) = moved {
+                let _ = tokio::fs::remove_file(&sibling).await;
+                return Err(format!(
+                    "Failed to move download to {}: {}",
+                    destination.display(),
+                    e
+                ));
+            }
+            let _ = tokio::fs::remove_file(&self.path).await;
+        }
+        self.finished = true;
+        self.area.release(self.reserved);
+        Ok(destination.to_path_buf())
+    }
+}
+
+impl Drop for StagedFile<'_> {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        self.area.release(self.reserved);
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!(
+                    "Failed to remove staged download {}: {}",
+                    self.path.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persisted::TempConfig;
+    use sha2::{Digest, Sha256};
+
+    #[tokio::test]
+    async fn commits_only_verified_downloads() {
+        let config = TempConfig::new();
+        let dir = config.dir();
+        let area = StagingArea::with_persistence(dir.join("staging"), &config.path);
+        let limits = StagingConfig {
+            quota_bytes: 10,
+            min_free_bytes: 0,
+        };
+        area.set_config(limits.clone()).unwrap();
+        assert_eq!(
+            StagingArea::with_persistence(dir.join("staging"), &config.path).config(),
+            limits
+        );
+        let destination = dir.join("out").join("file.txt");
+
+        let staged = area.stage("abc", 5).unwrap();
+        assert!(area.stage("def", 6).is_err(), "quota must be enforced");
+        std::fs::write(staged.path(), b"hel").unwrap();
+        let verification = Verification {
+            expected_size: Some(5),
+            ..Default::default()
+        };
+        assert!(staged.commit(&destination, &verification).await.is_err());
+        assert!(!destination.exists());
+        assert_eq!(area.status().reserved_bytes, 0);
+        assert_eq!(std::fs::read_dir(area.root()).unwrap().count(), 0);
+
+        let staged = area.stage("abc", 5).unwrap();
+        std::fs::write(staged.path(), b"hello").unwrap();
+        let verification = Verification {
+            expected_size: Some(5),
+            expected_sha256: Some(format!("{:x}", Sha256::digest(b"hello"))),
+            ..Default::default()
+        };
+        let path = staged.commit(&destination, &verification).await.unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), b"hello");
+        assert_eq!(area.status().active_downloads, 0);
+    }
+
+    #[tokio::test]
+    async fn published_ids_match_by_content_hash_or_merkle_root() {
+        use crate::manager::Sha256Hasher;
+        use rs_merkle::{Hasher, MerkleTree};
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        let data: Vec<u8> = (0..MERKLE_CHUNK_SIZE * 2 + 17).map(|i| i as u8).collect();
+        std::fs::write(&path, &data).unwrap();
+
+        let sha256 = format!("{:x}", Sha256::digest(&data));
+        let multihash = format!("1220{}", sha256);
+        let leaves: Vec<[u8; 32]> = data
+            .chunks(MERKLE_CHUNK_SIZE)
+            .map(Sha256Hasher::hash)
+            .collect();
+        let root = hex::encode(
+            MerkleTree::<Sha256Hasher>::from_leaves(&leaves)
+                .root()
+                .unwrap(),
+        );
+
+        assert!(matches_published_id(&path, &sha256).await.unwrap());
+        assert!(matches_published_id(&path, &multihash).await.unwrap());
+        assert!(matches_published_id(&path, &root).await.unwrap());
+        let other = format!("{:x}", Sha256::digest(b"other"));
+        assert!(!matches_published_id(&path, &other).await.unwrap());
+        assert!(matches_published_id(&path, "not-an-id").await.is_err());
+    }
+}
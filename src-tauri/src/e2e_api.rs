@@ -645,8 +645,11 @@ async fn api_upload_generate(
     // - BitTorrent: seed via ProtocolManager to obtain a magnet/info_hash, then publish DHT metadata keyed by info_hash
     let published_key: String = if protocol_upper == "HTTP" {
         // Move into provider storage dir and register with HTTP file server state.
-        let permanent_path = app_state.http_server_state.storage_dir.join(&file_hash);
-        if let Err(e) = tokio::fs::rename(&tmp_path, &permanent_path).await {
+        if let Err(e) = app_state
+            .http_server_state
+            .store_file(&file_hash, &tmp_path)
+            .await
+        {
             return (StatusCode::INTERNAL_SERVER_ERROR, Json(crate::http_server::ErrorResponse {
                 error: format!("Failed to move file into storage: {}", e),
             }))
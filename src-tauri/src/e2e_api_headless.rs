@@ -1,5 +1,5 @@
 use axum::{
-    extract::State,
+    extract::{Query, State},
     http::StatusCode,
     response::IntoResponse,
     routing::{get, post},
@@ -47,6 +47,8 @@ pub struct HeadlessE2eState {
     pub ftp_server: Option<Arc<ftp_server::FtpServer>>,
     /// BitTorrent handler (used for BitTorrent upload/download E2E).
     pub bittorrent_handler: Option<Arc<bittorrent_handler::BitTorrentHandler>>,
+    /// Persistent transfer event log (queried via /api/transfers/log).
+    pub transfer_log: Option<Arc<chiral_network::transfer_log::TransferLog>>,
 }
 
 fn extract_btih_info_hash(identifier: &str) -> Option<String> {
@@ -333,9 +335,103 @@ fn create_router(state: HeadlessE2eState) -> Router {
         .route("/api/download", post(api_download))
         .route("/api/pay", post(api_pay))
         .route("/api/tx/receipt", post(api_tx_receipt))
+        .route("/api/events", get(api_events))
+        .route("/api/transfers/log", post(api_transfer_log))
+        .route("/api/metrics/downloads", get(api_download_metrics))
         .with_state(Arc::new(state))
 }
 
+async fn api_transfer_log(
+    State(state): State<Arc<HeadlessE2eState>>,
+    Json(query): Json<chiral_network::transfer_log::TransferLogQuery>,
+) -> impl IntoResponse {
+    let Some(log) = state.transfer_log.clone() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(http_server::ErrorResponse {
+                error: "Transfer event log is not available".to_string(),
+            }),
+        )
+            .into_response();
+    };
+    match tokio::task::spawn_blocking(move || log.query(&query)).await {
+        Ok(Ok(page)) => (StatusCode::OK, Json(page)).into_response(),
+        Ok(Err(error)) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(http_server::ErrorResponse { error }),
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(http_server::ErrorResponse {
+                error: format!("Transfer log query task failed: {}", e),
+            }),
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EventsQuery {
+    limit: Option<usize>,
+    min_severity: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct EventsResponse {
+    events: Vec<chiral_network::event_bus::EventEnvelope>,
+}
+
+async fn api_events(Query(query): Query<EventsQuery>) -> impl IntoResponse {
+    use chiral_network::event_bus::{self, EventSeverity};
+
+    let min_severity = match query.min_severity.as_deref() {
+        None => None,
+        Some("debug") => Some(EventSeverity::Debug),
+        Some("info") => Some(EventSeverity::Info),
+        Some("warning") => Some(EventSeverity::Warning),
+        Some("error") => Some(EventSeverity::Error),
+        Some(other) => {
+            return (
+                StatusCode::BAD_REQUEST,
+                Json(http_server::ErrorResponse {
+                    error: format!("Unknown severity: {}", other),
+                }),
+            )
+                .into_response();
+        }
+    };
+    let events = event_bus::global().recent(query.limit.unwrap_or(100), min_severity);
+    (StatusCode::OK, Json(EventsResponse { events })).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct DownloadMetricsQuery {
+    recent_limit: Option<usize>,
+}
+
+/// Download counters, recent attempts and latency/size histograms
+async fn api_download_metrics(
+    State(state): State<Arc<HeadlessE2eState>>,
+    Query(query): Query<DownloadMetricsQuery>,
+) -> impl IntoResponse {
+    let Some(ft) = state.file_transfer_service.clone() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(http_server::ErrorResponse {
+                error: "File transfer service is not running".to_string(),
+            }),
+        )
+            .into_response();
+    };
+    let snapshot = ft
+        .download_metrics_snapshot_with_limit(query.recent_limit.unwrap_or(20))
+        .await;
+    (StatusCode::OK, Json(snapshot)).into_response()
+}
+
 async fn api_health(State(state): State<Arc<HeadlessE2eState>>) -> impl IntoResponse {
     let peer_id = state.dht.get_peer_id().await;
     let dht_cmd_alive = state.dht.is_command_channel_alive().await;
@@ -457,8 +553,11 @@ async fn api_upload_generate(
 
     let published_key = if protocol_upper == "HTTP" {
         // Move into provider storage dir and register with HTTP file server state.
-        let permanent_path = state.http_server_state.storage_dir.join(&file_hash);
-        if let Err(e) = tokio::fs::rename(&tmp_path, &permanent_path).await {
+        if let Err(e) = state
+            .http_server_state
+            .store_file(&file_hash, &tmp_path)
+            .await
+        {
             return (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(http_server::ErrorResponse {
@@ -615,6 +714,7 @@ async fn api_upload_generate(
             merkle_root: file_hash.clone(),
             chunks: manifest_chunks,
             encrypted_key_bundle: None,
+            content_info: None,
         };
         let manifest_json = match serde_json::to_string(&file_manifest) {
             Ok(s) => s,
@@ -859,7 +959,9 @@ async fn api_upload_generate(
                     .into_response();
             }
         };
+        let stored_size = bytes.len() as u64;
         ft.store_file_data(merkle_root.clone(), file_name.clone(), bytes).await;
+        crate::file_transfer::publish_external_upload(&merkle_root, &file_name, stored_size);
 
         let meta = dht::models::FileMetadata {
             merkle_root: merkle_root.clone(),
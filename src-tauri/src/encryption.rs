@@ -162,6 +162,27 @@ impl FileEncryption {
         key: &[u8; 32],
         encryption_info: &EncryptionInfo,
     ) -> Result<u64, String> {
+        // Read encrypted file
+        let ciphertext = fs::read(input_path)
+            .await
+            .map_err(|e| format!("Failed to read encrypted file: {}", e))?;
+
+        let plaintext = Self::decrypt_bytes(&ciphertext, key, encryption_info)?;
+
+        // Write decrypted file
+        fs::write(output_path, &plaintext)
+            .await
+            .map_err(|e| format!("Failed to write decrypted file: {}", e))?;
+
+        Ok(plaintext.len() as u64)
+    }
+
+    /// Decrypt data encrypted by `encrypt_file`
+    pub fn decrypt_bytes(
+        ciphertext: &[u8],
+        key: &[u8; 32],
+        encryption_info: &EncryptionInfo,
+    ) -> Result<Vec<u8>, String> {
         // Verify encryption method
         if encryption_info.method != "AES-256-GCM" {
             return Err(format!(
@@ -176,11 +197,6 @@ impl FileEncryption {
             return Err("Invalid decryption key (fingerprint mismatch)".to_string());
         }
 
-        // Read encrypted file
-        let ciphertext = fs::read(input_path)
-            .await
-            .map_err(|e| format!("Failed to read encrypted file: {}", e))?;
-
         // Create cipher
         let key = Key::<Aes256Gcm>::from_slice(key);
         let cipher = Aes256Gcm::new(key);
@@ -191,17 +207,10 @@ impl FileEncryption {
         }
         let nonce = Nonce::from_slice(&encryption_info.nonce);
 
-        // Decrypt the file
-        let plaintext = cipher
-            .decrypt(nonce, ciphertext.as_ref())
-            .map_err(|e| format!("Decryption failed: {}", e))?;
-
-        // Write decrypted file
-        fs::write(output_path, &plaintext)
-            .await
-            .map_err(|e| format!("Failed to write decrypted file: {}", e))?;
-
-        Ok(plaintext.len() as u64)
+        // Decrypt the data
+        cipher
+            .decrypt(nonce, ciphertext)
+            .map_err(|e| format!("Decryption failed: {}", e))
     }
 
     /// Encrypt file with password
@@ -0,0 +1,363 @@
+// Error taxonomy shared by service events and command results
+//
+// Services historically report failures as free-form strings, which forces the
+// frontend to string-match. `ErrorCode` gives every failure a stable,
+// machine-readable code; the human-readable text travels alongside it as the
+// detail.
+//
+// Events carry the code as a field (`FileTransferEvent::Error`, and every
+// error envelope on the unified event bus). Commands keep returning
+// `Result<_, String>` for compatibility, but errors built from a
+// `ServiceError` are rendered as "<code>: <detail>" so the code can be
+// recovered with `ServiceError::parse`.
+//
+// Failures are coded where they happen: IO errors by their `io::ErrorKind`
+// (`ServiceError::io`), lower-layer errors by the code they already carry
+// (`ServiceError::wrap`). `ErrorCode::classify` reads that code back, even
+// when context was prepended to it; only messages that never had a code
+// (from peers or other crates) fall back to matching known phrases.
+
+use crate::transfer_events::ErrorCategory;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// Requested file or record does not exist
+    NotFound,
+    /// No peer is providing the requested content
+    NoProviders,
+    /// Writing to local storage failed
+    WriteFailed,
+    /// Reading from local storage failed
+    ReadFailed,
+    /// Not enough free disk space to write the result
+    DiskFull,
+    /// Storage quota or rate limit exhausted
+    QuotaExceeded,
+    /// The remote peer rejected the request
+    PeerRefused,
+    Timeout,
+    /// Connection-level failure (dial, stream reset, unreachable)
+    Network,
+    /// Hash or signature verification failed
+    Verification,
+    /// Missing or invalid keys, accounts or credentials
+    Unauthorized,
+    InvalidInput,
+    /// The service needed for the operation is not running
+    ServiceUnavailable,
+    /// The service is running but cannot take more work right now
+    Busy,
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::NoProviders => "no_providers",
+            ErrorCode::WriteFailed => "write_failed",
+            ErrorCode::ReadFailed => "read_failed",
+            ErrorCode::DiskFull => "disk_full",
+            ErrorCode::QuotaExceeded => "quota_exceeded",
+            ErrorCode::PeerRefused => "peer_refused",
+            ErrorCode::Timeout => "timeout",
+            ErrorCode::Network => "network",
+            ErrorCode::Verification => "verification",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::InvalidInput => "invalid_input",
+            ErrorCode::ServiceUnavailable => "service_unavailable",
+            ErrorCode::Busy => "busy",
+            ErrorCode::Internal => "internal",
+        }
+    }
+
+    pub fn from_str_code(code: &str) -> Option<Self> {
+        const ALL: [ErrorCode; 15] = [
+            ErrorCode::NotFound,
+            ErrorCode::NoProviders,
+            ErrorCode::WriteFailed,
+            ErrorCode::ReadFailed,
+            ErrorCode::DiskFull,
+            ErrorCode::QuotaExceeded,
+            ErrorCode::PeerRefused,
+            ErrorCode::Timeout,
+            ErrorCode::Network,
+            ErrorCode::Verification,
+            ErrorCode::Unauthorized,
+            ErrorCode::InvalidInput,
+            ErrorCode::ServiceUnavailable,
+            ErrorCode::Busy,
+            ErrorCode::Internal,
+        ];
+        ALL.into_iter().find(|c| c.as_str() == code)
+    }
+
+    /// Code of an error string: the one it carries, found behind any context
+    /// prepended to it, or a guess from its wording when it has none
+    pub fn classify(message: &str) -> Self {
+        match ServiceError::find(message) {
+            Some(error) => error.code,
+            None => Self::guess(message),
+        }
+    }
+
+    /// Code for a failed IO call; `fallback` is what the operation failing
+    /// means when the error kind says nothing more specific
+    pub fn from_io(error: &io::Error, fallback: ErrorCode) -> Self {
+        match error.kind() {
+            io::ErrorKind::NotFound => ErrorCode::NotFound,
+            io::ErrorKind::TimedOut => ErrorCode::Timeout,
+            io::ErrorKind::WriteZero => ErrorCode::DiskFull,
+            io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => ErrorCode::InvalidInput,
+            _ if error.raw_os_error() == Some(libc::ENOSPC) => ErrorCode::DiskFull,
+            _ => fallback,
+        }
+    }
+
+    /// Guess for a message that carries no code. Order matters: local IO
+    /// failures are matched before generic phrases like "denied" or "peer".
+    fn guess(message: &str) -> Self {
+        let m = message.to_ascii_lowercase();
+        let has = |needles: &[&str]| needles.iter().any(|n| m.contains(n));
+
+        if has(&["timed out", "timeout", "deadline"]) {
+            ErrorCode::Timeout
+        } else if has(&["no providers", "no seeders", "no peers", "no sources", "no available"]) {
+            ErrorCode::NoProviders
+        } else if has(&["no space", "insufficient space", "disk full"]) {
+            ErrorCode::DiskFull
+        } else if has(&["quota", "rate limit"]) {
+            ErrorCode::QuotaExceeded
+        } else if has(&["failed to write", "write failure", "failed to create", "failed to save"]) {
+            ErrorCode::WriteFailed
+        } else if has(&["failed to read", "failed to open", "failed to load"]) {
+            ErrorCode::ReadFailed
+        } else if has(&["refused", "rejected", "denied", "blocked by"]) {
+            ErrorCode::PeerRefused
+        } else if has(&["hash mismatch", "verification", "checksum", "integrity", "signature"]) {
+            ErrorCode::Verification
+        } else if has(&["decrypt", "private key", "public key", "no active account", "unauthorized"]) {
+            ErrorCode::Unauthorized
+        } else if has(&["not running", "not initialized", "not available", "unavailable"]) {
+            ErrorCode::ServiceUnavailable
+        } else if has(&["not found", "no such file", "missing"]) {
+            ErrorCode::NotFound
+        } else if has(&["invalid", "must be", "cannot be empty", "failed to parse"]) {
+            ErrorCode::InvalidInput
+        } else if has(&["connection", "dial", "unreachable", "network", "stream", "peer"]) {
+            ErrorCode::Network
+        } else {
+            ErrorCode::Internal
+        }
+    }
+
+    /// Most likely code for a failure only known by its category
+    pub fn from_category(category: &ErrorCategory) -> Self {
+        match category {
+            ErrorCategory::Network => ErrorCode::Network,
+            ErrorCategory::Filesystem => ErrorCode::WriteFailed,
+            ErrorCategory::Verification => ErrorCode::Verification,
+            ErrorCategory::Authentication => ErrorCode::Unauthorized,
+            ErrorCategory::NoSources => ErrorCode::NoProviders,
+            ErrorCategory::RateLimit => ErrorCode::QuotaExceeded,
+            ErrorCategory::Timeout => ErrorCode::Timeout,
+            ErrorCategory::Protocol | ErrorCategory::Unknown => ErrorCode::Internal,
+        }
+    }
+
+    /// Coarse category used by `TransferFailedEvent` analytics
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            ErrorCode::NotFound | ErrorCode::NoProviders => ErrorCategory::NoSources,
+            ErrorCode::WriteFailed | ErrorCode::ReadFailed | ErrorCode::DiskFull => {
+                ErrorCategory::Filesystem
+            }
+            ErrorCode::QuotaExceeded | ErrorCode::Busy => ErrorCategory::RateLimit,
+            ErrorCode::Timeout => ErrorCategory::Timeout,
+            ErrorCode::PeerRefused | ErrorCode::Network => ErrorCategory::Network,
+            ErrorCode::Verification => ErrorCategory::Verification,
+            ErrorCode::Unauthorized => ErrorCategory::Authentication,
+            ErrorCode::InvalidInput => ErrorCategory::Protocol,
+            ErrorCode::ServiceUnavailable | ErrorCode::Internal => ErrorCategory::Unknown,
+        }
+    }
+
+    /// Whether retrying the same operation later can reasonably succeed
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ErrorCode::NoProviders
+                | ErrorCode::Timeout
+                | ErrorCode::Network
+                | ErrorCode::PeerRefused
+                | ErrorCode::ServiceUnavailable
+                | ErrorCode::Busy
+        )
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An error code plus human-readable detail
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceError {
+    pub code: ErrorCode,
+    pub detail: String,
+}
+
+impl ServiceError {
+    pub fn new(code: ErrorCode, detail: impl Into<String>) -> Self {
+        Self {
+            code,
+            detail: detail.into(),
+        }
+    }
+
+    /// Wrap a legacy error string, classifying it
+    pub fn from_message(message: impl Into<String>) -> Self {
+        let detail = message.into();
+        if let Some(error) = Self::parse(&detail) {
+            return error;
+        }
+        Self {
+            code: ErrorCode::classify(&detail),
+            detail,
+        }
+    }
+
+    /// Recover a `ServiceError` from its "<code>: <detail>" string form
+    pub fn parse(s: &str) -> Option<Self> {
+        let (code, detail) = s.split_once(": ")?;
+        Some(Self {
+            code: ErrorCode::from_str_code(code)?,
+            detail: detail.to_string(),
+        })
+    }
+    /// Like `parse`, but also finds the error behind context prepended to
+    /// its string form, as in "Download failed: <code>: <detail>"
+    pub fn find(s: &str) -> Option<Self> {
+        std::iter::once(0)
+            .chain(s.match_indices(": ").map(|(at, sep)| at + sep.len()))
+            .find_map(|start| Self::parse(&s[start..]))
+    }
+
+    /// A failed IO call, coded by the kind of error
+    pub fn io(fallback: ErrorCode, context: impl fmt::Display, error: &io::Error) -> Self {
+        Self::new(
+            ErrorCode::from_io(error, fallback),
+            format!("{}: {}", context, error),
+        )
+    }
+
+    /// Put context in front of an error string from a lower layer, keeping
+    /// its code when it has one
+    pub fn wrap(fallback: ErrorCode, context: impl fmt::Display, error: &str) -> Self {
+        match Self::find(error) {
+            Some(inner) => Self::new(inner.code, format!("{}: {}", context, inner.detail)),
+            None => Self::new(fallback, format!("{}: {}", context, error)),
+        }
+    }
+}
+
+/// `map_err` adapter coding a failed IO call with `ServiceError::io`
+pub fn io_error(
+    fallback: ErrorCode,
+    context: impl fmt::Display,
+) -> impl FnOnce(io::Error) -> String {
+    move |error| ServiceError::io(fallback, context, &error).into()
+}
+
+/// `map_err` adapter wrapping an error string with `ServiceError::wrap`
+pub fn wrap_error(
+    fallback: ErrorCode,
+    context: impl fmt::Display,
+) -> impl FnOnce(String) -> String {
+    move |error| ServiceError::wrap(fallback, context, &error).into()
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.detail)
+    }
+}
+
+impl std::error::Error for ServiceError {}
+
+impl From<ServiceError> for String {
+    fn from(error: ServiceError) -> Self {
+        error.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_messages_are_classified() {
+        assert_eq!(ErrorCode::classify("File not found in storage"), ErrorCode::NotFound);
+        assert_eq!(
+            ErrorCode::classify("Failed to write file: permission denied"),
+            ErrorCode::WriteFailed
+        );
+        assert_eq!(ErrorCode::classify("Peer rejected the request"), ErrorCode::PeerRefused);
+        assert_eq!(ErrorCode::classify("Download timed out after 30s"), ErrorCode::Timeout);
+        assert_eq!(ErrorCode::classify("No providers found for file"), ErrorCode::NoProviders);
+        assert_eq!(ErrorCode::classify("Failed to read metadata: eof"), ErrorCode::ReadFailed);
+        assert_eq!(
+            ErrorCode::classify("Failed to write file: No space left on device (os error 28)"),
+            ErrorCode::DiskFull
+        );
+        assert_eq!(
+            ErrorCode::classify("Storage quota exceeded"),
+            ErrorCode::QuotaExceeded
+        );
+        assert_eq!(ErrorCode::classify("something odd"), ErrorCode::Internal);
+    }
+
+    #[test]
+    fn string_form_round_trips() {
+        let error = ServiceError::new(ErrorCode::QuotaExceeded, "Not enough disk space");
+        let rendered: String = error.clone().into();
+        assert_eq!(rendered, "quota_exceeded: Not enough disk space");
+        assert_eq!(ServiceError::parse(&rendered), Some(error));
+        assert_eq!(ErrorCode::classify(&rendered), ErrorCode::QuotaExceeded);
+        assert_eq!(ServiceError::parse("Upload failed: boom"), None);
+        assert_eq!(
+            ErrorCode::classify("Download failed: not_found: File not found in storage"),
+            ErrorCode::NotFound
+        );
+        assert_eq!(ErrorCode::from_str_code("busy"), Some(ErrorCode::Busy));
+    }
+
+    #[test]
+    fn io_errors_are_coded_by_kind() {
+        let missing = io::Error::from(io::ErrorKind::NotFound);
+        let error = ServiceError::io(ErrorCode::ReadFailed, "Failed to read a.bin", &missing);
+        assert_eq!(error.code, ErrorCode::NotFound);
+        let full = io::Error::from_raw_os_error(libc::ENOSPC);
+        let error = ServiceError::io(ErrorCode::WriteFailed, "Failed to write a.bin", &full);
+        assert_eq!(error.code, ErrorCode::DiskFull);
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        let error = ServiceError::io(ErrorCode::WriteFailed, "Failed to write a.bin", &denied);
+        assert_eq!(error.code, ErrorCode::WriteFailed);
+
+        // Wrapping keeps the inner code; "timed out" in the detail is not
+        // taken for a timeout
+        let inner = String::from(ServiceError::new(ErrorCode::Verification, "timed out"));
+        let wrapped = ServiceError::wrap(ErrorCode::Internal, "Download failed", &inner);
+        assert_eq!(wrapped.code, ErrorCode::Verification);
+        assert_eq!(wrapped.detail, "Download failed: timed out");
+        let plain = ServiceError::wrap(ErrorCode::WriteFailed, "Failed to store", "boom");
+        assert_eq!(plain.to_string(), "write_failed: Failed to store: boom");
+    }
+}
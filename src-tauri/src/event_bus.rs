@@ -0,0 +1,386 @@
+// Unified Event Bus - single stream of typed events from every service
+//
+// FileTransferService, the DHT (including the relay client/server layer) and the
+// transfer lifecycle bus each keep their own channels for their direct consumers.
+// In addition, everything they emit is published here wrapped in an
+// `EventEnvelope`, so the Tauri frontend and the headless APIs can consume one
+// stream with a uniform shape instead of polling every service separately.
+// Alerts (see `alerts`), shared-file changes (see `shared_file_watcher`),
+// served-file/payment notices (see `activity_feed`) and relay registry changes
+// (see `relay_registry`) are published only here.
+//
+// The bus is a process-wide broadcast channel. Publishing never blocks: slow
+// subscribers lag and skip events rather than stalling the services. A small
+// ring of recent envelopes is kept for consumers that poll (headless API).
+
+use crate::activity_feed::ActivityEvent;
+use crate::alerts::AlertEvent;
+use crate::dht::DhtEvent;
+use crate::error_codes::{ErrorCode, ServiceError};
+use crate::file_transfer::FileTransferEvent;
+use crate::relay_registry::RelayRegistryEvent;
+use crate::shared_file_watcher::SharedFileEvent;
+use crate::transfer_events::{current_timestamp_ms, TransferEvent};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tauri::{AppHandle, Emitter};
+use tokio::sync::broadcast;
+use tracing::{debug, warn};
+
+/// Tauri channel carrying every envelope
+pub const EVENT_BUS_CHANNEL: &str = "chiral:event";
+
+/// Broadcast capacity before lagging subscribers start skipping events
+const BUS_CAPACITY: usize = 1024;
+
+/// Number of envelopes retained for polling consumers
+const RECENT_CAPACITY: usize = 256;
+
+static GLOBAL_BUS: Lazy<EventBus> = Lazy::new(|| EventBus::new(BUS_CAPACITY, RECENT_CAPACITY));
+
+/// The process-wide event bus
+pub fn global() -> &'static EventBus {
+    &GLOBAL_BUS
+}
+
+/// Service that produced an event
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EventSource {
+    FileTransfer,
+    Transfer,
+    Dht,
+    Relay,
+    Alert,
+    SharedFile,
+    Activity,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum EventSeverity {
+    Debug,
+    Info,
+    Warning,
+    Error,
+}
+
+/// Service-specific event carried by an envelope
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", content = "data", rename_all = "snake_case")]
+pub enum EventPayload {
+    FileTransfer(FileTransferEvent),
+    Transfer(TransferEvent),
+    Dht(DhtEvent),
+    Alert(AlertEvent),
+    SharedFile(SharedFileEvent),
+    Activity(ActivityEvent),
+    RelayRegistry(RelayRegistryEvent),
+}
+
+/// Common wrapper for everything published on the bus
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EventEnvelope {
+    /// Monotonic sequence number, unique per process
+    pub id: u64,
+    pub source: EventSource,
+    pub severity: EventSeverity,
+    /// Groups events belonging to one operation (e.g. a transfer ID)
+    pub correlation_id: Option<String>,
+    /// Unix timestamp in milliseconds
+    pub timestamp: u64,
+    /// Error code and detail when the payload reports a failure
+    pub error: Option<ServiceError>,
+    pub payload: EventPayload,
+}
+
+impl EventPayload {
+    /// Typed error carried by failure events, so consumers can branch on the
+    /// code instead of matching message text
+    pub fn error(&self) -> Option<ServiceError> {
+        match self {
+            EventPayload::FileTransfer(FileTransferEvent::Error { code, message, .. }) => {
+                Some(ServiceError::new(*code, message.clone()))
+            }
+            EventPayload::FileTransfer(FileTransferEvent::FileNotFound { file_hash, .. }) => Some(
+                ServiceError::new(ErrorCode::NotFound, format!("File not found: {}", file_hash)),
+            ),
+            EventPayload::Transfer(TransferEvent::Failed(e)) => {
+                let code = match ErrorCode::classify(&e.error) {
+                    ErrorCode::Internal => ErrorCode::from_category(&e.error_category),
+                    code => code,
+                };
+                Some(ServiceError::new(code, e.error.clone()))
+            }
+            EventPayload::Transfer(TransferEvent::ChunkFailed(e)) => {
+                Some(ServiceError::from_message(e.error.clone()))
+            }
+            EventPayload::Dht(DhtEvent::Error(message)) => {
+                Some(ServiceError::from_message(message.clone()))
+            }
+            EventPayload::Dht(DhtEvent::BitswapError { error, .. }) => {
+                Some(ServiceError::from_message(error.clone()))
+            }
+            EventPayload::Dht(DhtEvent::FileNotFound(hash)) => Some(ServiceError::new(
+                ErrorCode::NotFound,
+                format!("File not found: {}", hash),
+            )),
+            _ => None,
+        }
+    }
+}
+
+pub struct EventBus {
+    tx: broadcast::Sender<EventEnvelope>,
+    next_id: AtomicU64,
+    recent: Mutex<VecDeque<EventEnvelope>>,
+    recent_capacity: usize,
+}
+
+impl EventBus {
+    pub fn new(capacity: usize, recent_capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity);
+        Self {
+            tx,
+            next_id: AtomicU64::new(1),
+            recent: Mutex::new(VecDeque::with_capacity(recent_capacity)),
+            recent_capacity,
+        }
+    }
+
+    /// Wrap `payload` in an envelope and publish it; returns the envelope ID
+    pub fn publish(
+        &self,
+        source: EventSource,
+        severity: EventSeverity,
+        correlation_id: Option<String>,
+        payload: EventPayload,
+    ) -> u64 {
+        let envelope = EventEnvelope {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            source,
+            severity,
+            correlation_id,
+            timestamp: current_timestamp_ms(),
+            error: payload.error(),
+            payload,
+        };
+        let id = envelope.id;
+
+        if let Ok(mut recent) = self.recent.lock() {
+            if recent.len() == self.recent_capacity {
+                recent.pop_front();
+            }
+            recent.push_back(envelope.clone());
+        }
+
+        // An error only means there are no subscribers right now
+        let _ = self.tx.send(envelope);
+        id
+    }
+
+    pub fn publish_file_transfer(&self, event: &FileTransferEvent) -> u64 {
+        let severity = match event {
+            FileTransferEvent::Error { .. } => EventSeverity::Error,
+            FileTransferEvent::FileNotFound { .. }
+            | FileTransferEvent::IntegrityFailed { .. } => EventSeverity::Warning,
+            FileTransferEvent::DownloadAttempt(_) | FileTransferEvent::Progress(_) => {
+                EventSeverity::Debug
+            }
+            _ => EventSeverity::Info,
+        };
+        let correlation_id = event.transfer_id().map(str::to_string);
+        self.publish(
+            EventSource::FileTransfer,
+            severity,
+            correlation_id,
+            EventPayload::FileTransfer(event.clone()),
+        )
+    }
+
+    pub fn publish_transfer(&self, event: &TransferEvent) -> u64 {
+        let (severity, transfer_id) = match event {
+            TransferEvent::Queued(e) => (EventSeverity::Info, &e.transfer_id),
+            TransferEvent::Started(e) => (EventSeverity::Info, &e.transfer_id),
+            TransferEvent::SourceConnected(e) => (EventSeverity::Debug, &e.transfer_id),
+            TransferEvent::SourceDisconnected(e) => (EventSeverity::Warning, &e.transfer_id),
+            TransferEvent::ChunkCompleted(e) => (EventSeverity::Debug, &e.transfer_id),
+            TransferEvent::ChunkFailed(e) => (EventSeverity::Warning, &e.transfer_id),
+            TransferEvent::Progress(e) => (EventSeverity::Debug, &e.transfer_id),
+            TransferEvent::Paused(e) => (EventSeverity::Info, &e.transfer_id),
+            TransferEvent::Resumed(e) => (EventSeverity::Info, &e.transfer_id),
+            TransferEvent::Completed(e) => (EventSeverity::Info, &e.transfer_id),
+            TransferEvent::Failed(e) => (EventSeverity::Error, &e.transfer_id),
+            TransferEvent::Canceled(e) => (EventSeverity::Info, &e.transfer_id),
+            TransferEvent::SpeedUpdate(e) => (EventSeverity::Debug, &e.transfer_id),
+            TransferEvent::SlowTransfer(e) => (EventSeverity::Warning, &e.transfer_id),
+            TransferEvent::Stalled(e) => (EventSeverity::Warning, &e.transfer_id),
+        };
+        self.publish(
+            EventSource::Transfer,
+            severity,
+            Some(transfer_id.clone()),
+            EventPayload::Transfer(event.clone()),
+        )
+    }
+
+    /// Publish a DHT event. Raw Bitswap block payloads are not mirrored since
+    /// they carry chunk data; returns `None` for those.
+    pub fn publish_dht(&self, event: &DhtEvent) -> Option<u64> {
+        if matches!(event, DhtEvent::BitswapDataReceived { .. }) {
+            return None;
+        }
+        let severity = match event {
+            DhtEvent::Error(_) | DhtEvent::BitswapError { .. } => EventSeverity::Error,
+            DhtEvent::Warning(_) | DhtEvent::FileNotFound(_) => EventSeverity::Warning,
+            DhtEvent::PeerRtt { .. } | DhtEvent::BitswapChunkDownloaded { .. } => {
+                EventSeverity::Debug
+            }
+            _ => EventSeverity::Info,
+        };
+        // Relay reservations/circuits are surfaced through the DHT channel;
+        // tag them so consumers can filter the relay layer on its own.
+        let source = match event {
+            DhtEvent::ProxyStatus { status, .. } if status.starts_with("relay_") => {
+                EventSource::Relay
+            }
+            DhtEvent::ReputationEvent { event_type, .. } if event_type.starts_with("Relay") => {
+                EventSource::Relay
+            }
+            DhtEvent::RelayAnnounced { .. }
+            | DhtEvent::RelayReservation(_)
+            | DhtEvent::HolePunch { .. }
+            | DhtEvent::RelayUsage { .. } => EventSource::Relay,
+            _ => EventSource::Dht,
+        };
+        let correlation_id = match event {
+            DhtEvent::FileDownloaded { file_hash } => Some(file_hash.clone()),
+            DhtEvent::BitswapChunkDownloaded { file_hash, .. } => Some(file_hash.clone()),
+            DhtEvent::BitswapError { query_id, .. } => Some(query_id.clone()),
+            _ => None,
+        };
+        Some(self.publish(source, severity, correlation_id, EventPayload::Dht(event.clone())))
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<EventEnvelope> {
+        self.tx.subscribe()
+    }
+
+    /// Most recent envelopes, oldest first, optionally filtered by minimum severity
+    pub fn recent(&self, limit: usize, min_severity: Option<EventSeverity>) -> Vec<EventEnvelope> {
+        let recent = match self.recent.lock() {
+            Ok(recent) => recent,
+            Err(_) => return Vec::new(),
+        };
+        let mut out: Vec<EventEnvelope> = recent
+            .iter()
+            .rev()
+            .filter(|e| min_severity.map_or(true, |min| e.severity >= min))
+            .take(limit)
+            .cloned()
+            .collect();
+        out.reverse();
+        out
+    }
+}
+
+/// Forward every envelope to the frontend on `EVENT_BUS_CHANNEL`
+pub fn spawn_tauri_forwarder(app_handle: AppHandle) -> tauri::async_runtime::JoinHandle<()> {
+    let mut rx = global().subscribe();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(envelope) => {
+                    if let Err(e) = app_handle.emit(EVENT_BUS_CHANNEL, &envelope) {
+                        warn!("Failed to emit bus event {}: {}", envelope.id, e);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    debug!("Event bus forwarder lagged, skipped {} events", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+/// Pass every envelope through `map` and store what it yields with `record`
+/// until the bus closes. Stores are written on the blocking pool so a slow
+/// disk doesn't hold up the bus; `name` labels the warnings.
+pub async fn record_events<T, M, R>(name: &str, mut map: M, record: R)
+where
+    T: Send + 'static,
+    M: FnMut(&EventEnvelope) -> Option<T>,
+    R: Fn(&T) -> Result<i64, String> + Send + Sync + 'static,
+{
+    let record = std::sync::Arc::new(record);
+    let mut rx = global().subscribe();
+    loop {
+        match rx.recv().await {
+            Ok(envelope) => {
+                let Some(item) = map(&envelope) else {
+                    continue;
+                };
+                let record = record.clone();
+                match tokio::task::spawn_blocking(move || record(&item)).await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(e)) => warn!("{}", e),
+                    Err(e) => warn!("{} writer task failed: {}", name, e),
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("{} recorder lagged, {} events not recorded", name, skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => {
+                debug!("Event bus closed, stopping {} recorder", name);
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recent_is_bounded_and_filters_by_severity() {
+        let bus = EventBus::new(16, 3);
+        for i in 0..4 {
+            bus.publish(
+                EventSource::Dht,
+                if i % 2 == 0 { EventSeverity::Info } else { EventSeverity::Error },
+                None,
+                EventPayload::Dht(DhtEvent::Info(format!("event {}", i))),
+            );
+        }
+
+        let all = bus.recent(10, None);
+        assert_eq!(all.len(), 3);
+        assert!(all.windows(2).all(|w| w[0].id < w[1].id));
+
+        let errors = bus.recent(10, Some(EventSeverity::Error));
+        assert_eq!(errors.iter().map(|e| e.id).collect::<Vec<_>>(), vec![2, 4]);
+    }
+
+    #[test]
+    fn relay_events_are_tagged_with_relay_source() {
+        let bus = EventBus::new(16, 16);
+        let mut rx = bus.subscribe();
+        bus.publish_dht(&DhtEvent::ProxyStatus {
+            id: "relay-peer".into(),
+            address: String::new(),
+            status: "relay_ready".into(),
+            latency_ms: None,
+            error: None,
+        });
+
+        let envelope = rx.try_recv().expect("envelope");
+        assert_eq!(envelope.source, EventSource::Relay);
+        assert_eq!(envelope.severity, EventSeverity::Info);
+    }
+}
@@ -1,21 +1,66 @@
+use crate::blob_store::{BlobStore, LinkedFile};
 use crate::encryption;
+use crate::error_codes::{io_error, wrap_error, ErrorCode, ServiceError};
+use crate::histogram::{Histogram, HistogramSnapshot, LATENCY_BOUNDS_MS, SIZE_BOUNDS_BYTES};
+use crate::profiling::PROFILE_TARGET;
+use crate::runtime_env::RuntimeEnv;
 use crate::transfer_events::{
-    TransferEventBus, TransferCompletedEvent, TransferFailedEvent,
-    TransferStartedEvent, SourceInfo, SourceType, SourceSummary, ErrorCategory,
-    current_timestamp_ms,
+    current_timestamp_ms, PauseReason, SourceInfo, SourceSummary, SourceType,
+    TransferCanceledEvent, TransferCompletedEvent, TransferEvent, TransferEventBus,
+    TransferFailedEvent, TransferPausedEvent, TransferResumedEvent, TransferStartedEvent,
 };
+use bytes::BytesMut;
 use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
-use std::path::PathBuf;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
-use tauri::AppHandle;
-use tokio::sync::{mpsc, Mutex};
-use tokio::time::{sleep, Duration};
-use tracing::{debug, error, info, info_span, warn};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter};
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, info_span, trace_span, warn, Instrument};
 use x25519_dalek::StaticSecret;
 
+pub mod batch;
+pub mod buffers;
+pub mod chunking;
+pub mod circuit;
+pub mod command_queue;
+pub mod control;
+pub mod folder;
+pub mod gateway;
+pub mod hashing;
+pub mod naming;
+pub mod progress;
+pub mod protocol;
+pub mod ranges;
+pub mod recipient;
+pub mod resume;
+pub mod retry;
+pub mod scrub;
+pub mod seeding;
+pub mod swarm;
+
+use batch::{BatchItem, BatchProgress, BatchTable};
+use chunking::{ChunkError, ChunkManifest};
+use circuit::{CircuitBreaker, OpenCircuit};
+use command_queue::{
+    command_queue, CommandQueueConfig, CommandQueueStats, CommandSender, OverflowPolicy,
+};
+use control::{DownloadJob, DownloadTable, StopRequest, TransferPriority};
+use folder::FolderManifest;
+use gateway::PartialFiles;
+use hashing::{ContentHash, HashAlgorithm};
+use naming::{NamingRules, OutputTarget};
+use progress::{ProgressReporter, TransferDirection, TransferProgress};
+use resume::{PartFile, ResumeRecord, ResumeStore};
+use retry::RetryPolicy;
+use scrub::{CorruptBlob, ScrubReport, ScrubSchedule, ScrubState};
+use seeding::{FileSeedingStats, SeedingStats};
+use swarm::{ChunkFetcher, ProviderStats, RemoteSources, SwarmConfig};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EncryptedFileMetadata {
     pub original_file_hash: String,
@@ -25,59 +70,363 @@ pub struct EncryptedFileMetadata {
     pub recipient_public_key: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct FileRequest {
     pub file_hash: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct FileResponse {
+    #[serde(with = "protocol::base64_bytes")]
     pub file_data: Vec<u8>,
     pub file_name: String,
     pub file_size: u64,
+    /// Detected from the file's bytes at upload; absent from older peers
+    #[serde(default)]
+    pub mime_type: Option<String>,
+    /// When the source file was created, Unix seconds
+    #[serde(default)]
+    pub created_at: Option<u64>,
+}
+
+/// Where `download_file_named` put a download
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct NamedDownload {
+    /// None when the name was taken and the download skipped
+    pub transfer_id: Option<String>,
+    pub output_path: String,
 }
 
-// Simplified file transfer service without complex libp2p request-response
-// This provides basic file storage and retrieval functionality
+/// A row of the stored file listing
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct StoredFile {
+    #[serde(flatten)]
+    pub entry: crate::local_library::LibraryEntry,
+    pub pinned: bool,
+    /// Unix timestamp (seconds) the file is deleted at, if it expires
+    pub expires_at: Option<u64>,
+    /// Requests for the file this node answered
+    pub times_served: u64,
+}
+
+// File storage and retrieval. Other nodes reach the store through the
+// request-response protocol in `protocol`, carried by the DHT swarm.
 
 #[derive(Debug)]
 pub enum FileTransferCommand {
     UploadFile {
+        transfer_id: String,
         file_path: String,
         file_name: String,
+        /// X25519 public key to encrypt the file for, hex
+        recipient_public_key: Option<String>,
+        /// Unix seconds after which the stored file is garbage collected
+        expires_at: Option<u64>,
+        /// Index the file where it is instead of copying it into the store
+        in_place: bool,
+        active_account: Option<String>,
+        active_private_key: Option<String>,
+        /// Told the file hash, or the error, once the upload is done
+        reply: Option<oneshot::Sender<Result<String, String>>>,
+    },
+    /// Upload every file under a directory and store a signed manifest of
+    /// the tree, whose hash identifies the folder
+    UploadDirectory {
+        transfer_id: String,
+        dir_path: String,
         active_account: Option<String>,
         active_private_key: Option<String>,
     },
     DownloadFile {
+        transfer_id: String,
         file_hash: String,
         output_path: String,
+        /// Peers to fetch chunks from when the file is not stored locally
+        providers: Vec<String>,
+        /// Overrides the service's retry policy for this download
+        retry_policy: Option<RetryPolicy>,
+        priority: TransferPriority,
+        /// Fetch chunks in order so the file can be read while it downloads
+        streaming: bool,
+        active_account: Option<String>,
+        active_private_key: Option<String>,
+    },
+    /// Fetch a folder manifest, then queue a download for every file it
+    /// lists, each under `<transfer_id>/<index>`
+    DownloadDirectory {
+        transfer_id: String,
+        manifest_hash: String,
+        output_dir: String,
+        providers: Vec<String>,
+        retry_policy: Option<RetryPolicy>,
+        priority: TransferPriority,
         active_account: Option<String>,
         active_private_key: Option<String>,
     },
+    /// Download several files as one batch: they share the download queue
+    /// and the batch reports its progress and completion as a whole
+    DownloadBatch {
+        batch_id: String,
+        items: Vec<BatchItem>,
+        providers: Vec<String>,
+        retry_policy: Option<RetryPolicy>,
+        priority: TransferPriority,
+        active_private_key: Option<String>,
+    },
+    /// Move a queued download within the queue
+    SetPriority {
+        transfer_id: String,
+        priority: TransferPriority,
+    },
+    /// How many downloads may run at once; extra ones wait in the queue
+    SetMaxConcurrentTransfers {
+        limit: usize,
+    },
+    /// Stop an in-flight or paused download; its partial output is discarded
+    CancelTransfer {
+        transfer_id: String,
+    },
+    /// Stop an in-flight download but keep what it has written so far
+    PauseTransfer {
+        transfer_id: String,
+    },
+    /// Restart a paused download from where it stopped
+    ResumeTransfer {
+        transfer_id: String,
+    },
     GetStoredFiles,
+    /// Delete a stored file and stop announcing this node as its provider
+    RemoveFile {
+        file_hash: String,
+    },
+    /// Remove unpinned stored files, oldest first, until they take at most
+    /// `max_bytes`
+    TrimStorage {
+        max_bytes: u64,
+        reply: oneshot::Sender<Vec<String>>,
+    },
+    /// Delete stored files whose expiry has passed; sent periodically by
+    /// the garbage collection task
+    CollectExpired,
+    /// Re-hash every stored file, fetching corrupted ones again from other
+    /// providers if `repair` is set
+    VerifyStorage {
+        repair: bool,
+        reply: Option<oneshot::Sender<Result<ScrubReport, String>>>,
+    },
+    /// Start the background scrub if its schedule says it is due; sent
+    /// periodically by the garbage collection task
+    ScheduledScrub,
+    SetScrubSchedule {
+        schedule: Option<ScrubSchedule>,
+    },
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum FileTransferEvent {
     FileUploaded {
+        transfer_id: String,
         file_hash: String,
         file_name: String,
+        file_size: u64,
     },
     FileDownloaded {
+        transfer_id: String,
         file_path: String,
     },
+    FolderUploaded {
+        transfer_id: String,
+        manifest_hash: String,
+        name: String,
+        file_count: usize,
+    },
+    /// The folder manifest arrived and verified; its files are queued as
+    /// downloads of their own
+    FolderQueued {
+        transfer_id: String,
+        name: String,
+        file_count: usize,
+    },
     FileNotFound {
+        transfer_id: String,
         file_hash: String,
     },
     Error {
+        transfer_id: String,
+        direction: TransferDirection,
+        code: ErrorCode,
         message: String,
     },
     DownloadAttempt(DownloadAttemptSnapshot),
+    /// Periodic bytes-done report while an upload or download runs
+    Progress(TransferProgress),
+    Cancelled {
+        transfer_id: String,
+    },
+    Paused {
+        transfer_id: String,
+        bytes_done: u64,
+        total_bytes: u64,
+    },
+    Resumed {
+        transfer_id: String,
+    },
+    /// The download is waiting for a free slot; 0 is next in line
+    Queued {
+        transfer_id: String,
+        position: usize,
+    },
+    /// The written output did not hash to the requested content address;
+    /// the attempt is retried if the retry policy allows
+    IntegrityFailed {
+        transfer_id: String,
+        expected_hash: String,
+        actual_hash: String,
+        attempt: u32,
+    },
+    /// A stored file reached its expiry, was deleted and is no longer
+    /// provided
+    FileExpired {
+        file_hash: String,
+        expires_at: u64,
+        published_ids: Vec<String>,
+    },
+    /// A stored file was deleted on request or to make room and is no
+    /// longer provided. `published_ids` are the other ids it was published
+    /// under, e.g. its merkle root.
+    FileRemoved {
+        file_hash: String,
+        published_ids: Vec<String>,
+    },
+    /// A file of a batch ended
+    BatchProgress(BatchProgress),
+    /// Every file of a batch ended; `failed` lists the hashes of those that
+    /// failed or were cancelled
+    BatchCompleted {
+        batch_id: String,
+        file_count: usize,
+        failed: Vec<String>,
+    },
+    /// A scrub re-hashed the stored files
+    StorageScrubbed(ScrubReport),
+    /// The first `readable_bytes` of a streaming download's part file are
+    /// written and verified and can be read before the download finishes
+    StreamReadable {
+        transfer_id: String,
+        file_hash: String,
+        part_path: String,
+        readable_bytes: u64,
+        total_bytes: u64,
+    },
+    /// Downloads of a file hash failed repeatedly; new ones are refused
+    /// until the circuit reopens
+    CircuitOpen(OpenCircuit),
+}
+
+impl FileTransferEvent {
+    /// The upload or download this event belongs to. Two downloads of the
+    /// same hash run side by side are told apart by this alone. `None` for
+    /// storage events that belong to no transfer.
+    pub fn transfer_id(&self) -> Option<&str> {
+        let transfer_id = match self {
+            FileTransferEvent::FileUploaded { transfer_id, .. }
+            | FileTransferEvent::FileDownloaded { transfer_id, .. }
+            | FileTransferEvent::FolderUploaded { transfer_id, .. }
+            | FileTransferEvent::FolderQueued { transfer_id, .. }
+            | FileTransferEvent::FileNotFound { transfer_id, .. }
+            | FileTransferEvent::Error { transfer_id, .. }
+            | FileTransferEvent::Cancelled { transfer_id }
+            | FileTransferEvent::Paused { transfer_id, .. }
+            | FileTransferEvent::Resumed { transfer_id }
+            | FileTransferEvent::Queued { transfer_id, .. }
+            | FileTransferEvent::IntegrityFailed { transfer_id, .. }
+            | FileTransferEvent::StreamReadable { transfer_id, .. } => transfer_id,
+            FileTransferEvent::DownloadAttempt(snapshot) => &snapshot.transfer_id,
+            FileTransferEvent::Progress(progress) => &progress.transfer_id,
+            FileTransferEvent::BatchProgress(progress) => &progress.batch_id,
+            FileTransferEvent::BatchCompleted { batch_id, .. } => batch_id,
+            FileTransferEvent::FileExpired { .. }
+            | FileTransferEvent::FileRemoved { .. }
+            | FileTransferEvent::StorageScrubbed(_)
+            | FileTransferEvent::CircuitOpen(_) => return None,
+        };
+        Some(transfer_id)
+    }
+
+    /// Name of the event, as in its serialized `type` field
+    pub fn kind(&self) -> &'static str {
+        match self {
+            FileTransferEvent::FileUploaded { .. } => "file_uploaded",
+            FileTransferEvent::FileDownloaded { .. } => "file_downloaded",
+            FileTransferEvent::FolderUploaded { .. } => "folder_uploaded",
+            FileTransferEvent::FolderQueued { .. } => "folder_queued",
+            FileTransferEvent::FileNotFound { .. } => "file_not_found",
+            FileTransferEvent::Error { .. } => "error",
+            FileTransferEvent::DownloadAttempt(_) => "download_attempt",
+            FileTransferEvent::Progress(_) => "progress",
+            FileTransferEvent::Cancelled { .. } => "cancelled",
+            FileTransferEvent::Paused { .. } => "paused",
+            FileTransferEvent::Resumed { .. } => "resumed",
+            FileTransferEvent::Queued { .. } => "queued",
+            FileTransferEvent::IntegrityFailed { .. } => "integrity_failed",
+            FileTransferEvent::FileExpired { .. } => "file_expired",
+            FileTransferEvent::FileRemoved { .. } => "file_removed",
+            FileTransferEvent::BatchProgress(_) => "batch_progress",
+            FileTransferEvent::BatchCompleted { .. } => "batch_completed",
+            FileTransferEvent::StorageScrubbed(_) => "storage_scrubbed",
+            FileTransferEvent::StreamReadable { .. } => "stream_readable",
+            FileTransferEvent::CircuitOpen(_) => "circuit_open",
+        }
+    }
+
+    /// Push the event to the webview, on `file_transfer:<kind>` and on
+    /// `FILE_TRANSFER_CHANNEL`
+    fn emit(&self, app: &AppHandle) {
+        let typed_channel = format!("file_transfer:{}", self.kind());
+        for channel in [typed_channel.as_str(), FILE_TRANSFER_CHANNEL] {
+            if let Err(e) = app.emit(channel, self) {
+                warn!("Failed to emit event to {}: {}", channel, e);
+            }
+        }
+    }
 }
 
-const MAX_DOWNLOAD_ATTEMPTS: u32 = 3;
-const BASE_BACKOFF_MS: u64 = 250;
-const MAX_BACKOFF_MS: u64 = 1_500;
+/// Webview channel carrying every file transfer event
+pub const FILE_TRANSFER_CHANNEL: &str = "file_transfer:event";
+
+/// Bytes read from stored files whose metadata has no MIME type
+const MIME_SNIFF_LEN: u64 = 4096;
+
+/// How often the garbage collection task looks for expired files
+const GC_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Correlation ID assigned to an upload or download when it is enqueued.
+/// Every event and tracing span belonging to the operation carries it.
+pub fn new_transfer_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// Publish an upload that did not go through the upload command, e.g. a
+/// file seeded over HTTP or BitTorrent, so it is recorded like the others.
+/// Returns the transfer ID it was given.
+pub fn publish_external_upload(file_hash: &str, file_name: &str, file_size: u64) -> String {
+    let transfer_id = new_transfer_id();
+    crate::event_bus::global().publish_file_transfer(&FileTransferEvent::FileUploaded {
+        transfer_id: transfer_id.clone(),
+        file_hash: file_hash.to_string(),
+        file_name: file_name.to_string(),
+        file_size,
+    });
+    transfer_id
+}
+
+/// Error a download returns once its cancellation token fires
+pub(crate) fn cancelled_error() -> String {
+    "Transfer cancelled".to_string()
+}
 
 #[derive(Debug, Clone, Serialize, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
@@ -90,14 +439,31 @@ pub enum AttemptStatus {
 #[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct DownloadAttemptSnapshot {
+    /// ID assigned when the download was enqueued, shared by all its events
+    pub transfer_id: String,
     pub file_hash: String,
     pub attempt: u32,
     pub max_attempts: u32,
     pub status: AttemptStatus,
     pub duration_ms: u64,
+    /// Time from the start of the attempt until file data was available
+    pub time_to_first_byte_ms: Option<u64>,
+    /// Size of the delivered file, for successful attempts
+    pub file_size: Option<u64>,
     pub timestamp: u64,
 }
 
+/// Distributions of download attempt latency and delivered file size
+#[derive(Debug, Clone, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadHistograms {
+    /// Duration of every attempt, in milliseconds
+    pub attempt_duration_ms: HistogramSnapshot,
+    pub time_to_first_byte_ms: HistogramSnapshot,
+    /// Size of successfully downloaded files, in bytes
+    pub file_size_bytes: HistogramSnapshot,
+}
+
 #[derive(Debug, Clone, Serialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct DownloadMetricsSnapshot {
@@ -105,173 +471,453 @@ pub struct DownloadMetricsSnapshot {
     pub total_failures: u64,
     pub total_retries: u64,
     pub recent_attempts: Vec<DownloadAttemptSnapshot>,
+    pub histograms: DownloadHistograms,
+}
+
+/// Constant-size view of the download metrics, cheap enough to poll every second.
+#[derive(Debug, Clone, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadMetricsSummary {
+    pub total_success: u64,
+    pub total_failures: u64,
+    pub total_retries: u64,
+    /// Share of finished downloads (success or final failure) that succeeded, 0.0 - 1.0
+    pub success_rate: f64,
+    /// Running mean duration of successful attempts
+    pub average_success_duration_ms: u64,
+    pub attempts_last_minute: u64,
+    /// Downloads that ended, in success or final failure; retries of the
+    /// same download are not counted again
+    pub finished_last_minute: u64,
+    pub successes_last_minute: u64,
+    pub failures_last_minute: u64,
+    pub retries_last_minute: u64,
+    pub last_attempt: Option<DownloadAttemptSnapshot>,
+}
+
+const DEFAULT_RECENT_ATTEMPTS: usize = 20;
+/// Upper bound on retained attempts, whatever the configuration asks for
+pub const MAX_RECENT_ATTEMPTS: usize = 10_000;
+const RATE_WINDOW_SECS: u64 = 60;
+
+/// How much download attempt history is kept for `recent_attempts`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct AttemptRetention {
+    /// Maximum number of attempts kept (capped at `MAX_RECENT_ATTEMPTS`)
+    pub max_entries: usize,
+    /// Drop attempts older than this many seconds; `None` keeps them until
+    /// they are pushed out by `max_entries`
+    pub max_age_secs: Option<u64>,
+}
+
+impl Default for AttemptRetention {
+    fn default() -> Self {
+        Self {
+            max_entries: DEFAULT_RECENT_ATTEMPTS,
+            max_age_secs: None,
+        }
+    }
+}
+
+/// Per-second counters for one slot of the rolling rate window.
+#[derive(Debug, Default, Clone, Copy)]
+struct RateBucket {
+    second: u64,
+    successes: u64,
+    failures: u64,
+    retries: u64,
+}
+
+/// Fixed ring of per-second buckets covering the last `RATE_WINDOW_SECS`.
+/// Buckets are lazily reset when their slot is reused, so recording is O(1)
+/// and reading is bounded by the window size regardless of traffic.
+#[derive(Debug, Clone)]
+struct RollingRates {
+    buckets: [RateBucket; RATE_WINDOW_SECS as usize],
+}
+
+impl Default for RollingRates {
+    fn default() -> Self {
+        Self {
+            buckets: [RateBucket::default(); RATE_WINDOW_SECS as usize],
+        }
+    }
+}
+
+impl RollingRates {
+    fn record(&mut self, now_secs: u64, status: &AttemptStatus) {
+        let bucket = &mut self.buckets[(now_secs % RATE_WINDOW_SECS) as usize];
+        if bucket.second != now_secs {
+            *bucket = RateBucket {
+                second: now_secs,
+                ..RateBucket::default()
+            };
+        }
+        match status {
+            AttemptStatus::Retrying => bucket.retries += 1,
+            AttemptStatus::Success => bucket.successes += 1,
+            AttemptStatus::Failed => bucket.failures += 1,
+        }
+    }
+
+    fn window(&self, now_secs: u64) -> RateBucket {
+        self.buckets
+            .iter()
+            .filter(|b| b.second <= now_secs && now_secs - b.second < RATE_WINDOW_SECS)
+            .filter(|b| b.successes + b.failures + b.retries > 0)
+            .fold(RateBucket::default(), |mut acc, b| {
+                acc.successes += b.successes;
+                acc.failures += b.failures;
+                acc.retries += b.retries;
+                acc
+            })
+    }
+}
+
+/// What a successful download attempt delivered
+struct DeliveredFile {
+    size: u64,
+    /// Monotonic time the file data was in hand, before it was written out
+    first_byte_at: Duration,
+    /// Content hash the output must have, when it was not already checked
+    expected_hash: Option<String>,
+    /// What each remote provider served; empty for local copies
+    sources: Vec<ProviderStats>,
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Clone)]
 struct DownloadMetrics {
     total_success: u64,
     total_failures: u64,
     total_retries: u64,
+    success_duration_total_ms: u64,
     recent_attempts: VecDeque<DownloadAttemptSnapshot>,
+    retention: AttemptRetention,
+    rates: RollingRates,
+    attempt_duration: Histogram,
+    time_to_first_byte: Histogram,
+    file_size: Histogram,
+}
+
+impl Default for DownloadMetrics {
+    fn default() -> Self {
+        Self {
+            total_success: 0,
+            total_failures: 0,
+            total_retries: 0,
+            success_duration_total_ms: 0,
+            recent_attempts: VecDeque::new(),
+            retention: AttemptRetention::default(),
+            rates: RollingRates::default(),
+            attempt_duration: Histogram::new(LATENCY_BOUNDS_MS),
+            time_to_first_byte: Histogram::new(LATENCY_BOUNDS_MS),
+            file_size: Histogram::new(SIZE_BOUNDS_BYTES),
+        }
+    }
 }
 
 impl DownloadMetrics {
     fn record_attempt(&mut self, snapshot: DownloadAttemptSnapshot) {
+        self.attempt_duration.record(snapshot.duration_ms);
+        if let Some(ttfb) = snapshot.time_to_first_byte_ms {
+            self.time_to_first_byte.record(ttfb);
+        }
+        if let (AttemptStatus::Success, Some(size)) = (&snapshot.status, snapshot.file_size) {
+            self.file_size.record(size);
+        }
         match snapshot.status {
             AttemptStatus::Retrying => {
                 self.total_retries = self.total_retries.saturating_add(1);
             }
             AttemptStatus::Success => {
                 self.total_success = self.total_success.saturating_add(1);
+                self.success_duration_total_ms = self
+                    .success_duration_total_ms
+                    .saturating_add(snapshot.duration_ms);
             }
             AttemptStatus::Failed => {
                 self.total_failures = self.total_failures.saturating_add(1);
             }
         }
+        self.rates.record(snapshot.timestamp, &snapshot.status);
 
+        let now_secs = snapshot.timestamp;
         self.recent_attempts.push_front(snapshot);
-        while self.recent_attempts.len() > 20 {
-            self.recent_attempts.pop_back();
+        self.prune(now_secs);
+    }
+
+    /// Apply the retention policy; the history is newest-first so expired and
+    /// excess entries are all at the back.
+    fn prune(&mut self, now_secs: u64) {
+        let max_entries = self.retention.max_entries.min(MAX_RECENT_ATTEMPTS);
+        self.recent_attempts.truncate(max_entries);
+        if let Some(max_age) = self.retention.max_age_secs {
+            let cutoff = now_secs.saturating_sub(max_age);
+            while self
+                .recent_attempts
+                .back()
+                .is_some_and(|a| a.timestamp < cutoff)
+            {
+                self.recent_attempts.pop_back();
+            }
         }
     }
 
-    fn snapshot(&self) -> DownloadMetricsSnapshot {
+    fn set_retention(&mut self, retention: AttemptRetention, now_secs: u64) {
+        self.retention = retention;
+        self.prune(now_secs);
+    }
+
+    /// Drop the attempt history, keeping counters and rates; returns how many
+    /// attempts were removed.
+    fn clear_recent(&mut self) -> usize {
+        let removed = self.recent_attempts.len();
+        self.recent_attempts.clear();
+        removed
+    }
+
+    /// Newest-first view of at most `limit` recent attempts.
+    fn recent(&self, limit: usize) -> Vec<DownloadAttemptSnapshot> {
+        self.recent_attempts.iter().take(limit).cloned().collect()
+    }
+
+    fn summary_at(&self, now_secs: u64) -> DownloadMetricsSummary {
+        let finished = self.total_success + self.total_failures;
+        let window = self.rates.window(now_secs);
+        DownloadMetricsSummary {
+            total_success: self.total_success,
+            total_failures: self.total_failures,
+            total_retries: self.total_retries,
+            success_rate: if finished == 0 {
+                0.0
+            } else {
+                self.total_success as f64 / finished as f64
+            },
+            average_success_duration_ms: self
+                .success_duration_total_ms
+                .checked_div(self.total_success)
+                .unwrap_or(0),
+            attempts_last_minute: window.successes + window.failures + window.retries,
+            finished_last_minute: window.successes + window.failures,
+            successes_last_minute: window.successes,
+            failures_last_minute: window.failures,
+            retries_last_minute: window.retries,
+            last_attempt: self.recent_attempts.front().cloned(),
+        }
+    }
+
+    fn summary(&self) -> DownloadMetricsSummary {
+        let now_secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.summary_at(now_secs)
+    }
+
+    fn snapshot_with_limit(&self, limit: usize) -> DownloadMetricsSnapshot {
         DownloadMetricsSnapshot {
             total_success: self.total_success,
             total_failures: self.total_failures,
             total_retries: self.total_retries,
-            recent_attempts: self.recent_attempts.iter().cloned().collect(),
+            recent_attempts: self.recent(limit),
+            histograms: self.histograms(),
         }
     }
-}
 
-#[cfg(test)]
-use std::sync::atomic::{AtomicU32, Ordering};
+    fn histograms(&self) -> DownloadHistograms {
+        DownloadHistograms {
+            attempt_duration_ms: self.attempt_duration.snapshot(),
+            time_to_first_byte_ms: self.time_to_first_byte.snapshot(),
+            file_size_bytes: self.file_size.snapshot(),
+        }
+    }
 
-#[cfg(test)]
-static LAST_DOWNLOAD_ATTEMPTS: AtomicU32 = AtomicU32::new(0);
+    fn snapshot(&self) -> DownloadMetricsSnapshot {
+        self.snapshot_with_limit(self.recent_attempts.len())
+    }
+}
 
-#[cfg(test)]
-static FAIL_WRITE_BEFORE_SUCCESS: AtomicU32 = AtomicU32::new(0);
+/// Handles a download task needs, shared by every download the loop starts
+#[derive(Clone)]
+struct DownloadContext {
+    blobs: Arc<BlobStore>,
+    event_tx: mpsc::Sender<FileTransferEvent>,
+    download_metrics: Arc<Mutex<DownloadMetrics>>,
+    keystore: Arc<Mutex<crate::keystore::Keystore>>,
+    event_bus: Option<Arc<TransferEventBus>>,
+    chunk_fetcher: Arc<Mutex<Option<Arc<dyn ChunkFetcher>>>>,
+    retry_policy: Arc<Mutex<RetryPolicy>>,
+    downloads: Arc<std::sync::Mutex<DownloadTable>>,
+    batches: Arc<std::sync::Mutex<BatchTable>>,
+    scrub: Arc<std::sync::Mutex<ScrubState>>,
+    circuits: Arc<std::sync::Mutex<CircuitBreaker>>,
+    env: RuntimeEnv,
+}
 
 pub struct FileTransferService {
-    cmd_tx: mpsc::Sender<FileTransferCommand>,
+    cmd_tx: CommandSender<FileTransferCommand>,
     event_rx: Arc<Mutex<mpsc::Receiver<FileTransferEvent>>>,
     storage_dir: PathBuf,
+    blobs: Arc<BlobStore>,
     download_metrics: Arc<Mutex<DownloadMetrics>>,
     event_bus: Option<Arc<TransferEventBus>>,
+    chunk_fetcher: Arc<Mutex<Option<Arc<dyn ChunkFetcher>>>>,
+    retry_policy: Arc<Mutex<RetryPolicy>>,
+    hash_algorithm: Arc<Mutex<HashAlgorithm>>,
+    seeding: std::sync::Mutex<SeedingStats>,
+    /// Streaming downloads the HTTP gateway can serve before they finish
+    partials: Arc<std::sync::Mutex<PartialFiles>>,
+    /// File hashes new downloads are refused for after repeated failures
+    circuits: Arc<std::sync::Mutex<CircuitBreaker>>,
+    env: RuntimeEnv,
 }
 
 impl FileTransferService {
-    fn backoff_delay(attempt: u32) -> Duration {
-        if attempt <= 1 {
-            return Duration::from_millis(0);
-        }
-
-        let shift = (attempt - 1).min(4);
-        let multiplier = 1u64 << shift;
-        let delay = BASE_BACKOFF_MS.saturating_mul(multiplier);
-        Duration::from_millis(delay.min(MAX_BACKOFF_MS))
-    }
-
     async fn download_with_retries(
+        transfer_id: &str,
         file_hash: &str,
         output_path: &str,
-        storage_dir: &PathBuf,
+        blobs: &BlobStore,
+        remote: &RemoteSources,
         event_tx: mpsc::Sender<FileTransferEvent>,
         download_metrics: Arc<Mutex<DownloadMetrics>>,
         keystore: Arc<Mutex<crate::keystore::Keystore>>,
         active_account: Option<&str>,
         active_private_key: Option<&str>,
-    ) -> Result<(), String> {
+        policy: &RetryPolicy,
+        progress: &ProgressReporter,
+        cancel: &CancellationToken,
+        env: &RuntimeEnv,
+    ) -> Result<Vec<ProviderStats>, String> {
         let mut attempt = 0u32;
         let mut last_error: Option<String> = None;
 
-        while attempt < MAX_DOWNLOAD_ATTEMPTS {
+        while attempt < policy.max_attempts {
+            if cancel.is_cancelled() {
+                return Err(cancelled_error());
+            }
             attempt += 1;
             let span = info_span!(
                 "download_attempt",
                 module = "file_transfer",
+                transfer_id = %transfer_id,
                 hash = %file_hash,
                 attempt,
-                max_attempts = MAX_DOWNLOAD_ATTEMPTS
+                max_attempts = policy.max_attempts
             );
-            let start = Instant::now();
-
             if attempt > 1 {
-                let delay = Self::backoff_delay(attempt);
+                let delay = policy.backoff_delay(attempt, env.random.as_ref());
                 span.in_scope(|| debug!(?delay, "waiting before retry"));
                 if delay > Duration::from_millis(0) {
-                    sleep(delay).await;
+                    tokio::select! {
+                        _ = env.clock.sleep(delay) => {}
+                        _ = cancel.cancelled() => return Err(cancelled_error()),
+                    }
                 }
             }
 
+            let start = env.clock.monotonic();
+
             let result = {
                 let guard = span.enter();
                 let result = Self::handle_download_file(
+                    transfer_id,
                     file_hash,
                     output_path,
-                    storage_dir,
+                    blobs,
+                    remote,
+                    progress,
+                    cancel,
                     &keystore,
                     active_account,
                     active_private_key,
+                    env,
                 )
                 .await;
                 drop(guard); // Explicitly drop the guard
                 result
             };
 
+            // Re-hash what landed on disk before calling the attempt a
+            // success, then open it if it was encrypted for this account
+            let result = match result {
+                Ok(delivered) => {
+                    match Self::check_integrity(
+                        transfer_id,
+                        output_path,
+                        &delivered,
+                        attempt,
+                        &event_tx,
+                    )
+                    .await
+                    {
+                        Ok(()) => Self::open_for_recipient(output_path, active_private_key)
+                            .await
+                            .map(|()| delivered),
+                        Err(e) => Err(e),
+                    }
+                }
+                Err(e) => Err(e),
+            };
+
             match result {
-                Ok(()) => {
-                    let duration_ms = start.elapsed().as_millis() as u64;
-                    span.in_scope(|| info!(duration_ms = duration_ms, "download_succeeded"));
+                Ok(delivered) => {
+                    let duration_ms = (env.clock.monotonic() - start).as_millis() as u64;
+                    let ttfb_ms = (delivered.first_byte_at - start).as_millis() as u64;
+                    span.in_scope(|| {
+                        info!(
+                            duration_ms = duration_ms,
+                            ttfb_ms = ttfb_ms,
+                            bytes = delivered.size,
+                            "download_succeeded"
+                        )
+                    });
                     let snapshot = DownloadAttemptSnapshot {
+                        transfer_id: transfer_id.to_string(),
                         file_hash: file_hash.to_string(),
                         attempt,
-                        max_attempts: MAX_DOWNLOAD_ATTEMPTS,
+                        max_attempts: policy.max_attempts,
                         status: AttemptStatus::Success,
                         duration_ms,
-                        timestamp: SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap_or_default()
-                            .as_secs(),
+                        time_to_first_byte_ms: Some(ttfb_ms),
+                        file_size: Some(delivered.size),
+                        timestamp: env.clock.now_secs(),
                     };
+                    progress.finish();
                     Self::emit_attempt(event_tx.clone(), download_metrics.clone(), snapshot).await;
-                    #[cfg(test)]
-                    {
-                        LAST_DOWNLOAD_ATTEMPTS.store(attempt, Ordering::SeqCst);
-                    }
-                    return Ok(());
+                    return Ok(delivered.sources);
+                }
+                Err(_) if cancel.is_cancelled() => {
+                    span.in_scope(|| info!("download_cancelled"));
+                    return Err(cancelled_error());
                 }
                 Err(err) => {
-                    let duration_ms = start.elapsed().as_millis() as u64;
+                    let duration_ms = (env.clock.monotonic() - start).as_millis() as u64;
                     span.in_scope(|| warn!(duration_ms = duration_ms, %err, "download_failed"));
                     last_error = Some(err.clone());
 
-                    let status = if attempt >= MAX_DOWNLOAD_ATTEMPTS {
-                        AttemptStatus::Failed
-                    } else {
+                    let retry = policy.should_retry(attempt, &err);
+                    let status = if retry {
                         AttemptStatus::Retrying
+                    } else {
+                        AttemptStatus::Failed
                     };
 
                     let snapshot = DownloadAttemptSnapshot {
+                        transfer_id: transfer_id.to_string(),
                         file_hash: file_hash.to_string(),
                         attempt,
-                        max_attempts: MAX_DOWNLOAD_ATTEMPTS,
+                        max_attempts: policy.max_attempts,
                         status,
                         duration_ms,
-                        timestamp: SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap_or_default()
-                            .as_secs(),
+                        time_to_first_byte_ms: None,
+                        file_size: None,
+                        timestamp: env.clock.now_secs(),
                     };
                     Self::emit_attempt(event_tx.clone(), download_metrics.clone(), snapshot).await;
 
-                    if attempt >= MAX_DOWNLOAD_ATTEMPTS {
-                        #[cfg(test)]
-                        {
-                            LAST_DOWNLOAD_ATTEMPTS.store(attempt, Ordering::SeqCst);
-                        }
+                    if !retry {
                         return Err(err);
                     }
                 }
@@ -281,19 +927,142 @@ impl FileTransferService {
         Err(last_error.unwrap_or_else(|| "Download failed".to_string()))
     }
 
-    async fn write_output(output_path: &str, data: &[u8]) -> Result<(), String> {
-        #[cfg(test)]
+    /// Compare the written output against the hash it was requested by. A
+    /// mismatch removes the output and reports `IntegrityFailed`; the error
+    /// returned is a verification failure, which the retry loop treats like
+    /// any other failed attempt.
+    async fn check_integrity(
+        transfer_id: &str,
+        output_path: &str,
+        delivered: &DeliveredFile,
+        attempt: u32,
+        event_tx: &mpsc::Sender<FileTransferEvent>,
+    ) -> Result<(), String> {
+        let Some(expected) = delivered.expected_hash.as_deref() else {
+            return Ok(());
+        };
+        // Content hashes and merkle roots can be checked, other ids can't
+        let Some(content_hash) = ContentHash::parse(expected) else {
+            return Ok(());
+        };
+        // A 64-hex id may be either, so a match on the root also counts
+        if crate::download_staging::matches_published_id(Path::new(output_path), expected)
+            .instrument(trace_span!(target: PROFILE_TARGET, "hash_output"))
+            .await?
         {
-            let remaining = FAIL_WRITE_BEFORE_SUCCESS.load(Ordering::SeqCst);
-            if remaining > 0 {
-                FAIL_WRITE_BEFORE_SUCCESS.fetch_sub(1, Ordering::SeqCst);
-                return Err("simulated write failure".to_string());
+            return Ok(());
+        }
+        let actual = content_hash
+            .algorithm
+            .hash_file(Path::new(output_path))
+            .await?
+            .to_string();
+
+        warn!(%transfer_id, %expected, %actual, "written file does not match its content hash");
+        let _ = tokio::fs::remove_file(output_path).await;
+        let _ = event_tx
+            .send(FileTransferEvent::IntegrityFailed {
+                transfer_id: transfer_id.to_string(),
+                expected_hash: expected.to_string(),
+                actual_hash: actual.clone(),
+                attempt,
+            })
+            .await;
+        Err(ServiceError::new(
+            ErrorCode::Verification,
+            format!("Written file hashes to {}, expected {}", actual, expected),
+        )
+        .into())
+    }
+
+    /// Replace a downloaded recipient envelope with its contents, decrypted
+    /// with the account key. Other files are left as they are.
+    async fn open_for_recipient(
+        output_path: &str,
+        active_private_key: Option<&str>,
+    ) -> Result<(), String> {
+        let data = tokio::fs::read(output_path).await.map_err(io_error(
+            ErrorCode::ReadFailed,
+            "Failed to read downloaded file",
+        ))?;
+        if !recipient::is_envelope(&data) {
+            return Ok(());
+        }
+        let private_key = active_private_key.ok_or_else(|| {
+            ServiceError::new(
+                ErrorCode::Unauthorized,
+                "File is encrypted for a recipient; log in to decrypt it",
+            )
+            .to_string()
+        })?;
+        let plaintext = recipient::open(&data, private_key)
+            .map_err(|e| ServiceError::new(ErrorCode::Unauthorized, e).to_string())?;
+        tokio::fs::write(output_path, plaintext)
+            .await
+            .map_err(io_error(
+                ErrorCode::WriteFailed,
+                "Failed to write decrypted file",
+            ))
+    }
+
+    /// Fail with `DiskFull` before writing anything if the volume holding
+    /// `output_path` cannot take a `file_size` byte file. Chunks an earlier
+    /// attempt already wrote to the part file are not counted again.
+    async fn ensure_disk_space(
+        env: &RuntimeEnv,
+        storage_dir: &Path,
+        file_hash: &str,
+        output_path: &str,
+        file_size: u64,
+    ) -> Result<(), String> {
+        let written = ResumeStore::in_storage_dir(storage_dir)
+            .load(file_hash, output_path)
+            .await
+            .filter(|record| record.file_size == file_size)
+            .map_or(0, |record| record.received_bytes());
+        let needed = file_size.saturating_sub(written);
+        // The output directory may not exist yet; measure the volume of the
+        // nearest one that does
+        let Some(dir) = Path::new(output_path)
+            .ancestors()
+            .skip(1)
+            .map(|dir| {
+                if dir.as_os_str().is_empty() {
+                    Path::new(".")
+                } else {
+                    dir
+                }
+            })
+            .find(|dir| dir.exists())
+        else {
+            return Ok(());
+        };
+        let available = match env.fs.available_space(dir).await {
+            Ok(available) => available,
+            Err(e) => {
+                debug!(dir = %dir.display(), "could not check free disk space: {}", e);
+                return Ok(());
             }
+        };
+        if available >= needed {
+            return Ok(());
         }
+        Err(ServiceError::new(
+            ErrorCode::DiskFull,
+            format!(
+                "Not enough disk space for {}: {} bytes needed, {} available",
+                output_path, needed, available
+            ),
+        )
+        .into())
+    }
 
-        tokio::fs::write(output_path, data)
+    async fn write_output(env: &RuntimeEnv, output_path: &str, data: &[u8]) -> Result<(), String> {
+        env.fs
+            .write(Path::new(output_path), data)
+            .instrument(trace_span!(target: PROFILE_TARGET, "disk_write", bytes = data.len()))
             .await
-            .map_err(|e| format!("Failed to write file: {}", e))
+            .map_err(io_error(ErrorCode::WriteFailed, "Failed to write file"))
     }
 
     async fn emit_attempt(
@@ -314,47 +1083,120 @@ impl FileTransferService {
         }
     }
 
-    #[cfg(test)]
-    pub(crate) fn reset_retry_counters() {
-        LAST_DOWNLOAD_ATTEMPTS.store(0, Ordering::SeqCst);
-        FAIL_WRITE_BEFORE_SUCCESS.store(0, Ordering::SeqCst);
+    pub async fn new_with_encryption_and_keystore(
+        encryption_enabled: bool,
+        keystore: Arc<Mutex<crate::keystore::Keystore>>,
+    ) -> Result<Self, String> {
+        Self::new_with_storage_dir(Self::get_storage_dir()?, encryption_enabled, keystore, None).await
     }
 
-    #[cfg(test)]
-    pub(crate) fn set_fail_write_attempts(count: u32) {
-        FAIL_WRITE_BEFORE_SUCCESS.store(count, Ordering::SeqCst);
+    /// Create with custom storage directory, encryption, keystore, and optional AppHandle
+    pub async fn new_with_storage_dir(
+        storage_dir: PathBuf,
+        encryption_enabled: bool,
+        keystore: Arc<Mutex<crate::keystore::Keystore>>,
+        app_handle: Option<AppHandle>,
+    ) -> Result<Self, String> {
+        Self::new_with_env(
+            storage_dir,
+            encryption_enabled,
+            keystore,
+            app_handle,
+            RuntimeEnv::system(),
+        )
+        .await
     }
 
-    #[cfg(test)]
-    pub(crate) fn last_attempts() -> u32 {
-        LAST_DOWNLOAD_ATTEMPTS.load(Ordering::SeqCst)
+    /// Create with an explicit clock, RNG and filesystem, e.g. a deterministic
+    /// `RuntimeEnv` in tests
+    pub async fn new_with_env(
+        storage_dir: PathBuf,
+        encryption_enabled: bool,
+        keystore: Arc<Mutex<crate::keystore::Keystore>>,
+        app_handle: Option<AppHandle>,
+        env: RuntimeEnv,
+    ) -> Result<Self, String> {
+        Self::new_with_retry_policy(
+            storage_dir,
+            encryption_enabled,
+            keystore,
+            app_handle,
+            env,
+            RetryPolicy::default(),
+        )
+        .await
     }
 
-    pub async fn new_with_encryption_and_keystore(
+    /// Create with the retry policy downloads use unless their command
+    /// brings one
+    pub async fn new_with_retry_policy(
+        storage_dir: PathBuf,
         encryption_enabled: bool,
         keystore: Arc<Mutex<crate::keystore::Keystore>>,
+        app_handle: Option<AppHandle>,
+        env: RuntimeEnv,
+        retry_policy: RetryPolicy,
     ) -> Result<Self, String> {
-        Self::new_with_storage_dir(Self::get_storage_dir()?, encryption_enabled, keystore, None).await
+        Self::new_with_command_queue(
+            storage_dir,
+            encryption_enabled,
+            keystore,
+            app_handle,
+            env,
+            retry_policy,
+            CommandQueueConfig::default(),
+        )
+        .await
     }
 
-    /// Create with custom storage directory, encryption, keystore, and optional AppHandle
-    pub async fn new_with_storage_dir(
+    /// Create with a command queue of `queue.capacity` commands. When it is
+    /// full, sends wait or fail with a busy error as `queue.overflow` says.
+    pub async fn new_with_command_queue(
         storage_dir: PathBuf,
         encryption_enabled: bool,
         keystore: Arc<Mutex<crate::keystore::Keystore>>,
         app_handle: Option<AppHandle>,
+        env: RuntimeEnv,
+        retry_policy: RetryPolicy,
+        queue: CommandQueueConfig,
     ) -> Result<Self, String> {
+        retry_policy.validate()?;
+        queue.validate()?;
+        // Opening the blob store creates the storage directory if needed
+        let blobs = Arc::new(BlobStore::open_with_env(&storage_dir, &env).await?);
 
-        // Create storage directory if it doesn't exist
-        if !storage_dir.exists() {
-            tokio::fs::create_dir_all(&storage_dir)
-                .await
-                .map_err(|e| format!("Failed to create storage directory: {}", e))?;
-        }
-
-        let (cmd_tx, cmd_rx) = mpsc::channel(100);
+        let (cmd_tx, cmd_rx) = command_queue(queue);
+        let (service_event_tx, mut service_event_rx) = mpsc::channel(100);
         let (event_tx, event_rx) = mpsc::channel(100);
         let download_metrics = Arc::new(Mutex::new(DownloadMetrics::default()));
+        let chunk_fetcher = Arc::new(Mutex::new(None));
+        let retry_policy = Arc::new(Mutex::new(retry_policy));
+        let hash_algorithm = Arc::new(Mutex::new(HashAlgorithm::default()));
+        let partials = Arc::new(std::sync::Mutex::new(PartialFiles::default()));
+        let circuits = Arc::new(std::sync::Mutex::new(CircuitBreaker::default()));
+
+        // Mirror every service event onto the unified event bus and push it
+        // to the webview before handing it to the local consumer. The webview
+        // is the main consumer when there is one, so the local queue then
+        // drops events when full instead of holding the service up.
+        let webview = app_handle.clone();
+        let event_partials = partials.clone();
+        tokio::spawn(async move {
+            while let Some(event) = service_event_rx.recv().await {
+                event_partials.lock().unwrap().observe(&event);
+                crate::event_bus::global().publish_file_transfer(&event);
+                let Some(app) = &webview else {
+                    if event_tx.send(event).await.is_err() {
+                        break;
+                    }
+                    continue;
+                };
+                event.emit(app);
+                if let Err(mpsc::error::TrySendError::Closed(_)) = event_tx.try_send(event) {
+                    break;
+                }
+            }
+        });
 
         // Create TransferEventBus if app_handle is provided
         let event_bus = app_handle.map(|handle| Arc::new(TransferEventBus::new(handle)));
@@ -362,20 +1204,81 @@ impl FileTransferService {
         // Spawn the file transfer service task
         tokio::spawn(Self::run_file_transfer_service(
             cmd_rx,
-            event_tx,
-            storage_dir.clone(),
+            service_event_tx,
+            blobs.clone(),
             download_metrics.clone(),
             encryption_enabled,
             keystore.clone(),
             event_bus.clone(),
+            chunk_fetcher.clone(),
+            retry_policy.clone(),
+            hash_algorithm.clone(),
+            circuits.clone(),
+            env.clone(),
         ));
 
+        // Garbage collect expired files, run scheduled scrubs and age out
+        // download attempts until the service is dropped. Attempts are also
+        // pruned when recorded, but an idle node records none.
+        let gc_tx = cmd_tx.downgrade();
+        let gc_metrics = download_metrics.clone();
+        let gc_clock = env.clock.clone();
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(GC_INTERVAL);
+            'ticks: loop {
+                ticks.tick().await;
+                let Some(cmd_tx) = gc_tx.upgrade() else {
+                    break;
+                };
+                gc_metrics.lock().await.prune(gc_clock.now_secs());
+                for cmd in [
+                    FileTransferCommand::CollectExpired,
+                    FileTransferCommand::ScheduledScrub,
+                ] {
+                    if cmd_tx.send_with(cmd, OverflowPolicy::Block).await.is_err() {
+                        break 'ticks;
+                    }
+                }
+            }
+        });
+
+        // Pick up downloads interrupted by a crash or restart
+        for record in ResumeStore::in_storage_dir(&storage_dir).pending().await {
+            info!(
+                transfer_id = %record.transfer_id,
+                hash = %record.file_hash,
+                done = record.received_count(),
+                total = record.chunk_count,
+                "re-queuing interrupted download"
+            );
+            let resumed = FileTransferCommand::DownloadFile {
+                transfer_id: record.transfer_id,
+                file_hash: record.file_hash,
+                output_path: record.output_path,
+                providers: record.providers,
+                retry_policy: None,
+                priority: TransferPriority::Normal,
+                streaming: false,
+                active_account: None,
+                active_private_key: None,
+            };
+            let _ = cmd_tx.send_with(resumed, OverflowPolicy::Block).await;
+        }
+
         Ok(FileTransferService {
             cmd_tx,
             event_rx: Arc::new(Mutex::new(event_rx)),
             storage_dir,
+            blobs,
             download_metrics,
             event_bus,
+            chunk_fetcher,
+            retry_policy,
+            hash_algorithm,
+            seeding: Default::default(),
+            partials,
+            circuits,
+            env,
         })
     }
 
@@ -410,167 +1313,1021 @@ impl FileTransferService {
     async fn run_file_transfer_service(
         mut cmd_rx: mpsc::Receiver<FileTransferCommand>,
         event_tx: mpsc::Sender<FileTransferEvent>,
-        storage_dir: PathBuf,
+        blobs: Arc<BlobStore>,
         download_metrics: Arc<Mutex<DownloadMetrics>>,
         encryption_enabled: bool,
         keystore: Arc<Mutex<crate::keystore::Keystore>>,
         event_bus: Option<Arc<TransferEventBus>>,
+        chunk_fetcher: Arc<Mutex<Option<Arc<dyn ChunkFetcher>>>>,
+        retry_policy: Arc<Mutex<RetryPolicy>>,
+        hash_algorithm: Arc<Mutex<HashAlgorithm>>,
+        circuits: Arc<std::sync::Mutex<CircuitBreaker>>,
+        env: RuntimeEnv,
     ) {
+        let ctx = DownloadContext {
+            blobs: blobs.clone(),
+            event_tx: event_tx.clone(),
+            download_metrics,
+            keystore: keystore.clone(),
+            event_bus,
+            chunk_fetcher,
+            retry_policy,
+            downloads: Arc::default(),
+            batches: Arc::default(),
+            scrub: Arc::default(),
+            circuits,
+            env: env.clone(),
+        };
+
         while let Some(cmd) = cmd_rx.recv().await {
             match cmd {
                 FileTransferCommand::UploadFile {
+                    transfer_id,
                     file_path,
                     file_name,
+                    recipient_public_key,
+                    expires_at,
+                    in_place,
                     active_account,
                     active_private_key,
-                } => match Self::handle_upload_file(
-                    &file_path,
-                    &file_name,
-                    &storage_dir,
-                    encryption_enabled,
-                    None,
-                    &keystore,
-                    active_account.as_deref(),
-                    active_private_key.as_deref(),
-                )
-                .await
-                {
-                    Ok((file_hash, _encrypted_metadata)) => {
-                        let _ = event_tx
-                            .send(FileTransferEvent::FileUploaded {
-                                file_hash: file_hash.clone(),
-                                file_name: file_name.clone(),
-                            })
-                            .await;
-                    }
-                    Err(e) => {
-                        let error_msg = format!("Upload failed: {}", e);
-                        let _ = event_tx
-                            .send(FileTransferEvent::Error {
-                                message: error_msg.clone(),
-                            })
+                    reply,
+                } => {
+                    let progress = ProgressReporter::new(
+                        &transfer_id,
+                        TransferDirection::Upload,
+                        event_tx.clone(),
+                        env.clock.clone(),
+                    );
+                    let algorithm = *hash_algorithm.lock().await;
+                    let upload = async {
+                        if !in_place {
+                            return Self::handle_upload_file(
+                                &file_path,
+                                &file_name,
+                                &blobs,
+                                &progress,
+                                algorithm,
+                                encryption_enabled,
+                                recipient_public_key.as_deref(),
+                                &keystore,
+                                active_account.as_deref(),
+                                active_private_key.as_deref(),
+                            )
                             .await;
-                        error!("File upload failed: {}", error_msg);
+                        }
+                        if encryption_enabled || recipient_public_key.is_some() {
+                            return Err(String::from(ServiceError::new(
+                                ErrorCode::InvalidInput,
+                                "Encrypted files can't be shared in place",
+                            )));
+                        }
+                        Self::handle_import_file(
+                            &file_path, &file_name, &blobs, &progress, algorithm,
+                        )
+                        .await
+                        .map(|hash| (hash, None))
+                    };
+                    match upload
+                        .instrument(info_span!(
+                            "upload",
+                            module = "file_transfer",
+                            transfer_id = %transfer_id,
+                            file = %file_name
+                        ))
+                        .await
+                    {
+                        Ok((file_hash, _encrypted_metadata)) => {
+                            progress.finish();
+                            if let Some(expires_at) = expires_at {
+                                if let Err(e) = blobs.set_expiry(&file_hash, Some(expires_at)).await
+                                {
+                                    warn!(hash = %file_hash, "failed to set file expiry: {}", e);
+                                }
+                            }
+                            let _ = event_tx
+                                .send(FileTransferEvent::FileUploaded {
+                                    transfer_id,
+                                    file_hash: file_hash.clone(),
+                                    file_name: file_name.clone(),
+                                    file_size: progress.snapshot().bytes_done,
+                                })
+                                .await;
+                            if let Some(reply) = reply {
+                                let _ = reply.send(Ok(file_hash));
+                            }
+                        }
+                        Err(e) => {
+                            let error_msg = format!("Upload failed: {}", e);
+                            let code = ErrorCode::classify(&e);
+                            let _ = event_tx
+                                .send(FileTransferEvent::Error {
+                                    transfer_id: transfer_id.clone(),
+                                    direction: TransferDirection::Upload,
+                                    code,
+                                    message: error_msg.clone(),
+                                })
+                                .await;
+                            error!(%transfer_id, "File upload failed: {}", error_msg);
+                            if let Some(reply) = reply {
+                                let _ = reply.send(Err(ServiceError::new(code, error_msg).into()));
+                            }
+                        }
                     }
-                },
-                FileTransferCommand::DownloadFile {
-                    file_hash,
-                    output_path,
+                }
+                FileTransferCommand::UploadDirectory {
+                    transfer_id,
+                    dir_path,
                     active_account,
                     active_private_key,
                 } => {
-                    let start_time = current_timestamp_ms();
-
-                    // Emit started event via TransferEventBus
-                    if let Some(ref bus) = event_bus {
-                        bus.emit_started(TransferStartedEvent {
-                            transfer_id: file_hash.clone(),
-                            file_hash: file_hash.clone(),
-                            file_name: output_path.clone(),
-                            file_size: 0, // Unknown at this point
-                            total_chunks: 0,
-                            chunk_size: 0,
-                            started_at: start_time,
-                            available_sources: vec![SourceInfo {
-                                id: "local-storage".to_string(),
-                                source_type: SourceType::P2p,
-                                address: "local".to_string(),
-                                reputation: Some(1.0),
-                                estimated_speed_bps: None,
-                                latency_ms: None,
-                                location: None,
-                            }],
-                            selected_sources: vec!["local-storage".to_string()],
-                        });
-                    }
-
-                    match Self::download_with_retries(
-                        &file_hash,
-                        &output_path,
-                        &storage_dir,
+                    let progress = ProgressReporter::new(
+                        &transfer_id,
+                        TransferDirection::Upload,
                         event_tx.clone(),
-                        download_metrics.clone(),
-                        keystore.clone(),
+                        env.clock.clone(),
+                    );
+                    let algorithm = *hash_algorithm.lock().await;
+                    match Self::handle_upload_directory(
+                        &dir_path,
+                        &blobs,
+                        &progress,
+                        &env,
+                        algorithm,
+                        encryption_enabled,
+                        &keystore,
                         active_account.as_deref(),
                         active_private_key.as_deref(),
                     )
+                    .instrument(info_span!(
+                        "upload_directory",
+                        module = "file_transfer",
+                        transfer_id = %transfer_id,
+                        dir = %dir_path
+                    ))
                     .await
                     {
-                        Ok(()) => {
+                        Ok((manifest_hash, folder)) => {
+                            progress.finish();
                             let _ = event_tx
-                                .send(FileTransferEvent::FileDownloaded {
-                                    file_path: output_path.clone(),
+                                .send(FileTransferEvent::FolderUploaded {
+                                    transfer_id,
+                                    manifest_hash,
+                                    file_count: folder.manifest.entries.len(),
+                                    name: folder.manifest.name,
                                 })
                                 .await;
-
-                            // Emit completed event via TransferEventBus
-                            if let Some(ref bus) = event_bus {
-                                let end_time = current_timestamp_ms();
-                                let duration_secs = (end_time - start_time) / 1000;
-                                bus.emit_completed(TransferCompletedEvent {
-                                    transfer_id: file_hash.clone(),
-                                    file_hash: file_hash.clone(),
-                                    file_name: output_path.clone(),
-                                    file_size: 0, // Would need to track actual size
-                                    output_path: output_path.clone(),
-                                    completed_at: end_time,
-                                    duration_seconds: duration_secs,
-                                    average_speed_bps: 0.0,
-                                    total_chunks: 0,
-                                    sources_used: vec![SourceSummary {
-                                        source_id: "local-storage".to_string(),
-                                        source_type: SourceType::P2p,
-                                        chunks_provided: 1,
-                                        bytes_provided: 0,
-                                        average_speed_bps: 0.0,
-                                        connection_duration_seconds: duration_secs,
-                                    }],
-                                });
-                            }
-
-                            info!(
-                                "File downloaded successfully: {} -> {}",
-                                file_hash, output_path
-                            );
                         }
                         Err(e) => {
-                            let error_msg = format!("Download failed: {}", e);
+                            let error_msg = format!("Folder upload failed: {}", e);
                             let _ = event_tx
                                 .send(FileTransferEvent::Error {
+                                    transfer_id: transfer_id.clone(),
+                                    direction: TransferDirection::Upload,
+                                    code: ErrorCode::classify(&e),
                                     message: error_msg.clone(),
                                 })
                                 .await;
-
-                            // Emit failed event via TransferEventBus
-                            if let Some(ref bus) = event_bus {
-                                bus.emit_failed(TransferFailedEvent {
-                                    transfer_id: file_hash.clone(),
-                                    file_hash: file_hash.clone(),
-                                    failed_at: current_timestamp_ms(),
-                                    error: error_msg.clone(),
-                                    error_category: ErrorCategory::Unknown,
-                                    downloaded_bytes: 0,
-                                    total_bytes: 0,
-                                    retry_possible: true,
-                                });
-                            }
-
-                            error!("File download failed: {}", error_msg);
+                            error!(%transfer_id, "Folder upload failed: {}", error_msg);
+                        }
+                    }
+                }
+                FileTransferCommand::DownloadFile {
+                    transfer_id,
+                    file_hash,
+                    output_path,
+                    providers,
+                    retry_policy,
+                    priority,
+                    streaming,
+                    active_account,
+                    active_private_key,
+                } => {
+                    let job = DownloadJob {
+                        transfer_id,
+                        file_hash,
+                        output_path,
+                        providers,
+                        retry_policy,
+                        priority,
+                        streaming,
+                        folder_root: None,
+                        active_account,
+                        active_private_key,
+                    };
+                    Self::enqueue_download(&ctx, job, false).await;
+                }
+                FileTransferCommand::DownloadDirectory {
+                    transfer_id,
+                    manifest_hash,
+                    output_dir,
+                    providers,
+                    retry_policy,
+                    priority,
+                    active_account,
+                    active_private_key,
+                } => {
+                    if let Err(e) = tokio::fs::create_dir_all(&output_dir).await {
+                        let _ = event_tx
+                            .send(FileTransferEvent::Error {
+                                transfer_id,
+                                direction: TransferDirection::Download,
+                                code: ErrorCode::WriteFailed,
+                                message: format!("Failed to create {}: {}", output_dir, e),
+                            })
+                            .await;
+                        continue;
+                    }
+                    // The manifest is fetched like any file, next to where
+                    // the folder's files will go
+                    let manifest_path =
+                        Path::new(&output_dir).join(format!(".{}.folder", transfer_id));
+                    let job = DownloadJob {
+                        transfer_id,
+                        file_hash: manifest_hash,
+                        output_path: manifest_path.to_string_lossy().to_string(),
+                        providers,
+                        retry_policy,
+                        priority,
+                        streaming: false,
+                        folder_root: Some(output_dir),
+                        active_account,
+                        active_private_key,
+                    };
+                    Self::enqueue_download(&ctx, job, false).await;
+                }
+                FileTransferCommand::DownloadBatch {
+                    batch_id,
+                    items,
+                    providers,
+                    retry_policy,
+                    priority,
+                    active_private_key,
+                } => {
+                    let jobs: Vec<DownloadJob> = items
+                        .into_iter()
+                        .enumerate()
+                        .map(|(index, item)| DownloadJob {
+                            transfer_id: batch::member_id(&batch_id, index),
+                            file_hash: item.file_hash,
+                            output_path: item.output_path,
+                            providers: providers.clone(),
+                            retry_policy: retry_policy.clone(),
+                            priority,
+                            streaming: false,
+                            folder_root: None,
+                            active_account: None,
+                            active_private_key: active_private_key.clone(),
+                        })
+                        .collect();
+                    let members: Vec<(String, String)> = jobs
+                        .iter()
+                        .map(|job| (job.transfer_id.clone(), job.file_hash.clone()))
+                        .collect();
+                    ctx.batches.lock().unwrap().insert(&batch_id, &members);
+                    info!(%batch_id, files = jobs.len(), "queueing batch download");
+                    for job in jobs {
+                        Self::enqueue_download(&ctx, job, false).await;
+                    }
+                }
+                FileTransferCommand::SetPriority {
+                    transfer_id,
+                    priority,
+                } => {
+                    let position = {
+                        let mut downloads = ctx.downloads.lock().unwrap();
+                        downloads
+                            .set_priority(&transfer_id, priority)
+                            .then(|| downloads.queue_position(&transfer_id))
+                            .flatten()
+                    };
+                    match position {
+                        Some(position) => {
+                            let _ = ctx
+                                .event_tx
+                                .send(FileTransferEvent::Queued {
+                                    transfer_id,
+                                    position,
+                                })
+                                .await;
                         }
+                        None => warn!(%transfer_id, "no queued download to reprioritise"),
                     }
                 }
+                FileTransferCommand::SetMaxConcurrentTransfers { limit } => {
+                    info!(limit, "setting max concurrent downloads");
+                    ctx.downloads.lock().unwrap().set_max_concurrent(limit);
+                    Self::start_ready(&ctx);
+                }
+                FileTransferCommand::PauseTransfer { transfer_id } => {
+                    let stopping = ctx
+                        .downloads
+                        .lock()
+                        .unwrap()
+                        .request_stop(&transfer_id, StopRequest::Pause);
+                    if stopping {
+                        info!(%transfer_id, "pausing download");
+                    } else {
+                        warn!(%transfer_id, "no running download to pause");
+                    }
+                }
+                FileTransferCommand::ResumeTransfer { transfer_id } => {
+                    let paused = ctx.downloads.lock().unwrap().take_paused(&transfer_id);
+                    let Some(paused) = paused else {
+                        warn!(%transfer_id, "no paused download to resume");
+                        continue;
+                    };
+                    info!(%transfer_id, "resuming download");
+                    let _ = ctx
+                        .event_tx
+                        .send(FileTransferEvent::Resumed {
+                            transfer_id: transfer_id.clone(),
+                        })
+                        .await;
+                    if let Some(ref bus) = ctx.event_bus {
+                        bus.emit_resumed(TransferResumedEvent {
+                            transfer_id: transfer_id.clone(),
+                            resumed_at: current_timestamp_ms(),
+                            downloaded_bytes: paused.progress.bytes_done,
+                            remaining_bytes: paused
+                                .progress
+                                .total_bytes
+                                .saturating_sub(paused.progress.bytes_done),
+                            active_sources: paused.job.providers.len().max(1),
+                        });
+                    }
+                    Self::enqueue_download(&ctx, paused.job, true).await;
+                }
+                FileTransferCommand::CancelTransfer { transfer_id } => {
+                    let stopping = ctx
+                        .downloads
+                        .lock()
+                        .unwrap()
+                        .request_stop(&transfer_id, StopRequest::Cancel);
+                    if stopping {
+                        info!(%transfer_id, "cancelling download");
+                        continue;
+                    }
+                    // A paused or queued download has no task to stop; drop it
+                    // here
+                    let (paused, queued) = {
+                        let mut downloads = ctx.downloads.lock().unwrap();
+                        (
+                            downloads.take_paused(&transfer_id),
+                            downloads.dequeue(&transfer_id),
+                        )
+                    };
+                    let (job, done) = match (paused, queued) {
+                        (Some(paused), _) => (paused.job, paused.progress),
+                        (None, Some(job)) => {
+                            let progress = ProgressReporter::silent(
+                                &transfer_id,
+                                TransferDirection::Download,
+                                ctx.env.clock.clone(),
+                            );
+                            (job, progress.snapshot())
+                        }
+                        (None, None) => {
+                            warn!(%transfer_id, "no download to cancel");
+                            continue;
+                        }
+                    };
+                    ResumeStore::in_storage_dir(ctx.blobs.root())
+                        .discard(&job.file_hash, &job.output_path)
+                        .await;
+                    Self::report_cancelled(&ctx, &transfer_id, &done).await;
+                }
                 FileTransferCommand::GetStoredFiles => {
                     // This could be used to list available files
                     debug!("GetStoredFiles command received");
                 }
+                FileTransferCommand::RemoveFile { file_hash } => {
+                    Self::remove_stored_file(&ctx, &file_hash).await;
+                }
+                FileTransferCommand::TrimStorage { max_bytes, reply } => {
+                    let _ = reply.send(Self::trim(&ctx, max_bytes).await);
+                }
+                FileTransferCommand::CollectExpired => {
+                    Self::collect_expired(&ctx, env.clock.now_secs()).await;
+                }
+                FileTransferCommand::VerifyStorage { repair, reply } => {
+                    Self::start_scrub(&ctx, repair, reply);
+                }
+                FileTransferCommand::ScheduledScrub => {
+                    let due = ctx.scrub.lock().unwrap().due(env.clock.now_secs());
+                    if let Some(repair) = due {
+                        Self::start_scrub(&ctx, repair, None);
+                    }
+                }
+                FileTransferCommand::SetScrubSchedule { schedule } => {
+                    ctx.scrub.lock().unwrap().set_schedule(schedule);
+                }
+            }
+        }
+    }
+
+    /// Scrub storage in the background unless a scrub is already running.
+    /// The report goes out as a `StorageScrubbed` event and to `reply`.
+    fn start_scrub(
+        ctx: &DownloadContext,
+        repair: bool,
+        reply: Option<oneshot::Sender<Result<ScrubReport, String>>>,
+    ) {
+        let started_at = ctx.env.clock.now_secs();
+        if !ctx.scrub.lock().unwrap().start(started_at) {
+            if let Some(reply) = reply {
+                let _ = reply.send(Err(String::from(ServiceError::new(
+                    ErrorCode::InvalidInput,
+                    "A storage scrub is already running",
+                ))));
+            }
+            return;
+        }
+        let ctx = ctx.clone();
+        tokio::spawn(
+            async move {
+                let report = Self::scrub_storage(&ctx, repair, started_at).await;
+                ctx.scrub.lock().unwrap().finish();
+                info!(
+                    checked = report.checked,
+                    corrupted = report.corrupted.len(),
+                    "storage scrub finished"
+                );
+                let _ = ctx
+                    .event_tx
+                    .send(FileTransferEvent::StorageScrubbed(report.clone()))
+                    .await;
+                if let Some(reply) = reply {
+                    let _ = reply.send(Ok(report));
+                }
+            }
+            .instrument(info_span!("scrub", module = "file_transfer", repair)),
+        );
+    }
+
+    async fn scrub_storage(ctx: &DownloadContext, repair: bool, started_at: u64) -> ScrubReport {
+        let mut report = ScrubReport {
+            started_at,
+            ..ScrubReport::default()
+        };
+        for entry in ctx.blobs.list().await {
+            let Some(expected) = scrub::expected_hash(&ctx.blobs, &entry) else {
+                report.skipped += 1;
+                continue;
+            };
+            let problem = scrub::check_blob(&ctx.blobs, &entry, expected).await;
+            report.checked += 1;
+            report.bytes_checked += entry.size;
+            let Some(problem) = problem else {
+                continue;
+            };
+            // Removed while the scrub ran
+            if ctx.blobs.entry(&entry.hash).await.is_none() {
+                continue;
+            }
+            warn!(hash = %entry.hash, "stored file failed verification: {}", problem);
+            let repaired = repair
+                && match Self::repair_blob(ctx, &entry.hash).await {
+                    Ok(()) => true,
+                    Err(e) => {
+                        warn!(hash = %entry.hash, "failed to repair stored file: {}", e);
+                        false
+                    }
+                };
+            report.corrupted.push(CorruptBlob {
+                file_hash: entry.hash,
+                problem,
+                repaired,
+            });
+        }
+        report.finished_at = ctx.env.clock.now_secs();
+        report
+    }
+
+    /// Fetch a verified copy of a stored file from the other nodes that
+    /// provide it and put it in place of the local one
+    async fn repair_blob(ctx: &DownloadContext, file_hash: &str) -> Result<(), String> {
+        let fetcher = ctx
+            .chunk_fetcher
+            .lock()
+            .await
+            .clone()
+            .ok_or_else(|| "No transport available for remote providers".to_string())?;
+        let providers = fetcher.find_providers(file_hash).await?;
+        let remote = RemoteSources::new(providers, Some(fetcher));
+        if !remote.is_available() {
+            return Err(ServiceError::new(
+                ErrorCode::NoProviders,
+                "No other node provides this file",
+            )
+            .into());
+        }
+
+        let storage_dir = ctx.blobs.root();
+        let transfer_id = format!("scrub/{}", file_hash);
+        let output = storage_dir.join(format!("{}.repair", file_hash));
+        let output_path = output.to_string_lossy();
+        // Nobody follows the progress of a repair
+        let (quiet_tx, _) = mpsc::channel(1);
+        let progress = ProgressReporter::new(
+            &transfer_id,
+            TransferDirection::Download,
+            quiet_tx,
+            ctx.env.clock.clone(),
+        );
+        let fetched = Self::download_from_providers(
+            &transfer_id,
+            file_hash,
+            &output_path,
+            storage_dir,
+            &remote,
+            &progress,
+            &CancellationToken::new(),
+            &ctx.env,
+        )
+        .await;
+        let fetched = match fetched {
+            Ok(delivered) => {
+                Self::check_integrity(&transfer_id, &output_path, &delivered, 1, &ctx.event_tx)
+                    .await
+            }
+            Err(e) => Err(e),
+        };
+        if let Err(e) = fetched {
+            // Not worth resuming after a restart; the next scrub tries again
+            ResumeStore::in_storage_dir(storage_dir)
+                .discard(file_hash, &output_path)
+                .await;
+            return Err(e);
+        }
+        ctx.blobs.put_file(file_hash, &output).await?;
+        Ok(())
+    }
+
+    /// Queue a download and start whatever the concurrency limit allows
+    async fn enqueue_download(ctx: &DownloadContext, job: DownloadJob, resumed: bool) {
+        let transfer_id = job.transfer_id.clone();
+        let now = ctx.env.clock.now_secs();
+        let refused = ctx.circuits.lock().unwrap().check(&job.file_hash, now);
+        if let (Err(reopens_at), false) = (refused, resumed) {
+            let message = format!(
+                "File {} appears to be unavailable after repeated failed downloads; \
+                 try again in {}s",
+                job.file_hash,
+                reopens_at.saturating_sub(now)
+            );
+            warn!(%transfer_id, "{}", message);
+            let _ = ctx
+                .event_tx
+                .send(FileTransferEvent::Error {
+                    transfer_id: transfer_id.clone(),
+                    direction: TransferDirection::Download,
+                    code: ErrorCode::NoProviders,
+                    message,
+                })
+                .await;
+            Self::finish_batch_member(ctx, &transfer_id, None).await;
+            return;
+        }
+        let queued = ctx.downloads.lock().unwrap().enqueue(job, resumed);
+        match queued {
+            Ok(position) => {
+                Self::start_ready(ctx);
+                let still_queued = ctx.downloads.lock().unwrap().queue_position(&transfer_id);
+                if still_queued.is_some() {
+                    debug!(%transfer_id, position, "download queued");
+                    let _ = ctx
+                        .event_tx
+                        .send(FileTransferEvent::Queued {
+                            transfer_id,
+                            position,
+                        })
+                        .await;
+                }
+            }
+            Err(e) => {
+                warn!(%transfer_id, "{}", e);
+                let _ = ctx
+                    .event_tx
+                    .send(FileTransferEvent::Error {
+                        transfer_id,
+                        direction: TransferDirection::Download,
+                        code: ErrorCode::InvalidInput,
+                        message: e,
+                    })
+                    .await;
+            }
+        }
+    }
+
+    /// Queue a download for every file of the folder whose manifest
+    /// `manifest_job` just fetched
+    async fn queue_folder_files(ctx: &DownloadContext, manifest_job: &DownloadJob) {
+        let transfer_id = &manifest_job.transfer_id;
+        let (folder, jobs) = match Self::expand_folder(manifest_job).await {
+            Ok(expanded) => expanded,
+            Err(e) => {
+                warn!(%transfer_id, "folder download failed: {}", e);
+                let _ = ctx
+                    .event_tx
+                    .send(FileTransferEvent::Error {
+                        transfer_id: transfer_id.clone(),
+                        direction: TransferDirection::Download,
+                        code: ErrorCode::classify(&e),
+                        message: format!("Folder download failed: {}", e),
+                    })
+                    .await;
+                return;
+            }
+        };
+
+        let name = folder.manifest.name;
+        info!(%transfer_id, %name, files = jobs.len(), "queueing folder download");
+        let _ = ctx
+            .event_tx
+            .send(FileTransferEvent::FolderQueued {
+                transfer_id: transfer_id.clone(),
+                name,
+                file_count: jobs.len(),
+            })
+            .await;
+        for job in jobs {
+            Self::enqueue_download(ctx, job, false).await;
+        }
+    }
+
+    /// Read and verify a downloaded folder manifest, removing it, and make
+    /// a download job for each file it lists
+    async fn expand_folder(
+        manifest_job: &DownloadJob,
+    ) -> Result<(FolderManifest, Vec<DownloadJob>), String> {
+        let root = manifest_job
+            .folder_root
+            .as_deref()
+            .ok_or_else(|| "Not a folder download".to_string())?;
+        let bytes = tokio::fs::read(&manifest_job.output_path)
+            .await
+            .map_err(io_error(
+                ErrorCode::ReadFailed,
+                "Failed to read folder manifest",
+            ))?;
+        let _ = tokio::fs::remove_file(&manifest_job.output_path).await;
+        let folder = FolderManifest::from_bytes(&bytes)
+            .map_err(|e| String::from(ServiceError::new(ErrorCode::Verification, e)))?;
+        let plan = folder.manifest.plan(&manifest_job.file_hash, root, &[])?;
+
+        let mut jobs = Vec::with_capacity(plan.entries.len());
+        for (index, entry) in plan.entries.iter().enumerate() {
+            let output_path = plan.output_path(&entry.path);
+            if let Some(parent) = output_path.parent() {
+                tokio::fs::create_dir_all(parent).await.map_err(io_error(
+                    ErrorCode::WriteFailed,
+                    format!("Failed to create {}", parent.display()),
+                ))?;
+            }
+            jobs.push(DownloadJob {
+                transfer_id: format!("{}/{}", manifest_job.transfer_id, index),
+                file_hash: entry.file_hash.clone(),
+                output_path: output_path.to_string_lossy().to_string(),
+                folder_root: None,
+                ..manifest_job.clone()
+            });
+        }
+        Ok((folder, jobs))
+    }
+
+    /// Run every download the queue lets start, each on its own task so
+    /// the command loop stays free to pause or cancel it
+    fn start_ready(ctx: &DownloadContext) {
+        let started = ctx.downloads.lock().unwrap().start_ready();
+        for download in started {
+            tokio::spawn(Self::run_download(
+                ctx.clone(),
+                download.job,
+                download.cancel,
+                download.resumed,
+            ));
+        }
+    }
+
+    async fn run_download(
+        ctx: DownloadContext,
+        job: DownloadJob,
+        cancel: CancellationToken,
+        resumed: bool,
+    ) {
+        let folder_job = job.folder_root.is_some().then(|| job.clone());
+        let DownloadJob {
+            transfer_id,
+            file_hash,
+            output_path,
+            providers,
+            retry_policy,
+            streaming,
+            active_account,
+            active_private_key,
+            ..
+        } = job;
+        let mut remote = RemoteSources::new(providers, ctx.chunk_fetcher.lock().await.clone());
+        if streaming {
+            remote.config = SwarmConfig::streaming();
+        }
+        let policy = match retry_policy {
+            Some(policy) => policy,
+            None => ctx.retry_policy.lock().await.clone(),
+        };
+        let start_time = current_timestamp_ms();
+        let progress = ProgressReporter::new(
+            &transfer_id,
+            TransferDirection::Download,
+            ctx.event_tx.clone(),
+            ctx.env.clock.clone(),
+        );
+
+        // Emit started event via TransferEventBus, or straight onto the
+        // unified bus without one, so the transfer log learns the job's
+        // providers. A resumed download already announced itself when it
+        // first started.
+        if !resumed {
+            let sources: Vec<(String, String)> = if remote.providers.is_empty() {
+                vec![("local-storage".to_string(), "local".to_string())]
+            } else {
+                remote
+                    .providers
+                    .iter()
+                    .map(|p| (p.clone(), p.clone()))
+                    .collect()
+            };
+            let started = TransferStartedEvent {
+                transfer_id: transfer_id.clone(),
+                file_hash: file_hash.clone(),
+                file_name: output_path.clone(),
+                file_size: 0, // Unknown at this point
+                total_chunks: 0,
+                chunk_size: 0,
+                started_at: start_time,
+                available_sources: sources
+                    .iter()
+                    .map(|(id, address)| SourceInfo {
+                        id: id.clone(),
+                        source_type: SourceType::P2p,
+                        address: address.clone(),
+                        reputation: None,
+                        estimated_speed_bps: None,
+                        latency_ms: None,
+                        location: None,
+                    })
+                    .collect(),
+                selected_sources: sources.into_iter().map(|(id, _)| id).collect(),
+            };
+            match &ctx.event_bus {
+                Some(bus) => bus.emit_started(started),
+                None => {
+                    crate::event_bus::global().publish_transfer(&TransferEvent::Started(started));
+                }
+            }
+        }
+
+        let result = Self::download_with_retries(
+            &transfer_id,
+            &file_hash,
+            &output_path,
+            &ctx.blobs,
+            &remote,
+            ctx.event_tx.clone(),
+            ctx.download_metrics.clone(),
+            ctx.keystore.clone(),
+            active_account.as_deref(),
+            active_private_key.as_deref(),
+            &policy,
+            &progress,
+            &cancel,
+            &ctx.env,
+        )
+        .await;
+        let done = progress.snapshot();
+        let stop = ctx
+            .downloads
+            .lock()
+            .unwrap()
+            .finish(&transfer_id, done.clone());
+        // The slot is free for the next queued download
+        Self::start_ready(&ctx);
+
+        if let (Ok(_), Some(folder_job)) = (&result, &folder_job) {
+            Self::queue_folder_files(&ctx, folder_job).await;
+            return;
+        }
+
+        match result {
+            Ok(served) => {
+                let _ = ctx
+                    .event_tx
+                    .send(FileTransferEvent::FileDownloaded {
+                        transfer_id: transfer_id.clone(),
+                        file_path: output_path.clone(),
+                    })
+                    .await;
+
+                // Emit completed event via TransferEventBus
+                if let Some(ref bus) = ctx.event_bus {
+                    let end_time = current_timestamp_ms();
+                    let duration_secs = (end_time - start_time) / 1000;
+                    bus.emit_completed(TransferCompletedEvent {
+                        transfer_id: transfer_id.clone(),
+                        file_hash: file_hash.clone(),
+                        file_name: output_path.clone(),
+                        file_size: done.bytes_done,
+                        output_path: output_path.clone(),
+                        completed_at: end_time,
+                        duration_seconds: duration_secs,
+                        average_speed_bps: 0.0,
+                        total_chunks: served.iter().map(|s| s.chunks).sum(),
+                        // Providers that served chunks; none for a local copy
+                        sources_used: served
+                            .iter()
+                            .filter(|s| s.chunks > 0)
+                            .map(|s| SourceSummary {
+                                source_id: s.provider.clone(),
+                                source_type: SourceType::P2p,
+                                chunks_provided: s.chunks,
+                                bytes_provided: s.bytes,
+                                average_speed_bps: 0.0,
+                                connection_duration_seconds: duration_secs,
+                            })
+                            .collect(),
+                    });
+                }
+
+                info!(
+                    %transfer_id,
+                    "File downloaded successfully: {} -> {}",
+                    file_hash, output_path
+                );
+                ctx.circuits.lock().unwrap().record_success(&file_hash);
+                Self::finish_batch_member(&ctx, &transfer_id, Some(done.bytes_done)).await;
+            }
+            Err(_) if stop == Some(StopRequest::Pause) => {
+                let _ = ctx
+                    .event_tx
+                    .send(FileTransferEvent::Paused {
+                        transfer_id: transfer_id.clone(),
+                        bytes_done: done.bytes_done,
+                        total_bytes: done.total_bytes,
+                    })
+                    .await;
+                if let Some(ref bus) = ctx.event_bus {
+                    bus.emit_paused(TransferPausedEvent {
+                        transfer_id: transfer_id.clone(),
+                        paused_at: current_timestamp_ms(),
+                        reason: PauseReason::UserRequested,
+                        can_resume: true,
+                        downloaded_bytes: done.bytes_done,
+                        total_bytes: done.total_bytes,
+                    });
+                }
+                info!(%transfer_id, "File download paused: {}", file_hash);
             }
+            Err(_) if cancel.is_cancelled() => {
+                ResumeStore::in_storage_dir(ctx.blobs.root())
+                    .discard(&file_hash, &output_path)
+                    .await;
+                Self::report_cancelled(&ctx, &transfer_id, &done).await;
+                info!(%transfer_id, "File download cancelled: {}", file_hash);
+            }
+            Err(e) => {
+                let error_msg = format!("Download failed: {}", e);
+                let code = ErrorCode::classify(&e);
+                let event = if code == ErrorCode::NotFound {
+                    FileTransferEvent::FileNotFound {
+                        transfer_id: transfer_id.clone(),
+                        file_hash: file_hash.clone(),
+                    }
+                } else {
+                    FileTransferEvent::Error {
+                        transfer_id: transfer_id.clone(),
+                        direction: TransferDirection::Download,
+                        code,
+                        message: error_msg.clone(),
+                    }
+                };
+                let _ = ctx.event_tx.send(event).await;
+
+                // Emit failed event via TransferEventBus
+                if let Some(ref bus) = ctx.event_bus {
+                    bus.emit_failed(TransferFailedEvent {
+                        transfer_id: transfer_id.clone(),
+                        file_hash: file_hash.clone(),
+                        failed_at: current_timestamp_ms(),
+                        error: error_msg.clone(),
+                        error_category: code.category(),
+                        downloaded_bytes: done.bytes_done,
+                        total_bytes: done.total_bytes,
+                        retry_possible: code.is_retryable(),
+                    });
+                }
+
+                error!(%transfer_id, "File download failed: {}", error_msg);
+                let opened = CircuitBreaker::counts(code)
+                    .then(|| {
+                        let now = ctx.env.clock.now_secs();
+                        ctx.circuits.lock().unwrap().record_failure(&file_hash, now)
+                    })
+                    .flatten();
+                if let Some(open) = opened {
+                    warn!(
+                        "Downloads of {} failed {} times; refusing new ones until {}",
+                        open.file_hash, open.failures, open.reopens_at
+                    );
+                    let _ = ctx
+                        .event_tx
+                        .send(FileTransferEvent::CircuitOpen(open))
+                        .await;
+                }
+                Self::finish_batch_member(&ctx, &transfer_id, None).await;
+            }
+        }
+    }
+
+    async fn report_cancelled(ctx: &DownloadContext, transfer_id: &str, done: &TransferProgress) {
+        let _ = ctx
+            .event_tx
+            .send(FileTransferEvent::Cancelled {
+                transfer_id: transfer_id.to_string(),
+            })
+            .await;
+        if let Some(ref bus) = ctx.event_bus {
+            bus.emit_canceled(TransferCanceledEvent {
+                transfer_id: transfer_id.to_string(),
+                canceled_at: current_timestamp_ms(),
+                downloaded_bytes: done.bytes_done,
+                total_bytes: done.total_bytes,
+                keep_partial: false,
+            });
+        }
+        Self::finish_batch_member(ctx, transfer_id, None).await;
+    }
+
+    /// Report on the batch a download belonged to, if any, now that it has
+    /// ended. `delivered` is the file size if it succeeded.
+    async fn finish_batch_member(ctx: &DownloadContext, transfer_id: &str, delivered: Option<u64>) {
+        let finished = ctx.batches.lock().unwrap().finish(transfer_id, delivered);
+        let Some((progress, failed)) = finished else {
+            return;
+        };
+        let batch_id = progress.batch_id.clone();
+        let file_count = progress.file_count;
+        let _ = ctx
+            .event_tx
+            .send(FileTransferEvent::BatchProgress(progress))
+            .await;
+        if let Some(failed) = failed {
+            info!(%batch_id, file_count, failed = failed.len(), "batch download finished");
+            let _ = ctx
+                .event_tx
+                .send(FileTransferEvent::BatchCompleted {
+                    batch_id,
+                    file_count,
+                    failed,
+                })
+                .await;
+        }
+    }
+
+    /// Read a file in chunk-sized pieces, reporting each one
+    async fn read_with_progress(
+        file_path: &str,
+        progress: &ProgressReporter,
+    ) -> Result<Vec<u8>, String> {
+        use tokio::io::AsyncReadExt;
+        let mut file = tokio::fs::File::open(file_path)
+            .await
+            .map_err(io_error(ErrorCode::ReadFailed, "Failed to read file"))?;
+        let total = file.metadata().await.map(|m| m.len()).unwrap_or(0);
+        progress.start(total, 0);
+
+        let mut data = Vec::with_capacity(total as usize);
+        let mut buf = buffers::chunk_buffers().take();
+        buf.resize(chunking::CHUNK_SIZE, 0);
+        loop {
+            let n = file
+                .read(&mut buf)
+                .await
+                .map_err(io_error(ErrorCode::ReadFailed, "Failed to read file"))?;
+            if n == 0 {
+                break;
+            }
+            data.extend_from_slice(&buf[..n]);
+            progress.add(n as u64);
         }
+        Ok(data)
     }
 
     async fn handle_upload_file(
         file_path: &str,
         file_name: &str,
-        storage_dir: &PathBuf,
+        blobs: &BlobStore,
+        progress: &ProgressReporter,
+        hash_algorithm: HashAlgorithm,
         encryption_enabled: bool,
         recipient_public_key: Option<&str>,
         keystore: &Arc<Mutex<crate::keystore::Keystore>>,
@@ -578,13 +2335,33 @@ impl FileTransferService {
         active_private_key: Option<&str>,
     ) -> Result<(String, Option<EncryptedFileMetadata>), String> {
         // Read the file
-        let file_data = tokio::fs::read(file_path)
-            .await
-            .map_err(|e| format!("Failed to read file: {}", e))?;
-
-        let original_file_hash = Self::calculate_file_hash(&file_data);
+        let file_data = Self::read_with_progress(file_path, progress)
+            .instrument(trace_span!(target: PROFILE_TARGET, "disk_read"))
+            .await?;
+
+        let original_file_hash = Self::calculate_file_hash_with(hash_algorithm, &file_data);
+        let storage_dir = blobs.root();
+
+        let (final_file_hash, encrypted_metadata) = if let Some(recipient) = recipient_public_key {
+            // Addressed to one account: the envelope is what gets stored and
+            // announced, so peers and relays never see the plaintext
+            let envelope = recipient::seal(&file_data, &recipient::parse_public_key(recipient)?)?;
+            let envelope_hash = Self::calculate_file_hash_with(hash_algorithm, &envelope);
+            blobs
+                .put(&envelope_hash, &envelope)
+                .await
+                .map_err(wrap_error(
+                    ErrorCode::WriteFailed,
+                    "Failed to write encrypted file to storage",
+                ))?;
+            chunking::save_manifest(
+                storage_dir,
+                &ChunkManifest::build(&envelope_hash, &envelope),
+            )
+            .await?;
 
-        let (final_file_hash, encrypted_metadata) = if encryption_enabled {
+            (envelope_hash, None)
+        } else if encryption_enabled {
             // Generate random encryption key
             let encryption_key = encryption::FileEncryption::generate_random_key();
 
@@ -618,64 +2395,84 @@ impl FileTransferService {
                 &encryption_key,
             )
             .await
-            .map_err(|e| format!("Failed to encrypt file: {}", e))?;
+            .map_err(|e| {
+                ServiceError::new(
+                    ErrorCode::Internal,
+                    format!("Failed to encrypt file: {}", e),
+                )
+                .to_string()
+            })?;
 
             // Read encrypted data
             let encrypted_data = tokio::fs::read(&temp_encrypted_path)
                 .await
-                .map_err(|e| format!("Failed to read encrypted file: {}", e))?;
-
-            let encrypted_file_hash = Self::calculate_file_hash(&encrypted_data);
+                .map_err(io_error(
+                    ErrorCode::ReadFailed,
+                    "Failed to read encrypted file",
+                ))?;
 
-            // Handle key exchange if recipient public key is provided
-            let (encrypted_key_bundle, recipient_pk) = if let Some(pk_hex) = recipient_public_key {
-                let pk_bytes = hex::decode(pk_hex.trim_start_matches("0x"))
-                    .map_err(|e| format!("Invalid recipient public key: {}", e))?;
-                let recipient_pk = x25519_dalek::PublicKey::from(
-                    <[u8; 32]>::try_from(pk_bytes)
-                        .map_err(|_| "Recipient public key must be 32 bytes".to_string())?,
-                );
-
-                let bundle = encryption::encrypt_aes_key(&encryption_key, &recipient_pk)
-                    .map_err(|e| format!("Failed to encrypt key for recipient: {}", e))?;
-
-                (Some(bundle), Some(pk_hex.to_string()))
-            } else {
-                (None, None)
-            };
+            let encrypted_file_hash =
+                Self::calculate_file_hash_with(hash_algorithm, &encrypted_data);
 
             let metadata = EncryptedFileMetadata {
                 original_file_hash: original_file_hash.clone(),
                 encrypted_file_hash: encrypted_file_hash.clone(),
                 encryption_info: encryption_result.encryption_info,
-                encrypted_key_bundle,
-                recipient_public_key: recipient_pk,
+                encrypted_key_bundle: None,
+                recipient_public_key: None,
             };
 
             // Store encrypted metadata
             let encrypted_meta_path = storage_dir.join(format!("{}.encmeta", encrypted_file_hash));
-            let encrypted_meta_json = serde_json::to_string(&metadata)
-                .map_err(|e| format!("Failed to serialize encrypted metadata: {}", e))?;
+            let encrypted_meta_json = serde_json::to_string(&metadata).map_err(|e| {
+                ServiceError::new(
+                    ErrorCode::Internal,
+                    format!("Failed to serialize encrypted metadata: {}", e),
+                )
+                .to_string()
+            })?;
             tokio::fs::write(&encrypted_meta_path, encrypted_meta_json)
                 .await
-                .map_err(|e| format!("Failed to write encrypted metadata: {}", e))?;
+                .map_err(io_error(
+                    ErrorCode::WriteFailed,
+                    "Failed to write encrypted metadata",
+                ))?;
+
+            chunking::save_manifest(
+                storage_dir,
+                &ChunkManifest::build(&encrypted_file_hash, &encrypted_data),
+            )
+            .await?;
 
-            // Store encrypted data
-            let encrypted_file_path = storage_dir.join(&encrypted_file_hash);
-            tokio::fs::write(&encrypted_file_path, &encrypted_data)
+            // Move the encrypted temp file into the blob store
+            blobs
+                .put_file(&encrypted_file_hash, &temp_encrypted_path)
                 .await
-                .map_err(|e| format!("Failed to write encrypted file to storage: {}", e))?;
-
-            // Clean up temp file
-            let _ = tokio::fs::remove_file(&temp_encrypted_path).await;
+                .map_err(wrap_error(
+                    ErrorCode::WriteFailed,
+                    "Failed to write encrypted file to storage",
+                ))?;
 
             (encrypted_file_hash, Some(metadata))
         } else {
             // Store unencrypted file
-            let file_path_in_storage = storage_dir.join(&original_file_hash);
-            tokio::fs::write(&file_path_in_storage, &file_data)
+            blobs
+                .put(&original_file_hash, &file_data)
+                .instrument(trace_span!(
+                    target: PROFILE_TARGET,
+                    "disk_write",
+                    bytes = file_data.len()
+                ))
                 .await
-                .map_err(|e| format!("Failed to write file to storage: {}", e))?;
+                .map_err(wrap_error(
+                    ErrorCode::WriteFailed,
+                    "Failed to write file to storage",
+                ))?;
+            chunking::save_manifest(
+                storage_dir,
+                &ChunkManifest::build(&original_file_hash, &file_data),
+            )
+            .await?;
 
             (original_file_hash, None)
         };
@@ -688,39 +2485,230 @@ impl FileTransferService {
                 .duration_since(UNIX_EPOCH)
                 .unwrap_or_default()
                 .as_secs(),
-            "is_encrypted": encryption_enabled,
+            "is_encrypted": encrypted_metadata.is_some(),
+            "encrypted_for": recipient_public_key,
+            // Detected before encryption, so stored ciphertext stays searchable by type
+            "mime_type": crate::content_info::detect_mime(&file_data),
+            "extension": crate::content_info::extension(file_name),
+            "created_at": crate::content_info::created_at(Path::new(file_path)).await,
         });
         let metadata_path = storage_dir.join(format!("{}.meta", final_file_hash));
         tokio::fs::write(&metadata_path, serde_json::to_string(&metadata).unwrap())
             .await
-            .map_err(|e| format!("Failed to write metadata: {}", e))?;
+            .map_err(io_error(ErrorCode::WriteFailed, "Failed to write metadata"))?;
 
         Ok((final_file_hash, encrypted_metadata))
     }
 
-    async fn handle_download_file(
-        file_hash: &str,
-        output_path: &str,
-        storage_dir: &PathBuf,
-        keystore: &Arc<Mutex<crate::keystore::Keystore>>,
-        active_account: Option<&str>,
-        active_private_key: Option<&str>,
-    ) -> Result<(), String> {
-        // Check if we have the file in storage
-        let file_path_in_storage = storage_dir.join(file_hash);
-        if !file_path_in_storage.exists() {
-            return Err("File not found in storage".to_string());
+    /// Share a file from where it is. The file is streamed once to hash it
+    /// and build its chunk manifest, then linked into the blob store, so
+    /// nothing is copied and it never has to fit in memory.
+    async fn handle_import_file(
+        file_path: &str,
+        file_name: &str,
+        blobs: &BlobStore,
+        progress: &ProgressReporter,
+        hash_algorithm: HashAlgorithm,
+    ) -> Result<String, String> {
+        use tokio::io::AsyncReadExt;
+        let (linked, size) = LinkedFile::stat(Path::new(file_path)).await?;
+        let mut file = tokio::fs::File::open(file_path)
+            .await
+            .map_err(io_error(ErrorCode::ReadFailed, "Failed to read file"))?;
+        progress.start(size, 0);
+
+        let mut hasher = hash_algorithm.hasher();
+        let mut manifest = chunking::ManifestBuilder::new(chunking::CHUNK_SIZE);
+        let mut head = Vec::new();
+        let mut buf = buffers::chunk_buffers().take();
+        buf.resize(chunking::CHUNK_SIZE, 0);
+        loop {
+            let n = file
+                .read(&mut buf)
+                .await
+                .map_err(io_error(ErrorCode::ReadFailed, "Failed to read file"))?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            manifest.update(&buf[..n]);
+            if (head.len() as u64) < MIME_SNIFF_LEN {
+                head.extend_from_slice(&buf[..n]);
+            }
+            progress.add(n as u64);
         }
 
-        // Check metadata to see if file is encrypted
+        let file_hash = hasher.finish().to_string();
+        blobs.link(&file_hash, linked, size).await?;
+        let storage_dir = blobs.root();
+        chunking::save_manifest(storage_dir, &manifest.finish(&file_hash)).await?;
+        let metadata = serde_json::json!({
+            "file_name": file_name,
+            "file_size": size,
+            "uploaded_at": SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            "is_encrypted": false,
+            "mime_type": crate::content_info::detect_mime(&head),
+            "extension": crate::content_info::extension(file_name),
+            "created_at": crate::content_info::created_at(Path::new(file_path)).await,
+        });
         let metadata_path = storage_dir.join(format!("{}.meta", file_hash));
-        let is_encrypted = if metadata_path.exists() {
-            let metadata_content = tokio::fs::read_to_string(&metadata_path)
+        tokio::fs::write(&metadata_path, metadata.to_string())
+            .await
+            .map_err(io_error(ErrorCode::WriteFailed, "Failed to write metadata"))?;
+        Ok(file_hash)
+    }
+
+    /// Upload every file under `dir_path`, then store a manifest of the
+    /// tree signed with the account key. Returns the manifest's hash.
+    async fn handle_upload_directory(
+        dir_path: &str,
+        blobs: &BlobStore,
+        progress: &ProgressReporter,
+        env: &RuntimeEnv,
+        hash_algorithm: HashAlgorithm,
+        encryption_enabled: bool,
+        keystore: &Arc<Mutex<crate::keystore::Keystore>>,
+        active_account: Option<&str>,
+        active_private_key: Option<&str>,
+    ) -> Result<(String, FolderManifest), String> {
+        let Some(private_key) = active_private_key else {
+            return Err(ServiceError::new(
+                ErrorCode::InvalidInput,
+                "An active account is needed to sign the folder manifest",
+            )
+            .into());
+        };
+        let root = Path::new(dir_path);
+        let name = root
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_else(|| dir_path.to_string());
+        let paths = folder::collect_files(root).await?;
+
+        let mut sizes = Vec::with_capacity(paths.len());
+        for path in &paths {
+            let metadata = tokio::fs::metadata(path).await.map_err(io_error(
+                ErrorCode::ReadFailed,
+                format!("Failed to read {}", path.display()),
+            ))?;
+            sizes.push(metadata.len());
+        }
+        progress.start(sizes.iter().sum(), 0);
+
+        let mut files = Vec::with_capacity(paths.len());
+        for (path, size) in paths.into_iter().zip(sizes) {
+            let path_str = path.to_string_lossy().to_string();
+            let file_name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| path_str.clone());
+            let file_progress =
+                ProgressReporter::silent(&path_str, TransferDirection::Upload, env.clock.clone());
+            let (file_hash, _) = Self::handle_upload_file(
+                &path_str,
+                &file_name,
+                blobs,
+                &file_progress,
+                hash_algorithm,
+                encryption_enabled,
+                None,
+                keystore,
+                active_account,
+                Some(private_key),
+            )
+            .await
+            .map_err(|e| format!("{}: {}", path_str, e))?;
+            progress.add(size);
+            files.push((path, file_hash, size));
+        }
+
+        let manifest = crate::directory_manifest::DirectoryManifest::new(
+            name,
+            root,
+            files,
+            env.clock.now_secs(),
+        )?;
+        let folder = FolderManifest::signed(manifest, private_key)?;
+        let bytes = folder.to_bytes()?;
+        let manifest_hash = Self::calculate_file_hash_with(hash_algorithm, &bytes);
+        blobs.put(&manifest_hash, &bytes).await.map_err(wrap_error(
+            ErrorCode::WriteFailed,
+            "Failed to write folder manifest to storage",
+        ))?;
+        let storage_dir = blobs.root();
+        chunking::save_manifest(storage_dir, &ChunkManifest::build(&manifest_hash, &bytes)).await?;
+
+        let metadata = serde_json::json!({
+            "file_name": folder.manifest.name,
+            "file_size": bytes.len(),
+            "uploaded_at": folder.manifest.created_at,
+            "is_encrypted": false,
+            "is_folder": true,
+            "file_count": folder.manifest.entries.len(),
+            "mime_type": "application/json",
+        });
+        let metadata_path = storage_dir.join(format!("{}.meta", manifest_hash));
+        tokio::fs::write(&metadata_path, metadata.to_string())
+            .await
+            .map_err(io_error(ErrorCode::WriteFailed, "Failed to write metadata"))?;
+
+        Ok((manifest_hash, folder))
+    }
+
+    async fn handle_download_file(
+        transfer_id: &str,
+        file_hash: &str,
+        output_path: &str,
+        blobs: &BlobStore,
+        remote: &RemoteSources,
+        progress: &ProgressReporter,
+        cancel: &CancellationToken,
+        keystore: &Arc<Mutex<crate::keystore::Keystore>>,
+        active_account: Option<&str>,
+        active_private_key: Option<&str>,
+        env: &RuntimeEnv,
+    ) -> Result<DeliveredFile, String> {
+        // Check if we have the file in storage, otherwise fetch it from peers
+        let Some(file_hash) = Self::resolve_stored_hash(blobs, file_hash).await else {
+            if remote.is_available() {
+                return Self::download_from_providers(
+                    transfer_id,
+                    file_hash,
+                    output_path,
+                    blobs.root(),
+                    remote,
+                    progress,
+                    cancel,
+                    env,
+                )
+                .await;
+            }
+            return Err(ServiceError::new(ErrorCode::NotFound, "File not found in storage").into());
+        };
+        let file_hash = file_hash.as_str();
+        let storage_dir = blobs.root();
+        if let Some(entry) = blobs.entry(file_hash).await {
+            Self::ensure_disk_space(env, storage_dir, file_hash, output_path, entry.size).await?;
+        }
+
+        // Check metadata to see if file is encrypted
+        let metadata_path = storage_dir.join(format!("{}.meta", file_hash));
+        let is_encrypted = if metadata_path.exists() {
+            let metadata_content = tokio::fs::read_to_string(&metadata_path)
                 .await
-                .map_err(|e| format!("Failed to read metadata: {}", e))?;
+                .map_err(io_error(ErrorCode::ReadFailed, "Failed to read metadata"))?;
 
-            let metadata: serde_json::Value = serde_json::from_str(&metadata_content)
-                .map_err(|e| format!("Failed to parse metadata: {}", e))?;
+            let metadata: serde_json::Value =
+                serde_json::from_str(&metadata_content).map_err(|e| {
+                    ServiceError::new(
+                        ErrorCode::InvalidInput,
+                        format!("Failed to parse metadata: {}", e),
+                    )
+                    .to_string()
+                })?;
 
             metadata
                 .get("is_encrypted")
@@ -730,20 +2718,32 @@ impl FileTransferService {
             false
         };
 
-        let final_data = if is_encrypted {
+        let (final_data, expected_hash) = if is_encrypted {
             // Try to find encrypted metadata
             let encrypted_meta_path = storage_dir.join(format!("{}.encmeta", file_hash));
             if !encrypted_meta_path.exists() {
-                return Err("Encrypted file found but no encryption metadata available".to_string());
+                return Err(ServiceError::new(
+                    ErrorCode::ReadFailed,
+                    "Encrypted file found but no encryption metadata available",
+                )
+                .into());
             }
 
             let encrypted_meta_content = tokio::fs::read_to_string(&encrypted_meta_path)
                 .await
-                .map_err(|e| format!("Failed to read encrypted metadata: {}", e))?;
+                .map_err(io_error(
+                    ErrorCode::ReadFailed,
+                    "Failed to read encrypted metadata",
+                ))?;
 
             let encrypted_metadata: EncryptedFileMetadata =
-                serde_json::from_str(&encrypted_meta_content)
-                    .map_err(|e| format!("Failed to parse encrypted metadata: {}", e))?;
+                serde_json::from_str(&encrypted_meta_content).map_err(|e| {
+                    ServiceError::new(
+                        ErrorCode::InvalidInput,
+                        format!("Failed to parse encrypted metadata: {}", e),
+                    )
+                    .to_string()
+                })?;
 
             // Try to get decryption key from keystore
             let decryption_key =
@@ -757,47 +2757,294 @@ impl FileTransferService {
                         Ok(key) => key,
                         Err(e) => {
                             warn!("Failed to retrieve decryption key from keystore: {}", e);
-                            return Err("No decryption key available for this file".to_string());
+                            return Err(ServiceError::new(
+                                ErrorCode::Unauthorized,
+                                "No decryption key available for this file",
+                            )
+                            .into());
                         }
                     }
                 } else {
-                    return Err("No active account available for file access".to_string());
+                    return Err(ServiceError::new(
+                        ErrorCode::Unauthorized,
+                        "No active account available for file access",
+                    )
+                    .into());
                 };
 
-            // Create temporary decrypted file path
-            let temp_decrypted_path = storage_dir.join(format!("{}.dec", file_hash));
+            // Check the ciphertext chunk by chunk before decrypting it
+            let stored = blobs.get(file_hash).await?.ok_or_else(|| {
+                ServiceError::new(ErrorCode::NotFound, "File not found in storage")
+            })?;
+            let stored = Self::verify_chunks(storage_dir, file_hash, stored).await?;
 
-            // Decrypt the file
-            encryption::FileEncryption::decrypt_file(
-                &file_path_in_storage,
-                &temp_decrypted_path,
+            // Decrypt in memory; the blob on disk may itself be sealed at rest
+            let decrypted_data = encryption::FileEncryption::decrypt_bytes(
+                &stored,
                 &decryption_key,
                 &encrypted_metadata.encryption_info,
             )
-            .await
-            .map_err(|e| format!("Failed to decrypt file: {}", e))?;
-
-            // Read decrypted data
-            let decrypted_data = tokio::fs::read(&temp_decrypted_path)
-                .await
-                .map_err(|e| format!("Failed to read decrypted file: {}", e))?;
-
-            // Clean up temp file
-            let _ = tokio::fs::remove_file(&temp_decrypted_path).await;
+            .map_err(|e| {
+                ServiceError::new(
+                    ErrorCode::Unauthorized,
+                    format!("Failed to decrypt file: {}", e),
+                )
+                .to_string()
+            })?;
 
-            decrypted_data
+            // The plaintext is addressed by the hash taken before encryption
+            (decrypted_data, encrypted_metadata.original_file_hash)
         } else {
             // Read the unencrypted file from storage
-            tokio::fs::read(&file_path_in_storage)
+            let stored = blobs
+                .get(file_hash)
+                .instrument(trace_span!(target: PROFILE_TARGET, "disk_read"))
                 .await
-                .map_err(|e| format!("Failed to read file from storage: {}", e))?
+                .map_err(wrap_error(
+                    ErrorCode::ReadFailed,
+                    "Failed to read file from storage",
+                ))?
+                .ok_or_else(|| {
+                    ServiceError::new(ErrorCode::NotFound, "File not found in storage")
+                })?;
+
+            // Chunked blobs are written chunk by chunk so an interrupted
+            // download can pick up where it stopped
+            if let Some(manifest) = chunking::load_manifest(storage_dir, file_hash).await? {
+                let first_byte_at = env.clock.monotonic();
+                Self::write_chunks_resumable(
+                    env,
+                    storage_dir,
+                    transfer_id,
+                    &manifest,
+                    &stored,
+                    output_path,
+                    progress,
+                    cancel,
+                )
+                .await?;
+                info!("File downloaded: {} -> {}", file_hash, output_path);
+                return Ok(DeliveredFile {
+                    size: stored.len() as u64,
+                    first_byte_at,
+                    expected_hash: Some(file_hash.to_string()),
+                    sources: Vec::new(),
+                });
+            }
+            (stored, file_hash.to_string())
         };
 
+        let first_byte_at = env.clock.monotonic();
+        progress.start(final_data.len() as u64, 0);
+
         // Write the file to the output path
-        Self::write_output(output_path, &final_data).await?;
+        Self::write_output(env, output_path, &final_data).await?;
+        progress.add(final_data.len() as u64);
 
         info!("File downloaded: {} -> {}", file_hash, output_path);
-        Ok(())
+        Ok(DeliveredFile {
+            size: final_data.len() as u64,
+            first_byte_at,
+            expected_hash: Some(expected_hash),
+            sources: Vec::new(),
+        })
+    }
+
+    /// Write a chunked blob to `output_path` through `<output>.part`, checking
+    /// every chunk first. Progress is recorded in a `ResumeStore` so chunks
+    /// written by an interrupted earlier attempt are not written again.
+    async fn write_chunks_resumable(
+        env: &RuntimeEnv,
+        storage_dir: &Path,
+        transfer_id: &str,
+        manifest: &ChunkManifest,
+        data: &[u8],
+        output_path: &str,
+        progress: &ProgressReporter,
+        cancel: &CancellationToken,
+    ) -> Result<(), String> {
+        manifest.validate().map_err(Self::verification_error)?;
+        if data.len() as u64 != manifest.file_size {
+            return Err(Self::verification_error(ChunkError::Invalid(format!(
+                "file has {} bytes, manifest expects {}",
+                data.len(),
+                manifest.file_size
+            ))));
+        }
+
+        let mut part = PartFile::open(
+            ResumeStore::in_storage_dir(storage_dir),
+            transfer_id,
+            output_path,
+            manifest,
+            env.clock.now_secs(),
+        )
+        .await?;
+        if !part.received().is_empty() {
+            info!(
+                %transfer_id,
+                resumed = part.received().len(),
+                total = manifest.chunk_count(),
+                "resuming download from earlier progress"
+            );
+        }
+        progress.start(manifest.file_size, part.received_bytes());
+
+        for index in part.missing() {
+            let written = match manifest.chunk_range(index) {
+                _ if cancel.is_cancelled() => Err(cancelled_error()),
+                Ok(range) => {
+                    let chunk = &data[range];
+                    match manifest.verify_chunk(index, chunk) {
+                        Ok(()) => part
+                            .write_chunk(index, chunk, env.clock.now_secs())
+                            .instrument(trace_span!(
+                                target: PROFILE_TARGET,
+                                "disk_write",
+                                bytes = chunk.len()
+                            ))
+                            .await
+                            .map(|()| progress.add(chunk.len() as u64)),
+                        Err(e) => Err(Self::verification_error(e)),
+                    }
+                }
+                Err(e) => Err(Self::verification_error(e)),
+            };
+            if let Err(err) = written {
+                // Keep what was written so the next attempt can resume from it
+                if let Err(e) = part.checkpoint(env.clock.now_secs()).await {
+                    warn!(%transfer_id, "failed to save download progress: {}", e);
+                }
+                return Err(err);
+            }
+        }
+
+        part.finish().await
+    }
+
+    /// Fetch a file the local store doesn't have from `remote.providers`,
+    /// spreading its chunks across them. Progress is recorded like any other
+    /// chunked download, so a retry only fetches the chunks still missing.
+    async fn download_from_providers(
+        transfer_id: &str,
+        file_hash: &str,
+        output_path: &str,
+        storage_dir: &Path,
+        remote: &RemoteSources,
+        progress: &ProgressReporter,
+        cancel: &CancellationToken,
+        env: &RuntimeEnv,
+    ) -> Result<DeliveredFile, String> {
+        let fetcher = remote
+            .fetcher
+            .clone()
+            .ok_or_else(|| "No transport available for remote providers".to_string())?;
+        let manifest = swarm::fetch_manifest(fetcher.as_ref(), &remote.providers, file_hash)
+            .await
+            .map_err(|e| String::from(ServiceError::new(ErrorCode::NoProviders, e)))?;
+        Self::ensure_disk_space(
+            env,
+            storage_dir,
+            &manifest.file_hash,
+            output_path,
+            manifest.file_size,
+        )
+        .await?;
+
+        let mut part = PartFile::open(
+            ResumeStore::in_storage_dir(storage_dir),
+            transfer_id,
+            output_path,
+            &manifest,
+            env.clock.now_secs(),
+        )
+        .await?;
+        part.set_providers(&remote.providers);
+        progress.start(manifest.file_size, part.received_bytes());
+        let first_byte_at = env.clock.monotonic();
+
+        let stats = match swarm::fetch_chunks(
+            fetcher,
+            &remote.providers,
+            &manifest,
+            &mut part,
+            remote.config,
+            env.clock.as_ref(),
+            progress,
+            cancel,
+        )
+        .await
+        {
+            Ok(stats) => stats,
+            Err(err) => {
+                if let Err(e) = part.checkpoint(env.clock.now_secs()).await {
+                    warn!(%transfer_id, "failed to save download progress: {}", e);
+                }
+                return Err(err);
+            }
+        };
+
+        part.finish().await?;
+
+        for s in &stats {
+            debug!(
+                %transfer_id,
+                provider = %s.provider,
+                chunks = s.chunks,
+                bytes = s.bytes,
+                failures = s.failures,
+                dropped = s.dropped,
+                "provider contribution"
+            );
+        }
+        info!(
+            "File downloaded from {} providers: {} -> {}",
+            stats.iter().filter(|s| s.chunks > 0).count(),
+            file_hash,
+            output_path
+        );
+        Ok(DeliveredFile {
+            size: manifest.file_size,
+            first_byte_at,
+            // The manifest only proves the chunks match its own root; a file
+            // requested by content hash is checked once assembled
+            expected_hash: (manifest.merkle_root != file_hash).then(|| file_hash.to_string()),
+            sources: stats,
+        })
+    }
+
+    fn verification_error(e: ChunkError) -> String {
+        ServiceError::new(
+            ErrorCode::Verification,
+            format!("Stored file failed verification: {}", e),
+        )
+        .into()
+    }
+
+    /// Downloads that were interrupted before completing, oldest first
+    pub async fn pending_resumes(&self) -> Vec<ResumeRecord> {
+        ResumeStore::in_storage_dir(&self.storage_dir).pending().await
+    }
+
+    /// Check stored data against its chunk manifest and reassemble it from
+    /// the verified chunks. Blobs stored before chunking have no manifest and
+    /// are passed through unchanged.
+    async fn verify_chunks(
+        storage_dir: &Path,
+        file_hash: &str,
+        data: Vec<u8>,
+    ) -> Result<Vec<u8>, String> {
+        let Some(manifest) = chunking::load_manifest(storage_dir, file_hash).await? else {
+            debug!(hash = %file_hash, "no chunk manifest, skipping chunk verification");
+            return Ok(data);
+        };
+        let _span = trace_span!(
+            target: PROFILE_TARGET,
+            "verify_chunks",
+            chunks = manifest.chunk_count()
+        )
+        .entered();
+        chunking::verify_and_assemble(&manifest, &data).map_err(Self::verification_error)
     }
 
     async fn get_decryption_key_for_file(
@@ -841,11 +3088,36 @@ impl FileTransferService {
         None
     }
 
+    /// Legacy hex SHA-256 identifier of `data`
     pub fn calculate_file_hash(data: &[u8]) -> String {
-        use sha2::{Digest, Sha256};
-        let mut hasher = Sha256::new();
-        hasher.update(data);
-        format!("{:x}", hasher.finalize())
+        Self::calculate_file_hash_with(HashAlgorithm::Sha256, data)
+    }
+
+    /// Identifier of `data` under `algorithm`, see `hashing`
+    pub fn calculate_file_hash_with(algorithm: HashAlgorithm, data: &[u8]) -> String {
+        let _span =
+            trace_span!(target: PROFILE_TARGET, "hash_file", %algorithm, bytes = data.len())
+                .entered();
+        algorithm.hash(data).to_string()
+    }
+
+    /// Key a file named by `id` is stored under, trying the other spelling
+    /// of a SHA-256 hash when `id` itself is not stored
+    async fn resolve_stored_hash(blobs: &BlobStore, id: &str) -> Option<String> {
+        if blobs.contains(id).await {
+            return Some(id.to_string());
+        }
+        if let Some(key) = blobs.resolve_alias(id).await {
+            if blobs.contains(&key).await {
+                return Some(key);
+            }
+        }
+        for key in ContentHash::parse(id)?.lookup_keys() {
+            if blobs.contains(&key).await {
+                return Some(key);
+            }
+        }
+        None
     }
 
     pub async fn upload_file_with_account(
@@ -854,16 +3126,136 @@ impl FileTransferService {
         file_name: String,
         active_account: Option<String>,
         active_private_key: Option<String>,
-    ) -> Result<(), String> {
-        self.cmd_tx
-            .send(FileTransferCommand::UploadFile {
-                file_path,
-                file_name,
-                active_account,
-                active_private_key,
-            })
-            .await
-            .map_err(|e| e.to_string())
+    ) -> Result<String, String> {
+        let transfer_id = new_transfer_id();
+        self.send_command(FileTransferCommand::UploadFile {
+            transfer_id: transfer_id.clone(),
+            file_path,
+            file_name,
+            recipient_public_key: None,
+            expires_at: None,
+            in_place: false,
+            active_account,
+            active_private_key,
+            reply: None,
+        })
+        .await?;
+        Ok(transfer_id)
+    }
+
+    /// Upload a file and wait until it is stored. Returns its hash, or the
+    /// error the upload failed with. Events are reported as for
+    /// `upload_file_with_account`.
+    pub async fn upload_file_and_wait(
+        &self,
+        file_path: String,
+        file_name: String,
+        active_account: Option<String>,
+        active_private_key: Option<String>,
+    ) -> Result<String, String> {
+        let (reply, done) = oneshot::channel();
+        self.send_command(FileTransferCommand::UploadFile {
+            transfer_id: new_transfer_id(),
+            file_path,
+            file_name,
+            recipient_public_key: None,
+            expires_at: None,
+            in_place: false,
+            active_account,
+            active_private_key,
+            reply: Some(reply),
+        })
+        .await?;
+        done.await.map_err(|_| {
+            String::from(ServiceError::new(
+                ErrorCode::ServiceUnavailable,
+                "File transfer service stopped before the upload finished",
+            ))
+        })?
+    }
+
+    /// Share a file without copying it into the store: it is hashed and
+    /// indexed where it is, and served from there for as long as it stays
+    /// unchanged. Waits until it is indexed and returns its hash. Encrypted
+    /// uploads need a copy, so this fails while upload encryption is on.
+    pub async fn upload_file_in_place(
+        &self,
+        file_path: String,
+        file_name: String,
+    ) -> Result<String, String> {
+        let (reply, done) = oneshot::channel();
+        self.send_command(FileTransferCommand::UploadFile {
+            transfer_id: new_transfer_id(),
+            file_path,
+            file_name,
+            recipient_public_key: None,
+            expires_at: None,
+            in_place: true,
+            active_account: None,
+            active_private_key: None,
+            reply: Some(reply),
+        })
+        .await?;
+        done.await.map_err(|_| {
+            String::from(ServiceError::new(
+                ErrorCode::ServiceUnavailable,
+                "File transfer service stopped before the file was indexed",
+            ))
+        })?
+    }
+
+    /// Upload a file that is deleted from storage once `expires_at` (Unix
+    /// seconds) has passed, unless it is pinned by then
+    pub async fn upload_file_with_expiry(
+        &self,
+        file_path: String,
+        file_name: String,
+        expires_at: u64,
+        active_account: Option<String>,
+        active_private_key: Option<String>,
+    ) -> Result<String, String> {
+        let transfer_id = new_transfer_id();
+        self.send_command(FileTransferCommand::UploadFile {
+            transfer_id: transfer_id.clone(),
+            file_path,
+            file_name,
+            recipient_public_key: None,
+            expires_at: Some(expires_at),
+            in_place: false,
+            active_account,
+            active_private_key,
+            reply: None,
+        })
+        .await?;
+        Ok(transfer_id)
+    }
+
+    /// Upload a file encrypted for the owner of `recipient_public_key`, an
+    /// X25519 key in hex (see `recipient::public_key_for`). The hash
+    /// reported by `FileUploaded` names the encrypted file; only the
+    /// recipient's downloads decrypt it.
+    pub async fn upload_file_for_recipient(
+        &self,
+        file_path: String,
+        file_name: String,
+        recipient_public_key: String,
+    ) -> Result<String, String> {
+        recipient::parse_public_key(&recipient_public_key)
+            .map_err(|e| ServiceError::new(ErrorCode::InvalidInput, e).to_string())?;
+        let transfer_id = new_transfer_id();
+        self.send_command(FileTransferCommand::UploadFile {
+            transfer_id: transfer_id.clone(),
+            file_path,
+            file_name,
+            recipient_public_key: Some(recipient_public_key),
+            expires_at: None,
+            in_place: false,
+            active_account: None,
+            active_private_key: None,
+            reply: None,
+        })
+        .await?;
+        Ok(transfer_id)
     }
 
     pub async fn download_file_with_account(
@@ -872,127 +3264,916 @@ impl FileTransferService {
         output_path: String,
         active_account: Option<String>,
         active_private_key: Option<String>,
-    ) -> Result<(), String> {
-        self.cmd_tx
-            .send(FileTransferCommand::DownloadFile {
-                file_hash,
-                output_path,
-                active_account,
-                active_private_key,
-            })
-            .await
-            .map_err(|e| e.to_string())
+    ) -> Result<String, String> {
+        let transfer_id = new_transfer_id();
+        self.send_command(FileTransferCommand::DownloadFile {
+            transfer_id: transfer_id.clone(),
+            file_hash,
+            output_path,
+            providers: Vec::new(),
+            retry_policy: None,
+            priority: TransferPriority::Normal,
+            streaming: false,
+            active_account,
+            active_private_key,
+        })
+        .await?;
+        Ok(transfer_id)
     }
 
-    pub async fn get_stored_files(&self) -> Result<Vec<(String, String)>, String> {
-        let mut files = Vec::new();
+    /// Download a file that may not be stored locally, fetching its chunks
+    /// from `providers` in parallel. `retry_policy` replaces the service's
+    /// default for this download only. A file encrypted for this account is
+    /// decrypted with `active_private_key`.
+    pub async fn download_file_from_providers(
+        &self,
+        file_hash: String,
+        output_path: String,
+        providers: Vec<String>,
+        retry_policy: Option<RetryPolicy>,
+        priority: TransferPriority,
+        active_private_key: Option<String>,
+    ) -> Result<String, String> {
+        self.queue_remote_download(
+            file_hash,
+            output_path,
+            providers,
+            retry_policy,
+            priority,
+            false,
+            active_private_key,
+        )
+        .await
+    }
 
-        // Read all .meta files in storage directory
-        let mut entries = tokio::fs::read_dir(&self.storage_dir)
-            .await
-            .map_err(|e| format!("Failed to read storage directory: {}", e))?;
+    /// Like `download_file_from_providers`, but chunks are fetched from the
+    /// start of the file onwards and `StreamReadable` events report how much
+    /// of `<output>.part` can be read, so a media player can start before
+    /// the download finishes.
+    pub async fn stream_file_from_providers(
+        &self,
+        file_hash: String,
+        output_path: String,
+        providers: Vec<String>,
+        retry_policy: Option<RetryPolicy>,
+        priority: TransferPriority,
+        active_private_key: Option<String>,
+    ) -> Result<String, String> {
+        self.queue_remote_download(
+            file_hash,
+            output_path,
+            providers,
+            retry_policy,
+            priority,
+            true,
+            active_private_key,
+        )
+        .await
+    }
 
-        while let Some(entry) = entries
-            .next_entry()
-            .await
-            .map_err(|e| format!("Failed to read directory entry: {}", e))?
-        {
-            let path = entry.path();
-            if let Some(extension) = path.extension() {
-                if extension == "meta" {
-                    if let Some(file_hash) = path.file_stem() {
-                        let metadata_content = tokio::fs::read_to_string(&path)
-                            .await
-                            .map_err(|e| format!("Failed to read metadata file: {}", e))?;
-
-                        let metadata: serde_json::Value =
-                            serde_json::from_str(&metadata_content)
-                                .map_err(|e| format!("Failed to parse metadata: {}", e))?;
-
-                        if let (Some(file_name), Some(_)) =
-                            (metadata.get("file_name"), metadata.get("file_size"))
-                        {
-                            if let (Some(name_str), Some(hash_str)) =
-                                (file_name.as_str(), file_hash.to_str())
-                            {
-                                files.push((hash_str.to_string(), name_str.to_string()));
-                            }
-                        }
-                    }
-                }
-            }
+    #[allow(clippy::too_many_arguments)]
+    async fn queue_remote_download(
+        &self,
+        file_hash: String,
+        output_path: String,
+        providers: Vec<String>,
+        retry_policy: Option<RetryPolicy>,
+        priority: TransferPriority,
+        streaming: bool,
+        active_private_key: Option<String>,
+    ) -> Result<String, String> {
+        if let Some(policy) = &retry_policy {
+            policy.validate()?;
         }
-
-        Ok(files)
+        let transfer_id = new_transfer_id();
+        self.send_command(FileTransferCommand::DownloadFile {
+            transfer_id: transfer_id.clone(),
+            file_hash,
+            output_path,
+            providers,
+            retry_policy,
+            priority,
+            streaming,
+            active_account: None,
+            active_private_key,
+        })
+        .await?;
+        Ok(transfer_id)
     }
 
-    pub async fn drain_events(&self, max: usize) -> Vec<FileTransferEvent> {
-        let mut events = Vec::new();
-        let mut event_rx = self.event_rx.lock().await;
-
-        for _ in 0..max {
-            match event_rx.try_recv() {
-                Ok(event) => events.push(event),
-                Err(_) => break,
+    /// Download a file into `dir` under a name picked by `rules` instead of
+    /// an explicit output path. Nothing is downloaded when the name is taken
+    /// and the collision policy is to skip.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn download_file_named(
+        &self,
+        file_hash: String,
+        file_name: &str,
+        dir: &Path,
+        rules: &NamingRules,
+        providers: Vec<String>,
+        retry_policy: Option<RetryPolicy>,
+        priority: TransferPriority,
+        active_private_key: Option<String>,
+    ) -> Result<NamedDownload, String> {
+        tokio::fs::create_dir_all(dir).await.map_err(io_error(
+            ErrorCode::WriteFailed,
+            format!("Failed to create {}", dir.display()),
+        ))?;
+        let output_path = match naming::resolve(dir, file_name, &file_hash, rules)? {
+            OutputTarget::Skip(path) => {
+                return Ok(NamedDownload {
+                    transfer_id: None,
+                    output_path: path.to_string_lossy().into_owned(),
+                })
             }
+            OutputTarget::Write(path) => path.to_string_lossy().into_owned(),
+        };
+        let transfer_id = self
+            .download_file_from_providers(
+                file_hash,
+                output_path.clone(),
+                providers,
+                retry_policy,
+                priority,
+                active_private_key,
+            )
+            .await?;
+        Ok(NamedDownload {
+            transfer_id: Some(transfer_id),
+            output_path,
+        })
+    }
+
+    /// Download several files as one batch that shares the download queue.
+    /// Each file is reported under its own transfer ID, `<batch id>/<index>`;
+    /// the batch as a whole with `BatchProgress` after each file and
+    /// `BatchCompleted` at the end. Returns the batch ID.
+    pub async fn download_batch(
+        &self,
+        items: Vec<BatchItem>,
+        providers: Vec<String>,
+        retry_policy: Option<RetryPolicy>,
+        priority: TransferPriority,
+        active_private_key: Option<String>,
+    ) -> Result<String, String> {
+        if items.is_empty() {
+            return Err(
+                ServiceError::new(ErrorCode::InvalidInput, "No files given to download").into(),
+            );
         }
+        if let Some(policy) = &retry_policy {
+            policy.validate()?;
+        }
+        let batch_id = new_transfer_id();
+        self.send_command(FileTransferCommand::DownloadBatch {
+            batch_id: batch_id.clone(),
+            items,
+            providers,
+            retry_policy,
+            priority,
+            active_private_key,
+        })
+        .await?;
+        Ok(batch_id)
+    }
 
-        events
+    /// Share a whole directory. The folder's identifier, the hash of its
+    /// signed manifest, arrives with the `FolderUploaded` event.
+    pub async fn upload_directory(
+        &self,
+        dir_path: String,
+        active_account: Option<String>,
+        active_private_key: Option<String>,
+    ) -> Result<String, String> {
+        let transfer_id = new_transfer_id();
+        self.send_command(FileTransferCommand::UploadDirectory {
+            transfer_id: transfer_id.clone(),
+            dir_path,
+            active_account,
+            active_private_key,
+        })
+        .await?;
+        Ok(transfer_id)
     }
 
-    pub async fn store_file_data(&self, file_hash: String, file_name: String, file_data: Vec<u8>) {
-        let file_path = self.storage_dir.join(&file_hash);
-        if let Err(e) = tokio::fs::write(&file_path, &file_data).await {
-            error!("Failed to store file data: {}", e);
-            return;
+    /// Download a shared folder into `output_dir`, recreating its tree.
+    /// Each file becomes a download of its own, `<transfer_id>/<index>`.
+    pub async fn download_directory(
+        &self,
+        manifest_hash: String,
+        output_dir: String,
+        providers: Vec<String>,
+        retry_policy: Option<RetryPolicy>,
+        priority: TransferPriority,
+    ) -> Result<String, String> {
+        if let Some(policy) = &retry_policy {
+            policy.validate()?;
         }
+        let transfer_id = new_transfer_id();
+        self.send_command(FileTransferCommand::DownloadDirectory {
+            transfer_id: transfer_id.clone(),
+            manifest_hash,
+            output_dir,
+            providers,
+            retry_policy,
+            priority,
+            active_account: None,
+            active_private_key: None,
+        })
+        .await?;
+        Ok(transfer_id)
+    }
 
-        // Store metadata
-        let metadata = serde_json::json!({
-            "file_name": file_name,
-            "file_size": file_data.len(),
-            "uploaded_at": SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs(),
-        });
-        let metadata_path = self.storage_dir.join(format!("{}.meta", file_hash));
-        if let Err(e) =
-            tokio::fs::write(&metadata_path, serde_json::to_string(&metadata).unwrap()).await
-        {
-            error!("Failed to store metadata: {}", e);
-        }
+    /// Ask a running or paused download to stop. The service answers with
+    /// a `Cancelled` event once the download has wound down.
+    pub async fn cancel_transfer(&self, transfer_id: String) -> Result<(), String> {
+        self.send_command(FileTransferCommand::CancelTransfer { transfer_id })
+            .await
     }
 
-    pub async fn get_file_data(&self, file_hash: &str) -> Option<Vec<u8>> {
-        let file_path = self.storage_dir.join(file_hash);
-        match tokio::fs::read(&file_path).await {
-            Ok(data) => Some(data),
-            Err(_) => None,
-        }
+    /// Halt a running download, keeping its progress. The service answers
+    /// with a `Paused` event.
+    pub async fn pause_transfer(&self, transfer_id: String) -> Result<(), String> {
+        self.send_command(FileTransferCommand::PauseTransfer { transfer_id })
+            .await
     }
 
-    pub async fn download_metrics_snapshot(&self) -> DownloadMetricsSnapshot {
-        let metrics = self.download_metrics.lock().await;
-        metrics.snapshot()
+    pub async fn resume_transfer(&self, transfer_id: String) -> Result<(), String> {
+        self.send_command(FileTransferCommand::ResumeTransfer { transfer_id })
+            .await
     }
 
-    pub fn get_storage_path(&self) -> &PathBuf {
-        &self.storage_dir
+    async fn send_command(&self, command: FileTransferCommand) -> Result<(), String> {
+        self.cmd_tx.send(command).await.map_err(String::from)
     }
-}
 
-#[cfg(test)]
-mod tests {
+    /// How deep the command queue is and how often it was full
+    pub fn command_queue_stats(&self) -> CommandQueueStats {
+        self.cmd_tx.stats()
+    }
+
+    /// Move a download that is still waiting in the queue
+    pub async fn set_transfer_priority(
+        &self,
+        transfer_id: String,
+        priority: TransferPriority,
+    ) -> Result<(), String> {
+        self.send_command(FileTransferCommand::SetPriority {
+            transfer_id,
+            priority,
+        })
+        .await
+    }
+
+    pub async fn set_max_concurrent_transfers(&self, limit: usize) -> Result<(), String> {
+        if limit == 0 {
+            return Err(ServiceError::new(
+                ErrorCode::InvalidInput,
+                "At least one transfer must be allowed to run",
+            )
+            .into());
+        }
+        self.send_command(FileTransferCommand::SetMaxConcurrentTransfers { limit })
+            .await
+    }
+
+    pub async fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy.lock().await.clone()
+    }
+
+    /// Default retry policy for downloads started from now on
+    pub async fn set_retry_policy(&self, policy: RetryPolicy) -> Result<(), String> {
+        policy.validate()?;
+        *self.retry_policy.lock().await = policy;
+        Ok(())
+    }
+
+    pub async fn hash_algorithm(&self) -> HashAlgorithm {
+        *self.hash_algorithm.lock().await
+    }
+
+    /// Algorithm that names files uploaded from now on. Files already
+    /// stored keep their identifiers.
+    pub async fn set_hash_algorithm(&self, algorithm: HashAlgorithm) {
+        *self.hash_algorithm.lock().await = algorithm;
+    }
+
+    /// Transport used to reach remote providers
+    pub async fn set_chunk_fetcher(&self, fetcher: Arc<dyn ChunkFetcher>) {
+        *self.chunk_fetcher.lock().await = Some(fetcher);
+    }
+
+    /// Every stored file with its metadata, pin state and how often it was
+    /// served, newest upload first
+    pub async fn get_stored_files(&self) -> Result<Vec<StoredFile>, String> {
+        let blobs: HashMap<String, crate::blob_store::BlobEntry> = self
+            .blobs
+            .list()
+            .await
+            .into_iter()
+            .map(|blob| (blob.hash.clone(), blob))
+            .collect();
+        let demand = crate::demand_stats::global();
+
+        let mut files = Vec::new();
+        for mut entry in crate::local_library::load_entries(&self.storage_dir, false).await? {
+            let blob = blobs.get(&entry.file_hash);
+            if entry.mime_type.is_none() {
+                // Read through the store so sealed blobs are sniffed decrypted
+                let len = entry.file_size.min(MIME_SNIFF_LEN) as usize;
+                if let Ok(Some(head)) = self.blobs.read_range(&entry.file_hash, 0, len).await {
+                    entry.mime_type = crate::content_info::detect_mime(&head).map(str::to_string);
+                }
+            }
+            if entry.uploaded_at == 0 {
+                entry.uploaded_at = blob.map_or(0, |blob| blob.stored_at);
+            }
+            let times_served = demand
+                .get(&entry.file_hash)
+                .map_or(0, |d| d.requests.saturating_sub(d.unserved));
+            files.push(StoredFile {
+                pinned: blob.is_some_and(|blob| blob.pinned),
+                expires_at: blob.and_then(|blob| blob.expires_at),
+                times_served,
+                entry,
+            });
+        }
+        files.sort_by(|a, b| {
+            b.entry
+                .uploaded_at
+                .cmp(&a.entry.uploaded_at)
+                .then_with(|| a.entry.file_name.cmp(&b.entry.file_name))
+        });
+        Ok(files)
+    }
+
+    /// Ranked search over stored files (see `local_library`)
+    pub async fn search_stored_files(
+        &self,
+        query: &crate::local_library::LibraryQuery,
+    ) -> Result<crate::local_library::LibrarySearchResult, String> {
+        crate::local_library::search_dir(&self.storage_dir, query).await
+    }
+
+    pub async fn set_stored_file_tags(
+        &self,
+        file_hash: &str,
+        tags: &[String],
+    ) -> Result<Vec<String>, String> {
+        crate::local_library::set_tags(&self.storage_dir, file_hash, tags).await
+    }
+
+    pub async fn drain_events(&self, max: usize) -> Vec<FileTransferEvent> {
+        let mut events = Vec::new();
+        let mut event_rx = self.event_rx.lock().await;
+
+        for _ in 0..max {
+            match event_rx.try_recv() {
+                Ok(event) => events.push(event),
+                Err(_) => break,
+            }
+        }
+
+        events
+    }
+
+    pub async fn store_file_data(&self, file_hash: String, file_name: String, file_data: Vec<u8>) {
+        if let Err(e) = self.blobs.put(&file_hash, &file_data).await {
+            error!("Failed to store file data: {}", e);
+            return;
+        }
+
+        // Store metadata
+        let metadata = serde_json::json!({
+            "file_name": file_name,
+            "file_size": file_data.len(),
+            "uploaded_at": SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            "mime_type": crate::content_info::detect_mime(&file_data),
+            "extension": crate::content_info::extension(&file_name),
+        });
+        let metadata_path = self.storage_dir.join(format!("{}.meta", file_hash));
+        if let Err(e) =
+            tokio::fs::write(&metadata_path, serde_json::to_string(&metadata).unwrap()).await
+        {
+            error!("Failed to store metadata: {}", e);
+        }
+    }
+
+    pub async fn get_file_data(&self, file_hash: &str) -> Option<Vec<u8>> {
+        let key = Self::resolve_stored_hash(&self.blobs, file_hash).await?;
+        self.blobs.get(&key).await.ok().flatten()
+    }
+
+    /// Size of a stored file, from the blob index
+    pub async fn stored_file_size(&self, file_hash: &str) -> Option<u64> {
+        let key = Self::resolve_stored_hash(&self.blobs, file_hash).await?;
+        self.blobs.entry(&key).await.map(|entry| entry.size)
+    }
+
+    /// Piece `index` of a stored file cut into `chunk_size` pieces. Only
+    /// that piece is read, so serving a chunk to a peer never holds the
+    /// whole file in memory. `Ok(None)` if the file isn't stored or has no
+    /// such piece.
+    pub async fn read_file_chunk(
+        &self,
+        file_hash: &str,
+        index: u64,
+        chunk_size: usize,
+    ) -> Result<Option<Vec<u8>>, String> {
+        let location = self.locate_chunk(file_hash, index, chunk_size).await;
+        let Some((key, offset, len)) = location else {
+            return Ok(None);
+        };
+        self.blobs.read_range(&key, offset, len).await
+    }
+
+    /// Like `read_file_chunk`, but into `buf`, typically one taken from
+    /// `buffers::chunk_buffers`. False if there is no such piece.
+    pub async fn read_file_chunk_into(
+        &self,
+        file_hash: &str,
+        index: u64,
+        chunk_size: usize,
+        buf: &mut BytesMut,
+    ) -> Result<bool, String> {
+        let location = self.locate_chunk(file_hash, index, chunk_size).await;
+        let Some((key, offset, len)) = location else {
+            buf.clear();
+            return Ok(false);
+        };
+        self.blobs.read_range_into(&key, offset, len, buf).await
+    }
+
+    /// Blob key, offset and length of piece `index` of a stored file
+    async fn locate_chunk(
+        &self,
+        file_hash: &str,
+        index: u64,
+        chunk_size: usize,
+    ) -> Option<(String, u64, usize)> {
+        let key = Self::resolve_stored_hash(&self.blobs, file_hash).await?;
+        let size = self.blobs.entry(&key).await?.size;
+        let offset = index.saturating_mul(chunk_size as u64);
+        if offset >= size {
+            return None;
+        }
+        let len = (size - offset).min(chunk_size as u64) as usize;
+        Some((key, offset, len))
+    }
+
+    /// The blob store files are kept in, for services that serve or
+    /// receive stored files themselves
+    pub fn blob_store(&self) -> Arc<BlobStore> {
+        self.blobs.clone()
+    }
+
+    /// Index entries of every stored blob, with their pin state
+    pub async fn stored_blobs(&self) -> Vec<crate::blob_store::BlobEntry> {
+        self.blobs.list().await
+    }
+
+    /// Keep a stored file when storage is trimmed or garbage collected
+    pub async fn pin_file(&self, file_hash: &str) -> Result<crate::blob_store::BlobEntry, String> {
+        self.set_pinned(file_hash, true).await
+    }
+
+    pub async fn unpin_file(
+        &self,
+        file_hash: &str,
+    ) -> Result<crate::blob_store::BlobEntry, String> {
+        self.set_pinned(file_hash, false).await
+    }
+
+    async fn set_pinned(
+        &self,
+        file_hash: &str,
+        pinned: bool,
+    ) -> Result<crate::blob_store::BlobEntry, String> {
+        let key = self.stored_key(file_hash).await?;
+        self.blobs
+            .set_pinned(&key, pinned)
+            .await?
+            .ok_or_else(|| Self::not_stored(file_hash))
+    }
+
+    /// Set or clear when a stored file is garbage collected, in Unix seconds
+    pub async fn set_file_expiry(
+        &self,
+        file_hash: &str,
+        expires_at: Option<u64>,
+    ) -> Result<crate::blob_store::BlobEntry, String> {
+        let key = self.stored_key(file_hash).await?;
+        self.blobs
+            .set_expiry(&key, expires_at)
+            .await?
+            .ok_or_else(|| Self::not_stored(file_hash))
+    }
+
+    /// Stop seeding a stored file: its blob and metadata are deleted and the
+    /// provider record is withdrawn. Completion is reported with
+    /// `FileRemoved`.
+    pub async fn remove_file(&self, file_hash: &str) -> Result<(), String> {
+        let file_hash = self.stored_key(file_hash).await?;
+        self.send_command(FileTransferCommand::RemoveFile { file_hash })
+            .await
+    }
+
+    /// Delete expired files now instead of waiting for the next scheduled
+    /// collection. Each deletion is reported with `FileExpired`.
+    pub async fn collect_expired_files(&self) -> Result<(), String> {
+        self.send_command(FileTransferCommand::CollectExpired).await
+    }
+
+    /// Let a stored file be found by the id it was published under, e.g. its
+    /// merkle root
+    pub async fn record_published_id(
+        &self,
+        published_id: &str,
+        file_hash: &str,
+    ) -> Result<(), String> {
+        let key = self.stored_key(file_hash).await?;
+        self.blobs.add_alias(published_id, &key).await
+    }
+
+    /// Move a file published as `published_id` into `trash`: its blob, if
+    /// this node stores it, and the metadata kept next to it
+    pub async fn trash_file(
+        &self,
+        trash: &crate::file_trash::FileTrash,
+        published_id: &str,
+        file_name: &str,
+        metadata: Option<crate::dht::FileMetadata>,
+    ) -> Result<crate::file_trash::TrashEntry, String> {
+        let key = Self::resolve_stored_hash(&self.blobs, published_id).await;
+        let paths: Vec<PathBuf> = key
+            .iter()
+            .flat_map(|key| {
+                [
+                    self.storage_dir.join(format!("{}.meta", key)),
+                    self.storage_dir.join(format!("{}.encmeta", key)),
+                ]
+            })
+            .collect();
+        let blob = key.as_deref().map(|key| (self.blobs.as_ref(), key));
+        trash
+            .move_in(published_id, file_name, blob, &paths, metadata)
+            .await
+    }
+
+    /// Identical and near-duplicate stored files; see
+    /// [`scan`](crate::library_dedup::scan)
+    pub async fn find_duplicate_files(
+        &self,
+        min_overlap: f64,
+    ) -> Result<crate::library_dedup::DuplicateReport, String> {
+        crate::library_dedup::scan(&self.blobs, min_overlap).await
+    }
+
+    /// Store identical copies of `keep` once; see
+    /// [`merge_identical`](crate::library_dedup::merge_identical)
+    pub async fn merge_duplicate_files(
+        &self,
+        keep: &str,
+        duplicates: &[String],
+    ) -> Result<crate::library_dedup::MergeResult, String> {
+        crate::library_dedup::merge_identical(&self.blobs, keep, duplicates).await
+    }
+
+    /// Put a trashed file back into storage
+    pub async fn restore_trashed_file(
+        &self,
+        trash: &crate::file_trash::FileTrash,
+        id: &str,
+    ) -> Result<crate::file_trash::TrashEntry, String> {
+        trash.restore(id, &self.blobs).await
+    }
+
+    async fn stored_key(&self, file_hash: &str) -> Result<String, String> {
+        Self::resolve_stored_hash(&self.blobs, file_hash)
+            .await
+            .ok_or_else(|| Self::not_stored(file_hash))
+    }
+
+    fn not_stored(file_hash: &str) -> String {
+        ServiceError::new(
+            ErrorCode::NotFound,
+            format!("File {} is not stored", file_hash),
+        )
+        .into()
+    }
+
+    /// Delete unpinned stored files, oldest first, until they take at most
+    /// `max_bytes`. Returns the hashes of the files deleted.
+    /// Each deletion is reported with `FileRemoved`.
+    pub async fn trim_storage(&self, max_bytes: u64) -> Result<Vec<String>, String> {
+        let (reply, done) = oneshot::channel();
+        self.send_command(FileTransferCommand::TrimStorage { max_bytes, reply })
+            .await?;
+        done.await
+            .map_err(|_| "File transfer service stopped before trimming storage".to_string())
+    }
+
+    async fn trim(ctx: &DownloadContext, max_bytes: u64) -> Vec<String> {
+        let mut hashes = Vec::new();
+        for entry in ctx.blobs.eviction_candidates(max_bytes).await {
+            // Pinned in the meantime
+            if ctx.blobs.entry(&entry.hash).await.is_none_or(|e| e.pinned) {
+                continue;
+            }
+            if Self::remove_stored_file(ctx, &entry.hash).await {
+                hashes.push(entry.hash);
+            }
+        }
+        if !hashes.is_empty() {
+            info!(count = hashes.len(), max_bytes, "trimmed stored files");
+        }
+        hashes
+    }
+
+    /// Delete stored files whose expiry has passed, reporting each with
+    /// `FileExpired`. Pinned files never expire.
+    async fn collect_expired(ctx: &DownloadContext, now: u64) {
+        for entry in ctx.blobs.expired(now).await {
+            let Some(published_ids) = Self::delete_stored_file(ctx, &entry.hash).await else {
+                continue;
+            };
+            info!(hash = %entry.hash, "deleted expired file");
+            let _ = ctx
+                .event_tx
+                .send(FileTransferEvent::FileExpired {
+                    expires_at: entry.expires_at.unwrap_or(now),
+                    file_hash: entry.hash,
+                    published_ids,
+                })
+                .await;
+        }
+    }
+
+    /// Delete a stored file, withdraw its provider record and report it
+    /// with `FileRemoved`. Returns whether it was deleted.
+    async fn remove_stored_file(ctx: &DownloadContext, file_hash: &str) -> bool {
+        let Some(key) = Self::resolve_stored_hash(&ctx.blobs, file_hash).await else {
+            warn!(hash = %file_hash, "no stored file to remove");
+            return false;
+        };
+        let Some(published_ids) = Self::delete_stored_file(ctx, &key).await else {
+            return false;
+        };
+        info!(hash = %key, "removed stored file");
+        let _ = ctx
+            .event_tx
+            .send(FileTransferEvent::FileRemoved {
+                file_hash: key,
+                published_ids,
+            })
+            .await;
+        true
+    }
+
+    /// Delete the blob stored as `key` with its sidecars and withdraw the
+    /// provider records for it. Returns the other ids it was published
+    /// under, or `None` if it couldn't be deleted.
+    async fn delete_stored_file(ctx: &DownloadContext, key: &str) -> Option<Vec<String>> {
+        // Gone with the blob, so looked up first
+        let published_ids = ctx.blobs.aliases_of(key).await;
+        if let Err(e) = ctx.blobs.remove(key).await {
+            warn!(hash = %key, "failed to delete stored file: {}", e);
+            return None;
+        }
+        Self::remove_sidecars(ctx.blobs.root(), key).await;
+        // Without a fetcher the node never joined the swarm, so there is no
+        // provider record to withdraw
+        let fetcher = ctx.chunk_fetcher.lock().await.clone();
+        if let Some(fetcher) = fetcher {
+            for id in std::iter::once(key).chain(published_ids.iter().map(String::as_str)) {
+                if let Err(e) = fetcher.stop_providing(id).await {
+                    warn!(hash = %id, "failed to withdraw provider record: {}", e);
+                }
+            }
+        }
+        Some(published_ids)
+    }
+
+    /// Delete the metadata files stored next to a blob
+    async fn remove_sidecars(storage_dir: &Path, file_hash: &str) {
+        for path in [
+            storage_dir.join(format!("{}.meta", file_hash)),
+            storage_dir.join(format!("{}.encmeta", file_hash)),
+            chunking::manifest_path(storage_dir, file_hash),
+        ] {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+    }
+
+    /// Bytes used by stored blobs, excluding sidecar metadata
+    pub async fn storage_bytes_used(&self) -> u64 {
+        self.blobs.total_bytes().await
+    }
+
+    /// Give the blob store the key derived from the logged in account so
+    /// blobs encrypted at rest can be read and written
+    pub async fn unlock_storage(&self, private_key: &str) -> Result<(), String> {
+        let key = crate::keystore::derive_storage_key(private_key)?;
+        self.blobs
+            .unlock(key)
+            .await
+            .map_err(|e| ServiceError::new(ErrorCode::Unauthorized, e).into())
+    }
+
+    /// Forget the storage key, e.g. on logout
+    pub fn lock_storage(&self) {
+        self.blobs.lock_key();
+    }
+
+    pub async fn encrypts_at_rest(&self) -> bool {
+        self.blobs.encrypts_at_rest().await
+    }
+
+    /// Turn encryption at rest on or off for every stored blob, unlocking
+    /// the store with `private_key` first. Returns how many blobs were
+    /// rewritten.
+    pub async fn set_encryption_at_rest(
+        &self,
+        enabled: bool,
+        private_key: &str,
+    ) -> Result<usize, String> {
+        self.unlock_storage(private_key).await?;
+        self.blobs
+            .set_encrypt_at_rest(enabled)
+            .await
+            .map_err(|e| ServiceError::new(ErrorCode::WriteFailed, e).into())
+    }
+
+    /// Re-hash every stored file and report the ones whose contents no
+    /// longer match their hash. With `repair`, those are fetched again from
+    /// other providers. Waits for the scrub to finish; the report is also
+    /// sent as a `StorageScrubbed` event.
+    pub async fn verify_storage(&self, repair: bool) -> Result<ScrubReport, String> {
+        let (reply, done) = oneshot::channel();
+        self.send_command(FileTransferCommand::VerifyStorage {
+            repair,
+            reply: Some(reply),
+        })
+        .await?;
+        done.await.map_err(|_| {
+            String::from(ServiceError::new(
+                ErrorCode::ServiceUnavailable,
+                "File transfer service stopped before the scrub finished",
+            ))
+        })?
+    }
+
+    /// Scrub storage in the background on `schedule`, or stop with `None`
+    pub async fn set_scrub_schedule(&self, schedule: Option<ScrubSchedule>) -> Result<(), String> {
+        self.send_command(FileTransferCommand::SetScrubSchedule { schedule })
+            .await
+    }
+
+    pub async fn deduplicates_storage(&self) -> bool {
+        self.blobs.deduplicates().await
+    }
+
+    /// Turn chunk-level deduplication of stored files on or off. Returns how
+    /// many blobs were moved.
+    pub async fn set_storage_deduplication(&self, enabled: bool) -> Result<usize, String> {
+        self.blobs
+            .set_deduplicate(enabled)
+            .await
+            .map_err(|e| ServiceError::new(ErrorCode::WriteFailed, e).into())
+    }
+
+    pub async fn storage_dedup_stats(&self) -> crate::chunk_store::DedupStats {
+        self.blobs.dedup_stats().await
+    }
+
+    /// Count `bytes` of a stored file as sent to `peer_id`. Serving paths
+    /// call this as each chunk or whole file goes out.
+    pub fn record_served(&self, file_hash: &str, peer_id: &str, bytes: u64) {
+        let now = self.env.clock.now_secs();
+        if let Ok(mut seeding) = self.seeding.lock() {
+            seeding.record(file_hash, peer_id, bytes, now);
+        }
+    }
+
+    /// What this node has uploaded, per file: bytes served, distinct peers
+    /// and when each file was last served. Most served first.
+    pub fn seeding_stats_snapshot(&self) -> Vec<FileSeedingStats> {
+        self.seeding
+            .lock()
+            .map(|seeding| seeding.snapshot())
+            .unwrap_or_default()
+    }
+
+    /// File hashes new downloads are refused for because downloads of them
+    /// kept failing
+    pub fn open_circuits(&self) -> Vec<OpenCircuit> {
+        let now = self.env.clock.now_secs();
+        self.circuits.lock().unwrap().open_circuits(now)
+    }
+
+    /// Accept downloads of `file_hash` again before its circuit reopens.
+    /// False if they were not being refused.
+    pub fn reset_circuit(&self, file_hash: &str) -> bool {
+        self.circuits.lock().unwrap().reset(file_hash)
+    }
+
+    pub async fn download_metrics_snapshot(&self) -> DownloadMetricsSnapshot {
+        let metrics = self.download_metrics.lock().await;
+        metrics.snapshot()
+    }
+
+    /// Snapshot that only copies the newest `limit` attempts.
+    pub async fn download_metrics_snapshot_with_limit(
+        &self,
+        limit: usize,
+    ) -> DownloadMetricsSnapshot {
+        let metrics = self.download_metrics.lock().await;
+        metrics.snapshot_with_limit(limit)
+    }
+
+    /// Counters and rolling rates without the attempt history.
+    pub async fn download_metrics_summary(&self) -> DownloadMetricsSummary {
+        let metrics = self.download_metrics.lock().await;
+        metrics.summary_at(self.env.clock.now_secs())
+    }
+
+    pub async fn attempt_retention(&self) -> AttemptRetention {
+        self.download_metrics.lock().await.retention
+    }
+
+    /// Change how much attempt history is kept; existing entries outside the
+    /// new policy are dropped immediately.
+    pub async fn set_attempt_retention(&self, retention: AttemptRetention) {
+        let now_secs = self.env.clock.now_secs();
+        let mut metrics = self.download_metrics.lock().await;
+        metrics.set_retention(retention, now_secs);
+    }
+
+    pub async fn clear_download_attempts(&self) -> usize {
+        self.download_metrics.lock().await.clear_recent()
+    }
+
+    /// Write the retained attempt history (newest first) to `path` as JSON.
+    pub async fn export_download_attempts(&self, path: &Path) -> Result<usize, String> {
+        let attempts = {
+            let metrics = self.download_metrics.lock().await;
+            metrics.recent(metrics.recent_attempts.len())
+        };
+        let json = serde_json::to_vec_pretty(&attempts).map_err(|e| {
+            ServiceError::new(
+                ErrorCode::Internal,
+                format!("Failed to serialize download attempts: {}", e),
+            )
+        })?;
+        tokio::fs::write(path, json).await.map_err(|e| {
+            ServiceError::new(
+                ErrorCode::WriteFailed,
+                format!("Failed to write download attempts: {}", e),
+            )
+        })?;
+        Ok(attempts.len())
+    }
+
+    pub fn get_storage_path(&self) -> &PathBuf {
+        &self.storage_dir
+    }
+}
+
+#[cfg(test)]
+mod tests {
     use super::*;
+    use crate::runtime_env::{FlakyFs, ManualClock};
     use std::sync::Arc;
     use tempfile::tempdir;
     use tokio::sync::{mpsc, Mutex};
 
+    fn attempts_seen(
+        event_rx: &mut mpsc::Receiver<FileTransferEvent>,
+    ) -> Vec<DownloadAttemptSnapshot> {
+        let mut attempts = Vec::new();
+        while let Ok(event) = event_rx.try_recv() {
+            if let FileTransferEvent::DownloadAttempt(snapshot) = event {
+                attempts.push(snapshot);
+            }
+        }
+        attempts
+    }
+
+    fn silent_progress(env: &RuntimeEnv) -> ProgressReporter {
+        ProgressReporter::silent("transfer", TransferDirection::Download, env.clock.clone())
+    }
+
     #[tokio::test]
     async fn download_retries_then_succeeds() {
-        FileTransferService::reset_retry_counters();
-        FileTransferService::set_fail_write_attempts(2);
+        let clock = ManualClock::default();
+        let fs = Arc::new(FlakyFs::failing_writes(2));
+        let env = RuntimeEnv::deterministic(42)
+            .with_clock(Arc::new(clock.clone()))
+            .with_fs(fs.clone());
 
         // Create a temporary storage directory
         let temp_dir = tempdir().expect("temp dir");
@@ -1029,16 +4210,23 @@ mod tests {
         let (event_tx, mut event_rx) = mpsc::channel(16);
         let metrics = Arc::new(Mutex::new(DownloadMetrics::default()));
 
+        let blobs = BlobStore::open(&storage_dir).await.expect("open blob store");
         let keystore = Arc::new(Mutex::new(crate::keystore::Keystore::new()));
         let result = FileTransferService::download_with_retries(
+            "transfer-1",
             test_hash,
             &output_str,
-            &storage_dir,
+            &blobs,
+            &RemoteSources::default(),
             event_tx.clone(),
             metrics.clone(),
             keystore,
             None,
             None,
+            &RetryPolicy::default(),
+            &silent_progress(&env),
+            &CancellationToken::new(),
+            &env,
         )
         .await;
 
@@ -1046,28 +4234,41 @@ mod tests {
 
         let written = tokio::fs::read(&output_path).await.expect("file read");
         assert_eq!(written, b"hello world");
-        assert_eq!(FileTransferService::last_attempts(), 3);
+        assert_eq!(fs.write_count(), 3);
+
+        // Backoff ran on the virtual clock: two jittered delays, no real sleeping
+        let sleeps = clock.sleeps();
+        assert_eq!(sleeps.len(), 2);
+        let policy = RetryPolicy::default();
+        for (attempt, delay) in (2u32..).zip(&sleeps) {
+            let base = policy.base_backoff_ms * (1 << (attempt - 1));
+            let ms = delay.as_millis() as f64;
+            assert!(ms >= base as f64 * (1.0 - policy.jitter) - 1.0, "{ms} too short");
+            assert!(ms <= base as f64 * (1.0 + policy.jitter), "{ms} too long");
+        }
 
         // Ensure we received attempt events
-        let mut statuses = Vec::new();
-        while let Ok(event) = event_rx.try_recv() {
-            if let FileTransferEvent::DownloadAttempt(snapshot) = event {
-                statuses.push(snapshot.status);
-            }
-        }
-        assert!(statuses.contains(&AttemptStatus::Retrying));
-        assert!(statuses.contains(&AttemptStatus::Success));
+        let attempts = attempts_seen(&mut event_rx);
+        assert_eq!(attempts.len(), 3);
+        assert!(attempts.iter().all(|a| a.transfer_id == "transfer-1"));
+        assert_eq!(attempts[0].status, AttemptStatus::Retrying);
+        assert_eq!(attempts[2].status, AttemptStatus::Success);
+        assert_eq!(attempts[2].timestamp, clock.now_secs());
 
         let snapshot = metrics.lock().await.snapshot();
         assert_eq!(snapshot.total_success, 1);
         assert_eq!(snapshot.total_failures, 0);
         assert_eq!(snapshot.total_retries, 2);
+        assert_eq!(snapshot.histograms.attempt_duration_ms.count, 3);
+        assert_eq!(snapshot.histograms.time_to_first_byte_ms.count, 1);
+        assert_eq!(snapshot.histograms.file_size_bytes.count, 1);
+        assert_eq!(snapshot.histograms.file_size_bytes.sum, 11);
     }
 
     #[tokio::test]
-    async fn download_fails_after_max_attempts_for_missing_file() {
-        FileTransferService::reset_retry_counters();
-        FileTransferService::set_fail_write_attempts(0);
+    async fn missing_file_fails_at_once_unless_the_policy_retries_it() {
+        let clock = ManualClock::default();
+        let env = RuntimeEnv::deterministic(7).with_clock(Arc::new(clock.clone()));
 
         // Create a temporary storage directory (empty)
         let temp_dir = tempdir().expect("temp dir");
@@ -1085,38 +4286,1414 @@ mod tests {
         let (event_tx, mut event_rx) = mpsc::channel(16);
         let metrics = Arc::new(Mutex::new(DownloadMetrics::default()));
 
+        let blobs = BlobStore::open(&storage_dir).await.expect("open blob store");
         let keystore = Arc::new(Mutex::new(crate::keystore::Keystore::new()));
         let result = FileTransferService::download_with_retries(
+            "transfer-1",
             "missing-hash",
             &output_str,
-            &storage_dir,
+            &blobs,
+            &RemoteSources::default(),
             event_tx.clone(),
             metrics.clone(),
-            keystore,
+            keystore.clone(),
             None,
             None,
+            &RetryPolicy::default(),
+            &silent_progress(&env),
+            &CancellationToken::new(),
+            &env,
         )
         .await;
 
-        assert!(result.is_err(), "expected download to fail");
-        assert_eq!(FileTransferService::last_attempts(), MAX_DOWNLOAD_ATTEMPTS);
+        // Not found is permanent: no retries, no backoff
+        let err = result.expect_err("expected download to fail");
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::NotFound);
+        let attempts = attempts_seen(&mut event_rx);
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].status, AttemptStatus::Failed);
+        assert!(clock.sleeps().is_empty());
+
+        // A policy that lists the class retries it until the budget runs out
+        let policy = RetryPolicy {
+            retry_on: Some([ErrorCode::NotFound].into_iter().collect()),
+            ..RetryPolicy::default()
+        };
+        let result = FileTransferService::download_with_retries(
+            "transfer-2",
+            "missing-hash",
+            &output_str,
+            &blobs,
+            &RemoteSources::default(),
+            event_tx.clone(),
+            metrics.clone(),
+            keystore,
+            None,
+            None,
+            &policy,
+            &silent_progress(&env),
+            &CancellationToken::new(),
+            &env,
+        )
+        .await;
+        assert!(result.is_err());
+        let attempts = attempts_seen(&mut event_rx);
+        assert_eq!(attempts.len(), policy.max_attempts as usize);
+        assert_eq!(
+            attempts.last().map(|a| a.status.clone()),
+            Some(AttemptStatus::Failed)
+        );
+        assert_eq!(clock.sleeps().len(), policy.max_attempts as usize - 1);
 
-        let mut failure_seen = false;
-        while let Ok(event) = event_rx.try_recv() {
-            if let FileTransferEvent::DownloadAttempt(snapshot) = event {
-                if matches!(snapshot.status, AttemptStatus::Failed) {
-                    failure_seen = true;
+        let snapshot = metrics.lock().await.snapshot();
+        assert_eq!(snapshot.total_success, 0);
+        assert_eq!(snapshot.total_failures, 2);
+        assert_eq!(
+            snapshot.total_retries,
+            policy.max_attempts.saturating_sub(1) as u64
+        );
+    }
+
+    #[tokio::test]
+    async fn download_fails_fast_when_the_disk_is_full() {
+        let clock = ManualClock::default();
+        let fs = Arc::new(FlakyFs::default());
+        let env = RuntimeEnv::deterministic(8)
+            .with_clock(Arc::new(clock.clone()))
+            .with_fs(fs.clone());
+        let temp_dir = tempdir().expect("temp dir");
+        let blobs = BlobStore::open(temp_dir.path())
+            .await
+            .expect("open blob store");
+        let data = b"hello world".to_vec();
+        let hash = FileTransferService::calculate_file_hash(&data);
+        blobs.put(&hash, &data).await.expect("store blob");
+
+        let output_dir = tempdir().expect("temp output dir");
+        // Not created yet: the volume of its parent is checked
+        let output_path = output_dir.path().join("nested").join("hello.txt");
+        let output_str = output_path.to_string_lossy().to_string();
+        let (event_tx, mut event_rx) = mpsc::channel(16);
+        let metrics = Arc::new(Mutex::new(DownloadMetrics::default()));
+        let keystore = Arc::new(Mutex::new(crate::keystore::Keystore::new()));
+
+        fs.set_free_space(Some(data.len() as u64 - 1));
+        let err = FileTransferService::download_with_retries(
+            "transfer-1",
+            &hash,
+            &output_str,
+            &blobs,
+            &RemoteSources::default(),
+            event_tx.clone(),
+            metrics.clone(),
+            keystore,
+            None,
+            None,
+            &RetryPolicy::default(),
+            &silent_progress(&env),
+            &CancellationToken::new(),
+            &env,
+        )
+        .await
+        .expect_err("not enough space");
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::DiskFull);
+        assert!(err.contains("11 bytes needed, 10 available"), "{err}");
+        assert_eq!(fs.write_count(), 0);
+        assert!(!output_path.exists());
+
+        // Nothing to wait for: the first attempt is the last
+        let attempts = attempts_seen(&mut event_rx);
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].status, AttemptStatus::Failed);
+        assert!(clock.sleeps().is_empty());
+    }
+
+    #[tokio::test]
+    async fn download_rejects_blob_that_no_longer_matches_its_chunks() {
+        let env = RuntimeEnv::deterministic(3);
+        let temp_dir = tempdir().expect("temp dir");
+        let storage_dir = temp_dir.path().to_path_buf();
+
+        let data = vec![7u8; chunking::CHUNK_SIZE + 10];
+        let hash = FileTransferService::calculate_file_hash(&data);
+        let blobs = BlobStore::open(&storage_dir).await.expect("open blob store");
+        blobs.put(&hash, &data).await.expect("store blob");
+        chunking::save_manifest(&storage_dir, &ChunkManifest::build(&hash, &data))
+            .await
+            .expect("store manifest");
+
+        let mut corrupted = data.clone();
+        corrupted[chunking::CHUNK_SIZE + 1] = 0;
+        blobs.put(&hash, &corrupted).await.expect("overwrite blob");
+
+        let temp_output_dir = tempdir().expect("temp output dir");
+        let output_path = temp_output_dir.path().join("out.bin");
+        let keystore = Arc::new(Mutex::new(crate::keystore::Keystore::new()));
+        let err = FileTransferService::handle_download_file(
+            "transfer-1",
+            &hash,
+            &output_path.to_string_lossy(),
+            &blobs,
+            &RemoteSources::default(),
+            &silent_progress(&env),
+            &CancellationToken::new(),
+            &keystore,
+            None,
+            None,
+            &env,
+        )
+        .await
+        .err()
+        .expect("corruption must be detected");
+
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::Verification);
+        assert!(err.contains("chunk 1"), "{err}");
+        assert!(!output_path.exists());
+    }
+
+    #[tokio::test]
+    async fn legacy_sha256_files_are_found_by_their_multihash() {
+        let env = RuntimeEnv::deterministic(4);
+        let temp_dir = tempdir().expect("temp dir");
+        let storage_dir = temp_dir.path().to_path_buf();
+
+        let data = b"stored before multihash support".to_vec();
+        let legacy = FileTransferService::calculate_file_hash(&data);
+        let blobs = BlobStore::open(&storage_dir).await.expect("open blob store");
+        blobs.put(&legacy, &data).await.expect("store blob");
+
+        let multihash = ContentHash::parse(&legacy).unwrap().to_multihash();
+        let output_dir = tempdir().expect("temp output dir");
+        let output_path = output_dir.path().join("out.bin");
+        let keystore = Arc::new(Mutex::new(crate::keystore::Keystore::new()));
+        let delivered = FileTransferService::handle_download_file(
+            "transfer-1",
+            &multihash,
+            &output_path.to_string_lossy(),
+            &blobs,
+            &RemoteSources::default(),
+            &silent_progress(&env),
+            &CancellationToken::new(),
+            &keystore,
+            None,
+            None,
+            &env,
+        )
+        .await
+        .expect("found under its legacy name");
+
+        assert_eq!(delivered.expected_hash.as_deref(), Some(legacy.as_str()));
+        assert_eq!(tokio::fs::read(&output_path).await.unwrap(), data);
+
+        let blake3 = FileTransferService::calculate_file_hash_with(HashAlgorithm::Blake3, &data);
+        assert!(blake3.starts_with("1e20"));
+        assert!(FileTransferService::resolve_stored_hash(&blobs, &blake3)
+            .await
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn interrupted_chunked_download_resumes_without_rewriting_chunks() {
+        let env = RuntimeEnv::deterministic(5);
+        let temp_dir = tempdir().expect("temp dir");
+        let storage_dir = temp_dir.path().to_path_buf();
+        let output_dir = tempdir().expect("temp output dir");
+        let output = output_dir.path().join("movie.bin");
+        let output_str = output.to_string_lossy().to_string();
+
+        let data: Vec<u8> = (0..40u32).map(|i| i as u8).collect();
+        let manifest = ChunkManifest::build_with_chunk_size("movie", &data, 10);
+
+        // First attempt dies at chunk 2 after writing chunks 0 and 1
+        let mut broken = data.clone();
+        broken[25] ^= 1;
+        let err = FileTransferService::write_chunks_resumable(
+            &env,
+            &storage_dir,
+            "transfer-1",
+            &manifest,
+            &broken,
+            &output_str,
+            &silent_progress(&env),
+            &CancellationToken::new(),
+        )
+        .await
+        .expect_err("chunk 2 is corrupt");
+        assert!(err.contains("chunk 2"), "{err}");
+        assert!(!output.exists());
+
+        let store = ResumeStore::in_storage_dir(&storage_dir);
+        let pending = store.pending().await;
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].received, vec![[0, 2]]);
+
+        // The retry only needs chunks 2 and 3: a bad chunk 0 in its source
+        // is never read because the part file already holds a verified copy
+        let mut retry_source = data.clone();
+        retry_source[0] ^= 1;
+        FileTransferService::write_chunks_resumable(
+            &env,
+            &storage_dir,
+            "transfer-2",
+            &manifest,
+            &retry_source,
+            &output_str,
+            &silent_progress(&env),
+            &CancellationToken::new(),
+        )
+        .await
+        .expect("resumed download completes");
+
+        assert_eq!(tokio::fs::read(&output).await.unwrap(), data);
+        assert!(!resume::part_path(&output_str).exists());
+        assert!(store.pending().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn stopped_download_keeps_progress_until_discarded() {
+        let env = RuntimeEnv::deterministic(11);
+        let temp_dir = tempdir().expect("temp dir");
+        let storage_dir = temp_dir.path().to_path_buf();
+        let output_dir = tempdir().expect("temp output dir");
+        let output = output_dir.path().join("movie.bin");
+        let output_str = output.to_string_lossy().to_string();
+
+        let data: Vec<u8> = (0..40u32).map(|i| i as u8).collect();
+        let manifest = ChunkManifest::build_with_chunk_size("movie", &data, 10);
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let err = FileTransferService::write_chunks_resumable(
+            &env,
+            &storage_dir,
+            "transfer-1",
+            &manifest,
+            &data,
+            &output_str,
+            &silent_progress(&env),
+            &cancel,
+        )
+        .await
+        .expect_err("cancelled before the first chunk");
+        assert_eq!(err, cancelled_error());
+        assert!(!output.exists());
+
+        // The part file stays for a pause to resume from; a cancel drops it
+        let store = ResumeStore::in_storage_dir(&storage_dir);
+        assert_eq!(store.pending().await.len(), 1);
+        assert!(resume::part_path(&output_str).exists());
+        store.discard("movie", &output_str).await;
+        assert!(store.pending().await.is_empty());
+        assert!(!resume::part_path(&output_str).exists());
+
+        // A cancelled download is not retried and records no attempts
+        let blobs = BlobStore::open(&storage_dir).await.expect("open blob store");
+        blobs.put("movie", &data).await.expect("store blob");
+        let (event_tx, mut event_rx) = mpsc::channel(16);
+        let metrics = Arc::new(Mutex::new(DownloadMetrics::default()));
+        let keystore = Arc::new(Mutex::new(crate::keystore::Keystore::new()));
+        let err = FileTransferService::download_with_retries(
+            "transfer-2",
+            "movie",
+            &output_str,
+            &blobs,
+            &RemoteSources::default(),
+            event_tx,
+            metrics.clone(),
+            keystore,
+            None,
+            None,
+            &RetryPolicy::default(),
+            &silent_progress(&env),
+            &cancel,
+            &env,
+        )
+        .await
+        .expect_err("download was cancelled");
+        assert_eq!(err, cancelled_error());
+        assert!(attempts_seen(&mut event_rx).is_empty());
+        assert_eq!(metrics.lock().await.snapshot().total_failures, 0);
+        assert!(!output.exists());
+    }
+
+    #[tokio::test]
+    async fn concurrent_downloads_of_one_hash_report_separately() {
+        let storage = tempdir().expect("temp dir");
+        let output_dir = tempdir().expect("temp output dir");
+        let keystore = Arc::new(Mutex::new(crate::keystore::Keystore::new()));
+        let service = FileTransferService::new_with_env(
+            storage.path().to_path_buf(),
+            false,
+            keystore,
+            None,
+            RuntimeEnv::deterministic(13),
+        )
+        .await
+        .expect("start service");
+
+        let mut ids = Vec::new();
+        for name in ["a.bin", "b.bin"] {
+            let output = output_dir.path().join(name).to_string_lossy().to_string();
+            ids.push(
+                service
+                    .download_file_with_account("missing-hash".to_string(), output, None, None)
+                    .await
+                    .expect("enqueue download"),
+            );
+        }
+        assert_ne!(ids[0], ids[1]);
+
+        let mut not_found = Vec::new();
+        for _ in 0..200 {
+            for event in service.drain_events(100).await {
+                if let FileTransferEvent::FileNotFound { transfer_id, .. } = event {
+                    not_found.push(transfer_id);
                 }
             }
+            if not_found.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
         }
-        assert!(failure_seen, "expected a failed attempt event");
+        not_found.sort();
+        ids.sort();
+        assert_eq!(not_found, ids);
+    }
 
-        let snapshot = metrics.lock().await.snapshot();
-        assert_eq!(snapshot.total_success, 0);
-        assert_eq!(snapshot.total_failures, 1);
+    #[tokio::test]
+    async fn hash_that_keeps_failing_is_refused_for_a_while() {
+        let storage = tempdir().expect("temp dir");
+        let output_dir = tempdir().expect("temp output dir");
+        let keystore = Arc::new(Mutex::new(crate::keystore::Keystore::new()));
+        let service = FileTransferService::new_with_env(
+            storage.path().to_path_buf(),
+            false,
+            keystore,
+            None,
+            RuntimeEnv::deterministic(17),
+        )
+        .await
+        .expect("start service");
+        let output = output_dir.path().join("a.bin");
+        let output = output.to_string_lossy().to_string();
+
+        let mut opened = None;
+        let mut refused = None;
+        for attempt in 0..4 {
+            let id = service
+                .download_file_with_account("missing-hash".to_string(), output.clone(), None, None)
+                .await
+                .expect("enqueue download");
+            let mut ended = false;
+            for _ in 0..200 {
+                for event in service.drain_events(100).await {
+                    match event {
+                        FileTransferEvent::CircuitOpen(open) => opened = Some(open),
+                        FileTransferEvent::FileNotFound { transfer_id, .. } => {
+                            ended |= transfer_id == id;
+                        }
+                        FileTransferEvent::Error {
+                            transfer_id, code, ..
+                        } if transfer_id == id => {
+                            refused = Some((attempt, code));
+                            ended = true;
+                        }
+                        _ => {}
+                    }
+                }
+                if ended {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            assert!(ended, "download {} never ended", attempt);
+        }
+
+        let opened = opened.expect("circuit opened");
+        assert_eq!(opened.file_hash, "missing-hash");
+        assert_eq!(opened.failures, 3);
+        assert_eq!(refused, Some((3, ErrorCode::NoProviders)));
+        assert_eq!(service.open_circuits(), vec![opened]);
+        assert!(service.reset_circuit("missing-hash"));
+        assert!(service.open_circuits().is_empty());
+    }
+
+    #[tokio::test]
+    async fn uploaded_folder_downloads_as_its_tree() {
+        let storage = tempdir().expect("temp dir");
+        let source = tempdir().expect("temp source dir");
+        let output_dir = tempdir().expect("temp output dir");
+        tokio::fs::create_dir_all(source.path().join("docs"))
+            .await
+            .expect("create source tree");
+        for (path, contents) in [("readme.txt", "hello"), ("docs/guide.txt", "read me")] {
+            tokio::fs::write(source.path().join(path), contents)
+                .await
+                .expect("write source file");
+        }
+
+        let keystore = Arc::new(Mutex::new(crate::keystore::Keystore::new()));
+        let service = FileTransferService::new_with_env(
+            storage.path().to_path_buf(),
+            false,
+            keystore,
+            None,
+            RuntimeEnv::deterministic(19),
+        )
+        .await
+        .expect("start service");
+        let key = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+        service
+            .upload_directory(
+                source.path().to_string_lossy().to_string(),
+                None,
+                Some(key.to_string()),
+            )
+            .await
+            .expect("start folder upload");
+
+        let mut manifest_hash = None;
+        for _ in 0..200 {
+            for event in service.drain_events(100).await {
+                if let FileTransferEvent::FolderUploaded {
+                    manifest_hash: hash,
+                    file_count,
+                    ..
+                } = event
+                {
+                    assert_eq!(file_count, 2);
+                    manifest_hash = Some(hash);
+                }
+            }
+            if manifest_hash.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let manifest_hash = manifest_hash.expect("folder uploaded");
+
+        let out = output_dir.path().join("copy");
+        let transfer_id = service
+            .download_directory(
+                manifest_hash,
+                out.to_string_lossy().to_string(),
+                Vec::new(),
+                None,
+                TransferPriority::Normal,
+            )
+            .await
+            .expect("start folder download");
+
+        let mut downloaded = Vec::new();
+        for _ in 0..200 {
+            for event in service.drain_events(100).await {
+                if let FileTransferEvent::FileDownloaded { transfer_id: id, .. } = event {
+                    downloaded.push(id);
+                }
+            }
+            if downloaded.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        downloaded.sort();
         assert_eq!(
-            snapshot.total_retries,
-            MAX_DOWNLOAD_ATTEMPTS.saturating_sub(1) as u64
+            downloaded,
+            vec![format!("{}/0", transfer_id), format!("{}/1", transfer_id)]
+        );
+        assert_eq!(
+            tokio::fs::read_to_string(out.join("docs/guide.txt"))
+                .await
+                .unwrap(),
+            "read me"
+        );
+        assert_eq!(
+            tokio::fs::read_to_string(out.join("readme.txt"))
+                .await
+                .unwrap(),
+            "hello"
+        );
+        assert!(!out.join(format!(".{}.folder", transfer_id)).exists());
+    }
+
+    #[tokio::test]
+    async fn recipient_uploads_are_stored_encrypted_and_opened_on_download() {
+        let storage = tempdir().expect("temp dir");
+        let output_dir = tempdir().expect("temp output dir");
+        let source = output_dir.path().join("letter.txt");
+        tokio::fs::write(&source, "meet at noon")
+            .await
+            .expect("write source file");
+
+        let keystore = Arc::new(Mutex::new(crate::keystore::Keystore::new()));
+        let service = FileTransferService::new_with_env(
+            storage.path().to_path_buf(),
+            false,
+            keystore,
+            None,
+            RuntimeEnv::deterministic(23),
+        )
+        .await
+        .expect("start service");
+        let key = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+        let recipient = hex::encode(recipient::public_key_for(key).unwrap().as_bytes());
+        assert!(service
+            .upload_file_for_recipient(
+                source.to_string_lossy().to_string(),
+                "letter.txt".to_string(),
+                "not-a-key".to_string(),
+            )
+            .await
+            .is_err());
+        service
+            .upload_file_for_recipient(
+                source.to_string_lossy().to_string(),
+                "letter.txt".to_string(),
+                recipient,
+            )
+            .await
+            .expect("start upload");
+
+        let mut file_hash = None;
+        for _ in 0..200 {
+            for event in service.drain_events(100).await {
+                if let FileTransferEvent::FileUploaded {
+                    file_hash: hash, ..
+                } = event
+                {
+                    file_hash = Some(hash);
+                }
+            }
+            if file_hash.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let file_hash = file_hash.expect("file uploaded");
+        let stored = service.get_file_data(&file_hash).await.expect("stored");
+        assert!(recipient::is_envelope(&stored));
+
+        let download = |key: Option<&str>, name: &str| {
+            let output = output_dir.path().join(name);
+            let service = &service;
+            let file_hash = file_hash.clone();
+            let key = key.map(str::to_string);
+            async move {
+                service
+                    .download_file_with_account(
+                        file_hash,
+                        output.to_string_lossy().to_string(),
+                        None,
+                        key,
+                    )
+                    .await
+                    .expect("start download");
+                for _ in 0..200 {
+                    for event in service.drain_events(100).await {
+                        match event {
+                            FileTransferEvent::FileDownloaded { .. } => {
+                                return Ok(tokio::fs::read_to_string(&output).await.unwrap())
+                            }
+                            FileTransferEvent::Error { message, .. } => return Err(message),
+                            _ => {}
+                        }
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                panic!("download did not finish");
+            }
+        };
+        assert_eq!(
+            download(Some(key), "opened.txt").await.unwrap(),
+            "meet at noon"
+        );
+        assert!(download(None, "locked.txt")
+            .await
+            .unwrap_err()
+            .contains("log in to decrypt"));
+    }
+
+    #[test]
+    fn event_kind_matches_serialized_type() {
+        let events = [
+            FileTransferEvent::FileUploaded {
+                transfer_id: "t".into(),
+                file_hash: "h".into(),
+                file_name: "a.txt".into(),
+                file_size: 3,
+            },
+            FileTransferEvent::IntegrityFailed {
+                transfer_id: "t".into(),
+                expected_hash: "h".into(),
+                actual_hash: "x".into(),
+                attempt: 1,
+            },
+            FileTransferEvent::DownloadAttempt(attempt_at(AttemptStatus::Success, 1, 1)),
+            FileTransferEvent::FileRemoved {
+                file_hash: "h".into(),
+                published_ids: Vec::new(),
+            },
+            FileTransferEvent::CircuitOpen(OpenCircuit {
+                file_hash: "h".into(),
+                failures: 3,
+                reopens_at: 10,
+            }),
+        ];
+        for event in events {
+            assert_eq!(serde_json::to_value(&event).unwrap()["type"], event.kind());
+        }
+    }
+
+    #[tokio::test]
+    async fn batch_download_reports_one_completion() {
+        let storage = tempdir().expect("temp dir");
+        let output_dir = tempdir().expect("temp output dir");
+        let keystore = Arc::new(Mutex::new(crate::keystore::Keystore::new()));
+        let service = FileTransferService::new_with_env(
+            storage.path().to_path_buf(),
+            false,
+            keystore,
+            None,
+            RuntimeEnv::deterministic(43),
+        )
+        .await
+        .expect("start service");
+        let stored = FileTransferService::calculate_file_hash(b"batch member");
+        service
+            .store_file_data(
+                stored.clone(),
+                "member.txt".to_string(),
+                b"batch member".to_vec(),
+            )
+            .await;
+        let missing = FileTransferService::calculate_file_hash(b"nobody has this");
+        let item = |hash: &str, name: &str| BatchItem {
+            file_hash: hash.to_string(),
+            output_path: output_dir.path().join(name).to_string_lossy().to_string(),
+        };
+
+        assert!(service
+            .download_batch(Vec::new(), Vec::new(), None, TransferPriority::Normal, None)
+            .await
+            .is_err());
+        let batch_id = service
+            .download_batch(
+                vec![item(&stored, "a.txt"), item(&missing, "b.txt")],
+                Vec::new(),
+                None,
+                TransferPriority::Normal,
+                None,
+            )
+            .await
+            .expect("start batch");
+
+        let mut progress = Vec::new();
+        let mut completed = None;
+        for _ in 0..200 {
+            for event in service.drain_events(100).await {
+                match event {
+                    FileTransferEvent::BatchProgress(p) => progress.push(p),
+                    FileTransferEvent::BatchCompleted {
+                        batch_id,
+                        file_count,
+                        failed,
+                    } => completed = Some((batch_id, file_count, failed)),
+                    _ => {}
+                }
+            }
+            if completed.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(completed, Some((batch_id.clone(), 2, vec![missing])));
+        assert_eq!(progress.len(), 2);
+        assert!(progress.iter().all(|p| p.batch_id == batch_id));
+        assert_eq!(progress[1].files_done, 1);
+        assert_eq!(progress[1].bytes_done, 12);
+        let delivered = tokio::fs::read(output_dir.path().join("a.txt")).await;
+        assert_eq!(delivered.unwrap(), b"batch member");
+    }
+
+    #[tokio::test]
+    async fn upload_and_wait_returns_the_hash() {
+        let storage = tempdir().expect("temp dir");
+        let source = storage.path().join("report.txt");
+        tokio::fs::write(&source, "quarterly numbers")
+            .await
+            .expect("write source file");
+
+        let keystore = Arc::new(Mutex::new(crate::keystore::Keystore::new()));
+        let service = FileTransferService::new_with_env(
+            storage.path().to_path_buf(),
+            false,
+            keystore,
+            None,
+            RuntimeEnv::deterministic(41),
+        )
+        .await
+        .expect("start service");
+        let file_hash = service
+            .upload_file_and_wait(
+                source.to_string_lossy().to_string(),
+                "report.txt".to_string(),
+                None,
+                None,
+            )
+            .await
+            .expect("upload");
+        assert_eq!(
+            file_hash,
+            FileTransferService::calculate_file_hash(b"quarterly numbers")
         );
+        assert!(service.get_file_data(&file_hash).await.is_some());
+
+        let missing = storage.path().join("missing.txt");
+        let err = service
+            .upload_file_and_wait(
+                missing.to_string_lossy().to_string(),
+                "missing.txt".to_string(),
+                None,
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert!(err.contains("Upload failed"));
+    }
+
+    #[tokio::test]
+    async fn in_place_uploads_are_indexed_without_a_copy() {
+        let storage = tempdir().expect("temp dir");
+        let library = tempdir().expect("temp dir");
+        let source = library.path().join("clip.mp4");
+        let data: Vec<u8> = (0..chunking::CHUNK_SIZE + 100)
+            .map(|i| (i % 241) as u8)
+            .collect();
+        tokio::fs::write(&source, &data)
+            .await
+            .expect("write source file");
+
+        let keystore = Arc::new(Mutex::new(crate::keystore::Keystore::new()));
+        let service = FileTransferService::new_with_env(
+            storage.path().to_path_buf(),
+            false,
+            keystore,
+            None,
+            RuntimeEnv::deterministic(43),
+        )
+        .await
+        .expect("start service");
+        let file_hash = service
+            .upload_file_in_place(source.to_string_lossy().to_string(), "clip.mp4".to_string())
+            .await
+            .expect("index file");
+        assert_eq!(file_hash, FileTransferService::calculate_file_hash(&data));
+        assert!(!storage.path().join(&file_hash).exists());
+        assert_eq!(service.storage_bytes_used().await, 0);
+        assert_eq!(service.get_file_data(&file_hash).await, Some(data.clone()));
+
+        let manifest = chunking::load_manifest(storage.path(), &file_hash)
+            .await
+            .expect("read manifest")
+            .expect("manifest saved");
+        assert_eq!(manifest, ChunkManifest::build(&file_hash, &data));
+        let stored = service.get_stored_files().await.expect("list files");
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].entry.file_size, data.len() as u64);
+
+        service.remove_file(&file_hash).await.expect("remove");
+        for _ in 0..200 {
+            if service.stored_blobs().await.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(service.stored_blobs().await.is_empty());
+        assert!(source.exists());
+    }
+
+    #[tokio::test]
+    async fn expired_uploads_are_collected() {
+        let storage = tempdir().expect("temp dir");
+        let source = storage.path().join("notes.txt");
+        tokio::fs::write(&source, "temporary")
+            .await
+            .expect("write source file");
+
+        let keystore = Arc::new(Mutex::new(crate::keystore::Keystore::new()));
+        let service = FileTransferService::new_with_env(
+            storage.path().to_path_buf(),
+            false,
+            keystore,
+            None,
+            RuntimeEnv::deterministic(29),
+        )
+        .await
+        .expect("start service");
+        let fetcher = Arc::new(WithdrawingFetcher::default());
+        service.set_chunk_fetcher(fetcher.clone()).await;
+        // The deterministic clock starts well after this
+        service
+            .upload_file_with_expiry(
+                source.to_string_lossy().to_string(),
+                "notes.txt".to_string(),
+                1_000,
+                None,
+                None,
+            )
+            .await
+            .expect("start upload");
+
+        let mut uploaded = None;
+        let mut expired = None;
+        for _ in 0..200 {
+            for event in service.drain_events(100).await {
+                match event {
+                    FileTransferEvent::FileUploaded { file_hash, .. } => {
+                        uploaded = Some(file_hash);
+                        service.collect_expired_files().await.expect("collect");
+                    }
+                    FileTransferEvent::FileExpired {
+                        file_hash,
+                        expires_at,
+                        ..
+                    } => expired = Some((file_hash, expires_at)),
+                    _ => {}
+                }
+            }
+            if expired.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let file_hash = uploaded.expect("file uploaded");
+        assert_eq!(expired, Some((file_hash.clone(), 1_000)));
+        assert!(service.get_file_data(&file_hash).await.is_none());
+        assert_eq!(*fetcher.withdrawn.lock().unwrap(), vec![file_hash.clone()]);
+        assert!(service.set_file_expiry(&file_hash, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn stored_files_list_pin_state_mime_and_serves() {
+        let storage = tempdir().expect("temp dir");
+        let keystore = Arc::new(Mutex::new(crate::keystore::Keystore::new()));
+        let service = FileTransferService::new_with_env(
+            storage.path().to_path_buf(),
+            false,
+            keystore,
+            None,
+            RuntimeEnv::deterministic(37),
+        )
+        .await
+        .expect("start service");
+        let png = FileTransferService::calculate_file_hash(b"listing test png");
+        let text = FileTransferService::calculate_file_hash(b"listing test text");
+        let mut image = b"\x89PNG\r\n\x1a\n".to_vec();
+        image.extend_from_slice(&[0u8; 24]);
+        service
+            .store_file_data(png.clone(), "logo.png".to_string(), image)
+            .await;
+        service
+            .store_file_data(text.clone(), "notes.txt".to_string(), b"hello".to_vec())
+            .await;
+        service.pin_file(&png).await.expect("pin");
+        let demand = crate::demand_stats::global();
+        demand.record(&png, Some("peer-a"), true);
+        demand.record(&png, Some("peer-b"), true);
+        demand.record(&png, Some("peer-c"), false);
+
+        let files = service.get_stored_files().await.expect("list");
+        assert_eq!(files.len(), 2);
+        let logo = files.iter().find(|f| f.entry.file_hash == png).unwrap();
+        assert_eq!(logo.entry.file_name, "logo.png");
+        assert_eq!(logo.entry.file_size, 32);
+        assert_eq!(logo.entry.mime_type.as_deref(), Some("image/png"));
+        assert_eq!(logo.entry.extension.as_deref(), Some("png"));
+        assert!(logo.pinned);
+        assert_eq!(logo.times_served, 2);
+        let notes = files.iter().find(|f| f.entry.file_hash == text).unwrap();
+        assert!(!notes.pinned);
+        assert_eq!(notes.times_served, 0);
+
+        let json = serde_json::to_value(logo).unwrap();
+        assert_eq!(json["fileName"], "logo.png");
+        assert_eq!(json["timesServed"], 2);
+    }
+
+    #[tokio::test]
+    async fn stored_files_are_read_a_chunk_at_a_time() {
+        let storage = tempdir().expect("temp dir");
+        let keystore = Arc::new(Mutex::new(crate::keystore::Keystore::new()));
+        let service = FileTransferService::new_with_env(
+            storage.path().to_path_buf(),
+            false,
+            keystore,
+            None,
+            RuntimeEnv::deterministic(41),
+        )
+        .await
+        .expect("start service");
+        let data: Vec<u8> = (0..250u32).map(|i| i as u8).collect();
+        let hash = FileTransferService::calculate_file_hash(&data);
+        service
+            .store_file_data(hash.clone(), "pieces.bin".to_string(), data.clone())
+            .await;
+
+        assert_eq!(service.stored_file_size(&hash).await, Some(250));
+        let mut joined = Vec::new();
+        for index in 0..3 {
+            let chunk = service.read_file_chunk(&hash, index, 100).await.unwrap();
+            joined.extend(chunk.expect("chunk is stored"));
+        }
+        assert_eq!(joined, data);
+        assert_eq!(service.read_file_chunk(&hash, 3, 100).await.unwrap(), None);
+        let missing = service.read_file_chunk("gone", 0, 100).await.unwrap();
+        assert!(missing.is_none());
+        assert_eq!(service.stored_file_size("gone").await, None);
+    }
+
+    /// Serves nothing and records withdrawn provider records
+    #[derive(Default)]
+    struct WithdrawingFetcher {
+        withdrawn: std::sync::Mutex<Vec<String>>,
+    }
+
+    #[async_trait::async_trait]
+    impl ChunkFetcher for WithdrawingFetcher {
+        async fn fetch_manifest(&self, _: &str, _: &str) -> Result<ChunkManifest, String> {
+            Err("no manifests here".to_string())
+        }
+
+        async fn fetch_chunk(&self, _: &str, _: &str, _: u32) -> Result<Vec<u8>, String> {
+            Err("no chunks here".to_string())
+        }
+
+        async fn stop_providing(&self, file_hash: &str) -> Result<(), String> {
+            self.withdrawn.lock().unwrap().push(file_hash.to_string());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn removed_files_are_deleted_and_withdrawn() {
+        let storage = tempdir().expect("temp dir");
+        let source = storage.path().join("shared.txt");
+        tokio::fs::write(&source, "no longer shared")
+            .await
+            .expect("write source file");
+
+        let keystore = Arc::new(Mutex::new(crate::keystore::Keystore::new()));
+        let service = FileTransferService::new_with_env(
+            storage.path().to_path_buf(),
+            false,
+            keystore,
+            None,
+            RuntimeEnv::deterministic(31),
+        )
+        .await
+        .expect("start service");
+        let fetcher = Arc::new(WithdrawingFetcher::default());
+        service.set_chunk_fetcher(fetcher.clone()).await;
+        service
+            .upload_file_with_account(
+                source.to_string_lossy().to_string(),
+                "shared.txt".to_string(),
+                None,
+                None,
+            )
+            .await
+            .expect("start upload");
+
+        let mut removed = None;
+        for _ in 0..200 {
+            for event in service.drain_events(100).await {
+                match event {
+                    FileTransferEvent::FileUploaded { file_hash, .. } => {
+                        service.remove_file(&file_hash).await.expect("remove");
+                    }
+                    FileTransferEvent::FileRemoved { file_hash, .. } => removed = Some(file_hash),
+                    _ => {}
+                }
+            }
+            if removed.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let file_hash = removed.expect("file removed");
+        assert!(service.get_file_data(&file_hash).await.is_none());
+        assert!(!storage.path().join(format!("{}.meta", file_hash)).exists());
+        assert_eq!(*fetcher.withdrawn.lock().unwrap(), vec![file_hash.clone()]);
+
+        let err = service.remove_file(&file_hash).await.unwrap_err();
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::NotFound);
+    }
+
+    #[tokio::test]
+    async fn trimmed_files_are_withdrawn_like_removed_ones() {
+        let storage = tempdir().expect("temp dir");
+        let keystore = Arc::new(Mutex::new(crate::keystore::Keystore::new()));
+        let service = FileTransferService::new_with_env(
+            storage.path().to_path_buf(),
+            false,
+            keystore,
+            None,
+            RuntimeEnv::deterministic(32),
+        )
+        .await
+        .expect("start service");
+        let fetcher = Arc::new(WithdrawingFetcher::default());
+        service.set_chunk_fetcher(fetcher.clone()).await;
+        let data = b"making room".to_vec();
+        let hash = FileTransferService::calculate_file_hash(&data);
+        service
+            .store_file_data(hash.clone(), "room.txt".to_string(), data)
+            .await;
+        service
+            .record_published_id("root", &hash)
+            .await
+            .expect("record published id");
+
+        let trimmed = service.trim_storage(0).await.expect("trim");
+        assert_eq!(trimmed, vec![hash.clone()]);
+        assert!(service.get_file_data(&hash).await.is_none());
+        assert_eq!(
+            *fetcher.withdrawn.lock().unwrap(),
+            vec![hash.clone(), "root".to_string()]
+        );
+        let mut removed = None;
+        for _ in 0..200 {
+            for event in service.drain_events(100).await {
+                if let FileTransferEvent::FileRemoved {
+                    file_hash,
+                    published_ids,
+                } = event
+                {
+                    removed = Some((file_hash, published_ids));
+                }
+            }
+            if removed.is_some() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(removed, Some((hash, vec!["root".to_string()])));
+    }
+
+    /// Flips a byte of the first file it writes
+    #[derive(Default)]
+    struct CorruptOnceFs {
+        corrupted: std::sync::atomic::AtomicBool,
+    }
+
+    #[async_trait::async_trait]
+    impl crate::runtime_env::FileIo for CorruptOnceFs {
+        async fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+            tokio::fs::read(path).await
+        }
+
+        async fn write(&self, path: &Path, data: &[u8]) -> std::io::Result<()> {
+            let mut data = data.to_vec();
+            if !self.corrupted.swap(true, std::sync::atomic::Ordering::SeqCst) {
+                data[0] ^= 0xff;
+            }
+            tokio::fs::write(path, data).await
+        }
+    }
+
+    #[tokio::test]
+    async fn output_that_fails_its_hash_is_reported_and_retried() {
+        let env = RuntimeEnv::deterministic(17).with_fs(Arc::new(CorruptOnceFs::default()));
+        let storage = tempdir().expect("temp dir");
+        let blobs = BlobStore::open(storage.path()).await.expect("open blob store");
+        let data = b"content addressed".to_vec();
+        let hash = FileTransferService::calculate_file_hash(&data);
+        blobs.put(&hash, &data).await.expect("store blob");
+
+        let output_dir = tempdir().expect("temp output dir");
+        let output = output_dir.path().join("out.txt");
+        let (event_tx, mut event_rx) = mpsc::channel(16);
+        let metrics = Arc::new(Mutex::new(DownloadMetrics::default()));
+        let keystore = Arc::new(Mutex::new(crate::keystore::Keystore::new()));
+        FileTransferService::download_with_retries(
+            "transfer-1",
+            &hash,
+            &output.to_string_lossy(),
+            &blobs,
+            &RemoteSources::default(),
+            event_tx,
+            metrics,
+            keystore,
+            None,
+            None,
+            &RetryPolicy::default(),
+            &silent_progress(&env),
+            &CancellationToken::new(),
+            &env,
+        )
+        .await
+        .expect("second attempt writes a good copy");
+        assert_eq!(tokio::fs::read(&output).await.unwrap(), data);
+
+        let mut integrity_failures = Vec::new();
+        let mut statuses = Vec::new();
+        while let Ok(event) = event_rx.try_recv() {
+            match event {
+                FileTransferEvent::IntegrityFailed {
+                    expected_hash,
+                    actual_hash,
+                    attempt,
+                    ..
+                } => integrity_failures.push((expected_hash, actual_hash, attempt)),
+                FileTransferEvent::DownloadAttempt(snapshot) => statuses.push(snapshot.status),
+                _ => {}
+            }
+        }
+        assert_eq!(integrity_failures.len(), 1);
+        assert_eq!(integrity_failures[0].0, hash);
+        assert_ne!(integrity_failures[0].1, hash);
+        assert_eq!(integrity_failures[0].2, 1);
+        assert_eq!(
+            statuses,
+            vec![AttemptStatus::Retrying, AttemptStatus::Success]
+        );
+    }
+
+    /// Serves the same file from every provider
+    struct StaticFetcher {
+        manifest: ChunkManifest,
+        data: Vec<u8>,
+    }
+
+    #[async_trait::async_trait]
+    impl ChunkFetcher for StaticFetcher {
+        async fn fetch_manifest(&self, _: &str, _: &str) -> Result<ChunkManifest, String> {
+            Ok(self.manifest.clone())
+        }
+
+        async fn fetch_chunk(&self, _: &str, _: &str, index: u32) -> Result<Vec<u8>, String> {
+            let range = self.manifest.chunk_range(index).map_err(|e| e.to_string())?;
+            Ok(self.data[range].to_vec())
+        }
+
+        async fn find_providers(&self, _: &str) -> Result<Vec<String>, String> {
+            Ok(vec!["peer-a".into()])
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_file_is_fetched_from_remote_providers() {
+        let env = RuntimeEnv::deterministic(9);
+        let storage = tempdir().expect("temp dir");
+        let blobs = BlobStore::open(storage.path()).await.expect("open blob store");
+        let output_dir = tempdir().expect("temp output dir");
+        let output = output_dir.path().join("remote.bin");
+        let keystore = Arc::new(Mutex::new(crate::keystore::Keystore::new()));
+
+        let data: Vec<u8> = (0..100u32).map(|i| (i * 7) as u8).collect();
+        let hash = FileTransferService::calculate_file_hash(&data);
+        let fetcher = Arc::new(StaticFetcher {
+            manifest: ChunkManifest::build_with_chunk_size(&hash, &data, 16),
+            data: data.clone(),
+        });
+        let remote = RemoteSources::new(vec!["peer-a".into(), "peer-b".into()], Some(fetcher));
+
+        let progress = silent_progress(&env);
+        let delivered = FileTransferService::handle_download_file(
+            "transfer-1",
+            &hash,
+            &output.to_string_lossy(),
+            &blobs,
+            &remote,
+            &progress,
+            &CancellationToken::new(),
+            &keystore,
+            None,
+            None,
+            &env,
+        )
+        .await
+        .expect("download from providers");
+
+        assert_eq!(delivered.size, 100);
+        assert_eq!(progress.snapshot().bytes_done, 100);
+        assert_eq!(tokio::fs::read(&output).await.unwrap(), data);
+        assert!(ResumeStore::in_storage_dir(storage.path()).pending().await.is_empty());
+
+        // A manifest that is self-consistent but describes other content
+        // fails the whole-file hash check and leaves nothing behind
+        let other = vec![1u8; 100];
+        let liar = Arc::new(StaticFetcher {
+            manifest: ChunkManifest::build_with_chunk_size(&hash, &other, 16),
+            data: other,
+        });
+        let remote = RemoteSources::new(vec!["peer-c".into()], Some(liar));
+        let bad_output = output_dir.path().join("bad.bin");
+        let (event_tx, mut event_rx) = mpsc::channel(16);
+        let err = FileTransferService::download_with_retries(
+            "transfer-2",
+            &hash,
+            &bad_output.to_string_lossy(),
+            &blobs,
+            &remote,
+            event_tx,
+            Arc::new(Mutex::new(DownloadMetrics::default())),
+            keystore,
+            None,
+            None,
+            &RetryPolicy {
+                max_attempts: 1,
+                ..RetryPolicy::default()
+            },
+            &silent_progress(&env),
+            &CancellationToken::new(),
+            &env,
+        )
+        .await
+        .err()
+        .expect("content hash mismatch");
+        assert_eq!(ErrorCode::classify(&err), ErrorCode::Verification);
+        assert!(!bad_output.exists());
+        assert!(!resume::part_path(&bad_output.to_string_lossy()).exists());
+        let mut reported = false;
+        while let Ok(event) = event_rx.try_recv() {
+            if let FileTransferEvent::IntegrityFailed { expected_hash, .. } = event {
+                reported = expected_hash == hash;
+            }
+        }
+        assert!(reported, "mismatch is reported as an integrity failure");
+    }
+
+    #[tokio::test]
+    async fn scrub_reports_corrupted_files_and_repairs_them_from_providers() {
+        let storage = tempdir().expect("temp dir");
+        let source_dir = tempdir().expect("temp dir");
+        let source = source_dir.path().join("notes.txt");
+        let data: Vec<u8> = (0..5000u32).map(|i| (i % 251) as u8).collect();
+        tokio::fs::write(&source, &data)
+            .await
+            .expect("write source file");
+
+        let keystore = Arc::new(Mutex::new(crate::keystore::Keystore::new()));
+        let service = FileTransferService::new_with_env(
+            storage.path().to_path_buf(),
+            false,
+            keystore,
+            None,
+            RuntimeEnv::deterministic(44),
+        )
+        .await
+        .expect("start service");
+        let file_hash = service
+            .upload_file_and_wait(
+                source.to_string_lossy().to_string(),
+                "notes.txt".to_string(),
+                None,
+                None,
+            )
+            .await
+            .expect("upload");
+        let report = service.verify_storage(false).await.expect("scrub");
+        assert_eq!(report.checked, 1);
+        assert!(report.corrupted.is_empty());
+
+        let mut damaged = data.clone();
+        damaged[1234] ^= 0xff;
+        tokio::fs::write(storage.path().join(&file_hash), &damaged)
+            .await
+            .expect("damage blob");
+        let report = service.verify_storage(false).await.expect("scrub");
+        assert_eq!(report.corrupted.len(), 1);
+        assert!(!report.corrupted[0].repaired);
+        // Nobody to fetch a good copy from yet
+        let report = service.verify_storage(true).await.expect("scrub");
+        assert!(!report.corrupted[0].repaired);
+
+        service
+            .set_chunk_fetcher(Arc::new(StaticFetcher {
+                manifest: ChunkManifest::build_with_chunk_size(&file_hash, &data, 1024),
+                data: data.clone(),
+            }))
+            .await;
+        let report = service.verify_storage(true).await.expect("scrub");
+        assert_eq!(report.corrupted[0].file_hash, file_hash);
+        assert!(report.corrupted[0].repaired);
+        assert_eq!(service.get_file_data(&file_hash).await, Some(data));
+        let report = service.verify_storage(false).await.expect("scrub");
+        assert!(report.corrupted.is_empty());
+        assert!(!storage.path().join(format!("{}.repair", file_hash)).exists());
+    }
+
+    fn attempt_at(status: AttemptStatus, timestamp: u64, duration_ms: u64) -> DownloadAttemptSnapshot {
+        DownloadAttemptSnapshot {
+            transfer_id: "transfer".to_string(),
+            file_hash: "hash".to_string(),
+            attempt: 1,
+            max_attempts: 3,
+            status,
+            duration_ms,
+            time_to_first_byte_ms: None,
+            file_size: None,
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn metrics_summary_tracks_rolling_window_and_caps_history() {
+        let mut metrics = DownloadMetrics::default();
+        metrics.record_attempt(attempt_at(AttemptStatus::Success, 1_000, 100));
+        metrics.record_attempt(attempt_at(AttemptStatus::Failed, 1_010, 0));
+        for i in 0..30 {
+            metrics.record_attempt(attempt_at(AttemptStatus::Success, 1_100 + i, 300));
+        }
+
+        let summary = metrics.summary_at(1_130);
+        assert_eq!(summary.total_success, 31);
+        assert_eq!(summary.total_failures, 1);
+        assert_eq!(summary.successes_last_minute, 30);
+        assert_eq!(summary.failures_last_minute, 0);
+        assert_eq!(summary.finished_last_minute, 30);
+        assert_eq!(summary.average_success_duration_ms, (100 + 30 * 300) / 31);
+        assert_eq!(summary.last_attempt.map(|a| a.timestamp), Some(1_129));
+
+        assert_eq!(metrics.snapshot().recent_attempts.len(), DEFAULT_RECENT_ATTEMPTS);
+        let limited = metrics.snapshot_with_limit(3);
+        assert_eq!(limited.recent_attempts.len(), 3);
+        assert_eq!(limited.recent_attempts[0].timestamp, 1_129);
+
+        // Once the window slides past, the per-minute counters drain
+        let later = metrics.summary_at(1_500);
+        assert_eq!(later.attempts_last_minute, 0);
+        assert_eq!(later.total_success, 31);
+    }
+
+    #[test]
+    fn attempt_retention_applies_count_and_age_limits() {
+        let mut metrics = DownloadMetrics::default();
+        metrics.set_retention(
+            AttemptRetention {
+                max_entries: 5,
+                max_age_secs: Some(60),
+            },
+            1_000,
+        );
+        for i in 0..10 {
+            metrics.record_attempt(attempt_at(AttemptStatus::Success, 1_000 + i, 10));
+        }
+        assert_eq!(metrics.snapshot().recent_attempts.len(), 5);
+
+        // Entries older than the window are dropped on the next record
+        metrics.record_attempt(attempt_at(AttemptStatus::Failed, 1_067, 10));
+        let timestamps: Vec<u64> = metrics
+            .snapshot()
+            .recent_attempts
+            .iter()
+            .map(|a| a.timestamp)
+            .collect();
+        assert_eq!(timestamps, vec![1_067, 1_009, 1_008, 1_007]);
+
+        assert_eq!(metrics.clear_recent(), 4);
+        assert!(metrics.snapshot().recent_attempts.is_empty());
+        assert_eq!(metrics.summary_at(1_067).total_success, 10);
     }
 }
@@ -0,0 +1,143 @@
+// Batch downloads
+//
+// `DownloadBatch` fetches a list of files as one unit. Every file becomes an
+// ordinary download with the transfer ID `<batch id>/<index>`, so the batch
+// shares the download queue, its priorities and its concurrency limit with
+// everything else, and each file still reports its own progress. The
+// `BatchTable` follows the files as they end and reports on the batch as a
+// whole: a `BatchProgress` event after each file and one `BatchCompleted`
+// once the last one has succeeded, failed or been cancelled. A paused file
+// keeps its batch open until it is resumed and ends.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// One file of a batch
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchItem {
+    pub file_hash: String,
+    pub output_path: String,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchProgress {
+    pub batch_id: String,
+    pub file_count: usize,
+    pub files_done: usize,
+    pub files_failed: usize,
+    /// Bytes of the files delivered so far
+    pub bytes_done: u64,
+}
+
+impl BatchProgress {
+    pub fn is_complete(&self) -> bool {
+        self.files_done + self.files_failed >= self.file_count
+    }
+}
+
+#[derive(Debug)]
+struct Batch {
+    /// Transfer ID of each file that has not ended yet, with its hash
+    pending: HashMap<String, String>,
+    /// Hashes of the files that failed or were cancelled
+    failed: Vec<String>,
+    progress: BatchProgress,
+}
+
+#[derive(Debug, Default)]
+pub struct BatchTable {
+    batches: HashMap<String, Batch>,
+    /// Batch of each pending file's transfer ID
+    members: HashMap<String, String>,
+}
+
+/// Transfer ID of the `index`th file of a batch
+pub fn member_id(batch_id: &str, index: usize) -> String {
+    format!("{}/{}", batch_id, index)
+}
+
+impl BatchTable {
+    /// Start following a batch. `items` pairs each file's transfer ID with
+    /// its hash.
+    pub fn insert(&mut self, batch_id: &str, items: &[(String, String)]) {
+        for (transfer_id, _) in items {
+            self.members
+                .insert(transfer_id.clone(), batch_id.to_string());
+        }
+        self.batches.insert(
+            batch_id.to_string(),
+            Batch {
+                pending: items.iter().cloned().collect(),
+                failed: Vec::new(),
+                progress: BatchProgress {
+                    batch_id: batch_id.to_string(),
+                    file_count: items.len(),
+                    files_done: 0,
+                    files_failed: 0,
+                    bytes_done: 0,
+                },
+            },
+        );
+    }
+
+    /// Record the end of a file: `delivered` is its size if it succeeded.
+    /// Returns the batch's progress, with the failed hashes once it is
+    /// complete, or `None` if the transfer is not part of a batch.
+    pub fn finish(
+        &mut self,
+        transfer_id: &str,
+        delivered: Option<u64>,
+    ) -> Option<(BatchProgress, Option<Vec<String>>)> {
+        let batch_id = self.members.remove(transfer_id)?;
+        let batch = self.batches.get_mut(&batch_id)?;
+        let file_hash = batch.pending.remove(transfer_id)?;
+        match delivered {
+            Some(bytes) => {
+                batch.progress.files_done += 1;
+                batch.progress.bytes_done += bytes;
+            }
+            None => {
+                batch.progress.files_failed += 1;
+                batch.failed.push(file_hash);
+            }
+        }
+        let progress = batch.progress.clone();
+        if !progress.is_complete() {
+            return Some((progress, None));
+        }
+        let batch = self.batches.remove(&batch_id)?;
+        Some((progress, Some(batch.failed)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch_completes_when_every_file_has_ended() {
+        let mut table = BatchTable::default();
+        let items: Vec<(String, String)> = ["a", "b", "c"]
+            .iter()
+            .enumerate()
+            .map(|(i, hash)| (member_id("batch", i), hash.to_string()))
+            .collect();
+        table.insert("batch", &items);
+        assert!(table.finish("other/0", Some(1)).is_none());
+
+        let (progress, failed) = table.finish("batch/0", Some(10)).unwrap();
+        assert_eq!((progress.files_done, progress.bytes_done), (1, 10));
+        assert!(failed.is_none());
+        assert!(table.finish("batch/0", Some(10)).is_none());
+
+        table.finish("batch/1", None).unwrap();
+        let (progress, failed) = table.finish("batch/2", Some(5)).unwrap();
+        assert!(progress.is_complete());
+        assert_eq!(progress.files_failed, 1);
+        assert_eq!(progress.bytes_done, 15);
+        assert_eq!(failed, Some(vec!["b".to_string()]));
+        assert!(table.batches.is_empty() && table.members.is_empty());
+    }
+}
@@ -0,0 +1,194 @@
+// Reusable chunk buffers
+//
+// Reading or hashing a chunk used to allocate a fresh vector for it, so a
+// node verifying, serving or uploading many chunks at once spent a good part
+// of its time in the allocator. A buffer taken from a `BufferPool` goes back
+// to the pool when it is dropped and the next read reuses it. A buffer can
+// also be frozen into `Bytes`, which is sliced and shared without copying;
+// `recycle` takes it back once nothing else holds it.
+//
+// At most `max_idle` buffers are kept, so a burst of transfers does not pin
+// its peak memory afterwards.
+
+use super::chunking::CHUNK_SIZE;
+use bytes::{Bytes, BytesMut};
+use serde::Serialize;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+pub const DEFAULT_MAX_IDLE: usize = 64;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct BufferPoolStats {
+    /// Buffers allocated because none was free
+    pub allocated: u64,
+    /// Buffers handed out again instead of allocated
+    pub reused: u64,
+    /// Buffers waiting in the pool
+    pub idle: usize,
+}
+
+#[derive(Debug)]
+pub struct BufferPool {
+    buffer_len: usize,
+    max_idle: usize,
+    idle: Mutex<Vec<BytesMut>>,
+    allocated: AtomicU64,
+    reused: AtomicU64,
+}
+
+impl BufferPool {
+    pub fn new(buffer_len: usize, max_idle: usize) -> Self {
+        Self {
+            buffer_len,
+            max_idle,
+            idle: Mutex::new(Vec::new()),
+            allocated: AtomicU64::new(0),
+            reused: AtomicU64::new(0),
+        }
+    }
+
+    pub fn buffer_len(&self) -> usize {
+        self.buffer_len
+    }
+
+    /// An empty buffer with room for at least `buffer_len` bytes
+    pub fn take(&self) -> PooledBuf<'_> {
+        let reused = self.idle.lock().unwrap().pop();
+        let buf = match reused {
+            Some(buf) => {
+                self.reused.fetch_add(1, Ordering::Relaxed);
+                buf
+            }
+            None => {
+                self.allocated.fetch_add(1, Ordering::Relaxed);
+                BytesMut::with_capacity(self.buffer_len)
+            }
+        };
+        PooledBuf {
+            pool: self,
+            buf: Some(buf),
+        }
+    }
+
+    /// Take back frozen contents of a pooled buffer. Only reused if this
+    /// was the last handle to them.
+    pub fn recycle(&self, bytes: Bytes) {
+        if let Ok(buf) = bytes.try_into_mut() {
+            self.give(buf);
+        }
+    }
+
+    fn give(&self, mut buf: BytesMut) {
+        buf.clear();
+        if buf.capacity() < self.buffer_len {
+            return;
+        }
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() < self.max_idle {
+            idle.push(buf);
+        }
+    }
+
+    pub fn stats(&self) -> BufferPoolStats {
+        BufferPoolStats {
+            allocated: self.allocated.load(Ordering::Relaxed),
+            reused: self.reused.load(Ordering::Relaxed),
+            idle: self.idle.lock().unwrap().len(),
+        }
+    }
+}
+
+/// Pool of `CHUNK_SIZE` buffers shared by every transfer
+pub fn chunk_buffers() -> &'static BufferPool {
+    static POOL: OnceLock<BufferPool> = OnceLock::new();
+    POOL.get_or_init(|| BufferPool::new(CHUNK_SIZE, DEFAULT_MAX_IDLE))
+}
+
+/// A buffer on loan from a `BufferPool`, returned to it on drop
+#[derive(Debug)]
+pub struct PooledBuf<'a> {
+    pool: &'a BufferPool,
+    buf: Option<BytesMut>,
+}
+
+impl PooledBuf<'_> {
+    /// The contents as shareable `Bytes`. The buffer leaves the pool; hand
+    /// it back with `BufferPool::recycle`.
+    pub fn freeze(mut self) -> Bytes {
+        self.buf.take().unwrap_or_default().freeze()
+    }
+}
+
+impl Deref for PooledBuf<'_> {
+    type Target = BytesMut;
+
+    fn deref(&self) -> &BytesMut {
+        self.buf.as_ref().expect("buffer is present until dropped")
+    }
+}
+
+impl DerefMut for PooledBuf<'_> {
+    fn deref_mut(&mut self) -> &mut BytesMut {
+        self.buf.as_mut().expect("buffer is present until dropped")
+    }
+}
+
+impl Drop for PooledBuf<'_> {
+    fn drop(&mut self) {
+        if let Some(buf) = self.buf.take() {
+            self.pool.give(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffers_are_reused_up_to_the_idle_limit() {
+        let pool = BufferPool::new(1024, 2);
+        {
+            let mut buf = pool.take();
+            buf.extend_from_slice(b"chunk");
+        }
+        let buf = pool.take();
+        assert!(buf.is_empty());
+        assert!(buf.capacity() >= 1024);
+        drop(buf);
+        assert_eq!(
+            pool.stats(),
+            BufferPoolStats {
+                allocated: 1,
+                reused: 1,
+                idle: 1,
+            }
+        );
+
+        let held: Vec<_> = (0..4).map(|_| pool.take()).collect();
+        drop(held);
+        assert_eq!(pool.stats().idle, 2);
+    }
+
+    #[test]
+    fn frozen_buffers_come_back_once_unshared() {
+        let pool = BufferPool::new(64, 4);
+        let mut buf = pool.take();
+        buf.resize(64, 7);
+        let bytes = buf.freeze();
+        let shared = bytes.slice(8..16);
+        assert_eq!(pool.stats().idle, 0);
+
+        pool.recycle(bytes);
+        assert_eq!(pool.stats().idle, 0);
+        drop(shared);
+
+        let mut buf = pool.take();
+        buf.resize(64, 1);
+        pool.recycle(buf.freeze());
+        assert_eq!(pool.stats().idle, 1);
+    }
+}
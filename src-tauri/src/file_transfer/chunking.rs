@@ -0,0 +1,424 @@
+// Fixed-size chunking with Merkle verification
+//
+// Uploads are split into `CHUNK_SIZE` pieces and a Merkle tree is built over
+// the SHA-256 of each piece. The resulting `ChunkManifest` is stored next to
+// the blob. On download every chunk is checked against its leaf before the
+// file is assembled, so a corrupted or substituted piece is caught at the
+// chunk that carries it rather than after the whole file was written.
+//
+// A peer that only knows the Merkle root can still check a single chunk it
+// received from an untrusted source with `verify_chunk_proof`.
+
+use crate::manager::Sha256Hasher;
+use rs_merkle::{Hasher, MerkleProof, MerkleTree};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+pub const CHUNK_SIZE: usize = 256 * 1024;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ChunkError {
+    #[error("chunk {index} is out of range ({count} chunks)")]
+    OutOfRange { index: u32, count: u32 },
+    #[error("chunk {index} failed hash verification")]
+    HashMismatch { index: u32 },
+    #[error("chunk {index} has {actual} bytes, expected {expected}")]
+    WrongSize {
+        index: u32,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("chunk hashes do not match the Merkle root")]
+    RootMismatch,
+    #[error("missing chunk {index}")]
+    Missing { index: u32 },
+    #[error("invalid manifest: {0}")]
+    Invalid(String),
+}
+
+/// Chunk layout and Merkle commitment of one stored blob
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChunkManifest {
+    pub file_hash: String,
+    pub file_size: u64,
+    pub chunk_size: usize,
+    /// Hex SHA-256 of every chunk, in order
+    pub chunk_hashes: Vec<String>,
+    pub merkle_root: String,
+}
+
+fn decode_hash(hex_hash: &str) -> Result<[u8; 32], ChunkError> {
+    hex::decode(hex_hash)
+        .map_err(|e| ChunkError::Invalid(e.to_string()))?
+        .try_into()
+        .map_err(|_| ChunkError::Invalid("hash is not 32 bytes".to_string()))
+}
+
+fn merkle_root(leaves: &[[u8; 32]]) -> String {
+    MerkleTree::<Sha256Hasher>::from_leaves(leaves)
+        .root()
+        .map(hex::encode)
+        // An empty file still needs a stable commitment
+        .unwrap_or_else(|| hex::encode(Sha256Hasher::hash(&[])))
+}
+
+/// Sidecar path of the manifest for `file_hash` in `storage_dir`
+pub fn manifest_path(storage_dir: &Path, file_hash: &str) -> PathBuf {
+    storage_dir.join(format!("{}.chunks", file_hash))
+}
+
+pub async fn save_manifest(storage_dir: &Path, manifest: &ChunkManifest) -> Result<(), String> {
+    let json = serde_json::to_vec(manifest)
+        .map_err(|e| format!("Failed to serialize chunk manifest: {}", e))?;
+    tokio::fs::write(manifest_path(storage_dir, &manifest.file_hash), json)
+        .await
+        .map_err(|e| format!("Failed to write chunk manifest: {}", e))
+}
+
+/// The stored manifest, or `None` for blobs stored before chunking existed
+pub async fn load_manifest(
+    storage_dir: &Path,
+    file_hash: &str,
+) -> Result<Option<ChunkManifest>, String> {
+    match tokio::fs::read(manifest_path(storage_dir, file_hash)).await {
+        Ok(raw) => serde_json::from_slice(&raw)
+            .map(Some)
+            .map_err(|e| format!("Failed to parse chunk manifest: {}", e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Failed to read chunk manifest: {}", e)),
+    }
+}
+
+impl ChunkManifest {
+    pub fn build(file_hash: &str, data: &[u8]) -> Self {
+        Self::build_with_chunk_size(file_hash, data, CHUNK_SIZE)
+    }
+
+    pub fn build_with_chunk_size(file_hash: &str, data: &[u8], chunk_size: usize) -> Self {
+        let chunk_size = chunk_size.max(1);
+        let leaves: Vec<[u8; 32]> = data.chunks(chunk_size).map(Sha256Hasher::hash).collect();
+        Self {
+            file_hash: file_hash.to_string(),
+            file_size: data.len() as u64,
+            chunk_size,
+            chunk_hashes: leaves.iter().map(hex::encode).collect(),
+            merkle_root: merkle_root(&leaves),
+        }
+    }
+
+    pub fn chunk_count(&self) -> u32 {
+        self.chunk_hashes.len() as u32
+    }
+
+    /// Byte range of chunk `index` within the file
+    pub fn chunk_range(&self, index: u32) -> Result<Range<usize>, ChunkError> {
+        if index >= self.chunk_count() {
+            return Err(ChunkError::OutOfRange {
+                index,
+                count: self.chunk_count(),
+            });
+        }
+        let start = index as usize * self.chunk_size;
+        let end = (start + self.chunk_size).min(self.file_size as usize);
+        Ok(start..end)
+    }
+
+    /// Check that the listed chunk hashes commit to `merkle_root`
+    pub fn validate(&self) -> Result<(), ChunkError> {
+        if self.chunk_size == 0 {
+            return Err(ChunkError::Invalid("chunk size is zero".to_string()));
+        }
+        let expected_chunks = (self.file_size as usize).div_ceil(self.chunk_size);
+        if expected_chunks != self.chunk_hashes.len() {
+            return Err(ChunkError::Invalid(format!(
+                "{} chunk hashes for {} chunks",
+                self.chunk_hashes.len(),
+                expected_chunks
+            )));
+        }
+        let leaves = self
+            .chunk_hashes
+            .iter()
+            .map(|h| decode_hash(h))
+            .collect::<Result<Vec<_>, _>>()?;
+        if merkle_root(&leaves) != self.merkle_root {
+            return Err(ChunkError::RootMismatch);
+        }
+        Ok(())
+    }
+
+    /// Check one chunk against its leaf hash
+    pub fn verify_chunk(&self, index: u32, data: &[u8]) -> Result<(), ChunkError> {
+        let range = self.chunk_range(index)?;
+        if data.len() != range.len() {
+            return Err(ChunkError::WrongSize {
+                index,
+                expected: range.len(),
+                actual: data.len(),
+            });
+        }
+        if hex::encode(Sha256Hasher::hash(data)) != self.chunk_hashes[index as usize] {
+            return Err(ChunkError::HashMismatch { index });
+        }
+        Ok(())
+    }
+
+    /// Merkle proof for chunk `index`, as hex sibling hashes
+    pub fn proof(&self, index: u32) -> Result<Vec<String>, ChunkError> {
+        self.chunk_range(index)?;
+        let leaves = self
+            .chunk_hashes
+            .iter()
+            .map(|h| decode_hash(h))
+            .collect::<Result<Vec<_>, _>>()?;
+        let tree = MerkleTree::<Sha256Hasher>::from_leaves(&leaves);
+        Ok(tree
+            .proof(&[index as usize])
+            .proof_hashes()
+            .iter()
+            .map(hex::encode)
+            .collect())
+    }
+}
+
+/// Verify a chunk knowing only the Merkle root, the chunk count and a proof
+pub fn verify_chunk_proof(
+    merkle_root_hex: &str,
+    chunk_count: u32,
+    index: u32,
+    data: &[u8],
+    proof_hex: &[String],
+) -> Result<(), ChunkError> {
+    if index >= chunk_count {
+        return Err(ChunkError::OutOfRange {
+            index,
+            count: chunk_count,
+        });
+    }
+    let root = decode_hash(merkle_root_hex)?;
+    let proof_hashes = proof_hex
+        .iter()
+        .map(|h| decode_hash(h))
+        .collect::<Result<Vec<_>, _>>()?;
+    let leaf = Sha256Hasher::hash(data);
+    let verified = MerkleProof::<Sha256Hasher>::new(proof_hashes).verify(
+        root,
+        &[index as usize],
+        &[leaf],
+        chunk_count as usize,
+    );
+    if verified {
+        Ok(())
+    } else {
+        Err(ChunkError::HashMismatch { index })
+    }
+}
+
+/// Builds a manifest from contents read piece by piece, so a large file
+/// never has to be held in memory
+pub struct ManifestBuilder {
+    chunk_size: usize,
+    leaves: Vec<[u8; 32]>,
+    /// Start of the chunk not complete yet
+    pending: Vec<u8>,
+    file_size: u64,
+}
+
+impl ManifestBuilder {
+    pub fn new(chunk_size: usize) -> Self {
+        let chunk_size = chunk_size.max(1);
+        Self {
+            chunk_size,
+            leaves: Vec::new(),
+            pending: Vec::with_capacity(chunk_size),
+            file_size: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.file_size += data.len() as u64;
+        while !data.is_empty() {
+            let take = (self.chunk_size - self.pending.len()).min(data.len());
+            self.pending.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.pending.len() == self.chunk_size {
+                self.leaves.push(Sha256Hasher::hash(&self.pending));
+                self.pending.clear();
+            }
+        }
+    }
+
+    pub fn finish(mut self, file_hash: &str) -> ChunkManifest {
+        if !self.pending.is_empty() {
+            self.leaves.push(Sha256Hasher::hash(&self.pending));
+        }
+        ChunkManifest {
+            file_hash: file_hash.to_string(),
+            file_size: self.file_size,
+            chunk_size: self.chunk_size,
+            chunk_hashes: self.leaves.iter().map(hex::encode).collect(),
+            merkle_root: merkle_root(&self.leaves),
+        }
+    }
+}
+
+/// Collects verified chunks in any order and yields the file once all are in
+pub struct ChunkAssembler {
+    manifest: ChunkManifest,
+    buffer: Vec<u8>,
+    received: Vec<bool>,
+    remaining: u32,
+}
+
+impl ChunkAssembler {
+    pub fn new(manifest: ChunkManifest) -> Result<Self, ChunkError> {
+        manifest.validate()?;
+        let count = manifest.chunk_count();
+        Ok(Self {
+            buffer: vec![0; manifest.file_size as usize],
+            received: vec![false; count as usize],
+            remaining: count,
+            manifest,
+        })
+    }
+
+    /// Verify and place a chunk; returns whether it was new
+    pub fn insert(&mut self, index: u32, data: &[u8]) -> Result<bool, ChunkError> {
+        self.manifest.verify_chunk(index, data)?;
+        if self.received[index as usize] {
+            return Ok(false);
+        }
+        let range = self.manifest.chunk_range(index)?;
+        self.buffer[range].copy_from_slice(data);
+        self.received[index as usize] = true;
+        self.remaining -= 1;
+        Ok(true)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.remaining == 0
+    }
+
+    pub fn missing(&self) -> impl Iterator<Item = u32> + '_ {
+        self.received
+            .iter()
+            .enumerate()
+            .filter(|(_, have)| !**have)
+            .map(|(i, _)| i as u32)
+    }
+
+    pub fn finish(self) -> Result<Vec<u8>, ChunkError> {
+        if let Some(index) = self.missing().next() {
+            return Err(ChunkError::Missing { index });
+        }
+        Ok(self.buffer)
+    }
+}
+
+/// Split `data` per `manifest`, verify every chunk and reassemble it
+pub fn verify_and_assemble(manifest: &ChunkManifest, data: &[u8]) -> Result<Vec<u8>, ChunkError> {
+    if data.len() as u64 != manifest.file_size {
+        return Err(ChunkError::Invalid(format!(
+            "file has {} bytes, manifest expects {}",
+            data.len(),
+            manifest.file_size
+        )));
+    }
+    let mut assembler = ChunkAssembler::new(manifest.clone())?;
+    for index in 0..manifest.chunk_count() {
+        let range = manifest.chunk_range(index)?;
+        assembler.insert(index, &data[range])?;
+    }
+    assembler.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn manifest_splits_into_fixed_chunks_with_short_tail() {
+        let data = sample(10);
+        let manifest = ChunkManifest::build_with_chunk_size("h", &data, 4);
+        assert_eq!(manifest.chunk_count(), 3);
+        assert_eq!(manifest.chunk_range(2).unwrap(), 8..10);
+        assert!(manifest.validate().is_ok());
+        assert_eq!(verify_and_assemble(&manifest, &data).unwrap(), data);
+    }
+
+    #[test]
+    fn streamed_manifest_matches_the_whole_file_one() {
+        let data = sample(23);
+        let mut builder = ManifestBuilder::new(4);
+        for piece in data.chunks(3) {
+            builder.update(piece);
+        }
+        assert_eq!(
+            builder.finish("h"),
+            ChunkManifest::build_with_chunk_size("h", &data, 4)
+        );
+        assert_eq!(
+            ManifestBuilder::new(4).finish("h"),
+            ChunkManifest::build_with_chunk_size("h", &[], 4)
+        );
+    }
+
+    #[test]
+    fn corrupted_chunk_is_reported_by_index() {
+        let data = sample(10);
+        let manifest = ChunkManifest::build_with_chunk_size("h", &data, 4);
+        let mut corrupted = data.clone();
+        corrupted[5] ^= 0xff;
+        assert_eq!(
+            verify_and_assemble(&manifest, &corrupted),
+            Err(ChunkError::HashMismatch { index: 1 })
+        );
+
+        let mut tampered = manifest.clone();
+        tampered.chunk_hashes[0] = tampered.chunk_hashes[1].clone();
+        assert_eq!(tampered.validate(), Err(ChunkError::RootMismatch));
+    }
+
+    #[test]
+    fn assembler_accepts_out_of_order_chunks() {
+        let data = sample(9);
+        let manifest = ChunkManifest::build_with_chunk_size("h", &data, 3);
+        let mut assembler = ChunkAssembler::new(manifest).unwrap();
+        assert!(assembler.insert(2, &data[6..9]).unwrap());
+        assert!(assembler.insert(0, &data[0..3]).unwrap());
+        assert!(!assembler.insert(0, &data[0..3]).unwrap());
+        assert_eq!(assembler.missing().collect::<Vec<_>>(), vec![1]);
+        assert!(assembler.insert(1, &data[0..3]).is_err());
+        assert!(assembler.insert(1, &data[3..6]).unwrap());
+        assert!(assembler.is_complete());
+        assert_eq!(assembler.finish().unwrap(), data);
+    }
+
+    #[test]
+    fn single_chunk_verifies_against_root_with_proof() {
+        let data = sample(20);
+        let manifest = ChunkManifest::build_with_chunk_size("h", &data, 4);
+        let proof = manifest.proof(3).unwrap();
+        let chunk = &data[manifest.chunk_range(3).unwrap()];
+        assert!(verify_chunk_proof(&manifest.merkle_root, 5, 3, chunk, &proof).is_ok());
+        assert!(verify_chunk_proof(&manifest.merkle_root, 5, 2, chunk, &proof).is_err());
+    }
+
+    #[test]
+    fn empty_file_has_a_valid_manifest() {
+        let manifest = ChunkManifest::build("h", &[]);
+        assert_eq!(manifest.chunk_count(), 0);
+        assert!(manifest.validate().is_ok());
+        assert_eq!(
+            verify_and_assemble(&manifest, &[]).unwrap(),
+            Vec::<u8>::new()
+        );
+    }
+}
@@ -0,0 +1,220 @@
+// Download circuit breaker
+//
+// When downloads of one file hash keep failing because the content cannot be
+// found or fetched, new downloads of it are refused for a while instead of
+// queueing behind the same failure again. `failure_threshold` failures
+// within `window_secs` open the circuit for that hash for `open_secs`. Once
+// that time is up the next download is let through as a trial: if it fails
+// too the circuit opens again at once, if it succeeds the hash is forgotten.
+//
+// Only failures that say something about the content count. A full disk or
+// a bad output path is a local problem and another download of the same
+// hash could well succeed.
+
+use crate::error_codes::ErrorCode;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+
+/// Failures that suggest the content itself is unavailable
+const UNAVAILABLE: [ErrorCode; 6] = [
+    ErrorCode::NotFound,
+    ErrorCode::NoProviders,
+    ErrorCode::PeerRefused,
+    ErrorCode::Timeout,
+    ErrorCode::Network,
+    ErrorCode::Verification,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitConfig {
+    pub failure_threshold: u32,
+    pub window_secs: u64,
+    pub open_secs: u64,
+}
+
+impl Default for CircuitConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 3,
+            window_secs: 600,
+            open_secs: 300,
+        }
+    }
+}
+
+/// A file hash new downloads are refused for
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenCircuit {
+    pub file_hash: String,
+    /// Failures that opened the circuit
+    pub failures: u32,
+    /// When downloads are accepted again, Unix seconds
+    pub reopens_at: u64,
+}
+
+#[derive(Debug, Default)]
+struct FileCircuit {
+    /// When each recent failure happened, Unix seconds
+    failures: VecDeque<u64>,
+    open_until: Option<u64>,
+    /// Failures that opened the circuit
+    opened_after: u32,
+    /// The circuit was open and a trial download is let through
+    trial: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct CircuitBreaker {
+    config: CircuitConfig,
+    files: HashMap<String, FileCircuit>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitConfig) -> Self {
+        Self {
+            config,
+            files: HashMap::new(),
+        }
+    }
+
+    /// Whether a download failing with `code` counts against its hash
+    pub fn counts(code: ErrorCode) -> bool {
+        UNAVAILABLE.contains(&code)
+    }
+
+    /// Count a failed download of `file_hash` at `now`. Returns the open
+    /// circuit if this failure opened it.
+    pub fn record_failure(&mut self, file_hash: &str, now: u64) -> Option<OpenCircuit> {
+        let config = self.config;
+        let file = self.files.entry(file_hash.to_string()).or_default();
+        if file.open_until.is_some_and(|until| now < until) {
+            return None;
+        }
+        let window_start = now.saturating_sub(config.window_secs);
+        while file.failures.front().is_some_and(|&at| at < window_start) {
+            file.failures.pop_front();
+        }
+        file.failures.push_back(now);
+        let failures = file.failures.len() as u32;
+        if !file.trial && failures < config.failure_threshold.max(1) {
+            return None;
+        }
+        let reopens_at = now.saturating_add(config.open_secs);
+        file.open_until = Some(reopens_at);
+        file.opened_after = failures;
+        file.trial = false;
+        file.failures.clear();
+        Some(OpenCircuit {
+            file_hash: file_hash.to_string(),
+            failures,
+            reopens_at,
+        })
+    }
+
+    /// A download of `file_hash` succeeded; its failures are forgotten
+    pub fn record_success(&mut self, file_hash: &str) {
+        self.files.remove(file_hash);
+    }
+
+    /// When downloads of `file_hash` are accepted again, if they are refused
+    /// at `now`. A circuit whose time is up lets the next download through
+    /// as a trial.
+    pub fn check(&mut self, file_hash: &str, now: u64) -> Result<(), u64> {
+        let Some(file) = self.files.get_mut(file_hash) else {
+            return Ok(());
+        };
+        match file.open_until {
+            Some(until) if now < until => Err(until),
+            Some(_) => {
+                file.open_until = None;
+                file.trial = true;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Accept downloads of `file_hash` again. False if it was not refused.
+    pub fn reset(&mut self, file_hash: &str) -> bool {
+        self.files
+            .remove(file_hash)
+            .is_some_and(|file| file.open_until.is_some())
+    }
+
+    /// Every hash refused at `now`, the soonest to reopen first
+    pub fn open_circuits(&self, now: u64) -> Vec<OpenCircuit> {
+        let mut open: Vec<OpenCircuit> = self
+            .files
+            .iter()
+            .filter_map(|(hash, file)| {
+                let until = file.open_until.filter(|&until| now < until)?;
+                Some(OpenCircuit {
+                    file_hash: hash.clone(),
+                    failures: file.opened_after,
+                    reopens_at: until,
+                })
+            })
+            .collect();
+        open.sort_by(|a, b| {
+            a.reopens_at
+                .cmp(&b.reopens_at)
+                .then_with(|| a.file_hash.cmp(&b.file_hash))
+        });
+        open
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(CircuitConfig {
+            failure_threshold: 3,
+            window_secs: 100,
+            open_secs: 50,
+        })
+    }
+
+    #[test]
+    fn repeated_failures_within_the_window_open_the_circuit() {
+        let mut circuits = breaker();
+        assert_eq!(circuits.record_failure("hash", 0), None);
+        assert_eq!(circuits.record_failure("hash", 60), None);
+        // The first failure is out of the window by now
+        assert_eq!(circuits.record_failure("hash", 150), None);
+        assert_eq!(circuits.check("hash", 150), Ok(()));
+
+        let opened = circuits.record_failure("hash", 160).unwrap();
+        assert_eq!(opened.failures, 3);
+        assert_eq!(opened.reopens_at, 210);
+        assert_eq!(circuits.check("hash", 200), Err(210));
+        assert_eq!(circuits.check("other", 200), Ok(()));
+        assert_eq!(circuits.open_circuits(200), vec![opened]);
+        assert!(circuits.open_circuits(210).is_empty());
+    }
+
+    #[test]
+    fn a_failed_trial_reopens_at_once_and_a_success_closes() {
+        let mut circuits = breaker();
+        for now in 0..3 {
+            circuits.record_failure("hash", now);
+        }
+        assert_eq!(circuits.check("hash", 60), Ok(()));
+        let reopened = circuits.record_failure("hash", 70).unwrap();
+        assert_eq!(reopened.reopens_at, 120);
+
+        assert!(circuits.reset("hash"));
+        assert!(!circuits.reset("hash"));
+        assert_eq!(circuits.check("hash", 80), Ok(()));
+
+        for now in 200..202 {
+            circuits.record_failure("hash", now);
+        }
+        circuits.record_success("hash");
+        assert_eq!(circuits.record_failure("hash", 203), None);
+        assert!(!CircuitBreaker::counts(ErrorCode::DiskFull));
+        assert!(CircuitBreaker::counts(ErrorCode::NoProviders));
+    }
+}
@@ -0,0 +1,306 @@
+// Bounded command queue
+//
+// Commands reach the service loop through a bounded channel. When the loop
+// falls behind and the queue fills up, a sender either waits for room
+// (`Block`) or is turned away at once with a `BusyError` (`Reject`), so a
+// caller that would rather tell the user to try again does not hang. The
+// policy is picked when the service is created and can be overridden for a
+// single command.
+//
+// The sender keeps track of how deep the queue is, the deepest it has been
+// and how often senders had to wait or were turned away.
+
+use crate::error_codes::{ErrorCode, ServiceError};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+pub const DEFAULT_CAPACITY: usize = 100;
+
+/// What a send does when the queue is full
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Wait until the service takes a command off the queue
+    #[default]
+    Block,
+    /// Fail with `BusyError` right away
+    Reject,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CommandQueueConfig {
+    /// Commands that can wait for the service loop
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for CommandQueueConfig {
+    fn default() -> Self {
+        Self {
+            capacity: DEFAULT_CAPACITY,
+            overflow: OverflowPolicy::default(),
+        }
+    }
+}
+
+impl CommandQueueConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.capacity == 0 {
+            return Err("Command queue capacity must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// The queue was full and the send was not allowed to wait
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct BusyError {
+    pub capacity: usize,
+}
+
+impl fmt::Display for BusyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "File transfer service is busy ({} commands queued); try again shortly",
+            self.capacity
+        )
+    }
+}
+
+impl std::error::Error for BusyError {}
+
+impl From<BusyError> for ServiceError {
+    fn from(error: BusyError) -> Self {
+        ServiceError::new(ErrorCode::Busy, error.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    Busy(BusyError),
+    /// The service loop has stopped
+    Closed,
+}
+
+impl From<SendError> for ServiceError {
+    fn from(error: SendError) -> Self {
+        match error {
+            SendError::Busy(busy) => busy.into(),
+            SendError::Closed => ServiceError::new(
+                ErrorCode::ServiceUnavailable,
+                "File transfer service is not running",
+            ),
+        }
+    }
+}
+
+impl From<SendError> for String {
+    fn from(error: SendError) -> Self {
+        ServiceError::from(error).to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CommandQueueStats {
+    /// Commands waiting right now
+    pub depth: usize,
+    pub capacity: usize,
+    /// Deepest the queue has been
+    pub peak_depth: usize,
+    pub sent: u64,
+    /// Sends that found the queue full and waited
+    pub waited: u64,
+    /// Sends turned away with `BusyError`
+    pub rejected: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    peak_depth: AtomicUsize,
+    sent: AtomicU64,
+    waited: AtomicU64,
+    rejected: AtomicU64,
+}
+
+/// Sending half of a command queue
+#[derive(Debug)]
+pub struct CommandSender<T> {
+    tx: mpsc::Sender<T>,
+    overflow: OverflowPolicy,
+    counters: Arc<Counters>,
+}
+
+impl<T> Clone for CommandSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            tx: self.tx.clone(),
+            overflow: self.overflow,
+            counters: self.counters.clone(),
+        }
+    }
+}
+
+/// A `CommandSender` that does not keep the queue open
+#[derive(Debug)]
+pub struct WeakCommandSender<T> {
+    tx: mpsc::WeakSender<T>,
+    overflow: OverflowPolicy,
+    counters: Arc<Counters>,
+}
+
+impl<T> WeakCommandSender<T> {
+    pub fn upgrade(&self) -> Option<CommandSender<T>> {
+        Some(CommandSender {
+            tx: self.tx.upgrade()?,
+            overflow: self.overflow,
+            counters: self.counters.clone(),
+        })
+    }
+}
+
+pub fn command_queue<T>(config: CommandQueueConfig) -> (CommandSender<T>, mpsc::Receiver<T>) {
+    let (tx, rx) = mpsc::channel(config.capacity.max(1));
+    let sender = CommandSender {
+        tx,
+        overflow: config.overflow,
+        counters: Arc::default(),
+    };
+    (sender, rx)
+}
+
+impl<T> CommandSender<T> {
+    /// Queue `command` under the queue's overflow policy
+    pub async fn send(&self, command: T) -> Result<(), SendError> {
+        self.send_with(command, self.overflow).await
+    }
+
+    /// Queue `command`, doing what `overflow` says if the queue is full
+    pub async fn send_with(&self, command: T, overflow: OverflowPolicy) -> Result<(), SendError> {
+        match self.tx.try_send(command) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Closed(_)) => return Err(SendError::Closed),
+            Err(mpsc::error::TrySendError::Full(command)) => match overflow {
+                OverflowPolicy::Reject => {
+                    self.counters.rejected.fetch_add(1, Ordering::Relaxed);
+                    return Err(SendError::Busy(BusyError {
+                        capacity: self.tx.max_capacity(),
+                    }));
+                }
+                OverflowPolicy::Block => {
+                    self.counters.waited.fetch_add(1, Ordering::Relaxed);
+                    self.tx.send(command).await.map_err(|_| SendError::Closed)?;
+                }
+            },
+        }
+        self.counters.sent.fetch_add(1, Ordering::Relaxed);
+        self.counters
+            .peak_depth
+            .fetch_max(self.depth(), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Commands queued and not yet taken by the service
+    pub fn depth(&self) -> usize {
+        self.tx.max_capacity() - self.tx.capacity()
+    }
+
+    pub fn overflow(&self) -> OverflowPolicy {
+        self.overflow
+    }
+
+    pub fn downgrade(&self) -> WeakCommandSender<T> {
+        WeakCommandSender {
+            tx: self.tx.downgrade(),
+            overflow: self.overflow,
+            counters: self.counters.clone(),
+        }
+    }
+
+    pub fn stats(&self) -> CommandQueueStats {
+        CommandQueueStats {
+            depth: self.depth(),
+            capacity: self.tx.max_capacity(),
+            peak_depth: self.counters.peak_depth.load(Ordering::Relaxed),
+            sent: self.counters.sent.load(Ordering::Relaxed),
+            waited: self.counters.waited.load(Ordering::Relaxed),
+            rejected: self.counters.rejected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(overflow: OverflowPolicy) -> CommandQueueConfig {
+        CommandQueueConfig {
+            capacity: 2,
+            overflow,
+        }
+    }
+
+    #[tokio::test]
+    async fn a_full_queue_rejects_or_waits_as_told() {
+        let (tx, mut rx) = command_queue::<u32>(config(OverflowPolicy::Reject));
+        tx.send(1).await.unwrap();
+        tx.send(2).await.unwrap();
+        assert_eq!(
+            tx.send(3).await,
+            Err(SendError::Busy(BusyError { capacity: 2 }))
+        );
+        let error = String::from(tx.send(3).await.unwrap_err());
+        assert_eq!(
+            ServiceError::parse(&error).map(|e| e.code),
+            Some(ErrorCode::Busy)
+        );
+
+        // This one may wait; it goes in once the service takes a command
+        let waiting = tx.clone();
+        let blocked =
+            tokio::spawn(async move { waiting.send_with(3, OverflowPolicy::Block).await });
+        tokio::task::yield_now().await;
+        assert!(!blocked.is_finished());
+        assert_eq!(rx.recv().await, Some(1));
+        blocked.await.unwrap().unwrap();
+        assert_eq!(
+            tx.stats(),
+            CommandQueueStats {
+                depth: 2,
+                capacity: 2,
+                peak_depth: 2,
+                sent: 3,
+                waited: 1,
+                rejected: 2,
+            }
+        );
+
+        drop(rx);
+        assert_eq!(tx.send(4).await, Err(SendError::Closed));
+    }
+
+    #[tokio::test]
+    async fn weak_senders_do_not_keep_the_queue_open() {
+        let (tx, mut rx) = command_queue::<u32>(CommandQueueConfig::default());
+        let weak = tx.downgrade();
+        weak.upgrade().unwrap().send(1).await.unwrap();
+        assert_eq!(tx.stats().sent, 1);
+        assert_eq!(tx.overflow(), OverflowPolicy::Block);
+        drop(tx);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(rx.recv().await, Some(1));
+        assert_eq!(rx.recv().await, None);
+        assert!(CommandQueueConfig {
+            capacity: 0,
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+    }
+}
@@ -0,0 +1,354 @@
+// Queued, running and paused downloads
+//
+// The command loop hands every download to its own task so it can keep
+// receiving commands. `DownloadTable` decides when those tasks start and
+// tracks them while they run. New downloads wait in a queue ordered by
+// priority, then by arrival, and start while fewer than `max_concurrent`
+// are running. A running download has a cancellation token and remembers
+// why it was asked to stop, and a paused download keeps the request it was
+// started with so `ResumeTransfer` can queue it again. Pausing leaves the
+// part file and resume record in place, so the restarted task only fetches
+// the chunks still missing.
+
+use super::progress::TransferProgress;
+use super::retry::RetryPolicy;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use tokio_util::sync::CancellationToken;
+
+/// Downloads running at once unless configured otherwise
+pub const DEFAULT_MAX_CONCURRENT: usize = 3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+/// Everything needed to (re)start a download
+#[derive(Debug, Clone)]
+pub struct DownloadJob {
+    pub transfer_id: String,
+    pub file_hash: String,
+    pub output_path: String,
+    pub providers: Vec<String>,
+    pub retry_policy: Option<RetryPolicy>,
+    pub priority: TransferPriority,
+    /// Fetch chunks in order so the file can be read while it downloads
+    pub streaming: bool,
+    /// Set when the job fetches a folder manifest: the directory the
+    /// folder's files are downloaded into once it arrives
+    pub folder_root: Option<String>,
+    pub active_account: Option<String>,
+    pub active_private_key: Option<String>,
+}
+
+/// Why a running download was told to stop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopRequest {
+    /// Stop and throw the partial output away
+    Cancel,
+    /// Stop but keep the partial output for a later resume
+    Pause,
+}
+
+#[derive(Debug)]
+struct Queued {
+    job: DownloadJob,
+    /// Arrival order, to keep downloads of equal priority first come first
+    /// served
+    seq: u64,
+    resumed: bool,
+}
+
+/// A download the table has just moved from the queue to running
+#[derive(Debug)]
+pub struct StartedDownload {
+    pub job: DownloadJob,
+    pub cancel: CancellationToken,
+    /// Restarted after a pause rather than started fresh
+    pub resumed: bool,
+}
+
+#[derive(Debug)]
+struct Running {
+    job: DownloadJob,
+    cancel: CancellationToken,
+    stop: Option<StopRequest>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PausedDownload {
+    pub job: DownloadJob,
+    /// Where the download stood when it stopped
+    pub progress: TransferProgress,
+}
+
+#[derive(Debug)]
+pub struct DownloadTable {
+    queued: Vec<Queued>,
+    running: HashMap<String, Running>,
+    paused: HashMap<String, PausedDownload>,
+    max_concurrent: usize,
+    next_seq: u64,
+}
+
+impl Default for DownloadTable {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_CONCURRENT)
+    }
+}
+
+impl DownloadTable {
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            queued: Vec::new(),
+            running: HashMap::new(),
+            paused: HashMap::new(),
+            max_concurrent: max_concurrent.max(1),
+            next_seq: 0,
+        }
+    }
+
+    /// Queue a download. Returns its place in the queue, 0 being next.
+    pub fn enqueue(&mut self, job: DownloadJob, resumed: bool) -> Result<usize, String> {
+        let id = &job.transfer_id;
+        if self.running.contains_key(id) || self.queued.iter().any(|q| &q.job.transfer_id == id) {
+            return Err(format!("Transfer {} is already queued or running", id));
+        }
+        self.paused.remove(id);
+        let transfer_id = id.clone();
+        self.queued.push(Queued {
+            job,
+            seq: self.next_seq,
+            resumed,
+        });
+        self.next_seq += 1;
+        self.sort_queue();
+        Ok(self.queue_position(&transfer_id).unwrap_or_default())
+    }
+
+    /// Move queued downloads to running while there is room, handing each
+    /// one out with its token
+    pub fn start_ready(&mut self) -> Vec<StartedDownload> {
+        let mut started = Vec::new();
+        while self.running.len() < self.max_concurrent && !self.queued.is_empty() {
+            let Queued { job, resumed, .. } = self.queued.remove(0);
+            let cancel = CancellationToken::new();
+            self.running.insert(
+                job.transfer_id.clone(),
+                Running {
+                    job: job.clone(),
+                    cancel: cancel.clone(),
+                    stop: None,
+                },
+            );
+            started.push(StartedDownload {
+                job,
+                cancel,
+                resumed,
+            });
+        }
+        started
+    }
+
+    /// Change the priority of a queued download, moving it within the
+    /// queue. Returns false if it is not waiting in the queue.
+    pub fn set_priority(&mut self, transfer_id: &str, priority: TransferPriority) -> bool {
+        let Some(queued) = self
+            .queued
+            .iter_mut()
+            .find(|q| q.job.transfer_id == transfer_id)
+        else {
+            return false;
+        };
+        queued.job.priority = priority;
+        self.sort_queue();
+        true
+    }
+
+    pub fn set_max_concurrent(&mut self, max_concurrent: usize) {
+        self.max_concurrent = max_concurrent.max(1);
+    }
+
+    pub fn max_concurrent(&self) -> usize {
+        self.max_concurrent
+    }
+
+    pub fn queue_position(&self, transfer_id: &str) -> Option<usize> {
+        self.queued
+            .iter()
+            .position(|q| q.job.transfer_id == transfer_id)
+    }
+
+    /// Take a download out of the queue before it started
+    pub fn dequeue(&mut self, transfer_id: &str) -> Option<DownloadJob> {
+        let index = self.queue_position(transfer_id)?;
+        Some(self.queued.remove(index).job)
+    }
+
+    fn sort_queue(&mut self) {
+        self.queued
+            .sort_by(|a, b| b.job.priority.cmp(&a.job.priority).then(a.seq.cmp(&b.seq)));
+    }
+
+    /// Ask a running download to stop. A cancel overrides an earlier pause,
+    /// never the other way round. Returns false if it is not running.
+    pub fn request_stop(&mut self, transfer_id: &str, stop: StopRequest) -> bool {
+        let Some(running) = self.running.get_mut(transfer_id) else {
+            return false;
+        };
+        if running.stop != Some(StopRequest::Cancel) {
+            running.stop = Some(stop);
+        }
+        running.cancel.cancel();
+        true
+    }
+
+    /// Drop a finished download. If it ended because of a pause request it
+    /// moves to the paused set. Returns the stop request that ended it.
+    pub fn finish(&mut self, transfer_id: &str, progress: TransferProgress) -> Option<StopRequest> {
+        let running = self.running.remove(transfer_id)?;
+        if running.stop == Some(StopRequest::Pause) {
+            self.paused.insert(
+                transfer_id.to_string(),
+                PausedDownload {
+                    job: running.job,
+                    progress,
+                },
+            );
+        }
+        running.stop
+    }
+
+    pub fn take_paused(&mut self, transfer_id: &str) -> Option<PausedDownload> {
+        self.paused.remove(transfer_id)
+    }
+
+    pub fn is_running(&self, transfer_id: &str) -> bool {
+        self.running.contains_key(transfer_id)
+    }
+
+    pub fn is_paused(&self, transfer_id: &str) -> bool {
+        self.paused.contains_key(transfer_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_transfer::progress::TransferDirection;
+
+    fn job(id: &str) -> DownloadJob {
+        DownloadJob {
+            transfer_id: id.to_string(),
+            file_hash: "hash".to_string(),
+            output_path: "/tmp/out".to_string(),
+            providers: Vec::new(),
+            retry_policy: None,
+            priority: TransferPriority::Normal,
+            streaming: false,
+            folder_root: None,
+            active_account: None,
+            active_private_key: None,
+        }
+    }
+
+    fn progress(id: &str, done: u64) -> TransferProgress {
+        TransferProgress {
+            transfer_id: id.to_string(),
+            direction: TransferDirection::Download,
+            bytes_done: done,
+            total_bytes: 100,
+            speed_bps: 0.0,
+            eta_secs: None,
+            timestamp: 0,
+        }
+    }
+
+    /// Queue `job` and start it right away
+    fn start(table: &mut DownloadTable, job: DownloadJob) -> CancellationToken {
+        table.enqueue(job, false).unwrap();
+        table.start_ready().pop().expect("started").cancel
+    }
+
+    #[test]
+    fn paused_downloads_are_kept_for_resume() {
+        let mut table = DownloadTable::default();
+        let token = start(&mut table, job("t1"));
+        assert!(table.enqueue(job("t1"), false).is_err());
+
+        assert!(table.request_stop("t1", StopRequest::Pause));
+        assert!(token.is_cancelled());
+        assert_eq!(
+            table.finish("t1", progress("t1", 40)),
+            Some(StopRequest::Pause)
+        );
+        assert!(!table.is_running("t1"));
+        assert!(table.is_paused("t1"));
+
+        let paused = table.take_paused("t1").unwrap();
+        assert_eq!(paused.progress.bytes_done, 40);
+        table.enqueue(paused.job, true).unwrap();
+        let restarted = table.start_ready().pop().unwrap();
+        assert!(restarted.resumed);
+        assert!(!restarted.cancel.is_cancelled());
+        assert!(table.is_running("t1"));
+    }
+
+    #[test]
+    fn cancel_wins_over_pause() {
+        let mut table = DownloadTable::default();
+        start(&mut table, job("t1"));
+        table.request_stop("t1", StopRequest::Cancel);
+        table.request_stop("t1", StopRequest::Pause);
+        assert_eq!(
+            table.finish("t1", progress("t1", 0)),
+            Some(StopRequest::Cancel)
+        );
+        assert!(!table.is_paused("t1"));
+
+        assert!(!table.request_stop("unknown", StopRequest::Pause));
+        start(&mut table, job("t2"));
+        assert_eq!(table.finish("t2", progress("t2", 100)), None);
+    }
+
+    #[test]
+    fn queue_respects_priority_and_concurrency_limit() {
+        let mut table = DownloadTable::new(2);
+        let with_priority = |id: &str, priority| DownloadJob {
+            priority,
+            ..job(id)
+        };
+        table
+            .enqueue(with_priority("low", TransferPriority::Low), false)
+            .unwrap();
+        table.enqueue(job("normal-1"), false).unwrap();
+        table.enqueue(job("normal-2"), false).unwrap();
+        let position = table
+            .enqueue(with_priority("high", TransferPriority::High), false)
+            .unwrap();
+        assert_eq!(position, 0);
+
+        let ids = |started: Vec<StartedDownload>| -> Vec<String> {
+            started.into_iter().map(|s| s.job.transfer_id).collect()
+        };
+        assert_eq!(ids(table.start_ready()), vec!["high", "normal-1"]);
+        assert!(table.start_ready().is_empty());
+
+        // Reordering: the low priority download jumps ahead of normal-2
+        assert!(table.set_priority("low", TransferPriority::High));
+        assert!(!table.set_priority("high", TransferPriority::Low));
+        assert_eq!(table.queue_position("low"), Some(0));
+
+        table.finish("high", progress("high", 100));
+        assert_eq!(ids(table.start_ready()), vec!["low"]);
+
+        table.set_max_concurrent(4);
+        assert_eq!(ids(table.start_ready()), vec!["normal-2"]);
+        assert!(table.dequeue("normal-2").is_none());
+    }
+}
@@ -0,0 +1,169 @@
+// Signed folder manifests
+//
+// `UploadDirectory` shares a whole folder as one stored object: the
+// `DirectoryManifest` of its files, signed with the uploader's account key.
+// The manifest is stored and fetched like any other file, so its own hash is
+// the folder's identifier and providers serve it chunk by chunk. A
+// downloader checks the signature and the entry paths before queueing the
+// files it lists.
+
+use crate::directory_manifest::DirectoryManifest;
+use secp256k1::{ecdsa::Signature, Message, PublicKey, Secp256k1, SecretKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// Domain separator of the signed payload
+const SIGNING_CONTEXT: &str = "chiral-folder-manifest";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FolderManifest {
+    #[serde(flatten)]
+    pub manifest: DirectoryManifest,
+    /// Compressed secp256k1 public key of the uploader's account, hex
+    pub public_key: String,
+    /// Compact ECDSA signature over the manifest and public key, hex
+    pub signature: String,
+}
+
+fn signing_digest(manifest: &DirectoryManifest, public_key: &str) -> Result<Message, String> {
+    let payload = serde_json::to_vec(&(SIGNING_CONTEXT, manifest, public_key))
+        .map_err(|e| format!("Failed to serialize folder manifest: {}", e))?;
+    Message::from_slice(&Sha256::digest(&payload))
+        .map_err(|e| format!("Invalid folder manifest digest: {}", e))
+}
+
+impl FolderManifest {
+    /// Sign `manifest` with the account key `private_key_hex`
+    pub fn signed(manifest: DirectoryManifest, private_key_hex: &str) -> Result<Self, String> {
+        let key_bytes = hex::decode(private_key_hex.trim_start_matches("0x"))
+            .map_err(|e| format!("Invalid hex private key: {}", e))?;
+        let secret_key =
+            SecretKey::from_slice(&key_bytes).map_err(|e| format!("Invalid private key: {}", e))?;
+        let secp = Secp256k1::new();
+        let public_key = hex::encode(PublicKey::from_secret_key(&secp, &secret_key).serialize());
+        let signature = secp.sign_ecdsa(&signing_digest(&manifest, &public_key)?, &secret_key);
+        Ok(Self {
+            manifest,
+            public_key,
+            signature: hex::encode(signature.serialize_compact()),
+        })
+    }
+
+    /// Check the entries and the signature
+    pub fn verify(&self) -> Result<(), String> {
+        self.manifest.validate()?;
+        let key_bytes = hex::decode(&self.public_key)
+            .map_err(|e| format!("Invalid manifest public key: {}", e))?;
+        let public_key = PublicKey::from_slice(&key_bytes)
+            .map_err(|e| format!("Invalid manifest public key: {}", e))?;
+        let signature_bytes = hex::decode(&self.signature)
+            .map_err(|e| format!("Invalid manifest signature: {}", e))?;
+        let signature = Signature::from_compact(&signature_bytes)
+            .map_err(|e| format!("Invalid manifest signature: {}", e))?;
+        Secp256k1::verification_only()
+            .verify_ecdsa(
+                &signing_digest(&self.manifest, &self.public_key)?,
+                &signature,
+                &public_key,
+            )
+            .map_err(|_| "Folder manifest signature does not verify".to_string())
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        serde_json::to_vec(self).map_err(|e| format!("Failed to serialize folder manifest: {}", e))
+    }
+
+    /// Parse and verify a manifest
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        let folder: Self =
+            serde_json::from_slice(bytes).map_err(|e| format!("Not a folder manifest: {}", e))?;
+        folder.verify()?;
+        Ok(folder)
+    }
+}
+
+/// Regular files under `root`, sorted. Symlinks are skipped so a folder
+/// can't reach outside itself.
+pub async fn collect_files(root: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let mut entries = tokio::fs::read_dir(&dir)
+            .await
+            .map_err(|e| format!("Failed to read directory {}: {}", dir.display(), e))?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(|e| format!("Failed to read directory entry: {}", e))?
+        {
+            let file_type = entry
+                .file_type()
+                .await
+                .map_err(|e| format!("Failed to read file type: {}", e))?;
+            if file_type.is_dir() {
+                pending.push(entry.path());
+            } else if file_type.is_file() {
+                files.push(entry.path());
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    #[test]
+    fn signed_manifest_round_trips_and_detects_tampering() {
+        let root = Path::new("/photos");
+        let manifest = DirectoryManifest::new(
+            "photos".into(),
+            root,
+            vec![
+                (root.join("b/2.jpg"), "h2".into(), 3),
+                (root.join("a.jpg"), "h1".into(), 3),
+            ],
+            10,
+        )
+        .unwrap();
+        let folder = FolderManifest::signed(manifest, KEY).unwrap();
+        let parsed = FolderManifest::from_bytes(&folder.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed, folder);
+        assert_eq!(parsed.manifest.entries[0].path, "a.jpg");
+
+        let mut tampered = folder.clone();
+        tampered.manifest.entries[0].file_hash = "other".to_string();
+        assert!(tampered.verify().unwrap_err().contains("signature"));
+
+        let mut escaping = folder;
+        escaping.manifest.entries[0].path = "../evil".to_string();
+        assert!(escaping
+            .verify()
+            .unwrap_err()
+            .contains("Invalid manifest path"));
+    }
+
+    #[tokio::test]
+    async fn files_are_collected_recursively() {
+        let dir = tempdir().unwrap();
+        tokio::fs::create_dir_all(dir.path().join("sub/deeper"))
+            .await
+            .unwrap();
+        for path in ["z.txt", "sub/a.txt", "sub/deeper/b.txt"] {
+            tokio::fs::write(dir.path().join(path), path).await.unwrap();
+        }
+        let files = collect_files(dir.path()).await.unwrap();
+        let expected: Vec<PathBuf> = ["sub/a.txt", "sub/deeper/b.txt", "z.txt"]
+            .iter()
+            .map(|p| dir.path().join(p))
+            .collect();
+        assert_eq!(files, expected);
+    }
+}
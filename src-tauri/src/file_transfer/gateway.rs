@@ -0,0 +1,497 @@
+// Local HTTP gateway
+//
+// An optional server on 127.0.0.1 that serves files by hash, so the
+// webview's <video> and <audio> elements can play them straight from the
+// node. Stored files are read through the blob store, so sealed,
+// deduplicated and linked blobs come out as plain bytes. A streaming
+// download still in progress is served from its part file: bytes past the
+// verified prefix are sent as they arrive, so a player seeking ahead just
+// waits for them instead of failing.
+//
+// Range requests (`bytes=a-b`, `bytes=a-`, `bytes=-n`) are answered with
+// 206. Every request must carry the token generated when the gateway
+// starts, so other local programs and web pages cannot read the store.
+
+use super::{FileTransferEvent, FileTransferService};
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio::sync::oneshot;
+use tracing::{debug, info, warn};
+
+/// Bytes read per body frame
+const READ_LEN: u64 = 256 * 1024;
+/// Bytes read to guess the content type
+const SNIFF_LEN: u64 = 4096;
+/// How often a read ahead of a partial file checks for new bytes
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+/// A read ahead of a partial file fails after waiting this long
+const STALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A streaming download the gateway can serve before it finishes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialFile {
+    pub transfer_id: String,
+    pub path: PathBuf,
+    /// Verified bytes from the start of the file
+    pub readable_bytes: u64,
+    pub total_bytes: u64,
+}
+
+/// Streaming downloads by file hash, kept up to date from transfer events.
+/// A finished download stays readable at its output path.
+#[derive(Debug, Default)]
+pub struct PartialFiles {
+    files: HashMap<String, PartialFile>,
+}
+
+impl PartialFiles {
+    pub fn observe(&mut self, event: &FileTransferEvent) {
+        match event {
+            FileTransferEvent::StreamReadable {
+                transfer_id,
+                file_hash,
+                part_path,
+                readable_bytes,
+                total_bytes,
+            } => {
+                self.files.insert(
+                    file_hash.clone(),
+                    PartialFile {
+                        transfer_id: transfer_id.clone(),
+                        path: PathBuf::from(part_path),
+                        readable_bytes: *readable_bytes,
+                        total_bytes: *total_bytes,
+                    },
+                );
+            }
+            FileTransferEvent::FileDownloaded {
+                transfer_id,
+                file_path,
+            } => {
+                for file in self.files.values_mut() {
+                    if &file.transfer_id == transfer_id {
+                        file.path = PathBuf::from(file_path);
+                        file.readable_bytes = file.total_bytes;
+                    }
+                }
+            }
+            FileTransferEvent::Error { transfer_id, .. }
+            | FileTransferEvent::Cancelled { transfer_id } => {
+                self.files
+                    .retain(|_, file| &file.transfer_id != transfer_id);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn get(&self, file_hash: &str) -> Option<PartialFile> {
+        self.files.get(file_hash).cloned()
+    }
+}
+
+/// Where the bytes of a requested file come from
+#[derive(Debug, Clone)]
+enum Source {
+    /// Blob store key
+    Stored(String),
+    Partial(String),
+}
+
+struct GatewayState {
+    service: Arc<FileTransferService>,
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+/// A running gateway; stops when dropped
+pub struct Gateway {
+    addr: SocketAddr,
+    token: String,
+    shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl Gateway {
+    /// Serve `service`'s files on 127.0.0.1:`port`; port 0 picks a free one
+    pub async fn start(service: Arc<FileTransferService>, port: u16) -> Result<Self, String> {
+        let token = hex::encode(rand::random::<[u8; 16]>());
+        let app = router(service, token.clone());
+        let listener = tokio::net::TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], port)))
+            .await
+            .map_err(|e| format!("Failed to start HTTP gateway: {}", e))?;
+        let addr = listener.local_addr().map_err(|e| e.to_string())?;
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        tokio::spawn(async move {
+            let server = axum::serve(listener, app).with_graceful_shutdown(async {
+                shutdown_rx.await.ok();
+            });
+            if let Err(e) = server.await {
+                warn!("HTTP gateway error: {}", e);
+            }
+        });
+        info!(%addr, "HTTP gateway listening");
+        Ok(Self {
+            addr,
+            token,
+            shutdown: Some(shutdown),
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// URL a media element can load `file_hash` from
+    pub fn file_url(&self, file_hash: &str) -> String {
+        format!(
+            "http://{}/files/{}?token={}",
+            self.addr, file_hash, self.token
+        )
+    }
+}
+
+impl Drop for Gateway {
+    fn drop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+    }
+}
+
+fn router(service: Arc<FileTransferService>, token: String) -> Router {
+    Router::new()
+        .route("/files/:file_hash", get(serve_file))
+        .with_state(Arc::new(GatewayState { service, token }))
+}
+
+async fn serve_file(
+    Path(file_hash): Path<String>,
+    Query(query): Query<TokenQuery>,
+    State(state): State<Arc<GatewayState>>,
+    headers: HeaderMap,
+) -> Response {
+    if query.token.as_deref() != Some(state.token.as_str()) {
+        return (StatusCode::FORBIDDEN, "Missing or wrong gateway token").into_response();
+    }
+    let Some((source, size)) = locate(&state.service, &file_hash).await else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("File not found: {}", file_hash),
+        )
+            .into_response();
+    };
+
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok());
+    let (status, start, end) = match range.map(|range| parse_range(range, size)) {
+        None | Some(RangeRequest::Ignored) => (StatusCode::OK, 0, size),
+        Some(RangeRequest::Bytes(start, end)) => (StatusCode::PARTIAL_CONTENT, start, end),
+        Some(RangeRequest::Unsatisfiable) => {
+            return (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{}", size))],
+            )
+                .into_response();
+        }
+    };
+    debug!(hash = %file_hash, start, end, size, "gateway read");
+
+    let head = read(&state.service, &source, 0, size.min(SNIFF_LEN))
+        .await
+        .unwrap_or_default();
+    let content_type =
+        crate::content_info::detect_mime(&head).unwrap_or("application/octet-stream");
+    let service = state.service.clone();
+    let body = futures::stream::unfold(start, move |offset| {
+        let service = service.clone();
+        let source = source.clone();
+        async move {
+            if offset >= end {
+                return None;
+            }
+            let len = (end - offset).min(READ_LEN);
+            match read(&service, &source, offset, len).await {
+                Ok(data) => Some((Ok(Bytes::from(data)), offset + len)),
+                Err(e) => Some((Err(std::io::Error::other(e)), end)),
+            }
+        }
+    });
+
+    let mut response = Response::new(Body::from_stream(body));
+    *response.status_mut() = status;
+    let headers = response.headers_mut();
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(end - start));
+    if status == StatusCode::PARTIAL_CONTENT {
+        let content_range = format!("bytes {}-{}/{}", start, end - 1, size);
+        if let Ok(value) = HeaderValue::from_str(&content_range) {
+            headers.insert(header::CONTENT_RANGE, value);
+        }
+    }
+    response
+}
+
+/// The source of `file_hash` and its full size
+async fn locate(service: &FileTransferService, file_hash: &str) -> Option<(Source, u64)> {
+    if let Some(key) = FileTransferService::resolve_stored_hash(&service.blobs, file_hash).await {
+        let size = service.blobs.entry(&key).await?.size;
+        return Some((Source::Stored(key), size));
+    }
+    let partial = service.partials.lock().unwrap().get(file_hash)?;
+    Some((Source::Partial(file_hash.to_string()), partial.total_bytes))
+}
+
+async fn read(
+    service: &FileTransferService,
+    source: &Source,
+    offset: u64,
+    len: u64,
+) -> Result<Vec<u8>, String> {
+    match source {
+        Source::Stored(key) => service
+            .blobs
+            .read_range(key, offset, len as usize)
+            .await?
+            .ok_or_else(|| "File was removed from storage".to_string()),
+        Source::Partial(file_hash) => read_partial(service, file_hash, offset, len).await,
+    }
+}
+
+/// Read from a download in progress, waiting for the bytes to be verified.
+/// The file moves to its output path when the download finishes, so the
+/// entry is looked up again on every try.
+async fn read_partial(
+    service: &FileTransferService,
+    file_hash: &str,
+    offset: u64,
+    len: u64,
+) -> Result<Vec<u8>, String> {
+    let deadline = tokio::time::Instant::now() + STALL_TIMEOUT;
+    loop {
+        let file = service
+            .partials
+            .lock()
+            .unwrap()
+            .get(file_hash)
+            .ok_or_else(|| format!("Download of {} stopped", file_hash))?;
+        if file.readable_bytes >= offset + len {
+            match read_file(&file.path, offset, len).await {
+                Ok(data) => return Ok(data),
+                Err(e) if tokio::time::Instant::now() >= deadline => return Err(e),
+                Err(e) => debug!(hash = %file_hash, "partial read failed, retrying: {}", e),
+            }
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return Err(format!(
+                "Timed out waiting for bytes {}..{} of {}",
+                offset,
+                offset + len,
+                file_hash
+            ));
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+async fn read_file(path: &std::path::Path, offset: u64, len: u64) -> Result<Vec<u8>, String> {
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    file.seek(std::io::SeekFrom::Start(offset))
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let mut buf = vec![0u8; len as usize];
+    file.read_exact(&mut buf)
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    Ok(buf)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum RangeRequest {
+    /// Half-open byte range
+    Bytes(u64, u64),
+    /// Not a single byte range; answered with the whole file
+    Ignored,
+    Unsatisfiable,
+}
+
+fn parse_range(header: &str, size: u64) -> RangeRequest {
+    let Some((start, end)) = header
+        .trim()
+        .strip_prefix("bytes=")
+        .filter(|spec| !spec.contains(','))
+        .and_then(|spec| spec.split_once('-'))
+    else {
+        return RangeRequest::Ignored;
+    };
+    let (start, end) = (start.trim(), end.trim());
+    let parsed = match (start.parse::<u64>(), end.parse::<u64>()) {
+        (Ok(start), Ok(end)) if start <= end => Some((start, end.saturating_add(1))),
+        (Ok(start), Err(_)) if end.is_empty() => Some((start, size)),
+        (Err(_), Ok(suffix)) if start.is_empty() => Some((size.saturating_sub(suffix), size)),
+        _ => None,
+    };
+    match parsed {
+        None => RangeRequest::Ignored,
+        Some((start, end)) if start >= size || start >= end => RangeRequest::Unsatisfiable,
+        Some((start, end)) => RangeRequest::Bytes(start, end.min(size)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime_env::RuntimeEnv;
+    use tempfile::tempdir;
+    use tower::util::ServiceExt;
+
+    async fn get(app: &Router, uri: &str, range: Option<&str>) -> (StatusCode, HeaderMap, Bytes) {
+        let mut request = axum::http::Request::builder().uri(uri);
+        if let Some(range) = range {
+            request = request.header(header::RANGE, range);
+        }
+        let response = app
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, headers, body)
+    }
+
+    #[tokio::test]
+    async fn serves_stored_and_partial_files_by_range() {
+        let storage = tempdir().unwrap();
+        let keystore = Arc::new(tokio::sync::Mutex::new(crate::keystore::Keystore::new()));
+        let service = Arc::new(
+            FileTransferService::new_with_env(
+                storage.path().to_path_buf(),
+                false,
+                keystore,
+                None,
+                RuntimeEnv::deterministic(11),
+            )
+            .await
+            .unwrap(),
+        );
+        let mut image = b"\x89PNG\r\n\x1a\n".to_vec();
+        image.extend((0..100u8).collect::<Vec<_>>());
+        let hash = FileTransferService::calculate_file_hash(&image);
+        service
+            .store_file_data(hash.clone(), "clip.png".to_string(), image.clone())
+            .await;
+        let app = router(service.clone(), "secret".to_string());
+        let uri = format!("/files/{}?token=secret", hash);
+
+        let (status, headers, body) = get(&app, &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[header::CONTENT_TYPE], "image/png");
+        assert_eq!(body.as_ref(), image.as_slice());
+
+        let (status, headers, body) = get(&app, &uri, Some("bytes=8-11")).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(headers[header::CONTENT_RANGE], "bytes 8-11/108");
+        assert_eq!(body.as_ref(), &[0, 1, 2, 3]);
+
+        let (status, _, _) = get(&app, &uri, Some("bytes=500-")).await;
+        assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+        let (status, _, _) = get(&app, &format!("/files/{}", hash), None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _, _) = get(&app, "/files/unknown?token=secret", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // A streaming download is served from its part file
+        let part = storage.path().join("movie.mp4.part");
+        std::fs::write(&part, b"moviedata").unwrap();
+        service
+            .partials
+            .lock()
+            .unwrap()
+            .observe(&FileTransferEvent::StreamReadable {
+                transfer_id: "t1".to_string(),
+                file_hash: "movie".to_string(),
+                part_path: part.to_string_lossy().into_owned(),
+                readable_bytes: 9,
+                total_bytes: 9,
+            });
+        let (status, _, body) = get(&app, "/files/movie?token=secret", Some("bytes=-4")).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(body.as_ref(), b"data");
+    }
+
+    #[test]
+    fn ranges_are_parsed_and_clamped() {
+        assert_eq!(parse_range("bytes=0-99", 1000), RangeRequest::Bytes(0, 100));
+        assert_eq!(
+            parse_range("bytes=900-", 1000),
+            RangeRequest::Bytes(900, 1000)
+        );
+        assert_eq!(
+            parse_range("bytes=-100", 1000),
+            RangeRequest::Bytes(900, 1000)
+        );
+        assert_eq!(
+            parse_range("bytes=500-5000", 1000),
+            RangeRequest::Bytes(500, 1000)
+        );
+        assert_eq!(
+            parse_range("bytes=1000-", 1000),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(parse_range("bytes=-0", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-1,5-9", 1000), RangeRequest::Ignored);
+        assert_eq!(parse_range("items=0-1", 1000), RangeRequest::Ignored);
+        assert_eq!(parse_range("bytes=9-1", 1000), RangeRequest::Ignored);
+    }
+
+    #[test]
+    fn partial_files_follow_their_download() {
+        let mut partials = PartialFiles::default();
+        let readable = |readable_bytes| FileTransferEvent::StreamReadable {
+            transfer_id: "t1".to_string(),
+            file_hash: "hash".to_string(),
+            part_path: "/downloads/movie.mp4.part".to_string(),
+            readable_bytes,
+            total_bytes: 100,
+        };
+        partials.observe(&readable(0));
+        partials.observe(&readable(40));
+        assert_eq!(partials.get("hash").unwrap().readable_bytes, 40);
+
+        partials.observe(&FileTransferEvent::FileDownloaded {
+            transfer_id: "t1".to_string(),
+            file_path: "/downloads/movie.mp4".to_string(),
+        });
+        let done = partials.get("hash").unwrap();
+        assert_eq!(done.path, PathBuf::from("/downloads/movie.mp4"));
+        assert_eq!(done.readable_bytes, 100);
+
+        partials.observe(&FileTransferEvent::Cancelled {
+            transfer_id: "t1".to_string(),
+        });
+        assert!(partials.get("hash").is_none());
+    }
+}
@@ -0,0 +1,233 @@
+// Content hashing
+//
+// Stored files are addressed by a hash of their contents. Identifiers used
+// to be bare hex SHA-256 digests, and SHA-256 files are still named that way
+// so existing hashes, metadata and keystore entries keep working. BLAKE3
+// identifiers are hex multihashes (code, length, digest) since a bare
+// 32-byte hex digest could not be told apart from SHA-256. Lookups accept
+// either spelling of a SHA-256 hash: `1220<digest>` finds a file stored
+// under `<digest>` and the other way round.
+
+use crate::error_codes::{io_error, ErrorCode};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Multihash table code of SHA2-256
+const SHA2_256_CODE: u8 = 0x12;
+/// Multihash table code of BLAKE3 (256-bit output)
+const BLAKE3_CODE: u8 = 0x1e;
+const DIGEST_LEN: usize = 32;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    fn code(self) -> u8 {
+        match self {
+            Self::Sha256 => SHA2_256_CODE,
+            Self::Blake3 => BLAKE3_CODE,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            SHA2_256_CODE => Some(Self::Sha256),
+            BLAKE3_CODE => Some(Self::Blake3),
+            _ => None,
+        }
+    }
+
+    /// Incremental hasher, for contents read piece by piece
+    pub fn hasher(self) -> ContentHasher {
+        match self {
+            Self::Sha256 => ContentHasher::Sha256(Sha256::new()),
+            Self::Blake3 => ContentHasher::Blake3(Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    /// Identifier of `data` under this algorithm
+    pub fn hash(self, data: &[u8]) -> ContentHash {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finish()
+    }
+
+    /// Identifier of the file at `path`, read in the background
+    pub async fn hash_file(self, path: &Path) -> Result<ContentHash, String> {
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            use std::io::Read;
+            let mut file = std::fs::File::open(&path).map_err(io_error(
+                ErrorCode::ReadFailed,
+                format!("Failed to open {}", path.display()),
+            ))?;
+            let mut hasher = self.hasher();
+            let mut buffer = vec![0u8; 1024 * 1024];
+            loop {
+                let n = file.read(&mut buffer).map_err(io_error(
+                    ErrorCode::ReadFailed,
+                    format!("Failed to read {}", path.display()),
+                ))?;
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&buffer[..n]);
+            }
+            Ok(hasher.finish())
+        })
+        .await
+        .map_err(|e| format!("Hashing task failed: {}", e))?
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Sha256 => "sha256",
+            Self::Blake3 => "blake3",
+        })
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('-', "").as_str() {
+            "sha256" | "sha2256" => Ok(Self::Sha256),
+            "blake3" | "b3" => Ok(Self::Blake3),
+            _ => Err(format!("Unsupported hash algorithm: {}", s)),
+        }
+    }
+}
+
+pub enum ContentHasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl ContentHasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha256(h) => h.update(data),
+            Self::Blake3(h) => {
+                h.update(data);
+            }
+        }
+    }
+
+    pub fn finish(self) -> ContentHash {
+        let (algorithm, digest) = match self {
+            Self::Sha256(h) => (HashAlgorithm::Sha256, h.finalize().into()),
+            Self::Blake3(h) => (HashAlgorithm::Blake3, *h.finalize().as_bytes()),
+        };
+        ContentHash { algorithm, digest }
+    }
+}
+
+/// A parsed content identifier
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ContentHash {
+    pub algorithm: HashAlgorithm,
+    pub digest: [u8; DIGEST_LEN],
+}
+
+impl ContentHash {
+    /// Parse a bare hex SHA-256 digest or a hex multihash of a supported
+    /// algorithm. Anything else, e.g. a CID or a manifest root, is `None`.
+    pub fn parse(id: &str) -> Option<Self> {
+        let bytes = hex::decode(id).ok()?;
+        let (algorithm, digest) = match bytes.len() {
+            DIGEST_LEN => (HashAlgorithm::Sha256, bytes.as_slice()),
+            n if n == DIGEST_LEN + 2 && bytes[1] as usize == DIGEST_LEN => {
+                (HashAlgorithm::from_code(bytes[0])?, &bytes[2..])
+            }
+            _ => return None,
+        };
+        Some(Self {
+            algorithm,
+            digest: digest.try_into().ok()?,
+        })
+    }
+
+    /// Hex multihash, whatever the algorithm
+    pub fn to_multihash(&self) -> String {
+        let mut bytes = Vec::with_capacity(DIGEST_LEN + 2);
+        bytes.extend_from_slice(&[self.algorithm.code(), DIGEST_LEN as u8]);
+        bytes.extend_from_slice(&self.digest);
+        hex::encode(bytes)
+    }
+
+    /// Every identifier a file with this hash may be stored under, the
+    /// canonical one first
+    pub fn lookup_keys(&self) -> Vec<String> {
+        match self.algorithm {
+            HashAlgorithm::Sha256 => vec![hex::encode(self.digest), self.to_multihash()],
+            HashAlgorithm::Blake3 => vec![self.to_multihash()],
+        }
+    }
+
+    /// Whether `id` names the same content, in any accepted spelling
+    pub fn matches(&self, id: &str) -> bool {
+        Self::parse(id).is_some_and(|other| other == *self)
+    }
+}
+
+/// Canonical form: bare hex for SHA-256, hex multihash otherwise
+impl fmt::Display for ContentHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.algorithm {
+            HashAlgorithm::Sha256 => f.write_str(&hex::encode(self.digest)),
+            HashAlgorithm::Blake3 => f.write_str(&self.to_multihash()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha256_keeps_its_legacy_identifier() {
+        let id = HashAlgorithm::Sha256.hash(b"abc").to_string();
+        assert_eq!(
+            id,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let parsed = ContentHash::parse(&id).unwrap();
+        let multihash = parsed.to_multihash();
+        assert_eq!(&multihash[..4], "1220");
+        assert_eq!(ContentHash::parse(&multihash), Some(parsed));
+        assert_eq!(parsed.lookup_keys(), vec![id.clone(), multihash.clone()]);
+        assert!(parsed.matches(&multihash.to_uppercase()));
+    }
+
+    #[test]
+    fn blake3_identifiers_are_multihashes() {
+        let hash = HashAlgorithm::Blake3.hash(b"abc");
+        let id = hash.to_string();
+        assert_eq!(
+            id,
+            "1e206437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+        assert_eq!(ContentHash::parse(&id), Some(hash));
+        assert_eq!(hash.lookup_keys(), vec![id]);
+        assert!(!hash.matches(&HashAlgorithm::Sha256.hash(b"abc").to_string()));
+
+        assert_eq!(ContentHash::parse("not-a-hash"), None);
+        assert_eq!(
+            ContentHash::parse(&format!("1320{}", "00".repeat(32))),
+            None
+        );
+        assert_eq!("BLAKE3".parse(), Ok(HashAlgorithm::Blake3));
+    }
+}
@@ -0,0 +1,222 @@
+// Download file naming
+//
+// A download sent to a directory instead of an explicit output path is named
+// from a template. `{name}` is the file's name without its extension,
+// `{ext}` the extension, `{hash}` the full file hash and `{shorthash}` its
+// first eight hex digits. The extension is added back after the template
+// unless the template places `{ext}` itself, so `{name}-{shorthash}` turns
+// `report.pdf` into `report-1a2b3c4d.pdf`.
+//
+// When a file by that name already exists the collision policy decides:
+// pick the next free `name (n).ext`, overwrite it, or skip the download.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_TEMPLATE: &str = "{name}";
+const SHORT_HASH_LEN: usize = 8;
+const PLACEHOLDERS: [&str; 4] = ["name", "ext", "hash", "shorthash"];
+/// Numbered names tried before a rename gives up
+const MAX_RENAMES: u32 = 10_000;
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CollisionPolicy {
+    /// Use the first free `name (n).ext`
+    #[default]
+    Rename,
+    Overwrite,
+    /// Leave the existing file and don't download
+    Skip,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NamingRules {
+    pub template: String,
+    pub collision: CollisionPolicy,
+}
+
+impl Default for NamingRules {
+    fn default() -> Self {
+        Self {
+            template: DEFAULT_TEMPLATE.to_string(),
+            collision: CollisionPolicy::default(),
+        }
+    }
+}
+
+/// Where a named download goes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputTarget {
+    Write(PathBuf),
+    /// A file is already there and the policy is to skip
+    Skip(PathBuf),
+}
+
+/// Check that `template` only uses known placeholders and names a file
+pub fn validate_template(template: &str) -> Result<(), String> {
+    render(template, "file.bin", "0".repeat(64).as_str()).map(|_| ())
+}
+
+/// The file name `template` gives a file called `file_name` with hash
+/// `file_hash`
+pub fn render(template: &str, file_name: &str, file_hash: &str) -> Result<String, String> {
+    let (stem, ext) = split_name(file_name);
+    let hex = file_hash.rsplit(':').next().unwrap_or(file_hash);
+    let short: String = hex.chars().take(SHORT_HASH_LEN).collect();
+
+    let mut rendered = String::new();
+    let mut places_ext = false;
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        rendered.push_str(&rest[..open]);
+        let close = rest[open..]
+            .find('}')
+            .map(|close| open + close)
+            .ok_or_else(|| format!("Unclosed placeholder in name template '{}'", template))?;
+        rendered.push_str(match &rest[open + 1..close] {
+            "name" => stem,
+            "ext" => {
+                places_ext = true;
+                ext
+            }
+            "hash" => hex,
+            "shorthash" => &short,
+            other => {
+                return Err(format!(
+                    "Unknown placeholder {{{}}} in name template; use one of {}",
+                    other,
+                    PLACEHOLDERS.map(|p| format!("{{{}}}", p)).join(", ")
+                ))
+            }
+        });
+        rest = &rest[close + 1..];
+    }
+    rendered.push_str(rest);
+    if !places_ext && !ext.is_empty() {
+        rendered.push('.');
+        rendered.push_str(ext);
+    }
+
+    let rendered = rendered.trim();
+    if rendered.is_empty() || rendered == "." || rendered == ".." {
+        return Err(format!(
+            "Name template '{}' gives an empty file name",
+            template
+        ));
+    }
+    if rendered.contains(['/', '\\']) {
+        return Err(format!(
+            "Name template '{}' must give a file name, not a path",
+            template
+        ));
+    }
+    Ok(rendered.to_string())
+}
+
+/// Pick the output path for `file_name` in `dir` under `rules`
+pub fn resolve(
+    dir: &Path,
+    file_name: &str,
+    file_hash: &str,
+    rules: &NamingRules,
+) -> Result<OutputTarget, String> {
+    // Only the last component of a remote file name is used
+    let file_name = Path::new(file_name)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(file_hash);
+    let name = render(&rules.template, file_name, file_hash)?;
+    let path = dir.join(&name);
+    if !path.exists() {
+        return Ok(OutputTarget::Write(path));
+    }
+    match rules.collision {
+        CollisionPolicy::Overwrite => Ok(OutputTarget::Write(path)),
+        CollisionPolicy::Skip => Ok(OutputTarget::Skip(path)),
+        CollisionPolicy::Rename => {
+            let (stem, ext) = split_name(&name);
+            (1..=MAX_RENAMES)
+                .map(|n| match ext {
+                    "" => dir.join(format!("{} ({})", stem, n)),
+                    ext => dir.join(format!("{} ({}).{}", stem, n, ext)),
+                })
+                .find(|candidate| !candidate.exists())
+                .map(OutputTarget::Write)
+                .ok_or_else(|| format!("No free file name left for {} in {}", name, dir.display()))
+        }
+    }
+}
+
+/// `report.tar.gz` is `report.tar` and `gz`; a leading dot is part of the
+/// name, not an extension
+fn split_name(file_name: &str) -> (&str, &str) {
+    match file_name.rfind('.') {
+        Some(dot) if dot > 0 => (&file_name[..dot], &file_name[dot + 1..]),
+        _ => (file_name, ""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const HASH: &str = "1a2b3c4d5e6f00112233445566778899aabbccddeeff00112233445566778899";
+
+    #[test]
+    fn templates_fill_in_placeholders_and_keep_the_extension() {
+        assert_eq!(render("{name}", "report.pdf", HASH).unwrap(), "report.pdf");
+        assert_eq!(
+            render("{name}-{shorthash}", "report.pdf", HASH).unwrap(),
+            "report-1a2b3c4d.pdf"
+        );
+        let prefixed = format!("blake3:{}", HASH);
+        assert_eq!(
+            render("{shorthash}_{name}.{ext}", "a.tar.gz", &prefixed).unwrap(),
+            "1a2b3c4d_a.tar.gz"
+        );
+        assert_eq!(render("{hash}", "README", HASH).unwrap(), HASH);
+        assert_eq!(render("{name}", ".env", HASH).unwrap(), ".env");
+
+        let unknown = render("{title}", "a.txt", HASH).unwrap_err();
+        assert!(unknown.contains("{shorthash}"), "{unknown}");
+        assert!(render("{name", "a.txt", HASH).is_err());
+        assert!(render("x/{name}", "a.txt", HASH).is_err());
+        assert!(validate_template("{ext}").is_ok());
+        assert!(validate_template("  ").is_err());
+    }
+
+    #[test]
+    fn collisions_are_renamed_overwritten_or_skipped() {
+        let dir = tempdir().unwrap();
+        let target = |file_name: &str, collision| {
+            let rules = NamingRules {
+                template: DEFAULT_TEMPLATE.to_string(),
+                collision,
+            };
+            resolve(dir.path(), file_name, HASH, &rules).unwrap()
+        };
+        let taken = dir.path().join("notes.txt");
+        assert_eq!(
+            target("../notes.txt", CollisionPolicy::Rename),
+            OutputTarget::Write(taken.clone())
+        );
+
+        std::fs::write(&taken, b"old").unwrap();
+        std::fs::write(dir.path().join("notes (1).txt"), b"old").unwrap();
+        assert_eq!(
+            target("notes.txt", CollisionPolicy::Rename),
+            OutputTarget::Write(dir.path().join("notes (2).txt"))
+        );
+        assert_eq!(
+            target("notes.txt", CollisionPolicy::Overwrite),
+            OutputTarget::Write(taken.clone())
+        );
+        assert_eq!(
+            target("notes.txt", CollisionPolicy::Skip),
+            OutputTarget::Skip(taken)
+        );
+    }
+}
@@ -0,0 +1,268 @@
+// Transfer progress reporting
+//
+// Uploads and downloads report bytes as they move through a
+// `ProgressReporter`, which turns them into `FileTransferEvent::Progress`
+// events at most every `PROGRESS_INTERVAL`, plus a final one when the
+// transfer ends. Speed is averaged over the last `SPEED_WINDOW` so a stall
+// shows up quickly, and the ETA is derived from it. Events are sent with
+// `try_send`: progress is advisory and must never hold up a transfer when
+// the event channel is full. Streaming downloads also report how much of
+// the part file can already be read, unthrottled, since a player waits on it.
+
+use super::resume::PartFile;
+use super::FileTransferEvent;
+use crate::runtime_env::Clock;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Minimum time between two progress events of one transfer
+pub const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+/// Span of recent samples the current speed is averaged over
+pub const SPEED_WINDOW: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferDirection {
+    Upload,
+    Download,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferProgress {
+    pub transfer_id: String,
+    pub direction: TransferDirection,
+    pub bytes_done: u64,
+    /// Zero while the size is not known yet
+    pub total_bytes: u64,
+    /// Bytes per second over the last `SPEED_WINDOW`
+    pub speed_bps: f64,
+    pub eta_secs: Option<u64>,
+    /// Unix milliseconds
+    pub timestamp: u64,
+}
+
+impl TransferProgress {
+    pub fn fraction(&self) -> Option<f64> {
+        (self.total_bytes > 0).then(|| self.bytes_done as f64 / self.total_bytes as f64)
+    }
+}
+
+/// Byte counter with a sliding speed window
+#[derive(Debug, Default)]
+struct ProgressState {
+    total: u64,
+    done: u64,
+    /// (monotonic time, bytes done) samples inside the speed window
+    samples: VecDeque<(Duration, u64)>,
+    last_emit: Option<Duration>,
+}
+
+impl ProgressState {
+    fn sample(&mut self, now: Duration) {
+        self.samples.push_back((now, self.done));
+        while self.samples.len() > 2
+            && self
+                .samples
+                .front()
+                .is_some_and(|(at, _)| now.saturating_sub(*at) > SPEED_WINDOW)
+        {
+            self.samples.pop_front();
+        }
+    }
+
+    fn speed(&self) -> f64 {
+        match (self.samples.front(), self.samples.back()) {
+            (Some((t0, b0)), Some((t1, b1))) if t1 > t0 => {
+                (b1 - b0) as f64 / (*t1 - *t0).as_secs_f64()
+            }
+            _ => 0.0,
+        }
+    }
+
+    fn eta(&self, speed: f64) -> Option<u64> {
+        if self.total == 0 || speed <= 0.0 {
+            return None;
+        }
+        let remaining = self.total.saturating_sub(self.done);
+        Some((remaining as f64 / speed).ceil() as u64)
+    }
+}
+
+pub struct ProgressReporter {
+    transfer_id: String,
+    direction: TransferDirection,
+    tx: Option<mpsc::Sender<FileTransferEvent>>,
+    clock: Arc<dyn Clock>,
+    state: Mutex<ProgressState>,
+}
+
+impl ProgressReporter {
+    pub fn new(
+        transfer_id: &str,
+        direction: TransferDirection,
+        tx: mpsc::Sender<FileTransferEvent>,
+        clock: Arc<dyn Clock>,
+    ) -> Self {
+        Self {
+            transfer_id: transfer_id.to_string(),
+            direction,
+            tx: Some(tx),
+            clock,
+            state: Mutex::new(ProgressState::default()),
+        }
+    }
+
+    /// A reporter that only counts, for callers with no event channel
+    pub fn silent(transfer_id: &str, direction: TransferDirection, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            transfer_id: transfer_id.to_string(),
+            direction,
+            tx: None,
+            clock,
+            state: Mutex::new(ProgressState::default()),
+        }
+    }
+
+    /// (Re)start counting towards `total` with `already_done` bytes in hand,
+    /// e.g. chunks kept from an interrupted attempt
+    pub fn start(&self, total: u64, already_done: u64) {
+        let now = self.clock.monotonic();
+        let mut state = self.state.lock().unwrap();
+        state.total = total;
+        state.done = already_done;
+        state.samples.clear();
+        state.sample(now);
+        state.last_emit = Some(now);
+        self.emit(&state);
+    }
+
+    pub fn add(&self, bytes: u64) {
+        let now = self.clock.monotonic();
+        let mut state = self.state.lock().unwrap();
+        state.done += bytes;
+        state.sample(now);
+        let due = state
+            .last_emit
+            .is_none_or(|at| now.saturating_sub(at) >= PROGRESS_INTERVAL);
+        if due {
+            state.last_emit = Some(now);
+            self.emit(&state);
+        }
+    }
+
+    /// Report how much of a streaming download's part file is written and
+    /// verified from the start
+    pub fn readable(&self, part: &PartFile) {
+        if let Some(tx) = &self.tx {
+            let _ = tx.try_send(FileTransferEvent::StreamReadable {
+                transfer_id: self.transfer_id.clone(),
+                file_hash: part.file_hash().to_string(),
+                part_path: part.path().to_string_lossy().into_owned(),
+                readable_bytes: part.readable_bytes(),
+                total_bytes: part.file_size(),
+            });
+        }
+    }
+
+    /// Report the final position regardless of the interval
+    pub fn finish(&self) {
+        let now = self.clock.monotonic();
+        let mut state = self.state.lock().unwrap();
+        state.sample(now);
+        state.last_emit = Some(now);
+        self.emit(&state);
+    }
+
+    pub fn snapshot(&self) -> TransferProgress {
+        let state = self.state.lock().unwrap();
+        self.snapshot_of(&state)
+    }
+
+    fn snapshot_of(&self, state: &ProgressState) -> TransferProgress {
+        let speed = state.speed();
+        TransferProgress {
+            transfer_id: self.transfer_id.clone(),
+            direction: self.direction,
+            bytes_done: state.done,
+            total_bytes: state.total,
+            speed_bps: speed,
+            eta_secs: state.eta(speed),
+            timestamp: self.clock.now_ms(),
+        }
+    }
+
+    fn emit(&self, state: &ProgressState) {
+        if let Some(tx) = &self.tx {
+            let _ = tx.try_send(FileTransferEvent::Progress(self.snapshot_of(state)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime_env::ManualClock;
+
+    fn progress_events(rx: &mut mpsc::Receiver<FileTransferEvent>) -> Vec<TransferProgress> {
+        let mut out = Vec::new();
+        while let Ok(event) = rx.try_recv() {
+            if let FileTransferEvent::Progress(p) = event {
+                out.push(p);
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn reports_are_throttled_and_carry_speed_and_eta() {
+        let clock = ManualClock::default();
+        let (tx, mut rx) = mpsc::channel(64);
+        let reporter = ProgressReporter::new(
+            "t1",
+            TransferDirection::Download,
+            tx,
+            Arc::new(clock.clone()),
+        );
+
+        reporter.start(1_000, 0);
+        // Ten 50-byte steps 100ms apart: one report per 500ms
+        for _ in 0..10 {
+            clock.advance(Duration::from_millis(100));
+            reporter.add(50);
+        }
+        reporter.finish();
+
+        let events = progress_events(&mut rx);
+        let done: Vec<u64> = events.iter().map(|p| p.bytes_done).collect();
+        assert_eq!(done, vec![0, 250, 500, 500]);
+
+        let last = events.last().unwrap();
+        assert_eq!(last.transfer_id, "t1");
+        assert_eq!(last.total_bytes, 1_000);
+        assert!((last.speed_bps - 500.0).abs() < 1.0, "{}", last.speed_bps);
+        assert_eq!(last.eta_secs, Some(1));
+        assert_eq!(last.fraction(), Some(0.5));
+    }
+
+    #[test]
+    fn speed_only_covers_the_recent_window() {
+        let clock = ManualClock::default();
+        let reporter =
+            ProgressReporter::silent("t2", TransferDirection::Upload, Arc::new(clock.clone()));
+        reporter.start(0, 0);
+        reporter.add(10_000);
+        // A long stall pushes the burst out of the window
+        for _ in 0..3 {
+            clock.advance(SPEED_WINDOW);
+            reporter.add(0);
+        }
+        let snapshot = reporter.snapshot();
+        assert_eq!(snapshot.speed_bps, 0.0);
+        assert_eq!(snapshot.eta_secs, None);
+        assert_eq!(snapshot.fraction(), None);
+    }
+}
@@ -0,0 +1,404 @@
+// Node-to-node file transfer messages
+//
+// Requests and responses exchanged over `/chiral/file-transfer/1.0.0`. A
+// small file can be asked for whole with a `FileRequest`; anything larger is
+// fetched as its chunk manifest followed by individually verified chunks,
+// which is what `swarm` schedules across providers. The libp2p codec sits
+// with the other request-response codecs in `dht`; this module only defines
+// the messages and how a node answers them from its blob store.
+
+use super::chunking::{self, ChunkManifest, ManifestBuilder};
+use super::{FileRequest, FileResponse, FileTransferService};
+use crate::error_codes::{ErrorCode, ServiceError};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tracing::debug;
+
+pub const PROTOCOL_NAME: &str = "/chiral/file-transfer/1.0.0";
+
+/// Largest file served in a single `FileResponse`. Frames are capped at
+/// 10 MiB and base64 adds a third, so bigger files go chunk by chunk.
+pub const MAX_WHOLE_FILE_BYTES: u64 = 6 * 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TransferRequest {
+    File(FileRequest),
+    Manifest { file_hash: String },
+    Chunk { file_hash: String, index: u32 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TransferResponse {
+    File(FileResponse),
+    Manifest(ChunkManifest),
+    Chunk {
+        file_hash: String,
+        index: u32,
+        #[serde(with = "base64_bytes")]
+        data: Vec<u8>,
+    },
+    Error {
+        code: ErrorCode,
+        message: String,
+    },
+}
+
+impl TransferRequest {
+    pub fn file_hash(&self) -> &str {
+        match self {
+            TransferRequest::File(request) => &request.file_hash,
+            TransferRequest::Manifest { file_hash } | TransferRequest::Chunk { file_hash, .. } => {
+                file_hash
+            }
+        }
+    }
+
+    /// Rough wire size, for connection stats
+    pub fn approx_len(&self) -> usize {
+        self.file_hash().len() + 4
+    }
+}
+
+impl TransferResponse {
+    fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        TransferResponse::Error {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Rough wire size, for connection stats
+    pub fn approx_len(&self) -> usize {
+        match self {
+            TransferResponse::File(file) => file.file_data.len() + file.file_name.len(),
+            TransferResponse::Manifest(manifest) => manifest.chunk_hashes.len() * 64,
+            TransferResponse::Chunk { data, .. } => data.len(),
+            TransferResponse::Error { message, .. } => message.len(),
+        }
+    }
+
+    /// Turn an `Error` response into a `ServiceError` string
+    pub fn into_result(self) -> Result<Self, String> {
+        match self {
+            TransferResponse::Error { code, message } => {
+                Err(crate::error_codes::ServiceError::new(code, message).into())
+            }
+            other => Ok(other),
+        }
+    }
+}
+
+/// Byte payloads travel as base64 strings inside the JSON frames
+pub mod base64_bytes {
+    use base64::{engine::general_purpose, Engine as _};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&general_purpose::STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Stored-file metadata a peer needs before serving it
+async fn read_meta(storage_dir: &Path, file_hash: &str) -> serde_json::Value {
+    let path = storage_dir.join(format!("{}.meta", file_hash));
+    match tokio::fs::read_to_string(&path).await {
+        Ok(raw) => serde_json::from_str(&raw).unwrap_or_default(),
+        Err(_) => serde_json::Value::Null,
+    }
+}
+
+impl FileTransferService {
+    /// Answer a transfer request from the node `peer_id` out of the local
+    /// store. Files are asked for by the id they were announced under, a
+    /// merkle root or a content hash. Encrypted files are not served: their
+    /// keys are only released through the key-request protocol, so a remote
+    /// node could not use the bytes. File bytes sent count towards the
+    /// seeding statistics.
+    pub async fn serve_request(&self, peer_id: &str, request: TransferRequest) -> TransferResponse {
+        let Some(file_hash) = Self::resolve_stored_hash(&self.blobs, request.file_hash()).await
+        else {
+            return TransferResponse::error(ErrorCode::NotFound, "File not found in storage");
+        };
+        let meta = read_meta(&self.storage_dir, &file_hash).await;
+        if meta
+            .get("is_encrypted")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false)
+        {
+            return TransferResponse::error(
+                ErrorCode::Unauthorized,
+                "Encrypted files are not served over the transfer protocol",
+            );
+        }
+
+        let result = match request {
+            TransferRequest::File(_) => self.serve_whole_file(&file_hash, &meta).await,
+            TransferRequest::Manifest { .. } => self
+                .manifest_for(&file_hash)
+                .await
+                .map(TransferResponse::Manifest),
+            TransferRequest::Chunk { index, .. } => self.serve_chunk(&file_hash, index).await,
+        };
+        match &result {
+            Ok(TransferResponse::File(file)) => {
+                self.record_served(&file_hash, peer_id, file.file_data.len() as u64)
+            }
+            Ok(TransferResponse::Chunk { data, .. }) => {
+                self.record_served(&file_hash, peer_id, data.len() as u64)
+            }
+            _ => {}
+        }
+        result.unwrap_or_else(|message| {
+            debug!(hash = %file_hash, "transfer request failed: {}", message);
+            TransferResponse::error(ErrorCode::classify(&message), message)
+        })
+    }
+
+    async fn serve_whole_file(
+        &self,
+        file_hash: &str,
+        meta: &serde_json::Value,
+    ) -> Result<TransferResponse, String> {
+        let size = self
+            .blobs
+            .entry(file_hash)
+            .await
+            .map(|e| e.size)
+            .unwrap_or_default();
+        if size > MAX_WHOLE_FILE_BYTES {
+            return Ok(TransferResponse::error(
+                ErrorCode::InvalidInput,
+                format!(
+                    "File is {} bytes; files over {} bytes must be fetched by chunk",
+                    size, MAX_WHOLE_FILE_BYTES
+                ),
+            ));
+        }
+        let file_data =
+            self.blobs.get(file_hash).await?.ok_or_else(|| {
+                ServiceError::new(ErrorCode::NotFound, "File not found in storage")
+            })?;
+        Ok(TransferResponse::File(FileResponse {
+            file_size: file_data.len() as u64,
+            file_name: meta
+                .get("file_name")
+                .and_then(|v| v.as_str())
+                .unwrap_or(file_hash)
+                .to_string(),
+            mime_type: meta
+                .get("mime_type")
+                .and_then(|v| v.as_str())
+                .map(str::to_string),
+            created_at: meta.get("created_at").and_then(|v| v.as_u64()),
+            file_data,
+        }))
+    }
+
+    async fn serve_chunk(&self, file_hash: &str, index: u32) -> Result<TransferResponse, String> {
+        let manifest = self.manifest_for(file_hash).await?;
+        let range = manifest.chunk_range(index).map_err(|e| e.to_string())?;
+        let data = self
+            .blobs
+            .read_range(file_hash, range.start as u64, range.len())
+            .await?
+            .ok_or_else(|| ServiceError::new(ErrorCode::NotFound, "File not found in storage"))?;
+        Ok(TransferResponse::Chunk {
+            file_hash: file_hash.to_string(),
+            index,
+            data,
+        })
+    }
+
+    /// The stored manifest, built and saved on first use for blobs stored
+    /// before chunking. The blob is read a chunk at a time, so building it
+    /// never holds the whole file in memory.
+    async fn manifest_for(&self, file_hash: &str) -> Result<ChunkManifest, String> {
+        let storage_dir = self.blobs.root();
+        if let Some(manifest) = chunking::load_manifest(storage_dir, file_hash).await? {
+            return Ok(manifest);
+        }
+        let not_found = || ServiceError::new(ErrorCode::NotFound, "File not found in storage");
+        let size = self
+            .blobs
+            .entry(file_hash)
+            .await
+            .ok_or_else(not_found)?
+            .size;
+        let mut builder = ManifestBuilder::new(chunking::CHUNK_SIZE);
+        let mut offset = 0;
+        while offset < size {
+            let len = (size - offset).min(chunking::CHUNK_SIZE as u64) as usize;
+            let data = self
+                .blobs
+                .read_range(file_hash, offset, len)
+                .await?
+                .ok_or_else(not_found)?;
+            builder.update(&data);
+            offset += len as u64;
+        }
+        let manifest = builder.finish(file_hash);
+        chunking::save_manifest(storage_dir, &manifest).await?;
+        Ok(manifest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime_env::RuntimeEnv;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+    use tokio::sync::Mutex;
+
+    async fn service(dir: &Path) -> FileTransferService {
+        let keystore = Arc::new(Mutex::new(crate::keystore::Keystore::new()));
+        FileTransferService::new_with_env(
+            dir.to_path_buf(),
+            false,
+            keystore,
+            None,
+            RuntimeEnv::deterministic(1),
+        )
+        .await
+        .expect("start service")
+    }
+
+    #[tokio::test]
+    async fn serves_whole_files_manifests_and_chunks() {
+        let dir = tempdir().unwrap();
+        let service = service(dir.path()).await;
+        let data: Vec<u8> = (0..chunking::CHUNK_SIZE as u32 + 100)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let hash = FileTransferService::calculate_file_hash(&data);
+        service
+            .store_file_data(hash.clone(), "movie.bin".into(), data.clone())
+            .await;
+
+        let TransferResponse::File(file) = service
+            .serve_request(
+                "peer",
+                TransferRequest::File(FileRequest {
+                    file_hash: hash.clone(),
+                }),
+            )
+            .await
+        else {
+            panic!("expected the whole file");
+        };
+        assert_eq!(file.file_name, "movie.bin");
+        assert_eq!(file.file_data, data);
+
+        let TransferResponse::Manifest(manifest) = service
+            .serve_request(
+                "peer",
+                TransferRequest::Manifest {
+                    file_hash: hash.clone(),
+                },
+            )
+            .await
+        else {
+            panic!("expected a manifest");
+        };
+        assert_eq!(manifest.chunk_count(), 2);
+
+        let response = service
+            .serve_request(
+                "peer",
+                TransferRequest::Chunk {
+                    file_hash: hash.clone(),
+                    index: 1,
+                },
+            )
+            .await;
+        let TransferResponse::Chunk {
+            index, data: chunk, ..
+        } = response.clone()
+        else {
+            panic!("expected a chunk");
+        };
+        assert_eq!(index, 1);
+        manifest.verify_chunk(1, &chunk).unwrap();
+
+        // Files published by merkle root are served under it too, with a
+        // manifest committing to that root
+        service
+            .record_published_id(&manifest.merkle_root, &hash)
+            .await
+            .unwrap();
+        let TransferResponse::Manifest(by_root) = service
+            .serve_request(
+                "peer",
+                TransferRequest::Manifest {
+                    file_hash: manifest.merkle_root.clone(),
+                },
+            )
+            .await
+        else {
+            panic!("expected a manifest by merkle root");
+        };
+        assert_eq!(by_root, manifest);
+
+        // The whole file and the chunk were sent; the manifest is not counted
+        let seeding = service.seeding_stats_snapshot();
+        assert_eq!(seeding.len(), 1);
+        assert_eq!(seeding[0].file_hash, hash);
+        assert_eq!(seeding[0].bytes_served, (data.len() + chunk.len()) as u64);
+        assert_eq!(seeding[0].pieces_served, 2);
+        assert_eq!(seeding[0].unique_peers, 1);
+
+        // Chunks survive the JSON framing unchanged
+        let wire = serde_json::to_vec(&response).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<TransferResponse>(&wire).unwrap(),
+            response
+        );
+    }
+
+    #[tokio::test]
+    async fn unknown_files_and_chunks_are_errors() {
+        let dir = tempdir().unwrap();
+        let service = service(dir.path()).await;
+        let response = service
+            .serve_request(
+                "peer",
+                TransferRequest::Manifest {
+                    file_hash: "nope".into(),
+                },
+            )
+            .await;
+        assert!(matches!(
+            response,
+            TransferResponse::Error {
+                code: ErrorCode::NotFound,
+                ..
+            }
+        ));
+
+        let hash = FileTransferService::calculate_file_hash(b"tiny");
+        service
+            .store_file_data(hash.clone(), "tiny".into(), b"tiny".to_vec())
+            .await;
+        let err = service
+            .serve_request(
+                "peer",
+                TransferRequest::Chunk {
+                    file_hash: hash,
+                    index: 5,
+                },
+            )
+            .await
+            .into_result()
+            .unwrap_err();
+        assert!(err.contains("out of range"), "{err}");
+    }
+}
@@ -0,0 +1,41 @@
+// Chunk index ranges
+//
+// Sets of received chunk indices are stored and sent as half-open
+// `[start, end)` ranges, which keeps mostly complete transfers down to a
+// handful of pairs. Used by download resume records, push uploads and chunk
+// availability announcements.
+
+use std::collections::BTreeSet;
+
+/// Collapse `indices` into sorted, non-overlapping ranges
+pub fn to_ranges(indices: &BTreeSet<u32>) -> Vec<[u32; 2]> {
+    let mut ranges: Vec<[u32; 2]> = Vec::new();
+    for &index in indices {
+        match ranges.last_mut() {
+            Some(last) if last[1] == index => last[1] = index + 1,
+            _ => ranges.push([index, index + 1]),
+        }
+    }
+    ranges
+}
+
+/// Every index covered by `ranges`
+pub fn from_ranges(ranges: &[[u32; 2]]) -> BTreeSet<u32> {
+    ranges
+        .iter()
+        .flat_map(|[start, end]| *start..*end)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_round_trip() {
+        let indices: BTreeSet<u32> = [1, 2, 3, 7].into_iter().collect();
+        assert_eq!(to_ranges(&indices), vec![[1, 4], [7, 8]]);
+        assert_eq!(from_ranges(&to_ranges(&indices)), indices);
+        assert!(to_ranges(&BTreeSet::new()).is_empty());
+    }
+}
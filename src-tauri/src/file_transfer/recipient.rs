@@ -0,0 +1,171 @@
+// Files encrypted for one recipient
+//
+// An upload can be addressed to a single account. The file is encrypted
+// with a fresh AES-256-GCM key and that key is wrapped for the recipient's
+// X25519 public key with `encryption::encrypt_aes_key`. Everything needed to
+// open the file travels inside the stored object: an envelope of a short
+// header followed by the ciphertext. It is hashed, announced, relayed and
+// seeded like any other file, so peers and relays only ever see ciphertext.
+// A download that turns out to be an envelope is opened with the local
+// account key after its integrity has been checked.
+
+use crate::encryption::{self, EncryptedAesKeyBundle, FileEncryption};
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+const MAGIC: &[u8; 8] = b"CHRLE2E1";
+/// Headers are a few hundred bytes; anything larger is not an envelope
+const MAX_HEADER_LEN: usize = 4096;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EnvelopeHeader {
+    /// X25519 public key the file is addressed to, hex
+    recipient_public_key: String,
+    key_bundle: EncryptedAesKeyBundle,
+    /// Nonce of the file ciphertext, hex
+    nonce: String,
+}
+
+fn decode_key(hex_key: &str, what: &str) -> Result<[u8; 32], String> {
+    let bytes = hex::decode(hex_key.trim_start_matches("0x"))
+        .map_err(|e| format!("Invalid {}: {}", what, e))?;
+    bytes
+        .try_into()
+        .map_err(|_| format!("Invalid {}: expected 32 bytes", what))
+}
+
+/// Parse a recipient's hex X25519 public key
+pub fn parse_public_key(hex_key: &str) -> Result<PublicKey, String> {
+    decode_key(hex_key, "recipient public key").map(PublicKey::from)
+}
+
+/// X25519 public key that files for the account `private_key_hex` are
+/// addressed to
+pub fn public_key_for(private_key_hex: &str) -> Result<PublicKey, String> {
+    Ok(PublicKey::from(&secret_for(private_key_hex)?))
+}
+
+fn secret_for(private_key_hex: &str) -> Result<StaticSecret, String> {
+    decode_key(private_key_hex, "private key").map(StaticSecret::from)
+}
+
+/// Whether `data` starts like an envelope
+pub fn is_envelope(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Encrypt `data` so only the holder of `recipient`'s private key can read it
+pub fn seal(data: &[u8], recipient: &PublicKey) -> Result<Vec<u8>, String> {
+    let file_key = FileEncryption::generate_random_key();
+    let mut nonce = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let header = EnvelopeHeader {
+        recipient_public_key: hex::encode(recipient.as_bytes()),
+        key_bundle: encryption::encrypt_aes_key(&file_key, recipient)?,
+        nonce: hex::encode(nonce),
+    };
+    let header = serde_json::to_vec(&header)
+        .map_err(|e| format!("Failed to serialize envelope header: {}", e))?;
+
+    let mut envelope = Vec::with_capacity(MAGIC.len() + 4 + header.len() + data.len() + 16);
+    envelope.extend_from_slice(MAGIC);
+    envelope.extend_from_slice(&(header.len() as u32).to_be_bytes());
+    envelope.extend_from_slice(&header);
+    // The header is authenticated along with the contents
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&file_key));
+    let ciphertext = cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: data,
+                aad: &envelope,
+            },
+        )
+        .map_err(|e| format!("Failed to encrypt file for recipient: {}", e))?;
+    envelope.extend_from_slice(&ciphertext);
+    Ok(envelope)
+}
+
+/// Decrypt an envelope with the account key `private_key_hex`
+pub fn open(envelope: &[u8], private_key_hex: &str) -> Result<Vec<u8>, String> {
+    let body = envelope
+        .strip_prefix(MAGIC)
+        .ok_or_else(|| "Not an encrypted file envelope".to_string())?;
+    let header_len = body
+        .get(..4)
+        .map(|len| u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize)
+        .filter(|len| *len <= MAX_HEADER_LEN && body.len() >= 4 + len)
+        .ok_or_else(|| "Encrypted file envelope is truncated".to_string())?;
+    let header_end = MAGIC.len() + 4 + header_len;
+    let header: EnvelopeHeader = serde_json::from_slice(&envelope[MAGIC.len() + 4..header_end])
+        .map_err(|e| format!("Invalid envelope header: {}", e))?;
+
+    let secret = secret_for(private_key_hex)?;
+    if header.recipient_public_key != hex::encode(PublicKey::from(&secret).as_bytes()) {
+        return Err("File is encrypted for another account".to_string());
+    }
+    let file_key = encryption::decrypt_aes_key(&header.key_bundle, &secret)?;
+    let nonce = decode_nonce(&header.nonce)?;
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&file_key));
+    cipher
+        .decrypt(
+            Nonce::from_slice(&nonce),
+            Payload {
+                msg: &envelope[header_end..],
+                aad: &envelope[..header_end],
+            },
+        )
+        .map_err(|_| "Encrypted file failed authentication".to_string())
+}
+
+fn decode_nonce(hex_nonce: &str) -> Result<[u8; 12], String> {
+    hex::decode(hex_nonce)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| "Invalid envelope nonce".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECIPIENT: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+    const OTHER: &str = "0x8da4ef21b864d2cc526dbdb2a120bd2874c36c9d0a1fb7f8c63d7f7a8b41de8f";
+
+    #[test]
+    fn only_the_recipient_can_open_an_envelope() {
+        let recipient = public_key_for(RECIPIENT).unwrap();
+        let same = parse_public_key(&hex::encode(recipient.as_bytes())).unwrap();
+        assert_eq!(same, recipient);
+
+        let envelope = seal(b"for your eyes only", &recipient).unwrap();
+        assert!(is_envelope(&envelope));
+        assert!(!envelope.windows(4).any(|w| w == b"eyes"));
+        assert_eq!(open(&envelope, RECIPIENT).unwrap(), b"for your eyes only");
+        assert_eq!(
+            open(&envelope, OTHER).unwrap_err(),
+            "File is encrypted for another account"
+        );
+
+        let mut tampered = envelope.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(open(&tampered, RECIPIENT).is_err());
+    }
+
+    #[test]
+    fn plain_files_are_not_envelopes() {
+        assert!(!is_envelope(b"hello"));
+        assert!(open(b"hello", RECIPIENT).is_err());
+        let mut truncated = MAGIC.to_vec();
+        truncated.extend_from_slice(&100u32.to_be_bytes());
+        assert_eq!(
+            open(&truncated, RECIPIENT).unwrap_err(),
+            "Encrypted file envelope is truncated"
+        );
+        assert!(parse_public_key("abcd").is_err());
+    }
+}
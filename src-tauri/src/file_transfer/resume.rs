@@ -0,0 +1,430 @@
+// Resumable download state
+//
+// A chunked download writes verified chunks straight into `<output>.part`
+// and records which chunks landed in a small JSON file under
+// `<storage>/transfers/`. If the app dies mid-transfer the record survives;
+// the next attempt (or the service on its next start) reopens the part file,
+// re-checks the chunks the record claims against the manifest, and only
+// writes the rest. The record is removed once the part file is renamed into
+// place.
+
+use super::buffers::chunk_buffers;
+use super::chunking::ChunkManifest;
+use super::ranges::{from_ranges, to_ranges};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tracing::warn;
+
+const RECORD_VERSION: u32 = 1;
+/// Directory under the storage dir holding one record per unfinished download
+pub const RESUME_DIR: &str = "transfers";
+/// The record is rewritten after this many newly written chunks
+pub const SAVE_EVERY_CHUNKS: u32 = 16;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ResumeRecord {
+    pub version: u32,
+    pub transfer_id: String,
+    pub file_hash: String,
+    pub output_path: String,
+    pub file_size: u64,
+    pub chunk_size: usize,
+    pub chunk_count: u32,
+    /// Root of the manifest the chunks were checked against
+    pub merkle_root: String,
+    /// Chunks written to the part file, as half-open index ranges
+    pub received: Vec<[u32; 2]>,
+    /// Remote peers the chunks are being fetched from, empty for local
+    /// downloads
+    #[serde(default)]
+    pub providers: Vec<String>,
+    pub updated_at: u64,
+}
+
+impl ResumeRecord {
+    pub fn new(transfer_id: &str, output_path: &str, manifest: &ChunkManifest, now: u64) -> Self {
+        Self {
+            version: RECORD_VERSION,
+            transfer_id: transfer_id.to_string(),
+            file_hash: manifest.file_hash.clone(),
+            output_path: output_path.to_string(),
+            file_size: manifest.file_size,
+            chunk_size: manifest.chunk_size,
+            chunk_count: manifest.chunk_count(),
+            merkle_root: manifest.merkle_root.clone(),
+            received: Vec::new(),
+            providers: Vec::new(),
+            updated_at: now,
+        }
+    }
+
+    /// Whether the record was made for this exact manifest
+    pub fn matches(&self, manifest: &ChunkManifest) -> bool {
+        self.version == RECORD_VERSION
+            && self.file_hash == manifest.file_hash
+            && self.merkle_root == manifest.merkle_root
+            && self.chunk_size == manifest.chunk_size
+            && self.file_size == manifest.file_size
+    }
+
+    pub fn received_set(&self) -> BTreeSet<u32> {
+        from_ranges(&self.received)
+    }
+
+    pub fn set_received(&mut self, received: &BTreeSet<u32>, now: u64) {
+        self.received = to_ranges(received);
+        self.updated_at = now;
+    }
+
+    pub fn received_count(&self) -> u32 {
+        self.received.iter().map(|[start, end]| end - start).sum()
+    }
+
+    /// Bytes of the file the received chunks hold
+    pub fn received_bytes(&self) -> u64 {
+        let chunk_size = self.chunk_size as u64;
+        self.received
+            .iter()
+            .map(|[start, end]| {
+                let end = (*end as u64 * chunk_size).min(self.file_size);
+                end.saturating_sub(*start as u64 * chunk_size)
+            })
+            .sum()
+    }
+}
+
+/// Where a download is assembled before being renamed to `output_path`
+pub fn part_path(output_path: &str) -> PathBuf {
+    PathBuf::from(format!("{}.part", output_path))
+}
+
+/// Records are keyed by the hash and destination, so a retry or a restart of
+/// the same download finds the earlier progress whatever its transfer ID.
+fn record_key(file_hash: &str, output_path: &str) -> String {
+    use sha2::{Digest, Sha256};
+    let mut hasher = Sha256::new();
+    hasher.update(file_hash.as_bytes());
+    hasher.update([0u8]);
+    hasher.update(output_path.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+#[derive(Debug, Clone)]
+pub struct ResumeStore {
+    dir: PathBuf,
+}
+
+impl ResumeStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn in_storage_dir(storage_dir: &Path) -> Self {
+        Self::new(storage_dir.join(RESUME_DIR))
+    }
+
+    fn path(&self, file_hash: &str, output_path: &str) -> PathBuf {
+        self.dir
+            .join(format!("{}.json", record_key(file_hash, output_path)))
+    }
+
+    pub async fn save(&self, record: &ResumeRecord) -> Result<(), String> {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|e| format!("Failed to create resume directory: {}", e))?;
+        let path = self.path(&record.file_hash, &record.output_path);
+        let tmp = path.with_extension("json.tmp");
+        let json = serde_json::to_vec(record)
+            .map_err(|e| format!("Failed to serialize resume record: {}", e))?;
+        tokio::fs::write(&tmp, json)
+            .await
+            .map_err(|e| format!("Failed to write resume record: {}", e))?;
+        tokio::fs::rename(&tmp, &path)
+            .await
+            .map_err(|e| format!("Failed to write resume record: {}", e))
+    }
+
+    pub async fn load(&self, file_hash: &str, output_path: &str) -> Option<ResumeRecord> {
+        let raw = tokio::fs::read(self.path(file_hash, output_path))
+            .await
+            .ok()?;
+        match serde_json::from_slice(&raw) {
+            Ok(record) => Some(record),
+            Err(e) => {
+                warn!("Ignoring unreadable resume record for {}: {}", file_hash, e);
+                None
+            }
+        }
+    }
+
+    pub async fn remove(&self, file_hash: &str, output_path: &str) {
+        let _ = tokio::fs::remove_file(self.path(file_hash, output_path)).await;
+    }
+
+    /// Drop an unfinished download entirely: its record and its part file
+    pub async fn discard(&self, file_hash: &str, output_path: &str) {
+        let _ = tokio::fs::remove_file(part_path(output_path)).await;
+        self.remove(file_hash, output_path).await;
+    }
+
+    /// Every unfinished download, oldest first
+    pub async fn pending(&self) -> Vec<ResumeRecord> {
+        let mut records = Vec::new();
+        let Ok(mut dir) = tokio::fs::read_dir(&self.dir).await else {
+            return records;
+        };
+        while let Ok(Some(item)) = dir.next_entry().await {
+            let path = item.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            match tokio::fs::read(&path)
+                .await
+                .map(|raw| serde_json::from_slice(&raw))
+            {
+                Ok(Ok(record)) => records.push(record),
+                _ => warn!("Skipping unreadable resume record {}", path.display()),
+            }
+        }
+        records.sort_by_key(|r: &ResumeRecord| r.updated_at);
+        records
+    }
+}
+
+fn write_err(e: std::io::Error) -> String {
+    format!("Failed to write file: {}", e)
+}
+
+/// A download being assembled in `<output>.part`, with its resume record
+pub struct PartFile {
+    store: ResumeStore,
+    record: ResumeRecord,
+    manifest: ChunkManifest,
+    file: tokio::fs::File,
+    part: PathBuf,
+    output_path: String,
+    received: BTreeSet<u32>,
+    unsaved: u32,
+}
+
+impl PartFile {
+    /// Open or create the part file. Chunks an earlier attempt recorded are
+    /// re-checked against the manifest, since a crash can leave a chunk
+    /// recorded whose bytes never reached the disk.
+    pub async fn open(
+        store: ResumeStore,
+        transfer_id: &str,
+        output_path: &str,
+        manifest: &ChunkManifest,
+        now: u64,
+    ) -> Result<Self, String> {
+        let part = part_path(output_path);
+        let record = match store.load(&manifest.file_hash, output_path).await {
+            Some(record) if record.matches(manifest) && part.exists() => record,
+            _ => ResumeRecord::new(transfer_id, output_path, manifest, now),
+        };
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&part)
+            .await
+            .map_err(write_err)?;
+        file.set_len(manifest.file_size).await.map_err(write_err)?;
+
+        let mut received = BTreeSet::new();
+        let mut buf = chunk_buffers().take();
+        for index in record.received_set() {
+            let Ok(range) = manifest.chunk_range(index) else {
+                continue;
+            };
+            buf.clear();
+            buf.resize(range.len(), 0);
+            let readable = file.seek(SeekFrom::Start(range.start as u64)).await.is_ok()
+                && file.read_exact(&mut buf[..]).await.is_ok();
+            if readable && manifest.verify_chunk(index, &buf).is_ok() {
+                received.insert(index);
+            }
+        }
+
+        Ok(Self {
+            store,
+            record,
+            manifest: manifest.clone(),
+            file,
+            part,
+            output_path: output_path.to_string(),
+            received,
+            unsaved: 0,
+        })
+    }
+
+    /// Remember where the chunks come from, so a download resumed after a
+    /// restart can go back to the same peers
+    pub fn set_providers(&mut self, providers: &[String]) {
+        self.record.providers = providers.to_vec();
+    }
+
+    pub fn received(&self) -> &BTreeSet<u32> {
+        &self.received
+    }
+
+    /// Chunks still to be written, in order
+    pub fn missing(&self) -> Vec<u32> {
+        (0..self.manifest.chunk_count())
+            .filter(|i| !self.received.contains(i))
+            .collect()
+    }
+
+    /// Bytes covered by the chunks already written
+    pub fn received_bytes(&self) -> u64 {
+        self.received
+            .iter()
+            .filter_map(|index| self.manifest.chunk_range(*index).ok())
+            .map(|range| range.len() as u64)
+            .sum()
+    }
+
+    /// The lowest chunk not written yet, or the chunk count when complete
+    pub fn first_missing(&self) -> u32 {
+        (0..self.manifest.chunk_count())
+            .find(|i| !self.received.contains(i))
+            .unwrap_or(self.manifest.chunk_count())
+    }
+
+    /// Length of the verified prefix of the part file, which can be read
+    /// while later chunks are still arriving
+    pub fn readable_bytes(&self) -> u64 {
+        match self.first_missing() {
+            index if index == self.manifest.chunk_count() => self.manifest.file_size,
+            index => self
+                .manifest
+                .chunk_range(index)
+                .map(|range| range.start as u64)
+                .unwrap_or(0),
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.received.len() as u32 == self.manifest.chunk_count()
+    }
+
+    /// Write a chunk the caller has already verified
+    pub async fn write_chunk(&mut self, index: u32, data: &[u8], now: u64) -> Result<(), String> {
+        let range = self
+            .manifest
+            .chunk_range(index)
+            .map_err(|e| e.to_string())?;
+        self.file
+            .seek(SeekFrom::Start(range.start as u64))
+            .await
+            .map_err(write_err)?;
+        self.file.write_all(data).await.map_err(write_err)?;
+        if self.received.insert(index) {
+            self.unsaved += 1;
+        }
+        if self.unsaved >= SAVE_EVERY_CHUNKS {
+            self.checkpoint(now).await?;
+        }
+        Ok(())
+    }
+
+    /// Flush written chunks and record them
+    pub async fn checkpoint(&mut self, now: u64) -> Result<(), String> {
+        self.file.sync_data().await.map_err(write_err)?;
+        self.record.set_received(&self.received, now);
+        self.store.save(&self.record).await?;
+        self.unsaved = 0;
+        Ok(())
+    }
+
+    /// Move the completed part file to the output path and drop the record
+    pub async fn finish(mut self) -> Result<(), String> {
+        if !self.is_complete() {
+            let index = self.missing()[0];
+            return Err(format!("Cannot finish download: missing chunk {}", index));
+        }
+        self.file.sync_all().await.map_err(write_err)?;
+        drop(self.file);
+        tokio::fs::rename(&self.part, &self.output_path)
+            .await
+            .map_err(write_err)?;
+        self.store
+            .remove(&self.manifest.file_hash, &self.output_path)
+            .await;
+        Ok(())
+    }
+
+    /// Delete the part file and its record, e.g. after the assembled file
+    /// turned out not to match its content hash
+    pub async fn discard(self) {
+        drop(self.file);
+        let _ = tokio::fs::remove_file(&self.part).await;
+        self.store
+            .remove(&self.manifest.file_hash, &self.output_path)
+            .await;
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.part
+    }
+
+    pub fn file_hash(&self) -> &str {
+        &self.manifest.file_hash
+    }
+
+    pub fn file_size(&self) -> u64 {
+        self.manifest.file_size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn records_round_trip_and_are_keyed_by_hash_and_destination() {
+        let dir = tempdir().unwrap();
+        let store = ResumeStore::new(dir.path());
+        let manifest = ChunkManifest::build_with_chunk_size("hash", &[1u8; 10], 4);
+
+        let mut record = ResumeRecord::new("t1", "/out/a", &manifest, 5);
+        record.set_received(&[0u32, 2].into_iter().collect(), 6);
+        store.save(&record).await.unwrap();
+        store
+            .save(&ResumeRecord::new("t2", "/out/b", &manifest, 1))
+            .await
+            .unwrap();
+
+        let loaded = store.load("hash", "/out/a").await.unwrap();
+        assert_eq!(loaded, record);
+        assert!(loaded.matches(&manifest));
+        assert_eq!(loaded.received_count(), 2);
+
+        let pending: Vec<String> = store
+            .pending()
+            .await
+            .into_iter()
+            .map(|r| r.transfer_id)
+            .collect();
+        assert_eq!(pending, vec!["t2".to_string(), "t1".to_string()]);
+
+        store.remove("hash", "/out/a").await;
+        assert!(store.load("hash", "/out/a").await.is_none());
+    }
+
+    #[test]
+    fn record_for_other_content_does_not_match() {
+        let manifest = ChunkManifest::build_with_chunk_size("hash", &[1u8; 10], 4);
+        let other = ChunkManifest::build_with_chunk_size("hash", &[2u8; 10], 4);
+        let record = ResumeRecord::new("t", "/out", &manifest, 0);
+        assert!(!record.matches(&other));
+    }
+}
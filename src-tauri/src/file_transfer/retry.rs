@@ -0,0 +1,170 @@
+// Download retry policy
+//
+// A failed download attempt is retried with exponential backoff until the
+// policy's attempt budget runs out. Delays double from `base_backoff_ms` up
+// to `max_backoff_ms` and are spread by `jitter` either way so downloads
+// that failed together don't all retry at the same instant. Failures are
+// classified by `ErrorCode`, and `retry_on` narrows which classes are worth
+// another attempt. By default every class is except the permanent ones a
+// second attempt of the same download cannot fix: a file that does not
+// exist, a full disk, bad input or missing credentials.
+// The service holds a default policy and a download command can bring its
+// own.
+
+use crate::error_codes::ErrorCode;
+use crate::runtime_env::RandomSource;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first
+    pub max_attempts: u32,
+    pub base_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Fraction by which a delay may be spread either way, 0.0..=1.0
+    pub jitter: f64,
+    /// Error classes worth retrying; `None` retries every failure but the
+    /// `PERMANENT_FAILURES`
+    pub retry_on: Option<HashSet<ErrorCode>>,
+}
+
+/// Failures that end a download at once unless `retry_on` lists them
+pub const PERMANENT_FAILURES: [ErrorCode; 4] = [
+    ErrorCode::NotFound,
+    ErrorCode::DiskFull,
+    ErrorCode::InvalidInput,
+    ErrorCode::Unauthorized,
+];
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_backoff_ms: 250,
+            max_backoff_ms: 1_500,
+            jitter: 0.2,
+            retry_on: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that gives up after the first failure
+    pub fn no_retry() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_attempts == 0 {
+            return Err("Retry policy must allow at least one attempt".to_string());
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            return Err(format!(
+                "Retry jitter must be between 0 and 1, got {}",
+                self.jitter
+            ));
+        }
+        if self.base_backoff_ms > self.max_backoff_ms {
+            return Err("Base backoff must not exceed the maximum backoff".to_string());
+        }
+        Ok(())
+    }
+
+    /// Whether another attempt may follow a failed `attempt` (1-based)
+    pub fn should_retry(&self, attempt: u32, error: &str) -> bool {
+        let code = ErrorCode::classify(error);
+        attempt < self.max_attempts
+            && match &self.retry_on {
+                Some(codes) => codes.contains(&code),
+                None => !PERMANENT_FAILURES.contains(&code),
+            }
+    }
+
+    /// Delay before `attempt` (1-based); the first attempt starts at once
+    pub fn backoff_delay(&self, attempt: u32, random: &dyn RandomSource) -> Duration {
+        if attempt <= 1 {
+            return Duration::from_millis(0);
+        }
+
+        let shift = (attempt - 1).min(4);
+        let multiplier = 1u64 << shift;
+        let delay = self
+            .base_backoff_ms
+            .saturating_mul(multiplier)
+            .min(self.max_backoff_ms);
+        let spread = 1.0 + self.jitter * (random.next_f64() * 2.0 - 1.0);
+        Duration::from_millis((delay as f64 * spread) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime_env::SeededRandom;
+
+    #[test]
+    fn delays_grow_and_stay_within_jitter_and_cap() {
+        let policy = RetryPolicy {
+            max_attempts: 6,
+            base_backoff_ms: 100,
+            max_backoff_ms: 1_000,
+            jitter: 0.1,
+            retry_on: None,
+        };
+        let random = SeededRandom::new(7);
+        assert_eq!(policy.backoff_delay(1, &random), Duration::ZERO);
+        for (attempt, base) in [(2, 200.0), (3, 400.0), (4, 800.0), (5, 1_000.0)] {
+            let ms = policy.backoff_delay(attempt, &random).as_millis() as f64;
+            assert!(
+                ms >= base * 0.9 - 1.0 && ms <= base * 1.1,
+                "{attempt}: {ms}"
+            );
+        }
+    }
+
+    #[test]
+    fn only_listed_error_classes_are_retried() {
+        let policy = RetryPolicy {
+            retry_on: Some(
+                [ErrorCode::Timeout, ErrorCode::Network]
+                    .into_iter()
+                    .collect(),
+            ),
+            ..RetryPolicy::default()
+        };
+        assert!(policy.should_retry(1, "request timed out"));
+        assert!(!policy.should_retry(1, "File not found in storage"));
+        assert!(!policy.should_retry(3, "request timed out"));
+
+        let default = RetryPolicy::default();
+        assert!(default.should_retry(2, "Download timed out after 30s"));
+        assert!(default.should_retry(1, "Stored file failed verification: bad chunk"));
+        assert!(!default.should_retry(1, "File not found in storage"));
+        assert!(!default.should_retry(1, "disk_full: Not enough disk space"));
+        assert!(!default.should_retry(1, "Failed to decrypt: private key required"));
+        assert!(!RetryPolicy::no_retry().should_retry(1, "request timed out"));
+    }
+
+    #[test]
+    fn invalid_policies_are_rejected() {
+        assert!(RetryPolicy::default().validate().is_ok());
+        assert!(RetryPolicy {
+            max_attempts: 0,
+            ..RetryPolicy::default()
+        }
+        .validate()
+        .is_err());
+        assert!(RetryPolicy {
+            jitter: 1.5,
+            ..RetryPolicy::default()
+        }
+        .validate()
+        .is_err());
+    }
+}
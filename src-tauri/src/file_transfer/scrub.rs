@@ -0,0 +1,169 @@
+// Storage scrubbing
+//
+// A scrub re-hashes every stored blob whose key is a content hash and
+// reports the ones whose bytes no longer match it: bit rot, a truncated
+// file, a linked file edited after it was shared. Blobs are read in pieces
+// through the blob store, so sealed, deduplicated and linked blobs are
+// checked as the bytes peers would be served. With repair on, a corrupted
+// blob is fetched again from other providers and replaces the bad copy.
+//
+// Scrubs run on request or on a schedule. `ScrubState` makes sure only one
+// runs at a time and tells the periodic tick when the next one is due.
+
+use super::buffers::chunk_buffers;
+use super::chunking::CHUNK_SIZE;
+use super::hashing::ContentHash;
+use crate::blob_store::{BlobEntry, BlobStore};
+use serde::{Deserialize, Serialize};
+
+/// Bytes hashed per read, one pooled chunk buffer
+const READ_LEN: u64 = CHUNK_SIZE as u64;
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CorruptBlob {
+    pub file_hash: String,
+    pub problem: String,
+    /// Replaced by a verified copy from the network
+    pub repaired: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ScrubReport {
+    /// Unix seconds
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub checked: usize,
+    pub bytes_checked: u64,
+    /// Blobs whose key is not a content hash, or that are sealed while the
+    /// store is locked
+    pub skipped: usize,
+    pub corrupted: Vec<CorruptBlob>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ScrubSchedule {
+    /// Seconds from the start of one background scrub to the next
+    pub interval_secs: u64,
+    /// Re-fetch corrupted blobs from other providers
+    pub repair: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct ScrubState {
+    schedule: Option<ScrubSchedule>,
+    running: bool,
+    /// When the last scrub started, Unix seconds
+    last_started: Option<u64>,
+}
+
+impl ScrubState {
+    pub fn set_schedule(&mut self, schedule: Option<ScrubSchedule>) {
+        self.schedule = schedule;
+    }
+
+    /// Claim the scrubber for a run starting at `now`. False if one is
+    /// already running.
+    pub fn start(&mut self, now: u64) -> bool {
+        if self.running {
+            return false;
+        }
+        self.running = true;
+        self.last_started = Some(now);
+        true
+    }
+
+    pub fn finish(&mut self) {
+        self.running = false;
+    }
+
+    /// Whether a scheduled scrub should start at `now`, and with repair
+    pub fn due(&self, now: u64) -> Option<bool> {
+        let schedule = self.schedule?;
+        let due = match self.last_started {
+            Some(last) => now >= last.saturating_add(schedule.interval_secs.max(1)),
+            None => true,
+        };
+        (due && !self.running).then_some(schedule.repair)
+    }
+}
+
+/// Whether `entry` can be checked, and the hash its contents must have
+pub fn expected_hash(blobs: &BlobStore, entry: &BlobEntry) -> Option<ContentHash> {
+    if entry.sealed && !blobs.is_unlocked() {
+        return None;
+    }
+    ContentHash::parse(&entry.hash)
+}
+
+/// Re-hash a stored blob. Returns what is wrong with it, if anything.
+pub async fn check_blob(
+    blobs: &BlobStore,
+    entry: &BlobEntry,
+    expected: ContentHash,
+) -> Option<String> {
+    let mut hasher = expected.algorithm.hasher();
+    let mut buf = chunk_buffers().take();
+    let mut offset = 0;
+    while offset < entry.size {
+        let len = (entry.size - offset).min(READ_LEN);
+        match blobs
+            .read_range_into(&entry.hash, offset, len as usize, &mut buf)
+            .await
+        {
+            Ok(true) => hasher.update(&buf),
+            Ok(false) => return Some("File is missing from storage".to_string()),
+            Err(e) => return Some(e),
+        }
+        offset += len;
+    }
+    (hasher.finish() != expected).then(|| "Contents do not match the file hash".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_transfer::hashing::HashAlgorithm;
+    use tempfile::tempdir;
+
+    #[test]
+    fn scheduled_scrubs_wait_for_the_interval_and_never_overlap() {
+        let mut state = ScrubState::default();
+        assert_eq!(state.due(100), None);
+        state.set_schedule(Some(ScrubSchedule {
+            interval_secs: 60,
+            repair: true,
+        }));
+        assert_eq!(state.due(100), Some(true));
+        assert!(state.start(100));
+        assert!(!state.start(100));
+        assert_eq!(state.due(500), None);
+        state.finish();
+        assert_eq!(state.due(159), None);
+        assert_eq!(state.due(160), Some(true));
+    }
+
+    #[tokio::test]
+    async fn damaged_blobs_fail_the_check() {
+        let dir = tempdir().unwrap();
+        let blobs = BlobStore::open(dir.path()).await.unwrap();
+        let data = b"stored contents".to_vec();
+        let hash = HashAlgorithm::Sha256.hash(&data).to_string();
+        let entry = blobs.put(&hash, &data).await.unwrap();
+        let expected = expected_hash(&blobs, &entry).unwrap();
+        assert_eq!(check_blob(&blobs, &entry, expected).await, None);
+
+        std::fs::write(dir.path().join(&hash), b"stored c0ntents").unwrap();
+        assert_eq!(
+            check_blob(&blobs, &entry, expected).await.as_deref(),
+            Some("Contents do not match the file hash")
+        );
+        std::fs::write(dir.path().join(&hash), b"stored").unwrap();
+        assert!(check_blob(&blobs, &entry, expected).await.is_some());
+
+        let legacy = blobs.put("legacy", b"x").await.unwrap();
+        assert!(expected_hash(&blobs, &legacy).is_none());
+    }
+}
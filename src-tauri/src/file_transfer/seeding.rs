@@ -0,0 +1,143 @@
+// Seeding statistics
+//
+// Counts what this node actually uploads, per file hash: bytes served, the
+// distinct peers they went to, and when the file was last served. Every
+// serving path reports through `FileTransferService::record_served` as the
+// bytes go out — transfer-protocol chunks and whole files, WebRTC chunks and
+// DHT chunk requests — so a partial upload counts for what was sent.
+// `demand_stats` counts requests, including ones that could not be served;
+// this counts bytes, so together they tell a seeder which files are asked for
+// and which are really being fetched from it.
+//
+// The counts live in memory for the life of the service. Distinct peers are
+// counted up to `MAX_PEERS_PER_FILE`.
+
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+pub const MAX_PEERS_PER_FILE: usize = 256;
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FileSeedingStats {
+    pub file_hash: String,
+    pub bytes_served: u64,
+    /// Chunks or whole files sent
+    pub pieces_served: u64,
+    /// Distinct peers served, capped at `MAX_PEERS_PER_FILE`
+    pub unique_peers: usize,
+    /// Unix seconds
+    pub first_served_at: u64,
+    pub last_served_at: u64,
+}
+
+#[derive(Debug, Default)]
+struct FileSeeding {
+    bytes_served: u64,
+    pieces_served: u64,
+    peers: HashSet<String>,
+    first_served_at: u64,
+    last_served_at: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct SeedingStats {
+    files: HashMap<String, FileSeeding>,
+}
+
+impl SeedingStats {
+    /// Count `bytes` of `file_hash` sent to `peer` at `now`
+    pub fn record(&mut self, file_hash: &str, peer: &str, bytes: u64, now: u64) {
+        let file = self
+            .files
+            .entry(file_hash.to_string())
+            .or_insert_with(|| FileSeeding {
+                first_served_at: now,
+                ..FileSeeding::default()
+            });
+        file.bytes_served += bytes;
+        file.pieces_served += 1;
+        file.last_served_at = file.last_served_at.max(now);
+        if file.peers.len() < MAX_PEERS_PER_FILE {
+            file.peers.insert(peer.to_string());
+        }
+    }
+
+    pub fn get(&self, file_hash: &str) -> Option<FileSeedingStats> {
+        self.files
+            .get(file_hash)
+            .map(|file| file.summary(file_hash))
+    }
+
+    /// Every file served so far, most bytes first, most recent first on ties
+    pub fn snapshot(&self) -> Vec<FileSeedingStats> {
+        let mut files: Vec<FileSeedingStats> = self
+            .files
+            .iter()
+            .map(|(hash, file)| file.summary(hash))
+            .collect();
+        files.sort_by(|a, b| {
+            b.bytes_served
+                .cmp(&a.bytes_served)
+                .then(b.last_served_at.cmp(&a.last_served_at))
+                .then_with(|| a.file_hash.cmp(&b.file_hash))
+        });
+        files
+    }
+}
+
+impl FileSeeding {
+    fn summary(&self, file_hash: &str) -> FileSeedingStats {
+        FileSeedingStats {
+            file_hash: file_hash.to_string(),
+            bytes_served: self.bytes_served,
+            pieces_served: self.pieces_served,
+            unique_peers: self.peers.len(),
+            first_served_at: self.first_served_at,
+            last_served_at: self.last_served_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_bytes_and_distinct_peers_per_file() {
+        let mut stats = SeedingStats::default();
+        stats.record("popular", "peer-a", 1000, 10);
+        stats.record("popular", "peer-b", 1000, 20);
+        stats.record("popular", "peer-a", 500, 30);
+        stats.record("niche", "peer-c", 100, 40);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.len(), 2);
+        assert_eq!(
+            snapshot[0],
+            FileSeedingStats {
+                file_hash: "popular".to_string(),
+                bytes_served: 2500,
+                pieces_served: 3,
+                unique_peers: 2,
+                first_served_at: 10,
+                last_served_at: 30,
+            }
+        );
+        assert_eq!(snapshot[1].file_hash, "niche");
+
+        assert!(stats.get("unknown").is_none());
+        assert_eq!(stats.get("niche").unwrap().last_served_at, 40);
+    }
+
+    #[test]
+    fn distinct_peers_are_capped() {
+        let mut stats = SeedingStats::default();
+        for i in 0..MAX_PEERS_PER_FILE + 10 {
+            stats.record("hash", &format!("peer-{}", i), 1, 0);
+        }
+        let file = stats.get("hash").unwrap();
+        assert_eq!(file.unique_peers, MAX_PEERS_PER_FILE);
+        assert_eq!(file.pieces_served, MAX_PEERS_PER_FILE as u64 + 10);
+    }
+}
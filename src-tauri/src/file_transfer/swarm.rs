@@ -0,0 +1,642 @@
+// Multi-source chunk fetching
+//
+// When a file is not in the local blob store, a download can name several
+// providers. The manifest comes from the first provider that returns one
+// committing to the requested id: a file announced under its merkle root
+// needs a manifest with that root, so every verified chunk is part of it,
+// and a content hash needs a manifest for that hash, with the assembled file
+// checked against it at the end. Chunks are then spread across all
+// providers, each with a bounded number of requests in flight, so faster
+// providers naturally serve more chunks. A chunk that fails verification or
+// transfer is requeued for a provider that has not failed it yet, and a
+// provider that keeps failing is dropped for the rest of the download.
+//
+// A streaming download, e.g. media to be played while it arrives, hands
+// chunks out strictly in order and never more than `streaming_window` past
+// the first one still missing, so the start of the file fills in first. The
+// verified prefix is reported as it grows, and a player can read the part
+// file up to it.
+//
+// The transport is behind `ChunkFetcher`, so the scheduling is the same for
+// the libp2p protocol and for tests.
+
+use super::chunking::ChunkManifest;
+use super::hashing::ContentHash;
+use super::progress::ProgressReporter;
+use super::resume::PartFile;
+use crate::runtime_env::Clock;
+use async_trait::async_trait;
+use futures::stream::{FuturesUnordered, StreamExt};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+/// Chunks a streaming download may fetch ahead of the first missing one
+pub const DEFAULT_STREAMING_WINDOW: u32 = 16;
+
+/// Fetches manifests and chunks of stored files from remote providers
+#[async_trait]
+pub trait ChunkFetcher: Send + Sync {
+    async fn fetch_manifest(
+        &self,
+        provider: &str,
+        file_hash: &str,
+    ) -> Result<ChunkManifest, String>;
+
+    async fn fetch_chunk(
+        &self,
+        provider: &str,
+        file_hash: &str,
+        index: u32,
+    ) -> Result<Vec<u8>, String>;
+
+    /// Stop announcing this node as a provider of `file_hash`
+    async fn stop_providing(&self, _file_hash: &str) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Other nodes announcing themselves as providers of `file_hash`
+    async fn find_providers(&self, _file_hash: &str) -> Result<Vec<String>, String> {
+        Ok(Vec::new())
+    }
+}
+
+/// Providers a download may use, the transport to reach them and how chunks
+/// are spread across them
+#[derive(Clone, Default)]
+pub struct RemoteSources {
+    pub providers: Vec<String>,
+    pub fetcher: Option<Arc<dyn ChunkFetcher>>,
+    pub config: SwarmConfig,
+}
+
+impl RemoteSources {
+    pub fn new(providers: Vec<String>, fetcher: Option<Arc<dyn ChunkFetcher>>) -> Self {
+        Self {
+            providers,
+            fetcher,
+            config: SwarmConfig::default(),
+        }
+    }
+
+    pub fn is_available(&self) -> bool {
+        !self.providers.is_empty() && self.fetcher.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SwarmConfig {
+    /// Chunk requests outstanding per provider
+    pub per_provider_in_flight: usize,
+    /// A chunk failing this many times fails the download
+    pub max_chunk_attempts: u32,
+    /// A provider failing this many times in a row is dropped
+    pub max_provider_failures: u32,
+    /// Fetch chunks in order, at most this many past the first missing one,
+    /// and report the readable prefix. None fetches in any order.
+    pub streaming_window: Option<u32>,
+}
+
+impl Default for SwarmConfig {
+    fn default() -> Self {
+        Self {
+            per_provider_in_flight: 4,
+            max_chunk_attempts: 5,
+            max_provider_failures: 3,
+            streaming_window: None,
+        }
+    }
+}
+
+impl SwarmConfig {
+    pub fn streaming() -> Self {
+        Self {
+            streaming_window: Some(DEFAULT_STREAMING_WINDOW),
+            ..Self::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderStats {
+    pub provider: String,
+    pub chunks: u32,
+    pub bytes: u64,
+    pub failures: u32,
+    /// Dropped after too many consecutive failures
+    pub dropped: bool,
+}
+
+/// Whether `manifest` commits to the id a file was requested by: its merkle
+/// root, or its content hash (checked again once the file is assembled)
+pub fn commits_to(manifest: &ChunkManifest, id: &str) -> bool {
+    manifest.merkle_root == id || (manifest.file_hash == id && ContentHash::parse(id).is_some())
+}
+
+/// First manifest from `providers` that commits to `file_hash` and is
+/// internally consistent
+pub async fn fetch_manifest(
+    fetcher: &dyn ChunkFetcher,
+    providers: &[String],
+    file_hash: &str,
+) -> Result<ChunkManifest, String> {
+    let mut last_error = "no providers".to_string();
+    for provider in providers {
+        match fetcher.fetch_manifest(provider, file_hash).await {
+            Ok(manifest)
+                if manifest.file_hash == file_hash && !commits_to(&manifest, file_hash) =>
+            {
+                last_error = format!(
+                    "{} returned a manifest whose root {} is not the announced id",
+                    provider, manifest.merkle_root
+                );
+            }
+            Ok(manifest) if !commits_to(&manifest, file_hash) => {
+                last_error = format!("{} returned a manifest for another file", provider);
+            }
+            Ok(manifest) => match manifest.validate() {
+                Ok(()) => return Ok(manifest),
+                Err(e) => last_error = format!("{} returned an invalid manifest: {}", provider, e),
+            },
+            Err(e) => last_error = format!("{}: {}", provider, e),
+        }
+        debug!(%provider, hash = %file_hash, "manifest fetch failed: {}", last_error);
+    }
+    Err(format!(
+        "No provider returned a chunk manifest ({})",
+        last_error
+    ))
+}
+
+/// Fetch every chunk `part` is missing from `providers`, verifying each
+/// against `manifest` before it is written. Stops with the cancelled error
+/// as soon as `cancel` fires, dropping the requests still in flight. A
+/// streaming fetch reports the readable prefix of `part` through `progress`
+/// each time it grows.
+pub async fn fetch_chunks(
+    fetcher: Arc<dyn ChunkFetcher>,
+    providers: &[String],
+    manifest: &ChunkManifest,
+    part: &mut PartFile,
+    config: SwarmConfig,
+    clock: &dyn Clock,
+    progress: &ProgressReporter,
+    cancel: &CancellationToken,
+) -> Result<Vec<ProviderStats>, String> {
+    let mut stats: Vec<ProviderStats> = providers
+        .iter()
+        .map(|p| ProviderStats {
+            provider: p.clone(),
+            ..ProviderStats::default()
+        })
+        .collect();
+    let mut in_flight = vec![0usize; providers.len()];
+    let mut consecutive_failures = vec![0u32; providers.len()];
+    let mut queue: VecDeque<u32> = part.missing().into();
+    let mut attempts: HashMap<u32, u32> = HashMap::new();
+    let mut failed_on: HashMap<u32, HashSet<usize>> = HashMap::new();
+    let mut pending = FuturesUnordered::new();
+    let per_provider = config.per_provider_in_flight.max(1);
+    let mut readable = part.readable_bytes();
+    if config.streaming_window.is_some() {
+        progress.readable(part);
+    }
+
+    loop {
+        // Hand out queued chunks round-robin to providers with free slots.
+        // The queue is in chunk order, requeued chunks go to the front.
+        let horizon = config
+            .streaming_window
+            .map(|window| part.first_missing().saturating_add(window.max(1)));
+        loop {
+            let mut assigned = false;
+            for p in 0..providers.len() {
+                if stats[p].dropped || in_flight[p] >= per_provider {
+                    continue;
+                }
+                let Some(pos) = queue.iter().position(|c| {
+                    !failed_on.get(c).is_some_and(|f| f.contains(&p))
+                        && horizon.is_none_or(|horizon| *c < horizon)
+                }) else {
+                    continue;
+                };
+                let index = queue.remove(pos).expect("position is in range");
+                in_flight[p] += 1;
+                assigned = true;
+                let fetcher = fetcher.clone();
+                let provider = providers[p].clone();
+                let file_hash = manifest.file_hash.clone();
+                pending.push(async move {
+                    let result = fetcher.fetch_chunk(&provider, &file_hash, index).await;
+                    (p, index, result)
+                });
+            }
+            if !assigned {
+                break;
+            }
+        }
+
+        let next = tokio::select! {
+            next = pending.next() => next,
+            _ = cancel.cancelled() => return Err(super::cancelled_error()),
+        };
+        let Some((p, index, result)) = next else {
+            break;
+        };
+        in_flight[p] -= 1;
+
+        let verified = result.and_then(|data| {
+            manifest
+                .verify_chunk(index, &data)
+                .map(|()| data)
+                .map_err(|e| e.to_string())
+        });
+        match verified {
+            Ok(data) => {
+                part.write_chunk(index, &data, clock.now_secs()).await?;
+                progress.add(data.len() as u64);
+                stats[p].chunks += 1;
+                stats[p].bytes += data.len() as u64;
+                consecutive_failures[p] = 0;
+                if config.streaming_window.is_some() && part.readable_bytes() > readable {
+                    readable = part.readable_bytes();
+                    progress.readable(part);
+                }
+            }
+            Err(e) => {
+                stats[p].failures += 1;
+                consecutive_failures[p] += 1;
+                warn!(provider = %providers[p], chunk = index, "chunk fetch failed: {}", e);
+                if consecutive_failures[p] >= config.max_provider_failures {
+                    warn!(provider = %providers[p], "dropping provider after repeated failures");
+                    stats[p].dropped = true;
+                }
+
+                let tries = attempts.entry(index).or_insert(0);
+                *tries += 1;
+                if *tries >= config.max_chunk_attempts {
+                    return Err(format!(
+                        "Chunk {} failed {} times, last error: {}",
+                        index, tries, e
+                    ));
+                }
+
+                // Prefer another provider next time; once every remaining
+                // provider has failed the chunk, let all of them try again
+                let failed = failed_on.entry(index).or_default();
+                failed.insert(p);
+                let live: Vec<usize> = (0..providers.len())
+                    .filter(|q| !stats[*q].dropped)
+                    .collect();
+                if !live.is_empty() && live.iter().all(|q| failed.contains(q)) {
+                    failed.clear();
+                }
+                queue.push_front(index);
+            }
+        }
+    }
+
+    if let Some(index) = part.missing().first() {
+        return Err(format!(
+            "No provider could supply chunk {} of {}",
+            index, manifest.file_hash
+        ));
+    }
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file_transfer::progress::TransferDirection;
+    use crate::file_transfer::resume::ResumeStore;
+    use crate::runtime_env::ManualClock;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tempfile::tempdir;
+
+    struct MockFetcher {
+        data: Vec<u8>,
+        manifest: ChunkManifest,
+        /// Providers that serve corrupted chunks
+        bad: HashSet<String>,
+        requests: AtomicU32,
+    }
+
+    #[async_trait]
+    impl ChunkFetcher for MockFetcher {
+        async fn fetch_manifest(
+            &self,
+            provider: &str,
+            _file_hash: &str,
+        ) -> Result<ChunkManifest, String> {
+            if provider == "offline" {
+                return Err("unreachable".to_string());
+            }
+            Ok(self.manifest.clone())
+        }
+
+        async fn fetch_chunk(
+            &self,
+            provider: &str,
+            _file_hash: &str,
+            index: u32,
+        ) -> Result<Vec<u8>, String> {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+            tokio::task::yield_now().await;
+            let mut chunk = self.data[self.manifest.chunk_range(index).unwrap()].to_vec();
+            if self.bad.contains(provider) {
+                chunk[0] ^= 0xff;
+            }
+            Ok(chunk)
+        }
+    }
+
+    #[tokio::test]
+    async fn chunks_are_spread_across_providers_and_bad_ones_are_dropped() {
+        let dir = tempdir().unwrap();
+        let output = dir.path().join("out.bin").to_string_lossy().to_string();
+        let data: Vec<u8> = (0..200u32).map(|i| (i % 256) as u8).collect();
+        let manifest = ChunkManifest::build_with_chunk_size("file", &data, 10);
+        let fetcher = Arc::new(MockFetcher {
+            data: data.clone(),
+            manifest: manifest.clone(),
+            bad: ["evil".to_string()].into_iter().collect(),
+            requests: AtomicU32::new(0),
+        });
+        let providers: Vec<String> = ["offline", "a", "b", "evil"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+
+        let fetched = fetch_manifest(fetcher.as_ref(), &providers, &manifest.merkle_root)
+            .await
+            .unwrap();
+        assert_eq!(fetched, manifest);
+
+        let mut part = PartFile::open(ResumeStore::new(dir.path()), "t", &output, &manifest, 0)
+            .await
+            .unwrap();
+        let clock = ManualClock::default();
+        let progress =
+            ProgressReporter::silent("t", TransferDirection::Download, Arc::new(clock.clone()));
+        let stats = fetch_chunks(
+            fetcher.clone(),
+            &providers[1..],
+            &manifest,
+            &mut part,
+            SwarmConfig::default(),
+            &clock,
+            &progress,
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        part.finish().await.unwrap();
+
+        assert_eq!(std::fs::read(&output).unwrap(), data);
+        assert!(stats[0].chunks > 0 && stats[1].chunks > 0);
+        assert_eq!(stats[0].chunks + stats[1].chunks, 20);
+        assert_eq!(progress.snapshot().bytes_done, 200);
+        assert_eq!(stats[2].chunks, 0);
+        assert!(stats[2].dropped);
+    }
+
+    /// Hands out a different manifest per provider
+    struct ManifestFetcher(HashMap<String, ChunkManifest>);
+
+    #[async_trait]
+    impl ChunkFetcher for ManifestFetcher {
+        async fn fetch_manifest(
+            &self,
+            provider: &str,
+            _file_hash: &str,
+        ) -> Result<ChunkManifest, String> {
+            self.0
+                .get(provider)
+                .cloned()
+                .ok_or_else(|| "unreachable".to_string())
+        }
+
+        async fn fetch_chunk(&self, _: &str, _: &str, _: u32) -> Result<Vec<u8>, String> {
+            Err("no chunks here".to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn manifests_must_commit_to_the_announced_id() {
+        let data = vec![1u8; 30];
+        let genuine = ChunkManifest::build_with_chunk_size("stored-key", &data, 10);
+        let root = genuine.merkle_root.clone();
+        // Claims the announced id but its chunks hash to another root
+        let forged = ChunkManifest::build_with_chunk_size(&root, &[2u8; 30], 10);
+        let other = ChunkManifest::build_with_chunk_size("other", &data, 10);
+        let fetcher = ManifestFetcher(
+            [
+                ("forged".to_string(), forged),
+                ("other".to_string(), other.clone()),
+                ("genuine".to_string(), genuine.clone()),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        let providers =
+            |names: &[&str]| -> Vec<String> { names.iter().map(|n| n.to_string()).collect() };
+
+        let err = fetch_manifest(&fetcher, &providers(&["forged"]), &root)
+            .await
+            .unwrap_err();
+        assert!(err.contains("not the announced id"), "{err}");
+        let err = fetch_manifest(&fetcher, &providers(&["other"]), &root)
+            .await
+            .unwrap_err();
+        assert!(err.contains("another file"), "{err}");
+        let fetched = fetch_manifest(&fetcher, &providers(&["forged", "other", "genuine"]), &root)
+            .await
+            .unwrap();
+        assert_eq!(fetched, genuine);
+
+        // A content hash is matched by the manifest's file hash
+        let hash = crate::file_transfer::FileTransferService::calculate_file_hash(&data);
+        let by_hash = ChunkManifest::build_with_chunk_size(&hash, &data, 10);
+        let fetcher = ManifestFetcher([("a".to_string(), by_hash.clone())].into_iter().collect());
+        assert_eq!(
+            fetch_manifest(&fetcher, &providers(&["a"]), &hash)
+                .await
+                .unwrap(),
+            by_hash
+        );
+        // Any other id only by the root
+        assert!(fetch_manifest(&fetcher, &providers(&["a"]), "not-a-hash")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn download_fails_when_every_provider_is_bad() {
+        let dir = tempdir().unwrap();
+        let output = dir.path().join("out.bin").to_string_lossy().to_string();
+        let data = vec![9u8; 50];
+        let manifest = ChunkManifest::build_with_chunk_size("file", &data, 10);
+        let fetcher = Arc::new(MockFetcher {
+            data,
+            manifest: manifest.clone(),
+            bad: ["x".to_string()].into_iter().collect(),
+            requests: AtomicU32::new(0),
+        });
+        let mut part = PartFile::open(ResumeStore::new(dir.path()), "t", &output, &manifest, 0)
+            .await
+            .unwrap();
+
+        let clock = ManualClock::default();
+        let result = fetch_chunks(
+            fetcher,
+            &["x".to_string()],
+            &manifest,
+            &mut part,
+            SwarmConfig::default(),
+            &clock,
+            &ProgressReporter::silent("t", TransferDirection::Download, Arc::new(clock.clone())),
+            &CancellationToken::new(),
+        )
+        .await;
+        assert!(result.is_err());
+        assert!(part.received().is_empty());
+    }
+
+    #[tokio::test]
+    async fn cancelled_fetch_stops_without_requesting_chunks() {
+        let dir = tempdir().unwrap();
+        let output = dir.path().join("out.bin").to_string_lossy().to_string();
+        let data = vec![3u8; 50];
+        let manifest = ChunkManifest::build_with_chunk_size("file", &data, 10);
+        let fetcher = Arc::new(MockFetcher {
+            data,
+            manifest: manifest.clone(),
+            bad: HashSet::new(),
+            requests: AtomicU32::new(0),
+        });
+        let mut part = PartFile::open(ResumeStore::new(dir.path()), "t", &output, &manifest, 0)
+            .await
+            .unwrap();
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let clock = ManualClock::default();
+        let err = fetch_chunks(
+            fetcher.clone(),
+            &["a".to_string()],
+            &manifest,
+            &mut part,
+            SwarmConfig::default(),
+            &clock,
+            &ProgressReporter::silent("t", TransferDirection::Download, Arc::new(clock.clone())),
+            &cancel,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err, crate::file_transfer::cancelled_error());
+        assert_eq!(fetcher.requests.load(Ordering::SeqCst), 0);
+        assert!(part.received().is_empty());
+    }
+
+    /// Counts how many chunk requests are outstanding at once
+    struct PeakFetcher {
+        inner: MockFetcher,
+        active: AtomicU32,
+        peak: AtomicU32,
+    }
+
+    #[async_trait]
+    impl ChunkFetcher for PeakFetcher {
+        async fn fetch_manifest(
+            &self,
+            provider: &str,
+            file_hash: &str,
+        ) -> Result<ChunkManifest, String> {
+            self.inner.fetch_manifest(provider, file_hash).await
+        }
+
+        async fn fetch_chunk(
+            &self,
+            provider: &str,
+            file_hash: &str,
+            index: u32,
+        ) -> Result<Vec<u8>, String> {
+            let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(active, Ordering::SeqCst);
+            let chunk = self.inner.fetch_chunk(provider, file_hash, index).await;
+            self.active.fetch_sub(1, Ordering::SeqCst);
+            chunk
+        }
+    }
+
+    #[tokio::test]
+    async fn streaming_fetches_stay_near_the_start_and_report_the_readable_prefix() {
+        let dir = tempdir().unwrap();
+        let output = dir.path().join("out.bin").to_string_lossy().to_string();
+        let data: Vec<u8> = (0..200u32).map(|i| (i % 251) as u8).collect();
+        let manifest = ChunkManifest::build_with_chunk_size("file", &data, 10);
+        let fetcher = Arc::new(PeakFetcher {
+            inner: MockFetcher {
+                data: data.clone(),
+                manifest: manifest.clone(),
+                bad: HashSet::new(),
+                requests: AtomicU32::new(0),
+            },
+            active: AtomicU32::new(0),
+            peak: AtomicU32::new(0),
+        });
+        let mut part = PartFile::open(ResumeStore::new(dir.path()), "t", &output, &manifest, 0)
+            .await
+            .unwrap();
+        let clock = ManualClock::default();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(64);
+        let progress = ProgressReporter::new(
+            "t",
+            TransferDirection::Download,
+            tx,
+            Arc::new(clock.clone()),
+        );
+        let config = SwarmConfig {
+            per_provider_in_flight: 16,
+            streaming_window: Some(3),
+            ..SwarmConfig::default()
+        };
+
+        fetch_chunks(
+            fetcher.clone(),
+            &["a".to_string(), "b".to_string()],
+            &manifest,
+            &mut part,
+            config,
+            &clock,
+            &progress,
+            &CancellationToken::new(),
+        )
+        .await
+        .unwrap();
+        part.finish().await.unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), data);
+        // Two providers with free slots, but never more than the window
+        assert_eq!(fetcher.peak.load(Ordering::SeqCst), 3);
+
+        let mut readable = Vec::new();
+        while let Ok(event) = rx.try_recv() {
+            if let crate::file_transfer::FileTransferEvent::StreamReadable {
+                readable_bytes,
+                total_bytes,
+                ..
+            } = event
+            {
+                assert_eq!(total_bytes, 200);
+                readable.push(readable_bytes);
+            }
+        }
+        assert_eq!(readable.first(), Some(&0));
+        assert_eq!(readable.last(), Some(&200));
+        assert!(readable.windows(2).all(|w| w[0] < w[1]), "{readable:?}");
+    }
+}
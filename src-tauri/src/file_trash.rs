@@ -0,0 +1,451 @@
+// Trash for files removed from the library
+//
+// Removing a file used to stop publishing it and leave nothing to go back to;
+// for large uploads that meant hashing and uploading everything again. Removed
+// files are now moved into a trash directory instead: each removal gets its own
+// folder holding the blob taken out of the blob store, its .meta/.encmeta files
+// and an `entry.json` manifest with their original locations and the DHT
+// metadata, so a restore can put the files back and publish them again. The
+// blob goes back through the blob store, stored the way new blobs currently
+// are.
+//
+// Entries are kept for a configurable retention period and then deleted for
+// good. With secure erase enabled, file contents are overwritten with zeros
+// before deletion; on SSDs and copy-on-write filesystems this is best effort.
+
+use crate::blob_store::{BlobEntry, BlobStore};
+use crate::dht::FileMetadata;
+use crate::persisted::Persisted;
+use crate::runtime_env::{Clock, SystemClock};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+const MANIFEST_FILE: &str = "entry.json";
+const BLOB_FILE: &str = "blob";
+const ERASE_BLOCK: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TrashConfig {
+    /// Days a removed file stays restorable
+    pub retention_days: u32,
+    /// Overwrite contents with zeros before deleting
+    pub secure_erase: bool,
+}
+
+impl Default for TrashConfig {
+    fn default() -> Self {
+        Self {
+            retention_days: 7,
+            secure_erase: false,
+        }
+    }
+}
+
+/// One stored file moved into the trash
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashedItem {
+    pub original_path: String,
+    /// Name inside the entry's trash folder
+    pub stored_name: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TrashEntry {
+    pub id: String,
+    pub file_hash: String,
+    pub file_name: String,
+    /// Bytes held in the trash for this entry
+    pub size: u64,
+    pub trashed_at: u64,
+    pub expires_at: u64,
+    pub items: Vec<TrashedItem>,
+    /// Metadata the file was published with, used to publish it again on
+    /// restore
+    pub metadata: Option<FileMetadata>,
+    /// Index entry of the blob taken out of the blob store, kept in the
+    /// entry's folder as `blob`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob: Option<BlobEntry>,
+}
+
+/// Trash directory plus its (optionally persisted) retention settings
+pub struct FileTrash {
+    dir: PathBuf,
+    config: Persisted<TrashConfig>,
+    clock: Arc<dyn Clock>,
+}
+
+/// Entry ids become folder names; reject anything that could leave the trash
+fn validate_id(id: &str) -> Result<(), String> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err(format!("Invalid trash entry id: {}", id));
+    }
+    Ok(())
+}
+
+/// Rename, falling back to copy + remove when `to` is on another filesystem
+async fn move_file(from: &Path, to: &Path) -> Result<(), String> {
+    if tokio::fs::rename(from, to).await.is_ok() {
+        return Ok(());
+    }
+    tokio::fs::copy(from, to)
+        .await
+        .map_err(|e| format!("Failed to move {}: {}", from.display(), e))?;
+    tokio::fs::remove_file(from)
+        .await
+        .map_err(|e| format!("Failed to remove {}: {}", from.display(), e))
+}
+
+/// Overwrite the contents of `path` with zeros and flush them to disk
+async fn overwrite_with_zeros(path: &Path) -> Result<(), String> {
+    let len = tokio::fs::metadata(path)
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+        .len();
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .await
+        .map_err(|e| format!("Failed to open {} for erasing: {}", path.display(), e))?;
+    let zeros = vec![0u8; ERASE_BLOCK];
+    let mut remaining = len;
+    while remaining > 0 {
+        let n = remaining.min(ERASE_BLOCK as u64) as usize;
+        file.write_all(&zeros[..n])
+            .await
+            .map_err(|e| format!("Failed to erase {}: {}", path.display(), e))?;
+        remaining -= n as u64;
+    }
+    file.sync_all()
+        .await
+        .map_err(|e| format!("Failed to erase {}: {}", path.display(), e))
+}
+
+impl FileTrash {
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            config: Persisted::in_memory(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Trash in `dir` with settings loaded from and saved to `config_path`
+    pub fn with_persistence(dir: impl AsRef<Path>, config_path: impl AsRef<Path>) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            config: Persisted::with_persistence(config_path),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Read the time from `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn config(&self) -> TrashConfig {
+        self.config.get()
+    }
+
+    pub fn set_config(&self, config: TrashConfig) -> Result<(), String> {
+        self.config.set(config)
+    }
+
+    fn entry_dir(&self, id: &str) -> PathBuf {
+        self.dir.join(id)
+    }
+
+    async fn write_manifest(&self, entry: &TrashEntry) -> Result<(), String> {
+        let json = serde_json::to_vec_pretty(entry)
+            .map_err(|e| format!("Failed to serialize trash entry: {}", e))?;
+        tokio::fs::write(self.entry_dir(&entry.id).join(MANIFEST_FILE), json)
+            .await
+            .map_err(|e| format!("Failed to write trash entry: {}", e))
+    }
+
+    /// Move the blob `blob_key` out of `blobs` and the existing files among
+    /// `paths` into a new trash entry. `file_hash` is the id the file was
+    /// published under; `metadata` should not carry inline file data.
+    pub async fn move_in(
+        &self,
+        file_hash: &str,
+        file_name: &str,
+        blob: Option<(&BlobStore, &str)>,
+        paths: &[PathBuf],
+        metadata: Option<FileMetadata>,
+    ) -> Result<TrashEntry, String> {
+        let trashed_at = self.clock.now_secs();
+        let base: String = format!("{}-{}", file_hash, trashed_at)
+            .chars()
+            .filter(|c| c.is_ascii_alphanumeric() || *c == '-')
+            .collect();
+        let mut id = base.clone();
+        let mut n = 1;
+        while tokio::fs::metadata(self.entry_dir(&id)).await.is_ok() {
+            id = format!("{}-{}", base, n);
+            n += 1;
+        }
+        validate_id(&id)?;
+        let entry_dir = self.entry_dir(&id);
+        tokio::fs::create_dir_all(&entry_dir)
+            .await
+            .map_err(|e| format!("Failed to create trash directory: {}", e))?;
+
+        let blob_path = entry_dir.join(BLOB_FILE);
+        let blob = match blob {
+            Some((blobs, key)) => blobs.take(key, &blob_path).await?,
+            None => None,
+        };
+        let blob_size = tokio::fs::metadata(&blob_path)
+            .await
+            .map(|meta| meta.len())
+            .unwrap_or(0);
+
+        let mut items = Vec::new();
+        for (i, path) in paths.iter().enumerate() {
+            let Ok(meta) = tokio::fs::metadata(path).await else {
+                continue;
+            };
+            if !meta.is_file() {
+                continue;
+            }
+            let stored_name = format!(
+                "{}-{}",
+                i,
+                path.file_name().and_then(|n| n.to_str()).unwrap_or("file")
+            );
+            move_file(path, &entry_dir.join(&stored_name)).await?;
+            items.push(TrashedItem {
+                original_path: path.to_string_lossy().to_string(),
+                stored_name,
+                size: meta.len(),
+            });
+        }
+
+        let retention_secs = self.config().retention_days as u64 * 24 * 60 * 60;
+        let entry = TrashEntry {
+            id,
+            file_hash: file_hash.to_string(),
+            file_name: file_name.to_string(),
+            size: blob_size + items.iter().map(|i| i.size).sum::<u64>(),
+            trashed_at,
+            expires_at: trashed_at + retention_secs,
+            items,
+            metadata,
+            blob,
+        };
+        self.write_manifest(&entry).await?;
+        info!(
+            "Moved {} ({}) to trash, {} files",
+            entry.file_name,
+            entry.file_hash,
+            entry.items.len()
+        );
+        Ok(entry)
+    }
+
+    /// All entries, most recently trashed first
+    pub async fn list(&self) -> Result<Vec<TrashEntry>, String> {
+        let mut entries = Vec::new();
+        let mut dir = match tokio::fs::read_dir(&self.dir).await {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(entries),
+            Err(e) => return Err(format!("Failed to read trash directory: {}", e)),
+        };
+        while let Some(child) = dir
+            .next_entry()
+            .await
+            .map_err(|e| format!("Failed to read trash directory: {}", e))?
+        {
+            let manifest = child.path().join(MANIFEST_FILE);
+            let Ok(raw) = tokio::fs::read(&manifest).await else {
+                continue;
+            };
+            match serde_json::from_slice::<TrashEntry>(&raw) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => warn!(
+                    "Skipping unreadable trash entry {}: {}",
+                    manifest.display(),
+                    e
+                ),
+            }
+        }
+        entries.sort_by(|a, b| b.trashed_at.cmp(&a.trashed_at));
+        Ok(entries)
+    }
+
+    pub async fn get(&self, id: &str) -> Result<TrashEntry, String> {
+        validate_id(id)?;
+        let raw = tokio::fs::read(self.entry_dir(id).join(MANIFEST_FILE))
+            .await
+            .map_err(|_| format!("Trash entry {} not found", id))?;
+        serde_json::from_slice(&raw).map_err(|e| format!("Failed to parse trash entry: {}", e))
+    }
+
+    /// Put the blob of `id` back into `blobs`, published under the entry's
+    /// `file_hash` again, and move its files back to where they came from.
+    /// Fails without moving anything if the blob is stored again or one of
+    /// the original locations is taken.
+    pub async fn restore(&self, id: &str, blobs: &BlobStore) -> Result<TrashEntry, String> {
+        let entry = self.get(id).await?;
+        if let Some(blob) = &entry.blob {
+            if blobs.entry(&blob.hash).await.is_some() {
+                return Err(format!("{} is already stored", blob.hash));
+            }
+        }
+        for item in &entry.items {
+            if tokio::fs::metadata(&item.original_path).await.is_ok() {
+                return Err(format!("{} already exists", item.original_path));
+            }
+        }
+        let entry_dir = self.entry_dir(id);
+        if let Some(blob) = &entry.blob {
+            blobs.restore(blob, &entry_dir.join(BLOB_FILE)).await?;
+            blobs.add_alias(&entry.file_hash, &blob.hash).await?;
+        }
+        for item in &entry.items {
+            let original = PathBuf::from(&item.original_path);
+            if let Some(parent) = original.parent() {
+                tokio::fs::create_dir_all(parent)
+                    .await
+                    .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+            }
+            move_file(&entry_dir.join(&item.stored_name), &original).await?;
+        }
+        tokio::fs::remove_dir_all(&entry_dir)
+            .await
+            .map_err(|e| format!("Failed to remove trash entry: {}", e))?;
+        info!(
+            "Restored {} ({}) from trash",
+            entry.file_name, entry.file_hash
+        );
+        Ok(entry)
+    }
+
+    /// Permanently delete `id`, returning the bytes freed
+    pub async fn delete(&self, id: &str) -> Result<u64, String> {
+        let entry = self.get(id).await?;
+        let entry_dir = self.entry_dir(id);
+        if self.config().secure_erase {
+            let blob = entry
+                .blob
+                .as_ref()
+                .filter(|blob| blob.linked.is_none())
+                .map(|_| entry_dir.join(BLOB_FILE));
+            let items = entry.items.iter().map(|i| entry_dir.join(&i.stored_name));
+            for path in blob.into_iter().chain(items) {
+                if let Err(e) = overwrite_with_zeros(&path).await {
+                    warn!("{}", e);
+                }
+            }
+        }
+        tokio::fs::remove_dir_all(&entry_dir)
+            .await
+            .map_err(|e| format!("Failed to delete trash entry: {}", e))?;
+        Ok(entry.size)
+    }
+
+    /// Permanently delete every entry, or only those expired at `now`.
+    /// Returns the number of entries deleted.
+    pub async fn purge(&self, expired_at: Option<u64>) -> Result<usize, String> {
+        let mut deleted = 0;
+        for entry in self.list().await? {
+            if expired_at.is_some_and(|now| entry.expires_at > now) {
+                continue;
+            }
+            match self.delete(&entry.id).await {
+                Ok(_) => deleted += 1,
+                Err(e) => warn!("Failed to purge trash entry {}: {}", entry.id, e),
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Permanently delete the entries past their retention period
+    pub async fn purge_expired(&self) -> Result<usize, String> {
+        self.purge(Some(self.clock.now_secs())).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persisted::TempConfig;
+    use crate::runtime_env::ManualClock;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn trash_restore_and_purge() {
+        let config = TempConfig::new();
+        let root = config.dir();
+        let files = root.join("files");
+        let blobs = BlobStore::open(&files).await.unwrap();
+        blobs.put("abc123", b"payload").await.unwrap();
+        blobs.set_pinned("abc123", true).await.unwrap();
+        blobs.add_alias("root1", "abc123").await.unwrap();
+        let meta = files.join("abc123.meta");
+        tokio::fs::write(&meta, b"{}").await.unwrap();
+
+        let clock = Arc::new(ManualClock::new(Duration::from_secs(1_000)));
+        let trash =
+            FileTrash::with_persistence(root.join("trash"), &config.path).with_clock(clock.clone());
+        let paths = vec![meta.clone(), files.join("abc123.encmeta")];
+        let entry = trash
+            .move_in("root1", "a.bin", Some((&blobs, "abc123")), &paths, None)
+            .await
+            .unwrap();
+        assert_eq!(entry.items.len(), 1);
+        assert_eq!(entry.size, 9);
+        assert!(!blobs.contains("abc123").await);
+        assert_eq!(blobs.resolve_alias("root1").await, None);
+        assert_eq!(trash.list().await.unwrap().len(), 1);
+
+        // A new file at the original location blocks the restore
+        tokio::fs::write(&meta, b"new").await.unwrap();
+        assert!(trash.restore(&entry.id, &blobs).await.is_err());
+        assert!(!blobs.contains("abc123").await);
+        tokio::fs::remove_file(&meta).await.unwrap();
+        trash.restore(&entry.id, &blobs).await.unwrap();
+        assert_eq!(
+            blobs.get("abc123").await.unwrap().as_deref(),
+            Some(&b"payload"[..])
+        );
+        assert!(blobs.entry("abc123").await.unwrap().pinned);
+        assert_eq!(
+            blobs.resolve_alias("root1").await.as_deref(),
+            Some("abc123")
+        );
+        assert!(meta.exists());
+        assert!(trash.list().await.unwrap().is_empty());
+
+        let settings = TrashConfig {
+            retention_days: 1,
+            secure_erase: true,
+        };
+        trash.set_config(settings.clone()).unwrap();
+        assert_eq!(
+            FileTrash::with_persistence(root.join("trash"), &config.path).config(),
+            settings
+        );
+        let entry = trash
+            .move_in("root1", "a.bin", Some((&blobs, "abc123")), &paths, None)
+            .await
+            .unwrap();
+        assert_eq!(entry.trashed_at, 1_000);
+        assert_eq!(trash.purge_expired().await.unwrap(), 0);
+        clock.advance(Duration::from_secs(24 * 60 * 60));
+        assert_eq!(trash.purge_expired().await.unwrap(), 1);
+        assert!(!root.join("trash").join(&entry.id).exists());
+
+        assert!(trash.get("../files").await.is_err());
+    }
+}
@@ -0,0 +1,160 @@
+// Offline IP to country lookup
+//
+// Relays announce a region of their own (see `relay_selector`), but that is
+// only what the relay says about itself. Where its addresses actually are is
+// looked up here in a country database kept in the app data directory as
+// `geoip-country.csv`, so no address ever leaves the node to be located.
+//
+// The file is the free "IP to Country Lite" CSV from DB-IP or one in the same
+// shape: one range per line, `first address,last address,country code`, IPv4
+// and IPv6 mixed, fields optionally quoted. Lines that do not parse and
+// ranges without a real country ("ZZ", "-") are skipped. Without the file
+// relays are only placed by the region they announce.
+
+use libp2p::multiaddr::Protocol;
+use libp2p::Multiaddr;
+use std::net::IpAddr;
+use std::path::Path;
+
+pub const GEOIP_FILE: &str = "geoip-country.csv";
+
+/// Addresses `first..=last` are in `country`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CountryRange<T> {
+    first: T,
+    last: T,
+    country: String,
+}
+
+/// Country ranges, sorted by first address
+#[derive(Debug, Default, Clone)]
+pub struct GeoIpDb {
+    v4: Vec<CountryRange<u32>>,
+    v6: Vec<CountryRange<u128>>,
+}
+
+fn unquote(field: &str) -> &str {
+    field.trim().trim_matches('"')
+}
+
+/// Two letter country code, upper case
+fn country_code(field: &str) -> Option<String> {
+    let code = unquote(field);
+    let valid = code.len() == 2
+        && code.chars().all(|c| c.is_ascii_alphabetic())
+        && !code.eq_ignore_ascii_case("ZZ");
+    valid.then(|| code.to_ascii_uppercase())
+}
+
+fn find<T: Ord + Copy>(ranges: &[CountryRange<T>], ip: T) -> Option<&str> {
+    let after = ranges.partition_point(|range| range.first <= ip);
+    let range = ranges[..after].last()?;
+    (ip <= range.last).then_some(range.country.as_str())
+}
+
+impl GeoIpDb {
+    /// Database read from the CSV file at `path`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, String> {
+        let raw = std::fs::read_to_string(path.as_ref())
+            .map_err(|e| format!("Failed to read {}: {}", path.as_ref().display(), e))?;
+        Ok(Self::from_csv(&raw))
+    }
+
+    pub fn from_csv(raw: &str) -> Self {
+        let mut db = Self::default();
+        for line in raw.lines() {
+            let mut fields = line.split(',');
+            let (Some(first), Some(last), Some(country)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+            let Some(country) = country_code(country) else {
+                continue;
+            };
+            match (
+                unquote(first).parse::<IpAddr>(),
+                unquote(last).parse::<IpAddr>(),
+            ) {
+                (Ok(IpAddr::V4(first)), Ok(IpAddr::V4(last))) if first <= last => {
+                    db.v4.push(CountryRange {
+                        first: first.into(),
+                        last: last.into(),
+                        country,
+                    })
+                }
+                (Ok(IpAddr::V6(first)), Ok(IpAddr::V6(last))) if first <= last => {
+                    db.v6.push(CountryRange {
+                        first: first.into(),
+                        last: last.into(),
+                        country,
+                    })
+                }
+                _ => {}
+            }
+        }
+        db.v4.sort_by_key(|range| range.first);
+        db.v6.sort_by_key(|range| range.first);
+        db
+    }
+
+    /// Number of ranges loaded
+    pub fn len(&self) -> usize {
+        self.v4.len() + self.v6.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Country code of `ip`, if it is in a known range
+    pub fn lookup(&self, ip: IpAddr) -> Option<&str> {
+        match ip {
+            IpAddr::V4(ip) => find(&self.v4, u32::from(ip)),
+            IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                Some(ip) => find(&self.v4, u32::from(ip)),
+                None => find(&self.v6, u128::from(ip)),
+            },
+        }
+    }
+
+    /// Country code of the IP address in a multiaddr. DNS names are not
+    /// resolved, and circuit addresses are placed where their relay is.
+    pub fn lookup_multiaddr(&self, address: &str) -> Option<String> {
+        let address: Multiaddr = address.parse().ok()?;
+        address.iter().find_map(|protocol| match protocol {
+            Protocol::Ip4(ip) => self.lookup(ip.into()).map(str::to_string),
+            Protocol::Ip6(ip) => self.lookup(ip.into()).map(str::to_string),
+            _ => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &str = "\
+\"1.0.0.0\",\"1.0.0.255\",\"AU\"
+1.0.1.0,1.0.3.255,cn
+10.0.0.0,10.255.255.255,ZZ
+not,a,range
+2a00:1450::,2a00:1450:ffff:ffff:ffff:ffff:ffff:ffff,IE
+";
+
+    #[test]
+    fn addresses_are_placed_in_their_range() {
+        let db = GeoIpDb::from_csv(CSV);
+        assert_eq!(db.len(), 3);
+        assert_eq!(db.lookup("1.0.0.7".parse().unwrap()), Some("AU"));
+        assert_eq!(db.lookup("1.0.2.1".parse().unwrap()), Some("CN"));
+        assert_eq!(db.lookup("1.0.4.1".parse().unwrap()), None);
+        assert_eq!(db.lookup("10.1.2.3".parse().unwrap()), None);
+        assert_eq!(db.lookup("::ffff:1.0.0.1".parse().unwrap()), Some("AU"));
+        assert_eq!(
+            db.lookup_multiaddr("/ip6/2a00:1450::1/udp/4001/quic-v1"),
+            Some("IE".to_string())
+        );
+        assert_eq!(db.lookup_multiaddr("/dns4/relay.example/tcp/4001"), None);
+    }
+}
@@ -8,6 +8,10 @@ use crate::bittorrent_handler;
 use crate::file_transfer::FileTransferService;
 use crate::http_server;
 use crate::keystore::Keystore;
+use chiral_network::activity_feed::{self, ActivityFeed};
+use chiral_network::session_summary::{SessionTotals, SessionTracker};
+use chiral_network::settings::SettingsStore;
+use chiral_network::transfer_log::{self, TransferLog};
 use crate::webrtc_service::{set_webrtc_service, WebRTCService};
 use crate::{bandwidth::BandwidthController, manager::ChunkManager};
 use clap::Parser;
@@ -145,6 +149,14 @@ pub struct CliArgs {
     /// Resume a paused restartable download by ID
     #[arg(long)]
     pub resume_download: Option<String>,
+
+    /// Enable profiling spans and write a flamegraph-compatible folded stack file
+    #[arg(long)]
+    pub profile: bool,
+
+    /// Output path for the folded stacks written in --profile mode
+    #[arg(long, default_value = chiral_network::profiling::DEFAULT_PROFILE_OUTPUT)]
+    pub profile_output: String,
 }
 
 pub fn create_dht_config_from_args(args: &CliArgs) -> DhtConfig<'static> {
@@ -183,12 +195,54 @@ pub async fn run_headless(mut args: CliArgs) -> Result<(), Box<dyn std::error::E
         .try_init();
 
     info!("Starting Chiral Network in headless mode");
+    if let Some(profiler) = chiral_network::profiling::active() {
+        info!("Profiling enabled, writing to {}", profiler.output_path().display());
+        profiler.spawn_background_tasks();
+    }
     info!("CLI args: {:#?}", args);
 
     let download_restart_service = Arc::new(DownloadRestartService::new(None));
 
-    // Add default bootstrap nodes if no custom ones specified
+    let transfer_event_log = match TransferLog::open_default() {
+        Ok(log) => {
+            let log = Arc::new(log);
+            tokio::spawn(transfer_log::run_recorder(log.clone()));
+            Some(log)
+        }
+        Err(e) => {
+            warn!("Transfer event log disabled: {}", e);
+            None
+        }
+    };
+    match ActivityFeed::open_default() {
+        Ok(feed) => {
+            tokio::spawn(activity_feed::run_recorder(Arc::new(feed)));
+        }
+        Err(e) => warn!("Activity feed disabled: {}", e),
+    }
+
+    let session = Arc::new(
+        directories::ProjectDirs::from("com", "chiral-network", "chiral-network")
+            .map(|dirs| {
+                SessionTracker::with_persistence(dirs.data_dir().join("session_history.json"))
+            })
+            .unwrap_or_default(),
+    );
+    session.clone().spawn_event_counter();
+    if let Some(dirs) = directories::ProjectDirs::from("com", "chiral-network", "chiral-network") {
+        chiral_network::demand_stats::global().load(dirs.data_dir().join("demand_stats.json"));
+    }
+    let bandwidth = Arc::new(BandwidthController::new());
+
+    // Bootstrap nodes: --bootstrap, then the saved custom nodes, then the defaults
     let mut bootstrap_nodes = args.bootstrap.clone();
+    if bootstrap_nodes.is_empty() {
+        let saved = SettingsStore::load_default().get().custom_bootstrap_nodes;
+        if !saved.is_empty() {
+            info!("Using custom bootstrap nodes from settings: {:?}", saved);
+            bootstrap_nodes = saved;
+        }
+    }
     let provided_bootstrap = !bootstrap_nodes.is_empty();
     if !provided_bootstrap {
         // Use reliable IP-based bootstrap nodes so fresh nodes can join the mesh
@@ -237,9 +291,17 @@ pub async fn run_headless(mut args: CliArgs) -> Result<(), Box<dyn std::error::E
         || args.show_downloads;
 
     let file_transfer_service = if enable_p2p {
-        Some(Arc::new(FileTransferService::new().await.map_err(|e| {
-            format!("Failed to start file transfer service: {}", e)
-        })?))
+        let service = FileTransferService::new()
+            .await
+            .map_err(|e| format!("Failed to start file transfer service: {}", e))?;
+        let settings = SettingsStore::load_default().get();
+        service
+            .set_attempt_retention(settings.download_attempt_retention)
+            .await;
+        service
+            .set_max_concurrent_transfers(settings.max_concurrent_transfers)
+            .await?;
+        Some(Arc::new(service))
     } else {
         None
     };
@@ -258,8 +320,7 @@ pub async fn run_headless(mut args: CliArgs) -> Result<(), Box<dyn std::error::E
             return Ok(());
         };
         let keystore = Arc::new(Mutex::new(Keystore::load().unwrap_or_default()));
-        let bandwidth = Arc::new(BandwidthController::new());
-        match WebRTCService::new_headless(ft.clone(), keystore, bandwidth, None).await {
+        match WebRTCService::new_headless(ft.clone(), keystore, bandwidth.clone(), None).await {
             Ok(svc) => {
                 let arc = Arc::new(svc);
                 set_webrtc_service(arc.clone()).await;
@@ -436,6 +497,10 @@ pub async fn run_headless(mut args: CliArgs) -> Result<(), Box<dyn std::error::E
 
     let http_server_state = Arc::new(http_server::HttpServerState::new(storage_dir.clone()));
     http_server_state.set_dht(dht_arc.clone()).await;
+    match chiral_network::blob_store::BlobStore::open(storage_dir.clone()).await {
+        Ok(blobs) => http_server_state.set_blob_store(Arc::new(blobs)).await,
+        Err(e) => error!("Failed to open HTTP file storage: {}", e),
+    }
 
     // Start HTTP file server on a free port in 8080..=8090 and keep shutdown sender alive.
     let mut http_base_url: Option<String> = None;
@@ -536,6 +601,7 @@ pub async fn run_headless(mut args: CliArgs) -> Result<(), Box<dyn std::error::E
                         )))
                     },
                     bittorrent_handler: bt_handler,
+                    transfer_log: transfer_event_log.clone(),
                 };
                 match start_headless_e2e_api_server(state, port).await {
                     Ok((bound, shutdown_tx)) => {
@@ -630,6 +696,30 @@ pub async fn run_headless(mut args: CliArgs) -> Result<(), Box<dyn std::error::E
     signal::ctrl_c().await?;
 
     info!("Shutting down...");
+    let (bytes_served, bytes_downloaded) = bandwidth.lifetime_totals().await;
+    let network = dht_arc.session_network_totals();
+    match session.finish(SessionTotals {
+        bytes_served,
+        bytes_downloaded,
+        unique_peers: network.unique_peers,
+        relay_circuit_secs: network.relay_circuit_secs,
+    }) {
+        Ok(summary) => info!(
+            "Session summary: {}s, {} bytes served, {} peers, {} downloads completed, {} failed",
+            summary.duration_secs,
+            summary.bytes_served,
+            summary.unique_peers,
+            summary.downloads_completed,
+            summary.downloads_failed
+        ),
+        Err(e) => warn!("{}", e),
+    }
+    if let Err(e) = chiral_network::demand_stats::global().save() {
+        warn!("{}", e);
+    }
+    if let Some(profiler) = chiral_network::profiling::active() {
+        profiler.finish();
+    }
     Ok(())
 }
 
@@ -0,0 +1,145 @@
+// Fixed-bucket histograms for latency and size metrics
+//
+// Totals and means hide tail behaviour; a histogram with fixed upper bounds
+// keeps the distribution in constant memory so p90/p99 regressions show up.
+// Percentiles are estimated as the upper bound of the bucket holding the
+// requested rank (the observed maximum for the overflow bucket).
+
+use serde::Serialize;
+
+/// Attempt duration and time-to-first-byte buckets, in milliseconds
+pub const LATENCY_BOUNDS_MS: &[u64] = &[
+    10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 30_000, 60_000,
+];
+
+const KIB: u64 = 1024;
+const MIB: u64 = 1024 * KIB;
+const GIB: u64 = 1024 * MIB;
+
+/// File size buckets, in bytes
+pub const SIZE_BOUNDS_BYTES: &[u64] = &[
+    64 * KIB,
+    256 * KIB,
+    MIB,
+    4 * MIB,
+    16 * MIB,
+    64 * MIB,
+    256 * MIB,
+    GIB,
+    4 * GIB,
+];
+
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    bounds: &'static [u64],
+    /// One count per bound plus the overflow bucket
+    counts: Vec<u64>,
+    count: u64,
+    sum: u64,
+    max: u64,
+}
+
+/// One bucket: observations `<= upper_bound` and above the previous bound
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct HistogramBucket {
+    /// `None` for the overflow bucket
+    pub upper_bound: Option<u64>,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct HistogramSnapshot {
+    pub buckets: Vec<HistogramBucket>,
+    pub count: u64,
+    pub sum: u64,
+    pub max: u64,
+    pub p50: Option<u64>,
+    pub p90: Option<u64>,
+    pub p99: Option<u64>,
+}
+
+impl Histogram {
+    /// `bounds` must be sorted ascending
+    pub fn new(bounds: &'static [u64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            count: 0,
+            sum: 0,
+            max: 0,
+        }
+    }
+
+    pub fn record(&mut self, value: u64) {
+        let index = self.bounds.partition_point(|&bound| bound < value);
+        self.counts[index] += 1;
+        self.count += 1;
+        self.sum = self.sum.saturating_add(value);
+        self.max = self.max.max(value);
+    }
+
+    /// Estimated value at `quantile` (0.0 - 1.0); `None` when empty
+    pub fn percentile(&self, quantile: f64) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((quantile.clamp(0.0, 1.0) * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let bound = self.bounds.get(index).copied().unwrap_or(self.max);
+                return Some(bound.min(self.max));
+            }
+        }
+        Some(self.max)
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let buckets = self
+            .counts
+            .iter()
+            .enumerate()
+            .map(|(index, &count)| HistogramBucket {
+                upper_bound: self.bounds.get(index).copied(),
+                count,
+            })
+            .collect();
+        HistogramSnapshot {
+            buckets,
+            count: self.count,
+            sum: self.sum,
+            max: self.max,
+            p50: self.percentile(0.5),
+            p90: self.percentile(0.9),
+            p99: self.percentile(0.99),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn observations_land_in_bounded_buckets() {
+        let mut histogram = Histogram::new(&[10, 100, 1_000]);
+        assert_eq!(histogram.percentile(0.5), None);
+
+        for value in [1, 10, 11, 50, 90, 99, 100, 500, 5_000, 20] {
+            histogram.record(value);
+        }
+
+        let snapshot = histogram.snapshot();
+        let counts: Vec<u64> = snapshot.buckets.iter().map(|b| b.count).collect();
+        assert_eq!(counts, vec![2, 6, 1, 1]);
+        assert_eq!(snapshot.buckets[3].upper_bound, None);
+        assert_eq!(snapshot.count, 10);
+        assert_eq!(snapshot.sum, 5_881);
+        assert_eq!(snapshot.p50, Some(100));
+        assert_eq!(snapshot.p90, Some(1_000));
+        assert_eq!(snapshot.p99, Some(5_000));
+    }
+}
@@ -313,7 +313,9 @@ impl HttpDownloadClient {
         )
         .await;
 
-        self.assemble_file(&chunks, output_path).await?;
+        // Encrypted files are fetched as stored, so only plaintext can be checked against the id
+        let published_id = (!metadata.encrypted).then_some(file_hash);
+        self.assemble_file(&chunks, output_path, published_id).await?;
 
         // Final status
         self.send_progress(
@@ -528,7 +530,8 @@ impl HttpDownloadClient {
         )
         .await;
 
-        if let Err(e) = self.assemble_file(&chunks, output_path).await {
+        let published_id = (!metadata.encrypted).then_some(file_hash);
+        if let Err(e) = self.assemble_file(&chunks, output_path, published_id).await {
             // Emit failed event for assembly failure
             if let Some(ref bus) = event_bus {
                 bus.emit_failed(TransferFailedEvent {
@@ -1175,11 +1178,13 @@ impl HttpDownloadClient {
 
     /// Assemble chunks into final file
     ///
-    /// Writes chunks sequentially to the output file
+    /// Writes chunks sequentially to the output file, which must match
+    /// `published_id` when one is given
     async fn assemble_file(
         &self,
         chunks: &[Vec<u8>],
         output_path: &Path,
+        published_id: Option<&str>,
     ) -> Result<(), String> {
         // Ensure parent directory exists
         if let Some(parent) = output_path.parent() {
@@ -1190,29 +1195,45 @@ impl HttpDownloadClient {
 
         // Get unique output path to avoid overwriting existing files
         let unique_output_path = get_unique_filepath(output_path);
+        let total_bytes: usize = chunks.iter().map(|c| c.len()).sum();
 
-        tracing::info!("Creating output file: {}", unique_output_path.display());
+        // Write into the staging area; the file only appears at the output path once verified
+        let staged_name = unique_output_path
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let staged = crate::download_staging::global().stage(&staged_name, total_bytes as u64)?;
 
-        let mut file = File::create(&unique_output_path)
+        tracing::info!("Creating staged file: {}", staged.path().display());
+
+        let mut file = File::create(staged.path())
             .await
-            .map_err(|e| format!("Failed to create output file at {}: {}", unique_output_path.display(), e))?;
+            .map_err(|e| format!("Failed to create staged file at {}: {}", staged.path().display(), e))?;
 
         tracing::info!("Writing {} chunks to file...", chunks.len());
 
         for (index, chunk) in chunks.iter().enumerate() {
             file.write_all(chunk)
                 .await
-                .map_err(|e| format!("Failed to write chunk {} to {}: {}", index, unique_output_path.display(), e))?;
+                .map_err(|e| format!("Failed to write chunk {} to {}: {}", index, staged.path().display(), e))?;
         }
 
         file.flush()
             .await
-            .map_err(|e| format!("Failed to flush file {}: {}", unique_output_path.display(), e))?;
+            .map_err(|e| format!("Failed to flush file {}: {}", staged.path().display(), e))?;
+        drop(file);
+
+        let verification = crate::download_staging::Verification {
+            expected_size: Some(total_bytes as u64),
+            published_id: published_id.map(str::to_string),
+            ..Default::default()
+        };
+        staged.commit(&unique_output_path, &verification).await?;
 
         tracing::info!("Successfully assembled file: {} ({} chunks, {} bytes)", 
             unique_output_path.display(), 
             chunks.len(),
-            chunks.iter().map(|c| c.len()).sum::<usize>()
+            total_bytes
         );
 
         Ok(())
@@ -1,10 +1,13 @@
 use axum::{
+    body::Bytes,
     extract::{Path, State},
     http::StatusCode,
     response::{IntoResponse, Response},
-    routing::get,
+    routing::{get, post, put},
     Json, Router,
 };
+use chiral_network::blob_store::BlobStore;
+use chiral_network::push_upload::{self, OpenPushRequest, PushError, PushReceiver};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::net::SocketAddr;
@@ -22,9 +25,13 @@ use crate::dht::DhtService;
 /// - GET /health → Health check
 /// - GET /files/{file_hash} → Serve file (supports Range header for partial downloads)
 /// - GET /files/{file_hash}/metadata → Returns file metadata (name, size, encrypted status)
+/// - GET /previews/{file_hash}/{asset} → Thumbnail, preview clip or text excerpt for a file
+/// - POST /uploads, GET /uploads/{file_hash}, PUT /uploads/{file_hash}/chunks/{index},
+///   POST /uploads/{file_hash}/complete → Resumable pushes from other nodes (see `push_upload`)
 ///
 /// This approach:
-/// - Stores whole files (not pre-chunked)
+/// - Stores whole files (not pre-chunked) in the blob store, so sealed,
+///   deduplicated and linked files are served as plain bytes
 /// - Uses HTTP Range requests for chunking on-demand
 /// - Simpler than manifest-based chunking
 /// - Aligns with professor feedback (PR #543)
@@ -51,6 +58,12 @@ pub struct HttpServerState {
     
     /// DHT service for recording provider-side metrics
     pub dht: Arc<Mutex<Option<Arc<DhtService>>>>,
+
+    /// Sessions for files other nodes push to us; partial files live beside storage_dir
+    pub push: Arc<PushReceiver>,
+
+    /// Blob store over storage_dir that files are read from and written to
+    pub blobs: Arc<Mutex<Option<Arc<BlobStore>>>>,
 }
 
 impl HttpServerState {
@@ -58,12 +71,45 @@ impl HttpServerState {
     ///
     /// The storage_dir should point to the FileTransferService storage directory
     pub fn new(storage_dir: PathBuf) -> Self {
+        let incoming_dir = storage_dir.with_file_name("incoming");
+        let push = match directories::ProjectDirs::from("com", "chiral-network", "chiral-network") {
+            Some(dirs) => PushReceiver::with_persistence(
+                incoming_dir,
+                dirs.config_dir().join("push_receiver.json"),
+            ),
+            None => PushReceiver::new(incoming_dir),
+        };
         Self {
             storage_dir,
             files: Arc::new(RwLock::new(HashMap::new())),
             dht: Arc::new(Mutex::new(None)),
+            push: Arc::new(push),
+            blobs: Arc::new(Mutex::new(None)),
         }
     }
+
+    /// Set the blob store files are served from and stored in
+    pub async fn set_blob_store(&self, blobs: Arc<BlobStore>) {
+        *self.blobs.lock().await = Some(blobs);
+    }
+
+    async fn blob_store(&self) -> Result<Arc<BlobStore>, String> {
+        self.blobs
+            .lock()
+            .await
+            .clone()
+            .ok_or_else(|| "File storage is not available".to_string())
+    }
+
+    /// Move the file at `source` into storage as `file_hash`
+    pub async fn store_file(
+        &self,
+        file_hash: &str,
+        source: &std::path::Path,
+    ) -> Result<(), String> {
+        self.blob_store().await?.put_file(file_hash, source).await?;
+        Ok(())
+    }
     
     /// Set DHT service for metrics tracking
     pub async fn set_dht(&self, dht: Arc<DhtService>) {
@@ -75,7 +121,7 @@ impl HttpServerState {
     /// Register a file for HTTP serving
     ///
     /// This should be called after a file is successfully uploaded and stored
-    /// in the storage directory. The file should exist at: storage_dir/{metadata.hash}
+    /// in the blob store under metadata.file_hash
     pub async fn register_file(&self, metadata: HttpFileMetadata) {
         let mut files = self.files.write().await;
         files.insert(metadata.hash.clone(), metadata.clone());
@@ -126,7 +172,10 @@ async fn serve_metadata(
     tracing::info!("📋 Currently registered files: {:?}", files.keys().collect::<Vec<_>>());
     drop(files);
 
-    match state.get_file_metadata(&file_hash).await {
+    let metadata = state.get_file_metadata(&file_hash).await;
+    chiral_network::demand_stats::global().record(&file_hash, None, metadata.is_some());
+
+    match metadata {
         Some(metadata) => {
             tracing::info!("✅ Served metadata for {}: {} (file_hash: {})", 
                 file_hash, metadata.name, metadata.file_hash);
@@ -187,20 +236,23 @@ async fn serve_file(
         }
     };
 
-    // Build file path using the actual file_hash (SHA-256) used for storage
-    let file_path = state.storage_dir.join(&metadata.file_hash);
-
-    // Check if file exists on disk
-    if !file_path.exists() {
-        tracing::error!(
-            "File registered but not found on disk: {} at {:?}",
-            file_hash,
-            file_path
-        );
+    // Stored under the actual file_hash (SHA-256)
+    let blobs = match state.blob_store().await {
+        Ok(blobs) => blobs,
+        Err(e) => {
+            return (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(ErrorResponse { error: e }),
+            )
+                .into_response()
+        }
+    };
+    if !blobs.contains(&metadata.file_hash).await {
+        tracing::error!("File registered but not in storage: {}", file_hash);
         return (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
-                error: "File not found on disk".to_string(),
+                error: "File not found in storage".to_string(),
             }),
         )
             .into_response();
@@ -213,10 +265,10 @@ async fn serve_file(
 
     let response = if let Some(range_str) = range_header {
         // Serve partial content (Range request)
-        serve_file_range(&file_path, range_str, metadata.size).await
+        serve_file_range(&blobs, &metadata.file_hash, range_str, metadata.size).await
     } else {
         // Serve entire file
-        serve_entire_file(&file_path, metadata.size).await
+        serve_entire_file(&blobs, &metadata.file_hash).await
     };
     
     // Record provider-side metrics if downloader peer ID is available
@@ -237,15 +289,13 @@ async fn serve_file(
     response
 }
 
-/// Serve a byte range from a file (206 Partial Content)
+/// Serve a byte range from a stored file (206 Partial Content)
 async fn serve_file_range(
-    file_path: &PathBuf,
+    blobs: &BlobStore,
+    file_hash: &str,
     range_str: &str,
     file_size: u64,
 ) -> Response {
-    use tokio::fs::File;
-    use tokio::io::{AsyncReadExt, AsyncSeekExt};
-
     // Parse Range header: "bytes=start-end"
     let (start, end) = match parse_range_header(range_str, file_size) {
         Some(range) => range,
@@ -261,32 +311,15 @@ async fn serve_file_range(
         }
     };
 
-    // Open file
-    let mut file = match File::open(file_path).await {
-        Ok(f) => f,
-        Err(e) => {
-            tracing::error!("Failed to open file {:?}: {}", file_path, e);
-            return (StatusCode::INTERNAL_SERVER_ERROR).into_response();
-        }
-    };
-
-    // Seek to start position
-    if let Err(e) = file.seek(tokio::io::SeekFrom::Start(start)).await {
-        tracing::error!("Failed to seek in file: {}", e);
-        return (StatusCode::INTERNAL_SERVER_ERROR).into_response();
-    }
-
     // Read chunk
     let chunk_size = (end - start + 1) as usize;
-    let mut buffer = vec![0u8; chunk_size];
-
-    match file.read_exact(&mut buffer).await {
-        Ok(_) => {
+    match blobs.read_range(file_hash, start, chunk_size).await {
+        Ok(Some(buffer)) if buffer.len() == chunk_size => {
             tracing::debug!(
-                "Serving range {}-{} of {:?} ({} bytes)",
+                "Serving range {}-{} of {} ({} bytes)",
                 start,
                 end,
-                file_path,
+                file_hash,
                 chunk_size
             );
 
@@ -305,6 +338,10 @@ async fn serve_file_range(
             )
                 .into_response()
         }
+        Ok(_) => {
+            tracing::error!("Stored file {} is shorter than registered", file_hash);
+            (StatusCode::INTERNAL_SERVER_ERROR).into_response()
+        }
         Err(e) => {
             tracing::error!("Failed to read file: {}", e);
             (StatusCode::INTERNAL_SERVER_ERROR).into_response()
@@ -312,11 +349,11 @@ async fn serve_file_range(
     }
 }
 
-/// Serve the entire file (200 OK)
-async fn serve_entire_file(file_path: &PathBuf, file_size: u64) -> Response {
-    match tokio::fs::read(file_path).await {
-        Ok(data) => {
-            tracing::debug!("Serving entire file {:?} ({} bytes)", file_path, data.len());
+/// Serve the entire stored file (200 OK)
+async fn serve_entire_file(blobs: &BlobStore, file_hash: &str) -> Response {
+    match blobs.get(file_hash).await {
+        Ok(Some(data)) => {
+            tracing::debug!("Serving entire file {} ({} bytes)", file_hash, data.len());
 
             (
                 StatusCode::OK,
@@ -328,8 +365,9 @@ async fn serve_entire_file(file_path: &PathBuf, file_size: u64) -> Response {
             )
                 .into_response()
         }
+        Ok(None) => (StatusCode::NOT_FOUND).into_response(),
         Err(e) => {
-            tracing::error!("Failed to read file {:?}: {}", file_path, e);
+            tracing::error!("Failed to read file {}: {}", file_hash, e);
             (StatusCode::INTERNAL_SERVER_ERROR).into_response()
         }
     }
@@ -375,6 +413,150 @@ fn parse_range_header(range_str: &str, file_size: u64) -> Option<(u64, u64)> {
     Some((start, end))
 }
 
+/// GET /previews/{file_hash}/{asset}
+///
+/// Serves a generated thumbnail or preview clip, without requiring the file
+/// itself to be registered
+async fn serve_preview(Path((file_hash, asset)): Path<(String, String)>) -> Response {
+    let Some(path) = chiral_network::media_preview::PreviewStore::open_default()
+        .and_then(|store| store.asset_path(&file_hash, &asset))
+    else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: format!("No preview {} for {}", asset, file_hash),
+            }),
+        )
+            .into_response();
+    };
+    let content_type = match path.extension().and_then(|e| e.to_str()) {
+        Some("jpg") => "image/jpeg",
+        Some("mp4") => "video/mp4",
+        Some("mp3") => "audio/mpeg",
+        Some("txt") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    };
+    match tokio::fs::read(&path).await {
+        Ok(data) => (
+            StatusCode::OK,
+            [
+                ("Content-Type", content_type.to_string()),
+                ("Content-Length", data.len().to_string()),
+            ],
+            data,
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!("Failed to read preview {:?}: {}", path, e);
+            (StatusCode::INTERNAL_SERVER_ERROR).into_response()
+        }
+    }
+}
+
+/// Map a push error to its HTTP response
+fn push_error_response(error: PushError) -> Response {
+    let status = match &error {
+        PushError::Forbidden(_) => StatusCode::FORBIDDEN,
+        PushError::NotFound(_) => StatusCode::NOT_FOUND,
+        PushError::Invalid(_) => StatusCode::BAD_REQUEST,
+        PushError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (
+        status,
+        Json(ErrorResponse {
+            error: error.to_string(),
+        }),
+    )
+        .into_response()
+}
+
+fn push_token(headers: &axum::http::HeaderMap) -> Option<&str> {
+    headers
+        .get(push_upload::TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok())
+}
+
+/// POST /uploads
+///
+/// Opens a push session, or returns the existing one with the chunks already received
+async fn open_push(
+    State(state): State<Arc<HttpServerState>>,
+    headers: axum::http::HeaderMap,
+    Json(request): Json<OpenPushRequest>,
+) -> Response {
+    match state.push.open(push_token(&headers), request).await {
+        Ok(session) => (StatusCode::OK, Json(session)).into_response(),
+        Err(e) => push_error_response(e),
+    }
+}
+
+/// GET /uploads/{file_hash}
+async fn push_status(
+    Path(file_hash): Path<String>,
+    State(state): State<Arc<HttpServerState>>,
+    headers: axum::http::HeaderMap,
+) -> Response {
+    match state.push.status(push_token(&headers), &file_hash).await {
+        Ok(session) => (StatusCode::OK, Json(session)).into_response(),
+        Err(e) => push_error_response(e),
+    }
+}
+
+/// PUT /uploads/{file_hash}/chunks/{index}
+///
+/// Responds only after the chunk is synced to disk; the body lists every chunk held
+async fn put_push_chunk(
+    Path((file_hash, index)): Path<(String, u32)>,
+    State(state): State<Arc<HttpServerState>>,
+    headers: axum::http::HeaderMap,
+    body: Bytes,
+) -> Response {
+    let checksum = headers
+        .get(push_upload::CHUNK_HASH_HEADER)
+        .and_then(|v| v.to_str().ok());
+    match state
+        .push
+        .write_chunk(push_token(&headers), &file_hash, index, &body, checksum)
+        .await
+    {
+        Ok(session) => (StatusCode::OK, Json(session)).into_response(),
+        Err(e) => push_error_response(e),
+    }
+}
+
+/// POST /uploads/{file_hash}/complete
+///
+/// Verifies the whole file, moves it into storage and starts serving it
+async fn complete_push(
+    Path(file_hash): Path<String>,
+    State(state): State<Arc<HttpServerState>>,
+    headers: axum::http::HeaderMap,
+) -> Response {
+    let blobs = match state.blob_store().await {
+        Ok(blobs) => blobs,
+        Err(e) => return push_error_response(PushError::Internal(e)),
+    };
+    let session = match state
+        .push
+        .complete(push_token(&headers), &file_hash, &blobs)
+        .await
+    {
+        Ok(session) => session,
+        Err(e) => return push_error_response(e),
+    };
+    state
+        .register_file(HttpFileMetadata {
+            hash: session.file_hash.clone(),
+            file_hash: session.file_hash.clone(),
+            name: session.file_name.clone(),
+            size: session.file_size,
+            encrypted: false,
+        })
+        .await;
+    tracing::info!("📦 Stored pushed file {} ({})", session.file_name, session.file_hash);
+    (StatusCode::OK, Json(session)).into_response()
+}
+
 /// GET /health
 ///
 /// Health check endpoint
@@ -392,6 +574,11 @@ pub fn create_router(state: Arc<HttpServerState>) -> Router {
         .route("/health", get(health_check))
         .route("/files/:file_hash", get(serve_file))
         .route("/files/:file_hash/metadata", get(serve_metadata))
+        .route("/previews/:file_hash/:asset", get(serve_preview))
+        .route("/uploads", post(open_push))
+        .route("/uploads/:file_hash", get(push_status))
+        .route("/uploads/:file_hash/chunks/:index", put(put_push_chunk))
+        .route("/uploads/:file_hash/complete", post(complete_push))
         .layer(
             CorsLayer::new()
                 .allow_origin(Any)
@@ -359,6 +359,18 @@ impl Keystore {
     }
 }
 
+/// Key that encrypts the local blob store at rest, derived from an account's
+/// private key so it never has to be stored
+pub fn derive_storage_key(private_key: &str) -> Result<[u8; 32], String> {
+    let private_key_bytes = hex::decode(private_key.trim_start_matches("0x"))
+        .map_err(|e| format!("Invalid private key: {}", e))?;
+    let hk = hkdf::Hkdf::<sha2::Sha256>::new(Some(b"chiral-blob-store"), &private_key_bytes);
+    let mut key = [0u8; 32];
+    hk.expand(b"encryption-at-rest-v1", &mut key)
+        .map_err(|e| format!("HKDF expansion failed: {}", e))?;
+    Ok(key)
+}
+
 fn derive_key(password: &str, salt: &[u8]) -> Result<[u8; 32], String> {
     let mut key = [0u8; 32];
     // Increased iterations from 4096 to 100000 for better security
@@ -2,12 +2,36 @@
 pub mod protocols;
 pub mod analytics;
 pub mod bandwidth;
+pub mod bandwidth_history;
+pub mod histogram;
 pub mod config; 
 pub mod control_plane;
 pub mod multi_source_download;
 pub mod download_restart;
 pub mod p2p_download_recovery;
 pub mod transfer_events;
+pub mod error_codes;
+pub mod event_bus;
+pub mod transfer_log;
+pub mod alerts;
+pub mod debug_bundle;
+pub mod telemetry;
+pub mod session_summary;
+pub mod slow_transfer;
+pub mod stall_watchdog;
+pub mod demand_stats;
+pub mod shared_file_watcher;
+pub mod media_preview;
+pub mod content_info;
+pub mod local_library;
+pub mod library_dedup;
+pub mod notifications;
+pub mod subscriptions;
+pub mod activity_feed;
+pub mod reannounce;
+pub mod batch_upload;
+pub mod directory_manifest;
+pub mod file_trash;
 
 // Connection retry and resilience framework
 pub mod connection_retry;
@@ -23,16 +47,27 @@ pub mod http_download;
 pub mod bittorrent_handler;
 pub mod chiral_bittorrent_extension;
 pub mod download_paths;
+pub mod download_staging;
+pub mod push_upload;
+pub mod checksum_verify;
 
 // Required modules for multi_source_download
 pub mod dht;
 pub mod file_transfer;
+pub mod blob_store;
+pub mod chunk_store;
 pub mod ftp_downloader;
 pub mod ftp_server;
 pub mod peer_selection;
 pub mod peer_cache;
+pub mod trusted_peers;
 pub mod webrtc_service;
 
+// Deterministic in-process multi-node simulation for integration tests
+pub mod simulation;
+// Injectable clock, randomness and filesystem for deterministic tests
+pub mod runtime_env;
+
 // Required modules for encryption and keystore functionality
 pub mod encryption;
 pub mod keystore;
@@ -48,11 +83,29 @@ pub mod proxy_latency;
 pub mod stream_auth;
 // Reputation system
 pub mod reputation;
+// Known relays, kept across restarts
+pub mod relay_registry;
+// Periodic health probes of known relays
+pub mod relay_prober;
+// Strategies for picking relays among the known ones
+pub mod relay_selector;
+// Offline IP to country lookup for relay addresses
+pub mod geoip;
 // Payment checkpoint module
 pub mod payment_checkpoint;
 
 // Logger module for file-based logging
 pub mod logger;
+pub mod log_filter;
+
+// Backend-owned settings (settings.json)
+pub mod settings;
+// JSON files components keep their settings and state in
+pub mod persisted;
+pub mod device_sync;
+
+// Built-in profiling mode (--profile)
+pub mod profiling;
 
 // Ethereum/Geth integration
 pub mod ethereum;
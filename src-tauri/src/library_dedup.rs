@@ -0,0 +1,397 @@
+// Duplicate detection in the local file store
+//
+// Stored files are keyed by the hash they were published under, and the same
+// bytes can end up in the store more than once: uploaded under another name
+// through a protocol that keys by a different hash, or re-encrypted copies
+// decrypted back to the same content. Files are fingerprinted by their full
+// SHA-256 and by the SHA-256 of every fixed-size chunk, which finds
+//
+// - identical files: same content hash under different store keys. Merging
+//   moves the copies into the blob store's chunk store, where their chunks
+//   are shared and refcounted, so every key keeps serving while the bytes
+//   are stored once. Hard links would make erasing or overwriting one key
+//   destroy the others.
+// - near-duplicates: files sharing a large fraction of their chunks, e.g. a
+//   file and an appended-to version of it. These are only reported; the
+//   shared bytes are an estimate of what a chunk-level store would save.
+
+use crate::blob_store::{BlobEntry, BlobStore};
+use crate::local_library;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use tracing::info;
+
+/// Chunk size used for near-duplicate detection
+pub const CHUNK_SIZE: usize = 256 * 1024;
+/// Share of the smaller file's chunks two files must have in common
+pub const DEFAULT_MIN_OVERLAP: f64 = 0.5;
+/// Chunks found in more files than this (e.g. runs of zeros) are ignored for
+/// near-duplicate matching
+const MAX_CHUNK_FANOUT: usize = 32;
+
+/// Content fingerprint of one stored file
+#[derive(Debug, Clone)]
+pub struct Fingerprint {
+    pub file_hash: String,
+    pub file_name: String,
+    pub size: u64,
+    pub content_hash: String,
+    pub chunks: Vec<[u8; 32]>,
+    /// (device, inode) on Unix; files sharing it are already one copy on disk
+    pub inode: Option<(u64, u64)>,
+    /// Kept as shared chunks in the blob store, so identical files that are
+    /// both kept this way are already stored once
+    pub shares_chunks: bool,
+}
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateFile {
+    pub file_hash: String,
+    pub file_name: String,
+    pub size: u64,
+}
+
+impl From<&Fingerprint> for DuplicateFile {
+    fn from(fp: &Fingerprint) -> Self {
+        Self {
+            file_hash: fp.file_hash.clone(),
+            file_name: fp.file_name.clone(),
+            size: fp.size,
+        }
+    }
+}
+
+/// Byte-identical files stored under different keys
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateGroup {
+    pub content_hash: String,
+    pub size: u64,
+    pub files: Vec<DuplicateFile>,
+    /// Freed by merging the group (copies that are not hard links already)
+    pub reclaimable_bytes: u64,
+}
+
+/// Two different files sharing many chunks
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NearDuplicate {
+    pub first: DuplicateFile,
+    pub second: DuplicateFile,
+    pub shared_chunks: usize,
+    /// Shared chunks relative to the smaller file's distinct chunks (0..=1)
+    pub overlap: f64,
+    pub shared_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateReport {
+    pub scanned_files: usize,
+    pub identical: Vec<DuplicateGroup>,
+    pub near_duplicates: Vec<NearDuplicate>,
+    /// Reclaimable by merging all identical groups
+    pub reclaimable_bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeResult {
+    pub kept: String,
+    /// Keys now sharing the chunks of `kept`
+    pub linked: Vec<String>,
+    pub bytes_reclaimed: u64,
+}
+
+#[cfg(unix)]
+fn inode_of(meta: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn inode_of(_meta: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Feed a stored blob to `f` one chunk at a time, through the blob store
+/// so sealed, deduplicated and linked blobs read like any other
+async fn read_chunks(
+    blobs: &BlobStore,
+    file_hash: &str,
+    mut f: impl FnMut(&[u8]),
+) -> Result<BlobEntry, String> {
+    let entry = blobs
+        .entry(file_hash)
+        .await
+        .ok_or_else(|| format!("File {} is not stored", file_hash))?;
+    let mut offset = 0;
+    while offset < entry.size {
+        let len = (entry.size - offset).min(CHUNK_SIZE as u64) as usize;
+        let data = blobs
+            .read_range(file_hash, offset, len)
+            .await?
+            .ok_or_else(|| format!("File {} is not stored", file_hash))?;
+        f(&data);
+        offset += len as u64;
+    }
+    Ok(entry)
+}
+
+/// Full-content and per-chunk SHA-256 of a stored blob
+pub async fn fingerprint(
+    blobs: &BlobStore,
+    file_hash: &str,
+    file_name: &str,
+) -> Result<Fingerprint, String> {
+    let mut whole = Sha256::new();
+    let mut chunks = Vec::new();
+    let entry = read_chunks(blobs, file_hash, |chunk| {
+        whole.update(chunk);
+        chunks.push(Sha256::digest(chunk).into());
+    })
+    .await?;
+    // Only a blob kept as a file of its own has an inode worth comparing
+    let path = match &entry.linked {
+        Some(linked) => Some(linked.path.clone()),
+        None if !entry.deduplicated => Some(blobs.path(file_hash)),
+        None => None,
+    };
+    let inode = match path {
+        Some(path) => tokio::fs::metadata(&path)
+            .await
+            .ok()
+            .and_then(|meta| inode_of(&meta)),
+        None => None,
+    };
+    Ok(Fingerprint {
+        file_hash: file_hash.to_string(),
+        file_name: file_name.to_string(),
+        size: entry.size,
+        content_hash: hex::encode(whole.finalize()),
+        chunks,
+        inode,
+        shares_chunks: entry.deduplicated,
+    })
+}
+
+/// Group identical files and pair up near-duplicates among `fingerprints`
+pub fn find_duplicates(fingerprints: &[Fingerprint], min_overlap: f64) -> DuplicateReport {
+    let mut by_content: HashMap<&str, Vec<&Fingerprint>> = HashMap::new();
+    for fp in fingerprints {
+        by_content
+            .entry(fp.content_hash.as_str())
+            .or_default()
+            .push(fp);
+    }
+
+    let mut identical: Vec<DuplicateGroup> = by_content
+        .iter()
+        .filter(|(_, files)| files.len() > 1)
+        .map(|(content_hash, files)| {
+            let mut copies: Vec<Option<(u64, u64)>> = Vec::new();
+            let mut chunked = false;
+            for fp in files {
+                if fp.shares_chunks {
+                    if !chunked {
+                        chunked = true;
+                        copies.push(None);
+                    }
+                } else if fp.inode.is_none() || !copies.contains(&fp.inode) {
+                    copies.push(fp.inode);
+                }
+            }
+            let size = files[0].size;
+            let mut files: Vec<DuplicateFile> = files.iter().map(|fp| (*fp).into()).collect();
+            files.sort_by(|a, b| a.file_hash.cmp(&b.file_hash));
+            DuplicateGroup {
+                content_hash: content_hash.to_string(),
+                size,
+                files,
+                reclaimable_bytes: size * (copies.len() as u64 - 1),
+            }
+        })
+        .collect();
+    identical.sort_by(|a, b| b.reclaimable_bytes.cmp(&a.reclaimable_bytes));
+
+    // One representative per content hash; identical files are reported above
+    let mut distinct: Vec<&Fingerprint> = by_content.values().map(|files| files[0]).collect();
+    distinct.sort_by(|a, b| a.file_hash.cmp(&b.file_hash));
+    let chunk_sets: Vec<std::collections::HashSet<[u8; 32]>> = distinct
+        .iter()
+        .map(|fp| fp.chunks.iter().copied().collect())
+        .collect();
+
+    let mut holders: HashMap<[u8; 32], Vec<usize>> = HashMap::new();
+    for (i, set) in chunk_sets.iter().enumerate() {
+        for chunk in set {
+            holders.entry(*chunk).or_default().push(i);
+        }
+    }
+    let mut shared: HashMap<(usize, usize), usize> = HashMap::new();
+    for files in holders.values() {
+        if files.len() < 2 || files.len() > MAX_CHUNK_FANOUT {
+            continue;
+        }
+        for (n, &a) in files.iter().enumerate() {
+            for &b in &files[n + 1..] {
+                *shared.entry((a, b)).or_default() += 1;
+            }
+        }
+    }
+
+    let mut near_duplicates: Vec<NearDuplicate> = shared
+        .into_iter()
+        .filter_map(|((a, b), count)| {
+            let smaller = chunk_sets[a].len().min(chunk_sets[b].len()).max(1);
+            let overlap = count as f64 / smaller as f64;
+            (overlap >= min_overlap).then(|| NearDuplicate {
+                first: distinct[a].into(),
+                second: distinct[b].into(),
+                shared_chunks: count,
+                overlap,
+                shared_bytes: (count * CHUNK_SIZE) as u64,
+            })
+        })
+        .collect();
+    near_duplicates.sort_by(|a, b| b.shared_bytes.cmp(&a.shared_bytes));
+
+    DuplicateReport {
+        scanned_files: fingerprints.len(),
+        reclaimable_bytes: identical.iter().map(|g| g.reclaimable_bytes).sum(),
+        identical,
+        near_duplicates,
+    }
+}
+
+/// Fingerprint every stored file that has a `.meta` sidecar and report
+/// duplicates
+pub async fn scan(blobs: &BlobStore, min_overlap: f64) -> Result<DuplicateReport, String> {
+    let entries = local_library::load_entries(blobs.root(), false).await?;
+    let mut fingerprints = Vec::with_capacity(entries.len());
+    for entry in &entries {
+        if let Ok(fp) = fingerprint(blobs, &entry.file_hash, &entry.file_name).await {
+            fingerprints.push(fp);
+        }
+    }
+    tokio::task::spawn_blocking(move || find_duplicates(&fingerprints, min_overlap))
+        .await
+        .map_err(|e| format!("Duplicate scan task failed: {}", e))
+}
+
+/// SHA-256 of a stored blob, read a chunk at a time
+async fn blob_content_hash(blobs: &BlobStore, file_hash: &str) -> Result<String, String> {
+    let mut whole = Sha256::new();
+    read_chunks(blobs, file_hash, |chunk| whole.update(chunk)).await?;
+    Ok(hex::encode(whole.finalize()))
+}
+
+/// Store `keep` and each of `duplicates` as shared chunks after checking the
+/// contents really are identical
+pub async fn merge_identical(
+    blobs: &BlobStore,
+    keep: &str,
+    duplicates: &[String],
+) -> Result<MergeResult, String> {
+    let kept = blob_content_hash(blobs, keep).await?;
+    let targets: Vec<&String> = duplicates.iter().filter(|hash| *hash != keep).collect();
+    for hash in &targets {
+        if blob_content_hash(blobs, hash).await? != kept {
+            return Err(format!("{} differs from {}", hash, keep));
+        }
+    }
+
+    blobs.share_chunks(keep).await?;
+    let mut result = MergeResult {
+        kept: keep.to_string(),
+        linked: Vec::new(),
+        bytes_reclaimed: 0,
+    };
+    for hash in targets {
+        if blobs.share_chunks(hash).await? {
+            result.bytes_reclaimed += blobs.entry(hash).await.map_or(0, |e| e.size);
+            result.linked.push(hash.clone());
+        }
+    }
+    info!(
+        "Merged {} duplicates into {}, {} bytes reclaimed",
+        result.linked.len(),
+        result.kept,
+        result.bytes_reclaimed
+    );
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    async fn store(blobs: &BlobStore, hash: &str, name: &str, data: &[u8]) {
+        blobs.put(hash, data).await.unwrap();
+        let meta = serde_json::json!({
+            "file_name": name,
+            "file_size": data.len(),
+            "uploaded_at": 0,
+        });
+        tokio::fs::write(
+            blobs.root().join(format!("{}.meta", hash)),
+            meta.to_string(),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn finds_and_merges_duplicates() {
+        let dir = tempdir().unwrap();
+        let base: Vec<u8> = (0..CHUNK_SIZE * 4).map(|i| (i % 251) as u8).collect();
+        let mut appended = base.clone();
+        appended.extend(vec![7u8; CHUNK_SIZE]);
+
+        let blobs = BlobStore::open(dir.path()).await.unwrap();
+        store(&blobs, "aaa", "report.pdf", &base).await;
+        store(&blobs, "bbb", "report (copy).pdf", &base).await;
+        store(&blobs, "ccc", "report-v2.pdf", &appended).await;
+        store(&blobs, "ddd", "other.bin", b"unrelated").await;
+
+        let report = scan(&blobs, DEFAULT_MIN_OVERLAP).await.unwrap();
+        assert_eq!(report.scanned_files, 4);
+        assert_eq!(report.identical.len(), 1);
+        let group = &report.identical[0];
+        let hashes: Vec<&str> = group.files.iter().map(|f| f.file_hash.as_str()).collect();
+        assert_eq!(hashes, vec!["aaa", "bbb"]);
+        assert_eq!(report.reclaimable_bytes, base.len() as u64);
+
+        assert_eq!(report.near_duplicates.len(), 1);
+        let near = &report.near_duplicates[0];
+        assert_eq!(near.shared_chunks, 4);
+        assert_eq!(near.overlap, 1.0);
+
+        assert!(merge_identical(&blobs, "aaa", &["ccc".into()])
+            .await
+            .is_err());
+        assert!(merge_identical(&blobs, "aaa", &["../x".into()])
+            .await
+            .is_err());
+        let merged = merge_identical(&blobs, "aaa", &["bbb".into()])
+            .await
+            .unwrap();
+        assert_eq!(merged.linked, vec!["bbb"]);
+        assert_eq!(merged.bytes_reclaimed, base.len() as u64);
+        let stats = blobs.dedup_stats().await;
+        assert_eq!(stats.logical_bytes, 2 * base.len() as u64);
+        assert!(stats.stored_bytes <= base.len() as u64);
+        // Merged copies are read from the chunk store and count as one
+        let report = scan(&blobs, DEFAULT_MIN_OVERLAP).await.unwrap();
+        assert_eq!(report.scanned_files, 4);
+        assert_eq!(report.identical[0].files.len(), 2);
+        assert_eq!(report.reclaimable_bytes, 0);
+
+        // Deleting one copy leaves the other intact
+        blobs.remove("aaa").await.unwrap();
+        assert_eq!(blobs.get("bbb").await.unwrap(), Some(base));
+    }
+}
@@ -0,0 +1,407 @@
+// Search over the locally stored file index
+//
+// Every file in the file transfer storage directory has a `<hash>.meta` JSON
+// sidecar (name, size, upload time, and optionally MIME type, extension, the
+// source file's creation time and tags).
+// Listing every (hash, name) pair and filtering in the frontend stops scaling
+// after a few hundred files, so queries are evaluated here and only the best
+// matches are returned.
+//
+// A query is a free-text string plus optional tag, size, type and extension
+// filters. Each
+// whitespace-separated term must match the file name (substring), a hash
+// prefix or a tag; better matches (exact name, full hash, name prefix) rank
+// higher. Ties are broken by upload time, newest first.
+
+use crate::content_info;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+use tracing::warn;
+
+pub const DEFAULT_LIMIT: usize = 50;
+pub const MAX_LIMIT: usize = 500;
+/// Bytes read from stored files whose sidecar predates MIME detection
+const SNIFF_SIZE: usize = 4096;
+
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryEntry {
+    pub file_hash: String,
+    pub file_name: String,
+    pub file_size: u64,
+    /// Unix timestamp (seconds)
+    pub uploaded_at: u64,
+    pub mime_type: Option<String>,
+    /// Lowercased, without the dot
+    pub extension: Option<String>,
+    /// When the source file was created, Unix seconds, if known
+    pub created_at: Option<u64>,
+    pub tags: Vec<String>,
+}
+
+impl LibraryEntry {
+    /// Entry from a `.meta` sidecar; None when required fields are missing
+    pub fn from_meta(file_hash: &str, meta: &Value) -> Option<Self> {
+        let file_name = meta.get("file_name")?.as_str()?.to_string();
+        Some(Self {
+            file_hash: file_hash.to_string(),
+            // Sidecars written before extensions were recorded
+            extension: meta
+                .get("extension")
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .or_else(|| content_info::extension(&file_name)),
+            file_name,
+            file_size: meta.get("file_size")?.as_u64()?,
+            uploaded_at: meta.get("uploaded_at").and_then(Value::as_u64).unwrap_or(0),
+            mime_type: meta
+                .get("mime_type")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            created_at: meta.get("created_at").and_then(Value::as_u64),
+            tags: meta
+                .get("tags")
+                .and_then(Value::as_array)
+                .map(|tags| {
+                    tags.iter()
+                        .filter_map(Value::as_str)
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LibraryQuery {
+    /// Name substrings, hash prefixes or tags, separated by whitespace
+    pub query: String,
+    /// Files must carry all of these tags
+    pub tags: Vec<String>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// "image", "audio/flac", ... as accepted by `content_info::matches_type_filter`
+    pub content_type: Option<String>,
+    /// "pdf" or ".pdf"
+    pub extension: Option<String>,
+    /// At most `MAX_LIMIT`; `DEFAULT_LIMIT` when unset
+    pub limit: Option<usize>,
+}
+
+impl LibraryQuery {
+    fn content_type(&self) -> Option<&str> {
+        self.content_type.as_deref().filter(|t| !t.trim().is_empty())
+    }
+
+    fn extension(&self) -> Option<String> {
+        let extension = self.extension.as_deref()?.trim().trim_start_matches('.');
+        (!extension.is_empty()).then(|| extension.to_ascii_lowercase())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LibraryMatch {
+    #[serde(flatten)]
+    pub entry: LibraryEntry,
+    pub score: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LibrarySearchResult {
+    pub matches: Vec<LibraryMatch>,
+    /// Number of matching files before the limit was applied
+    pub total: usize,
+}
+
+/// Lowercased, trimmed and de-duplicated tags, empty ones dropped
+pub fn normalize_tags(tags: &[String]) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim().to_lowercase();
+        if !tag.is_empty() && !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    normalized
+}
+
+/// Score of one lowercased query term against an entry, None when it does
+/// not match at all
+fn term_score(entry: &LibraryEntry, name: &str, term: &str) -> Option<u32> {
+    let hash = entry.file_hash.to_lowercase();
+    let mut best = None;
+    let mut consider = |score: u32| best = Some(best.map_or(score, |b: u32| b.max(score)));
+
+    if hash == term {
+        consider(100);
+    } else if hash.starts_with(term) {
+        consider(60);
+    }
+    if name == term {
+        consider(90);
+    } else if let Some(pos) = name.find(term) {
+        let at_word_start = pos == 0
+            || name[..pos]
+                .chars()
+                .next_back()
+                .is_some_and(|c| !c.is_alphanumeric());
+        consider(if pos == 0 { 50 } else if at_word_start { 40 } else { 25 });
+    }
+    if entry.tags.iter().any(|tag| tag.to_lowercase() == term) {
+        consider(45);
+    }
+    best
+}
+
+/// Score of `entry` for `query`, None when any term or filter fails
+pub fn score(entry: &LibraryEntry, query: &LibraryQuery) -> Option<u32> {
+    if query.min_size.is_some_and(|min| entry.file_size < min)
+        || query.max_size.is_some_and(|max| entry.file_size > max)
+    {
+        return None;
+    }
+    if let Some(filter) = query.content_type() {
+        if !content_info::matches_type_filter(entry.mime_type.as_deref(), filter) {
+            return None;
+        }
+    }
+    if let Some(extension) = query.extension() {
+        if entry.extension.as_deref() != Some(extension.as_str()) {
+            return None;
+        }
+    }
+    let entry_tags = normalize_tags(&entry.tags);
+    if !normalize_tags(&query.tags)
+        .iter()
+        .all(|tag| entry_tags.contains(tag))
+    {
+        return None;
+    }
+
+    let name = entry.file_name.to_lowercase();
+    query
+        .query
+        .split_whitespace()
+        .map(|term| term_score(entry, &name, &term.to_lowercase()))
+        .sum()
+}
+
+/// Rank `entries` against `query`
+pub fn search(entries: Vec<LibraryEntry>, query: &LibraryQuery) -> LibrarySearchResult {
+    let mut matches: Vec<LibraryMatch> = entries
+        .into_iter()
+        .filter_map(|entry| score(&entry, query).map(|score| LibraryMatch { entry, score }))
+        .collect();
+    matches.sort_by(|a, b| {
+        b.score
+            .cmp(&a.score)
+            .then(b.entry.uploaded_at.cmp(&a.entry.uploaded_at))
+            .then_with(|| a.entry.file_name.cmp(&b.entry.file_name))
+    });
+    let total = matches.len();
+    matches.truncate(query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT));
+    LibrarySearchResult { matches, total }
+}
+
+/// MIME type from the first bytes of a stored file
+async fn sniff_mime(path: &Path) -> Option<String> {
+    let mut file = tokio::fs::File::open(path).await.ok()?;
+    let mut head = vec![0u8; SNIFF_SIZE];
+    let mut filled = 0;
+    while filled < head.len() {
+        match file.read(&mut head[filled..]).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => filled += n,
+        }
+    }
+    content_info::detect_mime(&head[..filled]).map(str::to_string)
+}
+
+/// Every entry in `storage_dir`. Unreadable sidecars are skipped. With
+/// `detect_missing_mime`, files whose sidecar has no MIME type are sniffed.
+pub async fn load_entries(
+    storage_dir: &Path,
+    detect_missing_mime: bool,
+) -> Result<Vec<LibraryEntry>, String> {
+    let mut entries = Vec::new();
+    let mut dir = tokio::fs::read_dir(storage_dir)
+        .await
+        .map_err(|e| format!("Failed to read storage directory: {}", e))?;
+
+    while let Some(item) = dir
+        .next_entry()
+        .await
+        .map_err(|e| format!("Failed to read directory entry: {}", e))?
+    {
+        let path = item.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("meta") {
+            continue;
+        }
+        let Some(file_hash) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+        let meta = match tokio::fs::read_to_string(&path).await {
+            Ok(raw) => serde_json::from_str::<Value>(&raw).ok(),
+            Err(_) => None,
+        };
+        let Some(mut entry) = meta.and_then(|m| LibraryEntry::from_meta(file_hash, &m)) else {
+            warn!("Skipping unreadable metadata file {}", path.display());
+            continue;
+        };
+        if detect_missing_mime && entry.mime_type.is_none() {
+            entry.mime_type = sniff_mime(&storage_dir.join(file_hash)).await;
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Replace the tags of a stored file, returning the normalized tags
+pub async fn set_tags(
+    storage_dir: &Path,
+    file_hash: &str,
+    tags: &[String],
+) -> Result<Vec<String>, String> {
+    if file_hash.is_empty() || !file_hash.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(format!("Invalid file hash: {}", file_hash));
+    }
+    let path = storage_dir.join(format!("{}.meta", file_hash));
+    let raw = tokio::fs::read_to_string(&path)
+        .await
+        .map_err(|e| format!("Failed to read metadata: {}", e))?;
+    let mut meta: Value =
+        serde_json::from_str(&raw).map_err(|e| format!("Failed to parse metadata: {}", e))?;
+    let Some(object) = meta.as_object_mut() else {
+        return Err("Failed to parse metadata: not an object".to_string());
+    };
+
+    let tags = normalize_tags(tags);
+    object.insert("tags".to_string(), Value::from(tags.clone()));
+    tokio::fs::write(&path, meta.to_string())
+        .await
+        .map_err(|e| format!("Failed to write metadata: {}", e))?;
+    Ok(tags)
+}
+
+/// Search the files stored in `storage_dir`
+pub async fn search_dir(
+    storage_dir: &Path,
+    query: &LibraryQuery,
+) -> Result<LibrarySearchResult, String> {
+    let entries = load_entries(storage_dir, query.content_type().is_some()).await?;
+    Ok(search(entries, query))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn ranks_and_filters_stored_files() {
+        let dir = tempdir().unwrap();
+        let files = [
+            ("aa11", "holiday.png", 5_000, 3, Some("image/png")),
+            ("bb22", "Holiday video.mp4", 900_000, 2, Some("video/mp4")),
+            ("cc33", "notes-holiday.txt", 300, 1, None),
+            ("dd44", "report.pdf", 40_000, 4, Some("application/pdf")),
+        ];
+        for (hash, name, size, uploaded_at, mime) in files {
+            let meta = json!({
+                "file_name": name,
+                "file_size": size,
+                "uploaded_at": uploaded_at,
+                "mime_type": mime,
+            });
+            std::fs::write(dir.path().join(format!("{}.meta", hash)), meta.to_string()).unwrap();
+        }
+        std::fs::write(dir.path().join("cc33"), b"plain text notes").unwrap();
+        std::fs::write(dir.path().join("broken.meta"), b"{").unwrap();
+
+        let hashes = |result: &LibrarySearchResult| -> Vec<String> {
+            result.matches.iter().map(|m| m.entry.file_hash.clone()).collect()
+        };
+        let run = |query: LibraryQuery| {
+            let dir = dir.path().to_path_buf();
+            async move { search_dir(&dir, &query).await.unwrap() }
+        };
+
+        // Name prefixes (newest first) rank above a match inside the name
+        let result = run(LibraryQuery {
+            query: "HOLIDAY".into(),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(hashes(&result), vec!["aa11", "bb22", "cc33"]);
+
+        // Hash prefix, and every term must match
+        let result = run(LibraryQuery {
+            query: "dd".into(),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(hashes(&result), vec!["dd44"]);
+        let result = run(LibraryQuery {
+            query: "holiday pdf".into(),
+            ..Default::default()
+        })
+        .await;
+        assert!(result.matches.is_empty());
+
+        // Size range and type filter; the text file's type is sniffed
+        let result = run(LibraryQuery {
+            min_size: Some(1_000),
+            max_size: Some(100_000),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(hashes(&result), vec!["dd44", "aa11"]);
+        let result = run(LibraryQuery {
+            content_type: Some("text".into()),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(hashes(&result), vec!["cc33"]);
+        let result = run(LibraryQuery {
+            extension: Some(".PDF".into()),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(hashes(&result), vec!["dd44"]);
+        assert_eq!(result.matches[0].entry.extension.as_deref(), Some("pdf"));
+
+        // Tags filter and match as terms
+        let tags = set_tags(dir.path(), "bb22", &[" Family ".into(), "family".into()])
+            .await
+            .unwrap();
+        assert_eq!(tags, vec!["family"]);
+        let result = run(LibraryQuery {
+            tags: vec!["FAMILY".into()],
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(hashes(&result), vec!["bb22"]);
+        let result = run(LibraryQuery {
+            query: "holiday family".into(),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(hashes(&result), vec!["bb22"]);
+
+        let result = run(LibraryQuery {
+            query: "holiday".into(),
+            limit: Some(1),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!((hashes(&result), result.total), (vec!["aa11".to_string()], 3));
+        assert!(set_tags(dir.path(), "../x", &[]).await.is_err());
+    }
+}
@@ -0,0 +1,179 @@
+// Persisted tracing filter
+//
+// Users can set per-module log levels (e.g. `file_transfer=debug,relay=info`)
+// that are saved next to the other settings, applied when the subscriber is
+// installed and changed at runtime through a reload handle, so debugging one
+// subsystem does not require RUST_LOG and a restart.
+//
+// The effective filter is RUST_LOG, then the built-in defaults, then the user's
+// directives, so the user's level wins for any target they name. Bare module
+// names are also applied to the same module inside this crate: `relay=debug`
+// matches both a `relay` target and `chiral_network::relay`.
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::warn;
+use tracing_subscriber::filter::Directive;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+const CRATE_TARGET: &str = "chiral_network";
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct LogFilterConfig {
+    /// Comma-separated filter directives, as in RUST_LOG
+    pub directives: String,
+}
+
+#[derive(Default)]
+struct FilterState {
+    defaults: Vec<String>,
+    config: LogFilterConfig,
+    persist_path: Option<PathBuf>,
+    handle: Option<reload::Handle<EnvFilter, Registry>>,
+    /// Why the saved filter was ignored, until `warn_load_error` logs it
+    load_error: Option<String>,
+}
+
+static STATE: Lazy<Mutex<FilterState>> = Lazy::new(|| Mutex::new(FilterState::default()));
+
+/// Split `spec` into directives, adding the crate-qualified form of bare
+/// module names. Fails on the first directive that does not parse.
+pub fn expand_directives(spec: &str) -> Result<Vec<String>, String> {
+    let mut directives = Vec::new();
+    for raw in spec.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+        raw.parse::<Directive>()
+            .map_err(|e| format!("Invalid log filter directive '{}': {}", raw, e))?;
+        directives.push(raw.to_string());
+
+        let Some((target, level)) = raw.split_once('=') else {
+            continue;
+        };
+        let is_bare_module = !target.is_empty()
+            && target != CRATE_TARGET
+            && target.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if is_bare_module {
+            directives.push(format!("{}::{}={}", CRATE_TARGET, target, level));
+        }
+    }
+    Ok(directives)
+}
+
+fn build_filter(defaults: &[String], directives: &[String]) -> EnvFilter {
+    let mut filter = EnvFilter::from_default_env();
+    for directive in defaults.iter().chain(directives) {
+        if let Ok(directive) = directive.parse::<Directive>() {
+            filter = filter.add_directive(directive);
+        }
+    }
+    filter
+}
+
+fn load_config(path: &Path) -> LogFilterConfig {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|raw| serde_json::from_str::<LogFilterConfig>(&raw).ok())
+        .unwrap_or_default()
+}
+
+/// Filter layer for the global subscriber, built from `defaults` plus the
+/// directives saved at `persist_path`. Add it directly on top of
+/// `tracing_subscriber::registry()` so `set_config` can reload it later.
+pub fn init_layer(
+    defaults: Vec<String>,
+    persist_path: Option<PathBuf>,
+) -> reload::Layer<EnvFilter, Registry> {
+    let config = persist_path.as_deref().map(load_config).unwrap_or_default();
+    // A saved filter that no longer parses falls back to the defaults. The
+    // subscriber is not installed yet, so the warning waits for it.
+    let (directives, load_error) = match expand_directives(&config.directives) {
+        Ok(directives) => (directives, None),
+        Err(e) => (Vec::new(), Some(e)),
+    };
+    let (layer, handle) = reload::Layer::new(build_filter(&defaults, &directives));
+
+    if let Ok(mut state) = STATE.lock() {
+        *state = FilterState {
+            defaults,
+            config,
+            persist_path,
+            handle: Some(handle),
+            load_error,
+        };
+    }
+    layer
+}
+
+/// Warn about a saved filter `init_layer` had to ignore. Call once the
+/// subscriber is installed.
+pub fn warn_load_error() {
+    let load_error = STATE
+        .lock()
+        .ok()
+        .and_then(|mut state| state.load_error.take());
+    if let Some(e) = load_error {
+        warn!("Ignoring saved log filter: {}", e);
+    }
+}
+
+/// The user's saved filter directives
+pub fn config() -> LogFilterConfig {
+    STATE
+        .lock()
+        .map(|state| state.config.clone())
+        .unwrap_or_default()
+}
+
+/// Validate, save and apply new filter directives. An empty string restores
+/// the defaults.
+pub fn set_config(config: LogFilterConfig) -> Result<(), String> {
+    let directives = expand_directives(&config.directives)?;
+    let mut state = STATE
+        .lock()
+        .map_err(|_| "Log filter lock poisoned".to_string())?;
+
+    if let Some(path) = &state.persist_path {
+        let json = serde_json::to_string_pretty(&config)
+            .map_err(|e| format!("Failed to serialize log filter: {}", e))?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create config directory: {}", e))?;
+        }
+        std::fs::write(path, json).map_err(|e| format!("Failed to save log filter: {}", e))?;
+    }
+    if let Some(handle) = &state.handle {
+        handle
+            .reload(build_filter(&state.defaults, &directives))
+            .map_err(|e| format!("Failed to apply log filter: {}", e))?;
+    }
+    state.config = config;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_bare_modules_and_rejects_bad_directives() {
+        let directives = expand_directives(" file_transfer=debug, libp2p_kad::kbucket=warn,info,");
+        let directives = directives.unwrap();
+        assert_eq!(
+            directives,
+            vec![
+                "file_transfer=debug",
+                "chiral_network::file_transfer=debug",
+                "libp2p_kad::kbucket=warn",
+                "info",
+            ]
+        );
+        assert_eq!(
+            expand_directives("chiral_network=trace").unwrap(),
+            vec!["chiral_network=trace"]
+        );
+        assert!(expand_directives("").unwrap().is_empty());
+        assert!(expand_directives("relay=loud").is_err());
+    }
+}
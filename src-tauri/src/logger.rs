@@ -1,7 +1,8 @@
+use std::collections::VecDeque;
 use std::fs::{self, File};
 use std::io::{self, Write};
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, OnceLock};
 use chrono::Local;
 use tracing_subscriber::fmt::MakeWriter;
 
@@ -269,3 +270,98 @@ impl Write for ThreadSafeWriterGuard {
         writer.flush()
     }
 }
+
+/// Number of formatted log lines kept in memory for the UI console
+pub const RECENT_LOG_CAPACITY: usize = 2000;
+
+static RECENT_LOGS: OnceLock<RecentLogBuffer> = OnceLock::new();
+
+/// Process-wide buffer of recent log lines. Add it to the subscriber as a
+/// `fmt` layer writer; since the registry's filter applies to every layer, the
+/// buffer only sees what the active filter lets through.
+pub fn recent_logs() -> &'static RecentLogBuffer {
+    RECENT_LOGS.get_or_init(|| RecentLogBuffer::new(RECENT_LOG_CAPACITY))
+}
+
+/// Ring buffer of the most recent formatted log lines
+#[derive(Clone)]
+pub struct RecentLogBuffer {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    capacity: usize,
+}
+
+impl RecentLogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    fn push_bytes(&self, buf: &[u8]) {
+        let text = String::from_utf8_lossy(buf);
+        let mut lines = match self.lines.lock() {
+            Ok(lines) => lines,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        for line in text.lines().filter(|l| !l.is_empty()) {
+            if lines.len() == self.capacity {
+                lines.pop_front();
+            }
+            lines.push_back(line.to_string());
+        }
+    }
+
+    /// The last `count` lines, oldest first
+    pub fn last(&self, count: usize) -> Vec<String> {
+        let lines = match self.lines.lock() {
+            Ok(lines) => lines,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let skip = lines.len().saturating_sub(count);
+        lines.iter().skip(skip).cloned().collect()
+    }
+}
+
+// fmt formats each event into one buffer before writing, so every write holds
+// whole lines
+pub struct RecentLogWriter {
+    buffer: RecentLogBuffer,
+}
+
+impl Write for RecentLogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.push_bytes(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for RecentLogBuffer {
+    type Writer = RecentLogWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RecentLogWriter {
+            buffer: self.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recent_log_buffer_keeps_last_lines() {
+        let buffer = RecentLogBuffer::new(3);
+        let mut writer = buffer.make_writer();
+        writer.write_all(b"one\ntwo\n").unwrap();
+        writer.write_all(b"three\nfour\n").unwrap();
+
+        assert_eq!(buffer.last(10), vec!["two", "three", "four"]);
+        assert_eq!(buffer.last(1), vec!["four"]);
+    }
+}
@@ -62,6 +62,7 @@ use crate::commands::proxy::{
 };
 use bandwidth::BandwidthController;
 use chiral_network::download_paths;
+use chiral_network::error_codes::{ErrorCode, ServiceError};
 use chiral_network::payment_checkpoint::PaymentCheckpointService;
 use chiral_network::transfer_events::{
     current_timestamp_ms, ErrorCategory, SourceInfo, SourceType, TransferCompletedEvent,
@@ -98,7 +99,12 @@ use ethereum::{
     GethProcess,
     MinedBlock,
 };
-use file_transfer::{DownloadMetricsSnapshot, FileTransferEvent, FileTransferService};
+use file_transfer::hashing::HashAlgorithm;
+use file_transfer::scrub::{ScrubReport, ScrubSchedule};
+use file_transfer::{
+    AttemptRetention, DownloadMetricsSnapshot, DownloadMetricsSummary, FileTransferEvent,
+    FileTransferService, NamedDownload,
+};
 use fs2::available_space;
 use geth_downloader::GethDownloader;
 use keystore::Keystore;
@@ -147,38 +153,6 @@ use std::ops::Range;
 use suppaftp::FtpStream;
 
 use x25519_dalek::{PublicKey, StaticSecret}; // For key handling
-                                             // Settings structure for backend use
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct BackendSettings {
-    #[serde(rename = "storagePath")]
-    storage_path: String,
-    #[serde(rename = "enableFileLogging")]
-    enable_file_logging: bool,
-    #[serde(rename = "maxLogSizeMB")]
-    max_log_size_mb: u64,
-    #[serde(rename = "maxStorageSize")]
-    max_storage_size: Option<u64>, // GB
-    #[serde(rename = "autoCleanup")]
-    auto_cleanup: Option<bool>,
-    #[serde(rename = "cleanupThreshold")]
-    cleanup_threshold: Option<u64>, // %
-    #[serde(rename = "cacheSize")]
-    cache_size: Option<u64>, // MB
-}
-
-impl Default for BackendSettings {
-    fn default() -> Self {
-        Self {
-            storage_path: "".to_string(), // No hardcoded default - get_download_directory handles this
-            enable_file_logging: false,
-            max_log_size_mb: 10,
-            max_storage_size: Some(100), // 100 GB default
-            auto_cleanup: Some(true),
-            cleanup_threshold: Some(90), // 90% default
-            cache_size: Some(1024),      // 1024 MB default
-        }
-    }
-}
 
 /// Get a unique file path by adding (1), (2), etc. if the file already exists
 /// Example: "file.txt" -> "file (1).txt" if "file.txt" exists
@@ -353,11 +327,19 @@ struct AppState {
     keystore: Arc<Mutex<Keystore>>,
     proxies: Arc<Mutex<Vec<ProxyNode>>>,
     privacy_proxies: Arc<Mutex<Vec<String>>>,
-    file_transfer_pump: Mutex<Option<JoinHandle<()>>>,
     multi_source_pump: Mutex<Option<JoinHandle<()>>>,
     socks5_proxy_cli: Mutex<Option<String>>,
     analytics: Arc<analytics::AnalyticsService>,
     bandwidth: Arc<BandwidthController>,
+    bandwidth_history: Arc<chiral_network::bandwidth_history::BandwidthHistory>,
+    alert_monitor: Arc<chiral_network::alerts::AlertMonitor>,
+    telemetry: Arc<chiral_network::telemetry::TelemetryReporter>,
+    session: Arc<chiral_network::session_summary::SessionTracker>,
+    slow_transfer: Arc<chiral_network::slow_transfer::SlowTransferSettings>,
+    stall: Arc<chiral_network::stall_watchdog::StallSettings>,
+    settings: Arc<chiral_network::settings::SettingsStore>,
+    shared_files: Arc<chiral_network::shared_file_watcher::SharedFileWatcher>,
+    notifications: Arc<chiral_network::notifications::NotificationSettings>,
     payment_checkpoint: Arc<PaymentCheckpointService>,
 
     // New fields for transaction queue
@@ -379,6 +361,9 @@ struct AppState {
     http_server_addr: Arc<Mutex<Option<std::net::SocketAddr>>>,
     http_server_shutdown: Arc<Mutex<Option<tokio::sync::oneshot::Sender<()>>>>,
 
+    // Localhost gateway for playing stored and streaming files in the webview
+    http_gateway: Mutex<Option<file_transfer::gateway::Gateway>>,
+
     // Stream authentication service
     stream_auth: Arc<Mutex<StreamAuthService>>,
 
@@ -390,8 +375,8 @@ struct AppState {
     proof_watcher: Arc<Mutex<Option<JoinHandle<()>>>>,
     proof_contract_address: Arc<Mutex<Option<String>>>,
 
-    // Relay reputation statistics storage
-    relay_reputation: Arc<Mutex<std::collections::HashMap<String, RelayNodeStats>>>,
+    // Known relays and their reputation, saved across restarts
+    relay_reputation: Arc<chiral_network::relay_registry::RelayRegistry>,
 
     // Relay node aliases (peer_id -> alias)
     relay_aliases: Arc<Mutex<std::collections::HashMap<String, String>>>,
@@ -416,6 +401,21 @@ struct AppState {
 
     // FTP server for serving uploaded files
     ftp_server: Arc<chiral_network::ftp_server::FtpServer>,
+
+    // Persistent log of terminal transfer events (None if the database could not be opened)
+    transfer_log: Option<Arc<chiral_network::transfer_log::TransferLog>>,
+
+    // Recent activity for the home screen (None if the database could not be opened)
+    activity_feed: Option<Arc<chiral_network::activity_feed::ActivityFeed>>,
+
+    // Files this node published, re-announced on a jittered schedule
+    reannouncer: Arc<chiral_network::reannounce::Reannouncer>,
+
+    // Files removed from the library, restorable until their retention ends
+    trash: Arc<chiral_network::file_trash::FileTrash>,
+
+    // Followed publishers and auto-download limits
+    subscriptions: Arc<chiral_network::subscriptions::Subscriptions>,
 }
 
 /// Tauri command to create a new Chiral account
@@ -434,6 +434,7 @@ async fn create_chiral_account(state: State<'_, AppState>) -> Result<EthAccount,
         let mut active_key = state.active_account_private_key.lock().await;
         *active_key = Some(account.private_key.clone());
     }
+    unlock_file_storage(&state).await;
 
     Ok(account)
 }
@@ -456,6 +457,7 @@ async fn import_chiral_account(
         let mut active_key = state.active_account_private_key.lock().await;
         *active_key = Some(account.private_key.clone());
     }
+    unlock_file_storage(&state).await;
 
     Ok(account)
 }
@@ -669,6 +671,7 @@ async fn load_account_from_keystore(
             .set_active_private_key(Some(private_key.clone()))
             .await;
     }
+    unlock_file_storage(&state).await;
 
     // Derive account details from private key
     get_account_from_private_key(&private_key)
@@ -819,8 +822,15 @@ async fn record_download_payment(
     amount: f64,
     transaction_id: u64,
     transaction_hash: String,
+    transfer_id: Option<String>,
     state: State<'_, AppState>,
 ) -> Result<(), String> {
+    tracing::info!(
+        transfer_id = transfer_id.as_deref().unwrap_or(""),
+        file_hash = %file_hash,
+        amount,
+        "download_payment_recorded"
+    );
     println!(
         "📝 Download payment recorded: {} Chiral to wallet {} (peer: {}) from {} (peer: {}) tx: {}",
         amount,
@@ -848,7 +858,21 @@ async fn record_download_payment(
         amount: f64,
         transaction_id: u64,
         transaction_hash: String,
-    }
+        /// Download the payment belongs to, when known
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        transfer_id: Option<String>,
+    }
+
+    chiral_network::activity_feed::publish(
+        chiral_network::activity_feed::ActivityEvent::PaymentSent {
+            peer_id: seeder_peer_id.clone(),
+            file_hash: file_hash.clone(),
+            file_name: file_name.clone(),
+            amount,
+            transaction_hash: transaction_hash.clone(),
+            transfer_id: transfer_id.clone(),
+        },
+    );
 
     let payment_msg = PaymentNotificationMessage {
         file_hash,
@@ -860,6 +884,7 @@ async fn record_download_payment(
         amount,
         transaction_id,
         transaction_hash: transaction_hash.clone(),
+        transfer_id,
     };
 
     // Serialize the payment message
@@ -1590,6 +1615,7 @@ async fn start_dht_node(
     autonat_servers: Option<Vec<String>>,
     preferred_relays: Option<Vec<String>>,
     proxy_address: Option<String>,
+    relay_selection: Option<chiral_network::relay_selector::RelaySelection>,
     // Currently NOT sent by tauri frontend,
     // just using defaults present in struct for single src of truth
     // is_bootstrap: Option<bool>, false
@@ -1604,7 +1630,22 @@ async fn start_dht_node(
     }
 
     let autonat_server_list = autonat_servers.unwrap_or(bootstrap_nodes.clone());
-    let preferred_relays_list = preferred_relays.unwrap_or_default();
+    // Without relays of its own choosing the node uses known relays, picked
+    // by the requested strategy (healthiest first by default)
+    let configured_relays = preferred_relays.filter(|relays| !relays.is_empty());
+    let relay_selection = relay_selection.unwrap_or_default();
+    let preferred_relays_list = match &configured_relays {
+        Some(relays) => relays.clone(),
+        None => {
+            state
+                .relay_reputation
+                .select_addresses(
+                    chiral_network::relay_registry::RESTORED_RELAYS,
+                    relay_selection.selector().as_ref(),
+                )
+                .await
+        }
+    };
 
     // Get the proxy from the command line, if it was provided at launch
     let cli_proxy = state.socks5_proxy_cli.lock().await.clone();
@@ -1716,6 +1757,9 @@ async fn start_dht_node(
             for ev in events {
                 match ev {
                     DhtEvent::PeerDiscovered { peer_id, addresses } => {
+                        relay_reputation_arc
+                            .note_addresses(&peer_id, &addresses, relay_reputation_arc.now())
+                            .await;
                         let payload = serde_json::json!({
                             "peerId": peer_id,
                             "addresses": addresses,
@@ -1853,37 +1897,13 @@ async fn start_dht_node(
                         data,
                     } => {
                         // Update relay reputation statistics
-                        let mut stats = relay_reputation_arc.lock().await;
-                        let entry = stats.entry(peer_id.clone()).or_insert(RelayNodeStats {
-                            peer_id: peer_id.clone(),
-                            alias: None,
-                            reputation_score: 0.0,
-                            reservations_accepted: 0,
-                            circuits_established: 0,
-                            circuits_successful: 0,
-                            total_events: 0,
-                            last_seen: 0,
-                        });
-
-                        // Update statistics based on event type
-                        entry.reputation_score += impact;
-                        entry.total_events += 1;
-                        entry.last_seen = data
+                        let at = data
                             .get("timestamp")
                             .and_then(|v| v.as_u64())
-                            .unwrap_or_else(|| {
-                                std::time::SystemTime::now()
-                                    .duration_since(std::time::UNIX_EPOCH)
-                                    .unwrap_or(std::time::Duration::from_secs(0))
-                                    .as_secs()
-                            });
-
-                        match event_type.as_str() {
-                            "RelayReservationAccepted" => entry.reservations_accepted += 1,
-                            "RelayCircuitEstablished" => entry.circuits_established += 1,
-                            "RelayCircuitSuccessful" => entry.circuits_successful += 1,
-                            _ => {}
-                        }
+                            .unwrap_or_else(|| relay_reputation_arc.now());
+                        relay_reputation_arc
+                            .record_event(&peer_id, &event_type, impact, at)
+                            .await;
 
                         // Emit event to frontend
                         let payload = serde_json::json!({
@@ -1908,6 +1928,44 @@ async fn start_dht_node(
                         });
                         let _ = app_handle.emit("bitswap_chunk_downloaded", payload);
                     }
+                    DhtEvent::RelayAnnounced { announcement, via } => {
+                        relay_reputation_arc
+                            .record_announcement(&announcement, &via)
+                            .await;
+                    }
+                    DhtEvent::RelayReservation(reservation) => {
+                        relay_reputation_arc.record_reservation(&reservation).await;
+                        let _ = app_handle.emit("relay_reservation", &reservation);
+                    }
+                    DhtEvent::HolePunch {
+                        peer_id,
+                        relay_peer_id,
+                        success,
+                    } => {
+                        if let Some(relay) = &relay_peer_id {
+                            relay_reputation_arc
+                                .record_hole_punch(relay, success, relay_reputation_arc.now())
+                                .await;
+                        }
+                        let payload = serde_json::json!({ "peerId": peer_id, "relayPeerId": relay_peer_id, "success": success });
+                        let _ = app_handle.emit("hole_punch", payload);
+                    }
+                    DhtEvent::RelayUsage {
+                        relay_peer_id,
+                        circuits_opened,
+                        bytes,
+                        failures,
+                    } => {
+                        relay_reputation_arc
+                            .record_usage(
+                                &relay_peer_id,
+                                circuits_opened,
+                                bytes,
+                                failures,
+                                relay_reputation_arc.now(),
+                            )
+                            .await;
+                    }
                     DhtEvent::PaymentNotificationReceived { from_peer, payload } => {
                         println!(
                             "💰 Payment notification received from peer {}: {:?}",
@@ -1943,6 +2001,13 @@ async fn start_dht_node(
         let mut dht_guard = state.dht.lock().await;
         *dht_guard = Some(dht_arc.clone());
     }
+    sync_blocked_relays(&state).await;
+
+    // Downloads of files we don't hold fetch their chunks from peers over the DHT swarm
+    if let Some(ft) = state.file_transfer.lock().await.as_ref() {
+        ft.set_chunk_fetcher(Arc::new(dht::DhtChunkFetcher::new(&dht_arc)))
+            .await;
+    }
 
     // Store chunk manager in AppState
     {
@@ -1953,6 +2018,41 @@ async fn start_dht_node(
     // Also attach DHT to HTTP server state for provider-side metrics
     state.http_server_state.set_dht(dht_arc.clone()).await;
 
+    // Reserve with the relays in use, picking them again as scores change
+    let registry = state.relay_reputation.clone();
+    let dht_for_relays = Arc::downgrade(&dht_arc);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(chiral_network::relay_selector::RESELECT_INTERVAL);
+        let mut in_use = std::collections::HashSet::new();
+        loop {
+            ticker.tick().await;
+            let Some(dht) = dht_for_relays.upgrade() else {
+                break;
+            };
+            let selected = match &configured_relays {
+                Some(relays) => relays.clone(),
+                None => {
+                    registry
+                        .select_addresses(
+                            chiral_network::relay_registry::RESTORED_RELAYS,
+                            relay_selection.selector().as_ref(),
+                        )
+                        .await
+                }
+            };
+            let selected_set: std::collections::HashSet<String> =
+                selected.iter().cloned().collect();
+            if selected_set == in_use {
+                continue;
+            }
+            if dht.set_relay_candidates(selected).await.is_err() {
+                // The DHT node stopped
+                break;
+            }
+            in_use = selected_set;
+        }
+    });
+
     // Monitor peer health and auto-reconnect to bootstrap when needed
     let dht_for_monitor = dht_arc.clone();
     let app_for_monitor = app.clone();
@@ -2049,6 +2149,9 @@ async fn stop_dht_node(app: tauri::AppHandle, state: State<'_, AppState>) -> Res
 
 #[tauri::command]
 async fn stop_publishing_file(state: State<'_, AppState>, file_hash: String) -> Result<(), String> {
+    state.shared_files.unregister(&file_hash);
+    state.reannouncer.unpin(&file_hash);
+    remove_previews(&file_hash);
     let dht = {
         let dht_guard = state.dht.lock().await;
         dht_guard.as_ref().cloned()
@@ -2060,6 +2163,281 @@ async fn stop_publishing_file(state: State<'_, AppState>, file_hash: String) ->
     }
 }
 
+/// Cached metadata of the library file `id` names: its merkle root, the
+/// magnet link it is seeded under or one of its FTP/HTTP source URLs
+async fn library_file_metadata(dht: &DhtService, id: &str) -> Option<FileMetadata> {
+    if let Some(metadata) = dht.cached_file_metadata(id).await {
+        return Some(metadata);
+    }
+    let info_hash = bittorrent_handler::BitTorrentHandler::extract_info_hash(id);
+    dht.get_all_file_metadata()
+        .await
+        .ok()?
+        .into_iter()
+        .find(|metadata| {
+            let same_torrent = match (&info_hash, &metadata.info_hash) {
+                (Some(wanted), Some(own)) => wanted.eq_ignore_ascii_case(own),
+                _ => false,
+            };
+            same_torrent
+                || metadata.ftp_sources.iter().flatten().any(|s| s.url == id)
+                || metadata.http_sources.iter().flatten().any(|s| s.url == id)
+        })
+}
+
+/// Stop sharing a library file and move its stored copies to the trash.
+/// `file_hash` is the id the library lists it under: a merkle root, magnet
+/// link or source URL.
+#[tauri::command]
+async fn trash_library_file(
+    state: State<'_, AppState>,
+    file_hash: String,
+) -> Result<chiral_network::file_trash::TrashEntry, String> {
+    let ft = { state.file_transfer.lock().await.as_ref().cloned() }
+        .ok_or("File transfer service is not running")?;
+    let dht = { state.dht.lock().await.as_ref().cloned() };
+
+    let mut metadata = match &dht {
+        Some(dht) => library_file_metadata(dht, &file_hash).await,
+        None => None,
+    };
+    if let Some(metadata) = metadata.as_mut() {
+        metadata.file_data.clear();
+    }
+    let published_id = metadata
+        .as_ref()
+        .map(|metadata| metadata.merkle_root.clone())
+        .unwrap_or_else(|| file_hash.clone());
+    let file_name = metadata
+        .as_ref()
+        .map(|metadata| metadata.file_name.clone())
+        .unwrap_or_else(|| file_hash.clone());
+
+    state.shared_files.unregister(&published_id);
+    state.reannouncer.unpin(&published_id);
+    state.http_server_state.unregister_file(&published_id).await;
+    if let Some(dht) = &dht {
+        if let Err(e) = dht.stop_publishing_file(published_id.clone()).await {
+            warn!("Failed to stop publishing {}: {}", published_id, e);
+        }
+    }
+
+    ft.trash_file(&state.trash, &published_id, &file_name, metadata)
+        .await
+}
+
+#[tauri::command]
+async fn list_trash(
+    state: State<'_, AppState>,
+) -> Result<Vec<chiral_network::file_trash::TrashEntry>, String> {
+    state.trash.list().await
+}
+
+/// Put a trashed file back and publish it again with its previous metadata
+#[tauri::command]
+async fn restore_trashed_file(
+    state: State<'_, AppState>,
+    id: String,
+) -> Result<chiral_network::file_trash::TrashEntry, String> {
+    let ft = { state.file_transfer.lock().await.as_ref().cloned() }
+        .ok_or("File transfer service is not running")?;
+    let entry = ft.restore_trashed_file(&state.trash, &id).await?;
+    if let Some(blob) = &entry.blob {
+        watch_linked_blob(&state, &ft, blob).await;
+    }
+    if let Some(metadata) = entry.metadata.clone() {
+        let dht = { state.dht.lock().await.as_ref().cloned() };
+        match dht {
+            Some(dht) => dht.publish_file(metadata, None).await?,
+            None => warn!("Restored {} but DHT is not running to publish it", entry.file_hash),
+        }
+    }
+    Ok(entry)
+}
+
+/// Permanently delete one trash entry; returns the bytes freed
+#[tauri::command]
+async fn delete_trashed_file(state: State<'_, AppState>, id: String) -> Result<u64, String> {
+    state.trash.delete(&id).await
+}
+
+/// Permanently delete everything in the trash; returns the number of entries
+#[tauri::command]
+async fn empty_trash(state: State<'_, AppState>) -> Result<usize, String> {
+    state.trash.purge(None).await
+}
+
+#[tauri::command]
+async fn get_trash_config(
+    state: State<'_, AppState>,
+) -> Result<chiral_network::file_trash::TrashConfig, String> {
+    Ok(state.trash.config())
+}
+
+#[tauri::command]
+async fn set_trash_config(
+    state: State<'_, AppState>,
+    config: chiral_network::file_trash::TrashConfig,
+) -> Result<(), String> {
+    state.trash.set_config(config)
+}
+
+/// Announcement state of every file this node published
+#[tauri::command]
+async fn get_reannounce_status(
+    state: State<'_, AppState>,
+) -> Result<Vec<chiral_network::reannounce::ReannounceStatus>, String> {
+    Ok(state.reannouncer.statuses())
+}
+
+/// Re-announce one published file now instead of waiting for the next pass
+#[tauri::command]
+async fn force_reannounce(
+    state: State<'_, AppState>,
+    file_hash: String,
+) -> Result<chiral_network::reannounce::ReannounceStatus, String> {
+    let dht = { state.dht.lock().await.as_ref().cloned() };
+    let dht = dht.ok_or_else(|| "DHT node is not running".to_string())?;
+    state.reannouncer.reannounce(&dht, &file_hash).await
+}
+
+#[tauri::command]
+async fn list_subscriptions(
+    state: State<'_, AppState>,
+) -> Result<Vec<chiral_network::subscriptions::Subscription>, String> {
+    Ok(state.subscriptions.list())
+}
+
+/// Follow a publisher, or update the settings of an existing subscription
+#[tauri::command]
+async fn subscribe_publisher(
+    state: State<'_, AppState>,
+    subscription: chiral_network::subscriptions::Subscription,
+) -> Result<chiral_network::subscriptions::Subscription, String> {
+    if let Some(dir) = &subscription.download_dir {
+        if !Path::new(dir).is_dir() {
+            return Err(format!("Download folder does not exist: {}", dir));
+        }
+    }
+    state.subscriptions.subscribe(subscription)
+}
+
+#[tauri::command]
+async fn unsubscribe_publisher(
+    state: State<'_, AppState>,
+    publisher_id: String,
+) -> Result<bool, String> {
+    state.subscriptions.unsubscribe(&publisher_id)
+}
+
+#[tauri::command]
+async fn get_subscription_limits(
+    state: State<'_, AppState>,
+) -> Result<chiral_network::subscriptions::SubscriptionLimits, String> {
+    Ok(state.subscriptions.state().limits)
+}
+
+#[tauri::command]
+async fn set_subscription_limits(
+    state: State<'_, AppState>,
+    limits: chiral_network::subscriptions::SubscriptionLimits,
+) -> Result<(), String> {
+    state.subscriptions.set_limits(limits)
+}
+
+/// The verified feed a publisher currently announces, if any
+#[tauri::command]
+async fn get_publisher_feed(
+    state: State<'_, AppState>,
+    publisher_id: String,
+) -> Result<Option<chiral_network::dht::publisher_feed::PublisherFeed>, String> {
+    let dht = { state.dht.lock().await.as_ref().cloned() };
+    let dht = dht.ok_or_else(|| "DHT node is not running".to_string())?;
+    dht.fetch_publisher_feed(&publisher_id).await
+}
+
+/// Notify about a new entry in a followed publisher's feed and start its
+/// auto-download when the subscription and budgets allow it
+async fn handle_subscription_entry(
+    app_handle: &tauri::AppHandle,
+    dht: &DhtService,
+    sub: &chiral_network::subscriptions::Subscription,
+    entry: chiral_network::dht::publisher_feed::FeedEntry,
+) {
+    use chiral_network::notifications;
+    use chiral_network::subscriptions::{
+        self, AutoDownload, PublishedFile, SubscriptionContentEvent,
+    };
+
+    let state = app_handle.state::<AppState>();
+    let publisher_name = sub.display_name();
+    notifications::show(
+        app_handle,
+        &state.notifications,
+        &notifications::for_subscription(&publisher_name, &entry.file_name, entry.file_size),
+    );
+
+    // The size and price in the feed are only the publisher's word; the
+    // limits are checked against the metadata the download goes by
+    let published = if sub.auto_download && sub.download_dir.is_some() {
+        match dht
+            .synchronous_search_metadata(entry.file_hash.clone(), 35000)
+            .await
+        {
+            Ok(Some(metadata)) => Some(PublishedFile {
+                file_name: metadata.file_name,
+                file_size: metadata.file_size,
+                price: metadata.price,
+            }),
+            Ok(None) => None,
+            Err(e) => {
+                warn!("Metadata lookup for {} failed: {}", entry.file_hash, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let rules = state.settings.get().naming_rules();
+    let now = state.subscriptions.now();
+    let decision = state
+        .subscriptions
+        .claim_auto_download(&sub.publisher_id, &entry, published.as_ref(), &rules, now)
+        .unwrap_or_else(AutoDownload::Skip);
+    let skipped_reason = match decision {
+        AutoDownload::Start(path) => {
+            let output = path.to_string_lossy().into_owned();
+            let file_hash = entry.file_hash.clone();
+            let app_handle = app_handle.clone();
+            tauri::async_runtime::spawn(async move {
+                let state = app_handle.state::<AppState>();
+                if let Err(e) = download_file_from_network(state, file_hash.clone(), output).await {
+                    warn!("Auto-download of {} failed: {}", file_hash, e);
+                }
+            });
+            None
+        }
+        AutoDownload::Skip(reason) => Some(reason),
+    };
+
+    if let Err(e) = state
+        .subscriptions
+        .mark_seen(&sub.publisher_id, entry.published_at)
+    {
+        warn!("Failed to save subscription state: {}", e);
+    }
+    let _ = app_handle.emit(
+        subscriptions::CONTENT_EVENT,
+        SubscriptionContentEvent {
+            publisher_id: sub.publisher_id.clone(),
+            publisher_name,
+            auto_downloaded: skipped_reason.is_none(),
+            skipped_reason,
+            entry,
+        },
+    );
+}
+
 #[tauri::command]
 async fn connect_to_peer(state: State<'_, AppState>, peer_address: String) -> Result<(), String> {
     let dht = {
@@ -2144,71 +2522,222 @@ async fn get_dht_connected_peers(state: State<'_, AppState>) -> Result<Vec<Strin
 }
 
 #[tauri::command]
-async fn create_auth_session(
+async fn get_peer_connection_stats(
     state: State<'_, AppState>,
-    session_id: String,
-    hmac_key: Vec<u8>,
-) -> Result<(), String> {
-    let mut auth_service = state.stream_auth.lock().await;
-    auth_service.create_session(session_id, hmac_key)
+) -> Result<Vec<dht::connection_stats::PeerConnectionStats>, String> {
+    let dht = {
+        let dht_guard = state.dht.lock().await;
+        dht_guard.as_ref().cloned()
+    };
+
+    Ok(dht.map(|dht| dht.get_peer_connection_stats()).unwrap_or_default())
 }
 
 #[tauri::command]
-async fn verify_stream_auth(
+async fn get_peer_churn_stats(
     state: State<'_, AppState>,
-    session_id: String,
-    auth_message: AuthMessage,
-) -> Result<bool, String> {
-    let mut auth_service = state.stream_auth.lock().await;
-    auth_service.verify_data(&session_id, &auth_message)
+    limit: Option<usize>,
+) -> Result<dht::connection_stats::ChurnStats, String> {
+    let dht = {
+        let dht_guard = state.dht.lock().await;
+        dht_guard.as_ref().cloned()
+    };
+
+    let dht = dht
+        .ok_or_else(|| ServiceError::new(ErrorCode::ServiceUnavailable, "DHT not running"))?;
+    dht.peer_churn_stats(limit.unwrap_or(10))
+        .ok_or_else(|| "Connection statistics lock poisoned".to_string())
 }
 
 #[tauri::command]
-async fn generate_hmac_key() -> Vec<u8> {
-    StreamAuthService::generate_hmac_key()
+async fn set_dht_query_tracing(state: State<'_, AppState>, enabled: bool) -> Result<(), String> {
+    let dht = {
+        let dht_guard = state.dht.lock().await;
+        dht_guard.as_ref().cloned()
+    };
+
+    let dht = dht
+        .ok_or_else(|| ServiceError::new(ErrorCode::ServiceUnavailable, "DHT not running"))?;
+    dht.set_query_tracing(enabled);
+    Ok(())
 }
 
 #[tauri::command]
-async fn cleanup_auth_sessions(state: State<'_, AppState>) -> Result<(), String> {
-    let mut auth_service = state.stream_auth.lock().await;
-    auth_service.cleanup_expired_sessions();
-    auth_service.cleanup_expired_exchanges();
-    Ok(())
+async fn get_dht_query_trace(
+    state: State<'_, AppState>,
+    query_id: String,
+) -> Result<Option<dht::query_trace::QueryTrace>, String> {
+    let dht = {
+        let dht_guard = state.dht.lock().await;
+        dht_guard.as_ref().cloned()
+    };
+
+    let dht = dht
+        .ok_or_else(|| ServiceError::new(ErrorCode::ServiceUnavailable, "DHT not running"))?;
+    Ok(dht.query_trace(&query_id))
 }
 
 #[tauri::command]
-async fn initiate_hmac_key_exchange(
+async fn list_dht_query_traces(
     state: State<'_, AppState>,
-    initiator_peer_id: String,
-    target_peer_id: String,
-    session_id: String,
-) -> Result<HmacKeyExchangeRequest, String> {
-    let mut auth_service = state.stream_auth.lock().await;
-    auth_service.initiate_key_exchange(initiator_peer_id, target_peer_id, session_id)
+    limit: Option<usize>,
+) -> Result<Vec<dht::query_trace::QueryTrace>, String> {
+    let dht = {
+        let dht_guard = state.dht.lock().await;
+        dht_guard.as_ref().cloned()
+    };
+
+    let dht = dht
+        .ok_or_else(|| ServiceError::new(ErrorCode::ServiceUnavailable, "DHT not running"))?;
+    Ok(dht.recent_query_traces(limit.unwrap_or(50)))
 }
 
 #[tauri::command]
-async fn respond_to_hmac_key_exchange(
+async fn get_dial_failures(
     state: State<'_, AppState>,
-    request: HmacKeyExchangeRequest,
-    responder_peer_id: String,
-) -> Result<HmacKeyExchangeResponse, String> {
-    let mut auth_service = state.stream_auth.lock().await;
-    auth_service.respond_to_key_exchange(request, responder_peer_id)
+    peer_id: Option<String>,
+    limit: Option<usize>,
+) -> Result<Vec<dht::dial_failures::PeerDialFailures>, String> {
+    let dht = {
+        let dht_guard = state.dht.lock().await;
+        dht_guard.as_ref().cloned()
+    };
+
+    let dht = dht
+        .ok_or_else(|| ServiceError::new(ErrorCode::ServiceUnavailable, "DHT not running"))?;
+    Ok(dht.dial_failures(peer_id.as_deref(), limit.unwrap_or(20)))
 }
 
+/// State of this node's circuit relay reservations, active ones first
 #[tauri::command]
-async fn confirm_hmac_key_exchange(
+async fn get_relay_reservations(
     state: State<'_, AppState>,
-    response: HmacKeyExchangeResponse,
-    initiator_peer_id: String,
-) -> Result<HmacKeyExchangeConfirmation, String> {
-    let mut auth_service = state.stream_auth.lock().await;
-    auth_service.confirm_key_exchange(response, initiator_peer_id)
+) -> Result<Vec<dht::reservations::ReservationState>, String> {
+    let dht = {
+        let dht_guard = state.dht.lock().await;
+        dht_guard.as_ref().cloned()
+    };
+
+    let dht =
+        dht.ok_or_else(|| ServiceError::new(ErrorCode::ServiceUnavailable, "DHT not running"))?;
+    Ok(dht.relay_reservations())
 }
 
+/// Pass/warn/fail report on reachability, NAT, bootstrap nodes and DHT latency
 #[tauri::command]
-async fn finalize_hmac_key_exchange(
+async fn run_network_diagnostics(
+    state: State<'_, AppState>,
+) -> Result<dht::diagnostics::DiagnosticsReport, String> {
+    let dht = {
+        let dht_guard = state.dht.lock().await;
+        dht_guard.as_ref().cloned()
+    };
+
+    match dht {
+        Some(dht) => Ok(dht.run_diagnostics().await),
+        None => Err(ServiceError::new(ErrorCode::ServiceUnavailable, "DHT not running").into()),
+    }
+}
+
+/// Providers of `file_hash` other than this node, their reachability and an
+/// availability grade
+#[tauri::command]
+async fn get_file_availability(
+    state: State<'_, AppState>,
+    file_hash: String,
+) -> Result<dht::availability::AvailabilityReport, String> {
+    let dht = {
+        let dht_guard = state.dht.lock().await;
+        dht_guard.as_ref().cloned()
+    };
+
+    match dht {
+        Some(dht) => Ok(dht.file_availability(&file_hash).await),
+        None => Err(ServiceError::new(ErrorCode::ServiceUnavailable, "DHT not running").into()),
+    }
+}
+
+#[tauri::command]
+async fn get_network_topology(
+    state: State<'_, AppState>,
+) -> Result<dht::topology::NetworkTopology, String> {
+    let dht = {
+        let dht_guard = state.dht.lock().await;
+        dht_guard.as_ref().cloned()
+    };
+
+    match dht {
+        Some(dht) => dht.network_topology().await,
+        None => Err(ServiceError::new(ErrorCode::ServiceUnavailable, "DHT not running").into()),
+    }
+}
+
+#[tauri::command]
+async fn create_auth_session(
+    state: State<'_, AppState>,
+    session_id: String,
+    hmac_key: Vec<u8>,
+) -> Result<(), String> {
+    let mut auth_service = state.stream_auth.lock().await;
+    auth_service.create_session(session_id, hmac_key)
+}
+
+#[tauri::command]
+async fn verify_stream_auth(
+    state: State<'_, AppState>,
+    session_id: String,
+    auth_message: AuthMessage,
+) -> Result<bool, String> {
+    let mut auth_service = state.stream_auth.lock().await;
+    auth_service.verify_data(&session_id, &auth_message)
+}
+
+#[tauri::command]
+async fn generate_hmac_key() -> Vec<u8> {
+    StreamAuthService::generate_hmac_key()
+}
+
+#[tauri::command]
+async fn cleanup_auth_sessions(state: State<'_, AppState>) -> Result<(), String> {
+    let mut auth_service = state.stream_auth.lock().await;
+    auth_service.cleanup_expired_sessions();
+    auth_service.cleanup_expired_exchanges();
+    Ok(())
+}
+
+#[tauri::command]
+async fn initiate_hmac_key_exchange(
+    state: State<'_, AppState>,
+    initiator_peer_id: String,
+    target_peer_id: String,
+    session_id: String,
+) -> Result<HmacKeyExchangeRequest, String> {
+    let mut auth_service = state.stream_auth.lock().await;
+    auth_service.initiate_key_exchange(initiator_peer_id, target_peer_id, session_id)
+}
+
+#[tauri::command]
+async fn respond_to_hmac_key_exchange(
+    state: State<'_, AppState>,
+    request: HmacKeyExchangeRequest,
+    responder_peer_id: String,
+) -> Result<HmacKeyExchangeResponse, String> {
+    let mut auth_service = state.stream_auth.lock().await;
+    auth_service.respond_to_key_exchange(request, responder_peer_id)
+}
+
+#[tauri::command]
+async fn confirm_hmac_key_exchange(
+    state: State<'_, AppState>,
+    response: HmacKeyExchangeResponse,
+    initiator_peer_id: String,
+) -> Result<HmacKeyExchangeConfirmation, String> {
+    let mut auth_service = state.stream_auth.lock().await;
+    auth_service.confirm_key_exchange(response, initiator_peer_id)
+}
+
+#[tauri::command]
+async fn finalize_hmac_key_exchange(
     state: State<'_, AppState>,
     confirmation: HmacKeyExchangeConfirmation,
     responder_peer_id: String,
@@ -2355,6 +2884,42 @@ async fn get_dht_events(state: State<'_, AppState>) -> Result<Vec<String>, Strin
                         file_hash, chunk_index, total_chunks, chunk_size
                     )
                 }
+                DhtEvent::RelayAnnounced { announcement, .. } => {
+                    format!(
+                        "relay_announced:{}:{}",
+                        announcement.peer_id,
+                        announcement.addresses.join(",")
+                    )
+                }
+                DhtEvent::RelayReservation(reservation) => {
+                    format!(
+                        "relay_reservation:{}:{:?}",
+                        reservation.relay_peer_id, reservation.status
+                    )
+                }
+                DhtEvent::HolePunch {
+                    peer_id,
+                    relay_peer_id,
+                    success,
+                } => {
+                    format!(
+                        "hole_punch:{}:{}:{}",
+                        peer_id,
+                        relay_peer_id.unwrap_or_default(),
+                        success
+                    )
+                }
+                DhtEvent::RelayUsage {
+                    relay_peer_id,
+                    circuits_opened,
+                    bytes,
+                    failures,
+                } => {
+                    format!(
+                        "relay_usage:{}:{}:{}:{}",
+                        relay_peer_id, circuits_opened, bytes, failures
+                    )
+                }
                 DhtEvent::PaymentNotificationReceived { from_peer, payload } => {
                     format!("payment_notification_received:{}:{:?}", from_peer, payload)
                 }
@@ -3507,6 +4072,34 @@ fn get_download_directory(app: tauri::AppHandle) -> Result<String, String> {
     download_paths::get_download_directory(&app)
 }
 
+/// Space reserved by in-flight downloads in the staging area, and its limits
+#[tauri::command]
+fn get_download_staging_status() -> chiral_network::download_staging::StagingStatus {
+    chiral_network::download_staging::global().status()
+}
+
+#[tauri::command]
+fn set_download_staging_config(
+    config: chiral_network::download_staging::StagingConfig,
+) -> Result<(), String> {
+    chiral_network::download_staging::global().set_config(config)
+}
+
+/// Compare a downloaded file with its entry in an upstream checksum file
+/// (SHA256SUMS, MD5SUMS, B3SUMS) or with a pasted digest
+#[tauri::command]
+async fn verify_file_checksum(
+    file_path: String,
+    source: chiral_network::checksum_verify::ChecksumSource,
+    algorithm: Option<chiral_network::checksum_verify::ChecksumAlgorithm>,
+) -> Result<chiral_network::checksum_verify::ChecksumReport, String> {
+    tokio::task::spawn_blocking(move || {
+        chiral_network::checksum_verify::verify(Path::new(&file_path), &source, algorithm)
+    })
+    .await
+    .map_err(|e| format!("Checksum task failed: {}", e))?
+}
+
 /// Validates a storage path to ensure it's a valid absolute path
 /// This prevents issues where relative paths or tilde expansion
 /// could create directories in unexpected locations.
@@ -3595,10 +4188,36 @@ async fn start_file_transfer_service(
         .map_err(|e| format!("Failed to start file transfer service: {}", e))?;
 
     let ft_arc = Arc::new(file_transfer_service);
+    let settings = state.settings.get();
+    ft_arc
+        .set_attempt_retention(settings.download_attempt_retention)
+        .await;
+    ft_arc
+        .set_max_concurrent_transfers(settings.max_concurrent_transfers)
+        .await?;
     {
         let mut ft_guard = state.file_transfer.lock().await;
         *ft_guard = Some(ft_arc.clone());
     }
+    state
+        .http_server_state
+        .set_blob_store(ft_arc.blob_store())
+        .await;
+    unlock_file_storage(&state).await;
+    // Linked blobs the watcher lost track of, e.g. with its saved list gone
+    {
+        let watched: std::collections::HashSet<String> = state
+            .shared_files
+            .list()
+            .into_iter()
+            .map(|file| file.file_hash)
+            .collect();
+        for entry in ft_arc.stored_blobs().await {
+            if entry.linked.is_some() && !watched.contains(&entry.hash) {
+                watch_linked_blob(&state, &ft_arc, &entry).await;
+            }
+        }
+    }
 
     // Initialize WebRTC service with file transfer service (without multi_source_service initially)
     let webrtc_service = WebRTCService::new(
@@ -3646,7 +4265,9 @@ async fn start_file_transfer_service(
             transfer_event_bus,
             state.analytics.clone(),
             chunk_manager,
-        );
+        )
+        .with_slow_transfer_settings(state.slow_transfer.clone())
+        .with_stall_settings(state.stall.clone());
         let multi_source_arc = Arc::new(multi_source_service);
 
         // Update WebRTCService with MultiSourceDownloadService for hash verification
@@ -3696,18 +4317,6 @@ async fn start_file_transfer_service(
         });
     }
 
-    {
-        let mut pump_guard = state.file_transfer_pump.lock().await;
-        if pump_guard.is_none() {
-            let app_handle = app.clone();
-            let ft_clone = ft_arc.clone();
-            let handle = tokio::spawn(async move {
-                pump_file_transfer_events(app_handle, ft_clone).await;
-            });
-            *pump_guard = Some(handle);
-        }
-    }
-
     Ok(())
 }
 
@@ -3720,104 +4329,437 @@ async fn upload_file_to_network(
     protocol: Option<String>,
     original_file_name: Option<String>,
 ) -> Result<(), String> {
-    // Use provided original filename, or extract from path if not provided
-    let original_file_name = original_file_name.unwrap_or_else(|| {
-        Path::new(&file_path)
-            .file_name()
-            .and_then(|s| s.to_str())
-            .unwrap_or("unknown")
-            .to_string()
-    });
+    upload_path_to_network(
+        app,
+        state,
+        file_path,
+        price,
+        protocol,
+        original_file_name,
+        true,
+    )
+    .await
+    .map(|_| ())
+}
 
-    // Ensure price is never null - default to 0
-    let price = price.unwrap_or(0.0);
+/// Upload several files as one batch: one aggregate progress event after each
+/// file and a single summary instead of an error per failed file
+#[tauri::command]
+async fn upload_files(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    paths: Vec<String>,
+    price: Option<f64>,
+    protocol: Option<String>,
+) -> Result<chiral_network::batch_upload::BatchUploadSummary, String> {
+    use chiral_network::batch_upload::{self, BatchUpload};
 
-    // Get the active account for uploader_address
-    let account = get_active_account(&state).await?;
+    let paths = batch_upload::dedup_paths(paths);
+    if paths.is_empty() {
+        return Err("No files to upload".to_string());
+    }
+    if paths.len() > batch_upload::MAX_BATCH_FILES {
+        return Err(format!(
+            "Too many files in one batch ({}, at most {})",
+            paths.len(),
+            batch_upload::MAX_BATCH_FILES
+        ));
+    }
 
-    // Calculate file hash without loading entire file into memory
-    let mut hasher = sha2::Sha256::new();
-    let mut file = tokio::fs::File::open(&file_path)
-        .await
-        .map_err(|e| format!("Failed to open file for hashing: {}", e))?;
-    let mut buffer = vec![0u8; 64 * 1024]; // 64KB chunks for hashing
+    let mut files = Vec::with_capacity(paths.len());
+    for path in paths {
+        let size = tokio::fs::metadata(&path).await.map(|m| m.len()).unwrap_or(0);
+        files.push((path, size));
+    }
 
-    loop {
-        let bytes_read = file
-            .read(&mut buffer)
-            .await
-            .map_err(|e| format!("Failed to read chunk for hashing: {}", e))?;
-        if bytes_read == 0 {
-            break;
+    let started = Instant::now();
+    let mut batch = BatchUpload::new(format!("batch-{}", uuid::Uuid::new_v4()), &files);
+    for (path, size) in &files {
+        let _ = app.emit(batch_upload::PROGRESS_EVENT, batch.progress(Some(path)));
+        let result = upload_path_to_network(
+            app.clone(),
+            state.clone(),
+            path.clone(),
+            price,
+            protocol.clone(),
+            None,
+            false,
+        )
+        .await;
+        if let Err(e) = &result {
+            warn!("Batch upload of {} failed: {}", path, e);
         }
-        hasher.update(&buffer[..bytes_read]);
+        batch.record(path, *size, result);
     }
+    let _ = app.emit(batch_upload::PROGRESS_EVENT, batch.progress(None));
 
-    let file_hash = format!("{:x}", hasher.finalize());
-    let file_size = tokio::fs::metadata(&file_path)
-        .await
-        .map_err(|e| format!("Failed to get file size: {}", e))?
-        .len();
+    let summary = batch.finish(started.elapsed());
+    info!(
+        "Batch upload {} finished: {} succeeded, {} failed",
+        summary.batch_id, summary.succeeded, summary.failed
+    );
+    Ok(summary)
+}
 
-    // Normalize protocol for robust matching (tests/users may send different casing like "Bitswap", "BitSwap", "BITSWAP").
-    let protocol_upper = protocol
-        .as_deref()
-        .unwrap_or("")
-        .trim()
-        .to_uppercase();
-    let dont_need_to_copy_protocols = vec!["BITSWAP", "WEBRTC"];
-    let mut file_path = file_path.clone();
+/// Publish the files of a batch upload from `root_dir` as a directory
+/// manifest and return its ID
+#[tauri::command]
+async fn publish_directory_manifest(
+    state: State<'_, AppState>,
+    name: String,
+    root_dir: String,
+    items: Vec<chiral_network::batch_upload::BatchUploadItem>,
+) -> Result<String, String> {
+    use chiral_network::directory_manifest::{self, DirectoryManifest};
 
-    // Handle protocol-specific uploads
-    if let Some(protocol_name) = &protocol {
-        if !dont_need_to_copy_protocols.contains(&protocol_upper.as_str()) {
-            // handle if error
-            file_path = copy_file_to_temp(file_path.clone())
-                .await
-                .map_err(|e| format!("Failed to copy file to temp: {}", e))?;
-        }
+    let dht = { state.dht.lock().await.as_ref().cloned() };
+    let dht = dht.ok_or_else(|| "DHT node is not running".to_string())?;
+    // Entries are downloaded by the merkle root their metadata is announced under
+    let mut files = Vec::new();
+    for item in items {
+        let Some(id) = item.file_hash else {
+            continue;
+        };
+        let metadata = library_file_metadata(&dht, &id)
+            .await
+            .ok_or_else(|| format!("{} is not published from this node", item.path))?;
+        files.push((
+            PathBuf::from(item.path),
+            metadata.merkle_root,
+            item.file_size,
+        ));
+    }
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let manifest = DirectoryManifest::new(name, Path::new(&root_dir), files, now)?;
+    let manifest_id = manifest.id()?;
+    dht.put_dht_value(directory_manifest::dht_key(&manifest_id), manifest.to_bytes()?)
+        .await?;
+    info!(
+        "Published directory manifest {} with {} entries",
+        manifest_id,
+        manifest.entries.len()
+    );
+    Ok(manifest_id)
+}
 
-        match protocol_upper.as_str() {
-            "HTTP" => {
-                let permanent_path = state.http_server_state.storage_dir.join(&file_hash);
-                // Move/rename temp file to permanent storage instead of copying
-                tokio::fs::rename(&file_path, &permanent_path)
-                    .await
-                    .map_err(|e| format!("Failed to move file to permanent storage: {}", e))?;
+async fn fetch_directory_manifest(
+    state: &AppState,
+    manifest_id: &str,
+) -> Result<chiral_network::directory_manifest::DirectoryManifest, String> {
+    use chiral_network::directory_manifest::{self, DirectoryManifest};
 
-                // Update file_path to point to the permanent location
-                let file_path = permanent_path.to_string_lossy().to_string();
+    let dht = { state.dht.lock().await.as_ref().cloned() };
+    let dht = dht.ok_or_else(|| "DHT node is not running".to_string())?;
+    let raw = dht
+        .get_dht_value(directory_manifest::dht_key(manifest_id))
+        .await?
+        .ok_or_else(|| format!("Directory manifest {} not found", manifest_id))?;
+    DirectoryManifest::from_dht(&raw, manifest_id)
+}
 
-                state
-                    .http_server_state
-                    .register_file(http_server::HttpFileMetadata {
-                        hash: file_hash.clone(),
-                        file_hash: file_hash.clone(),
-                        name: original_file_name.clone(),
-                        size: file_size,
-                        encrypted: false,
-                    })
-                    .await;
-            }
-            "BITTORRENT" => {
-                // Check if file exists before attempting to seed
-                if !std::path::Path::new(&file_path).exists() {
-                    error!(
-                        "BitTorrent seeding failed: File does not exist: {}",
-                        file_path
-                    );
-                    return Err(format!("File does not exist: {}", file_path));
-                }
+#[tauri::command]
+async fn get_directory_manifest(
+    state: State<'_, AppState>,
+    manifest_id: String,
+) -> Result<chiral_network::directory_manifest::DirectoryManifest, String> {
+    fetch_directory_manifest(&state, &manifest_id).await
+}
 
-                if !std::path::Path::new(&file_path).is_file() {
-                    error!(
-                        "BitTorrent seeding failed: Path is not a file: {}",
-                        file_path
-                    );
-                    return Err(format!("Path is not a file: {}", file_path));
-                }
+/// Download the entries of a directory manifest matching `selection` (paths,
+/// directory prefixes or globs; empty for everything) into `output_dir`,
+/// keeping their relative layout. Returns the plan right away; each entry
+/// then reports its progress on its own.
+#[tauri::command]
+async fn download_directory_manifest(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    manifest_id: String,
+    output_dir: String,
+    selection: Vec<String>,
+) -> Result<chiral_network::directory_manifest::DirectoryDownloadPlan, String> {
+    use chiral_network::directory_manifest;
+    use chiral_network::file_transfer::control::TransferPriority;
 
-                // Use torrent seeding
+    if !Path::new(&output_dir).is_dir() {
+        return Err(format!("Directory does not exist: {}", output_dir));
+    }
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    let dht = {
+        let dht_guard = state.dht.lock().await;
+        dht_guard.as_ref().cloned()
+    }
+    .ok_or("DHT not running")?;
+    let manifest = fetch_directory_manifest(&state, &manifest_id).await?;
+    let plan = manifest.plan(&manifest_id, &output_dir, &selection)?;
+    for entry in &plan.entries {
+        if let Some(parent) = plan.output_path(&entry.path).parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+    }
+    info!(
+        "Downloading {} of {} entries from directory manifest {}",
+        plan.entries.len(),
+        manifest.entries.len(),
+        manifest_id
+    );
+
+    // Files encrypted for this account are decrypted once they arrive
+    let private_key = state.active_account_private_key.lock().await.clone();
+    let start = {
+        let app = app.clone();
+        move |file_hash: String, output: PathBuf| {
+            let (app, ft, dht, private_key) =
+                (app.clone(), ft.clone(), dht.clone(), private_key.clone());
+            async move {
+                let providers = dht.get_seeders_for_file(&file_hash).await;
+                if providers.is_empty() {
+                    return Err(format!("No seeders available for {}", file_hash));
+                }
+                let requested = output.to_string_lossy();
+                let output = resolve_output_path(
+                    &app.state::<AppState>(),
+                    &requested,
+                    &file_hash,
+                    &file_hash,
+                )?
+                .ok_or_else(|| format!("{} already exists", requested))?;
+                ft.download_file_from_providers(
+                    file_hash,
+                    output,
+                    providers,
+                    None,
+                    TransferPriority::Normal,
+                    private_key,
+                )
+                .await
+            }
+        }
+    };
+    let report = move |changed: Vec<directory_manifest::DirectoryEntryProgress>| {
+        for progress in changed {
+            let _ = app.emit(directory_manifest::PROGRESS_EVENT, progress);
+        }
+    };
+    tauri::async_runtime::spawn(directory_manifest::run_plan(plan.clone(), start, report));
+
+    Ok(plan)
+}
+
+/// Push a local file to a storage provider's HTTP server, resuming any earlier
+/// push of the same file to that provider
+#[tauri::command]
+async fn push_file_to_provider(
+    app: tauri::AppHandle,
+    provider_url: String,
+    token: Option<String>,
+    file_path: String,
+) -> Result<chiral_network::push_upload::PushSession, String> {
+    use chiral_network::push_upload;
+
+    let path = PathBuf::from(&file_path);
+    let file_hash = HashAlgorithm::Sha256.hash_file(&path).await?.to_string();
+    let client = reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(10))
+        .timeout(Duration::from_secs(120))
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+    push_upload::push_file(
+        &client,
+        &provider_url,
+        token.as_deref(),
+        &path,
+        &file_hash,
+        |progress| {
+            let _ = app.emit(push_upload::PROGRESS_EVENT, progress);
+        },
+    )
+    .await
+}
+
+#[tauri::command]
+async fn get_push_receiver_config(
+    state: State<'_, AppState>,
+) -> Result<chiral_network::push_upload::PushReceiverConfig, String> {
+    Ok(state.http_server_state.push.config())
+}
+
+#[tauri::command]
+async fn set_push_receiver_config(
+    state: State<'_, AppState>,
+    config: chiral_network::push_upload::PushReceiverConfig,
+) -> Result<(), String> {
+    state.http_server_state.push.set_config(config)
+}
+
+/// Hash, register and publish one file over `protocol`; returns the id it
+/// is published under (its merkle root, info hash or ed2k hash). With
+/// `detach`, WebRTC uploads finish in the background and the SHA-256 content
+/// hash is returned instead.
+async fn upload_path_to_network(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    file_path: String,
+    price: Option<f64>,
+    protocol: Option<String>,
+    original_file_name: Option<String>,
+    detach: bool,
+) -> Result<String, String> {
+    // The default flow uploads through the file transfer service, which
+    // reports the upload itself; the other protocols are reported here
+    let seeded_elsewhere = matches!(
+        protocol
+            .as_deref()
+            .map(|p| p.trim().to_uppercase())
+            .as_deref(),
+        Some("HTTP" | "BITTORRENT" | "ED2K" | "FTP" | "BITSWAP")
+    );
+    let file_name = original_file_name.clone().unwrap_or_else(|| {
+        Path::new(&file_path)
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or("unknown")
+            .to_string()
+    });
+    let file_size = tokio::fs::metadata(&file_path).await.map_or(0, |m| m.len());
+    let result = share_path_over_protocol(
+        app,
+        state,
+        file_path,
+        price,
+        protocol,
+        original_file_name,
+        detach,
+    )
+    .await;
+    if let (Ok(published_id), true) = (&result, seeded_elsewhere) {
+        file_transfer::publish_external_upload(published_id, &file_name, file_size);
+    }
+    result
+}
+
+async fn share_path_over_protocol(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    file_path: String,
+    price: Option<f64>,
+    protocol: Option<String>,
+    original_file_name: Option<String>,
+    detach: bool,
+) -> Result<String, String> {
+    // Use provided original filename, or extract from path if not provided
+    let original_file_name = original_file_name.unwrap_or_else(|| {
+        Path::new(&file_path)
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or("unknown")
+            .to_string()
+    });
+
+    // Ensure price is never null - default to 0
+    let price = price.unwrap_or(0.0);
+
+    // Get the active account for uploader_address
+    let account = get_active_account(&state).await?;
+
+    // Calculate file hash without loading entire file into memory
+    let file_hash = HashAlgorithm::Sha256
+        .hash_file(Path::new(&file_path))
+        .await?
+        .to_string();
+    let file_size = tokio::fs::metadata(&file_path)
+        .await
+        .map_err(|e| format!("Failed to get file size: {}", e))?
+        .len();
+    let content_hash = file_hash.clone();
+
+    // Type and basic metadata (dimensions, duration, title) from the contents
+    let mut content_info = chiral_network::content_info::extract(Path::new(&file_path))
+        .await
+        .ok();
+    let mime_type = content_info.as_ref().and_then(|info| info.mime_type.clone());
+
+    // Thumbnails and preview clips for media files and excerpts of text files.
+    // Generated under the content hash so the manifest can list them, then
+    // moved under the published id once the file is announced.
+    if chiral_network::media_preview::MediaKind::from_path(Path::new(&file_path)).is_some() {
+        if let Some(previews) = chiral_network::media_preview::PreviewStore::open_default() {
+            match previews.generate(&file_hash, Path::new(&file_path)).await {
+                Ok(Some(preview)) => {
+                    content_info.get_or_insert_with(Default::default).preview =
+                        Some(preview.reference());
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Failed to generate previews for {}: {}", file_hash, e),
+            }
+        }
+    }
+
+    // Normalize protocol for robust matching (tests/users may send different casing like "Bitswap", "BitSwap", "BITSWAP").
+    let protocol_upper = protocol
+        .as_deref()
+        .unwrap_or("")
+        .trim()
+        .to_uppercase();
+    let dont_need_to_copy_protocols = vec!["BITSWAP", "WEBRTC"];
+    let mut file_path = file_path.clone();
+
+    // Handle protocol-specific uploads
+    if let Some(protocol_name) = &protocol {
+        if !dont_need_to_copy_protocols.contains(&protocol_upper.as_str()) {
+            // handle if error
+            file_path = copy_file_to_temp(file_path.clone())
+                .await
+                .map_err(|e| format!("Failed to copy file to temp: {}", e))?;
+        }
+
+        match protocol_upper.as_str() {
+            "HTTP" => {
+                // Move the temp file into storage instead of copying
+                state
+                    .http_server_state
+                    .store_file(&file_hash, std::path::Path::new(&file_path))
+                    .await
+                    .map_err(|e| format!("Failed to move file to permanent storage: {}", e))?;
+
+                state
+                    .http_server_state
+                    .register_file(http_server::HttpFileMetadata {
+                        hash: file_hash.clone(),
+                        file_hash: file_hash.clone(),
+                        name: original_file_name.clone(),
+                        size: file_size,
+                        encrypted: false,
+                    })
+                    .await;
+            }
+            "BITTORRENT" => {
+                // Check if file exists before attempting to seed
+                if !std::path::Path::new(&file_path).exists() {
+                    error!(
+                        "BitTorrent seeding failed: File does not exist: {}",
+                        file_path
+                    );
+                    return Err(format!("File does not exist: {}", file_path));
+                }
+
+                if !std::path::Path::new(&file_path).is_file() {
+                    error!(
+                        "BitTorrent seeding failed: Path is not a file: {}",
+                        file_path
+                    );
+                    return Err(format!("Path is not a file: {}", file_path));
+                }
+
+                // Use torrent seeding
                 let handler = state.bittorrent_handler.clone();
                 match create_and_seed_torrent_internal(file_path.clone(), handler).await {
                     Ok(magnet_link) => {
@@ -3856,7 +4798,7 @@ async fn upload_file_to_network(
                                 .duration_since(std::time::UNIX_EPOCH)
                                 .unwrap_or_default()
                                 .as_secs(),
-                            mime_type: None,
+                            mime_type: mime_type.clone(),
                             is_encrypted: false,
                             encryption_method: None,
                             key_fingerprint: None,
@@ -3886,8 +4828,9 @@ async fn upload_file_to_network(
                                 // Don't fail the upload, just log the warning
                             }
                         }
+                        key_previews_by_published_id(&file_hash, &metadata.merkle_root);
 
-                        return Ok(());
+                        return Ok(metadata.merkle_root);
                     }
                     Err(e) => {
                         return Err(format!("Failed to create torrent: {}", e));
@@ -3953,7 +4896,7 @@ async fn upload_file_to_network(
                                 .duration_since(std::time::UNIX_EPOCH)
                                 .unwrap_or_default()
                                 .as_secs(),
-                            mime_type: None,
+                            mime_type: mime_type.clone(),
                             is_encrypted: false,
                             encryption_method: None,
                             key_fingerprint: None,
@@ -3993,8 +4936,9 @@ async fn upload_file_to_network(
                                 // Don't fail the upload, just log the warning
                             }
                         }
+                        key_previews_by_published_id(&file_hash, &metadata.merkle_root);
 
-                        return Ok(());
+                        return Ok(metadata.merkle_root);
                     }
                     Err(e) => {
                         println!("❌ ED2K seeding failed: {}", e);
@@ -4055,6 +4999,7 @@ async fn upload_file_to_network(
                     merkle_root: file_hash.clone(),
                     chunks: manifest_chunks,
                     encrypted_key_bundle: None,
+                    content_info: content_info.clone(),
                 };
                 let manifest_json = serde_json::to_string(&file_manifest)
                     .map_err(|e| format!("Failed to serialize FileManifest: {}", e))?;
@@ -4082,7 +5027,7 @@ async fn upload_file_to_network(
                         .duration_since(std::time::UNIX_EPOCH)
                         .unwrap_or_default()
                         .as_secs(),
-                    mime_type: None,
+                    mime_type: mime_type.clone(),
                     is_encrypted: false,
                     encryption_method: None,
                     key_fingerprint: None,
@@ -4125,7 +5070,7 @@ async fn upload_file_to_network(
                 }
 
                 println!("✅ FTP upload complete - file available at: {}", ftp_url);
-                return Ok(());
+                return Ok(content_hash);
             }
             "BITSWAP" => {
                 // Use streaming upload for Bitswap to handle large files
@@ -4214,6 +5159,7 @@ async fn upload_file_to_network(
                 }
 
                 // After all chunks are uploaded, finalize the metadata
+                let mut published_id = None;
                 let mut upload_sessions = state.upload_sessions.lock().await;
                 if let Some(session) = upload_sessions.get_mut(&upload_id) {
                     if session.is_complete {
@@ -4297,6 +5243,7 @@ async fn upload_file_to_network(
                             merkle_root: merkle_root.clone(),
                             chunks: manifest_chunks,
                             encrypted_key_bundle: None,
+                            content_info: content_info.clone(),
                         };
 
                         // Serialize manifest to JSON
@@ -4314,7 +5261,7 @@ async fn upload_file_to_network(
                                 vec![local_peer_id.clone()]
                             },
                             created_at,
-                            mime_type: None,
+                            mime_type: mime_type.clone(),
                             is_encrypted: false,
                             encryption_method: None,
                             key_fingerprint: None,
@@ -4347,9 +5294,11 @@ async fn upload_file_to_network(
                         } else {
                             return Err("DHT not running".into());
                         }
+                        key_previews_by_published_id(&content_hash, &merkle_root);
 
                         let file_hash = root_cid.to_string();
                         println!("✅ Bitswap streaming upload completed: {}", file_hash);
+                        published_id = Some(merkle_root);
 
                         // Clean up session
                         upload_sessions.remove(&upload_id);
@@ -4357,7 +5306,8 @@ async fn upload_file_to_network(
                 }
                 drop(upload_sessions);
 
-                return Ok(());
+                return published_id
+                    .ok_or_else(|| format!("Bitswap upload of {} did not complete", file_path));
             }
             _ => {
                 // WebRTC and other protocols use the default Chiral flow
@@ -4390,9 +5340,10 @@ async fn upload_file_to_network(
                 // Get local peer ID to add as seeder
                 let local_peer_id = dht.get_peer_id().await;
 
-                // Spawn background task - return immediately to avoid callback timeout
-                tokio::spawn(async move {
-                    let result: Result<(), String> = async {
+                // Spawn background task so a detached upload returns immediately,
+                // avoiding callback timeouts
+                let publish = tokio::spawn(async move {
+                    let result: Result<String, String> = async {
                         let c = file_path.clone();
                         let file_name = Path::new(&c)
                             .file_name()
@@ -4434,8 +5385,9 @@ async fn upload_file_to_network(
                         .await
                         .map_err(|e| format!("Failed to spawn blocking task: {}", e))?;
 
-                        let file_manifest = file_manifest_result
+                        let mut file_manifest = file_manifest_result
                             .map_err(|e| format!("Failed to create FileManifest: {}", e))?;
+                        file_manifest.manifest.content_info = content_info.clone();
 
                         // Serialize manifest to JSON
                         let manifest_json = serde_json::to_string(&file_manifest.manifest)
@@ -4454,7 +5406,7 @@ async fn upload_file_to_network(
                             file_data: vec![],
                             seeders: vec![local_peer_id.clone()],
                             created_at,
-                            mime_type: None,
+                            mime_type: mime_type.clone(),
                             is_encrypted: false,
                             encryption_method: None,
                             key_fingerprint: None,
@@ -4473,6 +5425,7 @@ async fn upload_file_to_network(
                         };
 
                         dht.publish_file(metadata.clone(), None).await?;
+                        key_previews_by_published_id(&file_hash, &metadata.merkle_root);
 
                         ft.store_file_data(
                             file_hash.clone(),
@@ -4480,29 +5433,278 @@ async fn upload_file_to_network(
                             file_data.clone(),
                         )
                         .await;
+                        if let Err(e) = ft
+                            .record_published_id(&metadata.merkle_root, &file_hash)
+                            .await
+                        {
+                            warn!(
+                                "Stored copy of {} is not found by its merkle root: {}",
+                                file_name, e
+                            );
+                        }
 
                         info!(
                             "WebRTC upload complete: {} (merkle_root: {})",
                             file_name, metadata.merkle_root
                         );
 
-                        Ok(())
+                        Ok(metadata.merkle_root)
                     }
                     .await;
 
-                    if let Err(e) = result {
+                    if let Err(e) = &result {
                         error!("WebRTC upload failed: {}", e);
                     }
+                    result
                 });
 
-                // Return immediately - frontend will receive published_file event when done
-                return Ok(());
+                if detach {
+                    // Frontend will receive published_file event when done
+                    return Ok(content_hash);
+                }
+                return publish
+                    .await
+                    .map_err(|e| format!("WebRTC upload task failed: {}", e))?;
             }
         }
     }
 
     // This code path should no longer be reached for WebRTC uploads
-    Err("Unexpected code path in upload_file_to_network".to_string())
+    Err("Unexpected code path in share_path_over_protocol".to_string())
+}
+
+/// Move the previews generated under a file's content hash to the id it was
+/// published under
+fn key_previews_by_published_id(content_hash: &str, published_id: &str) {
+    if let Some(previews) = chiral_network::media_preview::PreviewStore::open_default() {
+        if let Err(e) = previews.rekey(content_hash, published_id) {
+            warn!("Failed to move previews of {}: {}", content_hash, e);
+        }
+    }
+}
+
+/// Drop the previews of a file that is no longer published
+fn remove_previews(file_hash: &str) {
+    if let Some(previews) = chiral_network::media_preview::PreviewStore::open_default() {
+        if let Err(e) = previews.remove(file_hash) {
+            warn!("Failed to remove previews of {}: {}", file_hash, e);
+        }
+    }
+}
+
+/// Stop watching and re-announcing a file that is no longer stored, and
+/// drop its previews
+fn forget_stored_file(state: &AppState, file_hash: &str) {
+    state.shared_files.unregister(file_hash);
+    state.reannouncer.unpin(file_hash);
+    remove_previews(file_hash);
+}
+
+/// React to a shared-in-place file changing on disk: withdraw the old
+/// announcement, and share modified files again with their new contents
+async fn handle_shared_file_change(
+    app: &tauri::AppHandle,
+    change: chiral_network::shared_file_watcher::SharedFileChange,
+) {
+    use chiral_network::shared_file_watcher::{publish, SharedFileChange, SharedFileEvent};
+
+    let (file, new_content_hash) = match change {
+        SharedFileChange::Deleted { file } => (file, None),
+        SharedFileChange::Modified {
+            file,
+            new_content_hash,
+            ..
+        } => (file, Some(new_content_hash)),
+    };
+    let path = file.path.to_string_lossy().to_string();
+    match &new_content_hash {
+        Some(new_content_hash) => {
+            info!("Shared file {} changed on disk", path);
+            publish(SharedFileEvent::Modified {
+                file_hash: file.file_hash.clone(),
+                path: path.clone(),
+                old_content_hash: file.content_hash.clone(),
+                new_content_hash: new_content_hash.clone(),
+            });
+        }
+        None => {
+            warn!("Shared file {} was deleted", path);
+            publish(SharedFileEvent::Deleted {
+                file_hash: file.file_hash.clone(),
+                path: path.clone(),
+            });
+        }
+    }
+
+    // Peers must not be sent the changed bytes under the old hash
+    let state = app.state::<AppState>();
+    let dht = { state.dht.lock().await.as_ref().cloned() };
+    let ft = { state.file_transfer.lock().await.as_ref().cloned() };
+    state.reannouncer.unpin(&file.file_hash);
+    let announced = match &dht {
+        Some(dht) => {
+            let announced = dht.cached_file_metadata(&file.file_hash).await;
+            if let Err(e) = dht.stop_publishing_file(file.file_hash.clone()).await {
+                warn!("Failed to stop publishing {}: {}", file.file_hash, e);
+            }
+            announced
+        }
+        None => None,
+    };
+    // The index entry points at bytes that are gone; the file itself is
+    // never deleted through a link
+    if let Some(ft) = &ft {
+        if let Err(e) = ft.remove_file(&file.file_hash).await {
+            warn!("Failed to drop stale index entry {}: {}", file.file_hash, e);
+        }
+    }
+
+    if new_content_hash.is_none() {
+        publish(SharedFileEvent::Retracted {
+            file_hash: file.file_hash,
+            path,
+            reason: "Source file was deleted".to_string(),
+        });
+        return;
+    }
+    let republished = async {
+        let new_file_hash = share_file_in_place(&state, path.clone()).await?;
+        if let (Some(dht), Some(mut metadata)) = (dht, announced) {
+            // Chunk map and CIDs described the old contents
+            metadata.merkle_root = new_file_hash.clone();
+            metadata.file_size = tokio::fs::metadata(&path)
+                .await
+                .map(|m| m.len())
+                .unwrap_or(metadata.file_size);
+            metadata.file_data.clear();
+            metadata.manifest = None;
+            metadata.cids = None;
+            dht.publish_file(metadata, None).await?;
+        }
+        Ok::<_, String>(new_file_hash)
+    }
+    .await;
+    match republished {
+        Ok(new_file_hash) => {
+            publish(SharedFileEvent::Republished {
+                old_file_hash: file.file_hash,
+                new_file_hash,
+                path,
+            });
+        }
+        Err(e) => {
+            warn!("Failed to republish changed file {}: {}", path, e);
+            publish(SharedFileEvent::Retracted {
+                file_hash: file.file_hash,
+                path,
+                reason: format!("Source file was modified and could not be republished: {}", e),
+            });
+        }
+    }
+}
+
+/// Index `file_path` where it is and watch it for changes. Returns its hash.
+async fn share_file_in_place(state: &AppState, file_path: String) -> Result<String, String> {
+    let file_name = Path::new(&file_path)
+        .file_name()
+        .and_then(|s| s.to_str())
+        .ok_or_else(|| format!("Invalid file path: {}", file_path))?
+        .to_string();
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    let file_hash = ft
+        .upload_file_in_place(file_path.clone(), file_name.clone())
+        .await?;
+    if let Err(e) = state
+        .shared_files
+        .register(file_hash.clone(), file_path.clone(), file_name)
+        .await
+    {
+        warn!("Not watching shared file {}: {}", file_path, e);
+    }
+    Ok(file_hash)
+}
+
+/// Watch the source of a linked blob so changes to it are noticed. If it
+/// already changed since it was linked, the blob is dropped instead.
+async fn watch_linked_blob(
+    state: &AppState,
+    ft: &FileTransferService,
+    entry: &chiral_network::blob_store::BlobEntry,
+) {
+    use chiral_network::blob_store::LinkedFile;
+    let Some(linked) = &entry.linked else {
+        return;
+    };
+    let unchanged = LinkedFile::stat(&linked.path)
+        .await
+        .is_ok_and(|(now, size)| now == *linked && size == entry.size);
+    if !unchanged {
+        warn!(
+            "Shared file {} changed while it was not watched",
+            linked.path.display()
+        );
+        if let Err(e) = ft.remove_file(&entry.hash).await {
+            warn!("Failed to drop stale index entry {}: {}", entry.hash, e);
+        }
+        return;
+    }
+    let file_name = linked
+        .path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| entry.hash.clone());
+    if let Err(e) = state
+        .shared_files
+        .register(entry.hash.clone(), linked.path.clone(), file_name)
+        .await
+    {
+        warn!("Not watching shared file {}: {}", linked.path.display(), e);
+    }
+}
+
+/// Thumbnail/preview clip index for a file, if previews were generated
+#[tauri::command]
+async fn get_file_preview(
+    file_hash: String,
+) -> Result<Option<chiral_network::media_preview::PreviewInfo>, String> {
+    Ok(chiral_network::media_preview::PreviewStore::open_default()
+        .and_then(|store| store.get(&file_hash)))
+}
+
+/// A file's preview ready to display: thumbnail as a data URL, text excerpt
+/// inline
+#[tauri::command]
+async fn get_preview(
+    file_hash: String,
+) -> Result<Option<chiral_network::media_preview::FilePreview>, String> {
+    Ok(chiral_network::media_preview::PreviewStore::open_default()
+        .and_then(|store| store.load(&file_hash)))
+}
+
+/// One preview asset (e.g. "thumbnail.jpg"), base64-encoded
+#[tauri::command]
+async fn get_file_preview_asset(file_hash: String, asset: String) -> Result<String, String> {
+    use base64::{engine::general_purpose, Engine as _};
+
+    let path = chiral_network::media_preview::PreviewStore::open_default()
+        .and_then(|store| store.asset_path(&file_hash, &asset))
+        .ok_or_else(|| format!("No preview {} for {}", asset, file_hash))?;
+    let data = tokio::fs::read(&path)
+        .await
+        .map_err(|e| format!("Failed to read preview: {}", e))?;
+    Ok(general_purpose::STANDARD.encode(&data))
+}
+
+/// Files shared in place that are being watched for changes
+#[tauri::command]
+async fn list_watched_shared_files(
+    state: State<'_, AppState>,
+) -> Result<Vec<chiral_network::shared_file_watcher::SharedFile>, String> {
+    Ok(state.shared_files.list())
 }
 /// List files in an FTP directory
 #[tauri::command]
@@ -5333,17 +6535,617 @@ async fn download_blocks_from_network(
     }
 }
 
-#[tauri::command]
-async fn download_file_from_network(
-    state: State<'_, AppState>,
-    file_hash: String,
-    output_path: String, // Remove the underscore - we'll use this now
-) -> Result<String, String> {
-    use std::path::Path;
-
-    // ✅ VALIDATE OUTPUT PATH BEFORE STARTING DOWNLOAD
-    let path = Path::new(&output_path);
-
+/// Where a download asked to go to `output_path` is written. A directory
+/// gets a file named from `file_name` with the template from settings; an
+/// existing file is renamed around, overwritten or kept as the collision
+/// policy says. `None` means the file is kept and nothing is downloaded.
+fn resolve_output_path(
+    state: &AppState,
+    output_path: &str,
+    file_name: &str,
+    file_hash: &str,
+) -> Result<Option<String>, String> {
+    use chiral_network::file_transfer::naming::{self, NamingRules, OutputTarget};
+    let rules = state.settings.get().naming_rules();
+    let path = Path::new(output_path);
+    let target = if path.is_dir() {
+        naming::resolve(path, file_name, file_hash, &rules)?
+    } else {
+        // The caller picked the name; only collisions are up to the rules
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let file_name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or_else(|| format!("Invalid file path: {}", output_path))?;
+        let rules = NamingRules {
+            template: naming::DEFAULT_TEMPLATE.to_string(),
+            ..rules
+        };
+        naming::resolve(dir, file_name, file_hash, &rules)?
+    };
+    Ok(match target {
+        OutputTarget::Write(path) => Some(path.to_string_lossy().into_owned()),
+        OutputTarget::Skip(path) => {
+            info!(
+                "Not downloading {}: {} already exists",
+                file_hash,
+                path.display()
+            );
+            None
+        }
+    })
+}
+
+/// Download a file straight from the given peers over the file transfer
+/// protocol, spreading its chunks across them. Returns the transfer ID.
+/// `retry_policy` replaces the default retry behaviour for this download.
+/// With `streaming` the file is fetched from the start onwards and
+/// `file_transfer:stream_readable` says how much of the part file can be
+/// played already.
+#[tauri::command]
+async fn download_file_from_peers(
+    state: State<'_, AppState>,
+    file_hash: String,
+    output_path: String,
+    peer_ids: Vec<String>,
+    retry_policy: Option<file_transfer::retry::RetryPolicy>,
+    priority: Option<file_transfer::control::TransferPriority>,
+    streaming: Option<bool>,
+) -> Result<String, String> {
+    if peer_ids.is_empty() {
+        return Err("No peers given to download from".to_string());
+    }
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    let output_path = resolve_output_path(&state, &output_path, &file_hash, &file_hash)?
+        .ok_or_else(|| format!("{} already exists", output_path))?;
+    // Files encrypted for this account are decrypted once they arrive
+    let private_key = state.active_account_private_key.lock().await.clone();
+    if streaming.unwrap_or(false) {
+        return ft
+            .stream_file_from_providers(
+                file_hash,
+                output_path,
+                peer_ids,
+                retry_policy,
+                priority.unwrap_or_default(),
+                private_key,
+            )
+            .await;
+    }
+    ft.download_file_from_providers(
+        file_hash,
+        output_path,
+        peer_ids,
+        retry_policy,
+        priority.unwrap_or_default(),
+        private_key,
+    )
+    .await
+}
+
+/// Download a file into the download directory, or `output_dir` when given,
+/// naming it with the template and collision policy from settings.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn download_file_to_directory(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    file_hash: String,
+    file_name: String,
+    peer_ids: Vec<String>,
+    output_dir: Option<String>,
+    retry_policy: Option<file_transfer::retry::RetryPolicy>,
+    priority: Option<file_transfer::control::TransferPriority>,
+) -> Result<NamedDownload, String> {
+    if peer_ids.is_empty() {
+        return Err("No peers given to download from".to_string());
+    }
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    let dir = match output_dir {
+        Some(dir) => dir,
+        None => download_paths::get_download_directory(&app)?,
+    };
+    let rules = state.settings.get().naming_rules();
+    let private_key = state.active_account_private_key.lock().await.clone();
+    ft.download_file_named(
+        file_hash,
+        &file_name,
+        Path::new(&dir),
+        &rules,
+        peer_ids,
+        retry_policy,
+        priority.unwrap_or_default(),
+        private_key,
+    )
+    .await
+}
+
+/// Download a list of files, e.g. the entries of a folder manifest, as one
+/// batch. Returns the batch ID; `file_transfer:batch_progress` follows each
+/// file and `file_transfer:batch_completed` the whole batch.
+#[tauri::command]
+async fn download_batch(
+    state: State<'_, AppState>,
+    items: Vec<file_transfer::batch::BatchItem>,
+    peer_ids: Vec<String>,
+    retry_policy: Option<file_transfer::retry::RetryPolicy>,
+    priority: Option<file_transfer::control::TransferPriority>,
+) -> Result<String, String> {
+    if peer_ids.is_empty() {
+        return Err("No peers given to download from".to_string());
+    }
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    let private_key = state.active_account_private_key.lock().await.clone();
+    ft.download_batch(
+        items,
+        peer_ids,
+        retry_policy,
+        priority.unwrap_or_default(),
+        private_key,
+    )
+    .await
+}
+
+/// Keep a stored file when storage is trimmed or garbage collected
+#[tauri::command]
+async fn pin_file(
+    state: State<'_, AppState>,
+    file_hash: String,
+) -> Result<chiral_network::blob_store::BlobEntry, String> {
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    ft.pin_file(&file_hash).await
+}
+
+#[tauri::command]
+async fn unpin_file(
+    state: State<'_, AppState>,
+    file_hash: String,
+) -> Result<chiral_network::blob_store::BlobEntry, String> {
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    ft.unpin_file(&file_hash).await
+}
+
+/// Stored files for the "My Files" table: name, size, upload time, MIME
+/// type, pin state and how often each was served
+#[tauri::command]
+async fn get_stored_files(
+    state: State<'_, AppState>,
+) -> Result<Vec<file_transfer::StoredFile>, String> {
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    ft.get_stored_files().await
+}
+
+/// Every stored file with its size and pin state
+#[tauri::command]
+async fn get_stored_blobs(
+    state: State<'_, AppState>,
+) -> Result<Vec<chiral_network::blob_store::BlobEntry>, String> {
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    Ok(ft.stored_blobs().await)
+}
+
+/// Stop seeding a stored file and delete it. Completion is reported with
+/// the `file_removed` event.
+#[tauri::command]
+async fn remove_stored_file(state: State<'_, AppState>, file_hash: String) -> Result<(), String> {
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    // Keep it from being announced again
+    forget_stored_file(&state, &file_hash);
+    ft.remove_file(&file_hash).await
+}
+
+/// Store a file and return its hash once it is stored
+#[tauri::command]
+async fn upload_file_and_get_hash(
+    state: State<'_, AppState>,
+    file_path: String,
+) -> Result<String, String> {
+    let file_name = Path::new(&file_path)
+        .file_name()
+        .and_then(|s| s.to_str())
+        .ok_or_else(|| format!("Invalid file path: {}", file_path))?
+        .to_string();
+    let account = state.active_account.lock().await.clone();
+    let private_key = state.active_account_private_key.lock().await.clone();
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    ft.upload_file_and_wait(file_path, file_name, account, private_key)
+        .await
+}
+
+/// Share a file from where it is on disk instead of copying it into
+/// storage. Returns its hash once it is indexed.
+#[tauri::command]
+async fn upload_file_in_place(
+    state: State<'_, AppState>,
+    file_path: String,
+) -> Result<String, String> {
+    share_file_in_place(&state, file_path).await
+}
+
+/// Store a file that is deleted again once `expires_at` (Unix seconds) has
+/// passed. Returns the transfer ID; the hash arrives with `file_uploaded`.
+#[tauri::command]
+async fn upload_file_with_expiry(
+    state: State<'_, AppState>,
+    file_path: String,
+    expires_at: u64,
+) -> Result<String, String> {
+    let file_name = Path::new(&file_path)
+        .file_name()
+        .and_then(|s| s.to_str())
+        .ok_or_else(|| format!("Invalid file path: {}", file_path))?
+        .to_string();
+    let account = state.active_account.lock().await.clone();
+    let private_key = state.active_account_private_key.lock().await.clone();
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    ft.upload_file_with_expiry(file_path, file_name, expires_at, account, private_key)
+        .await
+}
+
+/// Set when a stored file expires, or make it permanent with `None`
+#[tauri::command]
+async fn set_file_expiry(
+    state: State<'_, AppState>,
+    file_hash: String,
+    expires_at: Option<u64>,
+) -> Result<chiral_network::blob_store::BlobEntry, String> {
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    ft.set_file_expiry(&file_hash, expires_at).await
+}
+
+/// Delete unpinned stored files, oldest first, until they take at most
+/// `max_bytes`. Returns the hashes of the files deleted.
+#[tauri::command]
+async fn trim_file_storage(
+    state: State<'_, AppState>,
+    max_bytes: u64,
+) -> Result<Vec<String>, String> {
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    ft.trim_storage(max_bytes).await
+}
+
+/// X25519 public key others encrypt files for this account with, hex
+#[tauri::command]
+async fn get_recipient_public_key(state: State<'_, AppState>) -> Result<String, String> {
+    let private_key = state
+        .active_account_private_key
+        .lock()
+        .await
+        .clone()
+        .ok_or("No account is currently active. Please log in.")?;
+    let public_key = file_transfer::recipient::public_key_for(&private_key)?;
+    Ok(hex::encode(public_key.as_bytes()))
+}
+
+/// Store a file encrypted for one recipient, who is the only one able to
+/// read it after downloading. Its hash, the hash to announce and share,
+/// arrives with the `file_uploaded` event. Returns the transfer ID.
+#[tauri::command]
+async fn upload_file_for_recipient(
+    state: State<'_, AppState>,
+    file_path: String,
+    recipient_public_key: String,
+) -> Result<String, String> {
+    let file_name = Path::new(&file_path)
+        .file_name()
+        .and_then(|s| s.to_str())
+        .ok_or_else(|| format!("Invalid file path: {}", file_path))?
+        .to_string();
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    ft.upload_file_for_recipient(file_path, file_name, recipient_public_key)
+        .await
+}
+
+/// Share a whole directory under one identifier, the hash of its signed
+/// manifest, reported by the `folder_uploaded` event. Returns the transfer
+/// ID.
+#[tauri::command]
+async fn upload_directory(state: State<'_, AppState>, dir_path: String) -> Result<String, String> {
+    let account = get_active_account(&state).await?;
+    let private_key = {
+        let key_guard = state.active_account_private_key.lock().await;
+        key_guard
+            .clone()
+            .ok_or("No private key available. Please log in again.")?
+    };
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    ft.upload_directory(dir_path, Some(account), Some(private_key))
+        .await
+}
+
+/// Download a shared folder into `output_dir`, one download per file.
+/// Returns the transfer ID of the manifest download.
+#[tauri::command]
+async fn download_directory(
+    state: State<'_, AppState>,
+    manifest_hash: String,
+    output_dir: String,
+    peer_ids: Option<Vec<String>>,
+    retry_policy: Option<file_transfer::retry::RetryPolicy>,
+    priority: Option<file_transfer::control::TransferPriority>,
+) -> Result<String, String> {
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    ft.download_directory(
+        manifest_hash,
+        output_dir,
+        peer_ids.unwrap_or_default(),
+        retry_policy,
+        priority.unwrap_or_default(),
+    )
+    .await
+}
+
+/// Stop a running download started by one of the download commands
+#[tauri::command]
+async fn cancel_file_transfer(
+    state: State<'_, AppState>,
+    transfer_id: String,
+) -> Result<(), String> {
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    ft.cancel_transfer(transfer_id).await
+}
+
+/// Halt a running download without losing what it has fetched so far
+#[tauri::command]
+async fn pause_file_transfer(
+    state: State<'_, AppState>,
+    transfer_id: String,
+) -> Result<(), String> {
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    ft.pause_transfer(transfer_id).await
+}
+
+/// Reorder a download that is still waiting for a free transfer slot
+#[tauri::command]
+async fn set_file_transfer_priority(
+    state: State<'_, AppState>,
+    transfer_id: String,
+    priority: file_transfer::control::TransferPriority,
+) -> Result<(), String> {
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    ft.set_transfer_priority(transfer_id, priority).await
+}
+
+/// Saves the limit in the settings and applies it to the running service
+#[tauri::command]
+async fn set_max_concurrent_file_transfers(
+    state: State<'_, AppState>,
+    limit: usize,
+) -> Result<(), String> {
+    state
+        .settings
+        .update(serde_json::json!({ "maxConcurrentTransfers": limit }))?;
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    };
+    match ft {
+        Some(ft) => ft.set_max_concurrent_transfers(limit).await,
+        None => Ok(()),
+    }
+}
+
+/// Hash algorithm that names newly uploaded files
+#[tauri::command]
+async fn get_file_hash_algorithm(
+    state: State<'_, AppState>,
+) -> Result<file_transfer::hashing::HashAlgorithm, String> {
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    Ok(ft.hash_algorithm().await)
+}
+
+#[tauri::command]
+async fn set_file_hash_algorithm(
+    state: State<'_, AppState>,
+    algorithm: file_transfer::hashing::HashAlgorithm,
+) -> Result<(), String> {
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    ft.set_hash_algorithm(algorithm).await;
+    Ok(())
+}
+
+/// Whether stored files are kept encrypted on disk
+#[tauri::command]
+async fn get_encryption_at_rest(state: State<'_, AppState>) -> Result<bool, String> {
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    Ok(ft.encrypts_at_rest().await)
+}
+
+/// Encrypt or decrypt every stored file with a key derived from the active
+/// account. Returns how many files were rewritten.
+#[tauri::command]
+async fn set_encryption_at_rest(
+    state: State<'_, AppState>,
+    enabled: bool,
+) -> Result<usize, String> {
+    let private_key = state
+        .active_account_private_key
+        .lock()
+        .await
+        .clone()
+        .ok_or("No account is currently active. Please log in.")?;
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    ft.set_encryption_at_rest(enabled, &private_key).await
+}
+
+/// Whether stored files are split into chunks shared between files
+#[tauri::command]
+async fn get_storage_deduplication(state: State<'_, AppState>) -> Result<bool, String> {
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    Ok(ft.deduplicates_storage().await)
+}
+
+/// Move every stored file into the deduplicated chunk store or back out.
+/// Returns how many files were moved.
+#[tauri::command]
+async fn set_storage_deduplication(
+    state: State<'_, AppState>,
+    enabled: bool,
+) -> Result<usize, String> {
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    ft.set_storage_deduplication(enabled).await
+}
+
+#[tauri::command]
+async fn get_storage_dedup_stats(
+    state: State<'_, AppState>,
+) -> Result<chiral_network::chunk_store::DedupStats, String> {
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    Ok(ft.storage_dedup_stats().await)
+}
+
+#[tauri::command]
+async fn verify_storage(state: State<'_, AppState>, repair: bool) -> Result<ScrubReport, String> {
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    ft.verify_storage(repair).await
+}
+
+#[tauri::command]
+async fn set_scrub_schedule(
+    state: State<'_, AppState>,
+    interval_secs: Option<u64>,
+    repair: bool,
+) -> Result<(), String> {
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    let schedule = interval_secs.map(|interval_secs| ScrubSchedule {
+        interval_secs,
+        repair,
+    });
+    ft.set_scrub_schedule(schedule).await
+}
+
+#[tauri::command]
+async fn resume_file_transfer(
+    state: State<'_, AppState>,
+    transfer_id: String,
+) -> Result<(), String> {
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    ft.resume_transfer(transfer_id).await
+}
+
+#[tauri::command]
+async fn download_file_from_network(
+    state: State<'_, AppState>,
+    file_hash: String,
+    output_path: String, // Remove the underscore - we'll use this now
+) -> Result<String, String> {
+    use std::path::Path;
+
+    // ✅ VALIDATE OUTPUT PATH BEFORE STARTING DOWNLOAD
+    let path = Path::new(&output_path);
+
     // Check if parent directory exists
     if let Some(parent) = path.parent() {
         if !parent.exists() {
@@ -5410,15 +7212,15 @@ async fn download_file_from_network(
                     // Record requested output path so the WebRTC assembler can respect it.
                     // Bitswap already uses the passed output_path directly, but WebRTC assembles in webrtc_service.rs.
                     {
-                        // If the caller passed a directory path, write into it using the metadata file name.
-                        let resolved = {
-                            let p = std::path::PathBuf::from(&output_path);
-                            if p.exists() && p.is_dir() {
-                                p.join(&metadata.file_name).to_string_lossy().to_string()
-                            } else {
-                                output_path.clone()
-                            }
-                        };
+                        // A directory gets the metadata file name, and an existing file is
+                        // not overwritten unless the collision policy says so
+                        let resolved = resolve_output_path(
+                            &state,
+                            &output_path,
+                            &metadata.file_name,
+                            &metadata.merkle_root,
+                        )?
+                        .ok_or_else(|| format!("{} already exists", output_path))?;
 
                         webrtc_service::set_requested_download_output_path(
                             metadata.merkle_root.clone(),
@@ -6319,17 +8121,29 @@ async fn get_file_transfer_events(state: State<'_, AppState>) -> Result<Vec<Stri
                 FileTransferEvent::FileUploaded {
                     file_hash,
                     file_name,
+                    ..
                 } => {
                     format!("file_uploaded:{}:{}", file_hash, file_name)
                 }
-                FileTransferEvent::FileDownloaded { file_path } => {
+                FileTransferEvent::FileDownloaded { file_path, .. } => {
                     format!("file_downloaded:{}", file_path)
                 }
-                FileTransferEvent::FileNotFound { file_hash } => {
+                FileTransferEvent::FolderUploaded {
+                    manifest_hash,
+                    name,
+                    file_count,
+                    ..
+                } => format!("folder_uploaded:{}:{}:{}", manifest_hash, name, file_count),
+                FileTransferEvent::FolderQueued {
+                    transfer_id,
+                    file_count,
+                    ..
+                } => format!("folder_queued:{}:{}", transfer_id, file_count),
+                FileTransferEvent::FileNotFound { file_hash, .. } => {
                     format!("file_not_found:{}", file_hash)
                 }
-                FileTransferEvent::Error { message } => {
-                    format!("error:{}", message)
+                FileTransferEvent::Error { code, message, .. } => {
+                    format!("error:{}: {}", code, message)
                 }
                 FileTransferEvent::DownloadAttempt(snapshot) => {
                     match serde_json::to_string(&snapshot) {
@@ -6337,6 +8151,74 @@ async fn get_file_transfer_events(state: State<'_, AppState>) -> Result<Vec<Stri
                         Err(_) => "download_attempt:{}".to_string(),
                     }
                 }
+                FileTransferEvent::Progress(progress) => match serde_json::to_string(&progress) {
+                    Ok(json) => format!("progress:{}", json),
+                    Err(_) => "progress:{}".to_string(),
+                },
+                FileTransferEvent::Cancelled { transfer_id } => {
+                    format!("cancelled:{}", transfer_id)
+                }
+                FileTransferEvent::Paused {
+                    transfer_id,
+                    bytes_done,
+                    total_bytes,
+                } => format!("paused:{}:{}/{}", transfer_id, bytes_done, total_bytes),
+                FileTransferEvent::Resumed { transfer_id } => {
+                    format!("resumed:{}", transfer_id)
+                }
+                FileTransferEvent::Queued {
+                    transfer_id,
+                    position,
+                } => format!("queued:{}:{}", transfer_id, position),
+                FileTransferEvent::IntegrityFailed {
+                    transfer_id,
+                    expected_hash,
+                    actual_hash,
+                    ..
+                } => format!(
+                    "integrity_failed:{}:{}:{}",
+                    transfer_id, expected_hash, actual_hash
+                ),
+                FileTransferEvent::FileExpired { file_hash, .. } => {
+                    format!("file_expired:{}", file_hash)
+                }
+                FileTransferEvent::FileRemoved { file_hash, .. } => {
+                    format!("file_removed:{}", file_hash)
+                }
+                FileTransferEvent::BatchProgress(progress) => format!(
+                    "batch_progress:{}:{}:{}:{}",
+                    progress.batch_id,
+                    progress.files_done,
+                    progress.files_failed,
+                    progress.file_count
+                ),
+                FileTransferEvent::BatchCompleted {
+                    batch_id,
+                    file_count,
+                    failed,
+                } => format!(
+                    "batch_completed:{}:{}:{}",
+                    batch_id,
+                    file_count,
+                    failed.len()
+                ),
+                FileTransferEvent::StorageScrubbed(report) => format!(
+                    "storage_scrubbed:{}:{}",
+                    report.checked,
+                    report.corrupted.len()
+                ),
+                FileTransferEvent::StreamReadable {
+                    transfer_id,
+                    readable_bytes,
+                    total_bytes,
+                    ..
+                } => format!(
+                    "stream_readable:{}:{}/{}",
+                    transfer_id, readable_bytes, total_bytes
+                ),
+                FileTransferEvent::CircuitOpen(open) => {
+                    format!("circuit_open:{}:{}", open.file_hash, open.reopens_at)
+                }
             })
             .collect();
         Ok(mapped)
@@ -6348,47 +8230,253 @@ async fn get_file_transfer_events(state: State<'_, AppState>) -> Result<Vec<Stri
 #[tauri::command]
 async fn get_download_metrics(
     state: State<'_, AppState>,
+    recent_limit: Option<usize>,
 ) -> Result<DownloadMetricsSnapshot, String> {
     let ft = {
         let ft_guard = state.file_transfer.lock().await;
         ft_guard.as_ref().cloned()
     };
 
-    if let Some(ft) = ft {
-        Ok(ft.download_metrics_snapshot().await)
-    } else {
-        Ok(DownloadMetricsSnapshot::default())
+    match (ft, recent_limit) {
+        (Some(ft), Some(limit)) => Ok(ft.download_metrics_snapshot_with_limit(limit).await),
+        (Some(ft), None) => Ok(ft.download_metrics_snapshot().await),
+        (None, _) => Ok(DownloadMetricsSnapshot::default()),
     }
 }
 
-async fn pump_file_transfer_events(app: tauri::AppHandle, ft: Arc<FileTransferService>) {
-    loop {
-        let events = ft.drain_events(64).await;
-        if events.is_empty() {
-            if Arc::strong_count(&ft) <= 1 {
-                break;
-            }
-            sleep(Duration::from_millis(250)).await;
-            continue;
+fn open_transfer_log() -> Option<Arc<chiral_network::transfer_log::TransferLog>> {
+    match chiral_network::transfer_log::TransferLog::open_default() {
+        Ok(log) => Some(Arc::new(log)),
+        Err(e) => {
+            warn!("Transfer event log disabled: {}", e);
+            None
         }
+    }
+}
 
-        for event in events {
-            match event {
-                FileTransferEvent::DownloadAttempt(snapshot) => {
-                    if let Err(err) = app.emit("download_attempt", &snapshot) {
-                        warn!("Failed to emit download_attempt event: {}", err);
-                    }
-                }
-                other => {
-                    if let Err(err) = app.emit("file_transfer_event", format!("{:?}", other)) {
-                        warn!("Failed to emit file_transfer_event: {}", err);
-                    }
-                }
-            }
+fn open_activity_feed() -> Option<Arc<chiral_network::activity_feed::ActivityFeed>> {
+    match chiral_network::activity_feed::ActivityFeed::open_default() {
+        Ok(feed) => Some(Arc::new(feed)),
+        Err(e) => {
+            warn!("Activity feed disabled: {}", e);
+            None
         }
     }
 }
 
+#[tauri::command]
+async fn get_alert_thresholds(
+    state: State<'_, AppState>,
+) -> Result<chiral_network::alerts::AlertThresholds, String> {
+    Ok(state.alert_monitor.thresholds())
+}
+
+#[tauri::command]
+async fn set_alert_thresholds(
+    state: State<'_, AppState>,
+    thresholds: chiral_network::alerts::AlertThresholds,
+) -> Result<(), String> {
+    state.alert_monitor.set_thresholds(thresholds)
+}
+
+#[tauri::command]
+async fn get_active_alerts(
+    state: State<'_, AppState>,
+) -> Result<Vec<chiral_network::alerts::AlertEvent>, String> {
+    Ok(state.alert_monitor.active_alerts())
+}
+
+/// Gather the values the alert monitor checks from the running services
+async fn sample_node_health(app_handle: &tauri::AppHandle) -> chiral_network::alerts::HealthSample {
+    let mut sample = chiral_network::alerts::HealthSample::default();
+    let Some(state) = app_handle.try_state::<AppState>() else {
+        return sample;
+    };
+
+    sample.free_disk_bytes = download_paths::get_download_directory(app_handle)
+        .ok()
+        .and_then(|dir| chiral_network::alerts::free_disk_bytes(Path::new(&dir)));
+
+    let ft = state.file_transfer.lock().await.as_ref().cloned();
+    if let Some(ft) = ft {
+        let summary = ft.download_metrics_summary().await;
+        sample.downloads_finished = summary.finished_last_minute;
+        sample.download_failures = summary.failures_last_minute;
+    }
+
+    let dht = state.dht.lock().await.as_ref().cloned();
+    if let Some(dht) = dht {
+        sample.peer_count = Some(dht.get_connected_peers().await.len());
+        sample.relay_count = dht.network_topology().await.ok().map(|t| t.relays.len());
+    }
+    sample
+}
+
+#[tauri::command]
+async fn get_telemetry_config(
+    state: State<'_, AppState>,
+) -> Result<chiral_network::telemetry::TelemetryConfig, String> {
+    Ok(state.telemetry.config())
+}
+
+#[tauri::command]
+async fn set_telemetry_config(
+    state: State<'_, AppState>,
+    config: chiral_network::telemetry::TelemetryConfig,
+) -> Result<(), String> {
+    state.telemetry.set_config(config)
+}
+
+/// The report telemetry would send right now, without sending it
+#[tauri::command]
+async fn preview_telemetry_report(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+) -> Result<chiral_network::telemetry::TelemetryReport, String> {
+    let sample = sample_telemetry(&app).await;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    Ok(state.telemetry.build_report(&sample, now))
+}
+
+#[tauri::command]
+async fn get_last_telemetry_report(
+    state: State<'_, AppState>,
+) -> Result<Option<chiral_network::telemetry::TelemetryReport>, String> {
+    Ok(state.telemetry.last_report())
+}
+
+/// Gather the coarse network state telemetry reports
+async fn sample_telemetry(
+    app_handle: &tauri::AppHandle,
+) -> chiral_network::telemetry::TelemetrySample {
+    let mut sample = chiral_network::telemetry::TelemetrySample::default();
+    let Some(state) = app_handle.try_state::<AppState>() else {
+        return sample;
+    };
+    let dht = state.dht.lock().await.as_ref().cloned();
+    if let Some(dht) = dht {
+        let metrics = dht.metrics_snapshot().await;
+        sample.peer_count = Some(metrics.peer_count);
+        sample.reachability = Some(metrics.reachability);
+        sample.relay_available = Some(
+            metrics.active_relay_peer_id.is_some()
+                || dht
+                    .network_topology()
+                    .await
+                    .map_or(false, |t| !t.relays.is_empty()),
+        );
+    }
+    sample
+}
+
+#[tauri::command]
+async fn query_transfer_log(
+    state: State<'_, AppState>,
+    query: Option<chiral_network::transfer_log::TransferLogQuery>,
+) -> Result<chiral_network::transfer_log::TransferLogPage, String> {
+    let log = state
+        .transfer_log
+        .clone()
+        .ok_or_else(|| "Transfer event log is not available".to_string())?;
+    let query = query.unwrap_or_default();
+    tokio::task::spawn_blocking(move || log.query(&query))
+        .await
+        .map_err(|e| format!("Transfer log query task failed: {}", e))?
+}
+
+/// Recent node activity, newest first
+#[tauri::command]
+async fn get_activity_feed(
+    state: State<'_, AppState>,
+    query: Option<chiral_network::activity_feed::ActivityQuery>,
+) -> Result<chiral_network::activity_feed::ActivityPage, String> {
+    let feed = state
+        .activity_feed
+        .clone()
+        .ok_or_else(|| "Activity feed is not available".to_string())?;
+    let query = query.unwrap_or_default();
+    tokio::task::spawn_blocking(move || feed.query(&query))
+        .await
+        .map_err(|e| format!("Activity feed query task failed: {}", e))?
+}
+
+#[tauri::command]
+async fn get_download_metrics_summary(
+    state: State<'_, AppState>,
+) -> Result<DownloadMetricsSummary, String> {
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    };
+
+    if let Some(ft) = ft {
+        Ok(ft.download_metrics_summary().await)
+    } else {
+        Ok(DownloadMetricsSummary::default())
+    }
+}
+
+#[tauri::command]
+async fn get_download_attempt_retention(
+    state: State<'_, AppState>,
+) -> Result<AttemptRetention, String> {
+    Ok(state.settings.get().download_attempt_retention)
+}
+
+/// Saves the retention in the settings and applies it to the running service
+#[tauri::command]
+async fn set_download_attempt_retention(
+    state: State<'_, AppState>,
+    retention: AttemptRetention,
+) -> Result<(), String> {
+    state
+        .settings
+        .update(serde_json::json!({ "downloadAttemptRetention": retention }))?;
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    };
+
+    if let Some(ft) = ft {
+        ft.set_attempt_retention(retention).await;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+async fn clear_download_attempts(state: State<'_, AppState>) -> Result<usize, String> {
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    };
+
+    match ft {
+        Some(ft) => Ok(ft.clear_download_attempts().await),
+        None => Ok(0),
+    }
+}
+
+/// Writes the retained download attempt history to `path` as JSON and returns
+/// the number of attempts exported.
+#[tauri::command]
+async fn export_download_attempts(
+    state: State<'_, AppState>,
+    path: String,
+) -> Result<usize, String> {
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    };
+
+    let ft = ft.ok_or_else(|| {
+        ServiceError::new(ErrorCode::ServiceUnavailable, "File transfer service is not running")
+    })?;
+    ft.export_download_attempts(Path::new(&path)).await
+}
+
 async fn pump_multi_source_events(app: tauri::AppHandle, ms: Arc<MultiSourceDownloadService>) {
     loop {
         let events = ms.drain_events(64).await;
@@ -6502,6 +8590,35 @@ async fn get_multi_source_progress(
     }
 }
 
+/// Per-chunk provider counts for an in-progress or queued download, for the
+/// availability heatmap. Queued downloads are looked up in the DHT.
+#[tauri::command]
+async fn get_chunk_availability(
+    state: State<'_, AppState>,
+    file_hash: String,
+) -> Result<multi_source_download::ChunkAvailability, String> {
+    let ms = {
+        let ms_guard = state.multi_source_download.lock().await;
+        ms_guard.as_ref().cloned()
+    };
+
+    let multi_source_service =
+        ms.ok_or_else(|| "Multi-source download service not available".to_string())?;
+    multi_source_service.chunk_availability(&file_hash, 10_000).await
+}
+
+/// Peers exchanging chunk-have announcements for a download and how much of
+/// the file each one has
+#[tauri::command]
+async fn get_chunk_swarm_members(
+    state: State<'_, AppState>,
+    file_hash: String,
+) -> Result<Vec<dht::chunk_have::ChunkHolder>, String> {
+    let dht = { state.dht.lock().await.as_ref().cloned() };
+    let dht = dht.ok_or_else(|| "DHT not running".to_string())?;
+    Ok(dht.chunk_swarm_members(&file_hash))
+}
+
 #[tauri::command]
 async fn update_proxy_latency(
     state: State<'_, AppState>,
@@ -6677,6 +8794,29 @@ async fn search_file_metadata(
     }
 }
 
+/// Known file metadata (published or discovered) whose detected content type
+/// matches `content_type`, e.g. "image" or "audio/flac"; all files when None
+#[tauri::command]
+async fn list_known_files_by_type(
+    state: State<'_, AppState>,
+    content_type: Option<String>,
+) -> Result<Vec<FileMetadata>, String> {
+    let dht = {
+        let dht_guard = state.dht.lock().await;
+        dht_guard.as_ref().cloned()
+    };
+    let dht = dht.ok_or_else(|| "DHT node is not running".to_string())?;
+
+    let mut files = dht.get_all_file_metadata().await?;
+    if let Some(filter) = content_type.filter(|f| !f.trim().is_empty()) {
+        files.retain(|file| {
+            chiral_network::content_info::matches_type_filter(file.mime_type.as_deref(), &filter)
+        });
+    }
+    files.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    Ok(files)
+}
+
 #[tauri::command]
 async fn get_file_seeders(
     state: State<'_, AppState>,
@@ -6857,6 +8997,37 @@ async fn get_storage_usage(
         .map_err(|e| format!("Failed to calculate storage usage: {}", e))
 }
 
+/// Disk usage split by pinned content, cached downloads, partial transfers,
+/// indices and logs, with the `top_n` (default 10) largest files of each
+#[tauri::command]
+async fn get_storage_breakdown(
+    app_handle: tauri::AppHandle,
+    top_n: Option<usize>,
+) -> Result<storage_manager::StorageBreakdown, String> {
+    use storage_manager::{BreakdownLocations, StorageManager};
+
+    let config = create_storage_config(&app_handle)
+        .await
+        .map_err(|e| format!("Failed to create storage config: {}", e))?;
+    let proj_dirs = directories::ProjectDirs::from("com", "chiral-network", "chiral-network")
+        .ok_or_else(|| "Failed to determine project directories".to_string())?;
+    let logs_path = app_handle
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data directory: {}", e))?
+        .join("logs");
+    let locations = BreakdownLocations {
+        pinned_path: proj_dirs.data_dir().join("files"),
+        data_dir: proj_dirs.data_dir().to_path_buf(),
+        logs_path,
+    };
+
+    StorageManager::new(config)
+        .calculate_breakdown(locations, top_n.unwrap_or(10))
+        .await
+        .map_err(|e| format!("Failed to calculate storage breakdown: {}", e))
+}
+
 /// Trigger manual cleanup (ignores autoCleanup setting)
 #[tauri::command]
 async fn force_storage_cleanup(
@@ -6901,20 +9072,7 @@ async fn create_storage_config(
 ) -> Result<storage_manager::StorageConfig, String> {
     use std::path::PathBuf;
 
-    // Load settings from the store
-    let settings_path = app_handle
-        .path()
-        .app_data_dir()
-        .map_err(|e| format!("Failed to get app data dir: {}", e))?
-        .join("settings.json");
-
-    let settings: BackendSettings = if settings_path.exists() {
-        let contents = std::fs::read_to_string(&settings_path)
-            .map_err(|e| format!("Failed to read settings: {}", e))?;
-        serde_json::from_str(&contents).map_err(|e| format!("Failed to parse settings: {}", e))?
-    } else {
-        BackendSettings::default()
-    };
+    let settings = app_handle.state::<AppState>().settings.get();
 
     // Get download directory
     let download_path = if settings.storage_path.is_empty() {
@@ -6937,10 +9095,10 @@ async fn create_storage_config(
     let chunk_storage_path = proj_dirs.data_dir().join("chunk_storage");
 
     Ok(storage_manager::StorageConfig {
-        max_storage_size_gb: settings.max_storage_size.unwrap_or(100),
-        auto_cleanup: settings.auto_cleanup.unwrap_or(true),
-        cleanup_threshold: settings.cleanup_threshold.unwrap_or(90),
-        cache_size_mb: settings.cache_size.unwrap_or(1024),
+        max_storage_size_gb: settings.max_storage_size,
+        auto_cleanup: settings.auto_cleanup,
+        cleanup_threshold: settings.cleanup_threshold,
+        cache_size_mb: settings.cache_size,
         download_path,
         blockstore_path,
         temp_path,
@@ -6963,21 +9121,7 @@ async fn get_blockstore_stats(
     let blockstore_path = proj_dirs.data_dir().join("blockstore_db");
 
     // Get cache size from settings
-    let settings_path = app_handle
-        .path()
-        .app_data_dir()
-        .map_err(|e| format!("Failed to get app data dir: {}", e))?
-        .join("settings.json");
-
-    let cache_limit_mb = if settings_path.exists() {
-        let contents = std::fs::read_to_string(&settings_path)
-            .map_err(|e| format!("Failed to read settings: {}", e))?;
-        let settings: BackendSettings = serde_json::from_str(&contents)
-            .map_err(|e| format!("Failed to parse settings: {}", e))?;
-        settings.cache_size.unwrap_or(1024)
-    } else {
-        1024 // Default 1GB
-    };
+    let cache_limit_mb = app_handle.state::<AppState>().settings.get().cache_size;
 
     let manager = blockstore_manager::BlockstoreManager::new(blockstore_path, cache_limit_mb);
     manager
@@ -6997,21 +9141,7 @@ async fn clear_blockstore(
 
     tracing::warn!("Clearing entire blockstore at {:?}", blockstore_path);
 
-    let settings_path = app_handle
-        .path()
-        .app_data_dir()
-        .map_err(|e| format!("Failed to get app data dir: {}", e))?
-        .join("settings.json");
-
-    let cache_limit_mb = if settings_path.exists() {
-        let contents = std::fs::read_to_string(&settings_path)
-            .map_err(|e| format!("Failed to read settings: {}", e))?;
-        let settings: BackendSettings = serde_json::from_str(&contents)
-            .map_err(|e| format!("Failed to parse settings: {}", e))?;
-        settings.cache_size.unwrap_or(1024)
-    } else {
-        1024
-    };
+    let cache_limit_mb = app_handle.state::<AppState>().settings.get().cache_size;
 
     let manager = blockstore_manager::BlockstoreManager::new(blockstore_path, cache_limit_mb);
     manager
@@ -7035,21 +9165,7 @@ async fn cleanup_old_blockstore_files(
         max_age_days
     );
 
-    let settings_path = app_handle
-        .path()
-        .app_data_dir()
-        .map_err(|e| format!("Failed to get app data dir: {}", e))?
-        .join("settings.json");
-
-    let cache_limit_mb = if settings_path.exists() {
-        let contents = std::fs::read_to_string(&settings_path)
-            .map_err(|e| format!("Failed to read settings: {}", e))?;
-        let settings: BackendSettings = serde_json::from_str(&contents)
-            .map_err(|e| format!("Failed to parse settings: {}", e))?;
-        settings.cache_size.unwrap_or(1024)
-    } else {
-        1024
-    };
+    let cache_limit_mb = app_handle.state::<AppState>().settings.get().cache_size;
 
     let manager = blockstore_manager::BlockstoreManager::new(blockstore_path, cache_limit_mb);
     manager
@@ -7067,21 +9183,7 @@ async fn auto_cleanup_blockstore(
 
     let blockstore_path = proj_dirs.data_dir().join("blockstore_db");
 
-    let settings_path = app_handle
-        .path()
-        .app_data_dir()
-        .map_err(|e| format!("Failed to get app data dir: {}", e))?
-        .join("settings.json");
-
-    let cache_limit_mb = if settings_path.exists() {
-        let contents = std::fs::read_to_string(&settings_path)
-            .map_err(|e| format!("Failed to read settings: {}", e))?;
-        let settings: BackendSettings = serde_json::from_str(&contents)
-            .map_err(|e| format!("Failed to parse settings: {}", e))?;
-        settings.cache_size.unwrap_or(1024)
-    } else {
-        1024
-    };
+    let cache_limit_mb = app_handle.state::<AppState>().settings.get().cache_size;
 
     let manager = blockstore_manager::BlockstoreManager::new(blockstore_path, cache_limit_mb);
     manager
@@ -7284,9 +9386,27 @@ async fn logout(state: State<'_, AppState>) -> Result<(), ()> {
         webrtc_service.set_active_private_key(None).await;
     }
 
+    // Stored files encrypted at rest can't be read until the next login
+    if let Some(ft) = state.file_transfer.lock().await.as_ref() {
+        ft.lock_storage();
+    }
+
     Ok(())
 }
 
+/// Unlock stored files encrypted at rest with the active account's key
+async fn unlock_file_storage(state: &State<'_, AppState>) {
+    let Some(private_key) = state.active_account_private_key.lock().await.clone() else {
+        return;
+    };
+    let ft = state.file_transfer.lock().await.as_ref().cloned();
+    if let Some(ft) = ft {
+        if let Err(e) = ft.unlock_storage(&private_key).await {
+            warn!("Stored files stay locked: {}", e);
+        }
+    }
+}
+
 async fn get_active_account(state: &State<'_, AppState>) -> Result<String, String> {
     state
         .active_account
@@ -7571,13 +9691,43 @@ async fn cleanup_inactive_peers(
     state: State<'_, AppState>,
     max_age_seconds: u64,
 ) -> Result<(), String> {
-    let dht_guard = state.dht.lock().await;
-    if let Some(ref dht) = *dht_guard {
-        dht.cleanup_inactive_peers(max_age_seconds).await;
-        Ok(())
-    } else {
-        Err("DHT service not available".to_string())
-    }
+    let dht_guard = state.dht.lock().await;
+    if let Some(ref dht) = *dht_guard {
+        dht.cleanup_inactive_peers(max_age_seconds).await;
+        Ok(())
+    } else {
+        Err("DHT service not available".to_string())
+    }
+}
+
+#[tauri::command]
+fn list_trusted_peers() -> Vec<chiral_network::trusted_peers::TrustedPeer> {
+    chiral_network::trusted_peers::global().list()
+}
+
+#[tauri::command]
+fn add_trusted_peer(
+    peer_id: String,
+    label: Option<String>,
+) -> Result<chiral_network::trusted_peers::TrustedPeer, String> {
+    chiral_network::trusted_peers::global().add(peer_id.trim(), label)
+}
+
+#[tauri::command]
+fn remove_trusted_peer(peer_id: String) -> Result<bool, String> {
+    chiral_network::trusted_peers::global().remove(&peer_id)
+}
+
+#[tauri::command]
+fn get_trusted_peer_settings() -> chiral_network::trusted_peers::TrustedPeerSettings {
+    chiral_network::trusted_peers::global().settings()
+}
+
+#[tauri::command]
+fn set_trusted_peer_settings(
+    settings: chiral_network::trusted_peers::TrustedPeerSettings,
+) -> Result<(), String> {
+    chiral_network::trusted_peers::global().set_settings(settings)
 }
 
 #[tauri::command]
@@ -7807,6 +9957,21 @@ async fn get_bandwidth_stats(
     Ok(state.analytics.get_bandwidth_stats().await)
 }
 
+#[tauri::command]
+async fn get_bandwidth_series(
+    state: State<'_, AppState>,
+    resolution: Option<chiral_network::bandwidth_history::BandwidthResolution>,
+    since: Option<u64>,
+    until: Option<u64>,
+) -> Result<Vec<chiral_network::bandwidth_history::BandwidthBucket>, String> {
+    let resolution =
+        resolution.unwrap_or(chiral_network::bandwidth_history::BandwidthResolution::Minute);
+    Ok(state
+        .bandwidth_history
+        .series(resolution, since, until)
+        .await)
+}
+
 #[tauri::command]
 async fn get_bandwidth_history(
     state: State<'_, AppState>,
@@ -7864,25 +10029,119 @@ async fn check_suspicious_patterns(state: State<'_, AppState>) -> Result<(), Str
 }
 
 // Logger configuration commands
-/// Saves application settings to a JSON file in the app data directory
+/// Validates and saves the full application settings object
 #[tauri::command]
-async fn save_app_settings(app: tauri::AppHandle, settings_json: String) -> Result<(), String> {
-    let app_data_dir = app
-        .path()
-        .app_data_dir()
-        .map_err(|e| format!("Failed to get app data directory: {}", e))?;
+async fn save_app_settings(
+    state: State<'_, AppState>,
+    settings_json: String,
+) -> Result<(), String> {
+    let value: serde_json::Value = serde_json::from_str(&settings_json)
+        .map_err(|e| format!("Failed to parse settings: {}", e))?;
+    state.settings.replace(value)?;
+    info!("Settings saved");
+    Ok(())
+}
 
-    // Ensure the directory exists
-    std::fs::create_dir_all(&app_data_dir)
-        .map_err(|e| format!("Failed to create app data directory: {}", e))?;
+/// Application settings stored by the backend, or None if none were saved yet
+#[tauri::command]
+async fn get_app_settings(
+    state: State<'_, AppState>,
+) -> Result<Option<chiral_network::settings::AppSettings>, String> {
+    Ok(state.settings.saved())
+}
 
-    let settings_file = app_data_dir.join("settings.json");
+/// Merges `patch` (a partial settings object) into the stored settings
+#[tauri::command]
+async fn update_app_settings(
+    state: State<'_, AppState>,
+    patch: serde_json::Value,
+) -> Result<chiral_network::settings::AppSettings, String> {
+    state.settings.update(patch)
+}
 
-    std::fs::write(&settings_file, settings_json)
-        .map_err(|e| format!("Failed to write settings file: {}", e))?;
+/// Write accounts, settings, subscriptions, trusted peers and pins to
+/// `output_path` as a bundle encrypted with `passphrase`, for importing on
+/// another device
+#[tauri::command]
+async fn export_sync_bundle(
+    state: State<'_, AppState>,
+    passphrase: String,
+    output_path: String,
+) -> Result<(), String> {
+    use chiral_network::{device_sync, trusted_peers};
 
-    info!("Settings saved to: {}", settings_file.display());
-    Ok(())
+    let ft = state.file_transfer.lock().await.as_ref().cloned();
+    let pinned_files = match ft {
+        Some(ft) => ft
+            .stored_blobs()
+            .await
+            .into_iter()
+            .filter(|blob| blob.pinned)
+            .map(|blob| blob.hash)
+            .collect(),
+        None => Vec::new(),
+    };
+    let settings = state.settings.clone();
+    let subscriptions = state.subscriptions.clone();
+    tokio::task::spawn_blocking(move || {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let bundle = device_sync::collect(
+            &settings,
+            &subscriptions,
+            trusted_peers::global(),
+            pinned_files,
+            now,
+        )?;
+        let sealed = device_sync::seal(&bundle, &passphrase)?;
+        std::fs::write(&output_path, sealed)
+            .map_err(|e| format!("Failed to write sync bundle: {}", e))?;
+        info!(
+            "Exported sync bundle with {} accounts to {}",
+            bundle.accounts.len(),
+            output_path
+        );
+        Ok(())
+    })
+    .await
+    .map_err(|e| format!("Sync export task failed: {}", e))?
+}
+
+/// Decrypt a sync bundle exported on another device and merge it into this
+/// device's accounts, settings, subscriptions, trusted peers and pins
+#[tauri::command]
+async fn import_sync_bundle(
+    state: State<'_, AppState>,
+    passphrase: String,
+    bundle_path: String,
+) -> Result<chiral_network::device_sync::ImportSummary, String> {
+    use chiral_network::{device_sync, trusted_peers};
+
+    let settings = state.settings.clone();
+    let subscriptions = state.subscriptions.clone();
+    let (mut summary, pinned_files) = tokio::task::spawn_blocking(move || {
+        let sealed = std::fs::read_to_string(&bundle_path)
+            .map_err(|e| format!("Failed to read sync bundle: {}", e))?;
+        let mut bundle = device_sync::open(&sealed, &passphrase)?;
+        let pinned_files = std::mem::take(&mut bundle.pinned_files);
+        let summary =
+            device_sync::apply(bundle, &settings, &subscriptions, trusted_peers::global())?;
+        Ok::<_, String>((summary, pinned_files))
+    })
+    .await
+    .map_err(|e| format!("Sync import task failed: {}", e))??;
+
+    let ft = state.file_transfer.lock().await.as_ref().cloned();
+    for file_hash in pinned_files {
+        match &ft {
+            Some(ft) if ft.pin_file(&file_hash).await.is_ok() => summary.pinned_files += 1,
+            _ => summary.missing_pins.push(file_hash),
+        }
+    }
+    info!("Imported sync bundle: {:?}", summary);
+    Ok(summary)
 }
 
 /// Updates the file logger configuration at runtime.
@@ -7934,6 +10193,158 @@ async fn update_log_config(
     Ok(())
 }
 
+/// Returns the last `lines` log lines (default 200) captured since startup,
+/// after the active log filter was applied.
+#[tauri::command]
+fn get_recent_logs(lines: Option<usize>) -> Vec<String> {
+    logger::recent_logs().last(lines.unwrap_or(200).min(logger::RECENT_LOG_CAPACITY))
+}
+
+/// Where the user's tracing filter directives are saved
+fn log_filter_path() -> Option<PathBuf> {
+    ProjectDirs::from("com", "chiral-network", "chiral-network")
+        .map(|dirs| dirs.config_dir().join("log_filter.json"))
+}
+
+#[tauri::command]
+fn get_log_filter() -> chiral_network::log_filter::LogFilterConfig {
+    chiral_network::log_filter::config()
+}
+
+/// Saves per-module log levels (e.g. `file_transfer=debug,relay=info`) and
+/// applies them immediately; an empty string restores the defaults
+#[tauri::command]
+fn set_log_filter(directives: String) -> Result<(), String> {
+    chiral_network::log_filter::set_config(chiral_network::log_filter::LogFilterConfig {
+        directives,
+    })
+}
+
+/// Collects settings, recent logs, metrics, peer/relay summaries and version
+/// info, all redacted, into a zip for bug reports. Writes to `output_path`, or to
+/// `debug-bundles/` in the app data directory when none is given. Returns the
+/// bundle manifest listing what was included and excluded.
+#[tauri::command]
+async fn export_debug_bundle(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    output_path: Option<String>,
+) -> Result<chiral_network::debug_bundle::DebugBundleManifest, String> {
+    use chiral_network::debug_bundle::{DebugBundleBuilder, VersionInfo};
+
+    let app_data_dir = app
+        .path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data directory: {}", e))?;
+    let mut bundle = DebugBundleBuilder::new(VersionInfo::current());
+    bundle.add_json(
+        "version.json",
+        "Application version and platform",
+        &VersionInfo::current(),
+    );
+
+    match std::fs::read_to_string(app_data_dir.join("settings.json")) {
+        Ok(raw) => match serde_json::from_str::<serde_json::Value>(&raw) {
+            Ok(settings) => bundle.add_json(
+                "config/settings.json",
+                "Application settings with secrets redacted",
+                &settings,
+            ),
+            Err(e) => {
+                bundle.exclude("config/settings.json", &format!("Unparseable settings: {}", e))
+            }
+        },
+        Err(_) => bundle.exclude("config/settings.json", "No settings file saved yet"),
+    }
+
+    let logs = logger::recent_logs().last(logger::RECENT_LOG_CAPACITY);
+    bundle.add_text(
+        "logs/recent.log",
+        "Log lines captured since startup",
+        &logs.join("\n"),
+    );
+    bundle.add_json(
+        "events/recent.json",
+        "Recent service events from the event bus",
+        &chiral_network::event_bus::global().recent(500, None),
+    );
+
+    let ft = state.file_transfer.lock().await.as_ref().cloned();
+    match ft {
+        Some(ft) => {
+            bundle.add_json(
+                "metrics/downloads.json",
+                "Download counters, recent attempts and histograms",
+                &ft.download_metrics_snapshot().await,
+            );
+            bundle.add_json(
+                "metrics/download_summary.json",
+                "Rolling download rates",
+                &ft.download_metrics_summary().await,
+            );
+        }
+        None => bundle.exclude("metrics/downloads.json", "File transfer service not running"),
+    }
+    bundle.add_json(
+        "metrics/bandwidth.json",
+        "Bandwidth totals",
+        &state.analytics.get_bandwidth_stats().await,
+    );
+    bundle.add_json(
+        "metrics/alerts.json",
+        "Active health alerts",
+        &state.alert_monitor.active_alerts(),
+    );
+
+    let dht = state.dht.lock().await.as_ref().cloned();
+    match dht {
+        Some(dht) => {
+            bundle.add_json(
+                "network/peers.json",
+                "Per-peer connection statistics",
+                &dht.get_peer_connection_stats(),
+            );
+            match dht.network_topology().await {
+                Ok(topology) => bundle.add_json(
+                    "network/topology.json",
+                    "Relays, bootstrap nodes and routing table summary",
+                    &topology,
+                ),
+                Err(e) => bundle.exclude("network/topology.json", &e),
+            }
+        }
+        None => {
+            bundle.exclude("network/peers.json", "DHT not running");
+            bundle.exclude("network/topology.json", "DHT not running");
+        }
+    }
+
+    bundle.exclude("keystore and wallet files", "Contain private keys");
+    bundle.exclude(
+        "shared and downloaded file contents",
+        "User data, not needed for diagnosis",
+    );
+    bundle.exclude(
+        "transfer log database",
+        "Full transfer history; recent events are included instead",
+    );
+
+    let path = match output_path {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let stamp = chrono::Utc::now().format("%Y%m%d-%H%M%S");
+            app_data_dir
+                .join("debug-bundles")
+                .join(format!("chiral-debug-{}.zip", stamp))
+        }
+    };
+    let manifest = tokio::task::spawn_blocking(move || bundle.write_to(&path))
+        .await
+        .map_err(|e| format!("Debug bundle task failed: {}", e))??;
+    info!("Debug bundle written to {}", manifest.path);
+    Ok(manifest)
+}
+
 /// Get the directory where logs are stored
 #[tauri::command]
 fn get_logs_directory(app: tauri::AppHandle) -> Result<String, String> {
@@ -7963,7 +10374,6 @@ async fn reset_network_services(state: State<'_, AppState>) -> Result<(), String
     *state.multi_source_download.lock().await = None;
 
     // Stop any running pumps
-    *state.file_transfer_pump.lock().await = None;
     *state.multi_source_pump.lock().await = None;
     Ok(())
 }
@@ -7972,6 +10382,12 @@ async fn shutdown_application(app_handle: tauri::AppHandle) {
     tracing::info!("Window close requested - starting shutdown");
 
     if let Some(state) = app_handle.try_state::<AppState>() {
+        // Summarize the session while the DHT is still up
+        finish_session(&state).await;
+        if let Err(e) = chiral_network::demand_stats::global().save() {
+            tracing::warn!("{}", e);
+        }
+
         // Stop HTTP server if running
         let _server_addr = {
             let mut addr_lock = state.http_server_addr.lock().await;
@@ -8030,7 +10446,6 @@ async fn shutdown_application(app_handle: tauri::AppHandle) {
             *state.webrtc.lock().await = None;
             *state.file_transfer.lock().await = None;
             *state.multi_source_download.lock().await = None;
-            *state.file_transfer_pump.lock().await = None;
             *state.multi_source_pump.lock().await = None;
         }
 
@@ -8074,6 +10489,207 @@ fn prompt_close_confirmation(app_handle: &tauri::AppHandle) {
     }
 }
 
+/// Persist the summary of the session that is ending
+async fn finish_session(state: &AppState) {
+    let (bytes_served, bytes_downloaded) = state.bandwidth.lifetime_totals().await;
+    let mut totals = chiral_network::session_summary::SessionTotals {
+        bytes_served,
+        bytes_downloaded,
+        ..Default::default()
+    };
+    let dht = state.dht.lock().await.as_ref().cloned();
+    if let Some(dht) = dht {
+        let network = dht.session_network_totals();
+        totals.unique_peers = network.unique_peers;
+        totals.relay_circuit_secs = network.relay_circuit_secs;
+    }
+    match state.session.finish(totals) {
+        Ok(summary) => tracing::info!(
+            "Session summary: {}s, {} bytes served, {} peers, {} downloads completed, {} failed",
+            summary.duration_secs,
+            summary.bytes_served,
+            summary.unique_peers,
+            summary.downloads_completed,
+            summary.downloads_failed
+        ),
+        Err(e) => tracing::warn!("{}", e),
+    }
+}
+
+/// The last `limit` (default 5) finished sessions, newest first
+#[tauri::command]
+async fn get_session_summaries(
+    state: State<'_, AppState>,
+    limit: Option<usize>,
+) -> Result<Vec<chiral_network::session_summary::SessionSummary>, String> {
+    Ok(state.session.recent(limit.unwrap_or(5)))
+}
+
+/// Most requested hashes, and stored files ordered from least to most demand
+#[tauri::command]
+async fn get_demand_report(
+    state: State<'_, AppState>,
+    limit: Option<usize>,
+) -> Result<chiral_network::demand_stats::DemandReport, String> {
+    let file_transfer = state.file_transfer.lock().await.as_ref().cloned();
+    let stored: Vec<String> = match file_transfer {
+        Some(service) => service
+            .get_stored_files()
+            .await?
+            .into_iter()
+            .map(|file| file.entry.file_hash)
+            .collect(),
+        None => Vec::new(),
+    };
+    Ok(chiral_network::demand_stats::global().report(&stored, limit.unwrap_or(20)))
+}
+
+/// Bytes served, distinct peers and last upload time per file, most served
+/// first
+#[tauri::command]
+async fn get_seeding_stats(
+    state: State<'_, AppState>,
+) -> Result<Vec<chiral_network::file_transfer::seeding::FileSeedingStats>, String> {
+    let file_transfer = state.file_transfer.lock().await.as_ref().cloned();
+    Ok(file_transfer
+        .map(|service| service.seeding_stats_snapshot())
+        .unwrap_or_default())
+}
+
+/// File hashes whose downloads kept failing and are refused for now
+#[tauri::command]
+async fn get_open_download_circuits(
+    state: State<'_, AppState>,
+) -> Result<Vec<chiral_network::file_transfer::circuit::OpenCircuit>, String> {
+    let file_transfer = state.file_transfer.lock().await.as_ref().cloned();
+    Ok(file_transfer
+        .map(|service| service.open_circuits())
+        .unwrap_or_default())
+}
+
+/// Accept downloads of a refused file hash again right away
+#[tauri::command]
+async fn reset_download_circuit(
+    state: State<'_, AppState>,
+    file_hash: String,
+) -> Result<bool, String> {
+    let file_transfer = state.file_transfer.lock().await.as_ref().cloned();
+    let service =
+        file_transfer.ok_or_else(|| "File transfer service is not running".to_string())?;
+    Ok(service.reset_circuit(&file_hash))
+}
+
+/// How deep the file transfer command queue is and how often it filled up
+#[tauri::command]
+async fn get_command_queue_stats(
+    state: State<'_, AppState>,
+) -> Result<chiral_network::file_transfer::command_queue::CommandQueueStats, String> {
+    let file_transfer = state.file_transfer.lock().await.as_ref().cloned();
+    Ok(file_transfer
+        .map(|service| service.command_queue_stats())
+        .unwrap_or_default())
+}
+
+/// Ranked search over locally stored files by name, hash prefix, tags, size
+/// and content type
+#[tauri::command]
+async fn search_local_files(
+    state: State<'_, AppState>,
+    query: chiral_network::local_library::LibraryQuery,
+) -> Result<chiral_network::local_library::LibrarySearchResult, String> {
+    let file_transfer = state.file_transfer.lock().await.as_ref().cloned();
+    let service = file_transfer.ok_or_else(|| "File transfer service is not running".to_string())?;
+    service.search_stored_files(&query).await
+}
+
+/// Identical and near-duplicate files in the local store, with the space a
+/// merge would reclaim
+#[tauri::command]
+async fn find_duplicate_files(
+    state: State<'_, AppState>,
+    min_overlap: Option<f64>,
+) -> Result<chiral_network::library_dedup::DuplicateReport, String> {
+    use chiral_network::library_dedup;
+    let file_transfer = state.file_transfer.lock().await.as_ref().cloned();
+    let service = file_transfer.ok_or_else(|| "File transfer service is not running".to_string())?;
+    let min_overlap = min_overlap
+        .unwrap_or(library_dedup::DEFAULT_MIN_OVERLAP)
+        .clamp(0.0, 1.0);
+    service.find_duplicate_files(min_overlap).await
+}
+
+/// Store identical copies of `keep_hash` once, as shared chunks
+#[tauri::command]
+async fn merge_duplicate_files(
+    state: State<'_, AppState>,
+    keep_hash: String,
+    duplicate_hashes: Vec<String>,
+) -> Result<chiral_network::library_dedup::MergeResult, String> {
+    let file_transfer = state.file_transfer.lock().await.as_ref().cloned();
+    let service = file_transfer.ok_or_else(|| "File transfer service is not running".to_string())?;
+    service
+        .merge_duplicate_files(&keep_hash, &duplicate_hashes)
+        .await
+}
+
+#[tauri::command]
+async fn set_local_file_tags(
+    state: State<'_, AppState>,
+    file_hash: String,
+    tags: Vec<String>,
+) -> Result<Vec<String>, String> {
+    let file_transfer = state.file_transfer.lock().await.as_ref().cloned();
+    let service = file_transfer.ok_or_else(|| "File transfer service is not running".to_string())?;
+    service.set_stored_file_tags(&file_hash, &tags).await
+}
+
+#[tauri::command]
+async fn get_slow_transfer_config(
+    state: State<'_, AppState>,
+) -> Result<chiral_network::slow_transfer::SlowTransferConfig, String> {
+    Ok(state.slow_transfer.config())
+}
+
+/// Applies to running downloads from their next monitor tick
+#[tauri::command]
+async fn set_slow_transfer_config(
+    state: State<'_, AppState>,
+    config: chiral_network::slow_transfer::SlowTransferConfig,
+) -> Result<(), String> {
+    state.slow_transfer.set_config(config)
+}
+
+#[tauri::command]
+async fn get_notification_config(
+    state: State<'_, AppState>,
+) -> Result<chiral_network::notifications::NotificationConfig, String> {
+    Ok(state.notifications.config())
+}
+
+#[tauri::command]
+async fn set_notification_config(
+    state: State<'_, AppState>,
+    config: chiral_network::notifications::NotificationConfig,
+) -> Result<(), String> {
+    state.notifications.set_config(config)
+}
+
+#[tauri::command]
+async fn get_stall_watchdog_config(
+    state: State<'_, AppState>,
+) -> Result<chiral_network::stall_watchdog::StallConfig, String> {
+    Ok(state.stall.config())
+}
+
+/// Applies to running downloads from their next monitor tick
+#[tauri::command]
+async fn set_stall_watchdog_config(
+    state: State<'_, AppState>,
+    config: chiral_network::stall_watchdog::StallConfig,
+) -> Result<(), String> {
+    state.stall.set_config(config)
+}
+
 #[tauri::command]
 async fn confirm_exit(app_handle: tauri::AppHandle) -> Result<(), String> {
     shutdown_application(app_handle).await;
@@ -8156,6 +10772,51 @@ async fn stop_http_server(state: State<'_, AppState>) -> Result<(), String> {
     Ok(())
 }
 
+/// Start the localhost gateway that serves stored files and streaming
+/// downloads by hash, with Range support, for media elements. Returns its
+/// address.
+#[tauri::command]
+async fn start_http_gateway(
+    state: State<'_, AppState>,
+    port: Option<u16>,
+) -> Result<String, String> {
+    let mut gateway = state.http_gateway.lock().await;
+    if let Some(running) = gateway.as_ref() {
+        return Err(format!(
+            "HTTP gateway is already running on {}",
+            running.addr()
+        ));
+    }
+    let ft = {
+        let ft_guard = state.file_transfer.lock().await;
+        ft_guard.as_ref().cloned()
+    }
+    .ok_or("File transfer service is not running")?;
+    let started = file_transfer::gateway::Gateway::start(ft, port.unwrap_or(0)).await?;
+    let addr = format!("http://{}", started.addr());
+    *gateway = Some(started);
+    Ok(addr)
+}
+
+#[tauri::command]
+async fn stop_http_gateway(state: State<'_, AppState>) -> Result<(), String> {
+    state
+        .http_gateway
+        .lock()
+        .await
+        .take()
+        .map(drop)
+        .ok_or_else(|| "HTTP gateway is not running".to_string())
+}
+
+/// URL a `<video>` or `<audio>` element can play `file_hash` from
+#[tauri::command]
+async fn get_gateway_url(state: State<'_, AppState>, file_hash: String) -> Result<String, String> {
+    let gateway = state.http_gateway.lock().await;
+    let gateway = gateway.as_ref().ok_or("HTTP gateway is not running")?;
+    Ok(gateway.file_url(&file_hash))
+}
+
 /// Get HTTP server status
 #[tauri::command]
 async fn get_http_server_status(state: State<'_, AppState>) -> Result<serde_json::Value, String> {
@@ -9006,6 +11667,12 @@ fn main() {
     use clap::Parser;
     let args = headless::CliArgs::parse();
 
+    if args.profile {
+        chiral_network::profiling::install(chiral_network::profiling::Profiler::new(
+            &args.profile_output,
+        ));
+    }
+
     // Handle --download-geth flag
     if args.download_geth {
         use crate::geth_downloader::GethDownloader;
@@ -9052,30 +11719,31 @@ fn main() {
 
     // For headless mode, initialize basic console logging
     if args.headless {
-        use tracing_subscriber::{fmt, prelude::*, EnvFilter};
-        let mut filter = EnvFilter::from_default_env();
-
-        // Add directives with safe fallback
-        if let Ok(directive) = "chiral_network=info".parse() {
-            filter = filter.add_directive(directive);
-        }
-        if let Ok(directive) = "libp2p=warn".parse() {
-            filter = filter.add_directive(directive);
-        }
-        if let Ok(directive) = "libp2p_kad=warn".parse() {
-            filter = filter.add_directive(directive);
-        }
-        if let Ok(directive) = "libp2p_swarm=warn".parse() {
-            filter = filter.add_directive(directive);
-        }
-        if let Ok(directive) = "libp2p_mdns=warn".parse() {
-            filter = filter.add_directive(directive);
+        use tracing_subscriber::{fmt, prelude::*};
+        let mut defaults: Vec<String> = [
+            "chiral_network=info",
+            "libp2p=warn",
+            "libp2p_kad=warn",
+            "libp2p_swarm=warn",
+            "libp2p_mdns=warn",
+        ]
+        .iter()
+        .map(|d| d.to_string())
+        .collect();
+        let profile_layer = chiral_network::profiling::active().map(|p| p.layer());
+        if profile_layer.is_some() {
+            defaults.push(chiral_network::profiling::PROFILE_DIRECTIVE.to_string());
         }
+        // User-configured per-module levels, adjustable at runtime
+        let filter = chiral_network::log_filter::init_layer(defaults, log_filter_path());
 
         tracing_subscriber::registry()
-            .with(fmt::layer())
             .with(filter)
+            .with(fmt::layer())
+            .with(fmt::layer().with_ansi(false).with_writer(logger::recent_logs().clone()))
+            .with(profile_layer)
             .init();
+        chiral_network::log_filter::warn_load_error();
 
         println!("Running in headless mode...");
 
@@ -9333,11 +12001,85 @@ fn main() {
             )),
             proxies: Arc::new(Mutex::new(Vec::new())),
             privacy_proxies: Arc::new(Mutex::new(Vec::new())),
-            file_transfer_pump: Mutex::new(None),
             multi_source_pump: Mutex::new(None),
             socks5_proxy_cli: Mutex::new(args.socks5_proxy),
             analytics: Arc::new(analytics::AnalyticsService::new()),
             bandwidth: Arc::new(BandwidthController::new()),
+            bandwidth_history: Arc::new(
+                ProjectDirs::from("com", "chiral-network", "chiral-network")
+                    .map(|dirs| {
+                        chiral_network::bandwidth_history::BandwidthHistory::with_persistence(
+                            dirs.data_dir().join("bandwidth_history.json"),
+                        )
+                    })
+                    .unwrap_or_default(),
+            ),
+            alert_monitor: Arc::new(
+                ProjectDirs::from("com", "chiral-network", "chiral-network")
+                    .map(|dirs| {
+                        chiral_network::alerts::AlertMonitor::with_persistence(
+                            dirs.config_dir().join("alert_thresholds.json"),
+                        )
+                    })
+                    .unwrap_or_else(|| {
+                        chiral_network::alerts::AlertMonitor::new(Default::default())
+                    }),
+            ),
+            telemetry: Arc::new(
+                ProjectDirs::from("com", "chiral-network", "chiral-network")
+                    .map(|dirs| {
+                        chiral_network::telemetry::TelemetryReporter::with_persistence(
+                            dirs.config_dir().join("telemetry.json"),
+                        )
+                    })
+                    .unwrap_or_default(),
+            ),
+            session: Arc::new(
+                ProjectDirs::from("com", "chiral-network", "chiral-network")
+                    .map(|dirs| {
+                        chiral_network::session_summary::SessionTracker::with_persistence(
+                            dirs.data_dir().join("session_history.json"),
+                        )
+                    })
+                    .unwrap_or_default(),
+            ),
+            slow_transfer: Arc::new(
+                ProjectDirs::from("com", "chiral-network", "chiral-network")
+                    .map(|dirs| {
+                        chiral_network::slow_transfer::SlowTransferSettings::with_persistence(
+                            dirs.config_dir().join("slow_transfer.json"),
+                        )
+                    })
+                    .unwrap_or_default(),
+            ),
+            stall: Arc::new(
+                ProjectDirs::from("com", "chiral-network", "chiral-network")
+                    .map(|dirs| {
+                        chiral_network::stall_watchdog::StallSettings::with_persistence(
+                            dirs.config_dir().join("stall_watchdog.json"),
+                        )
+                    })
+                    .unwrap_or_default(),
+            ),
+            settings: Arc::new(chiral_network::settings::SettingsStore::load_default()),
+            shared_files: Arc::new(
+                ProjectDirs::from("com", "chiral-network", "chiral-network")
+                    .map(|dirs| {
+                        chiral_network::shared_file_watcher::SharedFileWatcher::with_persistence(
+                            dirs.config_dir().join("shared_files.json"),
+                        )
+                    })
+                    .unwrap_or_default(),
+            ),
+            notifications: Arc::new(
+                ProjectDirs::from("com", "chiral-network", "chiral-network")
+                    .map(|dirs| {
+                        chiral_network::notifications::NotificationSettings::with_persistence(
+                            dirs.config_dir().join("notifications.json"),
+                        )
+                    })
+                    .unwrap_or_default(),
+            ),
             payment_checkpoint: Arc::new(PaymentCheckpointService::new()),
 
             // Initialize transaction queue
@@ -9364,6 +12106,7 @@ fn main() {
             })),
             http_server_addr: Arc::new(Mutex::new(None)),
             http_server_shutdown: Arc::new(Mutex::new(None)),
+            http_gateway: Mutex::new(None),
 
             // Initialize stream authentication
             stream_auth: Arc::new(Mutex::new(stream_auth::StreamAuthService::new())),
@@ -9377,7 +12120,16 @@ fn main() {
             proof_contract_address: Arc::new(Mutex::new(None)),
 
             // Relay reputation statistics
-            relay_reputation: Arc::new(Mutex::new(std::collections::HashMap::new())),
+            relay_reputation: Arc::new(
+                ProjectDirs::from("com", "chiral-network", "chiral-network")
+                    .map(|dirs| {
+                        chiral_network::relay_registry::RelayRegistry::with_persistence(
+                            dirs.data_dir()
+                                .join(chiral_network::relay_registry::REGISTRY_FILE),
+                        )
+                    })
+                    .unwrap_or_default(),
+            ),
 
             // Relay aliases
             relay_aliases: Arc::new(Mutex::new(std::collections::HashMap::new())),
@@ -9403,6 +12155,34 @@ fn main() {
 
             // FTP server for serving uploaded files (created earlier for protocol manager)
             ftp_server: ftp_server_arc,
+
+            // Transfer event log (recorder is started in setup)
+            transfer_log: open_transfer_log(),
+            activity_feed: open_activity_feed(),
+            reannouncer: Arc::new(chiral_network::reannounce::Reannouncer::new()),
+            trash: Arc::new(
+                ProjectDirs::from("com", "chiral-network", "chiral-network")
+                    .map(|dirs| {
+                        chiral_network::file_trash::FileTrash::with_persistence(
+                            dirs.data_dir().join("trash"),
+                            dirs.config_dir().join("trash.json"),
+                        )
+                    })
+                    .unwrap_or_else(|| {
+                        chiral_network::file_trash::FileTrash::new(
+                            std::env::temp_dir().join("chiral-trash"),
+                        )
+                    }),
+            ),
+            subscriptions: Arc::new(
+                ProjectDirs::from("com", "chiral-network", "chiral-network")
+                    .map(|dirs| {
+                        chiral_network::subscriptions::Subscriptions::with_persistence(
+                            dirs.config_dir().join("subscriptions.json"),
+                        )
+                    })
+                    .unwrap_or_default(),
+            ),
         })
         .invoke_handler(tauri::generate_handler![
             create_chiral_account,
@@ -9492,13 +12272,32 @@ fn main() {
             start_dht_node,
             stop_dht_node,
             stop_publishing_file,
+            get_reannounce_status,
+            force_reannounce,
+            list_subscriptions,
+            subscribe_publisher,
+            unsubscribe_publisher,
+            get_subscription_limits,
+            set_subscription_limits,
+            get_publisher_feed,
+            trash_library_file,
+            list_trash,
+            restore_trashed_file,
+            delete_trashed_file,
+            empty_trash,
+            get_trash_config,
+            set_trash_config,
             search_file_metadata,
+            list_known_files_by_type,
             search_by_infohash,
             get_file_seeders,
             connect_to_peer,
             get_dht_events,
             detect_locale,
             get_download_directory,
+            get_download_staging_status,
+            set_download_staging_config,
+            verify_file_checksum,
             check_directory_exists,
             get_default_storage_directory,
             validate_storage_path,
@@ -9509,9 +12308,61 @@ fn main() {
             get_peer_id,
             is_dht_running,
             get_dht_connected_peers,
+            get_peer_connection_stats,
+            get_peer_churn_stats,
+            set_dht_query_tracing,
+            get_dht_query_trace,
+            list_dht_query_traces,
+            get_dial_failures,
+            get_relay_reservations,
+            get_network_topology,
+            run_network_diagnostics,
+            get_file_availability,
             start_file_transfer_service,
+            download_file_from_peers,
+            download_file_to_directory,
+            cancel_file_transfer,
+            pause_file_transfer,
+            resume_file_transfer,
+            set_file_transfer_priority,
+            set_max_concurrent_file_transfers,
+            upload_directory,
+            download_directory,
+            get_file_hash_algorithm,
+            set_file_hash_algorithm,
+            get_encryption_at_rest,
+            set_encryption_at_rest,
+            get_storage_deduplication,
+            set_storage_deduplication,
+            get_storage_dedup_stats,
+            verify_storage,
+            set_scrub_schedule,
+            get_recipient_public_key,
+            upload_file_for_recipient,
+            pin_file,
+            unpin_file,
+            get_stored_blobs,
+            get_stored_files,
+            trim_file_storage,
+            upload_file_and_get_hash,
+            upload_file_in_place,
+            download_batch,
+            upload_file_with_expiry,
+            set_file_expiry,
+            remove_stored_file,
             download_file_from_network,
             upload_file_to_network,
+            upload_files,
+            publish_directory_manifest,
+            get_directory_manifest,
+            download_directory_manifest,
+            push_file_to_provider,
+            get_push_receiver_config,
+            set_push_receiver_config,
+            list_watched_shared_files,
+            get_file_preview,
+            get_file_preview_asset,
+            get_preview,
             list_ftp_directory,
             delete_ftp_file,
             rename_ftp_file,
@@ -9529,6 +12380,8 @@ fn main() {
             start_multi_source_download,
             cancel_multi_source_download,
             get_multi_source_progress,
+            get_chunk_availability,
+            get_chunk_swarm_members,
             update_proxy_latency,
             get_proxy_optimization_status,
             download_file_multi_source,
@@ -9542,6 +12395,16 @@ fn main() {
             save_download_checkpoint,
             resume_download_from_checkpoint,
             get_download_metrics,
+            get_download_metrics_summary,
+            get_download_attempt_retention,
+            set_download_attempt_retention,
+            clear_download_attempts,
+            export_download_attempts,
+            query_transfer_log,
+            get_activity_feed,
+            get_alert_thresholds,
+            set_alert_thresholds,
+            get_active_alerts,
             encrypt_file_with_password,
             decrypt_file_with_password,
             encrypt_file_for_upload,
@@ -9570,6 +12433,11 @@ fn main() {
             select_peers_with_strategy,
             set_peer_encryption_support,
             cleanup_inactive_peers,
+            list_trusted_peers,
+            add_trusted_peer,
+            remove_trusted_peer,
+            get_trusted_peer_settings,
+            set_trusted_peer_settings,
             test_backend_connection,
             set_bandwidth_limits,
             establish_webrtc_connection,
@@ -9584,6 +12452,7 @@ fn main() {
             cancel_streaming_upload,
             get_bandwidth_stats,
             get_bandwidth_history,
+            get_bandwidth_series,
             get_performance_metrics,
             get_network_activity,
             get_resource_contribution,
@@ -9603,6 +12472,9 @@ fn main() {
             // HTTP server commands
             start_http_server,
             stop_http_server,
+            start_http_gateway,
+            stop_http_gateway,
+            get_gateway_url,
             get_http_server_status,
             // Reputation system commands
             publish_reputation_verdict,
@@ -9643,9 +12515,42 @@ fn main() {
             get_relay_reputation_stats,
             set_relay_alias,
             get_relay_alias,
+            block_relay,
+            unblock_relay,
+            get_blocked_relays,
+            get_relay_prune_stats,
+            list_relays_by_region,
             save_app_settings,
+            get_app_settings,
+            update_app_settings,
+            export_sync_bundle,
+            import_sync_bundle,
             update_log_config,
             get_logs_directory,
+            get_recent_logs,
+            get_log_filter,
+            set_log_filter,
+            get_telemetry_config,
+            set_telemetry_config,
+            preview_telemetry_report,
+            get_last_telemetry_report,
+            get_session_summaries,
+            get_slow_transfer_config,
+            set_slow_transfer_config,
+            get_notification_config,
+            set_notification_config,
+            get_stall_watchdog_config,
+            set_stall_watchdog_config,
+            get_demand_report,
+            get_seeding_stats,
+            get_open_download_circuits,
+            reset_download_circuit,
+            get_command_queue_stats,
+            search_local_files,
+            set_local_file_tags,
+            find_duplicate_files,
+            merge_duplicate_files,
+            export_debug_bundle,
             check_directory_exists,
             get_multiaddresses,
             clear_seed_list,
@@ -9667,6 +12572,7 @@ fn main() {
             remove_payment_checkpoint_session,
             // Storage management commands
             get_storage_usage,
+            get_storage_breakdown,
             force_storage_cleanup,
             check_and_cleanup_storage,
             // Blockstore management commands
@@ -9701,6 +12607,7 @@ fn main() {
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_store::Builder::default().build())
+        .plugin(tauri_plugin_notification::init())
         .on_window_event(|window, event| {
             if let tauri::WindowEvent::Destroyed = event {
                 // When window is destroyed, stop geth
@@ -9719,75 +12626,31 @@ fn main() {
                 app.handle().clone(),
             );
 
-            // Load settings from disk
-            // We only need log-related settings during setup; parse them from settings.json
-            // without depending on a local `load_settings_from_file` helper.
-            let settings = {
-                #[derive(serde::Deserialize)]
-                struct LogSettings {
-                    #[serde(rename = "enableFileLogging")]
-                    enable_file_logging: Option<bool>,
-                    #[serde(rename = "maxLogSizeMB")]
-                    max_log_size_mb: Option<u64>,
-                }
-
-                let app_data_dir = app
-                    .path()
-                    .app_data_dir()
-                    .expect("Failed to get app data directory");
-                let settings_file = app_data_dir.join("settings.json");
-
-                let mut settings = BackendSettings::default();
-                if settings_file.exists() {
-                    if let Ok(contents) = std::fs::read_to_string(&settings_file) {
-                        if let Ok(json) = serde_json::from_str::<serde_json::Value>(&contents) {
-                            settings.enable_file_logging = json
-                                .get("enableFileLogging")
-                                .and_then(|v| v.as_bool())
-                                .unwrap_or(settings.enable_file_logging);
-                            settings.max_log_size_mb = json
-                                .get("maxLogSizeMB")
-                                .and_then(|v| v.as_u64())
-                                .unwrap_or(settings.max_log_size_mb);
-                            settings.storage_path = json
-                                .get("storagePath")
-                                .and_then(|v| v.as_str())
-                                .unwrap_or("")
-                                .to_string();
-                        } else if let Ok(log_json) = serde_json::from_str::<LogSettings>(&contents)
-                        {
-                            // Fallback in case settings.json isn't a plain object
-                            settings.enable_file_logging = log_json
-                                .enable_file_logging
-                                .unwrap_or(settings.enable_file_logging);
-                            settings.max_log_size_mb =
-                                log_json.max_log_size_mb.unwrap_or(settings.max_log_size_mb);
-                        }
-                    }
-                }
-                settings
-            };
+            let settings = app.state::<AppState>().settings.get();
 
             // Initialize tracing subscriber with console output and optionally file output
-            use tracing_subscriber::{fmt, prelude::*, EnvFilter};
-
-            let env_filter = {
-                #[cfg(debug_assertions)]
-                {
-                    EnvFilter::from_default_env()
-                        .add_directive("chiral_network=info".parse().unwrap())
-                        .add_directive("libp2p=warn".parse().unwrap())
-                        .add_directive("libp2p_kad=warn".parse().unwrap())
-                        .add_directive("libp2p_swarm=warn".parse().unwrap())
-                        .add_directive("libp2p_mdns=warn".parse().unwrap())
-                }
-                #[cfg(not(debug_assertions))]
-                {
-                    EnvFilter::from_default_env()
-                        .add_directive("chiral_network=warn".parse().unwrap())
-                        .add_directive("libp2p=error".parse().unwrap())
-                }
-            };
+            use tracing_subscriber::{fmt, prelude::*};
+
+            #[cfg(debug_assertions)]
+            let default_directives = [
+                "chiral_network=info",
+                "libp2p=warn",
+                "libp2p_kad=warn",
+                "libp2p_swarm=warn",
+                "libp2p_mdns=warn",
+            ];
+            #[cfg(not(debug_assertions))]
+            let default_directives = ["chiral_network=warn", "libp2p=error"];
+            let mut defaults: Vec<String> =
+                default_directives.iter().map(|d| d.to_string()).collect();
+
+            // --profile: record span timings and enable the profiling spans
+            let profile_layer = chiral_network::profiling::active().map(|p| p.layer());
+            if profile_layer.is_some() {
+                defaults.push(chiral_network::profiling::PROFILE_DIRECTIVE.to_string());
+            }
+            // RUST_LOG and the defaults, overridden by the user's saved filter
+            let env_filter = chiral_network::log_filter::init_layer(defaults, log_filter_path());
 
             // Always create file logger (even if disabled) so it can be enabled/disabled later
             let app_data_dir = app
@@ -9817,16 +12680,381 @@ fn main() {
             // File output will only write if enabled in config
             if let Some(ref file_writer) = file_logger_writer {
                 tracing_subscriber::registry()
+                    .with(env_filter)
                     .with(fmt::layer()) // Console output
                     .with(fmt::layer().with_writer(file_writer.clone())) // File output (respects enabled flag)
-                    .with(env_filter)
+                    .with(fmt::layer().with_ansi(false).with_writer(logger::recent_logs().clone())) // In-app console
+                    .with(profile_layer)
                     .init();
             } else {
                 tracing_subscriber::registry()
-                    .with(fmt::layer()) // Console output only
                     .with(env_filter)
+                    .with(fmt::layer()) // Console output only
+                    .with(fmt::layer().with_ansi(false).with_writer(logger::recent_logs().clone())) // In-app console
+                    .with(profile_layer)
                     .init();
             }
+            chiral_network::log_filter::warn_load_error();
+
+            if let Some(profiler) = chiral_network::profiling::active() {
+                info!("Profiling enabled, writing to {}", profiler.output_path().display());
+                tauri::async_runtime::spawn(async move {
+                    profiler.spawn_background_tasks();
+                });
+            }
+
+            // Forward the unified service event stream to the frontend
+            chiral_network::event_bus::spawn_tauri_forwarder(app.handle().clone());
+
+            // Check files shared in place for edits and deletions
+            {
+                let app_handle = app.handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    let mut interval = tokio::time::interval(
+                        chiral_network::shared_file_watcher::SCAN_INTERVAL,
+                    );
+                    loop {
+                        interval.tick().await;
+                        let changes = app_handle.state::<AppState>().shared_files.scan().await;
+                        for change in changes {
+                            handle_shared_file_change(&app_handle, change).await;
+                        }
+                    }
+                });
+            }
+
+            // Desktop notifications for transfer milestones and direct-send offers
+            {
+                use chiral_network::notifications;
+                let settings = app.state::<AppState>().notifications.clone();
+                notifications::spawn_bus_notifier(app.handle().clone(), settings.clone());
+
+                let app_handle = app.handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    let mut interval = tokio::time::interval(notifications::OFFER_POLL_INTERVAL);
+                    let kind = notifications::NotificationKind::DirectSendOffer;
+                    let mut last_offer: Option<Vec<u8>> = None;
+                    loop {
+                        interval.tick().await;
+                        if !settings.config().allows(kind) {
+                            continue;
+                        }
+                        let dht = {
+                            let state = app_handle.state::<AppState>();
+                            let dht_guard = state.dht.lock().await;
+                            dht_guard.as_ref().cloned()
+                        };
+                        let Some(dht) = dht else {
+                            continue;
+                        };
+                        let key = notifications::offer_key(&dht.get_peer_id().await);
+                        let Ok(Some(raw)) = dht.get_dht_value(key).await else {
+                            continue;
+                        };
+                        // The key holds only the latest offer; notify once per new value
+                        if last_offer.as_ref() == Some(&raw) {
+                            continue;
+                        }
+                        if let Some(notification) = notifications::for_offer(&raw) {
+                            notifications::show(&app_handle, &settings, &notification);
+                        }
+                        last_offer = Some(raw);
+                    }
+                });
+            }
+
+            // Forward settings changes so every window sees the stored values
+            {
+                let mut changes = app.state::<AppState>().settings.subscribe();
+                let app_handle = app.handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    loop {
+                        match changes.recv().await {
+                            Ok(event) => {
+                                let _ = app_handle.emit("settings_changed", event);
+                            }
+                            Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                            Err(_) => break,
+                        }
+                    }
+                });
+            }
+
+            // Persist terminal transfer events from the bus
+            if let Some(log) = app
+                .try_state::<AppState>()
+                .and_then(|state| state.transfer_log.clone())
+            {
+                tauri::async_runtime::spawn(chiral_network::transfer_log::run_recorder(log));
+            }
+
+            // Record uploads, downloads, served peers, payments and relay events
+            if let Some(feed) = app
+                .try_state::<AppState>()
+                .and_then(|state| state.activity_feed.clone())
+            {
+                tauri::async_runtime::spawn(chiral_network::activity_feed::run_recorder(feed));
+            }
+
+            // Re-announce published files on a jittered schedule
+            {
+                use chiral_network::reannounce;
+                let reannouncer = app.state::<AppState>().reannouncer.clone();
+                tauri::async_runtime::spawn(reannounce::run_tracker(reannouncer.clone()));
+
+                let app_handle = app.handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    loop {
+                        tokio::time::sleep(reannounce::next_delay(
+                            reannounce::REANNOUNCE_INTERVAL,
+                            reannounce::REANNOUNCE_JITTER,
+                        ))
+                        .await;
+                        let dht = {
+                            let state = app_handle.state::<AppState>();
+                            let dht_guard = state.dht.lock().await;
+                            dht_guard.as_ref().cloned()
+                        };
+                        if let Some(dht) = dht {
+                            reannouncer.reannounce_all(&dht).await;
+                        }
+                    }
+                });
+            }
+
+            // Files deleted by the service itself, to make room or once
+            // expired, are forgotten like ones removed on request
+            {
+                use chiral_network::event_bus::{self, EventPayload};
+                let app_handle = app.handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    let mut rx = event_bus::global().subscribe();
+                    loop {
+                        let ids: Vec<String> = match rx.recv().await {
+                            Ok(envelope) => match envelope.payload {
+                                EventPayload::FileTransfer(
+                                    FileTransferEvent::FileRemoved {
+                                        file_hash,
+                                        published_ids,
+                                    }
+                                    | FileTransferEvent::FileExpired {
+                                        file_hash,
+                                        published_ids,
+                                        ..
+                                    },
+                                ) => std::iter::once(file_hash).chain(published_ids).collect(),
+                                _ => continue,
+                            },
+                            Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                            Err(_) => break,
+                        };
+                        let state = app_handle.state::<AppState>();
+                        for id in &ids {
+                            forget_stored_file(&state, id);
+                        }
+                    }
+                });
+            }
+
+            // Probe known relays so their health scores stay current
+            {
+                use chiral_network::relay_prober;
+                let registry = app.state::<AppState>().relay_reputation.clone();
+                let app_handle = app.handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    loop {
+                        tokio::time::sleep(relay_prober::PROBE_INTERVAL).await;
+                        let dht = {
+                            let state = app_handle.state::<AppState>();
+                            let dht_guard = state.dht.lock().await;
+                            dht_guard.as_ref().cloned()
+                        };
+                        if let Some(dht) = dht {
+                            relay_prober::probe_due(&dht, &registry).await;
+                        }
+                    }
+                });
+            }
+
+            // Prune stale relays, restarting the task when its settings change
+            {
+                let registry = app.state::<AppState>().relay_reputation.clone();
+                let settings = app.state::<AppState>().settings.clone();
+                let mut changes = settings.subscribe();
+                tauri::async_runtime::spawn(async move {
+                    registry.start_pruning(settings.get().relay_prune_config());
+                    loop {
+                        match changes.recv().await {
+                            Ok(event) => {
+                                if event.changed_keys.iter().any(|key| {
+                                    key == "relayPruneIntervalMins" || key == "relayMaxAgeDays"
+                                }) {
+                                    registry.start_pruning(event.settings.relay_prune_config());
+                                }
+                            }
+                            Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                            Err(_) => break,
+                        }
+                    }
+                });
+            }
+
+            // Locate relays with the offline GeoIP database, if one is installed
+            if let Some(dirs) = ProjectDirs::from("com", "chiral-network", "chiral-network") {
+                let path = dirs.data_dir().join(chiral_network::geoip::GEOIP_FILE);
+                let registry = app.state::<AppState>().relay_reputation.clone();
+                tauri::async_runtime::spawn(async move {
+                    if !path.exists() {
+                        return;
+                    }
+                    let loaded = tokio::task::spawn_blocking(move || {
+                        chiral_network::geoip::GeoIpDb::load(&path)
+                    })
+                    .await;
+                    match loaded {
+                        Ok(Ok(geoip)) => {
+                            info!("Loaded {} GeoIP ranges", geoip.len());
+                            registry.set_geoip(geoip).await;
+                        }
+                        Ok(Err(e)) => warn!("Failed to load GeoIP database: {}", e),
+                        Err(e) => warn!("GeoIP loading task failed: {}", e),
+                    }
+                });
+            }
+
+            // Permanently delete trash entries past their retention period
+            {
+                let trash = app.state::<AppState>().trash.clone();
+                tauri::async_runtime::spawn(async move {
+                    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+                    loop {
+                        interval.tick().await;
+                        match trash.purge_expired().await {
+                            Ok(0) => {}
+                            Ok(n) => info!("Purged {} expired trash entries", n),
+                            Err(e) => warn!("Failed to purge trash: {}", e),
+                        }
+                    }
+                });
+            }
+
+            // Remove staged downloads left behind by an interrupted session
+            tauri::async_runtime::spawn_blocking(|| {
+                use chiral_network::download_staging;
+                let removed = download_staging::global().purge_stale(download_staging::STALE_AFTER);
+                if removed > 0 {
+                    info!("Removed {} stale staged downloads", removed);
+                }
+            });
+
+            // Announce our own uploads in the signed publisher feed
+            {
+                use chiral_network::dht::publisher_feed::FeedEntry;
+                use chiral_network::event_bus::{self, EventPayload};
+                let app_handle = app.handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    let mut rx = event_bus::global().subscribe();
+                    loop {
+                        let metadata = match rx.recv().await {
+                            Ok(envelope) => match envelope.payload {
+                                EventPayload::Dht(DhtEvent::PublishedFile(metadata)) => metadata,
+                                _ => continue,
+                            },
+                            Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                            Err(_) => break,
+                        };
+                        let state = app_handle.state::<AppState>();
+                        // Downloads promoted to seeding are published too but keep
+                        // the original uploader; only our own uploads go in the feed
+                        let account = state.active_account.lock().await.clone();
+                        let is_own = match (&account, &metadata.uploader_address) {
+                            (Some(account), Some(uploader)) => {
+                                account.eq_ignore_ascii_case(uploader)
+                            }
+                            _ => false,
+                        };
+                        if !is_own {
+                            continue;
+                        }
+                        let dht = { state.dht.lock().await.as_ref().cloned() };
+                        let Some(dht) = dht else {
+                            continue;
+                        };
+                        let entry = FeedEntry {
+                            file_hash: metadata.merkle_root.clone(),
+                            file_name: metadata.file_name.clone(),
+                            file_size: metadata.file_size,
+                            price: metadata.price,
+                            published_at: SystemTime::now()
+                                .duration_since(UNIX_EPOCH)
+                                .unwrap_or_default()
+                                .as_secs(),
+                        };
+                        if let Err(e) = dht.publish_feed_entry(entry).await {
+                            warn!("Failed to update publisher feed: {}", e);
+                        }
+                    }
+                });
+            }
+
+            // Poll followed publishers, notify about new content and auto-download it
+            {
+                use chiral_network::subscriptions;
+                let app_handle = app.handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    let mut interval = tokio::time::interval(subscriptions::POLL_INTERVAL);
+                    loop {
+                        interval.tick().await;
+                        let state = app_handle.state::<AppState>();
+                        let dht = { state.dht.lock().await.as_ref().cloned() };
+                        let Some(dht) = dht else {
+                            continue;
+                        };
+                        for sub in state.subscriptions.list() {
+                            let feed = match dht.fetch_publisher_feed(&sub.publisher_id).await {
+                                Ok(Some(feed)) => feed,
+                                Ok(None) => continue,
+                                Err(e) => {
+                                    warn!("Ignoring feed of {}: {}", sub.publisher_id, e);
+                                    continue;
+                                }
+                            };
+                            for entry in state.subscriptions.new_entries(&sub.publisher_id, &feed) {
+                                handle_subscription_entry(&app_handle, &dht, &sub, entry).await;
+                            }
+                        }
+                    }
+                });
+            }
+
+            // Tell download swarm members which chunks we have
+            {
+                let app_handle = app.handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    let mut interval =
+                        tokio::time::interval(dht::chunk_have::ANNOUNCE_INTERVAL);
+                    loop {
+                        interval.tick().await;
+                        let state = app_handle.state::<AppState>();
+                        let dht = { state.dht.lock().await.as_ref().cloned() };
+                        if let Some(dht) = dht {
+                            dht.announce_chunk_haves().await;
+                        }
+                    }
+                });
+            }
+
+            // Count finished downloads for the session summary
+            if let Some(state) = app.try_state::<AppState>() {
+                let session = state.session.clone();
+                tauri::async_runtime::spawn(async move { session.spawn_event_counter() });
+            }
+
+            // Keep per-hash request counts across restarts
+            if let Some(dirs) = ProjectDirs::from("com", "chiral-network", "chiral-network") {
+                chiral_network::demand_stats::global()
+                    .load(dirs.data_dir().join("demand_stats.json"));
+            }
 
             // Store the file logger in app state so it can be updated later
             if let Some(file_writer) = file_logger_writer {
@@ -10150,8 +13378,10 @@ fn main() {
 
                 if let Some(dht_service) = dht_clone_for_pump {
                     let proxies_arc_for_pump = Arc::new(Mutex::new(Vec::new()));
-                    let relay_reputation_arc_for_pump =
-                        Arc::new(Mutex::new(std::collections::HashMap::new()));
+                    let relay_reputation_arc_for_pump = app_handle
+                        .try_state::<AppState>()
+                        .map(|state| state.relay_reputation.clone())
+                        .unwrap_or_default();
 
                     tauri::async_runtime::spawn(async move {
                         pump_dht_events(
@@ -10170,8 +13400,22 @@ fn main() {
                 let app_handle = app.handle().clone();
                 if let Some(state) = app_handle.try_state::<AppState>() {
                     let bandwidth_controller = state.bandwidth.clone();
+                    let bandwidth_history = state.bandwidth_history.clone();
+                    let alert_monitor = state.alert_monitor.clone();
+                    let telemetry = state.telemetry.clone();
                     let app_handle_for_bandwidth = app.handle().clone();
+                    let app_handle_for_alerts = app.handle().clone();
+                    let app_handle_for_telemetry = app.handle().clone();
                     tauri::async_runtime::spawn(async move {
+                        bandwidth_history.spawn_sampler(bandwidth_controller.clone());
+                        alert_monitor.spawn_monitor(move || {
+                            let app_handle = app_handle_for_alerts.clone();
+                            async move { sample_node_health(&app_handle).await }
+                        });
+                        telemetry.spawn_reporter(move || {
+                            let app_handle = app_handle_for_telemetry.clone();
+                            async move { sample_telemetry(&app_handle).await }
+                        });
                         bandwidth_controller
                             .set_app_handle(app_handle_for_bandwidth)
                             .await;
@@ -10264,6 +13508,9 @@ fn main() {
                 // Don't prevent exit, let it proceed naturally
             }
             tauri::RunEvent::Exit => {
+                if let Some(profiler) = chiral_network::profiling::active() {
+                    profiler.finish();
+                }
                 println!("App exiting, cleaning up geth...");
                 // Stop geth before exiting
                 if let Some(state) = app_handle.try_state::<AppState>() {
@@ -10748,6 +13995,46 @@ struct RelayNodeStats {
     circuits_successful: u64,
     total_events: u64,
     last_seen: u64,
+    last_rtt_ms: Option<u64>,
+    failed_probes: u32,
+    max_circuits: Option<u32>,
+    max_circuit_bytes: Option<u64>,
+    active_circuits: Option<u32>,
+    reservation_status: Option<dht::reservations::ReservationStatus>,
+    hole_punch_attempts: u64,
+    hole_punch_successes: u64,
+    circuits_opened: u64,
+    bytes_relayed: u64,
+    connection_failures: u64,
+    /// Country the relay is in, see `RelayInfo::located_region`
+    region: Option<String>,
+}
+
+impl RelayNodeStats {
+    fn new(relay: chiral_network::relay_registry::RelayInfo, alias: Option<String>) -> Self {
+        Self {
+            alias,
+            region: relay.located_region().map(str::to_string),
+            reputation_score: relay.health_score,
+            reservations_accepted: relay.reservations_accepted,
+            circuits_established: relay.circuits_established,
+            circuits_successful: relay.circuits_successful,
+            total_events: relay.total_events,
+            last_seen: relay.last_seen,
+            last_rtt_ms: relay.last_rtt_ms,
+            failed_probes: relay.failed_probes,
+            max_circuits: relay.capacity.map(|c| c.max_circuits),
+            max_circuit_bytes: relay.capacity.map(|c| c.max_circuit_bytes),
+            active_circuits: relay.capacity.map(|c| c.active_circuits),
+            reservation_status: relay.reservation.as_ref().map(|r| r.status),
+            hole_punch_attempts: relay.hole_punch_attempts,
+            hole_punch_successes: relay.hole_punch_successes,
+            circuits_opened: relay.circuits_opened,
+            bytes_relayed: relay.bytes_relayed,
+            connection_failures: relay.connection_failures,
+            peer_id: relay.peer_id,
+        }
+    }
 }
 
 #[tauri::command]
@@ -10755,32 +14042,23 @@ async fn get_relay_reputation_stats(
     state: State<'_, AppState>,
     limit: Option<usize>,
 ) -> Result<RelayReputationStats, String> {
-    // Read from relay reputation storage
-    let stats_map = state.relay_reputation.lock().await;
+    // Read from the relay registry, healthiest first
+    let known = state.relay_reputation.list().await;
     let aliases_map = state.relay_aliases.lock().await;
 
     let max_relays = limit.unwrap_or(100);
+    let total_relays = known.len();
 
-    // Convert HashMap to Vec, populate aliases, and sort by reputation score (descending)
-    let mut all_relays: Vec<RelayNodeStats> = stats_map
-        .values()
-        .map(|stats| {
-            let mut stats_with_alias = stats.clone();
-            stats_with_alias.alias = aliases_map.get(&stats.peer_id).cloned();
-            stats_with_alias
+    // Take top N relays and populate aliases
+    let top_relays = known
+        .into_iter()
+        .take(max_relays)
+        .map(|relay| {
+            let alias = aliases_map.get(&relay.peer_id).cloned();
+            RelayNodeStats::new(relay, alias)
         })
         .collect();
 
-    all_relays.sort_by(|a, b| {
-        b.reputation_score
-            .partial_cmp(&a.reputation_score)
-            .unwrap_or(std::cmp::Ordering::Equal)
-    });
-
-    // Take top N relays
-    let top_relays = all_relays.into_iter().take(max_relays).collect();
-    let total_relays = stats_map.len();
-
     Ok(RelayReputationStats {
         total_relays,
         top_relays,
@@ -10804,6 +14082,99 @@ async fn set_relay_alias(
     Ok(())
 }
 
+/// Keep a relay out of the registry and out of relay selection
+#[tauri::command]
+async fn block_relay(
+    state: State<'_, AppState>,
+    peer_id: String,
+    reason: Option<String>,
+) -> Result<chiral_network::relay_registry::BlockedRelay, String> {
+    let peer_id = peer_id.trim();
+    if peer_id.parse::<libp2p::PeerId>().is_err() {
+        return Err(ServiceError::new(
+            ErrorCode::InvalidInput,
+            format!("Invalid peer ID: {}", peer_id),
+        )
+        .into());
+    }
+    let reason = reason.unwrap_or_default();
+    let blocked = state
+        .relay_reputation
+        .block(peer_id, reason.trim(), state.relay_reputation.now())
+        .await;
+    sync_blocked_relays(&state).await;
+    Ok(blocked)
+}
+
+#[tauri::command]
+async fn unblock_relay(state: State<'_, AppState>, peer_id: String) -> Result<bool, String> {
+    let unblocked = state.relay_reputation.unblock(peer_id.trim()).await;
+    if unblocked {
+        sync_blocked_relays(&state).await;
+    }
+    Ok(unblocked)
+}
+
+/// Tell the running DHT node which relays are blocked, so it drops them
+async fn sync_blocked_relays(state: &AppState) {
+    let dht = state.dht.lock().await.as_ref().cloned();
+    let Some(dht) = dht else {
+        return;
+    };
+    let blocked: Vec<String> = state
+        .relay_reputation
+        .blocked()
+        .await
+        .into_iter()
+        .map(|relay| relay.peer_id)
+        .collect();
+    if let Err(e) = dht.set_blocked_relays(&blocked).await {
+        warn!("Failed to pass blocked relays to the DHT: {}", e);
+    }
+}
+
+#[tauri::command]
+async fn get_blocked_relays(
+    state: State<'_, AppState>,
+) -> Result<Vec<chiral_network::relay_registry::BlockedRelay>, String> {
+    Ok(state.relay_reputation.blocked().await)
+}
+
+#[tauri::command]
+async fn get_relay_prune_stats(
+    state: State<'_, AppState>,
+) -> Result<chiral_network::relay_registry::PruneStats, String> {
+    Ok(state.relay_reputation.prune_stats().await)
+}
+
+/// Known relays in `region` (a country code), healthiest first. Defaults to
+/// this node's own region.
+#[tauri::command]
+async fn list_relays_by_region(
+    state: State<'_, AppState>,
+    region: Option<String>,
+) -> Result<Vec<RelayNodeStats>, String> {
+    let region = region
+        .map(|region| region.trim().to_ascii_uppercase())
+        .filter(|region| !region.is_empty())
+        .or_else(chiral_network::relay_selector::local_region)
+        .ok_or_else(|| {
+            ServiceError::new(
+                ErrorCode::InvalidInput,
+                "No region given and this node's region is unknown",
+            )
+        })?;
+    let relays = state.relay_reputation.list_by_region(&region).await;
+    let aliases = state.relay_aliases.lock().await;
+    Ok(relays
+        .into_iter()
+        .map(|relay| {
+            let alias = aliases.get(&relay.peer_id).cloned();
+            RelayNodeStats::new(relay, alias)
+        })
+        .collect())
+}
+
 #[tauri::command]
 async fn get_relay_alias(
     state: State<'_, AppState>,
@@ -10865,7 +14236,7 @@ async fn pump_dht_events(
     app_handle: tauri::AppHandle,
     dht_service: Arc<DhtService>,
     proxies_arc: Arc<Mutex<Vec<ProxyNode>>>,
-    relay_reputation_arc: Arc<Mutex<std::collections::HashMap<String, RelayNodeStats>>>,
+    relay_reputation_arc: Arc<chiral_network::relay_registry::RelayRegistry>,
 ) {
     loop {
         let events = dht_service.drain_events(64).await;
@@ -10881,6 +14252,9 @@ async fn pump_dht_events(
         for ev in events {
             match ev {
                 DhtEvent::PeerDiscovered { peer_id, addresses } => {
+                    relay_reputation_arc
+                        .note_addresses(&peer_id, &addresses, relay_reputation_arc.now())
+                        .await;
                     let payload = serde_json::json!({ "peerId": peer_id, "addresses": addresses });
                     let _ = app_handle.emit("dht_peer_discovered", payload);
                 }
@@ -10947,36 +14321,13 @@ async fn pump_dht_events(
                     impact,
                     data,
                 } => {
-                    let mut stats = relay_reputation_arc.lock().await;
-                    let entry = stats.entry(peer_id.clone()).or_insert(RelayNodeStats {
-                        peer_id: peer_id.clone(),
-                        alias: None,
-                        reputation_score: 0.0,
-                        reservations_accepted: 0,
-                        circuits_established: 0,
-                        circuits_successful: 0,
-                        total_events: 0,
-                        last_seen: 0,
-                    });
-
-                    entry.reputation_score += impact;
-                    entry.total_events += 1;
-                    entry.last_seen = data
+                    let at = data
                         .get("timestamp")
                         .and_then(|v| v.as_u64())
-                        .unwrap_or_else(|| {
-                            SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .unwrap_or_default()
-                                .as_secs()
-                        });
-
-                    match event_type.as_str() {
-                        "RelayReservationAccepted" => entry.reservations_accepted += 1,
-                        "RelayCircuitEstablished" => entry.circuits_established += 1,
-                        "RelayCircuitSuccessful" => entry.circuits_successful += 1,
-                        _ => {}
-                    }
+                        .unwrap_or_else(|| relay_reputation_arc.now());
+                    relay_reputation_arc
+                        .record_event(&peer_id, &event_type, impact, at)
+                        .await;
 
                     let payload = serde_json::json!({ "peerId": peer_id, "eventType": event_type, "impact": impact, "data": data });
                     let _ = app_handle.emit("relay_reputation_event", payload);
@@ -10990,6 +14341,44 @@ async fn pump_dht_events(
                     let payload = serde_json::json!({ "fileHash": file_hash, "chunkIndex": chunk_index, "totalChunks": total_chunks, "chunkSize": chunk_size });
                     let _ = app_handle.emit("bitswap_chunk_downloaded", payload);
                 }
+                DhtEvent::RelayAnnounced { announcement, via } => {
+                    relay_reputation_arc
+                        .record_announcement(&announcement, &via)
+                        .await;
+                }
+                DhtEvent::RelayReservation(reservation) => {
+                    relay_reputation_arc.record_reservation(&reservation).await;
+                    let _ = app_handle.emit("relay_reservation", &reservation);
+                }
+                DhtEvent::HolePunch {
+                    peer_id,
+                    relay_peer_id,
+                    success,
+                } => {
+                    if let Some(relay) = &relay_peer_id {
+                        relay_reputation_arc
+                            .record_hole_punch(relay, success, relay_reputation_arc.now())
+                            .await;
+                    }
+                    let payload = serde_json::json!({ "peerId": peer_id, "relayPeerId": relay_peer_id, "success": success });
+                    let _ = app_handle.emit("hole_punch", payload);
+                }
+                DhtEvent::RelayUsage {
+                    relay_peer_id,
+                    circuits_opened,
+                    bytes,
+                    failures,
+                } => {
+                    relay_reputation_arc
+                        .record_usage(
+                            &relay_peer_id,
+                            circuits_opened,
+                            bytes,
+                            failures,
+                            relay_reputation_arc.now(),
+                        )
+                        .await;
+                }
                 DhtEvent::PaymentNotificationReceived { from_peer, payload } => {
                     if let Ok(notification) =
                         serde_json::from_value::<serde_json::Value>(payload.clone())
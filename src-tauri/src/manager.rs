@@ -76,7 +76,7 @@ pub struct ChunkInfo {
 
 /// Contains all metadata required to find, verify, and decrypt a file.
 /// This manifest should be saved by the uploader and securely sent to the recipient.
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default)]
 pub struct FileManifest {
     /// The Merkle root of all original chunk hashes. This is the file's unique identifier.
     pub merkle_root: String,
@@ -84,6 +84,9 @@ pub struct FileManifest {
     pub chunks: Vec<ChunkInfo>,
     /// The encrypted AES key bundle needed for decryption (None for unencrypted files).
     pub encrypted_key_bundle: Option<EncryptedAesKeyBundle>,
+    /// Detected content type and basic media/document metadata, when known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_info: Option<crate::content_info::ContentInfo>,
 }
 
 /// A simple Sha256 hasher implementation for the Merkle tree.
@@ -192,6 +195,7 @@ impl ChunkManager {
             merkle_root: hex::encode(merkle_root),
             chunks: chunks_info,
             encrypted_key_bundle: None,
+            content_info: None,
         };
 
         // Return the manifest AND the raw AES key for secure storage by the caller.
@@ -0,0 +1,505 @@
+// Thumbnails, preview clips and text excerpts for uploaded files
+//
+// When an image, video or audio file is uploaded a small thumbnail (and for
+// video/audio a short preview clip) is generated with ffmpeg; a text file
+// gets an excerpt of its first lines. They are stored under
+// `previews/<file id>/` in the data directory, next to a `preview.json`
+// describing what exists. Previews are generated under the content hash before
+// the file is published, listed in its manifest, and then moved under the id
+// the file is announced as, so search results and listings can fetch them
+// (locally or through the HTTP server's `/previews` route) without downloading
+// the file itself. Unpublishing a file removes its previews.
+//
+// ffmpeg is optional: when it is not installed media previews are skipped and
+// the upload proceeds normally. Text excerpts need nothing external.
+
+use crate::runtime_env::{Clock, SystemClock};
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::process::Command;
+use tracing::{debug, warn};
+
+pub const THUMBNAIL_FILE: &str = "thumbnail.jpg";
+pub const TEXT_FILE: &str = "preview.txt";
+const INDEX_FILE: &str = "preview.json";
+/// Longest side of a thumbnail, in pixels
+const THUMBNAIL_SIZE: u32 = 320;
+const VIDEO_CLIP_SECS: u32 = 10;
+const AUDIO_CLIP_SECS: u32 = 15;
+/// Most of a text file read for its excerpt
+const TEXT_PREVIEW_BYTES: usize = 4096;
+const TEXT_PREVIEW_LINES: usize = 40;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaKind {
+    Image,
+    Video,
+    Audio,
+    Text,
+}
+
+impl MediaKind {
+    /// Media kind from a file extension, None for non-media files
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "jpg" | "jpeg" | "png" | "gif" | "webp" | "bmp" | "tif" | "tiff" => {
+                Some(MediaKind::Image)
+            }
+            "mp4" | "m4v" | "mov" | "mkv" | "webm" | "avi" | "wmv" | "flv" | "mpg" | "mpeg" => {
+                Some(MediaKind::Video)
+            }
+            "mp3" | "wav" | "flac" | "ogg" | "oga" | "opus" | "m4a" | "aac" | "wma" => {
+                Some(MediaKind::Audio)
+            }
+            "txt" | "md" | "markdown" | "csv" | "tsv" | "log" | "json" | "yaml" | "yml"
+            | "toml" | "xml" | "html" | "htm" | "ini" | "rs" | "py" | "js" | "ts" | "c" | "h"
+            | "cpp" | "java" | "go" | "sh" => Some(MediaKind::Text),
+            _ => None,
+        }
+    }
+
+    fn clip_file(self) -> Option<&'static str> {
+        match self {
+            MediaKind::Image | MediaKind::Text => None,
+            MediaKind::Video => Some("preview.mp4"),
+            MediaKind::Audio => Some("preview.mp3"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewInfo {
+    pub file_hash: String,
+    pub kind: MediaKind,
+    /// Asset file names inside the preview directory
+    pub thumbnail: Option<String>,
+    pub clip: Option<String>,
+    #[serde(default)]
+    pub text: Option<String>,
+    /// Unix timestamp (seconds)
+    pub generated_at: u64,
+}
+
+impl PreviewInfo {
+    pub fn assets(&self) -> impl Iterator<Item = &str> {
+        self.thumbnail
+            .iter()
+            .chain(self.clip.iter())
+            .chain(self.text.iter())
+            .map(String::as_str)
+    }
+}
+
+/// What a manifest lists of a file's preview
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewRef {
+    pub kind: MediaKind,
+    /// Asset file names, served under the file's published id
+    pub assets: Vec<String>,
+}
+
+impl PreviewInfo {
+    pub fn reference(&self) -> PreviewRef {
+        PreviewRef {
+            kind: self.kind,
+            assets: self.assets().map(str::to_string).collect(),
+        }
+    }
+}
+
+/// A file's preview ready to display: the thumbnail inlined as a data URL
+/// and the text excerpt itself. Clips are large and stay an asset name.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FilePreview {
+    pub file_hash: String,
+    pub kind: MediaKind,
+    pub thumbnail: Option<String>,
+    pub clip: Option<String>,
+    pub text: Option<String>,
+    pub generated_at: u64,
+}
+
+/// `previews/` under the app data directory
+pub fn default_previews_dir() -> Option<PathBuf> {
+    directories::ProjectDirs::from("com", "chiral-network", "chiral-network")
+        .map(|dirs| dirs.data_dir().join("previews"))
+}
+
+/// File hashes are hex; anything else must not reach the filesystem
+fn is_valid_hash(file_hash: &str) -> bool {
+    !file_hash.is_empty() && file_hash.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+#[derive(Clone)]
+pub struct PreviewStore {
+    root: PathBuf,
+    clock: Arc<dyn Clock>,
+}
+
+impl std::fmt::Debug for PreviewStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PreviewStore")
+            .field("root", &self.root)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PreviewStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Read the time from `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Store in the default data directory, if it can be determined
+    pub fn open_default() -> Option<Self> {
+        default_previews_dir().map(Self::new)
+    }
+
+    fn dir(&self, file_hash: &str) -> Option<PathBuf> {
+        is_valid_hash(file_hash).then(|| self.root.join(file_hash))
+    }
+
+    /// What was generated for `file_hash`, if anything
+    pub fn get(&self, file_hash: &str) -> Option<PreviewInfo> {
+        let raw = std::fs::read_to_string(self.dir(file_hash)?.join(INDEX_FILE)).ok()?;
+        serde_json::from_str(&raw).ok()
+    }
+
+    /// Path of one asset listed in the preview index
+    pub fn asset_path(&self, file_hash: &str, asset: &str) -> Option<PathBuf> {
+        let info = self.get(file_hash)?;
+        info.assets()
+            .any(|a| a == asset)
+            .then(|| self.root.join(file_hash).join(asset))
+    }
+
+    /// The preview of `file_hash` with its small assets read in
+    pub fn load(&self, file_hash: &str) -> Option<FilePreview> {
+        let info = self.get(file_hash)?;
+        let read = |asset: &Option<String>| {
+            let asset = asset.as_deref()?;
+            std::fs::read(self.asset_path(file_hash, asset)?).ok()
+        };
+        let thumbnail = read(&info.thumbnail).map(|data| {
+            format!(
+                "data:image/jpeg;base64,{}",
+                general_purpose::STANDARD.encode(data)
+            )
+        });
+        let text = read(&info.text).map(|data| String::from_utf8_lossy(&data).into_owned());
+        Some(FilePreview {
+            file_hash: info.file_hash,
+            kind: info.kind,
+            thumbnail,
+            clip: info.clip,
+            text,
+            generated_at: info.generated_at,
+        })
+    }
+
+    pub fn remove(&self, file_hash: &str) -> Result<(), String> {
+        let Some(dir) = self.dir(file_hash) else {
+            return Ok(());
+        };
+        match std::fs::remove_dir_all(dir) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(format!("Failed to remove previews: {}", e)),
+        }
+    }
+
+    /// Move the previews of `from` to `to`, replacing any already there
+    pub fn rekey(&self, from: &str, to: &str) -> Result<(), String> {
+        if from == to {
+            return Ok(());
+        }
+        let Some(mut info) = self.get(from) else {
+            return Ok(());
+        };
+        let (Some(from_dir), Some(to_dir)) = (self.dir(from), self.dir(to)) else {
+            return Err(format!("Invalid file hash: {}", to));
+        };
+        self.remove(to)?;
+        std::fs::rename(from_dir, to_dir).map_err(|e| format!("Failed to move previews: {}", e))?;
+        info.file_hash = to.to_string();
+        self.write_index(&info)
+    }
+
+    /// Generate previews for the media or text file at `source`. Returns
+    /// None for other files, binary files with a text extension, or media
+    /// when ffmpeg is not available.
+    pub async fn generate(
+        &self,
+        file_hash: &str,
+        source: &Path,
+    ) -> Result<Option<PreviewInfo>, String> {
+        let Some(kind) = MediaKind::from_path(source) else {
+            return Ok(None);
+        };
+        let dir = self
+            .dir(file_hash)
+            .ok_or_else(|| format!("Invalid file hash: {}", file_hash))?;
+        if kind == MediaKind::Text {
+            return self.generate_text(file_hash, source, &dir).await;
+        }
+        tokio::fs::create_dir_all(&dir)
+            .await
+            .map_err(|e| format!("Failed to create preview directory: {}", e))?;
+
+        let scale = format!(
+            "scale={size}:{size}:force_original_aspect_ratio=decrease",
+            size = THUMBNAIL_SIZE
+        );
+        let thumbnail_path = dir.join(THUMBNAIL_FILE);
+        let source_arg = source.to_string_lossy().into_owned();
+        let mut thumbnail_args: Vec<String> = Vec::new();
+        if kind == MediaKind::Video {
+            // Skip the first second, which is often black
+            thumbnail_args.extend(["-ss", "1"].map(String::from));
+        }
+        thumbnail_args.extend([
+            "-i".to_string(),
+            source_arg.clone(),
+            // Audio files only get a thumbnail if they embed cover art
+            "-an".to_string(),
+            "-frames:v".to_string(),
+            "1".to_string(),
+            "-vf".to_string(),
+            scale,
+            thumbnail_path.to_string_lossy().into_owned(),
+        ]);
+
+        let thumbnail = match run_ffmpeg(&thumbnail_args).await {
+            Ok(true) => Some(THUMBNAIL_FILE.to_string()),
+            Ok(false) => None,
+            Err(e) => {
+                // ffmpeg missing: nothing can be generated
+                debug!("Skipping previews for {}: {}", file_hash, e);
+                let _ = tokio::fs::remove_dir(&dir).await;
+                return Ok(None);
+            }
+        };
+
+        let clip = match kind.clip_file() {
+            Some(clip_file) => {
+                let clip_path = dir.join(clip_file);
+                let mut args: Vec<String> = vec!["-i".to_string(), source_arg, "-t".to_string()];
+                if kind == MediaKind::Video {
+                    args.push(VIDEO_CLIP_SECS.to_string());
+                    args.extend(
+                        [
+                            "-vf",
+                            "scale=480:-2",
+                            "-c:v",
+                            "libx264",
+                            "-preset",
+                            "veryfast",
+                            "-crf",
+                            "30",
+                            "-c:a",
+                            "aac",
+                            "-b:a",
+                            "64k",
+                            "-movflags",
+                            "+faststart",
+                        ]
+                        .map(String::from),
+                    );
+                } else {
+                    args.push(AUDIO_CLIP_SECS.to_string());
+                    args.extend(["-vn", "-c:a", "libmp3lame", "-b:a", "96k"].map(String::from));
+                }
+                args.push(clip_path.to_string_lossy().into_owned());
+                match run_ffmpeg(&args).await {
+                    Ok(true) => Some(clip_file.to_string()),
+                    _ => None,
+                }
+            }
+            None => None,
+        };
+
+        if thumbnail.is_none() && clip.is_none() {
+            warn!("ffmpeg could not generate previews for {}", source.display());
+            let _ = tokio::fs::remove_dir_all(&dir).await;
+            return Ok(None);
+        }
+
+        let info = PreviewInfo {
+            file_hash: file_hash.to_string(),
+            kind,
+            thumbnail,
+            clip,
+            text: None,
+            generated_at: self.clock.now_secs(),
+        };
+        self.write_index(&info)?;
+        Ok(Some(info))
+    }
+
+    async fn generate_text(
+        &self,
+        file_hash: &str,
+        source: &Path,
+        dir: &Path,
+    ) -> Result<Option<PreviewInfo>, String> {
+        use tokio::io::AsyncReadExt;
+
+        let file = tokio::fs::File::open(source)
+            .await
+            .map_err(|e| format!("Failed to open {}: {}", source.display(), e))?;
+        let mut head = Vec::with_capacity(TEXT_PREVIEW_BYTES);
+        file.take(TEXT_PREVIEW_BYTES as u64)
+            .read_to_end(&mut head)
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", source.display(), e))?;
+        let Some(excerpt) = text_excerpt(&head) else {
+            debug!("Skipping text preview for binary file {}", source.display());
+            return Ok(None);
+        };
+
+        tokio::fs::create_dir_all(dir)
+            .await
+            .map_err(|e| format!("Failed to create preview directory: {}", e))?;
+        tokio::fs::write(dir.join(TEXT_FILE), excerpt)
+            .await
+            .map_err(|e| format!("Failed to save text preview: {}", e))?;
+        let info = PreviewInfo {
+            file_hash: file_hash.to_string(),
+            kind: MediaKind::Text,
+            thumbnail: None,
+            clip: None,
+            text: Some(TEXT_FILE.to_string()),
+            generated_at: self.clock.now_secs(),
+        };
+        self.write_index(&info)?;
+        Ok(Some(info))
+    }
+
+    fn write_index(&self, info: &PreviewInfo) -> Result<(), String> {
+        let dir = self
+            .dir(&info.file_hash)
+            .ok_or_else(|| format!("Invalid file hash: {}", info.file_hash))?;
+        let json = serde_json::to_string_pretty(info)
+            .map_err(|e| format!("Failed to serialize preview index: {}", e))?;
+        std::fs::write(dir.join(INDEX_FILE), json)
+            .map_err(|e| format!("Failed to save preview index: {}", e))
+    }
+}
+
+/// The first lines of `head`, the start of a file. None if it is not
+/// UTF-8 text; a character cut off at the end of `head` is dropped.
+fn text_excerpt(head: &[u8]) -> Option<String> {
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        // An incomplete character at the very end is just where the read stopped
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&head[..e.valid_up_to()]).ok()?,
+        Err(_) => return None,
+    };
+    if text.contains('\0') {
+        return None;
+    }
+    let lines: Vec<&str> = text.lines().take(TEXT_PREVIEW_LINES).collect();
+    Some(lines.join("\n"))
+}
+
+/// Run ffmpeg quietly, overwriting outputs. Ok(false) means ffmpeg ran but
+/// failed; Err means it could not be started.
+async fn run_ffmpeg(args: &[String]) -> Result<bool, String> {
+    let status = Command::new("ffmpeg")
+        .args(["-y", "-hide_banner", "-loglevel", "error"])
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .status()
+        .await
+        .map_err(|e| format!("Failed to run ffmpeg: {}", e))?;
+    Ok(status.success())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn detects_media_and_only_serves_indexed_assets() {
+        assert_eq!(MediaKind::from_path(Path::new("a/B.JPG")), Some(MediaKind::Image));
+        assert_eq!(MediaKind::from_path(Path::new("clip.webm")), Some(MediaKind::Video));
+        assert_eq!(MediaKind::from_path(Path::new("song.flac")), Some(MediaKind::Audio));
+        assert_eq!(MediaKind::from_path(Path::new("notes.txt")), Some(MediaKind::Text));
+        assert_eq!(MediaKind::from_path(Path::new("setup.exe")), None);
+        assert_eq!(MediaKind::from_path(Path::new("README")), None);
+
+        let dir = tempdir().unwrap();
+        let store = PreviewStore::new(dir.path());
+        std::fs::create_dir_all(dir.path().join("abc123")).unwrap();
+        let info = PreviewInfo {
+            file_hash: "abc123".into(),
+            kind: MediaKind::Audio,
+            thumbnail: None,
+            clip: Some("preview.mp3".into()),
+            text: None,
+            generated_at: 1,
+        };
+        store.write_index(&info).unwrap();
+
+        assert_eq!(store.get("abc123"), Some(info));
+        assert!(store.asset_path("abc123", "preview.mp3").is_some());
+        assert!(store.asset_path("abc123", THUMBNAIL_FILE).is_none());
+        assert!(store.asset_path("abc123", "../preview.json").is_none());
+        assert!(store.get("../abc123").is_none());
+
+        // Moved under the id the file is published as
+        store.rekey("abc123", "root456").unwrap();
+        assert!(store.get("abc123").is_none());
+        assert_eq!(store.get("root456").unwrap().file_hash, "root456");
+        assert!(store.asset_path("root456", "preview.mp3").is_some());
+
+        store.remove("root456").unwrap();
+        assert!(store.get("root456").is_none());
+    }
+
+    #[tokio::test]
+    async fn text_files_get_an_excerpt_of_their_first_lines() {
+        let dir = tempdir().unwrap();
+        let store = PreviewStore::new(dir.path().join("previews"));
+        let notes = dir.path().join("notes.md");
+        let body: Vec<String> = (1..=100).map(|n| format!("line {} é", n)).collect();
+        std::fs::write(&notes, body.join("\n")).unwrap();
+
+        let info = store.generate("abc123", &notes).await.unwrap().unwrap();
+        assert_eq!(info.kind, MediaKind::Text);
+        let preview = store.load("abc123").unwrap();
+        let text = preview.text.unwrap();
+        assert_eq!(text.lines().count(), TEXT_PREVIEW_LINES);
+        assert!(text.starts_with("line 1 é\nline 2 é"));
+        assert_eq!(preview.thumbnail, None);
+
+        // Binary contents behind a text extension get no preview
+        let blob = dir.path().join("data.txt");
+        std::fs::write(&blob, [0u8, 159, 146, 150]).unwrap();
+        assert_eq!(store.generate("def456", &blob).await.unwrap(), None);
+        assert!(store.get("def456").is_none());
+
+        // A character split by the end of the read is dropped
+        assert_eq!(text_excerpt(&"aé".as_bytes()[..2]), Some("a".into()));
+    }
+}
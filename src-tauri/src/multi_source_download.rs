@@ -7,10 +7,13 @@ use crate::download_source::{
 };
 use crate::ed2k_client::{Ed2kClient, Ed2kConfig, ED2K_CHUNK_SIZE};
 use crate::manager::{ChunkManager, FileManifest};
+use crate::slow_transfer::{SlowTransferSettings, SlowTransferWatchdog};
+use crate::stall_watchdog::{StallSettings, StallVerdict, StallWatchdog};
 use crate::transfer_events::{
     TransferEventBus, TransferStartedEvent, SourceConnectedEvent, SourceDisconnectedEvent,
     ChunkCompletedEvent, ChunkFailedEvent, TransferProgressEvent, TransferCompletedEvent,
     TransferFailedEvent, SourceInfo, SourceType, SourceSummary, DisconnectReason, ErrorCategory,
+    SlowTransferEvent, TransferStalledEvent,
     current_timestamp_ms, calculate_progress,
 };
 use crate::ftp_downloader::{FtpCredentials, FtpDownloader};
@@ -24,7 +27,8 @@ use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use suppaftp::FtpStream;
 use tokio::sync::{mpsc, Mutex, RwLock};
 use tokio::time::timeout;
-use tracing::{debug, error, info, warn};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn, Instrument};
 use url::Url;
 
 const DEFAULT_CHUNK_SIZE: usize = 256 * 1024; // 256KB chunks
@@ -35,6 +39,10 @@ const CONNECTION_TIMEOUT_SECS: u64 = 30;
 const CHUNK_REQUEST_TIMEOUT_SECS: u64 = 60;
 #[allow(dead_code)]
 const MAX_RETRY_ATTEMPTS: u32 = 3;
+/// How often a running download asks its chunk-have swarm for missing chunks
+const SWARM_FETCH_INTERVAL: Duration = Duration::from_secs(2);
+/// Swarm chunk requests in flight at once for one download
+const MAX_PARALLEL_SWARM_FETCHES: usize = 8;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(rename_all = "camelCase")]
@@ -63,6 +71,10 @@ pub struct SourceAssignment {
 
     /// Timestamp of last activity from this source
     pub last_activity: Option<u64>,
+
+    /// Cancelled when the source is dropped, aborting its in-flight requests
+    #[serde(skip)]
+    pub cancel: CancellationToken,
 }
 
 /// Status of a download source
@@ -100,6 +112,7 @@ impl SourceAssignment {
             status: SourceStatus::Connecting,
             connected_at: None,
             last_activity: None,
+            cancel: CancellationToken::new(),
         }
     }
 
@@ -138,6 +151,12 @@ pub fn verify_chunk_integrity(chunk: &ChunkInfo, data: &[u8]) -> Result<(), (Str
         None => return Ok(()),
     };
 
+    let _span = tracing::trace_span!(
+        target: crate::profiling::PROFILE_TARGET,
+        "hash_chunk",
+        bytes = data.len()
+    )
+    .entered();
     let mut hasher = Sha256::new();
     hasher.update(data);
     let actual = hex::encode(hasher.finalize());
@@ -164,11 +183,71 @@ pub struct MultiSourceProgress {
     pub source_assignments: Vec<SourceAssignment>,
 }
 
+/// How many known providers can serve each chunk of a download
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ChunkAvailability {
+    pub file_hash: String,
+    pub total_chunks: u32,
+    /// Providers that have not failed for this download
+    pub known_providers: usize,
+    /// Provider count per chunk, indexed by chunk ID
+    pub chunk_providers: Vec<u32>,
+    /// Chunks already downloaded and stored locally
+    pub completed_chunks: Vec<u32>,
+    /// Chunks still needed that no known provider can serve
+    pub unavailable_chunks: Vec<u32>,
+}
+
+impl ChunkAvailability {
+    /// `providers` holds the chunks each provider can serve, `None` for a
+    /// provider that has the whole file
+    pub fn compute(
+        file_hash: &str,
+        total_chunks: u32,
+        providers: &[Option<Vec<u32>>],
+        completed: &[u32],
+    ) -> Self {
+        let mut chunk_providers = vec![0u32; total_chunks as usize];
+        for provider in providers {
+            match provider {
+                None => chunk_providers.iter_mut().for_each(|count| *count += 1),
+                Some(chunks) => {
+                    for chunk_id in chunks {
+                        if let Some(count) = chunk_providers.get_mut(*chunk_id as usize) {
+                            *count += 1;
+                        }
+                    }
+                }
+            }
+        }
+        let mut completed_chunks: Vec<u32> = completed
+            .iter()
+            .copied()
+            .filter(|chunk_id| *chunk_id < total_chunks)
+            .collect();
+        completed_chunks.sort_unstable();
+        completed_chunks.dedup();
+        let unavailable_chunks = (0..total_chunks)
+            .filter(|chunk_id| chunk_providers[*chunk_id as usize] == 0)
+            .filter(|chunk_id| completed_chunks.binary_search(chunk_id).is_err())
+            .collect();
+
+        Self {
+            file_hash: file_hash.to_string(),
+            total_chunks,
+            known_providers: providers.len(),
+            chunk_providers,
+            completed_chunks,
+            unavailable_chunks,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ChunkRequest {
     #[allow(dead_code)]
     pub chunk_id: u32,
-    #[allow(dead_code)]
     pub source_id: String, // Changed from peer_id - can be peer ID, URL, etc.
     #[allow(dead_code)]
     pub requested_at: Instant,
@@ -224,6 +303,10 @@ pub struct MultiSourceDownloadService {
     analytics_service: Arc<AnalyticsService>,
     // Unified chunk storage manager for persistence and caching
     chunk_manager: Arc<ChunkManager>,
+    // Throughput floor and grace period for slow-transfer detection
+    slow_transfer: Arc<SlowTransferSettings>,
+    // Inactivity timeout and recovery attempts for stuck transfers
+    stall: Arc<StallSettings>,
 }
 
 #[derive(Debug, Serialize)]
@@ -240,6 +323,9 @@ pub enum MultiSourceCommand {
     RetryFailedChunks {
         file_hash: String,
     },
+    RecoverStalledDownload {
+        file_hash: String,
+    },
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -282,6 +368,17 @@ pub enum MultiSourceEvent {
         file_hash: String,
         error: String,
     },
+    SlowTransfer {
+        file_hash: String,
+        peer_id: Option<String>,
+        throughput_bps: f64,
+        rerouted_chunks: u32,
+    },
+    DownloadStalled {
+        file_hash: String,
+        idle_secs: u64,
+        recovery_attempt: u32,
+    },
 }
 
 impl MultiSourceDownloadService {
@@ -314,9 +411,23 @@ impl MultiSourceDownloadService {
             transfer_event_bus,
             analytics_service,
             chunk_manager,
+            slow_transfer: Arc::new(SlowTransferSettings::new()),
+            stall: Arc::new(StallSettings::new()),
         }
     }
 
+    /// Use shared slow-transfer settings instead of the defaults
+    pub fn with_slow_transfer_settings(mut self, settings: Arc<SlowTransferSettings>) -> Self {
+        self.slow_transfer = settings;
+        self
+    }
+
+    /// Use shared stall watchdog settings instead of the defaults
+    pub fn with_stall_settings(mut self, settings: Arc<StallSettings>) -> Self {
+        self.stall = settings;
+        self
+    }
+
     pub async fn start_download(
         &self,
         file_hash: String,
@@ -349,6 +460,62 @@ impl MultiSourceDownloadService {
         }
     }
 
+    /// Per-chunk provider counts for an active download, or for a queued one
+    /// from its DHT metadata and any chunks already on disk. Providers
+    /// announce whole files, so each healthy source counts toward every chunk;
+    /// peers in the download's chunk-have swarm count toward the chunks they
+    /// announced.
+    pub async fn chunk_availability(
+        &self,
+        file_hash: &str,
+        lookup_timeout_ms: u64,
+    ) -> Result<ChunkAvailability, String> {
+        {
+            let downloads = self.active_downloads.read().await;
+            if let Some(download) = downloads.get(file_hash) {
+                let mut providers: Vec<Option<Vec<u32>>> = download
+                    .source_assignments
+                    .values()
+                    .filter(|a| a.status != SourceStatus::Failed)
+                    .map(|_| None)
+                    .collect();
+                providers.extend(
+                    self.dht_service
+                        .swarm_partial_holdings(file_hash)
+                        .into_iter()
+                        .map(Some),
+                );
+                let completed: Vec<u32> = download.completed_chunks.keys().copied().collect();
+                return Ok(ChunkAvailability::compute(
+                    file_hash,
+                    download.chunks.len() as u32,
+                    &providers,
+                    &completed,
+                ));
+            }
+        }
+
+        let metadata = self
+            .dht_service
+            .synchronous_search_metadata(file_hash.to_string(), lookup_timeout_ms)
+            .await
+            .map_err(|e| format!("DHT search failed: {}", e))?
+            .ok_or_else(|| "File metadata not found".to_string())?;
+        let provider_count = metadata.seeders.len()
+            + metadata.ftp_sources.as_ref().map_or(0, Vec::len)
+            + metadata.ed2k_sources.as_ref().map_or(0, Vec::len)
+            + metadata.http_sources.as_ref().map_or(0, Vec::len)
+            + usize::from(metadata.info_hash.is_some());
+        let total_chunks = metadata.file_size.div_ceil(DEFAULT_CHUNK_SIZE as u64) as u32;
+        let completed = self.scan_existing_chunks(file_hash).await.unwrap_or_default();
+        Ok(ChunkAvailability::compute(
+            file_hash,
+            total_chunks,
+            &vec![None; provider_count],
+            &completed,
+        ))
+    }
+
     /// Verify chunk integrity and handle failure if hash mismatch
     /// Returns Ok(()) if verification passes, Err(()) if it fails
     pub async fn verify_chunk_for_download(
@@ -432,6 +599,11 @@ impl MultiSourceDownloadService {
                         error!("Failed to retry chunks for {}: {}", file_hash, e);
                     }
                 }
+                MultiSourceCommand::RecoverStalledDownload { file_hash } => {
+                    if let Err(e) = self.handle_recover_stalled_download(&file_hash).await {
+                        warn!("Failed to recover stalled download {}: {}", file_hash, e);
+                    }
+                }
             }
         }
     }
@@ -643,10 +815,42 @@ impl MultiSourceDownloadService {
             }
         }
 
+        // Exchange chunk-have announcements with the other participants so
+        // partially complete peers can serve chunks too
+        {
+            let seeders: Vec<String> = available_sources
+                .iter()
+                .filter_map(|source| match source {
+                    DownloadSource::P2p(info) => Some(info.peer_id.clone()),
+                    _ => None,
+                })
+                .collect();
+            let have: Vec<u32> = self
+                .active_downloads
+                .read()
+                .await
+                .get(&file_hash)
+                .map(|d| d.completed_chunks.keys().copied().collect())
+                .unwrap_or_default();
+            self.dht_service
+                .join_chunk_swarm(&file_hash, total_chunks, &have, &seeders)
+                .await;
+        }
+
         // Start source connections and assign chunks
         self.start_source_connections(&file_hash, selected_sources.clone())
             .await?;
 
+        // Swarm members serve chunks alongside the sources from the start
+        let source_peers: Vec<String> = selected_sources
+            .iter()
+            .filter_map(|source| match source {
+                DownloadSource::P2p(info) => Some(info.peer_id.clone()),
+                _ => None,
+            })
+            .collect();
+        self.spawn_swarm_fetcher(&file_hash, source_peers);
+
         // Emit download started event via TransferEventBus
         let available_source_infos: Vec<SourceInfo> = selected_sources.iter().map(|s| {
             let (source_type, address) = match s {
@@ -768,8 +972,13 @@ impl MultiSourceDownloadService {
     ) -> Vec<DownloadSource> {
         let mut sources = available_sources.to_vec();
 
-        // Sort by priority score (higher is better)
-        sources.sort_by(|a, b| b.priority_score().cmp(&a.priority_score()));
+        // Trusted peers first, then by priority score (higher is better)
+        let trusted_peers = crate::trusted_peers::global();
+        let preferred = |source: &DownloadSource| match source {
+            DownloadSource::P2p(info) => trusted_peers.preferred_source(&info.peer_id),
+            _ => false,
+        };
+        sources.sort_by_key(|s| std::cmp::Reverse((preferred(s), s.priority_score())));
 
         // Take the top sources
         sources.truncate(max_sources);
@@ -961,6 +1170,10 @@ impl MultiSourceDownloadService {
                     self.dht_service
                         .send_webrtc_offer(peer_id.clone(), offer_request),
                 )
+                .instrument(tracing::trace_span!(
+                    target: crate::profiling::PROFILE_TARGET,
+                    "net_wait_offer"
+                ))
                 .await
                 {
                     Ok(Ok(answer_receiver)) => {
@@ -968,6 +1181,10 @@ impl MultiSourceDownloadService {
                             Duration::from_secs(CONNECTION_TIMEOUT_SECS),
                             answer_receiver,
                         )
+                        .instrument(tracing::trace_span!(
+                            target: crate::profiling::PROFILE_TARGET,
+                            "net_wait_answer"
+                        ))
                         .await
                         {
                             Ok(Ok(Ok(answer_response))) => {
@@ -1160,6 +1377,7 @@ impl MultiSourceDownloadService {
                 }
             }
         }
+        let cancel = self.source_cancel_token(file_hash, &ftp_url_id).await;
 
         // Parse remote file path from FTP URL
         let remote_path = match self.parse_ftp_remote_path(&ftp_info.url) {
@@ -1193,6 +1411,9 @@ impl MultiSourceDownloadService {
                 if permit.is_err() {
                     continue;
                 }
+                if cancel.is_cancelled() {
+                    break;
+                }
 
                 let downloader = downloader.clone();
                 let connections = connections.clone();
@@ -1207,7 +1428,7 @@ impl MultiSourceDownloadService {
                 let ftp_info_for_task = ftp_info_clone.clone();
                 let command_tx = command_tx.clone();
 
-                let task = tokio::spawn(async move {
+                let task = tokio::spawn(cancel.clone().run_until_cancelled_owned(async move {
                     let _permit = permit.unwrap();
 
                     // Calculate byte range for this chunk
@@ -1529,7 +1750,7 @@ impl MultiSourceDownloadService {
                             Ok(())
                         }
                     }
-                });
+                }));
 
                 tasks.push(task);
             }
@@ -1587,10 +1808,16 @@ impl MultiSourceDownloadService {
         // In a full implementation, this would use the http_download.rs module
         // to download chunks with Range requests and verify hashes
 
-        // Get file metadata to access chunk information
-        let downloads = self.active_downloads.read().await;
-        let download = match downloads.get(file_hash) {
-            Some(download) => download,
+        // Get file metadata to access chunk information; the lock is released
+        // before any request so chunks can be stored and the source rerouted
+        let chunks = self
+            .active_downloads
+            .read()
+            .await
+            .get(file_hash)
+            .map(|download| download.chunks.clone());
+        let chunks = match chunks {
+            Some(chunks) => chunks,
             None => {
                 let error = format!("No active download found for file {}", file_hash);
                 error!("{}", error);
@@ -1598,14 +1825,23 @@ impl MultiSourceDownloadService {
                 return Err(error);
             }
         };
+        let cancel = self.source_cancel_token(file_hash, &http_info.url).await;
 
         // For each requested chunk, attempt HTTP download with hash verification
         for chunk_id in chunk_ids {
+            if cancel.is_cancelled() {
+                info!(
+                    "HTTP source {} was dropped, stopping its chunk requests",
+                    http_info.url
+                );
+                break;
+            }
+
             // Capture start time for duration tracking
             let download_start_ms = current_timestamp_ms();
 
             // Find chunk info
-            let chunk_info = match download.chunks.iter().find(|c| c.chunk_id == chunk_id) {
+            let chunk_info = match chunks.iter().find(|c| c.chunk_id == chunk_id) {
                 Some(chunk) => chunk,
                 None => {
                     warn!("Chunk {} not found in metadata for file {}", chunk_id, file_hash);
@@ -1624,14 +1860,14 @@ impl MultiSourceDownloadService {
                 .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
 
             // Make range request
-            let response = match client
+            let request = client
                 .get(&http_info.url)
                 .header("Range", format!("bytes={}-{}", start_byte, end_byte))
-                .send()
-                .await
-            {
-                Ok(resp) => resp,
-                Err(e) => {
+                .send();
+            let response = match cancel.run_until_cancelled(request).await {
+                None => break,
+                Some(Ok(resp)) => resp,
+                Some(Err(e)) => {
                     let error = format!("HTTP request failed for chunk {}: {}", chunk_id, e);
                     warn!("{}", error);
                     self.on_source_failed(file_hash, &http_info.url, error).await;
@@ -1649,9 +1885,10 @@ impl MultiSourceDownloadService {
             }
 
             // Read response data
-            let chunk_data = match response.bytes().await {
-                Ok(data) => data.to_vec(),
-                Err(e) => {
+            let chunk_data = match cancel.run_until_cancelled(response.bytes()).await {
+                None => break,
+                Some(Ok(data)) => data.to_vec(),
+                Some(Err(e)) => {
                     let error = format!("Failed to read HTTP response for chunk {}: {}", chunk_id, e);
                     warn!("{}", error);
                     self.on_source_failed(file_hash, &http_info.url, error).await;
@@ -1702,6 +1939,18 @@ impl MultiSourceDownloadService {
         Ok(())
     }
 
+    /// Cancellation token of a source's assignment; requests made with it
+    /// stop when the source is rerouted away from
+    async fn source_cancel_token(&self, file_hash: &str, source_id: &str) -> CancellationToken {
+        self.active_downloads
+            .read()
+            .await
+            .get(file_hash)
+            .and_then(|download| download.source_assignments.get(source_id))
+            .map(|assignment| assignment.cancel.clone())
+            .unwrap_or_default()
+    }
+
     /// Store a verified chunk in the active download
     async fn store_verified_chunk(
         &self,
@@ -1740,6 +1989,7 @@ impl MultiSourceDownloadService {
         // Also store in ChunkManager for potential deduplication
         let chunk_manager = self.chunk_manager.clone();
         let chunk_manager_clone = chunk_manager.clone();
+        let dht_service = self.dht_service.clone();
         tokio::spawn(async move {
             // Inline the disk storage logic to avoid lifetime issues
             let chunks_dir = std::path::Path::new("./chunks");
@@ -1773,6 +2023,9 @@ impl MultiSourceDownloadService {
                 hasher.update(&data_for_disk);
                 let content_hash = format!("{:x}", hasher.finalize());
                 let _ = chunk_manager_clone.save_chunk(&content_hash, &data_for_disk);
+
+                // Serve it to the rest of the swarm from now on
+                dht_service.add_swarm_chunk(&file_hash_for_disk, chunk_id_for_disk);
             }
         });
 
@@ -1803,12 +2056,168 @@ impl MultiSourceDownloadService {
 
         // Check if download is complete
         if is_complete {
+            self.dht_service.leave_chunk_swarm(file_hash);
             Self::finalize_download_static(&self.active_downloads, file_hash).await?;
         }
 
         Ok(())
     }
 
+    /// Fetch chunks from swarm members that announced them. Each chunk goes
+    /// to its least busy holder, with the others as fallbacks, and up to
+    /// `MAX_PARALLEL_SWARM_FETCHES` chunks are fetched at once. Members in
+    /// `skip` are left out. Returns the chunks no member could supply.
+    async fn fetch_chunks_from_swarm(
+        &self,
+        file_hash: &str,
+        chunk_ids: Vec<u32>,
+        skip: &[String],
+    ) -> Vec<u32> {
+        use futures::stream::{self, StreamExt};
+
+        let chunks: HashMap<u32, ChunkInfo> = {
+            let downloads = self.active_downloads.read().await;
+            let Some(download) = downloads.get(file_hash) else {
+                return chunk_ids;
+            };
+            download
+                .chunks
+                .iter()
+                .filter(|c| chunk_ids.contains(&c.chunk_id))
+                .map(|c| (c.chunk_id, c.clone()))
+                .collect()
+        };
+
+        let mut remaining = Vec::new();
+        let mut load: HashMap<String, usize> = HashMap::new();
+        let mut plans = Vec::new();
+        for chunk_id in chunk_ids {
+            let Some(chunk_info) = chunks.get(&chunk_id) else {
+                remaining.push(chunk_id);
+                continue;
+            };
+            let mut holders: Vec<String> = self
+                .dht_service
+                .swarm_chunk_holders(file_hash, chunk_id)
+                .into_iter()
+                .filter(|peer| !skip.contains(peer))
+                .collect();
+            if holders.is_empty() {
+                remaining.push(chunk_id);
+                continue;
+            }
+            // Stable, so partial holders still come first among equally busy peers
+            holders.sort_by_key(|peer| load.get(peer).copied().unwrap_or(0));
+            *load.entry(holders[0].clone()).or_default() += 1;
+            plans.push((chunk_info.clone(), holders));
+        }
+
+        let failed: Vec<u32> = stream::iter(plans)
+            .map(|(chunk_info, holders)| async move {
+                let stored = self
+                    .fetch_chunk_from_holders(file_hash, &chunk_info, &holders)
+                    .await;
+                (chunk_info.chunk_id, stored)
+            })
+            .buffer_unordered(MAX_PARALLEL_SWARM_FETCHES)
+            .filter_map(|(chunk_id, stored)| async move { (!stored).then_some(chunk_id) })
+            .collect()
+            .await;
+        remaining.extend(failed);
+        remaining
+    }
+
+    /// Fetch one chunk from the first of `holders` that serves a verified
+    /// copy and store it
+    async fn fetch_chunk_from_holders(
+        &self,
+        file_hash: &str,
+        chunk_info: &ChunkInfo,
+        holders: &[String],
+    ) -> bool {
+        let chunk_id = chunk_info.chunk_id;
+        for peer in holders {
+            let started_at = current_timestamp_ms();
+            let data = match self
+                .dht_service
+                .fetch_swarm_chunk(peer, file_hash, chunk_id)
+                .await
+            {
+                Ok(data) => data,
+                Err(e) => {
+                    debug!(
+                        "Swarm fetch of chunk {} from {} failed: {}",
+                        chunk_id, peer, e
+                    );
+                    continue;
+                }
+            };
+            if let Err((expected, actual)) = verify_chunk_integrity(chunk_info, &data) {
+                warn!(
+                    "Chunk {} from swarm peer {} failed verification: expected {}, got {}",
+                    chunk_id, peer, expected, actual
+                );
+                continue;
+            }
+            // A source may have delivered it while this request was in flight
+            let done = match self.active_downloads.read().await.get(file_hash) {
+                Some(download) => download.completed_chunks.contains_key(&chunk_id),
+                None => true,
+            };
+            if done {
+                return true;
+            }
+            if self
+                .store_verified_chunk(
+                    file_hash,
+                    chunk_info,
+                    data,
+                    started_at,
+                    peer,
+                    SourceType::P2p,
+                )
+                .await
+                .is_ok()
+            {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Fetch missing chunks from the download's swarm for as long as it runs,
+    /// in parallel with its sources. Partially complete peers and seeders
+    /// learned through announcements act as extra providers; peers that are
+    /// already sources of the download are left to the source pipeline.
+    fn spawn_swarm_fetcher(&self, file_hash: &str, sources: Vec<String>) {
+        let service = self.clone();
+        let file_hash = file_hash.to_string();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(SWARM_FETCH_INTERVAL);
+            loop {
+                interval.tick().await;
+                let missing: Vec<u32> = {
+                    let downloads = service.active_downloads.read().await;
+                    let Some(download) = downloads.get(&file_hash) else {
+                        break;
+                    };
+                    download
+                        .chunks
+                        .iter()
+                        .map(|c| c.chunk_id)
+                        .filter(|id| !download.completed_chunks.contains_key(id))
+                        .collect()
+                };
+                if missing.is_empty() {
+                    break;
+                }
+                service
+                    .fetch_chunks_from_swarm(&file_hash, missing, &sources)
+                    .await;
+            }
+        });
+    }
+
     /// Ingest a fully downloaded file (e.g., from BitTorrent) into the chunk pipeline
     async fn ingest_file_chunks(
         downloads: &Arc<RwLock<HashMap<String, ActiveDownload>>>,
@@ -1985,20 +2394,18 @@ impl MultiSourceDownloadService {
             }
         }
 
-        // Determine output folder for the torrent (parent of requested output path)
-        let (output_folder, expected_name) = {
+        // The torrent is fetched into the staging area; its data goes through the chunk
+        // pipeline, so the output path is only written once it matches the published id
+        let output_folder = crate::download_staging::global()
+            .root()
+            .join("bittorrent")
+            .join(file_hash);
+        let expected_name = {
             let downloads = self.active_downloads.read().await;
             let download = downloads
                 .get(file_hash)
                 .ok_or_else(|| "Download state missing during BitTorrent start".to_string())?;
-
-            let target_path = std::path::PathBuf::from(&download.output_path);
-            let parent = target_path
-                .parent()
-                .map(|p| p.to_path_buf())
-                .unwrap_or_else(|| std::path::PathBuf::from("."));
-
-            (parent, download.file_metadata.file_name.clone())
+            download.file_metadata.file_name.clone()
         };
 
         if let Err(e) = tokio::fs::create_dir_all(&output_folder).await {
@@ -2071,7 +2478,9 @@ impl MultiSourceDownloadService {
                     crate::bittorrent_handler::BitTorrentEvent::Completed => {
                         info!("BitTorrent download completed for {}", &file_hash_string);
 
-                        match tokio::fs::read(&target_path).await {
+                        let read = tokio::fs::read(&target_path).await;
+                        Self::discard_staged_torrent(&bittorrent_handler, &magnet).await;
+                        match read {
                             Ok(file_bytes) => {
                                 // Ingest the file into chunk pipeline so finalize_download works
                                 if let Err(e) = Self::ingest_file_chunks(
@@ -2113,6 +2522,7 @@ impl MultiSourceDownloadService {
                             "BitTorrent download failed for {}: {}",
                             file_hash_string, err
                         );
+                        Self::discard_staged_torrent(&bittorrent_handler, &magnet).await;
                         // Mark as failed
                         let mut downloads = downloads_arc.write().await;
                         if let Some(download) = downloads.get_mut(&file_hash_string) {
@@ -2129,6 +2539,16 @@ impl MultiSourceDownloadService {
         Ok(())
     }
 
+    /// Drop a torrent fetched for the chunk pipeline along with its staged files
+    async fn discard_staged_torrent(bittorrent_handler: &BitTorrentHandler, magnet: &str) {
+        let Some(info_hash) = BitTorrentHandler::extract_info_hash(magnet) else {
+            return;
+        };
+        if let Err(e) = bittorrent_handler.cancel_torrent(&info_hash, true).await {
+            warn!("Failed to remove staged torrent {}: {}", info_hash, e);
+        }
+    }
+
     /// Parse remote path from FTP URL (placeholder implementation)
     fn parse_ftp_remote_path(&self, url: &str) -> Result<String, String> {
         use url::Url;
@@ -2266,6 +2686,7 @@ impl MultiSourceDownloadService {
         let transfer_event_bus = Arc::clone(&self.transfer_event_bus);
         let event_tx = self.event_tx.clone();
         let chunk_manager = self.chunk_manager.clone();
+        let cancel = self.source_cancel_token(file_hash, &server_url_id).await;
 
         // Spawn task to download chunks
         tokio::spawn(async move {
@@ -2281,6 +2702,9 @@ impl MultiSourceDownloadService {
                 // Sort chunks by ID for ordered extraction
                 our_chunk_infos.sort_by_key(|chunk| chunk.chunk_id);
                 let permit = semaphore.clone().acquire_owned().await;
+                if cancel.is_cancelled() {
+                    break;
+                }
                 let ed2k_connections_clone = Arc::clone(&ed2k_connections);
                 let active_downloads_clone = Arc::clone(&active_downloads);
                 let file_hash_inner = file_hash_clone.clone();
@@ -2291,7 +2715,7 @@ impl MultiSourceDownloadService {
                 let event_tx_clone = event_tx.clone();
                 let chunk_manager_clone = chunk_manager.clone();
 
-                let handle = tokio::spawn(async move {
+                let handle = tokio::spawn(cancel.clone().run_until_cancelled_owned(async move {
                     let _permit = permit; // Hold permit until task completes
 
                     // Get ed2k client from pool
@@ -2559,7 +2983,7 @@ impl MultiSourceDownloadService {
                             }
                         }
                     }
-                });
+                }));
                 handles.push(handle);
             }
 
@@ -3239,6 +3663,7 @@ impl MultiSourceDownloadService {
 
     async fn handle_cancel_download(&self, file_hash: &str) {
         info!("Cancelling download for file: {}", file_hash);
+        self.dht_service.leave_chunk_swarm(file_hash);
 
         let download = {
             let mut downloads = self.active_downloads.write().await;
@@ -3320,6 +3745,15 @@ impl MultiSourceDownloadService {
             return Ok(());
         }
 
+        // Partially complete peers in the swarm may have what the sources
+        // failed to deliver
+        let failed_chunks = self
+            .fetch_chunks_from_swarm(file_hash, failed_chunks, &[])
+            .await;
+        if failed_chunks.is_empty() {
+            return Ok(());
+        }
+
         // Try to find available sources for retry.
         //
         // IMPORTANT:
@@ -3378,6 +3812,74 @@ impl MultiSourceDownloadService {
         Ok(())
     }
 
+    /// Recovery for a download that has stopped receiving data: look up the
+    /// file's providers again and move the unfinished chunks to peers that are
+    /// not already in use. Without new peers, drop and re-open the
+    /// connections to the current sources.
+    async fn handle_recover_stalled_download(&self, file_hash: &str) -> Result<(), String> {
+        let (metadata, current_sources) = {
+            let downloads = self.active_downloads.read().await;
+            let download = downloads.get(file_hash).ok_or("Download not found")?;
+            let sources: Vec<(String, DownloadSource)> = download
+                .source_assignments
+                .iter()
+                .map(|(id, assignment)| (id.clone(), assignment.source.clone()))
+                .collect();
+            (download.file_metadata.clone(), sources)
+        };
+
+        let new_peers: Vec<String> = match self.dht_service.discover_peers_for_file(&metadata).await {
+            Ok(peers) => peers
+                .into_iter()
+                .filter(|peer| !current_sources.iter().any(|(id, _)| id == peer))
+                .collect(),
+            Err(e) => {
+                warn!("Provider lookup for stalled download {} failed: {}", file_hash, e);
+                Vec::new()
+            }
+        };
+
+        let sources: Vec<DownloadSource> = if new_peers.is_empty() {
+            info!("No new providers for stalled download {}, reconnecting", file_hash);
+            for (source_id, source) in &current_sources {
+                if let DownloadSource::P2p(_) = source {
+                    let _ = self.webrtc_service.close_connection(source_id.clone()).await;
+                }
+            }
+            current_sources.into_iter().map(|(_, source)| source).collect()
+        } else {
+            info!(
+                "Moving stalled download {} to {} new providers",
+                file_hash,
+                new_peers.len()
+            );
+            new_peers
+                .into_iter()
+                .map(|peer_id| {
+                    DownloadSource::P2p(crate::download_source::P2pSourceInfo {
+                        peer_id,
+                        multiaddr: None,
+                        reputation: None,
+                        supports_encryption: false,
+                        protocol: Some("webrtc".to_string()),
+                    })
+                })
+                .collect()
+        };
+
+        // Everything not yet completed is reassigned below
+        {
+            let mut downloads = self.active_downloads.write().await;
+            if let Some(download) = downloads.get_mut(file_hash) {
+                download.failed_chunks.clear();
+                for assignment in download.source_assignments.values_mut() {
+                    assignment.status = SourceStatus::Failed;
+                }
+            }
+        }
+        self.start_source_connections(file_hash, sources).await
+    }
+
     fn calculate_progress(&self, download: &ActiveDownload) -> MultiSourceProgress {
         let total_chunks = download.chunks.len() as u32;
         let completed_chunks = download.completed_chunks.len() as u32;
@@ -3432,10 +3934,16 @@ impl MultiSourceDownloadService {
         let event_tx = self.event_tx.clone();
         let transfer_event_bus = self.transfer_event_bus.clone();
         let analytics_service = self.analytics_service.clone();
+        let command_tx = self.command_tx.clone();
+        let slow_transfer = self.slow_transfer.clone();
+        let stall = self.stall.clone();
+        let webrtc_service = self.webrtc_service.clone();
 
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(Duration::from_secs(2));
             let start_time = std::time::Instant::now();
+            let mut watchdog = SlowTransferWatchdog::new();
+            let mut stall_watchdog = StallWatchdog::new();
 
             loop {
                 interval.tick().await;
@@ -3560,6 +4068,131 @@ impl MultiSourceDownloadService {
                         break;
                     }
 
+                    let stall_config = stall.config();
+                    match stall_watchdog.observe(&stall_config, Instant::now(), progress.downloaded_size) {
+                        Some(StallVerdict::Stalled { idle_for, attempt }) => {
+                            warn!(
+                                "Download {} stalled for {}s, recovery attempt {}/{}",
+                                file_hash,
+                                idle_for.as_secs(),
+                                attempt,
+                                stall_config.max_recovery_attempts
+                            );
+                            transfer_event_bus.emit_stalled(TransferStalledEvent {
+                                transfer_id: file_hash.clone(),
+                                file_hash: file_hash.clone(),
+                                idle_seconds: idle_for.as_secs(),
+                                recovery_attempt: attempt,
+                                max_recovery_attempts: stall_config.max_recovery_attempts,
+                                downloaded_bytes: progress.downloaded_size,
+                                total_bytes: progress.total_size,
+                                detected_at: current_timestamp_ms(),
+                            });
+                            let _ = event_tx.send(MultiSourceEvent::DownloadStalled {
+                                file_hash: file_hash.clone(),
+                                idle_secs: idle_for.as_secs(),
+                                recovery_attempt: attempt,
+                            });
+                            let _ = command_tx.send(MultiSourceCommand::RecoverStalledDownload {
+                                file_hash: file_hash.clone(),
+                            });
+                        }
+                        Some(StallVerdict::TimedOut { idle_for, attempts }) => {
+                            let error = format!(
+                                "Transfer timed out: no data received for {}s after {} recovery attempts",
+                                idle_for.as_secs(),
+                                attempts
+                            );
+                            warn!("Download {}: {}", file_hash, error);
+                            transfer_event_bus.emit_failed_with_analytics(TransferFailedEvent {
+                                transfer_id: file_hash.clone(),
+                                file_hash: file_hash.clone(),
+                                failed_at: current_timestamp_ms(),
+                                error: error.clone(),
+                                error_category: ErrorCategory::Timeout,
+                                downloaded_bytes: progress.downloaded_size,
+                                total_bytes: progress.total_size,
+                                retry_possible: true,
+                            }, &analytics_service).await;
+                            let _ = event_tx.send(MultiSourceEvent::DownloadFailed {
+                                file_hash: file_hash.clone(),
+                                error,
+                            });
+                            // Tear down the connections; downloaded chunks stay on disk
+                            let _ = command_tx.send(MultiSourceCommand::CancelDownload {
+                                file_hash: file_hash.clone(),
+                            });
+                            break;
+                        }
+                        None => {}
+                    }
+
+                    let slow_config = slow_transfer.config();
+                    let active_sources: Vec<(String, u64)> = progress
+                        .source_assignments
+                        .iter()
+                        .filter(|a| {
+                            matches!(a.status, SourceStatus::Connected | SourceStatus::Downloading)
+                        })
+                        .map(|a| {
+                            let id = a.source_id();
+                            let bytes = sources_used
+                                .iter()
+                                .find(|s| s.source_id == id)
+                                .map_or(0, |s| s.bytes_provided);
+                            (id, bytes)
+                        })
+                        .collect();
+                    if let Some(verdict) = watchdog.observe(
+                        &slow_config,
+                        Instant::now(),
+                        progress.downloaded_size,
+                        &active_sources,
+                    ) {
+                        let source_id = verdict.slowest_source.map(|(id, _)| id);
+                        let reroute = slow_config.auto_switch && verdict.alternatives > 0;
+                        let rerouted = match source_id.as_deref() {
+                            Some(slow_id) if reroute => {
+                                Self::reroute_slow_source(&downloads, &file_hash, slow_id).await
+                            }
+                            _ => None,
+                        };
+                        if let Some((DownloadSource::P2p(peer), _)) = &rerouted {
+                            // Chunk requests already sent to a peer only stop with the connection
+                            let _ = webrtc_service.close_connection(peer.peer_id.clone()).await;
+                        }
+                        let rerouted_chunks = rerouted.map_or(0, |(_, chunks)| chunks);
+                        warn!(
+                            "Slow transfer {}: {:.0} B/s for {}s (floor {}), {} chunks rerouted",
+                            file_hash,
+                            verdict.throughput_bps,
+                            verdict.slow_for.as_secs(),
+                            slow_config.min_throughput_bps,
+                            rerouted_chunks
+                        );
+                        transfer_event_bus.emit_slow_transfer(SlowTransferEvent {
+                            transfer_id: file_hash.clone(),
+                            file_hash: file_hash.clone(),
+                            throughput_bps: verdict.throughput_bps,
+                            min_throughput_bps: slow_config.min_throughput_bps,
+                            slow_for_seconds: verdict.slow_for.as_secs(),
+                            source_id: source_id.clone(),
+                            rerouted_chunks,
+                            detected_at: current_timestamp_ms(),
+                        });
+                        let _ = event_tx.send(MultiSourceEvent::SlowTransfer {
+                            file_hash: file_hash.clone(),
+                            peer_id: source_id,
+                            throughput_bps: verdict.throughput_bps,
+                            rerouted_chunks,
+                        });
+                        if rerouted_chunks > 0 {
+                            let _ = command_tx.send(MultiSourceCommand::RetryFailedChunks {
+                                file_hash: file_hash.clone(),
+                            });
+                        }
+                    }
+
                     // Emit progress update via TransferEventBus with analytics
                     transfer_event_bus.emit_progress_with_analytics(TransferProgressEvent {
                         transfer_id: file_hash.clone(),
@@ -3588,6 +4221,33 @@ impl MultiSourceDownloadService {
         });
     }
 
+    /// Stop using a slow source: cancel its in-flight chunk requests, mark
+    /// it failed and queue its unfinished chunks for retry on the remaining
+    /// sources. Returns the dropped source and the number of chunks queued.
+    async fn reroute_slow_source(
+        downloads: &Arc<RwLock<HashMap<String, ActiveDownload>>>,
+        file_hash: &str,
+        source_id: &str,
+    ) -> Option<(DownloadSource, u32)> {
+        let mut downloads = downloads.write().await;
+        let download = downloads.get_mut(file_hash)?;
+        let assignment = download.source_assignments.get_mut(source_id)?;
+        // Cancel first so a late response can't land on a requeued chunk
+        assignment.cancel.cancel();
+        assignment.status = SourceStatus::Failed;
+        download
+            .pending_requests
+            .retain(|_, request| request.source_id != source_id);
+        let remaining: Vec<u32> = assignment
+            .chunks
+            .iter()
+            .copied()
+            .filter(|chunk_id| !download.completed_chunks.contains_key(chunk_id))
+            .collect();
+        download.failed_chunks.extend(&remaining);
+        Some((assignment.source.clone(), remaining.len() as u32))
+    }
+
     fn calculate_progress_static(download: &ActiveDownload) -> MultiSourceProgress {
         let total_chunks = download.chunks.len() as u32;
         let completed_chunks = download.completed_chunks.len() as u32;
@@ -3652,6 +4312,7 @@ impl MultiSourceDownloadService {
 
     /// Finalize a completed download
     async fn finalize_download(&self, file_hash: &str) -> Result<(), String> {
+        self.dht_service.leave_chunk_swarm(file_hash);
         Self::finalize_download_static(&self.active_downloads, file_hash).await?;
         // Remove persisted download state since download is complete
         if let Err(e) = self.remove_download_state(file_hash).await {
@@ -3671,20 +4332,18 @@ impl MultiSourceDownloadService {
 
         if let Some(download) = download {
             // Assemble file from chunks
-            // Stream assembly directly to disk (avoid allocating a full-file Vec<u8>).
+            // Stream assembly into the staging area (avoid allocating a full-file Vec<u8>);
+            // the file only appears at the output path once it matches the published id.
             let output_path = std::path::Path::new(&download.output_path);
-            if let Some(parent) = output_path.parent() {
-                tokio::fs::create_dir_all(parent)
-                    .await
-                    .map_err(|e| format!("Failed to create output directory: {}", e))?;
-            }
+            let staged = crate::download_staging::global()
+                .stage(file_hash, download.file_metadata.file_size)?;
 
             use tokio::io::{AsyncSeekExt, AsyncWriteExt};
             use std::io::SeekFrom;
 
-            let mut file = tokio::fs::File::create(output_path)
+            let mut file = tokio::fs::File::create(staged.path())
                 .await
-                .map_err(|e| format!("Failed to create output file: {}", e))?;
+                .map_err(|e| format!("Failed to create staged file: {}", e))?;
 
             // Pre-allocate file size to reduce fragmentation and improve write performance.
             file.set_len(download.file_metadata.file_size)
@@ -3708,6 +4367,17 @@ impl MultiSourceDownloadService {
             file.flush()
                 .await
                 .map_err(|e| format!("Failed to flush output file: {}", e))?;
+            drop(file);
+
+            // Encrypted files are assembled as published, so only plaintext is checked by id
+            let metadata = &download.file_metadata;
+            let verification = crate::download_staging::Verification {
+                expected_size: Some(metadata.file_size),
+                published_id: (!metadata.is_encrypted && !metadata.merkle_root.is_empty())
+                    .then(|| metadata.merkle_root.clone()),
+                ..Default::default()
+            };
+            staged.commit(output_path, &verification).await?;
 
             let duration = download.start_time.elapsed();
             let average_speed = download.file_metadata.file_size as f64 / duration.as_secs_f64();
@@ -4505,6 +5175,53 @@ mod tests {
         assert!(matches!(assignment.source, DownloadSource::Ftp(_)));
     }
 
+    #[tokio::test]
+    async fn rerouting_a_source_cancels_its_requests() {
+        use crate::download_source::HttpSourceInfo;
+
+        let url = "https://slow.example.com/file.bin".to_string();
+        let source = DownloadSource::Http(HttpSourceInfo {
+            url: url.clone(),
+            auth_header: None,
+            verify_ssl: true,
+            headers: None,
+            timeout_secs: Some(30),
+        });
+        let assignment = SourceAssignment::new(source, vec![0, 1]);
+        let cancel = assignment.cancel.clone();
+        let request = ChunkRequest {
+            chunk_id: 1,
+            source_id: url.clone(),
+            requested_at: Instant::now(),
+            retry_count: 0,
+        };
+        let download = ActiveDownload {
+            file_metadata: FileMetadata::default(),
+            chunks: Vec::new(),
+            source_assignments: HashMap::from([(url.clone(), assignment)]),
+            completed_chunks: HashMap::new(),
+            pending_requests: HashMap::from([(1, request)]),
+            failed_chunks: VecDeque::new(),
+            start_time: Instant::now(),
+            last_progress_update: Instant::now(),
+            output_path: String::new(),
+            ed2k_chunk_hashes: None,
+        };
+        let downloads = Arc::new(RwLock::new(HashMap::from([("file".to_string(), download)])));
+
+        let (_, rerouted) =
+            MultiSourceDownloadService::reroute_slow_source(&downloads, "file", &url)
+                .await
+                .unwrap();
+
+        assert_eq!(rerouted, 2);
+        assert!(cancel.is_cancelled());
+        let downloads = downloads.read().await;
+        let download = &downloads["file"];
+        assert!(download.pending_requests.is_empty());
+        assert_eq!(download.failed_chunks, VecDeque::from([0, 1]));
+    }
+
     #[test]
     fn verify_chunk_integrity_skips_non_hex_hash() {
         let data = b"hello world";
@@ -4541,6 +5258,22 @@ mod tests {
         assert_eq!(chunk.hash, "test_hash");
     }
 
+    #[test]
+    fn chunk_availability_flags_chunks_without_providers() {
+        let providers = vec![None, Some(vec![0, 1, 2]), Some(vec![1, 9])];
+        let availability = ChunkAvailability::compute("hash", 6, &providers, &[4, 0]);
+        assert_eq!(availability.known_providers, 3);
+        assert_eq!(availability.chunk_providers, vec![2, 3, 2, 1, 1, 1]);
+        assert_eq!(availability.completed_chunks, vec![0, 4]);
+        assert!(availability.unavailable_chunks.is_empty());
+
+        let partial_only = ChunkAvailability::compute("hash", 4, &providers[1..], &[3]);
+        assert_eq!(partial_only.chunk_providers, vec![1, 2, 1, 0]);
+        assert!(partial_only.unavailable_chunks.is_empty());
+        let missing = ChunkAvailability::compute("hash", 5, &providers[1..], &[]);
+        assert_eq!(missing.unavailable_chunks, vec![3, 4]);
+    }
+
     #[test]
     fn test_multi_source_constants() {
         assert_eq!(DEFAULT_CHUNK_SIZE, 256 * 1024);
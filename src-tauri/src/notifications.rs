@@ -0,0 +1,323 @@
+// Native desktop notifications for transfer milestones
+//
+// The backend watches the unified event bus and raises OS-level notifications
+// (through the Tauri notification plugin) for completed downloads and for
+// transfers that failed after their final retry, so the user hears about them
+// even when the window is hidden or minimized. Incoming direct-send (ChiralDrop)
+// offers are picked up by polling this peer's offer key on the DHT.
+//
+// Each kind of notification can be switched off separately; the toggles are
+// persisted like the other per-feature settings.
+
+use crate::event_bus::{self, EventPayload};
+use crate::transfer_events::TransferEvent;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::AppHandle;
+use tauri_plugin_notification::NotificationExt;
+use tokio::sync::broadcast;
+use tracing::{debug, warn};
+
+/// How often the DHT is checked for new direct-send offers
+pub const OFFER_POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct NotificationConfig {
+    /// Master switch
+    pub enabled: bool,
+    pub download_completed: bool,
+    /// Transfers that failed permanently (no retries left)
+    pub transfer_failed: bool,
+    pub direct_send_offers: bool,
+    /// New content from subscribed publishers
+    pub subscribed_content: bool,
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            download_completed: true,
+            transfer_failed: true,
+            direct_send_offers: true,
+            subscribed_content: true,
+        }
+    }
+}
+
+impl NotificationConfig {
+    pub fn allows(&self, kind: NotificationKind) -> bool {
+        self.enabled
+            && match kind {
+                NotificationKind::DownloadCompleted => self.download_completed,
+                NotificationKind::TransferFailed => self.transfer_failed,
+                NotificationKind::DirectSendOffer => self.direct_send_offers,
+                NotificationKind::SubscribedContent => self.subscribed_content,
+            }
+    }
+}
+
+/// Shared, optionally persisted notification toggles
+pub struct NotificationSettings {
+    config: Mutex<NotificationConfig>,
+    persist_path: Option<PathBuf>,
+}
+
+impl NotificationSettings {
+    pub fn new() -> Self {
+        Self {
+            config: Mutex::new(NotificationConfig::default()),
+            persist_path: None,
+        }
+    }
+
+    /// Settings loaded from and saved to `path`
+    pub fn with_persistence(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let config = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|raw| serde_json::from_str::<NotificationConfig>(&raw).ok())
+            .unwrap_or_default();
+        Self {
+            config: Mutex::new(config),
+            persist_path: Some(path),
+        }
+    }
+
+    pub fn config(&self) -> NotificationConfig {
+        self.config
+            .lock()
+            .map(|c| c.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn set_config(&self, config: NotificationConfig) -> Result<(), String> {
+        if let Some(path) = &self.persist_path {
+            let json = serde_json::to_string_pretty(&config)
+                .map_err(|e| format!("Failed to serialize notification settings: {}", e))?;
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| format!("Failed to create config directory: {}", e))?;
+            }
+            std::fs::write(path, json)
+                .map_err(|e| format!("Failed to save notification settings: {}", e))?;
+        }
+        let mut current = self
+            .config
+            .lock()
+            .map_err(|_| "Notification settings lock poisoned".to_string())?;
+        *current = config;
+        Ok(())
+    }
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    DownloadCompleted,
+    TransferFailed,
+    DirectSendOffer,
+    SubscribedContent,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DesktopNotification {
+    pub kind: NotificationKind,
+    pub title: String,
+    pub body: String,
+}
+
+/// A direct-send offer as published on the DHT by the sending peer
+#[derive(Debug, Clone, Deserialize)]
+pub struct DirectSendOffer {
+    pub notification_type: String,
+    pub sender_alias: String,
+    pub file_name: String,
+    pub file_size: u64,
+    pub price: f64,
+}
+
+/// DHT key holding the latest direct-send offer for `peer_id`
+pub fn offer_key(peer_id: &str) -> String {
+    format!("chiraldrop:{}", peer_id)
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+fn short_hash(hash: &str) -> &str {
+    hash.get(..12).unwrap_or(hash)
+}
+
+/// Notification for a bus event, if it is a milestone the user should hear
+/// about
+pub fn for_event(payload: &EventPayload) -> Option<DesktopNotification> {
+    match payload {
+        EventPayload::Transfer(TransferEvent::Completed(e)) => Some(DesktopNotification {
+            kind: NotificationKind::DownloadCompleted,
+            title: "Download complete".to_string(),
+            body: format!("{} ({})", e.file_name, format_size(e.file_size)),
+        }),
+        // A failure that will be retried is not the end of the transfer
+        EventPayload::Transfer(TransferEvent::Failed(e)) if !e.retry_possible => {
+            Some(DesktopNotification {
+                kind: NotificationKind::TransferFailed,
+                title: "Transfer failed".to_string(),
+                body: format!("{}: {}", short_hash(&e.file_hash), e.error),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Notification for the raw value stored at `offer_key`, when it is a
+/// transfer offer
+pub fn for_offer(raw: &[u8]) -> Option<DesktopNotification> {
+    let offer: DirectSendOffer = serde_json::from_slice(raw).ok()?;
+    if offer.notification_type != "transfer_request" {
+        return None;
+    }
+    let price = if offer.price > 0.0 {
+        format!(" for {} ETC", offer.price)
+    } else {
+        String::new()
+    };
+    Some(DesktopNotification {
+        kind: NotificationKind::DirectSendOffer,
+        title: format!("{} wants to send you a file", offer.sender_alias),
+        body: format!("{} ({}){}", offer.file_name, format_size(offer.file_size), price),
+    })
+}
+
+/// Notification for a file announced by a subscribed publisher
+pub fn for_subscription(publisher: &str, file_name: &str, file_size: u64) -> DesktopNotification {
+    DesktopNotification {
+        kind: NotificationKind::SubscribedContent,
+        title: format!("New from {}", publisher),
+        body: format!("{} ({})", file_name, format_size(file_size)),
+    }
+}
+
+/// Show `notification` if its kind is enabled
+pub fn show(
+    app_handle: &AppHandle,
+    settings: &NotificationSettings,
+    notification: &DesktopNotification,
+) {
+    if !settings.config().allows(notification.kind) {
+        return;
+    }
+    if let Err(e) = app_handle
+        .notification()
+        .builder()
+        .title(&notification.title)
+        .body(&notification.body)
+        .show()
+    {
+        warn!("Failed to show desktop notification: {}", e);
+    }
+}
+
+/// Raise notifications for milestones published on the event bus
+pub fn spawn_bus_notifier(
+    app_handle: AppHandle,
+    settings: Arc<NotificationSettings>,
+) -> tauri::async_runtime::JoinHandle<()> {
+    let mut rx = event_bus::global().subscribe();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            match rx.recv().await {
+                Ok(envelope) => {
+                    if let Some(notification) = for_event(&envelope.payload) {
+                        show(&app_handle, &settings, &notification);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    debug!("Notifier lagged, skipped {} events", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transfer_events::{ErrorCategory, TransferCompletedEvent, TransferFailedEvent};
+
+    #[test]
+    fn builds_notifications_and_respects_toggles() {
+        let completed = EventPayload::Transfer(TransferEvent::Completed(TransferCompletedEvent {
+            transfer_id: "t1".into(),
+            file_hash: "abc".into(),
+            file_name: "movie.mkv".into(),
+            file_size: 3 * 1024 * 1024,
+            output_path: "/tmp/movie.mkv".into(),
+            completed_at: 0,
+            duration_seconds: 1,
+            average_speed_bps: 0.0,
+            total_chunks: 1,
+            sources_used: Vec::new(),
+        }));
+        let notification = for_event(&completed).unwrap();
+        assert_eq!(notification.kind, NotificationKind::DownloadCompleted);
+        assert_eq!(notification.body, "movie.mkv (3.0 MB)");
+
+        let failed = EventPayload::Transfer(TransferEvent::Failed(TransferFailedEvent {
+            transfer_id: "t2".into(),
+            file_hash: "0123456789abcdef".into(),
+            failed_at: 0,
+            error: "no providers".into(),
+            error_category: ErrorCategory::Network,
+            downloaded_bytes: 0,
+            total_bytes: 10,
+            retry_possible: false,
+        }));
+        assert_eq!(for_event(&failed).unwrap().body, "0123456789ab: no providers");
+        let EventPayload::Transfer(TransferEvent::Failed(mut retried)) = failed else {
+            unreachable!()
+        };
+        retried.retry_possible = true;
+        let retried = EventPayload::Transfer(TransferEvent::Failed(retried));
+        assert!(for_event(&retried).is_none());
+
+        let offer = br#"{"notification_type":"transfer_request","sender_peer_id":"p",
+            "sender_alias":"Swift Comet","file_name":"a.zip","file_size":512,"price":0.5,
+            "file_hash":null}"#;
+        let notification = for_offer(offer).unwrap();
+        assert_eq!(notification.title, "Swift Comet wants to send you a file");
+        assert_eq!(notification.body, "a.zip (512 B) for 0.5 ETC");
+        let accepted = br#"{"notification_type":"transfer_accepted","sender_alias":"x",
+            "file_name":"a.zip","file_size":512,"price":0.0}"#;
+        assert!(for_offer(accepted).is_none());
+
+        let mut config = NotificationConfig::default();
+        assert!(config.allows(NotificationKind::TransferFailed));
+        config.transfer_failed = false;
+        assert!(!config.allows(NotificationKind::TransferFailed));
+        assert!(config.allows(NotificationKind::DirectSendOffer));
+        config.enabled = false;
+        assert!(!config.allows(NotificationKind::DownloadCompleted));
+    }
+}
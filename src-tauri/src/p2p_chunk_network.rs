@@ -1576,6 +1576,7 @@ mod tests {
                 },
             ],
             encrypted_key_bundle: None,
+            content_info: None,
         };
 
         let mut state = DlState::new(
@@ -2334,6 +2335,7 @@ mod tests {
                 },
             ],
             encrypted_key_bundle: None,
+            content_info: None,
         };
 
         // call on_download_start
@@ -379,8 +379,15 @@ impl PeerSelectionService {
             })
             .collect();
 
-        // Sort by score (descending)
-        candidates.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+        // Trusted peers first, then by score (descending)
+        let trusted_peers = crate::trusted_peers::global();
+        candidates.sort_by(|a, b| {
+            let a_trusted = trusted_peers.preferred_source(&a.0);
+            let b_trusted = trusted_peers.preferred_source(&b.0);
+            b_trusted
+                .cmp(&a_trusted)
+                .then_with(|| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal))
+        });
 
         // Select top candidates
         let selected: Vec<String> = candidates
@@ -0,0 +1,192 @@
+// JSON files components keep their settings and state in
+//
+// Files are written next to their final path and renamed into place, so a
+// crash never leaves a truncated file behind. A missing file loads as the
+// default; an unreadable one does too, with a warning, so a bad file never
+// keeps the app from starting.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::warn;
+
+/// Contents of the JSON file at `path`, or the default if it is missing or
+/// unreadable
+pub fn load<T: DeserializeOwned + Default>(path: &Path) -> T {
+    match std::fs::read_to_string(path) {
+        Ok(raw) => parse(path, &raw),
+        Err(_) => T::default(),
+    }
+}
+
+/// [`load`] without blocking the runtime
+pub async fn load_async<T: DeserializeOwned + Default>(path: &Path) -> T {
+    match tokio::fs::read_to_string(path).await {
+        Ok(raw) => parse(path, &raw),
+        Err(_) => T::default(),
+    }
+}
+
+fn parse<T: DeserializeOwned + Default>(path: &Path, raw: &str) -> T {
+    serde_json::from_str(raw).unwrap_or_else(|e| {
+        warn!("{} is unreadable, using defaults: {}", path.display(), e);
+        T::default()
+    })
+}
+
+/// Write `value` to `path` as JSON, creating its directory
+pub fn save<T: Serialize + ?Sized>(path: &Path, value: &T) -> Result<(), String> {
+    let json = serde_json::to_vec_pretty(value)
+        .map_err(|e| format!("Failed to serialize {}: {}", path.display(), e))?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    let tmp = tmp_path(path);
+    std::fs::write(&tmp, json)
+        .and_then(|()| std::fs::rename(&tmp, path))
+        .map_err(|e| format!("Failed to save {}: {}", path.display(), e))
+}
+
+/// [`save`] without blocking the runtime
+pub async fn save_async<T: Serialize + ?Sized>(path: &Path, value: &T) -> Result<(), String> {
+    let json = serde_json::to_vec_pretty(value)
+        .map_err(|e| format!("Failed to serialize {}: {}", path.display(), e))?;
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    let tmp = tmp_path(path);
+    let written = match tokio::fs::write(&tmp, json).await {
+        Ok(()) => tokio::fs::rename(&tmp, path).await,
+        Err(e) => Err(e),
+    };
+    written.map_err(|e| format!("Failed to save {}: {}", path.display(), e))
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+/// A value kept in memory and, with persistence, saved on every change
+pub struct Persisted<T> {
+    value: Mutex<T>,
+    path: Option<PathBuf>,
+}
+
+impl<T: Serialize + DeserializeOwned + Default + Clone> Persisted<T> {
+    /// Starts from the default; nothing is saved
+    pub fn in_memory() -> Self {
+        Self {
+            value: Mutex::new(T::default()),
+            path: None,
+        }
+    }
+
+    /// Loaded from and saved to `path`
+    pub fn with_persistence(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        Self {
+            value: Mutex::new(load(&path)),
+            path: Some(path),
+        }
+    }
+
+    pub fn get(&self) -> T {
+        self.value
+            .lock()
+            .map(|value| value.clone())
+            .unwrap_or_default()
+    }
+
+    /// Replace the value once it is saved
+    pub fn set(&self, value: T) -> Result<(), String> {
+        let mut current = self
+            .value
+            .lock()
+            .map_err(|_| "Persisted value lock poisoned".to_string())?;
+        if let Some(path) = &self.path {
+            save(path, &value)?;
+        }
+        *current = value;
+        Ok(())
+    }
+
+    /// Change the value and save it. The change stays in memory even if
+    /// saving fails.
+    pub fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> Result<R, String> {
+        let mut value = self
+            .value
+            .lock()
+            .map_err(|_| "Persisted value lock poisoned".to_string())?;
+        let result = f(&mut value);
+        if let Some(path) = &self.path {
+            save(path, &*value)?;
+        }
+        Ok(result)
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Default + Clone> Default for Persisted<T> {
+    fn default() -> Self {
+        Self::in_memory()
+    }
+}
+
+/// A path for a config file in a directory removed with it
+#[cfg(test)]
+pub(crate) struct TempConfig {
+    dir: tempfile::TempDir,
+    pub path: PathBuf,
+}
+
+#[cfg(test)]
+impl TempConfig {
+    pub(crate) fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config").join("settings.json");
+        Self { dir, path }
+    }
+
+    /// Scratch directory removed with the config, for anything a test keeps
+    /// next to it
+    pub(crate) fn dir(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+    struct Limits {
+        quota: u64,
+    }
+
+    #[test]
+    fn changes_survive_a_reload_and_bad_files_load_as_defaults() {
+        let config = TempConfig::new();
+        let limits = Persisted::<Limits>::with_persistence(&config.path);
+        assert_eq!(limits.get(), Limits::default());
+        limits.set(Limits { quota: 10 }).unwrap();
+        limits.update(|l| l.quota += 1).unwrap();
+        assert_eq!(
+            Persisted::<Limits>::with_persistence(&config.path).get(),
+            Limits { quota: 11 }
+        );
+        assert!(!tmp_path(&config.path).exists());
+
+        std::fs::write(&config.path, b"{ not json").unwrap();
+        assert_eq!(load::<Limits>(&config.path), Limits::default());
+
+        let unsaved = Persisted::<Limits>::in_memory();
+        unsaved.set(Limits { quota: 1 }).unwrap();
+        assert_eq!(unsaved.get().quota, 1);
+    }
+}
@@ -0,0 +1,318 @@
+// Built-in profiling mode
+//
+// When the node is started with `--profile`, a tracing layer records the wall
+// time of every span, from its first enter to its close, and aggregates the
+// self-time per span stack. Wall time rather than busy time, so an async span
+// parked on a network read is charged for the wait. The result is
+// written in the folded-stack format understood by `inferno-flamegraph` and
+// `flamegraph.pl`, so field reports can be turned into a flamegraph directly:
+//
+//     inferno-flamegraph < chiral-profile.folded > profile.svg
+//
+// Hot paths (hashing, disk IO, network waits) open spans under the
+// `chiral_profile` target at TRACE level. Those are compiled in but filtered
+// out unless profiling is enabled, so the normal build pays nothing for them.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::span::{Attributes, Id};
+use tracing::{info, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Tracing target used by the profiling spans in the transfer path
+pub const PROFILE_TARGET: &str = "chiral_profile";
+
+/// Filter directive that enables the profiling spans
+pub const PROFILE_DIRECTIVE: &str = "chiral_profile=trace";
+
+/// Default output file for `--profile` when no path is given
+pub const DEFAULT_PROFILE_OUTPUT: &str = "chiral-profile.folded";
+
+/// Interval between runtime poll-lag samples
+const POLL_SAMPLE_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Interval between periodic flushes of the folded output
+const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+static ACTIVE_PROFILER: OnceLock<Profiler> = OnceLock::new();
+
+/// Register the process-wide profiler. Only the first call takes effect.
+pub fn install(profiler: Profiler) -> &'static Profiler {
+    ACTIVE_PROFILER.get_or_init(|| profiler)
+}
+
+/// The profiler installed for this process, if `--profile` was given
+pub fn active() -> Option<&'static Profiler> {
+    ACTIVE_PROFILER.get()
+}
+
+/// Per-span bookkeeping stored in the registry extensions
+#[derive(Default)]
+struct SpanTiming {
+    /// First time the span was entered
+    entered_at: Option<Instant>,
+    /// Wall time of the closed child spans
+    child_wall: Duration,
+}
+
+/// Summary of the runtime poll-lag sampler
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PollLagStats {
+    pub samples: u64,
+    pub total_lag: Duration,
+    pub max_lag: Duration,
+}
+
+impl PollLagStats {
+    pub fn mean_lag(&self) -> Duration {
+        if self.samples == 0 {
+            Duration::ZERO
+        } else {
+            self.total_lag / self.samples as u32
+        }
+    }
+}
+
+#[derive(Default)]
+struct ProfileData {
+    /// Folded stack ("a;b;c") -> accumulated self time in microseconds
+    stacks: HashMap<String, u64>,
+    poll_lag: PollLagStats,
+}
+
+/// Shared handle to the collected profile, used to flush it to disk
+#[derive(Clone)]
+pub struct Profiler {
+    data: Arc<Mutex<ProfileData>>,
+    output_path: PathBuf,
+}
+
+impl Profiler {
+    pub fn new(output_path: impl AsRef<Path>) -> Self {
+        Self {
+            data: Arc::new(Mutex::new(ProfileData::default())),
+            output_path: output_path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Tracing layer feeding this profiler
+    pub fn layer(&self) -> ProfileLayer {
+        ProfileLayer {
+            data: self.data.clone(),
+        }
+    }
+
+    pub fn output_path(&self) -> &Path {
+        &self.output_path
+    }
+
+    pub fn poll_lag(&self) -> PollLagStats {
+        self.data.lock().map(|d| d.poll_lag).unwrap_or_default()
+    }
+
+    /// Render the collected stacks in folded format, sorted for stable diffs
+    pub fn folded(&self) -> String {
+        let data = match self.data.lock() {
+            Ok(data) => data,
+            Err(_) => return String::new(),
+        };
+        let mut lines: Vec<_> = data
+            .stacks
+            .iter()
+            .filter(|(_, micros)| **micros > 0)
+            .map(|(stack, micros)| format!("{} {}", stack, micros))
+            .collect();
+        lines.sort();
+        let mut out = lines.join("\n");
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Write the folded output, replacing the previous file atomically
+    pub fn flush(&self) -> io::Result<()> {
+        if let Some(parent) = self.output_path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+        let tmp_path = self.output_path.with_extension("folded.tmp");
+        {
+            let mut file = fs::File::create(&tmp_path)?;
+            file.write_all(self.folded().as_bytes())?;
+            file.sync_all()?;
+        }
+        fs::rename(&tmp_path, &self.output_path)
+    }
+
+    /// Spawn the background tasks: a sampler measuring how late the runtime
+    /// polls a timer task (a proxy for long task polls blocking the worker),
+    /// and a periodic flush so a crash still leaves a usable profile.
+    pub fn spawn_background_tasks(&self) {
+        let sampler = self.clone();
+        tokio::spawn(async move {
+            loop {
+                let started = Instant::now();
+                tokio::time::sleep(POLL_SAMPLE_INTERVAL).await;
+                let lag = started.elapsed().saturating_sub(POLL_SAMPLE_INTERVAL);
+                sampler.record_poll_lag(lag);
+            }
+        });
+
+        let flusher = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(FLUSH_INTERVAL);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                if let Err(e) = flusher.flush() {
+                    tracing::warn!("Failed to write profile output: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Flush and log a short summary; called on shutdown
+    pub fn finish(&self) {
+        let lag = self.poll_lag();
+        match self.flush() {
+            Ok(()) => info!(
+                "Profile written to {} (poll lag mean {:?}, max {:?} over {} samples)",
+                self.output_path.display(),
+                lag.mean_lag(),
+                lag.max_lag,
+                lag.samples
+            ),
+            Err(e) => tracing::warn!("Failed to write profile output: {}", e),
+        }
+    }
+
+    fn record_poll_lag(&self, lag: Duration) {
+        if let Ok(mut data) = self.data.lock() {
+            let stats = &mut data.poll_lag;
+            stats.samples += 1;
+            stats.total_lag += lag;
+            stats.max_lag = stats.max_lag.max(lag);
+            *data
+                .stacks
+                .entry("runtime;poll_lag".to_string())
+                .or_default() += lag.as_micros() as u64;
+        }
+    }
+}
+
+/// Tracing layer that accumulates span self-time by stack
+pub struct ProfileLayer {
+    data: Arc<Mutex<ProfileData>>,
+}
+
+impl<S> Layer<S> for ProfileLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, _attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanTiming::default());
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(timing) = span.extensions_mut().get_mut::<SpanTiming>() {
+                timing.entered_at.get_or_insert_with(Instant::now);
+            }
+        }
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(&id) else {
+            return;
+        };
+
+        let (wall, self_time) = {
+            let extensions = span.extensions();
+            let Some(timing) = extensions.get::<SpanTiming>() else {
+                return;
+            };
+            // Never entered, so it never ran
+            let Some(entered_at) = timing.entered_at else {
+                return;
+            };
+            let wall = entered_at.elapsed();
+            // Children that ran concurrently can add up to more than the parent
+            (wall, wall.saturating_sub(timing.child_wall))
+        };
+
+        // Charge the child's wall time to the parent so the parent's own
+        // self-time excludes it, as the folded format expects.
+        if let Some(parent) = span.parent() {
+            if let Some(timing) = parent.extensions_mut().get_mut::<SpanTiming>() {
+                timing.child_wall += wall;
+            }
+        }
+
+        let stack = span
+            .scope()
+            .from_root()
+            .map(|s| s.name())
+            .collect::<Vec<_>>()
+            .join(";");
+
+        if let Ok(mut data) = self.data.lock() {
+            *data.stacks.entry(stack).or_default() += self_time.as_micros() as u64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::prelude::*;
+
+    #[test]
+    fn nested_spans_are_folded_with_self_time() {
+        let profiler = Profiler::new(std::env::temp_dir().join("unused.folded"));
+        let subscriber = tracing_subscriber::registry().with(profiler.layer());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let outer = tracing::trace_span!(target: PROFILE_TARGET, "download");
+            let _outer = outer.enter();
+            std::thread::sleep(Duration::from_millis(2));
+            let inner = tracing::trace_span!(target: PROFILE_TARGET, "hash");
+            let _inner = inner.enter();
+            std::thread::sleep(Duration::from_millis(2));
+        });
+
+        let folded = profiler.folded();
+        assert!(folded.contains("download;hash "), "{folded}");
+        assert!(folded.lines().any(|l| l.starts_with("download ")), "{folded}");
+    }
+
+    #[test]
+    fn time_spent_outside_the_span_counts_as_wall_time() {
+        let profiler = Profiler::new(std::env::temp_dir().join("unused.folded"));
+        let subscriber = tracing_subscriber::registry().with(profiler.layer());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let wait = tracing::trace_span!(target: PROFILE_TARGET, "network_wait");
+            drop(wait.enter());
+            // Like an await point: the span is idle but not yet closed
+            std::thread::sleep(Duration::from_millis(20));
+            drop(wait.enter());
+        });
+
+        let folded = profiler.folded();
+        let micros: u64 = folded
+            .lines()
+            .find_map(|l| l.strip_prefix("network_wait "))
+            .and_then(|n| n.trim().parse().ok())
+            .unwrap_or_default();
+        assert!(micros >= 20_000, "{folded}");
+    }
+}
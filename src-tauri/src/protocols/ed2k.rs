@@ -11,7 +11,7 @@ use async_trait::async_trait;
 use md4::{Md4, Digest};
 use sha2::Sha256;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::io::AsyncReadExt;
@@ -324,6 +324,7 @@ impl Ed2kProtocolHandler {
             merkle_root,
             chunks: chunk_infos,
             encrypted_key_bundle: None, // ED2K doesn't use encryption
+            content_info: None,
         })
     }
 
@@ -332,6 +333,34 @@ impl Ed2kProtocolHandler {
         ((file_size as usize + ED2K_CHUNK_SIZE - 1) / ED2K_CHUNK_SIZE).max(1)
     }
 
+    /// Stage downloaded data, check it against the link's size and eD2k hash,
+    /// then move it to `output_path`
+    async fn commit_download(
+        output_path: &Path,
+        file_hash: &str,
+        file_size: u64,
+        data: &[u8],
+    ) -> Result<PathBuf, String> {
+        let staged = crate::download_staging::global().stage(file_hash, file_size)?;
+        tokio::fs::write(staged.path(), data)
+            .await
+            .map_err(|e| format!("Failed to write staged file: {}", e))?;
+        let actual = Ed2kClient::compute_file_hash(staged.path())
+            .await
+            .map_err(|e| format!("Failed to hash download: {}", e))?;
+        if !actual.eq_ignore_ascii_case(file_hash) {
+            return Err(format!(
+                "Download verification failed: expected eD2k hash {}, got {}",
+                file_hash, actual
+            ));
+        }
+        let verification = crate::download_staging::Verification {
+            expected_size: Some(file_size),
+            ..Default::default()
+        };
+        staged.commit(output_path, &verification).await
+    }
+
     fn normalized_sha256_hex(hash: &str) -> Option<String> {
         let trimmed = hash.trim();
         if trimmed.len() != 64 {
@@ -541,7 +570,9 @@ impl ProtocolHandler for Ed2kProtocolHandler {
                 }
             }
 
-            if let Err(e) = tokio::fs::write(&output_path, &all_data).await {
+            if let Err(e) =
+                Self::commit_download(&output_path, &file_hash, file_size, &all_data).await
+            {
                 error!("ED2K: Failed to write file: {}", e);
                 let mut prog = progress.lock().await;
                 if let Some(p) = prog.get_mut(&id) {
@@ -855,8 +886,10 @@ impl ProtocolHandler for Ed2kProtocolHandler {
                 }
             }
 
-            // Write to file
-            if let Err(e) = tokio::fs::write(&output_path, &all_data).await {
+            // Verify and move into place
+            if let Err(e) =
+                Self::commit_download(&output_path, &file_hash, file_size, &all_data).await
+            {
                 error!("ED2K: Failed to write file: {}", e);
                 let mut prog = progress.lock().await;
                 if let Some(p) = prog.get_mut(&id) {
@@ -0,0 +1,663 @@
+// Resumable push uploads
+//
+// Lets a node push a file to a storage provider (paid hosting or a friend's
+// seed box running Chiral) through the provider's HTTP server. The provider
+// keeps one session per file hash recording which chunks it has written and
+// synced to disk, and acknowledges a chunk only after that. A pusher that
+// loses its connection, or restarts, opens the session again, gets the
+// acknowledged chunks back and sends only the missing ones. Once every chunk
+// is in, the provider checks the SHA-256 of the whole file against the hash
+// the session was opened with and moves it into its blob store.
+//
+// `PushReceiver` is the provider side, mounted under `/uploads` by the HTTP
+// server; `push_file` is the client.
+
+use crate::blob_store::BlobStore;
+use crate::file_transfer::hashing::HashAlgorithm;
+use crate::file_transfer::ranges::{from_ranges, to_ranges};
+use crate::runtime_env::{Clock, SystemClock};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+/// Size of every chunk but the last
+pub const CHUNK_SIZE: u64 = 1024 * 1024;
+/// Emitted to the frontend by the push command
+pub const PROGRESS_EVENT: &str = "push_upload_progress";
+pub const TOKEN_HEADER: &str = "X-Push-Token";
+pub const CHUNK_HASH_HEADER: &str = "X-Chunk-Sha256";
+
+const MAX_CHUNK_ATTEMPTS: u32 = 5;
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+
+/// Whether and from whom this node accepts pushed files
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PushReceiverConfig {
+    pub enabled: bool,
+    /// Shared secret pushers must present; required to enable the receiver
+    pub token: Option<String>,
+    pub max_file_size: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenPushRequest {
+    /// Hex SHA-256 of the content
+    pub file_hash: String,
+    pub file_name: String,
+    pub file_size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PushSession {
+    pub file_hash: String,
+    pub file_name: String,
+    pub file_size: u64,
+    pub chunk_size: u64,
+    /// Acknowledged chunks as half-open `[start, end)` index ranges
+    pub received: Vec<[u32; 2]>,
+    pub complete: bool,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+impl PushSession {
+    pub fn total_chunks(&self) -> u32 {
+        self.file_size.div_ceil(self.chunk_size).max(1) as u32
+    }
+
+    pub fn received_chunks(&self) -> u32 {
+        self.received.iter().map(|[start, end]| end - start).sum()
+    }
+
+    /// Chunk indices not acknowledged yet, ascending
+    pub fn missing(&self) -> Vec<u32> {
+        let received = from_ranges(&self.received);
+        (0..self.total_chunks())
+            .filter(|i| !received.contains(i))
+            .collect()
+    }
+
+    fn chunk_len(&self, index: u32) -> u64 {
+        let offset = index as u64 * self.chunk_size;
+        self.chunk_size.min(self.file_size.saturating_sub(offset))
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PushProgress {
+    pub file_hash: String,
+    pub provider_url: String,
+    pub chunks_done: u32,
+    pub total_chunks: u32,
+    /// Chunks the provider already had when this attempt started
+    pub resumed_chunks: u32,
+    pub bytes_done: u64,
+    pub file_size: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PushError {
+    Forbidden(String),
+    NotFound(String),
+    Invalid(String),
+    Internal(String),
+}
+
+impl std::fmt::Display for PushError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PushError::Forbidden(msg)
+            | PushError::NotFound(msg)
+            | PushError::Invalid(msg)
+            | PushError::Internal(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+fn is_sha256_hex(hash: &str) -> bool {
+    hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn internal(context: &str, e: impl std::fmt::Display) -> PushError {
+    PushError::Internal(format!("{}: {}", context, e))
+}
+
+pub struct PushReceiver {
+    dir: PathBuf,
+    config: std::sync::Mutex<PushReceiverConfig>,
+    persist_path: Option<PathBuf>,
+    sessions: tokio::sync::Mutex<HashMap<String, PushSession>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl PushReceiver {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            config: std::sync::Mutex::new(PushReceiverConfig::default()),
+            persist_path: None,
+            sessions: tokio::sync::Mutex::new(HashMap::new()),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Receiver whose config is loaded from and saved to `config_path`
+    pub fn with_persistence(dir: impl Into<PathBuf>, config_path: impl AsRef<Path>) -> Self {
+        let config_path = config_path.as_ref().to_path_buf();
+        let config = std::fs::read_to_string(&config_path)
+            .ok()
+            .and_then(|raw| serde_json::from_str::<PushReceiverConfig>(&raw).ok())
+            .unwrap_or_default();
+        Self {
+            config: std::sync::Mutex::new(config),
+            persist_path: Some(config_path),
+            ..Self::new(dir)
+        }
+    }
+
+    /// Read the time from `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn config(&self) -> PushReceiverConfig {
+        self.config.lock().map(|c| c.clone()).unwrap_or_default()
+    }
+
+    pub fn set_config(&self, config: PushReceiverConfig) -> Result<(), String> {
+        let has_token = config.token.as_deref().is_some_and(|t| !t.is_empty());
+        if config.enabled && !has_token {
+            return Err("A push token is required to accept pushed files".to_string());
+        }
+        if let Some(path) = &self.persist_path {
+            let json = serde_json::to_string_pretty(&config)
+                .map_err(|e| format!("Failed to serialize push receiver config: {}", e))?;
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| format!("Failed to create config directory: {}", e))?;
+            }
+            std::fs::write(path, json)
+                .map_err(|e| format!("Failed to save push receiver config: {}", e))?;
+        }
+        *self
+            .config
+            .lock()
+            .map_err(|_| "Push receiver config lock poisoned".to_string())? = config;
+        Ok(())
+    }
+
+    fn authorize(&self, token: Option<&str>) -> Result<PushReceiverConfig, PushError> {
+        let config = self.config();
+        if !config.enabled {
+            return Err(PushError::Forbidden(
+                "This node does not accept pushed files".to_string(),
+            ));
+        }
+        // A config saved before tokens were required has none; refuse
+        // everyone rather than accept anyone
+        let Some(expected) = config.token.as_deref().filter(|t| !t.is_empty()) else {
+            return Err(PushError::Forbidden(
+                "No push token is configured".to_string(),
+            ));
+        };
+        match token {
+            None => Err(PushError::Forbidden("Missing push token".to_string())),
+            Some(token) if token != expected => {
+                Err(PushError::Forbidden("Invalid push token".to_string()))
+            }
+            Some(_) => Ok(config),
+        }
+    }
+
+    fn session_path(&self, file_hash: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", file_hash))
+    }
+
+    fn data_path(&self, file_hash: &str) -> PathBuf {
+        self.dir.join(format!("{}.part", file_hash))
+    }
+
+    async fn save(&self, session: &PushSession) -> Result<(), PushError> {
+        let json =
+            serde_json::to_vec(session).map_err(|e| internal("Failed to encode session", e))?;
+        let path = self.session_path(&session.file_hash);
+        let tmp = path.with_extension("json.tmp");
+        tokio::fs::write(&tmp, json)
+            .await
+            .map_err(|e| internal("Failed to save session", e))?;
+        tokio::fs::rename(&tmp, &path)
+            .await
+            .map_err(|e| internal("Failed to save session", e))
+    }
+
+    /// The session for `file_hash`, loaded from disk if this process has not
+    /// seen it yet
+    async fn load(
+        &self,
+        sessions: &mut HashMap<String, PushSession>,
+        file_hash: &str,
+    ) -> Option<PushSession> {
+        if let Some(session) = sessions.get(file_hash) {
+            return Some(session.clone());
+        }
+        let raw = tokio::fs::read(self.session_path(file_hash)).await.ok()?;
+        let session: PushSession = serde_json::from_slice(&raw).ok()?;
+        sessions.insert(file_hash.to_string(), session.clone());
+        Some(session)
+    }
+
+    /// Start a push, or resume the existing one for the same file
+    pub async fn open(
+        &self,
+        token: Option<&str>,
+        request: OpenPushRequest,
+    ) -> Result<PushSession, PushError> {
+        let config = self.authorize(token)?;
+        let file_hash = request.file_hash.to_ascii_lowercase();
+        if !is_sha256_hex(&file_hash) {
+            return Err(PushError::Invalid(
+                "File hash must be a hex SHA-256".to_string(),
+            ));
+        }
+        if config
+            .max_file_size
+            .is_some_and(|max| request.file_size > max)
+        {
+            return Err(PushError::Forbidden(format!(
+                "File exceeds the provider's limit of {} bytes",
+                config.max_file_size.unwrap_or_default()
+            )));
+        }
+
+        let mut sessions = self.sessions.lock().await;
+        if let Some(session) = self.load(&mut sessions, &file_hash).await {
+            if session.file_size == request.file_size {
+                return Ok(session);
+            }
+        }
+
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(|e| internal("Failed to create upload directory", e))?;
+        let file = tokio::fs::File::create(self.data_path(&file_hash))
+            .await
+            .map_err(|e| internal("Failed to create upload file", e))?;
+        file.set_len(request.file_size)
+            .await
+            .map_err(|e| internal("Failed to allocate upload file", e))?;
+
+        let now = self.clock.now_secs();
+        let session = PushSession {
+            file_hash: file_hash.clone(),
+            file_name: request.file_name,
+            file_size: request.file_size,
+            chunk_size: CHUNK_SIZE,
+            received: Vec::new(),
+            complete: false,
+            created_at: now,
+            updated_at: now,
+        };
+        self.save(&session).await?;
+        sessions.insert(file_hash, session.clone());
+        Ok(session)
+    }
+
+    pub async fn status(
+        &self,
+        token: Option<&str>,
+        file_hash: &str,
+    ) -> Result<PushSession, PushError> {
+        self.authorize(token)?;
+        let mut sessions = self.sessions.lock().await;
+        self.load(&mut sessions, &file_hash.to_ascii_lowercase())
+            .await
+            .ok_or_else(|| PushError::NotFound(format!("No push in progress for {}", file_hash)))
+    }
+
+    /// Write one chunk and acknowledge it once it is on disk
+    pub async fn write_chunk(
+        &self,
+        token: Option<&str>,
+        file_hash: &str,
+        index: u32,
+        data: &[u8],
+        chunk_sha256: Option<&str>,
+    ) -> Result<PushSession, PushError> {
+        self.authorize(token)?;
+        let file_hash = file_hash.to_ascii_lowercase();
+        let mut sessions = self.sessions.lock().await;
+        let mut session = self
+            .load(&mut sessions, &file_hash)
+            .await
+            .ok_or_else(|| PushError::NotFound(format!("No push in progress for {}", file_hash)))?;
+
+        if index >= session.total_chunks() {
+            return Err(PushError::Invalid(format!("Chunk {} out of range", index)));
+        }
+        if data.len() as u64 != session.chunk_len(index) {
+            return Err(PushError::Invalid(format!(
+                "Chunk {} has {} bytes, expected {}",
+                index,
+                data.len(),
+                session.chunk_len(index)
+            )));
+        }
+        if let Some(expected) = chunk_sha256 {
+            if !sha256_hex(data).eq_ignore_ascii_case(expected) {
+                return Err(PushError::Invalid(format!(
+                    "Chunk {} failed its checksum",
+                    index
+                )));
+            }
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .open(self.data_path(&file_hash))
+            .await
+            .map_err(|e| internal("Failed to open upload file", e))?;
+        file.seek(std::io::SeekFrom::Start(index as u64 * session.chunk_size))
+            .await
+            .map_err(|e| internal("Failed to seek upload file", e))?;
+        file.write_all(data)
+            .await
+            .map_err(|e| internal("Failed to write chunk", e))?;
+        file.sync_data()
+            .await
+            .map_err(|e| internal("Failed to sync chunk", e))?;
+
+        let mut received = from_ranges(&session.received);
+        received.insert(index);
+        session.received = to_ranges(&received);
+        session.updated_at = self.clock.now_secs();
+        self.save(&session).await?;
+        sessions.insert(file_hash, session.clone());
+        Ok(session)
+    }
+
+    /// Verify the assembled file and move it into `blobs`
+    pub async fn complete(
+        &self,
+        token: Option<&str>,
+        file_hash: &str,
+        blobs: &BlobStore,
+    ) -> Result<PushSession, PushError> {
+        self.authorize(token)?;
+        let file_hash = file_hash.to_ascii_lowercase();
+        let mut sessions = self.sessions.lock().await;
+        let mut session = self
+            .load(&mut sessions, &file_hash)
+            .await
+            .ok_or_else(|| PushError::NotFound(format!("No push in progress for {}", file_hash)))?;
+
+        let missing = session.missing().len();
+        if missing > 0 {
+            return Err(PushError::Invalid(format!(
+                "{} chunks still missing",
+                missing
+            )));
+        }
+        let data_path = self.data_path(&file_hash);
+        let actual = HashAlgorithm::Sha256
+            .hash_file(&data_path)
+            .await
+            .map_err(PushError::Internal)?
+            .to_string();
+        if actual != file_hash {
+            // Acknowledged chunks passed their checksums, so the pusher sent the
+            // wrong file; make it start over
+            session.received.clear();
+            self.save(&session).await?;
+            sessions.insert(file_hash.clone(), session);
+            return Err(PushError::Invalid(format!(
+                "Assembled file hashes to {}, expected {}",
+                actual, file_hash
+            )));
+        }
+
+        blobs
+            .put_file(&file_hash, &data_path)
+            .await
+            .map_err(|e| internal("Failed to store pushed file", e))?;
+        let metadata = serde_json::json!({
+            "file_name": session.file_name,
+            "file_size": session.file_size,
+            "uploaded_at": self.clock.now_secs(),
+            "is_encrypted": false,
+            "pushed": true,
+        });
+        tokio::fs::write(
+            blobs.root().join(format!("{}.meta", file_hash)),
+            metadata.to_string(),
+        )
+        .await
+        .map_err(|e| internal("Failed to write metadata", e))?;
+
+        let _ = tokio::fs::remove_file(self.session_path(&file_hash)).await;
+        sessions.remove(&file_hash);
+        session.complete = true;
+        Ok(session)
+    }
+}
+
+async fn read_chunk(
+    file: &mut tokio::fs::File,
+    session: &PushSession,
+    index: u32,
+) -> Result<Vec<u8>, String> {
+    let mut buffer = vec![0u8; session.chunk_len(index) as usize];
+    file.seek(std::io::SeekFrom::Start(index as u64 * session.chunk_size))
+        .await
+        .map_err(|e| format!("Failed to seek file: {}", e))?;
+    file.read_exact(&mut buffer)
+        .await
+        .map_err(|e| format!("Failed to read chunk {}: {}", index, e))?;
+    Ok(buffer)
+}
+
+async fn parse_session(response: reqwest::Response) -> Result<PushSession, String> {
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(format!("Provider returned {}: {}", status, body));
+    }
+    response
+        .json::<PushSession>()
+        .await
+        .map_err(|e| format!("Invalid response from provider: {}", e))
+}
+
+/// Push `path` to the provider at `provider_url`, resuming a previous push of
+/// the same file. `file_hash` must be the hex SHA-256 of the file.
+pub async fn push_file(
+    client: &reqwest::Client,
+    provider_url: &str,
+    token: Option<&str>,
+    path: &Path,
+    file_hash: &str,
+    mut on_progress: impl FnMut(&PushProgress),
+) -> Result<PushSession, String> {
+    let base = provider_url.trim_end_matches('/');
+    let file_size = tokio::fs::metadata(path)
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+        .len();
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| file_hash.to_string());
+
+    let with_token = |request: reqwest::RequestBuilder| match token {
+        Some(token) => request.header(TOKEN_HEADER, token),
+        None => request,
+    };
+
+    let request = OpenPushRequest {
+        file_hash: file_hash.to_string(),
+        file_name,
+        file_size,
+    };
+    let response = with_token(client.post(format!("{}/uploads", base)))
+        .json(&request)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to reach provider: {}", e))?;
+    let mut session = parse_session(response).await?;
+
+    let missing = session.missing();
+    let total_chunks = session.total_chunks();
+    let resumed_chunks = total_chunks - missing.len() as u32;
+    let mut progress = PushProgress {
+        file_hash: session.file_hash.clone(),
+        provider_url: base.to_string(),
+        chunks_done: resumed_chunks,
+        total_chunks,
+        resumed_chunks,
+        bytes_done: (0..total_chunks)
+            .filter(|i| !missing.contains(i))
+            .map(|i| session.chunk_len(i))
+            .sum(),
+        file_size,
+    };
+    on_progress(&progress);
+
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    for index in missing {
+        let data = read_chunk(&mut file, &session, index).await?;
+        let checksum = sha256_hex(&data);
+        let url = format!("{}/uploads/{}/chunks/{}", base, session.file_hash, index);
+
+        let mut attempt = 0;
+        session = loop {
+            attempt += 1;
+            let result = with_token(client.put(&url))
+                .header(CHUNK_HASH_HEADER, &checksum)
+                .body(data.clone())
+                .send()
+                .await
+                .map_err(|e| format!("Failed to send chunk {}: {}", index, e));
+            let result = match result {
+                Ok(response) => parse_session(response).await,
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(session) => break session,
+                Err(e) if attempt >= MAX_CHUNK_ATTEMPTS => return Err(e),
+                Err(e) => {
+                    tracing::warn!(
+                        "Push of chunk {} failed (attempt {}): {}",
+                        index,
+                        attempt,
+                        e
+                    );
+                    tokio::time::sleep(RETRY_BASE_DELAY * 2u32.pow(attempt - 1)).await;
+                }
+            }
+        };
+
+        progress.chunks_done += 1;
+        progress.bytes_done += data.len() as u64;
+        on_progress(&progress);
+    }
+
+    let response =
+        with_token(client.post(format!("{}/uploads/{}/complete", base, session.file_hash)))
+            .send()
+            .await
+            .map_err(|e| format!("Failed to reach provider: {}", e))?;
+    parse_session(response).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn resumes_and_verifies_pushed_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let receiver = PushReceiver::new(dir.path().join("incoming"));
+        let blobs = BlobStore::open(dir.path().join("files")).await.unwrap();
+        let content: Vec<u8> = (0..(CHUNK_SIZE * 2 + 10)).map(|i| i as u8).collect();
+        let hash = sha256_hex(&content);
+        let request = OpenPushRequest {
+            file_hash: hash.clone(),
+            file_name: "big.bin".into(),
+            file_size: content.len() as u64,
+        };
+
+        assert!(matches!(
+            receiver.open(None, request.clone()).await,
+            Err(PushError::Forbidden(_))
+        ));
+        assert!(receiver
+            .set_config(PushReceiverConfig {
+                enabled: true,
+                token: None,
+                max_file_size: None,
+            })
+            .is_err());
+        receiver
+            .set_config(PushReceiverConfig {
+                enabled: true,
+                token: Some("secret".into()),
+                max_file_size: None,
+            })
+            .unwrap();
+        let token = Some("secret");
+        assert!(matches!(
+            receiver.open(None, request.clone()).await,
+            Err(PushError::Forbidden(_))
+        ));
+
+        let session = receiver.open(token, request.clone()).await.unwrap();
+        assert_eq!(session.total_chunks(), 3);
+        let chunk = |i: u32| {
+            let start = (i as u64 * CHUNK_SIZE) as usize;
+            let end = (start + CHUNK_SIZE as usize).min(content.len());
+            content[start..end].to_vec()
+        };
+
+        receiver
+            .write_chunk(token, &hash, 2, &chunk(2), Some(&sha256_hex(&chunk(2))))
+            .await
+            .unwrap();
+        assert!(receiver
+            .write_chunk(token, &hash, 0, &chunk(0), Some("bad"))
+            .await
+            .is_err());
+
+        // A new receiver (provider restart) picks the session up from disk
+        let receiver = PushReceiver {
+            config: std::sync::Mutex::new(receiver.config()),
+            ..PushReceiver::new(dir.path().join("incoming"))
+        };
+        let resumed = receiver.open(token, request).await.unwrap();
+        assert_eq!(resumed.missing(), vec![0, 1]);
+        assert!(receiver.complete(token, &hash, &blobs).await.is_err());
+
+        for i in [0, 1] {
+            receiver
+                .write_chunk(token, &hash, i, &chunk(i), None)
+                .await
+                .unwrap();
+        }
+        let done = receiver.complete(token, &hash, &blobs).await.unwrap();
+        assert!(done.complete);
+        assert_eq!(blobs.get(&hash).await.unwrap(), Some(content.clone()));
+    }
+}
@@ -0,0 +1,292 @@
+// Scheduled re-announcement of pinned content
+//
+// Provider and metadata records for published files live on remote peers
+// only until they expire or those peers leave the network. This module keeps
+// the set of files this node published (learned from `PublishedFile` events on
+// the bus) and re-puts both records for every one of them on a jittered
+// interval, so a whole network of nodes started together does not re-announce
+// in lockstep. Every attempt is recorded per hash, which lets the UI point at
+// files whose announcements keep failing and force a retry for one of them.
+//
+// Pins last for the session, like the DHT metadata cache they are announced
+// from; publishing again after a restart pins the file again.
+
+use crate::dht::{DhtEvent, DhtService};
+use crate::event_bus::{self, EventPayload};
+use crate::runtime_env::{Clock, SystemClock};
+use rand::Rng;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::{debug, info, warn};
+
+/// Average time between two re-announce passes
+pub const REANNOUNCE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Each pass is moved by up to this much in either direction
+pub const REANNOUNCE_JITTER: Duration = Duration::from_secs(10 * 60);
+/// Pause between two files of one pass so a large library does not flood the
+/// routing table with queries at once
+pub const ITEM_SPACING: Duration = Duration::from_millis(500);
+
+/// Announcement state of one pinned file
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ReannounceStatus {
+    pub file_hash: String,
+    pub file_name: String,
+    pub pinned_at: u64,
+    pub last_attempt: Option<u64>,
+    pub last_success: Option<u64>,
+    /// Failed attempts since the last success
+    pub consecutive_failures: u32,
+    pub total_failures: u64,
+    pub last_error: Option<String>,
+}
+
+impl ReannounceStatus {
+    fn new(file_hash: String, file_name: String, now: u64) -> Self {
+        Self {
+            file_hash,
+            file_name,
+            pinned_at: now,
+            last_attempt: None,
+            last_success: None,
+            consecutive_failures: 0,
+            total_failures: 0,
+            last_error: None,
+        }
+    }
+}
+
+/// Pinned files and the outcome of their announcements
+pub struct Reannouncer {
+    pins: Mutex<HashMap<String, ReannounceStatus>>,
+    clock: Arc<dyn Clock>,
+}
+
+/// Delay before the next pass: `interval` moved by a random offset of at most
+/// `jitter`
+pub fn next_delay(interval: Duration, jitter: Duration) -> Duration {
+    let jitter_ms = jitter.as_millis() as i64;
+    if jitter_ms == 0 {
+        return interval;
+    }
+    let offset = rand::thread_rng().gen_range(-jitter_ms..=jitter_ms);
+    let delay_ms = (interval.as_millis() as i64 + offset).max(0);
+    Duration::from_millis(delay_ms as u64)
+}
+
+impl Default for Reannouncer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reannouncer {
+    pub fn new() -> Self {
+        Self {
+            pins: Mutex::new(HashMap::new()),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Read the time from `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Start tracking `file_hash`; pinning a file again keeps its history
+    pub fn pin(&self, file_hash: &str, file_name: &str) {
+        let now = self.clock.now_secs();
+        if let Ok(mut pins) = self.pins.lock() {
+            pins.entry(file_hash.to_string())
+                .and_modify(|status| status.file_name = file_name.to_string())
+                .or_insert_with(|| {
+                    ReannounceStatus::new(file_hash.to_string(), file_name.to_string(), now)
+                });
+        }
+    }
+
+    /// Stop re-announcing `file_hash`, e.g. after the user unpublished it
+    pub fn unpin(&self, file_hash: &str) -> bool {
+        self.pins
+            .lock()
+            .map(|mut pins| pins.remove(file_hash).is_some())
+            .unwrap_or(false)
+    }
+
+    pub fn is_pinned(&self, file_hash: &str) -> bool {
+        self.pins
+            .lock()
+            .map(|pins| pins.contains_key(file_hash))
+            .unwrap_or(false)
+    }
+
+    /// Status of every pinned file, failing ones first
+    pub fn statuses(&self) -> Vec<ReannounceStatus> {
+        let mut statuses: Vec<ReannounceStatus> = self
+            .pins
+            .lock()
+            .map(|pins| pins.values().cloned().collect())
+            .unwrap_or_default();
+        statuses.sort_by(|a, b| {
+            b.consecutive_failures
+                .cmp(&a.consecutive_failures)
+                .then_with(|| a.file_name.cmp(&b.file_name))
+        });
+        statuses
+    }
+
+    /// Pinned hashes in announcement order: never announced first, then the
+    /// longest without a successful announcement
+    fn due_hashes(&self) -> Vec<String> {
+        let mut pins: Vec<(Option<u64>, String)> = self
+            .pins
+            .lock()
+            .map(|pins| {
+                pins.values()
+                    .map(|s| (s.last_success, s.file_hash.clone()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        pins.sort();
+        pins.into_iter().map(|(_, hash)| hash).collect()
+    }
+
+    /// Record the outcome of one attempt; returns the updated status, or
+    /// `None` if the file was unpinned in the meantime
+    pub fn record_attempt(
+        &self,
+        file_hash: &str,
+        result: &Result<(), String>,
+        now: u64,
+    ) -> Option<ReannounceStatus> {
+        let mut pins = self.pins.lock().ok()?;
+        let status = pins.get_mut(file_hash)?;
+        status.last_attempt = Some(now);
+        match result {
+            Ok(()) => {
+                status.last_success = Some(now);
+                status.consecutive_failures = 0;
+                status.last_error = None;
+            }
+            Err(e) => {
+                status.consecutive_failures += 1;
+                status.total_failures += 1;
+                status.last_error = Some(e.clone());
+            }
+        }
+        Some(status.clone())
+    }
+
+    /// Re-announce one pinned file now
+    pub async fn reannounce(
+        &self,
+        dht: &DhtService,
+        file_hash: &str,
+    ) -> Result<ReannounceStatus, String> {
+        if !self.is_pinned(file_hash) {
+            return Err(format!("File {} is not published by this node", file_hash));
+        }
+        let result = match dht.cached_file_metadata(file_hash).await {
+            Some(metadata) => dht.reannounce_file(metadata).await,
+            None => Err("No cached metadata for this file".to_string()),
+        };
+        if let Err(e) = &result {
+            warn!("Failed to re-announce {}: {}", file_hash, e);
+        }
+        self.record_attempt(file_hash, &result, self.clock.now_secs())
+            .ok_or_else(|| format!("File {} is not published by this node", file_hash))
+    }
+
+    /// Re-announce every pinned file, pausing `ITEM_SPACING` between files.
+    /// Returns the number of files that failed.
+    pub async fn reannounce_all(&self, dht: &DhtService) -> usize {
+        let hashes = self.due_hashes();
+        let mut failed = 0;
+        for (i, file_hash) in hashes.iter().enumerate() {
+            if i > 0 {
+                tokio::time::sleep(ITEM_SPACING).await;
+            }
+            match self.reannounce(dht, file_hash).await {
+                Ok(status) if status.consecutive_failures == 0 => {}
+                _ => failed += 1,
+            }
+        }
+        info!(
+            "Re-announced {} pinned files ({} failed)",
+            hashes.len() - failed,
+            failed
+        );
+        failed
+    }
+}
+
+/// Pin every file this node publishes, as announced on the event bus
+pub async fn run_tracker(reannouncer: Arc<Reannouncer>) {
+    let mut rx = event_bus::global().subscribe();
+    loop {
+        match rx.recv().await {
+            Ok(envelope) => {
+                if let EventPayload::Dht(DhtEvent::PublishedFile(metadata)) = &envelope.payload {
+                    reannouncer.pin(&metadata.merkle_root, &metadata.file_name);
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                debug!("Re-announce tracker lagged, skipped {} events", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_failures_and_orders_pins() {
+        let reannouncer = Reannouncer::new();
+        reannouncer.pin("aaa", "a.txt");
+        reannouncer.pin("bbb", "b.txt");
+        reannouncer.record_attempt("aaa", &Ok(()), 100).unwrap();
+
+        // Never-announced files go first
+        assert_eq!(reannouncer.due_hashes(), vec!["bbb", "aaa"]);
+
+        let err = Err("No connected peers".to_string());
+        reannouncer.record_attempt("aaa", &err, 200);
+        let status = reannouncer.record_attempt("aaa", &err, 300).unwrap();
+        assert_eq!(status.consecutive_failures, 2);
+        assert_eq!(status.last_success, Some(100));
+        assert_eq!(status.last_error.as_deref(), Some("No connected peers"));
+        assert_eq!(reannouncer.statuses()[0].file_hash, "aaa");
+
+        let status = reannouncer.record_attempt("aaa", &Ok(()), 400).unwrap();
+        assert_eq!(status.consecutive_failures, 0);
+        assert_eq!(status.total_failures, 2);
+        assert!(status.last_error.is_none());
+
+        // Re-pinning keeps history; unpinned files are no longer tracked
+        reannouncer.pin("aaa", "renamed.txt");
+        assert_eq!(
+            reannouncer
+                .statuses()
+                .iter()
+                .find(|s| s.file_hash == "aaa")
+                .unwrap()
+                .total_failures,
+            2
+        );
+        assert!(reannouncer.unpin("bbb"));
+        assert!(reannouncer.record_attempt("bbb", &Ok(()), 500).is_none());
+
+        for _ in 0..20 {
+            let delay = next_delay(Duration::from_secs(60), Duration::from_secs(10));
+            assert!(delay >= Duration::from_secs(50) && delay <= Duration::from_secs(70));
+        }
+    }
+}
@@ -0,0 +1,146 @@
+// Active relay health probing
+//
+// Reputation events only arrive for relays the node happens to use, so the
+// score of every other known relay would rest on how it did long ago. Every
+// `PROBE_INTERVAL` the prober takes the relays probed least recently, dials
+// each one, times an echo round trip and asks for a reservation (given back
+// right away), and folds the outcome into the relay's health score.
+
+use crate::dht::DhtService;
+use crate::relay_registry::{RelayInfo, RelayRegistry};
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+pub const PROBE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// Longest wait for each step of a probe
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Relays probed per pass
+pub const RELAYS_PER_PASS: usize = 8;
+
+const PROBE_PAYLOAD: &[u8] = b"chiral-relay-probe";
+
+/// What one probe found out about a relay
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProbeOutcome {
+    /// Echo round trip; `None` if the relay could not be reached
+    pub rtt_ms: Option<u64>,
+    /// Whether it granted a reservation; `None` if it was not asked
+    pub reservation_accepted: Option<bool>,
+}
+
+impl ProbeOutcome {
+    pub fn unreachable() -> Self {
+        Self {
+            rtt_ms: None,
+            reservation_accepted: None,
+        }
+    }
+
+    /// Health score change for this outcome, on the scale of the relay
+    /// reputation events (a granted reservation there is worth 5)
+    pub fn impact(&self) -> f64 {
+        let Some(rtt) = self.rtt_ms else {
+            return -5.0;
+        };
+        let latency = match rtt {
+            0..=150 => 2.0,
+            151..=500 => 1.0,
+            501..=1500 => 0.0,
+            _ => -1.0,
+        };
+        let reservation = match self.reservation_accepted {
+            Some(true) => 3.0,
+            Some(false) => -3.0,
+            None => 0.0,
+        };
+        latency + reservation
+    }
+}
+
+/// Dial `relay`, time an echo and ask it for a reservation
+pub async fn probe_relay(dht: &DhtService, relay: &RelayInfo) -> ProbeOutcome {
+    let Some(address) = RelayRegistry::dial_address(relay) else {
+        return ProbeOutcome::unreachable();
+    };
+    if let Err(e) = dht.connect_peer(address.clone()).await {
+        debug!("Relay probe could not dial {}: {}", address, e);
+        return ProbeOutcome::unreachable();
+    }
+    // The first echo waits for the connection, the second one is timed
+    for attempt in 0..2 {
+        let started = Instant::now();
+        let echo = dht.echo(relay.peer_id.clone(), PROBE_PAYLOAD.to_vec());
+        match tokio::time::timeout(PROBE_TIMEOUT, echo).await {
+            Ok(Ok(_)) if attempt == 1 => {
+                let rtt_ms = started.elapsed().as_millis() as u64;
+                let reservation = dht.probe_relay_reservation(&address);
+                let accepted = match tokio::time::timeout(PROBE_TIMEOUT, reservation).await {
+                    Ok(Ok(())) => true,
+                    Ok(Err(e)) => {
+                        debug!("Relay {} refused a probe reservation: {}", relay.peer_id, e);
+                        false
+                    }
+                    Err(_) => false,
+                };
+                return ProbeOutcome {
+                    rtt_ms: Some(rtt_ms),
+                    reservation_accepted: Some(accepted),
+                };
+            }
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => {
+                debug!("Relay probe echo to {} failed: {}", relay.peer_id, e);
+                break;
+            }
+            Err(_) => break,
+        }
+    }
+    ProbeOutcome::unreachable()
+}
+
+/// Probe the relays due for it and record the outcomes. Returns how many
+/// could not be reached.
+pub async fn probe_due(dht: &DhtService, registry: &RelayRegistry) -> usize {
+    let relays = registry.due_for_probe(RELAYS_PER_PASS).await;
+    let mut unreachable = 0;
+    for relay in &relays {
+        let outcome = probe_relay(dht, relay).await;
+        if outcome.rtt_ms.is_none() {
+            unreachable += 1;
+        }
+        registry
+            .record_probe(
+                &relay.peer_id,
+                outcome.impact(),
+                outcome.rtt_ms,
+                registry.now(),
+            )
+            .await;
+    }
+    if !relays.is_empty() {
+        info!(
+            "Probed {} relays ({} unreachable)",
+            relays.len(),
+            unreachable
+        );
+    }
+    unreachable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fast_relays_that_grant_reservations_score_best() {
+        let outcome = |rtt_ms, reservation_accepted| ProbeOutcome {
+            rtt_ms,
+            reservation_accepted,
+        };
+        assert_eq!(outcome(Some(40), Some(true)).impact(), 5.0);
+        assert_eq!(outcome(Some(400), None).impact(), 1.0);
+        assert_eq!(outcome(Some(3000), Some(false)).impact(), -4.0);
+        assert_eq!(ProbeOutcome::unreachable().impact(), -5.0);
+        assert!(outcome(Some(3000), Some(false)).impact() > ProbeOutcome::unreachable().impact());
+    }
+}
@@ -0,0 +1,1484 @@
+// Known relays
+//
+// Every relay peer the node has dealt with, the health score it earned from
+// reservations, circuits and probes (see `relay_prober`), and the addresses
+// it was last seen at. The registry is saved to `relay_registry.json` in the
+// app data directory after each change and loaded on startup, so after a
+// restart the node already knows which relays worked and where to dial them
+// instead of discovering them again.
+//
+// Health scores decay while a relay goes unheard of, halving every
+// `SCORE_HALF_LIFE_SECS`: a relay that was good yesterday is probably still
+// fine, one last seen weeks ago is not much better than an unknown one.
+// Relays unheard of for `MAX_RELAY_AGE_SECS` are dropped on load, and while
+// the node runs a background task started with `start_pruning` drops them
+// periodically (interval and age come from the app settings).
+//
+// How much a relay carries for this node counts too: circuits opened through
+// it and bytes relayed raise its score a little, connections and reservations
+// that fail through it lower it, so relays that misbehave sink on their own
+// (see `usage_impact`).
+//
+// Where a relay is comes from the offline GeoIP database (see `geoip`) when
+// one is loaded: its addresses are located as they are learned, and the
+// country is kept next to the region the relay announced. Relays can be
+// listed by region so nearby ones can be preferred.
+//
+// Relays report their limits and current load in their announcements. When
+// picking relays the registry weighs health by spare capacity, so the few
+// best-known relays are not handed every reservation until they fill up.
+//
+// The state of this node's own reservation with each relay is kept here too,
+// for display, but only for the session: it is not saved.
+//
+// Anyone can announce a relay on the gossip topic, so relays known only from
+// announcements are capped, per peer that passed them on and in all, and
+// announcements alone save the registry at most once a minute. Relays that
+// earned a score are never held back by the caps.
+//
+// Relays the user blocks go on a denylist, saved along with the relays. A
+// blocked relay is dropped from the registry and stays out of it, so it is
+// never picked again, until it is unblocked. The DHT node is told as well, so
+// it gives up its reservation with the relay, disconnects it and stops
+// dialling through it (see `DhtCommand::SetBlockedRelays`).
+//
+// Every relay added, updated, pruned or removed is published on the unified
+// event bus as a `RelayRegistryEvent`, so the relay dashboard can follow the
+// registry live.
+
+use crate::dht::relay_gossip::{RelayAnnouncement, RelayCapacity};
+use crate::dht::reservations::ReservationState;
+use crate::event_bus::{self, EventPayload, EventSeverity, EventSource};
+use crate::geoip::GeoIpDb;
+use crate::persisted;
+use crate::relay_selector::{BestHealth, RelaySelector};
+use crate::runtime_env::{Clock, SystemClock};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+pub const REGISTRY_FILE: &str = "relay_registry.json";
+/// Time for an unrefreshed health score to halve (1 day)
+pub const SCORE_HALF_LIFE_SECS: u64 = 24 * 60 * 60;
+/// Relays not heard of for this long are forgotten (30 days)
+pub const MAX_RELAY_AGE_SECS: u64 = 30 * 24 * 60 * 60;
+/// How often stale relays are pruned unless configured otherwise
+pub const DEFAULT_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Relays offered as preferred relays after a restart
+pub const RESTORED_RELAYS: usize = 5;
+/// Most a single usage report can add to a relay's health score
+const MAX_USAGE_REWARD: f64 = 5.0;
+/// Addresses remembered per relay
+const MAX_ADDRESSES: usize = 8;
+/// Relays known only from announcements passed on by any one peer
+const MAX_ANNOUNCED_PER_SOURCE: usize = 16;
+/// Relays known only from announcements, in all
+const MAX_ANNOUNCED: usize = 256;
+/// Least time between saves caused by announcements alone
+const ANNOUNCEMENT_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct RelayInfo {
+    pub peer_id: String,
+    /// Multiaddrs the relay was last seen at
+    pub addresses: Vec<String>,
+    /// Sum of the impact of every reputation event, decayed over time
+    pub health_score: f64,
+    /// When `health_score` was last decayed, Unix seconds
+    pub scored_at: u64,
+    pub reservations_accepted: u64,
+    pub circuits_established: u64,
+    pub circuits_successful: u64,
+    pub total_events: u64,
+    /// Last event from or sighting of the relay, Unix seconds
+    pub last_seen: u64,
+    /// Round trip measured by the last successful probe
+    pub last_rtt_ms: Option<u64>,
+    /// Last probe, Unix seconds; 0 if never probed
+    pub last_probed: u64,
+    /// Probes in a row that could not reach the relay
+    pub failed_probes: u32,
+    /// Limits and load from the relay's latest announcement
+    pub capacity: Option<RelayCapacity>,
+    /// Country code the relay announced, see `relay_selector`
+    pub region: Option<String>,
+    /// Country its addresses are in according to the GeoIP database
+    pub country: Option<String>,
+    /// DCUtR upgrades tried on connections through the relay
+    pub hole_punch_attempts: u64,
+    /// Of which ended in a direct connection
+    pub hole_punch_successes: u64,
+    /// Circuits this node opened or accepted through the relay
+    pub circuits_opened: u64,
+    /// Payload bytes exchanged with peers reached only through the relay
+    pub bytes_relayed: u64,
+    /// Dials and reservations through the relay that failed
+    pub connection_failures: u64,
+    /// Our reservation with the relay this session, if we asked for one
+    #[serde(skip)]
+    pub reservation: Option<ReservationState>,
+    /// Peer that passed on the announcement the relay was added from, this
+    /// session
+    #[serde(skip)]
+    pub announced_via: Option<String>,
+}
+
+impl RelayInfo {
+    /// Health score weighed by spare capacity, for picking relays. A relay
+    /// with unknown load counts as half full; a full one counts for nothing.
+    /// Negative scores are left alone so bad relays stay behind idle ones.
+    pub fn selection_score(&self) -> f64 {
+        if self.health_score <= 0.0 {
+            return self.health_score;
+        }
+        let spare = self.capacity.and_then(|c| c.spare()).unwrap_or(0.5);
+        self.health_score * spare
+    }
+
+    /// Where the relay is: the country its addresses are in, else the
+    /// region it announced
+    pub fn located_region(&self) -> Option<&str> {
+        self.country.as_deref().or(self.region.as_deref())
+    }
+
+    /// Decay the score for the time up to `at` the relay went unheard of:
+    /// since it was last seen, probed or decayed, whichever came last
+    fn decay_to(&mut self, at: u64) {
+        let since = self.scored_at.max(self.last_seen).max(self.last_probed);
+        self.health_score = decayed_score(self.health_score, at.saturating_sub(since));
+        self.scored_at = since.max(at);
+    }
+
+    /// Known only from announcements: never probed, used or scored
+    fn is_unscored(&self) -> bool {
+        self.total_events == 0
+            && self.last_probed == 0
+            && self.circuits_opened == 0
+            && self.connection_failures == 0
+            && self.hole_punch_attempts == 0
+    }
+
+    /// Share of hole punches through the relay that went direct
+    pub fn hole_punch_rate(&self) -> Option<f64> {
+        (self.hole_punch_attempts > 0)
+            .then(|| self.hole_punch_successes as f64 / self.hole_punch_attempts as f64)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PersistedRegistry {
+    relays: Vec<RelayInfo>,
+    /// Missing from registries saved before relays could be blocked
+    #[serde(default)]
+    blocked: Vec<BlockedRelay>,
+}
+
+/// A relay on the denylist
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BlockedRelay {
+    pub peer_id: String,
+    pub reason: String,
+    /// Unix seconds
+    pub blocked_at: u64,
+}
+
+/// A change to the registry, published on the unified event bus
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RelayRegistryEvent {
+    Added {
+        relay: RelayInfo,
+    },
+    Updated {
+        relay: RelayInfo,
+    },
+    /// Forgotten after going unheard of for `MAX_RELAY_AGE_SECS`
+    Pruned {
+        peer_id: String,
+    },
+    /// Dropped because it was blocked
+    Removed {
+        peer_id: String,
+        reason: String,
+    },
+}
+
+impl RelayRegistryEvent {
+    fn peer_id(&self) -> &str {
+        match self {
+            RelayRegistryEvent::Added { relay } | RelayRegistryEvent::Updated { relay } => {
+                &relay.peer_id
+            }
+            RelayRegistryEvent::Pruned { peer_id }
+            | RelayRegistryEvent::Removed { peer_id, .. } => peer_id,
+        }
+    }
+}
+
+/// Publish a registry change on the unified event bus
+pub fn publish(event: RelayRegistryEvent) -> u64 {
+    // Updates come with every relay event and probe
+    let severity = match event {
+        RelayRegistryEvent::Updated { .. } => EventSeverity::Debug,
+        _ => EventSeverity::Info,
+    };
+    event_bus::global().publish(
+        EventSource::Relay,
+        severity,
+        Some(event.peer_id().to_string()),
+        EventPayload::RelayRegistry(event),
+    )
+}
+
+/// Publish `relay` as added or updated
+fn publish_relay(relay: &RelayInfo, added: bool) {
+    let relay = relay.clone();
+    publish(if added {
+        RelayRegistryEvent::Added { relay }
+    } else {
+        RelayRegistryEvent::Updated { relay }
+    });
+}
+
+/// Settings of the background pruning task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PruneConfig {
+    pub interval: Duration,
+    /// Relays unheard of for longer are pruned
+    pub max_age_secs: u64,
+}
+
+impl Default for PruneConfig {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_PRUNE_INTERVAL,
+            max_age_secs: MAX_RELAY_AGE_SECS,
+        }
+    }
+}
+
+/// What the background pruning task has done so far
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PruneStats {
+    pub running: bool,
+    pub runs: u64,
+    /// Unix seconds
+    pub last_run_at: Option<u64>,
+    pub pruned_last_run: usize,
+    pub pruned_total: u64,
+    /// Relays left after the last run
+    pub remaining: usize,
+}
+
+pub struct RelayRegistry {
+    relays: Mutex<HashMap<String, RelayInfo>>,
+    /// Locked after `relays` when both are needed
+    blocked: Mutex<HashMap<String, BlockedRelay>>,
+    persist_path: Option<PathBuf>,
+    pruner: std::sync::Mutex<Option<JoinHandle<()>>>,
+    prune_stats: Mutex<PruneStats>,
+    geoip: std::sync::Mutex<Option<Arc<GeoIpDb>>>,
+    /// Last save, for spacing out the ones announcements cause
+    saved_at: std::sync::Mutex<Option<Instant>>,
+    /// Announcements taken in since the last save
+    unsaved: AtomicBool,
+    clock: Arc<dyn Clock>,
+}
+
+/// Health score change for a usage report: each circuit is worth 1 and each
+/// MiB relayed 0.1, together at most `MAX_USAGE_REWARD`, and each failure
+/// costs 3
+pub fn usage_impact(circuits_opened: u64, bytes: u64, failures: u64) -> f64 {
+    let mib = bytes as f64 / (1024.0 * 1024.0);
+    let reward = (circuits_opened as f64 + mib * 0.1).min(MAX_USAGE_REWARD);
+    reward - failures as f64 * 3.0
+}
+
+/// `score` after `elapsed_secs` of decay
+pub fn decayed_score(score: f64, elapsed_secs: u64) -> f64 {
+    score * 0.5f64.powf(elapsed_secs as f64 / SCORE_HALF_LIFE_SECS as f64)
+}
+
+/// Put `addresses` ahead of the ones already known. True if they changed.
+fn merge_addresses(relay: &mut RelayInfo, addresses: &[String], at: u64) -> bool {
+    // Latest addresses first, then the older ones not seen again
+    let mut merged = addresses.to_vec();
+    merged.extend(
+        relay
+            .addresses
+            .iter()
+            .filter(|known| !addresses.contains(known))
+            .cloned(),
+    );
+    merged.truncate(MAX_ADDRESSES);
+    if merged == relay.addresses {
+        return false;
+    }
+    relay.addresses = merged;
+    relay.last_seen = relay.last_seen.max(at);
+    true
+}
+
+/// Set `relay.country` from the first of its addresses `geoip` can place.
+/// True if it changed.
+fn locate(relay: &mut RelayInfo, geoip: &GeoIpDb) -> bool {
+    let country = relay
+        .addresses
+        .iter()
+        .find_map(|address| geoip.lookup_multiaddr(address));
+    if country.is_none() || country == relay.country {
+        return false;
+    }
+    relay.country = country;
+    true
+}
+
+impl RelayRegistry {
+    /// In-memory registry; nothing is saved
+    pub fn new() -> Self {
+        Self {
+            relays: Mutex::new(HashMap::new()),
+            blocked: Mutex::new(HashMap::new()),
+            persist_path: None,
+            pruner: std::sync::Mutex::new(None),
+            prune_stats: Mutex::new(PruneStats::default()),
+            geoip: std::sync::Mutex::new(None),
+            saved_at: std::sync::Mutex::new(None),
+            unsaved: AtomicBool::new(false),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Registry loaded from and saved to `path`
+    pub fn with_persistence(path: impl AsRef<Path>) -> Self {
+        Self::load_at(path, SystemClock.now_secs())
+    }
+
+    /// Read the time from `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Current Unix seconds on the registry's clock
+    pub fn now(&self) -> u64 {
+        self.clock.now_secs()
+    }
+
+    /// Load as of `now` (Unix seconds): scores are decayed for the time since
+    /// they were last decayed and relays too old to be useful are dropped
+    pub fn load_at(path: impl AsRef<Path>, now: u64) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let saved: PersistedRegistry = persisted::load(&path);
+        let relays = saved
+            .relays
+            .into_iter()
+            .filter(|relay| {
+                let fresh = now.saturating_sub(relay.last_seen) < MAX_RELAY_AGE_SECS;
+                if !fresh {
+                    publish(RelayRegistryEvent::Pruned {
+                        peer_id: relay.peer_id.clone(),
+                    });
+                }
+                fresh
+            })
+            .map(|mut relay| {
+                relay.decay_to(now);
+                (relay.peer_id.clone(), relay)
+            })
+            .collect();
+        let blocked = saved
+            .blocked
+            .into_iter()
+            .map(|blocked| (blocked.peer_id.clone(), blocked))
+            .collect();
+        Self {
+            relays: Mutex::new(relays),
+            blocked: Mutex::new(blocked),
+            persist_path: Some(path),
+            pruner: std::sync::Mutex::new(None),
+            prune_stats: Mutex::new(PruneStats::default()),
+            geoip: std::sync::Mutex::new(None),
+            saved_at: std::sync::Mutex::new(None),
+            unsaved: AtomicBool::new(false),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Apply a reputation event for `peer_id` at `at` (Unix seconds).
+    /// Returns the relay's updated record, or `None` if it is blocked.
+    pub async fn record_event(
+        &self,
+        peer_id: &str,
+        event_type: &str,
+        impact: f64,
+        at: u64,
+    ) -> Option<RelayInfo> {
+        let mut relays = self.relays.lock().await;
+        if self.is_blocked(peer_id).await {
+            return None;
+        }
+        let added = !relays.contains_key(peer_id);
+        let relay = relays
+            .entry(peer_id.to_string())
+            .or_insert_with(|| RelayInfo {
+                peer_id: peer_id.to_string(),
+                last_seen: at,
+                ..RelayInfo::default()
+            });
+        // Scores decay between events too, so old standing counts for less
+        relay.decay_to(at);
+        relay.health_score += impact;
+        relay.total_events += 1;
+        relay.last_seen = relay.last_seen.max(at);
+        match event_type {
+            "RelayReservationAccepted" => relay.reservations_accepted += 1,
+            "RelayCircuitEstablished" => relay.circuits_established += 1,
+            "RelayCircuitSuccessful" => relay.circuits_successful += 1,
+            _ => {}
+        }
+        let updated = relay.clone();
+        self.save(&relays).await;
+        publish_relay(&updated, added);
+        Some(updated)
+    }
+
+    /// Count a DCUtR attempt on a connection relayed by `peer_id`. How
+    /// often peers get past a relay says more about their NATs than about
+    /// the relay, so the health score is left alone. `None` if the relay is
+    /// blocked.
+    pub async fn record_hole_punch(
+        &self,
+        peer_id: &str,
+        success: bool,
+        at: u64,
+    ) -> Option<RelayInfo> {
+        let mut relays = self.relays.lock().await;
+        if self.is_blocked(peer_id).await {
+            return None;
+        }
+        let added = !relays.contains_key(peer_id);
+        let relay = relays
+            .entry(peer_id.to_string())
+            .or_insert_with(|| RelayInfo {
+                peer_id: peer_id.to_string(),
+                last_seen: at,
+                ..RelayInfo::default()
+            });
+        relay.hole_punch_attempts += 1;
+        if success {
+            relay.hole_punch_successes += 1;
+        }
+        relay.last_seen = relay.last_seen.max(at);
+        let updated = relay.clone();
+        self.save(&relays).await;
+        publish_relay(&updated, added);
+        Some(updated)
+    }
+
+    /// Add what `peer_id` carried for us, or failed to, and fold it into its
+    /// health score. `None` if the relay is blocked.
+    pub async fn record_usage(
+        &self,
+        peer_id: &str,
+        circuits_opened: u64,
+        bytes: u64,
+        failures: u64,
+        at: u64,
+    ) -> Option<RelayInfo> {
+        let mut relays = self.relays.lock().await;
+        if self.is_blocked(peer_id).await {
+            return None;
+        }
+        let added = !relays.contains_key(peer_id);
+        let relay = relays
+            .entry(peer_id.to_string())
+            .or_insert_with(|| RelayInfo {
+                peer_id: peer_id.to_string(),
+                last_seen: at,
+                ..RelayInfo::default()
+            });
+        relay.decay_to(at);
+        relay.health_score += usage_impact(circuits_opened, bytes, failures);
+        relay.circuits_opened += circuits_opened;
+        relay.bytes_relayed += bytes;
+        relay.connection_failures += failures;
+        // Failures are no sign of life
+        if circuits_opened > 0 || bytes > 0 {
+            relay.last_seen = relay.last_seen.max(at);
+        }
+        let updated = relay.clone();
+        self.save(&relays).await;
+        publish_relay(&updated, added);
+        Some(updated)
+    }
+
+    /// Remember where a known relay was seen. Peers that are not relays in
+    /// the registry are ignored. True if anything changed.
+    pub async fn note_addresses(&self, peer_id: &str, addresses: &[String], at: u64) -> bool {
+        let mut relays = self.relays.lock().await;
+        let Some(relay) = relays.get_mut(peer_id) else {
+            return false;
+        };
+        if !merge_addresses(relay, addresses, at) {
+            return false;
+        }
+        if let Some(geoip) = self.geoip() {
+            locate(relay, &geoip);
+        }
+        let updated = relay.clone();
+        self.save(&relays).await;
+        publish_relay(&updated, false);
+        true
+    }
+
+    /// Take in a relay's own announcement, passed on to us by `via`. Unknown
+    /// relays are added with a neutral score until reservations and circuits
+    /// show how they do, unless `via` or everyone together already passed on
+    /// as many unscored relays as are kept. True if anything changed.
+    pub async fn record_announcement(&self, announcement: &RelayAnnouncement, via: &str) -> bool {
+        let peer_id = &announcement.peer_id;
+        let at = announcement.announced_at;
+        let mut relays = self.relays.lock().await;
+        if self.is_blocked(peer_id).await {
+            return false;
+        }
+        let added = !relays.contains_key(peer_id);
+        if added {
+            let unscored = relays.values().filter(|relay| relay.is_unscored());
+            let (total, from_via) = unscored.fold((0, 0), |(total, from_via), relay| {
+                let same = relay.announced_via.as_deref() == Some(via);
+                (total + 1, from_via + usize::from(same))
+            });
+            if total >= MAX_ANNOUNCED || from_via >= MAX_ANNOUNCED_PER_SOURCE {
+                debug!(
+                    "Not adding relay {} announced via {}: too many unscored relays",
+                    peer_id, via
+                );
+                return false;
+            }
+        }
+        let mut changed = added;
+        let relay = relays.entry(peer_id.clone()).or_insert_with(|| RelayInfo {
+            peer_id: peer_id.clone(),
+            last_seen: at,
+            announced_via: Some(via.to_string()),
+            ..RelayInfo::default()
+        });
+        if merge_addresses(relay, &announcement.addresses, at) {
+            changed = true;
+            if let Some(geoip) = self.geoip() {
+                locate(relay, &geoip);
+            }
+        }
+        if announcement.capacity.is_some() && announcement.capacity != relay.capacity {
+            relay.capacity = announcement.capacity;
+            changed = true;
+        }
+        if announcement.region.is_some() && announcement.region != relay.region {
+            relay.region = announcement.region.clone();
+            changed = true;
+        }
+        if changed {
+            let updated = relay.clone();
+            self.save_announcements(&relays).await;
+            publish_relay(&updated, added);
+        }
+        changed
+    }
+
+    /// Apply the result of probing a known relay at `at`. `rtt_ms` is `None`
+    /// when the relay could not be reached. Returns the updated record, or
+    /// `None` for relays not in the registry.
+    pub async fn record_probe(
+        &self,
+        peer_id: &str,
+        impact: f64,
+        rtt_ms: Option<u64>,
+        at: u64,
+    ) -> Option<RelayInfo> {
+        let mut relays = self.relays.lock().await;
+        let relay = relays.get_mut(peer_id)?;
+        relay.decay_to(at);
+        relay.health_score += impact;
+        relay.last_probed = relay.last_probed.max(at);
+        match rtt_ms {
+            Some(rtt) => {
+                relay.last_rtt_ms = Some(rtt);
+                relay.failed_probes = 0;
+                relay.last_seen = relay.last_seen.max(at);
+            }
+            None => relay.failed_probes += 1,
+        }
+        let updated = relay.clone();
+        self.save(&relays).await;
+        publish_relay(&updated, false);
+        Some(updated)
+    }
+
+    /// Keep the latest state of our reservation with a relay
+    pub async fn record_reservation(&self, state: &ReservationState) {
+        let mut relays = self.relays.lock().await;
+        if self.is_blocked(&state.relay_peer_id).await {
+            return;
+        }
+        let added = !relays.contains_key(&state.relay_peer_id);
+        let relay = relays
+            .entry(state.relay_peer_id.clone())
+            .or_insert_with(|| RelayInfo {
+                peer_id: state.relay_peer_id.clone(),
+                last_seen: state.requested_at,
+                ..RelayInfo::default()
+            });
+        relay.reservation = Some(state.clone());
+        publish_relay(relay, added);
+    }
+
+    /// Up to `limit` relays with an address to dial, least recently probed
+    /// first. Relays that earned a score or hold our reservation get at
+    /// least half of `limit`, so a flood of announced relays never probed
+    /// can't keep the ones in use from being probed; a share one side
+    /// leaves unused goes to the other.
+    pub async fn due_for_probe(&self, limit: usize) -> Vec<RelayInfo> {
+        let (mut known, mut announced): (Vec<RelayInfo>, Vec<RelayInfo>) = self
+            .relays
+            .lock()
+            .await
+            .values()
+            .filter(|relay| !relay.addresses.is_empty())
+            .cloned()
+            .partition(|relay| !relay.is_unscored() || relay.reservation.is_some());
+        let by_last_probe = |a: &RelayInfo, b: &RelayInfo| {
+            a.last_probed
+                .cmp(&b.last_probed)
+                .then_with(|| a.peer_id.cmp(&b.peer_id))
+        };
+        known.sort_by(by_last_probe);
+        announced.sort_by(by_last_probe);
+        let known_share = known.len().min(limit - (limit / 2).min(announced.len()));
+        known.truncate(known_share);
+        announced.truncate(limit - known_share);
+        known.append(&mut announced);
+        known.sort_by(by_last_probe);
+        known
+    }
+
+    /// The address of `relay` to dial, ending in its peer ID
+    pub fn dial_address(relay: &RelayInfo) -> Option<String> {
+        let address = relay.addresses.first()?;
+        if address.contains(&relay.peer_id) {
+            Some(address.clone())
+        } else {
+            Some(format!("{}/p2p/{}", address, relay.peer_id))
+        }
+    }
+
+    pub async fn get(&self, peer_id: &str) -> Option<RelayInfo> {
+        self.relays.lock().await.get(peer_id).cloned()
+    }
+
+    /// Every known relay, healthiest first
+    pub async fn list(&self) -> Vec<RelayInfo> {
+        let mut relays: Vec<RelayInfo> = self.relays.lock().await.values().cloned().collect();
+        relays.sort_by(|a, b| {
+            b.health_score
+                .total_cmp(&a.health_score)
+                .then_with(|| a.peer_id.cmp(&b.peer_id))
+        });
+        relays
+    }
+
+    /// Known relays located in `region` (a country code), healthiest first
+    pub async fn list_by_region(&self, region: &str) -> Vec<RelayInfo> {
+        let mut relays = self.list().await;
+        relays.retain(|relay| {
+            relay
+                .located_region()
+                .is_some_and(|located| located.eq_ignore_ascii_case(region))
+        });
+        relays
+    }
+
+    /// Locate relays with `geoip` from now on, and place the known ones
+    pub async fn set_geoip(&self, geoip: GeoIpDb) {
+        let geoip = Arc::new(geoip);
+        *self.geoip.lock().unwrap_or_else(|e| e.into_inner()) = Some(geoip.clone());
+        let mut relays = self.relays.lock().await;
+        let mut located = Vec::new();
+        for relay in relays.values_mut() {
+            if locate(relay, &geoip) {
+                located.push(relay.clone());
+            }
+        }
+        if located.is_empty() {
+            return;
+        }
+        self.save(&relays).await;
+        for relay in &located {
+            publish_relay(relay, false);
+        }
+    }
+
+    fn geoip(&self) -> Option<Arc<GeoIpDb>> {
+        self.geoip.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    pub async fn len(&self) -> usize {
+        self.relays.lock().await.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.relays.lock().await.is_empty()
+    }
+
+    /// Forget relays unheard of for more than `max_age_secs` as of `now`.
+    /// Returns their peer IDs.
+    pub async fn prune_stale(&self, max_age_secs: u64, now: u64) -> Vec<String> {
+        let mut relays = self.relays.lock().await;
+        let stale: Vec<String> = relays
+            .values()
+            .filter(|relay| now.saturating_sub(relay.last_seen) > max_age_secs)
+            .map(|relay| relay.peer_id.clone())
+            .collect();
+        if stale.is_empty() {
+            return stale;
+        }
+        for peer_id in &stale {
+            relays.remove(peer_id);
+            publish(RelayRegistryEvent::Pruned {
+                peer_id: peer_id.clone(),
+            });
+        }
+        self.save(&relays).await;
+        stale
+    }
+
+    /// Prune every `config.interval` in the background, replacing a pruning
+    /// task already running. The task ends with the registry.
+    pub fn start_pruning(self: &Arc<Self>, config: PruneConfig) {
+        let registry: Weak<Self> = Arc::downgrade(self);
+        let task = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(config.interval);
+            // Loading already pruned, so the first run is one interval in
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(registry) = registry.upgrade() else {
+                    break;
+                };
+                registry
+                    .run_prune(config.max_age_secs, registry.now())
+                    .await;
+            }
+        });
+        let mut pruner = self.pruner.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(previous) = pruner.replace(task) {
+            previous.abort();
+        }
+    }
+
+    pub fn stop_pruning(&self) {
+        let mut pruner = self.pruner.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(task) = pruner.take() {
+            task.abort();
+        }
+    }
+
+    pub fn is_pruning(&self) -> bool {
+        let pruner = self.pruner.lock().unwrap_or_else(|e| e.into_inner());
+        pruner.as_ref().is_some_and(|task| !task.is_finished())
+    }
+
+    pub async fn prune_stats(&self) -> PruneStats {
+        PruneStats {
+            running: self.is_pruning(),
+            ..self.prune_stats.lock().await.clone()
+        }
+    }
+
+    /// One pass of the pruning task
+    async fn run_prune(&self, max_age_secs: u64, now: u64) {
+        let pruned = self.prune_stale(max_age_secs, now).await.len();
+        self.flush().await;
+        let remaining = self.len().await;
+        let mut stats = self.prune_stats.lock().await;
+        stats.runs += 1;
+        stats.last_run_at = Some(now);
+        stats.pruned_last_run = pruned;
+        stats.pruned_total += pruned as u64;
+        stats.remaining = remaining;
+        if pruned > 0 {
+            info!("Pruned {} stale relays, {} left", pruned, remaining);
+        }
+    }
+
+    /// Dialable addresses of up to `limit` relays with a positive score,
+    /// best `selection_score` first, for use as preferred relays
+    pub async fn preferred_addresses(&self, limit: usize) -> Vec<String> {
+        self.select_addresses(limit, &BestHealth).await
+    }
+
+    /// Dialable addresses of the relays `select_relays` picks
+    pub async fn select_addresses(&self, n: usize, selector: &dyn RelaySelector) -> Vec<String> {
+        self.select_relays(n, selector)
+            .await
+            .iter()
+            .filter_map(Self::dial_address)
+            .collect()
+    }
+
+    /// Up to `n` relays picked by `selector` among those with a positive
+    /// score and an address to dial
+    pub async fn select_relays(&self, n: usize, selector: &dyn RelaySelector) -> Vec<RelayInfo> {
+        let mut candidates = self.list().await;
+        candidates.retain(|relay| relay.health_score > 0.0 && !relay.addresses.is_empty());
+        selector.select(candidates, n)
+    }
+
+    /// Put `peer_id` on the denylist and forget what is known about it
+    pub async fn block(&self, peer_id: &str, reason: &str, at: u64) -> BlockedRelay {
+        let mut relays = self.relays.lock().await;
+        let removed = relays.remove(peer_id).is_some();
+        let blocked = BlockedRelay {
+            peer_id: peer_id.to_string(),
+            reason: reason.to_string(),
+            blocked_at: at,
+        };
+        self.blocked
+            .lock()
+            .await
+            .insert(peer_id.to_string(), blocked.clone());
+        self.save(&relays).await;
+        if removed {
+            publish(RelayRegistryEvent::Removed {
+                peer_id: peer_id.to_string(),
+                reason: reason.to_string(),
+            });
+        }
+        blocked
+    }
+
+    /// Take `peer_id` off the denylist. False if it was not on it.
+    pub async fn unblock(&self, peer_id: &str) -> bool {
+        let relays = self.relays.lock().await;
+        if self.blocked.lock().await.remove(peer_id).is_none() {
+            return false;
+        }
+        self.save(&relays).await;
+        true
+    }
+
+    pub async fn is_blocked(&self, peer_id: &str) -> bool {
+        self.blocked.lock().await.contains_key(peer_id)
+    }
+
+    /// The denylist, most recently blocked first
+    pub async fn blocked(&self) -> Vec<BlockedRelay> {
+        let mut blocked: Vec<BlockedRelay> = self.blocked.lock().await.values().cloned().collect();
+        blocked.sort_by(|a, b| {
+            b.blocked_at
+                .cmp(&a.blocked_at)
+                .then_with(|| a.peer_id.cmp(&b.peer_id))
+        });
+        blocked
+    }
+
+    /// Save what announcements changed if the last save was long enough
+    /// ago; otherwise it goes with the next save
+    async fn save_announcements(&self, relays: &HashMap<String, RelayInfo>) {
+        let recently = self
+            .saved_at
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_some_and(|at| at.elapsed() < ANNOUNCEMENT_SAVE_INTERVAL);
+        if recently {
+            self.unsaved.store(true, Ordering::Relaxed);
+        } else {
+            self.save(relays).await;
+        }
+    }
+
+    /// Save announcements still waiting for a save
+    pub async fn flush(&self) {
+        let relays = self.relays.lock().await;
+        if self.unsaved.load(Ordering::Relaxed) {
+            self.save(&relays).await;
+        }
+    }
+
+    /// Called with `relays` locked, so saves never interleave
+    async fn save(&self, relays: &HashMap<String, RelayInfo>) {
+        let Some(path) = &self.persist_path else {
+            return;
+        };
+        self.unsaved.store(false, Ordering::Relaxed);
+        *self.saved_at.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now());
+        let saved = PersistedRegistry {
+            relays: relays.values().cloned().collect(),
+            blocked: self.blocked.lock().await.values().cloned().collect(),
+        };
+        if let Err(e) = persisted::save_async(path, &saved).await {
+            warn!("Failed to persist relay registry: {}", e);
+        }
+    }
+}
+
+impl Default for RelayRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for RelayRegistry {
+    fn drop(&mut self) {
+        self.stop_pruning();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dht::reservations::{ReservationStatus, RESERVATION_TTL_SECS};
+    use crate::runtime_env::ManualClock;
+    use tempfile::tempdir;
+
+    const DAY: u64 = SCORE_HALF_LIFE_SECS;
+    const ADDRESS: &str = "/ip4/203.0.113.7/tcp/4001";
+    const VIA: &str = "gossip-peer";
+
+    fn announced(peer_id: &str, addresses: &[&str], at: u64) -> RelayAnnouncement {
+        RelayAnnouncement {
+            peer_id: peer_id.to_string(),
+            addresses: addresses.iter().map(|a| a.to_string()).collect(),
+            announced_at: at,
+            capacity: None,
+            region: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn relays_survive_a_restart_with_decayed_scores() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(REGISTRY_FILE);
+        let registry = RelayRegistry::load_at(&path, 0);
+        registry
+            .record_event("relayA", "RelayReservationAccepted", 5.0, 1_000)
+            .await;
+        registry
+            .record_event("relayA", "RelayCircuitEstablished", 3.0, 1_000)
+            .await;
+        let old = registry
+            .record_event("relayB", "RelayCircuitSuccessful", 4.0, 10)
+            .await
+            .unwrap();
+        assert_eq!(old.health_score, 4.0);
+        let addresses = ["/ip4/10.0.0.1/tcp/4001".to_string()];
+        assert!(registry.note_addresses("relayA", &addresses, 1_000).await);
+        assert!(!registry.note_addresses("relayA", &addresses, 1_000).await);
+        assert!(!registry.note_addresses("stranger", &addresses, 1_000).await);
+        drop(registry);
+
+        let restored = RelayRegistry::load_at(&path, 1_000 + DAY);
+        let relay = restored.get("relayA").await.unwrap();
+        assert_eq!(relay.health_score, 4.0);
+        assert_eq!(relay.reservations_accepted, 1);
+        assert_eq!(relay.circuits_established, 1);
+        assert_eq!(relay.addresses, addresses);
+        assert_eq!(
+            restored.preferred_addresses(5).await,
+            vec!["/ip4/10.0.0.1/tcp/4001/p2p/relayA".to_string()]
+        );
+
+        // A month later both are forgotten
+        let later = RelayRegistry::load_at(&path, 1_000 + MAX_RELAY_AGE_SECS);
+        assert_eq!(later.len().await, 0);
+    }
+
+    #[tokio::test]
+    async fn decay_is_counted_once_across_a_restart() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(REGISTRY_FILE);
+        let registry = RelayRegistry::load_at(&path, 0);
+        registry
+            .record_event("relayA", "RelayReservationAccepted", 8.0, 0)
+            .await;
+        drop(registry);
+
+        // Loading a day later halves the score once; an event recorded
+        // right after does not decay it again
+        let restored = RelayRegistry::load_at(&path, DAY);
+        assert_eq!(restored.get("relayA").await.unwrap().health_score, 4.0);
+        let relay = restored
+            .record_event("relayA", "RelayCircuitEstablished", 0.0, DAY)
+            .await
+            .unwrap();
+        assert_eq!(relay.health_score, 4.0);
+        assert_eq!(relay.scored_at, DAY);
+        drop(restored);
+
+        // Restarting again at once costs nothing
+        let again = RelayRegistry::load_at(&path, DAY);
+        assert_eq!(again.get("relayA").await.unwrap().health_score, 4.0);
+    }
+
+    #[tokio::test]
+    async fn scores_decay_between_events_and_sort_the_list() {
+        let registry = RelayRegistry::new();
+        registry.record_event("a", "RelayRefused", 8.0, 0).await;
+        let a = registry
+            .record_event("a", "RelayRefused", -2.0, 2 * DAY)
+            .await
+            .unwrap();
+        assert_eq!(a.health_score, 0.0);
+        assert_eq!(a.total_events, 2);
+        registry
+            .record_event("b", "RelayCircuitSuccessful", 1.0, 0)
+            .await;
+
+        let order: Vec<String> = registry
+            .list()
+            .await
+            .into_iter()
+            .map(|r| r.peer_id)
+            .collect();
+        assert_eq!(order, vec!["b".to_string(), "a".to_string()]);
+        assert!(registry.preferred_addresses(5).await.is_empty());
+        assert_eq!(decayed_score(-6.0, DAY), -3.0);
+    }
+
+    #[tokio::test]
+    async fn announced_relays_are_added_without_standing() {
+        let registry = RelayRegistry::new();
+        assert!(
+            registry
+                .record_announcement(&announced("r", &[ADDRESS], 100), VIA)
+                .await
+        );
+        assert!(
+            !registry
+                .record_announcement(&announced("r", &[ADDRESS], 400), VIA)
+                .await
+        );
+        let relay = registry.get("r").await.unwrap();
+        assert_eq!(relay.health_score, 0.0);
+        assert_eq!(relay.total_events, 0);
+        assert_eq!(relay.addresses, [ADDRESS]);
+        // Known now, so later sightings update it
+        let moved = ["/ip4/203.0.113.8/tcp/4001".to_string()];
+        assert!(registry.note_addresses("r", &moved, 500).await);
+        assert_eq!(registry.get("r").await.unwrap().addresses.len(), 2);
+        assert!(registry.preferred_addresses(5).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn announced_relays_are_capped_and_saved_in_batches() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(REGISTRY_FILE);
+        let registry = RelayRegistry::load_at(&path, 0);
+        let announce = |peer_id: String, via: String| {
+            let registry = &registry;
+            async move {
+                registry
+                    .record_announcement(&announced(&peer_id, &[ADDRESS], 0), &via)
+                    .await
+            }
+        };
+        for i in 0..MAX_ANNOUNCED_PER_SOURCE {
+            assert!(announce(format!("sybil-{}", i), "sybil".into()).await);
+        }
+        assert!(!announce("sybil-more".into(), "sybil".into()).await);
+        assert!(announce("honest".into(), "neighbour".into()).await);
+        // A relay that earned a score no longer counts against its source
+        registry
+            .record_event("sybil-0", "RelayCircuitSuccessful", 5.0, 0)
+            .await;
+        assert!(announce("sybil-more".into(), "sybil".into()).await);
+
+        let unscored = MAX_ANNOUNCED_PER_SOURCE + 1;
+        for i in unscored..MAX_ANNOUNCED {
+            assert!(announce(format!("relay-{}", i), format!("peer-{}", i)).await);
+        }
+        assert!(!announce("one-too-many".into(), "elsewhere".into()).await);
+        // Relays already known keep taking announcements
+        let moved = announced("honest", &["/ip4/203.0.113.8/tcp/4001"], 10);
+        assert!(registry.record_announcement(&moved, "elsewhere").await);
+        assert_eq!(registry.len().await, MAX_ANNOUNCED + 1);
+
+        // Only the save made for the scored relay has reached the file
+        assert!(RelayRegistry::load_at(&path, 10).len().await <= unscored);
+        registry.flush().await;
+        assert_eq!(
+            RelayRegistry::load_at(&path, 10).len().await,
+            MAX_ANNOUNCED + 1
+        );
+    }
+
+    #[tokio::test]
+    async fn probes_adjust_scores_and_rotate() {
+        let registry = RelayRegistry::new();
+        registry
+            .record_announcement(&announced("a", &[ADDRESS], 0), VIA)
+            .await;
+        registry
+            .record_announcement(&announced("b", &[ADDRESS], 0), VIA)
+            .await;
+        registry
+            .record_announcement(&announced("silent", &[], 0), VIA)
+            .await;
+        assert!(registry
+            .record_probe("stranger", 1.0, None, 0)
+            .await
+            .is_none());
+
+        let a = registry.record_probe("a", 3.0, Some(40), 10).await.unwrap();
+        assert_eq!(a.health_score, 3.0);
+        assert_eq!(
+            (a.last_rtt_ms, a.last_probed, a.last_seen),
+            (Some(40), 10, 10)
+        );
+        registry.record_probe("b", -5.0, None, 20).await;
+        let b = registry.record_probe("b", -5.0, None, 30).await.unwrap();
+        assert_eq!(b.failed_probes, 2);
+        assert_eq!(b.last_seen, 0);
+
+        // Relays without an address can't be probed; the rest rotate
+        let due: Vec<String> = registry
+            .due_for_probe(5)
+            .await
+            .into_iter()
+            .map(|r| r.peer_id)
+            .collect();
+        assert_eq!(due, vec!["a".to_string(), "b".to_string()]);
+        assert_eq!(
+            RelayRegistry::dial_address(&a).as_deref(),
+            Some("/ip4/203.0.113.7/tcp/4001/p2p/a")
+        );
+    }
+
+    #[tokio::test]
+    async fn relays_in_use_are_probed_despite_a_flood_of_announcements() {
+        let registry = RelayRegistry::new();
+        for i in 0..MAX_ANNOUNCED_PER_SOURCE {
+            registry
+                .record_announcement(&announced(&format!("new-{:02}", i), &[ADDRESS], 0), VIA)
+                .await;
+        }
+        for peer_id in ["scored", "reserved"] {
+            registry
+                .record_announcement(&announced(peer_id, &[ADDRESS], 0), "neighbour")
+                .await;
+        }
+        registry.record_probe("scored", 3.0, Some(40), 50).await;
+        registry
+            .record_reservation(&ReservationState {
+                relay_peer_id: "reserved".to_string(),
+                listen_addr: format!("{}/p2p/reserved/p2p-circuit", ADDRESS),
+                status: ReservationStatus::Active,
+                requested_at: 40,
+                accepted_at: Some(50),
+                expires_at: Some(50 + RESERVATION_TTL_SECS),
+                renewals: 0,
+                failures: 0,
+                consecutive_failures: 0,
+                last_error: None,
+                retry_at: None,
+            })
+            .await;
+
+        let due: Vec<String> = registry
+            .due_for_probe(4)
+            .await
+            .into_iter()
+            .map(|r| r.peer_id)
+            .collect();
+        assert_eq!(due, ["new-00", "new-01", "reserved", "scored"]);
+        // Without enough announced relays the rest of the budget goes to
+        // the known ones, and the other way round
+        assert_eq!(
+            registry.due_for_probe(30).await.len(),
+            MAX_ANNOUNCED_PER_SOURCE + 2
+        );
+        let few = RelayRegistry::new();
+        for peer_id in ["k1", "k2", "k3", "x"] {
+            few.record_announcement(&announced(peer_id, &[ADDRESS], 0), VIA)
+                .await;
+        }
+        for peer_id in ["k1", "k2", "k3"] {
+            few.record_probe(peer_id, 1.0, Some(40), 10).await;
+        }
+        assert_eq!(few.due_for_probe(4).await.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn relays_with_spare_capacity_are_preferred() {
+        let registry = RelayRegistry::new();
+        let load = |active_circuits| {
+            Some(RelayCapacity {
+                max_circuits: 16,
+                max_circuit_bytes: 1 << 17,
+                active_circuits,
+            })
+        };
+        for (peer_id, active) in [("busy", Some(15)), ("idle", Some(2)), ("quiet", None)] {
+            let mut announcement = announced(peer_id, &[ADDRESS], 0);
+            announcement.capacity = active.and_then(load);
+            registry.record_announcement(&announcement, VIA).await;
+        }
+        // The busy relay is the healthiest, but nearly full
+        registry
+            .record_event("busy", "RelayCircuitSuccessful", 15.0, 0)
+            .await;
+        registry
+            .record_event("idle", "RelayCircuitSuccessful", 10.0, 0)
+            .await;
+        registry
+            .record_event("quiet", "RelayCircuitSuccessful", 10.0, 0)
+            .await;
+
+        let preferred: Vec<String> = registry
+            .preferred_addresses(5)
+            .await
+            .into_iter()
+            .map(|address| address.rsplit('/').next().unwrap().to_string())
+            .collect();
+        assert_eq!(preferred, ["idle", "quiet", "busy"]);
+
+        // A newer announcement replaces the reported load
+        let mut announcement = announced("busy", &[ADDRESS], 10);
+        announcement.capacity = load(0);
+        assert!(registry.record_announcement(&announcement, VIA).await);
+        assert_eq!(registry.preferred_addresses(1).await.len(), 1);
+        let busy = registry.get("busy").await.unwrap();
+        assert_eq!(busy.selection_score(), busy.health_score);
+    }
+
+    #[tokio::test]
+    async fn reservation_state_is_shown_but_not_saved() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(REGISTRY_FILE);
+        let registry = RelayRegistry::load_at(&path, 0);
+        registry
+            .record_event("relayA", "RelayReservationAccepted", 5.0, 100)
+            .await;
+        let state = ReservationState {
+            relay_peer_id: "relayA".to_string(),
+            listen_addr: format!("{}/p2p/relayA/p2p-circuit", ADDRESS),
+            status: ReservationStatus::Active,
+            requested_at: 90,
+            accepted_at: Some(100),
+            expires_at: Some(100 + RESERVATION_TTL_SECS),
+            renewals: 0,
+            failures: 0,
+            consecutive_failures: 0,
+            last_error: None,
+            retry_at: None,
+        };
+        registry.record_reservation(&state).await;
+        let relay = registry.get("relayA").await.unwrap();
+        assert_eq!(relay.reservation, Some(state));
+        assert_eq!(relay.reservations_accepted, 1);
+        registry
+            .record_event("relayA", "RelayCircuitEstablished", 3.0, 200)
+            .await;
+        drop(registry);
+
+        let restored = RelayRegistry::load_at(&path, 200);
+        assert_eq!(restored.get("relayA").await.unwrap().reservation, None);
+    }
+
+    #[tokio::test]
+    async fn hole_punch_rates_are_kept_per_relay() {
+        let dir = tempdir().unwrap();
+        let registry = RelayRegistry::load_at(&dir.path().join(REGISTRY_FILE), 0);
+        let relay = registry
+            .record_event("relayA", "RelayCircuitEstablished", 3.0, 0)
+            .await
+            .unwrap();
+        assert_eq!(relay.hole_punch_rate(), None);
+        registry.record_hole_punch("relayA", true, 10).await;
+        registry.record_hole_punch("relayA", false, 20).await;
+        registry.record_hole_punch("relayA", true, 30).await;
+        let relay = registry
+            .record_hole_punch("relayA", true, 40)
+            .await
+            .unwrap();
+        assert_eq!(relay.hole_punch_attempts, 4);
+        assert_eq!(relay.hole_punch_rate(), Some(0.75));
+        assert_eq!(relay.health_score, 3.0);
+    }
+
+    #[tokio::test]
+    async fn blocked_relays_stay_out_across_restarts() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(REGISTRY_FILE);
+        let registry = RelayRegistry::load_at(&path, 0);
+        registry
+            .record_announcement(&announced("bad", &[ADDRESS], 0), VIA)
+            .await;
+        registry
+            .record_event("bad", "RelayCircuitSuccessful", 10.0, 0)
+            .await;
+        registry.block("bad", "Drops circuits", 5).await;
+        assert!(registry.get("bad").await.is_none());
+        assert!(registry.preferred_addresses(5).await.is_empty());
+        assert!(registry
+            .record_event("bad", "RelayCircuitSuccessful", 10.0, 10)
+            .await
+            .is_none());
+        assert!(
+            !registry
+                .record_announcement(&announced("bad", &[ADDRESS], 10), VIA)
+                .await
+        );
+        drop(registry);
+
+        let restored = RelayRegistry::load_at(&path, 20);
+        assert!(restored.is_blocked("bad").await);
+        assert_eq!(restored.blocked().await[0].reason, "Drops circuits");
+        assert!(restored.unblock("bad").await);
+        assert!(!restored.unblock("bad").await);
+        restored
+            .record_announcement(&announced("bad", &[ADDRESS], 20), VIA)
+            .await;
+        assert!(restored.get("bad").await.is_some());
+        drop(restored);
+        assert!(!RelayRegistry::load_at(&path, 30).is_blocked("bad").await);
+    }
+
+    #[tokio::test]
+    async fn changes_are_published_on_the_event_bus() {
+        let mut rx = event_bus::global().subscribe();
+        let registry = RelayRegistry::new();
+        registry
+            .record_announcement(&announced("published", &[ADDRESS], 0), VIA)
+            .await;
+        registry
+            .record_event("published", "RelayCircuitSuccessful", 1.0, 0)
+            .await;
+        registry.block("published", "Test", 0).await;
+
+        let mut seen = Vec::new();
+        while let Ok(envelope) = rx.try_recv() {
+            if let EventPayload::RelayRegistry(event) = envelope.payload {
+                if event.peer_id() == "published" {
+                    seen.push(match event {
+                        RelayRegistryEvent::Added { .. } => "added",
+                        RelayRegistryEvent::Updated { .. } => "updated",
+                        RelayRegistryEvent::Pruned { .. } => "pruned",
+                        RelayRegistryEvent::Removed { .. } => "removed",
+                    });
+                }
+            }
+        }
+        assert_eq!(seen, ["added", "updated", "removed"]);
+    }
+
+    #[tokio::test]
+    async fn stale_relays_are_pruned_in_the_background() {
+        let clock = Arc::new(ManualClock::new(Duration::from_secs(10 * DAY)));
+        let registry = Arc::new(RelayRegistry::new().with_clock(clock.clone()));
+        let now = registry.now();
+        registry
+            .record_event("stale", "RelayCircuitSuccessful", 1.0, now - 2 * DAY)
+            .await;
+        registry
+            .record_event("fresh", "RelayCircuitSuccessful", 1.0, now)
+            .await;
+        assert_eq!(registry.prune_stale(3 * DAY, now).await.len(), 0);
+
+        // The pruner ages relays by the registry's clock
+        clock.advance(Duration::from_secs(2 * DAY));
+        registry.start_pruning(PruneConfig {
+            interval: Duration::from_millis(20),
+            max_age_secs: 3 * DAY,
+        });
+        assert!(registry.is_pruning());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        registry.stop_pruning();
+
+        let stats = registry.prune_stats().await;
+        assert!(!stats.running);
+        assert!(stats.runs >= 1);
+        assert_eq!(stats.pruned_total, 1);
+        assert_eq!(stats.remaining, 1);
+        assert!(registry.get("stale").await.is_none());
+        assert!(registry.get("fresh").await.is_some());
+
+        // Stopped means stopped
+        let runs = stats.runs;
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(registry.prune_stats().await.runs, runs);
+    }
+
+    #[tokio::test]
+    async fn usage_raises_and_failures_lower_the_score() {
+        assert_eq!(usage_impact(2, 0, 0), 2.0);
+        assert_eq!(usage_impact(0, 10 * 1024 * 1024, 0), 1.0);
+        assert_eq!(usage_impact(100, u64::MAX, 0), MAX_USAGE_REWARD);
+        assert_eq!(usage_impact(0, 0, 2), -6.0);
+
+        let registry = RelayRegistry::new();
+        registry.record_usage("good", 3, 0, 0, 0).await;
+        registry
+            .record_usage("good", 0, 10 * 1024 * 1024, 0, 0)
+            .await;
+        registry.record_usage("flaky", 3, 0, 0, 0).await;
+        let flaky = registry.record_usage("flaky", 0, 0, 2, 0).await.unwrap();
+        assert_eq!(flaky.connection_failures, 2);
+        assert_eq!(flaky.health_score, -3.0);
+        let good = registry.get("good").await.unwrap();
+        assert_eq!(good.circuits_opened, 3);
+        assert_eq!(good.bytes_relayed, 10 * 1024 * 1024);
+        assert_eq!(good.health_score, 4.0);
+        let order: Vec<String> = registry
+            .list()
+            .await
+            .into_iter()
+            .map(|r| r.peer_id)
+            .collect();
+        assert_eq!(order, ["good", "flaky"]);
+    }
+
+    #[tokio::test]
+    async fn relays_are_located_and_listed_by_region() {
+        let registry = RelayRegistry::new();
+        let mut announcement = announced("announced", &["/dns4/relay.example/tcp/4001"], 100);
+        announcement.region = Some("DE".to_string());
+        registry.record_announcement(&announcement, VIA).await;
+        registry
+            .record_announcement(&announced("early", &["/ip4/1.0.0.1/tcp/4001"], 100), VIA)
+            .await;
+        registry
+            .set_geoip(GeoIpDb::from_csv(
+                "1.0.0.0,1.0.0.255,AU\n2.0.0.0,2.0.0.255,DE\n",
+            ))
+            .await;
+        assert_eq!(
+            registry.get("early").await.unwrap().country.as_deref(),
+            Some("AU")
+        );
+        registry
+            .record_announcement(
+                &announced("late", &["/ip4/2.0.0.9/udp/4001/quic-v1"], 100),
+                VIA,
+            )
+            .await;
+        let ids = |relays: Vec<RelayInfo>| -> Vec<String> {
+            relays.into_iter().map(|r| r.peer_id).collect()
+        };
+        assert_eq!(
+            ids(registry.list_by_region("de").await),
+            ["announced", "late"]
+        );
+        assert_eq!(ids(registry.list_by_region("AU").await), ["early"]);
+        assert!(registry.list_by_region("US").await.is_empty());
+    }
+}
@@ -0,0 +1,249 @@
+// Relay selection strategies
+//
+// Which known relays a node asks for reservations is a matter of taste:
+// the healthiest ones, the closest ones, a random spread so load evens out
+// across the network, or the ones in the node's own region. Each choice is a
+// `RelaySelector`; `RelayRegistry::select_relays` hands it the candidates and
+// takes back the picked relays, best first. Unless the user chose relays of
+// their own, the pick is made again every `RESELECT_INTERVAL`, so the relays
+// in use follow the scores.
+//
+// A node's region is the country part of its system locale. That is a rough
+// hint, but the only one available without asking a geo-IP service; relays
+// send theirs along in their announcements, and where the offline GeoIP
+// database places a relay's address wins over what it announced.
+
+use crate::relay_registry::RelayInfo;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::time::Duration;
+
+/// How often relays picked from the registry are picked again
+pub const RESELECT_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// Picks relays to use out of the known ones
+pub trait RelaySelector: Send + Sync {
+    /// Up to `n` of `candidates`, best first. Candidates arrive healthiest
+    /// first.
+    fn select(&self, candidates: Vec<RelayInfo>, n: usize) -> Vec<RelayInfo>;
+}
+
+/// Strategy names as the frontend sends them
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RelaySelection {
+    #[default]
+    BestHealth,
+    LowestLatency,
+    RandomWeighted,
+    RegionAffinity,
+}
+
+impl RelaySelection {
+    pub fn selector(self) -> Box<dyn RelaySelector> {
+        match self {
+            RelaySelection::BestHealth => Box::new(BestHealth),
+            RelaySelection::LowestLatency => Box::new(LowestLatency),
+            RelaySelection::RandomWeighted => Box::new(RandomWeighted::new()),
+            RelaySelection::RegionAffinity => Box::new(RegionAffinity::local()),
+        }
+    }
+}
+
+fn by_selection_score(a: &RelayInfo, b: &RelayInfo) -> Ordering {
+    b.selection_score().total_cmp(&a.selection_score())
+}
+
+/// Highest health score, weighed by spare capacity
+pub struct BestHealth;
+
+impl RelaySelector for BestHealth {
+    fn select(&self, mut candidates: Vec<RelayInfo>, n: usize) -> Vec<RelayInfo> {
+        candidates.sort_by(by_selection_score);
+        candidates.truncate(n);
+        candidates
+    }
+}
+
+/// Shortest probed round trip; relays never probed come last
+pub struct LowestLatency;
+
+impl RelaySelector for LowestLatency {
+    fn select(&self, mut candidates: Vec<RelayInfo>, n: usize) -> Vec<RelayInfo> {
+        candidates.sort_by(|a, b| match (a.last_rtt_ms, b.last_rtt_ms) {
+            (Some(a_rtt), Some(b_rtt)) => a_rtt.cmp(&b_rtt),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => by_selection_score(a, b),
+        });
+        candidates.truncate(n);
+        candidates
+    }
+}
+
+/// Random picks, with chances in proportion to the selection score, so
+/// nodes spread over the good relays instead of all taking the best one
+pub struct RandomWeighted {
+    seed: Option<u64>,
+}
+
+impl RandomWeighted {
+    pub fn new() -> Self {
+        Self { seed: None }
+    }
+
+    /// Same picks for the same seed and candidates
+    pub fn with_seed(seed: u64) -> Self {
+        Self { seed: Some(seed) }
+    }
+}
+
+impl Default for RandomWeighted {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RelaySelector for RandomWeighted {
+    fn select(&self, mut candidates: Vec<RelayInfo>, n: usize) -> Vec<RelayInfo> {
+        let mut rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let mut picked = Vec::new();
+        while picked.len() < n && !candidates.is_empty() {
+            // Relays with no standing keep a small chance
+            let weights: Vec<f64> = candidates
+                .iter()
+                .map(|relay| relay.selection_score().max(0.01))
+                .collect();
+            let mut target = rng.gen::<f64>() * weights.iter().sum::<f64>();
+            let mut index = candidates.len() - 1;
+            for (i, weight) in weights.iter().enumerate() {
+                if target < *weight {
+                    index = i;
+                    break;
+                }
+                target -= weight;
+            }
+            picked.push(candidates.swap_remove(index));
+        }
+        picked
+    }
+}
+
+/// Relays in `region` first, then the rest, best health first in both
+pub struct RegionAffinity {
+    region: Option<String>,
+}
+
+impl RegionAffinity {
+    pub fn new(region: Option<String>) -> Self {
+        Self { region }
+    }
+
+    /// Affinity for this node's own region
+    pub fn local() -> Self {
+        Self::new(local_region())
+    }
+}
+
+impl RelaySelector for RegionAffinity {
+    fn select(&self, mut candidates: Vec<RelayInfo>, n: usize) -> Vec<RelayInfo> {
+        let region = self.region.as_deref();
+        candidates.sort_by(|a, b| {
+            let a_near = region.is_some() && a.located_region() == region;
+            let b_near = region.is_some() && b.located_region() == region;
+            b_near.cmp(&a_near).then_with(|| by_selection_score(a, b))
+        });
+        candidates.truncate(n);
+        candidates
+    }
+}
+
+/// Country code of `locale` ("en-US", "en_GB.UTF-8", "zh-Hans-CN")
+pub fn region_of_locale(locale: &str) -> Option<String> {
+    locale
+        .split(['-', '_', '.', '@'])
+        .skip(1)
+        .find(|part| part.len() == 2 && part.chars().all(|c| c.is_ascii_alphabetic()))
+        .map(|part| part.to_ascii_uppercase())
+}
+
+/// This node's region, from the system locale
+pub fn local_region() -> Option<String> {
+    sys_locale::get_locale().and_then(|locale| region_of_locale(&locale))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn relay(peer_id: &str, health_score: f64, rtt: Option<u64>, region: &str) -> RelayInfo {
+        RelayInfo {
+            peer_id: peer_id.to_string(),
+            health_score,
+            last_rtt_ms: rtt,
+            region: Some(region.to_string()),
+            ..RelayInfo::default()
+        }
+    }
+
+    fn ids(relays: Vec<RelayInfo>) -> Vec<String> {
+        relays.into_iter().map(|r| r.peer_id).collect()
+    }
+
+    fn candidates() -> Vec<RelayInfo> {
+        vec![
+            relay("healthy", 30.0, Some(400), "US"),
+            relay("near", 10.0, None, "DE"),
+            relay("fast", 5.0, Some(20), "JP"),
+        ]
+    }
+
+    #[test]
+    fn each_strategy_orders_by_its_own_measure() {
+        assert_eq!(ids(BestHealth.select(candidates(), 2)), ["healthy", "near"]);
+        assert_eq!(
+            ids(LowestLatency.select(candidates(), 3)),
+            ["fast", "healthy", "near"]
+        );
+        let near_de = RegionAffinity::new(Some("DE".to_string()));
+        assert_eq!(
+            ids(near_de.select(candidates(), 3)),
+            ["near", "healthy", "fast"]
+        );
+        let nowhere = RegionAffinity::new(None);
+        assert_eq!(ids(nowhere.select(candidates(), 1)), ["healthy"]);
+    }
+
+    #[test]
+    fn random_picks_favour_healthy_relays_without_repeats() {
+        let mut first = std::collections::HashMap::new();
+        for seed in 0..200 {
+            let picked = ids(RandomWeighted::with_seed(seed).select(candidates(), 3));
+            let mut unique = picked.clone();
+            unique.sort();
+            unique.dedup();
+            assert_eq!(unique.len(), 3);
+            *first.entry(picked[0].clone()).or_insert(0) += 1;
+        }
+        assert!(first["healthy"] > first["near"]);
+        assert!(first["near"] > first["fast"]);
+        let again = RandomWeighted::with_seed(7);
+        assert_eq!(
+            ids(again.select(candidates(), 3)),
+            ids(RandomWeighted::with_seed(7).select(candidates(), 3))
+        );
+    }
+
+    #[test]
+    fn regions_come_from_the_locale_country() {
+        assert_eq!(region_of_locale("en-US").as_deref(), Some("US"));
+        assert_eq!(region_of_locale("en_gb.UTF-8").as_deref(), Some("GB"));
+        assert_eq!(region_of_locale("zh-Hans-CN").as_deref(), Some("CN"));
+        assert_eq!(region_of_locale("fr"), None);
+    }
+}
@@ -0,0 +1,345 @@
+// Injectable side effects
+//
+// Services that retry, back off or time things out read the clock, draw
+// random numbers and touch the filesystem. Hard-wiring those makes tests
+// either slow (real sleeps) or reliant on test-only hooks in production
+// code. `RuntimeEnv` bundles the three behind traits: production uses
+// `RuntimeEnv::system()`, tests build a deterministic one with a manual
+// clock that never really sleeps, a seeded RNG and a filesystem that can be
+// told to fail.
+
+use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Source of wall-clock and monotonic time, and of delays
+#[async_trait]
+pub trait Clock: Send + Sync {
+    /// Time since the Unix epoch
+    fn wall(&self) -> Duration;
+
+    /// Monotonic time since an arbitrary fixed origin, for measuring elapsed time
+    fn monotonic(&self) -> Duration;
+
+    async fn sleep(&self, duration: Duration);
+
+    fn now_secs(&self) -> u64 {
+        self.wall().as_secs()
+    }
+
+    fn now_ms(&self) -> u64 {
+        self.wall().as_millis() as u64
+    }
+}
+
+static PROCESS_START: Lazy<Instant> = Lazy::new(Instant::now);
+
+/// The real clock, backed by `SystemTime`, `Instant` and `tokio::time::sleep`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+#[async_trait]
+impl Clock for SystemClock {
+    fn wall(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+    }
+
+    fn monotonic(&self) -> Duration {
+        PROCESS_START.elapsed()
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        tokio::time::sleep(duration).await;
+    }
+}
+
+/// Virtual clock for tests. Time only moves when `advance` is called or when
+/// someone sleeps: a sleep returns immediately after moving the clock
+/// forward, so retry loops run at full speed while still observing their
+/// delays.
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    inner: Arc<Mutex<ManualClockState>>,
+}
+
+#[derive(Debug)]
+struct ManualClockState {
+    epoch: Duration,
+    elapsed: Duration,
+    slept: Vec<Duration>,
+}
+
+impl ManualClock {
+    /// Start at `epoch` (time since the Unix epoch)
+    pub fn new(epoch: Duration) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(ManualClockState {
+                epoch,
+                elapsed: Duration::ZERO,
+                slept: Vec::new(),
+            })),
+        }
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.inner.lock().unwrap().elapsed += duration;
+    }
+
+    /// Every delay passed to `sleep`, in call order
+    pub fn sleeps(&self) -> Vec<Duration> {
+        self.inner.lock().unwrap().slept.clone()
+    }
+
+    pub fn total_slept(&self) -> Duration {
+        self.inner.lock().unwrap().slept.iter().sum()
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(1_700_000_000))
+    }
+}
+
+#[async_trait]
+impl Clock for ManualClock {
+    fn wall(&self) -> Duration {
+        let state = self.inner.lock().unwrap();
+        state.epoch + state.elapsed
+    }
+
+    fn monotonic(&self) -> Duration {
+        self.inner.lock().unwrap().elapsed
+    }
+
+    async fn sleep(&self, duration: Duration) {
+        let mut state = self.inner.lock().unwrap();
+        state.elapsed += duration;
+        state.slept.push(duration);
+    }
+}
+
+/// Randomness used for jitter and tie-breaking
+pub trait RandomSource: Send + Sync {
+    /// Uniform sample in `[0, 1)`
+    fn next_f64(&self) -> f64;
+}
+
+/// Thread-local OS-seeded randomness
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ThreadRandom;
+
+impl RandomSource for ThreadRandom {
+    fn next_f64(&self) -> f64 {
+        rand::random::<f64>()
+    }
+}
+
+/// Reproducible randomness: the same seed gives the same sequence
+#[derive(Debug)]
+pub struct SeededRandom {
+    rng: Mutex<StdRng>,
+}
+
+impl SeededRandom {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+        }
+    }
+}
+
+impl RandomSource for SeededRandom {
+    fn next_f64(&self) -> f64 {
+        self.rng.lock().unwrap().gen::<f64>()
+    }
+}
+
+/// Filesystem operations whose failures tests need to script
+#[async_trait]
+pub trait FileIo: Send + Sync {
+    async fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+    async fn write(&self, path: &Path, data: &[u8]) -> io::Result<()>;
+
+    /// Bytes free for writing on the volume holding `path`, which must exist
+    async fn available_space(&self, path: &Path) -> io::Result<u64> {
+        fs2::available_space(path)
+    }
+}
+
+/// Plain `tokio::fs`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TokioFs;
+
+#[async_trait]
+impl FileIo for TokioFs {
+    async fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        tokio::fs::read(path).await
+    }
+
+    async fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        tokio::fs::write(path, data).await
+    }
+}
+
+/// Real filesystem that fails a configurable number of upcoming writes, and
+/// can pretend the disk is nearly full
+#[derive(Debug, Default)]
+pub struct FlakyFs {
+    failing_writes: AtomicU32,
+    writes: AtomicU32,
+    free_space: Mutex<Option<u64>>,
+}
+
+impl FlakyFs {
+    pub fn failing_writes(count: u32) -> Self {
+        Self {
+            failing_writes: AtomicU32::new(count),
+            ..Self::default()
+        }
+    }
+
+    /// Report `bytes` free on every volume; `None` reports the real space
+    pub fn set_free_space(&self, bytes: Option<u64>) {
+        *self.free_space.lock().unwrap() = bytes;
+    }
+
+    pub fn fail_next_writes(&self, count: u32) {
+        self.failing_writes.store(count, Ordering::SeqCst);
+    }
+
+    /// Writes attempted so far, failed ones included
+    pub fn write_count(&self) -> u32 {
+        self.writes.load(Ordering::SeqCst)
+    }
+}
+
+#[async_trait]
+impl FileIo for FlakyFs {
+    async fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        tokio::fs::read(path).await
+    }
+
+    async fn write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        self.writes.fetch_add(1, Ordering::SeqCst);
+        let failed = self
+            .failing_writes
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok();
+        if failed {
+            return Err(io::Error::other("simulated write failure"));
+        }
+        tokio::fs::write(path, data).await
+    }
+
+    async fn available_space(&self, path: &Path) -> io::Result<u64> {
+        match *self.free_space.lock().unwrap() {
+            Some(bytes) => Ok(bytes),
+            None => fs2::available_space(path),
+        }
+    }
+}
+
+/// Clock, randomness and filesystem handed to a service at construction
+#[derive(Clone)]
+pub struct RuntimeEnv {
+    pub clock: Arc<dyn Clock>,
+    pub random: Arc<dyn RandomSource>,
+    pub fs: Arc<dyn FileIo>,
+}
+
+impl RuntimeEnv {
+    pub fn system() -> Self {
+        Self {
+            clock: Arc::new(SystemClock),
+            random: Arc::new(ThreadRandom),
+            fs: Arc::new(TokioFs),
+        }
+    }
+
+    /// Manual clock, seeded RNG and the real filesystem
+    pub fn deterministic(seed: u64) -> Self {
+        Self {
+            clock: Arc::new(ManualClock::default()),
+            random: Arc::new(SeededRandom::new(seed)),
+            fs: Arc::new(TokioFs),
+        }
+    }
+
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn with_random(mut self, random: Arc<dyn RandomSource>) -> Self {
+        self.random = random;
+        self
+    }
+
+    pub fn with_fs(mut self, fs: Arc<dyn FileIo>) -> Self {
+        self.fs = fs;
+        self
+    }
+}
+
+impl Default for RuntimeEnv {
+    fn default() -> Self {
+        Self::system()
+    }
+}
+
+impl std::fmt::Debug for RuntimeEnv {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RuntimeEnv").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn manual_clock_sleep_advances_without_waiting() {
+        let clock = ManualClock::new(Duration::from_secs(100));
+        let started = Instant::now();
+        clock.sleep(Duration::from_secs(3600)).await;
+        clock.advance(Duration::from_millis(500));
+
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(clock.now_secs(), 3_700);
+        assert_eq!(clock.monotonic(), Duration::from_millis(3_600_500));
+        assert_eq!(clock.sleeps(), vec![Duration::from_secs(3600)]);
+    }
+
+    #[test]
+    fn seeded_random_is_reproducible() {
+        let a = SeededRandom::new(7);
+        let b = SeededRandom::new(7);
+        for _ in 0..16 {
+            let x = a.next_f64();
+            assert_eq!(x, b.next_f64());
+            assert!((0.0..1.0).contains(&x));
+        }
+    }
+
+    #[tokio::test]
+    async fn flaky_fs_fails_only_the_configured_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out");
+        let fs = FlakyFs::failing_writes(2);
+
+        assert!(fs.write(&path, b"a").await.is_err());
+        assert!(fs.write(&path, b"b").await.is_err());
+        fs.write(&path, b"c").await.unwrap();
+        assert_eq!(fs.read(&path).await.unwrap(), b"c");
+        assert_eq!(fs.write_count(), 3);
+    }
+}
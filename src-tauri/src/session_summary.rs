@@ -0,0 +1,237 @@
+// Per-session summaries
+//
+// A `SessionTracker` counts finished downloads from the unified event bus
+// while the node runs. On shutdown the caller combines those counts with
+// bandwidth and network totals into a `SessionSummary`, which is appended to
+// a small on-disk history so the UI can show a "last session" card.
+
+use crate::event_bus;
+use crate::runtime_env::{Clock, SystemClock};
+use crate::transfer_log::{entry_from_envelope, TransferDirection, TransferOutcome};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use tracing::{debug, warn};
+
+/// Summaries kept on disk
+pub const MAX_SESSIONS: usize = 20;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionSummary {
+    /// Unix timestamps (seconds)
+    pub started_at: u64,
+    pub ended_at: u64,
+    pub duration_secs: u64,
+    pub bytes_served: u64,
+    pub bytes_downloaded: u64,
+    pub unique_peers: usize,
+    pub downloads_completed: u64,
+    pub downloads_failed: u64,
+    pub relay_minutes_provided: f64,
+}
+
+/// Network and bandwidth totals the tracker cannot observe itself
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SessionTotals {
+    pub bytes_served: u64,
+    pub bytes_downloaded: u64,
+    pub unique_peers: usize,
+    pub relay_circuit_secs: u64,
+}
+
+#[derive(Debug, Default)]
+struct DownloadCounts {
+    completed: u64,
+    failed: u64,
+    /// Transfers already counted; a failure can be reported by several
+    /// services under the same transfer ID
+    finished: HashSet<String>,
+}
+
+pub struct SessionTracker {
+    started_at: u64,
+    downloads: Mutex<DownloadCounts>,
+    history: Mutex<VecDeque<SessionSummary>>,
+    persist_path: Option<PathBuf>,
+    clock: Arc<dyn Clock>,
+}
+
+impl SessionTracker {
+    /// Tracker for a session starting now; history is not persisted
+    pub fn new() -> Self {
+        Self {
+            started_at: SystemClock.now_secs(),
+            downloads: Mutex::new(DownloadCounts::default()),
+            history: Mutex::new(VecDeque::new()),
+            persist_path: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Read the time from `clock`; the session starts at its current time
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.started_at = clock.now_secs();
+        self.clock = clock;
+        self
+    }
+
+    /// Tracker whose summary history is loaded from and saved to `path`
+    pub fn with_persistence(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let history = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|raw| serde_json::from_str::<VecDeque<SessionSummary>>(&raw).ok())
+            .unwrap_or_default();
+        Self {
+            history: Mutex::new(history),
+            persist_path: Some(path),
+            ..Self::new()
+        }
+    }
+
+    /// Count a finished download. `transfer_id` deduplicates reports of the
+    /// same transfer.
+    pub fn record_download(&self, transfer_id: Option<&str>, completed: bool) {
+        let Ok(mut counts) = self.downloads.lock() else {
+            return;
+        };
+        if let Some(id) = transfer_id {
+            if !counts.finished.insert(id.to_string()) {
+                return;
+            }
+        }
+        if completed {
+            counts.completed += 1;
+        } else {
+            counts.failed += 1;
+        }
+    }
+
+    /// Summary of the session so far, ending at `ended_at`
+    pub fn summarize(&self, ended_at: u64, totals: SessionTotals) -> SessionSummary {
+        let (completed, failed) = self
+            .downloads
+            .lock()
+            .map(|c| (c.completed, c.failed))
+            .unwrap_or_default();
+        SessionSummary {
+            started_at: self.started_at,
+            ended_at,
+            duration_secs: ended_at.saturating_sub(self.started_at),
+            bytes_served: totals.bytes_served,
+            bytes_downloaded: totals.bytes_downloaded,
+            unique_peers: totals.unique_peers,
+            downloads_completed: completed,
+            downloads_failed: failed,
+            relay_minutes_provided: totals.relay_circuit_secs as f64 / 60.0,
+        }
+    }
+
+    /// Summarize the session ending now and append it to the history
+    pub fn finish(&self, totals: SessionTotals) -> Result<SessionSummary, String> {
+        let summary = self.summarize(self.clock.now_secs(), totals);
+        let mut history = self
+            .history
+            .lock()
+            .map_err(|_| "Session history lock poisoned".to_string())?;
+        history.push_front(summary.clone());
+        history.truncate(MAX_SESSIONS);
+
+        if let Some(path) = &self.persist_path {
+            let json = serde_json::to_string_pretty(&*history)
+                .map_err(|e| format!("Failed to serialize session history: {}", e))?;
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| format!("Failed to create data directory: {}", e))?;
+            }
+            std::fs::write(path, json)
+                .map_err(|e| format!("Failed to save session history: {}", e))?;
+        }
+        Ok(summary)
+    }
+
+    /// Most recent finished sessions, newest first
+    pub fn recent(&self, limit: usize) -> Vec<SessionSummary> {
+        self.history
+            .lock()
+            .map(|h| h.iter().take(limit).cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Count finished downloads published on the event bus
+    pub fn spawn_event_counter(self: Arc<Self>) {
+        tokio::spawn(async move {
+            let mut rx = event_bus::global().subscribe();
+            loop {
+                match rx.recv().await {
+                    Ok(envelope) => {
+                        let Some(entry) = entry_from_envelope(&envelope) else {
+                            continue;
+                        };
+                        if entry.direction != TransferDirection::Download {
+                            continue;
+                        }
+                        match entry.outcome {
+                            TransferOutcome::Completed => {
+                                self.record_download(entry.transfer_id.as_deref(), true)
+                            }
+                            TransferOutcome::Failed => {
+                                self.record_download(entry.transfer_id.as_deref(), false)
+                            }
+                            _ => {}
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("Session tracker lagged, {} events not counted", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => {
+                        debug!("Event bus closed, stopping session tracker");
+                        break;
+                    }
+                }
+            }
+        });
+    }
+}
+
+impl Default for SessionTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_counts_each_transfer_once() {
+        let tracker = SessionTracker::new();
+        tracker.record_download(Some("a"), true);
+        tracker.record_download(Some("b"), false);
+        tracker.record_download(Some("b"), false);
+        tracker.record_download(None, false);
+
+        let summary = tracker.summarize(
+            tracker.started_at + 600,
+            SessionTotals {
+                bytes_served: 2_048,
+                bytes_downloaded: 1_024,
+                unique_peers: 7,
+                relay_circuit_secs: 90,
+            },
+        );
+        assert_eq!(summary.duration_secs, 600);
+        assert_eq!(summary.downloads_completed, 1);
+        assert_eq!(summary.downloads_failed, 2);
+        assert_eq!(summary.relay_minutes_provided, 1.5);
+
+        for _ in 0..MAX_SESSIONS + 3 {
+            tracker.finish(SessionTotals::default()).unwrap();
+        }
+        assert_eq!(tracker.recent(100).len(), MAX_SESSIONS);
+    }
+}
@@ -0,0 +1,414 @@
+// Backend-owned application settings
+//
+// settings.json in the app data directory is the single source of truth for
+// both the GUI and headless mode. The fields the backend acts on are typed and
+// validated here; everything else the frontend stores is kept verbatim in
+// `extra` so a save never drops settings this module does not know about.
+//
+// Files carry a `schemaVersion`. Older files are migrated step by step on load
+// and written back in the current format. Every successful change is broadcast
+// as a `SettingsChangedEvent` listing the keys that changed.
+
+use crate::file_transfer::control::DEFAULT_MAX_CONCURRENT;
+use crate::file_transfer::naming::{self, CollisionPolicy, NamingRules};
+use crate::file_transfer::AttemptRetention;
+use crate::relay_registry::PruneConfig;
+use libp2p::Multiaddr;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::warn;
+
+pub const SCHEMA_VERSION: u32 = 1;
+/// Tauri bundle identifier; the GUI's `app_data_dir()` is `<data dir>/<identifier>`
+const APP_IDENTIFIER: &str = "com.chiralnetwork";
+const SETTINGS_FILE: &str = "settings.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AppSettings {
+    pub schema_version: u32,
+    /// Download directory; empty means the platform default
+    pub storage_path: String,
+    /// File name given to downloads saved to the download directory, see
+    /// `file_transfer::naming`
+    pub download_name_template: String,
+    /// What to do when a download's file name is already taken
+    pub download_collision: CollisionPolicy,
+    /// GB
+    pub max_storage_size: u64,
+    pub auto_cleanup: bool,
+    /// Percent of `max_storage_size` that triggers cleanup
+    pub cleanup_threshold: u64,
+    /// MB
+    pub cache_size: u64,
+    pub port: u16,
+    pub enable_file_logging: bool,
+    #[serde(rename = "maxLogSizeMB")]
+    pub max_log_size_mb: u64,
+    /// Multiaddrs used instead of the built-in bootstrap nodes when non-empty
+    pub custom_bootstrap_nodes: Vec<String>,
+    /// Minutes between passes pruning stale relays from the relay registry
+    pub relay_prune_interval_mins: u64,
+    /// Relays unheard of for this many days are pruned
+    pub relay_max_age_days: u64,
+    /// How much download attempt history is kept
+    pub download_attempt_retention: AttemptRetention,
+    /// Downloads run at once; the rest wait in the queue
+    pub max_concurrent_transfers: usize,
+    /// Frontend-only settings, preserved as-is
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Default for AppSettings {
+    fn default() -> Self {
+        Self {
+            schema_version: SCHEMA_VERSION,
+            storage_path: String::new(),
+            download_name_template: naming::DEFAULT_TEMPLATE.to_string(),
+            download_collision: CollisionPolicy::default(),
+            max_storage_size: 100,
+            auto_cleanup: true,
+            cleanup_threshold: 90,
+            cache_size: 1024,
+            port: 30303,
+            enable_file_logging: false,
+            max_log_size_mb: 10,
+            custom_bootstrap_nodes: Vec::new(),
+            relay_prune_interval_mins: 60,
+            relay_max_age_days: 30,
+            download_attempt_retention: AttemptRetention::default(),
+            max_concurrent_transfers: DEFAULT_MAX_CONCURRENT,
+            extra: Map::new(),
+        }
+    }
+}
+
+impl AppSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.port == 0 {
+            return Err("Port must be between 1 and 65535".to_string());
+        }
+        if !(1..=100).contains(&self.cleanup_threshold) {
+            return Err("Cleanup threshold must be between 1 and 100".to_string());
+        }
+        if self.max_storage_size == 0 {
+            return Err("Max storage size must be at least 1 GB".to_string());
+        }
+        if self.max_log_size_mb == 0 {
+            return Err("Max log size must be at least 1 MB".to_string());
+        }
+        if self.relay_prune_interval_mins == 0 {
+            return Err("Relay prune interval must be at least 1 minute".to_string());
+        }
+        if self.relay_max_age_days == 0 {
+            return Err("Relay max age must be at least 1 day".to_string());
+        }
+        if self.max_concurrent_transfers == 0 {
+            return Err("At least one transfer must be allowed to run".to_string());
+        }
+        naming::validate_template(&self.download_name_template)?;
+        for node in &self.custom_bootstrap_nodes {
+            node.parse::<Multiaddr>()
+                .map_err(|e| format!("Invalid bootstrap node '{}': {}", node, e))?;
+        }
+        Ok(())
+    }
+
+    pub fn relay_prune_config(&self) -> PruneConfig {
+        PruneConfig {
+            interval: Duration::from_secs(self.relay_prune_interval_mins * 60),
+            max_age_secs: self.relay_max_age_days * 24 * 60 * 60,
+        }
+    }
+
+    pub fn naming_rules(&self) -> NamingRules {
+        NamingRules {
+            template: self.download_name_template.clone(),
+            collision: self.download_collision,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsChangedEvent {
+    /// Top-level keys whose values changed
+    pub changed_keys: Vec<String>,
+    pub settings: AppSettings,
+}
+
+/// Where settings live, shared by the GUI and headless mode
+pub fn default_settings_path() -> Option<PathBuf> {
+    directories::BaseDirs::new()
+        .map(|dirs| dirs.data_dir().join(APP_IDENTIFIER).join(SETTINGS_FILE))
+}
+
+/// Bring a settings object from any older schema up to `SCHEMA_VERSION`.
+/// Returns whether anything was migrated.
+pub fn migrate(value: &mut Value) -> bool {
+    let Some(object) = value.as_object_mut() else {
+        return false;
+    };
+    let mut version = object
+        .get("schemaVersion")
+        .and_then(Value::as_u64)
+        .unwrap_or(0) as u32;
+    let migrated = version < SCHEMA_VERSION;
+
+    while version < SCHEMA_VERSION {
+        if version == 0 {
+            migrate_v0(object);
+        }
+        version += 1;
+    }
+    if migrated {
+        object.insert("schemaVersion".to_string(), Value::from(SCHEMA_VERSION));
+    }
+    migrated
+}
+
+/// v0 (frontend-owned) files: bootstrap nodes could be saved as one
+/// comma/newline separated string, with blanks and duplicates
+fn migrate_v0(object: &mut Map<String, Value>) {
+    let nodes: Vec<String> = match object.get("customBootstrapNodes") {
+        Some(Value::String(raw)) => raw
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .map(str::to_string)
+            .collect(),
+        Some(Value::Array(items)) => items
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        _ => return,
+    };
+    let mut cleaned: Vec<String> = Vec::new();
+    for node in nodes {
+        let node = node.trim();
+        if !node.is_empty() && !cleaned.iter().any(|n| n == node) {
+            cleaned.push(node.to_string());
+        }
+    }
+    object.insert("customBootstrapNodes".to_string(), Value::from(cleaned));
+}
+
+fn parse_settings(mut value: Value) -> Result<(AppSettings, bool), String> {
+    let migrated = migrate(&mut value);
+    let settings = serde_json::from_value::<AppSettings>(value)
+        .map_err(|e| format!("Failed to parse settings: {}", e))?;
+    Ok((settings, migrated))
+}
+
+fn changed_keys(old: &AppSettings, new: &AppSettings) -> Vec<String> {
+    let (Ok(Value::Object(old)), Ok(Value::Object(new))) =
+        (serde_json::to_value(old), serde_json::to_value(new))
+    else {
+        return Vec::new();
+    };
+    let mut keys: Vec<String> = new
+        .iter()
+        .filter(|(key, value)| old.get(*key) != Some(*value))
+        .map(|(key, _)| key.clone())
+        .chain(old.keys().filter(|key| !new.contains_key(*key)).cloned())
+        .collect();
+    keys.sort();
+    keys
+}
+
+/// Shared, optionally persisted application settings
+pub struct SettingsStore {
+    settings: Mutex<AppSettings>,
+    persist_path: Option<PathBuf>,
+    /// Whether settings were loaded from disk or saved since startup
+    saved: AtomicBool,
+    changes: broadcast::Sender<SettingsChangedEvent>,
+}
+
+impl SettingsStore {
+    pub fn new() -> Self {
+        Self::from_settings(AppSettings::default(), None, false)
+    }
+
+    fn from_settings(settings: AppSettings, persist_path: Option<PathBuf>, saved: bool) -> Self {
+        let (changes, _) = broadcast::channel(16);
+        Self {
+            settings: Mutex::new(settings),
+            persist_path,
+            saved: AtomicBool::new(saved),
+            changes,
+        }
+    }
+
+    /// Settings loaded from and saved to `path`. Older schemas are migrated
+    /// and written back; an unreadable file falls back to the defaults.
+    pub fn with_persistence(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let loaded = std::fs::read_to_string(&path).ok().map(|raw| {
+            serde_json::from_str::<Value>(&raw)
+                .map_err(|e| format!("Failed to parse settings: {}", e))
+                .and_then(parse_settings)
+        });
+        let (settings, migrated, saved) = match loaded {
+            Some(Ok((settings, migrated))) => (settings, migrated, true),
+            Some(Err(e)) => {
+                warn!("Ignoring settings at {}: {}", path.display(), e);
+                (AppSettings::default(), false, false)
+            }
+            None => (AppSettings::default(), false, false),
+        };
+
+        let store = Self::from_settings(settings, Some(path), saved);
+        if migrated {
+            if let Err(e) = store.persist(&store.get()) {
+                warn!("Failed to save migrated settings: {}", e);
+            }
+        }
+        store
+    }
+
+    /// Store at `default_settings_path()`, or in memory only when the data
+    /// directory cannot be determined
+    pub fn load_default() -> Self {
+        default_settings_path()
+            .map(Self::with_persistence)
+            .unwrap_or_default()
+    }
+
+    pub fn get(&self) -> AppSettings {
+        self.settings
+            .lock()
+            .map(|s| s.clone())
+            .unwrap_or_default()
+    }
+
+    /// The stored settings, or None while only the defaults exist (nothing
+    /// has been saved yet)
+    pub fn saved(&self) -> Option<AppSettings> {
+        self.saved.load(Ordering::Relaxed).then(|| self.get())
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<SettingsChangedEvent> {
+        self.changes.subscribe()
+    }
+
+    /// Replace all settings with `value` (a full settings object, any schema
+    /// version)
+    pub fn replace(&self, value: Value) -> Result<AppSettings, String> {
+        let (settings, _) = parse_settings(value)?;
+        self.apply(settings)
+    }
+
+    /// Merge the top-level keys of `patch` into the current settings
+    pub fn update(&self, patch: Value) -> Result<AppSettings, String> {
+        let Value::Object(patch) = patch else {
+            return Err("Settings update must be a JSON object".to_string());
+        };
+        let mut current = match serde_json::to_value(self.get()) {
+            Ok(Value::Object(current)) => current,
+            _ => return Err("Failed to serialize settings".to_string()),
+        };
+        current.extend(patch);
+        self.replace(Value::Object(current))
+    }
+
+    fn apply(&self, mut settings: AppSettings) -> Result<AppSettings, String> {
+        settings.schema_version = SCHEMA_VERSION;
+        settings.validate()?;
+        self.persist(&settings)?;
+        self.saved.store(true, Ordering::Relaxed);
+
+        let previous = {
+            let mut current = self
+                .settings
+                .lock()
+                .map_err(|_| "Settings lock poisoned".to_string())?;
+            std::mem::replace(&mut *current, settings.clone())
+        };
+        let changed_keys = changed_keys(&previous, &settings);
+        if !changed_keys.is_empty() {
+            // No subscribers is fine
+            let _ = self.changes.send(SettingsChangedEvent {
+                changed_keys,
+                settings: settings.clone(),
+            });
+        }
+        Ok(settings)
+    }
+
+    fn persist(&self, settings: &AppSettings) -> Result<(), String> {
+        let Some(path) = &self.persist_path else {
+            return Ok(());
+        };
+        let json = serde_json::to_string_pretty(settings)
+            .map_err(|e| format!("Failed to serialize settings: {}", e))?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create app data directory: {}", e))?;
+        }
+        std::fs::write(path, json).map_err(|e| format!("Failed to write settings file: {}", e))
+    }
+}
+
+impl Default for SettingsStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn migrates_validates_and_reports_changes() {
+        let store = SettingsStore::new();
+        let mut changes = store.subscribe();
+        assert!(store.saved().is_none());
+
+        // A v0 file from the frontend, with a key this module does not model
+        let legacy = json!({
+            "port": 4001,
+            "theme": "dark",
+            "customBootstrapNodes": "/ip4/1.2.3.4/tcp/1, ,/ip4/1.2.3.4/tcp/1\n/dns4/a.io/tcp/1",
+        });
+        let settings = store.replace(legacy).unwrap();
+        assert_eq!(settings.schema_version, SCHEMA_VERSION);
+        assert_eq!(
+            settings.custom_bootstrap_nodes,
+            vec!["/ip4/1.2.3.4/tcp/1", "/dns4/a.io/tcp/1"]
+        );
+        assert_eq!(settings.extra["theme"], "dark");
+        assert_eq!(store.saved(), Some(settings.clone()));
+        let event = changes.try_recv().unwrap();
+        assert!(event.changed_keys.contains(&"customBootstrapNodes".to_string()));
+        assert!(event.changed_keys.contains(&"theme".to_string()));
+
+        let updated = store.update(json!({ "cacheSize": 2048 })).unwrap();
+        assert_eq!(updated.cache_size, 2048);
+        assert_eq!(updated.port, 4001);
+        assert_eq!(updated.extra["theme"], "dark");
+        assert_eq!(changes.try_recv().unwrap().changed_keys, vec!["cacheSize"]);
+
+        assert!(store.update(json!({ "customBootstrapNodes": ["nope"] })).is_err());
+        assert!(store.update(json!({ "cleanupThreshold": 0 })).is_err());
+        assert!(store.update(json!({ "relayMaxAgeDays": 0 })).is_err());
+        assert!(store.update(json!({ "maxConcurrentTransfers": 0 })).is_err());
+        assert!(store
+            .update(json!({ "downloadNameTemplate": "{title}" }))
+            .is_err());
+        assert_eq!(store.get(), updated);
+        assert!(changes.try_recv().is_err());
+
+        let retention =
+            json!({ "downloadAttemptRetention": { "maxEntries": 5, "maxAgeSecs": 60 } });
+        let updated = store.update(retention).unwrap();
+        assert_eq!(updated.download_attempt_retention.max_age_secs, Some(60));
+    }
+}
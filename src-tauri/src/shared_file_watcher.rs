@@ -0,0 +1,319 @@
+// Watcher for files shared in place
+//
+// Files shared in place are indexed at their own path instead of being copied
+// into the store, so editing or deleting one after sharing silently changes
+// what peers receive. Every such share is registered here with the file's
+// size, modification time and content hash; copied uploads are not, since the
+// store keeps its own bytes. A periodic scan compares each file against that
+// baseline: files whose size or mtime moved are re-hashed, and real content
+// changes or deletions are reported so the caller can republish or retract
+// the announcement. Each change is also published on the unified event bus.
+// The watch list is saved, so shares stay watched across restarts.
+
+use crate::event_bus::{self, EventPayload, EventSeverity, EventSource};
+use crate::file_transfer::hashing::HashAlgorithm;
+use crate::persisted;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, UNIX_EPOCH};
+use tracing::warn;
+
+/// How often shared files are checked
+pub const SCAN_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SharedFile {
+    /// Hash the file is announced under
+    pub file_hash: String,
+    pub path: PathBuf,
+    pub file_name: String,
+    pub size: u64,
+    /// Unix timestamp (milliseconds) of the last modification
+    pub modified_ms: u64,
+    /// SHA-256 of the file contents
+    pub content_hash: String,
+}
+
+/// A change detected by `SharedFileWatcher::scan`
+#[derive(Debug, Clone, PartialEq)]
+pub enum SharedFileChange {
+    Modified {
+        file: SharedFile,
+        new_content_hash: String,
+        new_size: u64,
+    },
+    Deleted {
+        file: SharedFile,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SharedFileEvent {
+    Modified {
+        file_hash: String,
+        path: String,
+        old_content_hash: String,
+        new_content_hash: String,
+    },
+    Deleted {
+        file_hash: String,
+        path: String,
+    },
+    /// The changed file is shared again with its new contents
+    Republished {
+        old_file_hash: String,
+        new_file_hash: String,
+        path: String,
+    },
+    /// The announcement was withdrawn
+    Retracted {
+        file_hash: String,
+        path: String,
+        reason: String,
+    },
+}
+
+impl SharedFileEvent {
+    fn file_hash(&self) -> &str {
+        match self {
+            SharedFileEvent::Modified { file_hash, .. }
+            | SharedFileEvent::Deleted { file_hash, .. }
+            | SharedFileEvent::Retracted { file_hash, .. } => file_hash,
+            SharedFileEvent::Republished { old_file_hash, .. } => old_file_hash,
+        }
+    }
+}
+
+/// Publish a shared-file change on the unified event bus
+pub fn publish(event: SharedFileEvent) -> u64 {
+    let severity = match event {
+        SharedFileEvent::Modified { .. } | SharedFileEvent::Republished { .. } => {
+            EventSeverity::Info
+        }
+        SharedFileEvent::Deleted { .. } | SharedFileEvent::Retracted { .. } => {
+            EventSeverity::Warning
+        }
+    };
+    event_bus::global().publish(
+        EventSource::SharedFile,
+        severity,
+        Some(event.file_hash().to_string()),
+        EventPayload::SharedFile(event),
+    )
+}
+
+fn modified_ms(metadata: &std::fs::Metadata) -> u64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[derive(Default)]
+pub struct SharedFileWatcher {
+    files: Mutex<HashMap<String, SharedFile>>,
+    persist_path: Option<PathBuf>,
+}
+
+impl SharedFileWatcher {
+    /// In-memory watch list; nothing is saved
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Watch list loaded from and saved to `path`
+    pub fn with_persistence(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let files: Vec<SharedFile> = persisted::load(&path);
+        Self {
+            files: Mutex::new(
+                files
+                    .into_iter()
+                    .map(|file| (file.file_hash.clone(), file))
+                    .collect(),
+            ),
+            persist_path: Some(path),
+        }
+    }
+
+    /// Change the watch list and save it
+    fn update<T>(&self, f: impl FnOnce(&mut HashMap<String, SharedFile>) -> T) -> Option<T> {
+        let mut files = self.files.lock().ok()?;
+        let result = f(&mut files);
+        if let Some(path) = &self.persist_path {
+            let mut list: Vec<&SharedFile> = files.values().collect();
+            list.sort_by(|a, b| a.path.cmp(&b.path));
+            if let Err(e) = persisted::save(path, &list) {
+                warn!("Failed to save watched shared files: {}", e);
+            }
+        }
+        Some(result)
+    }
+
+    /// Start watching `path`, announced under `file_hash`
+    pub async fn register(
+        &self,
+        file_hash: String,
+        path: impl Into<PathBuf>,
+        file_name: String,
+    ) -> Result<(), String> {
+        let path = path.into();
+        let metadata = tokio::fs::metadata(&path)
+            .await
+            .map_err(|e| format!("Failed to read file metadata: {}", e))?;
+        let content_hash = HashAlgorithm::Sha256.hash_file(&path).await?.to_string();
+        let file = SharedFile {
+            file_hash: file_hash.clone(),
+            path,
+            file_name,
+            size: metadata.len(),
+            modified_ms: modified_ms(&metadata),
+            content_hash,
+        };
+        self.update(|files| files.insert(file_hash, file));
+        Ok(())
+    }
+
+    pub fn unregister(&self, file_hash: &str) -> Option<SharedFile> {
+        self.update(|files| files.remove(file_hash)).flatten()
+    }
+
+    pub fn list(&self) -> Vec<SharedFile> {
+        let Ok(files) = self.files.lock() else {
+            return Vec::new();
+        };
+        let mut list: Vec<SharedFile> = files.values().cloned().collect();
+        list.sort_by(|a, b| a.path.cmp(&b.path));
+        list
+    }
+
+    /// Check every watched file once. Changed and deleted files stop being
+    /// watched; the caller re-registers whatever it shares again.
+    pub async fn scan(&self) -> Vec<SharedFileChange> {
+        let mut changes = Vec::new();
+        for file in self.list() {
+            let metadata = match tokio::fs::metadata(&file.path).await {
+                Ok(metadata) if metadata.is_file() => metadata,
+                Ok(_) => {
+                    self.unregister(&file.file_hash);
+                    changes.push(SharedFileChange::Deleted { file });
+                    continue;
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    self.unregister(&file.file_hash);
+                    changes.push(SharedFileChange::Deleted { file });
+                    continue;
+                }
+                // Transient errors (permissions, unmounted drive): try again later
+                Err(_) => continue,
+            };
+
+            let new_size = metadata.len();
+            let new_modified = modified_ms(&metadata);
+            if new_size == file.size && new_modified == file.modified_ms {
+                continue;
+            }
+            let new_content_hash = HashAlgorithm::Sha256.hash_file(&file.path).await;
+            let Ok(new_content_hash) = new_content_hash.map(|hash| hash.to_string()) else {
+                continue;
+            };
+            if new_content_hash == file.content_hash {
+                // Touched but unchanged
+                self.update(|files| {
+                    if let Some(entry) = files.get_mut(&file.file_hash) {
+                        entry.modified_ms = new_modified;
+                    }
+                });
+                continue;
+            }
+            self.unregister(&file.file_hash);
+            changes.push(SharedFileChange::Modified {
+                file,
+                new_content_hash,
+                new_size,
+            });
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persisted::TempConfig;
+    use std::time::SystemTime;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn reports_content_changes_and_deletions() {
+        let dir = tempdir().unwrap();
+        let edited = dir.path().join("edited.txt");
+        let touched = dir.path().join("touched.txt");
+        let removed = dir.path().join("removed.txt");
+        for path in [&edited, &touched, &removed] {
+            std::fs::write(path, b"original").unwrap();
+        }
+
+        let watcher = SharedFileWatcher::new();
+        for (hash, path) in [("a", &edited), ("b", &touched), ("c", &removed)] {
+            watcher
+                .register(hash.into(), path.clone(), "f".into())
+                .await
+                .unwrap();
+        }
+        assert!(watcher.scan().await.is_empty());
+
+        std::fs::write(&edited, b"changed contents").unwrap();
+        // Same bytes, new mtime
+        let later = SystemTime::now() + Duration::from_secs(5);
+        std::fs::File::options()
+            .write(true)
+            .open(&touched)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        std::fs::remove_file(&removed).unwrap();
+
+        let changes = watcher.scan().await;
+        assert_eq!(changes.len(), 2);
+        assert!(changes.iter().any(|c| matches!(
+            c,
+            SharedFileChange::Modified { file, new_size: 16, .. } if file.file_hash == "a"
+        )));
+        assert!(changes.iter().any(|c| matches!(
+            c,
+            SharedFileChange::Deleted { file } if file.file_hash == "c"
+        )));
+
+        // Only the touched file is still watched
+        let remaining = watcher.list();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].file_hash, "b");
+        assert!(watcher.scan().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn watch_list_survives_a_restart() {
+        let config = TempConfig::new();
+        let shared = config.dir().join("clip.mp4");
+        std::fs::write(&shared, b"frames").unwrap();
+        let saved = &config.path;
+
+        let watcher = SharedFileWatcher::with_persistence(saved);
+        watcher
+            .register("a".into(), shared.clone(), "clip.mp4".into())
+            .await
+            .unwrap();
+        let restored = SharedFileWatcher::with_persistence(saved);
+        assert_eq!(restored.list(), watcher.list());
+
+        restored.unregister("a");
+        assert!(SharedFileWatcher::with_persistence(saved).list().is_empty());
+    }
+}
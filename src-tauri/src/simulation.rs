@@ -0,0 +1,493 @@
+// In-process multi-node simulation
+//
+// Swarm downloads, relay failover and similar multi-peer logic are hard to
+// test against real sockets: timing differs between runs and failures are
+// hard to script. This harness runs N nodes in one process on a virtual
+// clock. Nodes are `NodeBehavior` implementations exchanging messages over
+// simulated links with latency, jitter and loss; faults (crashes, restarts,
+// slow nodes, degraded links) are scheduled up front or generated as churn.
+//
+// Everything random comes from one seeded RNG and events at the same instant
+// run in scheduling order, so a seed always produces the same run. No real
+// time passes: an hour of simulated traffic runs in milliseconds.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::time::Duration;
+
+pub type NodeId = usize;
+
+/// Delivery characteristics of a directed link
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkProfile {
+    pub latency: Duration,
+    /// Random extra delay of up to this much per message
+    pub jitter: Duration,
+    /// Probability in `[0, 1]` that a message is lost
+    pub loss: f64,
+}
+
+impl LinkProfile {
+    pub fn new(latency: Duration) -> Self {
+        Self {
+            latency,
+            jitter: Duration::ZERO,
+            loss: 0.0,
+        }
+    }
+
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn with_loss(mut self, loss: f64) -> Self {
+        self.loss = loss.clamp(0.0, 1.0);
+        self
+    }
+}
+
+impl Default for LinkProfile {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(20))
+    }
+}
+
+/// Scripted change to the simulated network
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    /// The node crashes: pending timers are dropped and messages to it lost
+    Down(NodeId),
+    /// The node restarts and `NodeBehavior::on_up` runs
+    Up(NodeId),
+    /// Replace the profile of the link in both directions
+    Link(NodeId, NodeId, LinkProfile),
+    /// Extra delay on everything the node sends, e.g. a slow seeder
+    Slow(NodeId, Duration),
+}
+
+/// What a node can do while handling an event
+pub struct SimContext<'a, M> {
+    node: NodeId,
+    now: Duration,
+    rng: &'a mut StdRng,
+    actions: Vec<Action<M>>,
+}
+
+enum Action<M> {
+    Send(NodeId, M),
+    Timer(Duration, u64),
+}
+
+impl<M> SimContext<'_, M> {
+    pub fn node(&self) -> NodeId {
+        self.node
+    }
+
+    /// Virtual time since the simulation started
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    /// The simulation's RNG; draw from it rather than `thread_rng` to keep
+    /// runs reproducible
+    pub fn rng(&mut self) -> &mut StdRng {
+        &mut *self.rng
+    }
+
+    pub fn send(&mut self, to: NodeId, message: M) {
+        self.actions.push(Action::Send(to, message));
+    }
+
+    /// Call `on_timer` with `tag` after `delay`, unless the node goes down
+    pub fn set_timer(&mut self, delay: Duration, tag: u64) {
+        self.actions.push(Action::Timer(delay, tag));
+    }
+}
+
+/// A simulated node. State the test wants to inspect afterwards can be kept
+/// behind an `Arc` shared with the test.
+pub trait NodeBehavior<M> {
+    fn on_start(&mut self, ctx: &mut SimContext<'_, M>);
+
+    fn on_message(&mut self, ctx: &mut SimContext<'_, M>, from: NodeId, message: M);
+
+    fn on_timer(&mut self, _ctx: &mut SimContext<'_, M>, _tag: u64) {}
+
+    /// Restart after a crash; starts over by default
+    fn on_up(&mut self, ctx: &mut SimContext<'_, M>) {
+        self.on_start(ctx);
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SimStats {
+    pub sent: u64,
+    pub delivered: u64,
+    /// Lost to link loss
+    pub lost: u64,
+    /// Addressed to a node that was down on arrival
+    pub undeliverable: u64,
+    pub timers_fired: u64,
+}
+
+enum EventKind<M> {
+    Start(NodeId),
+    Deliver {
+        from: NodeId,
+        to: NodeId,
+        message: M,
+    },
+    Timer {
+        node: NodeId,
+        tag: u64,
+        epoch: u64,
+    },
+    Fault(Fault),
+}
+
+struct Scheduled<M> {
+    at: Duration,
+    seq: u64,
+    kind: EventKind<M>,
+}
+
+impl<M> PartialEq for Scheduled<M> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.at, self.seq) == (other.at, other.seq)
+    }
+}
+
+impl<M> Eq for Scheduled<M> {}
+
+impl<M> PartialOrd for Scheduled<M> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<M> Ord for Scheduled<M> {
+    // Reversed: `BinaryHeap` pops the earliest event first
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.at, other.seq).cmp(&(self.at, self.seq))
+    }
+}
+
+struct NodeSlot<M> {
+    behavior: Box<dyn NodeBehavior<M>>,
+    up: bool,
+    /// Bumped on every crash so timers set before it are ignored
+    epoch: u64,
+    slowdown: Duration,
+}
+
+pub struct Simulation<M> {
+    now: Duration,
+    seq: u64,
+    rng: StdRng,
+    queue: BinaryHeap<Scheduled<M>>,
+    nodes: Vec<NodeSlot<M>>,
+    default_link: LinkProfile,
+    links: HashMap<(NodeId, NodeId), LinkProfile>,
+    stats: SimStats,
+}
+
+impl<M> Simulation<M> {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            now: Duration::ZERO,
+            seq: 0,
+            rng: StdRng::seed_from_u64(seed),
+            queue: BinaryHeap::new(),
+            nodes: Vec::new(),
+            default_link: LinkProfile::default(),
+            links: HashMap::new(),
+            stats: SimStats::default(),
+        }
+    }
+
+    /// Profile of every link without its own
+    pub fn with_default_link(mut self, profile: LinkProfile) -> Self {
+        self.default_link = profile;
+        self
+    }
+
+    /// Add a node; it starts at the current time
+    pub fn add_node(&mut self, behavior: impl NodeBehavior<M> + 'static) -> NodeId {
+        let id = self.nodes.len();
+        self.nodes.push(NodeSlot {
+            behavior: Box::new(behavior),
+            up: true,
+            epoch: 0,
+            slowdown: Duration::ZERO,
+        });
+        self.push(self.now, EventKind::Start(id));
+        id
+    }
+
+    pub fn set_link(&mut self, a: NodeId, b: NodeId, profile: LinkProfile) {
+        self.links.insert((a, b), profile);
+        self.links.insert((b, a), profile);
+    }
+
+    /// Apply `fault` at virtual time `at`
+    pub fn schedule(&mut self, at: Duration, fault: Fault) {
+        self.push(at.max(self.now), EventKind::Fault(fault));
+    }
+
+    /// Crash and restart `nodes` at random between `from` and `until`. Each
+    /// stays up for a random time of up to `max_uptime`, then down for
+    /// `downtime`.
+    pub fn churn(
+        &mut self,
+        nodes: &[NodeId],
+        from: Duration,
+        until: Duration,
+        max_uptime: Duration,
+        downtime: Duration,
+    ) {
+        let max_uptime_ms = max_uptime.as_millis().max(1) as u64;
+        for &node in nodes {
+            let mut at = from;
+            loop {
+                at += Duration::from_millis(self.rng.gen_range(1..=max_uptime_ms));
+                if at >= until {
+                    break;
+                }
+                self.schedule(at, Fault::Down(node));
+                at += downtime;
+                self.schedule(at, Fault::Up(node));
+            }
+        }
+    }
+
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    pub fn is_up(&self, node: NodeId) -> bool {
+        self.nodes.get(node).is_some_and(|slot| slot.up)
+    }
+
+    pub fn stats(&self) -> SimStats {
+        self.stats
+    }
+
+    fn push(&mut self, at: Duration, kind: EventKind<M>) {
+        self.seq += 1;
+        self.queue.push(Scheduled {
+            at,
+            seq: self.seq,
+            kind,
+        });
+    }
+
+    /// Process events up to and including `deadline`
+    pub fn run_until(&mut self, deadline: Duration) -> SimStats {
+        while self.queue.peek().is_some_and(|next| next.at <= deadline) {
+            if let Some(event) = self.queue.pop() {
+                self.now = event.at;
+                self.handle(event.kind);
+            }
+        }
+        self.now = self.now.max(deadline);
+        self.stats
+    }
+
+    /// Run for at most `limit` of virtual time, stopping early once `done`
+    /// holds. Returns whether it did.
+    pub fn run_while(&mut self, limit: Duration, mut done: impl FnMut(&Self) -> bool) -> bool {
+        let deadline = self.now + limit;
+        while !done(self) {
+            let Some(event) = self.queue.pop() else {
+                return false;
+            };
+            if event.at > deadline {
+                self.queue.push(event);
+                self.now = deadline;
+                return false;
+            }
+            self.now = event.at;
+            self.handle(event.kind);
+        }
+        true
+    }
+
+    fn handle(&mut self, kind: EventKind<M>) {
+        match kind {
+            EventKind::Start(node) => self.dispatch(node, |b, ctx| b.on_start(ctx)),
+            EventKind::Deliver { from, to, message } => {
+                if self.is_up(to) {
+                    self.stats.delivered += 1;
+                    self.dispatch(to, |b, ctx| b.on_message(ctx, from, message));
+                } else {
+                    self.stats.undeliverable += 1;
+                }
+            }
+            EventKind::Timer { node, tag, epoch } => {
+                let current = self.nodes.get(node).map(|slot| (slot.up, slot.epoch));
+                if current == Some((true, epoch)) {
+                    self.stats.timers_fired += 1;
+                    self.dispatch(node, |b, ctx| b.on_timer(ctx, tag));
+                }
+            }
+            EventKind::Fault(fault) => self.apply(fault),
+        }
+    }
+
+    fn apply(&mut self, fault: Fault) {
+        match fault {
+            Fault::Down(node) => {
+                if let Some(slot) = self.nodes.get_mut(node) {
+                    slot.up = false;
+                    slot.epoch += 1;
+                }
+            }
+            Fault::Up(node) => {
+                let restarted = match self.nodes.get_mut(node) {
+                    Some(slot) if !slot.up => {
+                        slot.up = true;
+                        true
+                    }
+                    _ => false,
+                };
+                if restarted {
+                    self.dispatch(node, |b, ctx| b.on_up(ctx));
+                }
+            }
+            Fault::Link(a, b, profile) => self.set_link(a, b, profile),
+            Fault::Slow(node, delay) => {
+                if let Some(slot) = self.nodes.get_mut(node) {
+                    slot.slowdown = delay;
+                }
+            }
+        }
+    }
+
+    fn dispatch(
+        &mut self,
+        node: NodeId,
+        f: impl FnOnce(&mut dyn NodeBehavior<M>, &mut SimContext<'_, M>),
+    ) {
+        let Some(slot) = self.nodes.get_mut(node) else {
+            return;
+        };
+        if !slot.up {
+            return;
+        }
+        let mut ctx = SimContext {
+            node,
+            now: self.now,
+            rng: &mut self.rng,
+            actions: Vec::new(),
+        };
+        f(slot.behavior.as_mut(), &mut ctx);
+        let (epoch, slowdown) = (slot.epoch, slot.slowdown);
+        for action in ctx.actions {
+            match action {
+                Action::Timer(delay, tag) => {
+                    self.push(self.now + delay, EventKind::Timer { node, tag, epoch })
+                }
+                Action::Send(to, message) => {
+                    self.stats.sent += 1;
+                    let link = self
+                        .links
+                        .get(&(node, to))
+                        .copied()
+                        .unwrap_or(self.default_link);
+                    if link.loss > 0.0 && self.rng.gen_bool(link.loss) {
+                        self.stats.lost += 1;
+                        continue;
+                    }
+                    let jitter_ms = link.jitter.as_millis() as u64;
+                    let jitter = match jitter_ms {
+                        0 => Duration::ZERO,
+                        ms => Duration::from_millis(self.rng.gen_range(0..=ms)),
+                    };
+                    let at = self.now + link.latency + jitter + slowdown;
+                    self.push(
+                        at,
+                        EventKind::Deliver {
+                            from: node,
+                            to,
+                            message,
+                        },
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Pings every other node once a second and logs what it receives
+    struct Pinger {
+        peers: Vec<NodeId>,
+        log: Arc<Mutex<Vec<(Duration, NodeId, NodeId)>>>,
+    }
+
+    impl NodeBehavior<u32> for Pinger {
+        fn on_start(&mut self, ctx: &mut SimContext<'_, u32>) {
+            ctx.set_timer(Duration::from_secs(1), 0);
+        }
+
+        fn on_message(&mut self, ctx: &mut SimContext<'_, u32>, from: NodeId, _: u32) {
+            self.log.lock().unwrap().push((ctx.now(), from, ctx.node()));
+        }
+
+        fn on_timer(&mut self, ctx: &mut SimContext<'_, u32>, _: u64) {
+            let me = ctx.node();
+            for &peer in self.peers.iter().filter(|&&p| p != me) {
+                ctx.send(peer, 0);
+            }
+            ctx.set_timer(Duration::from_secs(1), 0);
+        }
+    }
+
+    fn run(seed: u64) -> (SimStats, Vec<(Duration, NodeId, NodeId)>) {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let link = LinkProfile::new(Duration::from_millis(50))
+            .with_jitter(Duration::from_millis(30))
+            .with_loss(0.1);
+        let mut sim = Simulation::new(seed).with_default_link(link);
+        for _ in 0..4 {
+            sim.add_node(Pinger {
+                peers: (0..4).collect(),
+                log: log.clone(),
+            });
+        }
+        sim.schedule(Duration::from_millis(2500), Fault::Down(3));
+        sim.schedule(Duration::from_millis(5500), Fault::Up(3));
+        sim.schedule(Duration::ZERO, Fault::Slow(2, Duration::from_millis(400)));
+        let stats = sim.run_until(Duration::from_secs(10));
+        let log = log.lock().unwrap().clone();
+        (stats, log)
+    }
+
+    #[test]
+    fn runs_are_reproducible_and_faults_apply() {
+        let (stats, log) = run(7);
+        assert_eq!(run(7), (stats, log.clone()));
+        assert_ne!(run(8).1, log);
+
+        assert_eq!(
+            stats.sent,
+            stats.delivered + stats.lost + stats.undeliverable
+        );
+        assert!(stats.lost > 0 && stats.undeliverable > 0);
+        // Node 3 neither sends nor receives while down
+        let down = Duration::from_millis(2600)..Duration::from_millis(5500);
+        assert!(!log.iter().any(|(at, _, to)| *to == 3 && down.contains(at)));
+        // Everything from the slow node arrives at least 450ms after a tick
+        assert!(log
+            .iter()
+            .filter(|(_, from, _)| *from == 2)
+            .all(|(at, _, _)| at.subsec_millis() >= 450));
+    }
+}
@@ -0,0 +1,263 @@
+// Slow-transfer detection
+//
+// A `SlowTransferWatchdog` is fed cumulative byte counts for one download and
+// for each of its active sources. When the download's average throughput over
+// the grace window drops below the configured floor, it returns a verdict
+// naming the slowest source so the caller can emit a `SlowTransfer` event and
+// move that source's remaining chunks to faster providers.
+//
+// Throughput is averaged over the whole grace window rather than per tick:
+// chunks land in 256 KiB steps, so short windows would flap between zero and
+// bursts even on a steady link.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const MIN_GRACE_SECS: u64 = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SlowTransferConfig {
+    pub enabled: bool,
+    /// Throughput floor in bytes per second
+    pub min_throughput_bps: u64,
+    /// How long throughput may stay below the floor before the transfer is
+    /// flagged (at least 10 seconds)
+    pub grace_secs: u64,
+    /// Re-route the slowest source's remaining chunks when other sources are
+    /// available
+    pub auto_switch: bool,
+}
+
+impl Default for SlowTransferConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            min_throughput_bps: 16 * 1024,
+            grace_secs: 30,
+            auto_switch: true,
+        }
+    }
+}
+
+impl SlowTransferConfig {
+    pub fn grace(&self) -> Duration {
+        Duration::from_secs(self.grace_secs.max(MIN_GRACE_SECS))
+    }
+}
+
+/// Shared, optionally persisted slow-transfer settings
+pub struct SlowTransferSettings {
+    config: Mutex<SlowTransferConfig>,
+    persist_path: Option<PathBuf>,
+}
+
+impl SlowTransferSettings {
+    pub fn new() -> Self {
+        Self {
+            config: Mutex::new(SlowTransferConfig::default()),
+            persist_path: None,
+        }
+    }
+
+    /// Settings loaded from and saved to `path`
+    pub fn with_persistence(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let config = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|raw| serde_json::from_str::<SlowTransferConfig>(&raw).ok())
+            .unwrap_or_default();
+        Self {
+            config: Mutex::new(config),
+            persist_path: Some(path),
+        }
+    }
+
+    pub fn config(&self) -> SlowTransferConfig {
+        self.config
+            .lock()
+            .map(|c| c.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn set_config(&self, mut config: SlowTransferConfig) -> Result<(), String> {
+        if config.min_throughput_bps == 0 {
+            return Err("Invalid throughput floor: must be greater than zero".to_string());
+        }
+        config.grace_secs = config.grace_secs.max(MIN_GRACE_SECS);
+
+        if let Some(path) = &self.persist_path {
+            let json = serde_json::to_string_pretty(&config)
+                .map_err(|e| format!("Failed to serialize slow-transfer settings: {}", e))?;
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| format!("Failed to create config directory: {}", e))?;
+            }
+            std::fs::write(path, json)
+                .map_err(|e| format!("Failed to save slow-transfer settings: {}", e))?;
+        }
+        let mut current = self
+            .config
+            .lock()
+            .map_err(|_| "Slow-transfer settings lock poisoned".to_string())?;
+        *current = config;
+        Ok(())
+    }
+}
+
+impl Default for SlowTransferSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Cumulative byte samples covering (at least) one window
+#[derive(Debug, Default)]
+struct ThroughputWindow {
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl ThroughputWindow {
+    /// Record `total_bytes` at `now`. Returns the average throughput once the
+    /// samples span a full `window`.
+    fn observe(&mut self, now: Instant, total_bytes: u64, window: Duration) -> Option<f64> {
+        self.samples.push_back((now, total_bytes));
+        // Keep the newest sample at or before the window start
+        while self.samples.len() > 2 && now.duration_since(self.samples[1].0) >= window {
+            self.samples.pop_front();
+        }
+        let (start, start_bytes) = *self.samples.front()?;
+        let span = now.duration_since(start);
+        if span < window || span.is_zero() {
+            return None;
+        }
+        Some(total_bytes.saturating_sub(start_bytes) as f64 / span.as_secs_f64())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlowTransferVerdict {
+    pub throughput_bps: f64,
+    pub slow_for: Duration,
+    /// Slowest active source and its throughput, when one has been observed
+    /// for a full window
+    pub slowest_source: Option<(String, f64)>,
+    /// Active sources other than the slowest one
+    pub alternatives: usize,
+}
+
+/// Per-download throughput watchdog
+#[derive(Debug, Default)]
+pub struct SlowTransferWatchdog {
+    transfer: ThroughputWindow,
+    sources: HashMap<String, ThroughputWindow>,
+}
+
+impl SlowTransferWatchdog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record the download's total bytes and the bytes delivered by each
+    /// active source. Returns a verdict when the download's average
+    /// throughput over the grace window is below the floor; the window then
+    /// restarts so the same slowdown is reported at most once per grace period.
+    pub fn observe(
+        &mut self,
+        config: &SlowTransferConfig,
+        now: Instant,
+        total_bytes: u64,
+        active_sources: &[(String, u64)],
+    ) -> Option<SlowTransferVerdict> {
+        if !config.enabled {
+            return None;
+        }
+        let window = config.grace();
+
+        self.sources.retain(|id, _| active_sources.iter().any(|(active, _)| active == id));
+        let mut slowest: Option<(String, f64)> = None;
+        for (source_id, bytes) in active_sources {
+            let rate = self
+                .sources
+                .entry(source_id.clone())
+                .or_default()
+                .observe(now, *bytes, window);
+            if let Some(rate) = rate {
+                if slowest.as_ref().map_or(true, |(_, best)| rate < *best) {
+                    slowest = Some((source_id.clone(), rate));
+                }
+            }
+        }
+
+        let throughput_bps = self.transfer.observe(now, total_bytes, window)?;
+        if throughput_bps >= config.min_throughput_bps as f64 {
+            return None;
+        }
+
+        self.transfer = ThroughputWindow::default();
+        self.sources.clear();
+        Some(SlowTransferVerdict {
+            throughput_bps,
+            slow_for: window,
+            alternatives: active_sources.len().saturating_sub(1),
+            slowest_source: slowest,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flags_after_grace_and_names_slowest_source() {
+        let config = SlowTransferConfig {
+            min_throughput_bps: 10_000,
+            grace_secs: 10,
+            ..SlowTransferConfig::default()
+        };
+        let mut watchdog = SlowTransferWatchdog::new();
+        let start = Instant::now();
+        // "a" sends 10 KB/s for ten seconds, then drops to 500 B/s; "b" holds 6 KB/s
+        let a = |secs: u64| {
+            if secs <= 10 {
+                secs * 10_000
+            } else {
+                100_000 + (secs - 10) * 500
+            }
+        };
+        let b = |secs: u64| secs * 6_000;
+
+        let mut flagged = None;
+        for secs in (0..=30).step_by(2) {
+            let sources = [("a".to_string(), a(secs)), ("b".to_string(), b(secs))];
+            let now = start + Duration::from_secs(secs);
+            if let Some(verdict) = watchdog.observe(&config, now, a(secs) + b(secs), &sources) {
+                flagged = Some((secs, verdict));
+                break;
+            }
+        }
+
+        // The 10 s average first drops below the floor at 18 s
+        let (secs, verdict) = flagged.expect("slow transfer should be flagged");
+        assert_eq!(secs, 18);
+        assert_eq!(verdict.throughput_bps, 8_400.0);
+        assert_eq!(verdict.slowest_source, Some(("a".to_string(), 2_400.0)));
+        assert_eq!(verdict.alternatives, 1);
+
+        // The window restarts after a verdict
+        let sources = [("b".to_string(), b(20))];
+        let now = start + Duration::from_secs(20);
+        assert_eq!(watchdog.observe(&config, now, a(20) + b(20), &sources), None);
+
+        let disabled = SlowTransferConfig {
+            enabled: false,
+            ..config
+        };
+        let now = start + Duration::from_secs(60);
+        assert_eq!(watchdog.observe(&disabled, now, 0, &sources), None);
+    }
+}
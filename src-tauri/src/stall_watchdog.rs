@@ -0,0 +1,232 @@
+// Stuck-transfer watchdog
+//
+// A `StallWatchdog` is fed a download's cumulative byte count on every
+// monitor tick. When no bytes have moved for the inactivity timeout it
+// reports the transfer as stalled so the caller can emit a `Stalled` event and
+// try to recover (look for new providers, reconnect to the current ones).
+// Each recovery attempt gets another full timeout; once the attempts are used
+// up and the transfer is still idle, the watchdog reports a timeout and the
+// caller fails the transfer instead of leaving it hanging.
+
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const MIN_TIMEOUT_SECS: u64 = 15;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct StallConfig {
+    pub enabled: bool,
+    /// Seconds without any bytes received before a transfer counts as
+    /// stalled (at least 15)
+    pub inactivity_timeout_secs: u64,
+    /// Recovery attempts before the transfer is failed with a timeout
+    pub max_recovery_attempts: u32,
+}
+
+impl Default for StallConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            inactivity_timeout_secs: 60,
+            max_recovery_attempts: 3,
+        }
+    }
+}
+
+impl StallConfig {
+    pub fn inactivity_timeout(&self) -> Duration {
+        Duration::from_secs(self.inactivity_timeout_secs.max(MIN_TIMEOUT_SECS))
+    }
+}
+
+/// Shared, optionally persisted stall watchdog settings
+pub struct StallSettings {
+    config: Mutex<StallConfig>,
+    persist_path: Option<PathBuf>,
+}
+
+impl StallSettings {
+    pub fn new() -> Self {
+        Self {
+            config: Mutex::new(StallConfig::default()),
+            persist_path: None,
+        }
+    }
+
+    /// Settings loaded from and saved to `path`
+    pub fn with_persistence(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let config = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|raw| serde_json::from_str::<StallConfig>(&raw).ok())
+            .unwrap_or_default();
+        Self {
+            config: Mutex::new(config),
+            persist_path: Some(path),
+        }
+    }
+
+    pub fn config(&self) -> StallConfig {
+        self.config
+            .lock()
+            .map(|c| c.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn set_config(&self, mut config: StallConfig) -> Result<(), String> {
+        config.inactivity_timeout_secs = config.inactivity_timeout_secs.max(MIN_TIMEOUT_SECS);
+
+        if let Some(path) = &self.persist_path {
+            let json = serde_json::to_string_pretty(&config)
+                .map_err(|e| format!("Failed to serialize stall watchdog settings: {}", e))?;
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| format!("Failed to create config directory: {}", e))?;
+            }
+            std::fs::write(path, json)
+                .map_err(|e| format!("Failed to save stall watchdog settings: {}", e))?;
+        }
+        let mut current = self
+            .config
+            .lock()
+            .map_err(|_| "Stall watchdog settings lock poisoned".to_string())?;
+        *current = config;
+        Ok(())
+    }
+}
+
+impl Default for StallSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StallVerdict {
+    /// No progress for `idle_for`; `attempt` is the 1-based recovery attempt
+    /// the caller should make now
+    Stalled { idle_for: Duration, attempt: u32 },
+    /// Still no progress after every recovery attempt
+    TimedOut { idle_for: Duration, attempts: u32 },
+}
+
+/// Per-download inactivity watchdog
+#[derive(Debug, Default)]
+pub struct StallWatchdog {
+    /// When bytes last moved, and the byte count at that point
+    last_progress: Option<(Instant, u64)>,
+    recovery_attempts: u32,
+}
+
+impl StallWatchdog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Recovery attempts made since bytes last moved
+    pub fn recovery_attempts(&self) -> u32 {
+        self.recovery_attempts
+    }
+
+    /// Record the download's total bytes at `now`. Returns a verdict each
+    /// time another full inactivity timeout passes without progress; any
+    /// progress resets the timer and the recovery attempts.
+    pub fn observe(
+        &mut self,
+        config: &StallConfig,
+        now: Instant,
+        total_bytes: u64,
+    ) -> Option<StallVerdict> {
+        if !config.enabled {
+            self.last_progress = Some((now, total_bytes));
+            return None;
+        }
+        let since = match self.last_progress {
+            Some((since, bytes)) if total_bytes <= bytes => since,
+            _ => {
+                self.last_progress = Some((now, total_bytes));
+                self.recovery_attempts = 0;
+                return None;
+            }
+        };
+        let idle_for = now.saturating_duration_since(since);
+        let timeout = config.inactivity_timeout();
+        if idle_for < timeout * (self.recovery_attempts + 1) {
+            return None;
+        }
+        if self.recovery_attempts >= config.max_recovery_attempts {
+            return Some(StallVerdict::TimedOut {
+                idle_for,
+                attempts: self.recovery_attempts,
+            });
+        }
+        self.recovery_attempts += 1;
+        Some(StallVerdict::Stalled {
+            idle_for,
+            attempt: self.recovery_attempts,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stalls_recover_and_finally_time_out() {
+        let config = StallConfig {
+            inactivity_timeout_secs: 20,
+            max_recovery_attempts: 2,
+            ..StallConfig::default()
+        };
+        let mut watchdog = StallWatchdog::new();
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+
+        assert_eq!(watchdog.observe(&config, at(0), 1_000), None);
+        assert_eq!(watchdog.observe(&config, at(19), 1_000), None);
+        assert_eq!(
+            watchdog.observe(&config, at(20), 1_000),
+            Some(StallVerdict::Stalled {
+                idle_for: Duration::from_secs(20),
+                attempt: 1,
+            })
+        );
+        // Each attempt gets a full timeout before the next verdict
+        assert_eq!(watchdog.observe(&config, at(30), 1_000), None);
+
+        // Progress resets the attempts
+        assert_eq!(watchdog.observe(&config, at(35), 2_000), None);
+        assert_eq!(watchdog.recovery_attempts(), 0);
+
+        let verdicts: Vec<StallVerdict> = (36..=100)
+            .filter_map(|secs| watchdog.observe(&config, at(secs), 2_000))
+            .collect();
+        assert_eq!(
+            verdicts[..3],
+            [
+                StallVerdict::Stalled {
+                    idle_for: Duration::from_secs(20),
+                    attempt: 1,
+                },
+                StallVerdict::Stalled {
+                    idle_for: Duration::from_secs(40),
+                    attempt: 2,
+                },
+                StallVerdict::TimedOut {
+                    idle_for: Duration::from_secs(60),
+                    attempts: 2,
+                },
+            ]
+        );
+
+        let disabled = StallConfig {
+            enabled: false,
+            ..config
+        };
+        assert_eq!(watchdog.observe(&disabled, at(500), 2_000), None);
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::fs;
 use std::time::{SystemTime, Duration};
@@ -112,6 +113,72 @@ impl CleanupReport {
     }
 }
 
+/// What a file on disk is used for, as reported by the storage breakdown
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageCategory {
+    /// Content we seed: the file store and the Bitswap blockstore
+    Pinned,
+    /// Completed downloads and the chunk cache
+    CachedDownloads,
+    /// In-progress downloads (.part files, their metadata, temp transfers)
+    PartialTransfers,
+    /// Databases, caches and state files in the app data directory
+    Indices,
+    Logs,
+}
+
+impl StorageCategory {
+    pub const ALL: [StorageCategory; 5] = [
+        StorageCategory::Pinned,
+        StorageCategory::CachedDownloads,
+        StorageCategory::PartialTransfers,
+        StorageCategory::Indices,
+        StorageCategory::Logs,
+    ];
+}
+
+/// Locations scanned by the storage breakdown in addition to `StorageConfig`
+#[derive(Debug, Clone)]
+pub struct BreakdownLocations {
+    /// Seeded file store
+    pub pinned_path: PathBuf,
+    /// App data directory; whatever is not covered elsewhere counts as indices
+    pub data_dir: PathBuf,
+    pub logs_path: PathBuf,
+}
+
+/// One file in a storage breakdown
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FileUsage {
+    pub path: String,
+    pub bytes: u64,
+    pub category: StorageCategory,
+    /// Unix timestamp (seconds) of the last modification
+    pub modified: Option<u64>,
+}
+
+/// Disk usage of one category with its largest files
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CategoryUsage {
+    pub category: StorageCategory,
+    pub bytes: u64,
+    pub file_count: usize,
+    pub top_files: Vec<FileUsage>,
+}
+
+/// Disk usage split by what the data is for
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageBreakdown {
+    pub total_bytes: u64,
+    pub available_bytes: u64,
+    /// Every category, in `StorageCategory::ALL` order
+    pub categories: Vec<CategoryUsage>,
+    /// Largest files across all categories
+    pub top_files: Vec<FileUsage>,
+    pub timestamp: SystemTime,
+}
+
 /// Information about a file for cleanup purposes
 #[derive(Debug, Clone)]
 pub struct FileInfo {
@@ -179,6 +246,23 @@ impl StorageManager {
         })
     }
 
+    /// Disk usage split into pinned content, cached downloads, partial
+    /// transfers, indices and logs, with the `top_n` largest files of each
+    pub async fn calculate_breakdown(
+        &self,
+        locations: BreakdownLocations,
+        top_n: usize,
+    ) -> Result<StorageBreakdown> {
+        let config = self.config.clone();
+        let mut breakdown = tokio::task::spawn_blocking(move || {
+            build_breakdown(&config, &locations, top_n)
+        })
+        .await
+        .map_err(|e| anyhow::anyhow!("Task join error: {}", e))?;
+        breakdown.available_bytes = get_available_space(&self.config.download_path)?;
+        Ok(breakdown)
+    }
+
     /// Check if cleanup is needed and perform it if enabled
     pub async fn check_and_cleanup(&self) -> Result<Option<CleanupReport>> {
         let usage = self.calculate_usage().await?;
@@ -501,6 +585,124 @@ impl StorageManager {
     }
 }
 
+/// Category of a file found in the downloads directory
+fn classify_download(path: &Path) -> StorageCategory {
+    let name = path.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    if name.ends_with(".part") || name.ends_with(".part.meta.json") {
+        StorageCategory::PartialTransfers
+    } else {
+        StorageCategory::CachedDownloads
+    }
+}
+
+/// Walk `root` (a file or directory), skipping `skip`, and hand every regular
+/// file to `visit`. Symlinks are not followed.
+fn walk_files(root: &Path, skip: &[PathBuf], visit: &mut dyn FnMut(&Path, &fs::Metadata)) {
+    let Ok(metadata) = fs::symlink_metadata(root) else {
+        return;
+    };
+    if metadata.is_file() {
+        visit(root, &metadata);
+        return;
+    }
+    if !metadata.is_dir() {
+        return;
+    }
+    let Ok(entries) = fs::read_dir(root) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !skip.contains(&path) {
+            walk_files(&path, skip, visit);
+        }
+    }
+}
+
+/// Insert `file` into `top`, keeping only the `n` largest
+fn keep_largest(top: &mut Vec<FileUsage>, file: FileUsage, n: usize) {
+    if top.len() >= n && top.last().map_or(true, |smallest| smallest.bytes >= file.bytes) {
+        return;
+    }
+    let index = top.partition_point(|f| f.bytes >= file.bytes);
+    top.insert(index, file);
+    top.truncate(n);
+}
+
+fn build_breakdown(
+    config: &StorageConfig,
+    locations: &BreakdownLocations,
+    top_n: usize,
+) -> StorageBreakdown {
+    let roots: Vec<(PathBuf, Option<StorageCategory>)> = vec![
+        (locations.pinned_path.clone(), Some(StorageCategory::Pinned)),
+        (config.blockstore_path.clone(), Some(StorageCategory::Pinned)),
+        (config.download_path.clone(), None),
+        (config.chunk_storage_path.clone(), Some(StorageCategory::CachedDownloads)),
+        (config.temp_path.clone(), Some(StorageCategory::PartialTransfers)),
+        (locations.logs_path.clone(), Some(StorageCategory::Logs)),
+    ];
+    let covered: Vec<PathBuf> = roots.iter().map(|(path, _)| path.clone()).collect();
+
+    let mut usage: HashMap<StorageCategory, CategoryUsage> = StorageCategory::ALL
+        .iter()
+        .map(|&category| {
+            let empty = CategoryUsage {
+                category,
+                bytes: 0,
+                file_count: 0,
+                top_files: Vec::new(),
+            };
+            (category, empty)
+        })
+        .collect();
+    let mut top_files = Vec::new();
+    let mut seen = std::collections::HashSet::new();
+
+    let walks = roots
+        .iter()
+        .map(|(root, category)| (root, *category))
+        .chain(std::iter::once((&locations.data_dir, Some(StorageCategory::Indices))));
+    for (root, category) in walks {
+        // Other roots may live inside the data directory; each file is
+        // counted once, under the most specific root
+        let skip: Vec<PathBuf> = covered.iter().filter(|p| *p != root).cloned().collect();
+        walk_files(root, &skip, &mut |path, metadata| {
+            if !seen.insert(path.to_path_buf()) {
+                return;
+            }
+            let file = FileUsage {
+                path: path.to_string_lossy().to_string(),
+                bytes: metadata.len(),
+                category: category.unwrap_or_else(|| classify_download(path)),
+                modified: metadata
+                    .modified()
+                    .ok()
+                    .and_then(|t| t.duration_since(SystemTime::UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs()),
+            };
+            if let Some(entry) = usage.get_mut(&file.category) {
+                entry.bytes += file.bytes;
+                entry.file_count += 1;
+                keep_largest(&mut entry.top_files, file.clone(), top_n);
+            }
+            keep_largest(&mut top_files, file, top_n);
+        });
+    }
+
+    let categories: Vec<CategoryUsage> = StorageCategory::ALL
+        .iter()
+        .filter_map(|category| usage.remove(category))
+        .collect();
+    StorageBreakdown {
+        total_bytes: categories.iter().map(|c| c.bytes).sum(),
+        available_bytes: 0,
+        categories,
+        top_files,
+        timestamp: SystemTime::now(),
+    }
+}
+
 /// Calculate the total size of a directory recursively
 fn calculate_directory_size_sync(path: &Path) -> Result<u64> {
     if !path.exists() {
@@ -565,6 +767,55 @@ fn get_available_space(path: &Path) -> Result<u64> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_storage_breakdown_categories() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = |name: &str| {
+            let path = root.path().join(name);
+            fs::create_dir_all(&path).unwrap();
+            path
+        };
+        let write = |path: PathBuf, len: usize| fs::write(path, vec![0u8; len]).unwrap();
+
+        let data_dir = dir("data");
+        let config = StorageConfig {
+            max_storage_size_gb: 100,
+            auto_cleanup: false,
+            cleanup_threshold: 90,
+            cache_size_mb: 1024,
+            download_path: dir("downloads"),
+            blockstore_path: data_dir.join("blockstore_db"),
+            temp_path: dir("tmp"),
+            chunk_storage_path: data_dir.join("chunk_storage"),
+        };
+        let locations = BreakdownLocations {
+            pinned_path: dir("data/files"),
+            data_dir: data_dir.clone(),
+            logs_path: dir("logs"),
+        };
+        fs::create_dir_all(&config.chunk_storage_path).unwrap();
+
+        write(locations.pinned_path.join("seeded"), 500);
+        write(config.download_path.join("movie.mkv"), 300);
+        write(config.download_path.join("show.mkv.part"), 200);
+        write(config.download_path.join("show.mkv.part.meta.json"), 10);
+        write(config.chunk_storage_path.join("chunk-0"), 40);
+        write(config.temp_path.join("transfer"), 5);
+        write(data_dir.join("peer_cache.json"), 20);
+        write(locations.logs_path.join("chiral.log"), 70);
+
+        let breakdown = build_breakdown(&config, &locations, 2);
+        let bytes: Vec<u64> = breakdown.categories.iter().map(|c| c.bytes).collect();
+        assert_eq!(bytes, vec![500, 340, 215, 20, 70]);
+        assert_eq!(breakdown.total_bytes, 1_145);
+        let partial = &breakdown.categories[2];
+        assert_eq!(partial.file_count, 3);
+        assert_eq!(partial.top_files.len(), 2);
+        assert_eq!(partial.top_files[0].bytes, 200);
+        let top: Vec<u64> = breakdown.top_files.iter().map(|f| f.bytes).collect();
+        assert_eq!(top, vec![500, 300]);
+    }
+
     #[test]
     fn test_format_bytes() {
         assert_eq!(StorageUsage::format_bytes(512), "512 B");
@@ -0,0 +1,445 @@
+// Publisher subscriptions
+//
+// Users can follow publisher peer IDs. Their signed feeds (see
+// `dht::publisher_feed`) are polled periodically; entries newer than the last
+// one seen raise a notification and, for subscriptions with auto-download
+// enabled, start a download into the subscription's folder. Auto-downloads are
+// bounded per file (size, price) and per day across all subscriptions (bytes,
+// spend), so following a prolific publisher cannot fill the disk or drain the
+// wallet. The bounds are checked against the file's DHT metadata, which the
+// download and payment go by, not against the size and price in the feed, and
+// the file is named by the download naming rules so an existing file is never
+// silently overwritten.
+//
+// Subscriptions, limits and today's usage are persisted together.
+
+use crate::dht::publisher_feed::{FeedEntry, PublisherFeed};
+use crate::file_transfer::naming::{self, NamingRules, OutputTarget};
+use crate::persisted::Persisted;
+use crate::runtime_env::{Clock, SystemClock};
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How often subscribed feeds are fetched
+pub const POLL_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Event emitted to the frontend for every new entry
+pub const CONTENT_EVENT: &str = "subscription_content";
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Subscription {
+    pub publisher_id: String,
+    pub label: Option<String>,
+    pub auto_download: bool,
+    /// Folder auto-downloads are written to
+    pub download_dir: Option<String>,
+    /// Larger files are only announced
+    pub max_file_size: Option<u64>,
+    /// Highest price auto-downloaded; 0 means free files only
+    #[serde(default)]
+    pub max_price: f64,
+    pub subscribed_at: u64,
+    /// `published_at` of the newest entry already handled
+    #[serde(default)]
+    pub last_seen_at: u64,
+}
+
+impl Subscription {
+    pub fn display_name(&self) -> String {
+        self.label
+            .clone()
+            .unwrap_or_else(|| self.publisher_id.chars().take(12).collect())
+    }
+}
+
+/// Daily caps shared by all auto-downloads
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SubscriptionLimits {
+    pub daily_bytes: Option<u64>,
+    pub daily_spend: Option<f64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DailyUsage {
+    /// Days since the Unix epoch
+    pub day: u64,
+    pub bytes: u64,
+    pub spent: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SubscriptionState {
+    pub subscriptions: Vec<Subscription>,
+    pub limits: SubscriptionLimits,
+    pub usage: DailyUsage,
+}
+
+/// A feed entry's file as published in the DHT
+#[derive(Debug, Clone, PartialEq)]
+pub struct PublishedFile {
+    pub file_name: String,
+    pub file_size: u64,
+    pub price: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AutoDownload {
+    /// Download to this path
+    Start(PathBuf),
+    /// Only notify; the reason is shown to the user
+    Skip(String),
+}
+
+/// Sent to the frontend for every new feed entry
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscriptionContentEvent {
+    pub publisher_id: String,
+    pub publisher_name: String,
+    pub entry: FeedEntry,
+    pub auto_downloaded: bool,
+    pub skipped_reason: Option<String>,
+}
+
+pub struct Subscriptions {
+    state: Persisted<SubscriptionState>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Subscriptions {
+    pub fn new() -> Self {
+        Self {
+            state: Persisted::in_memory(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Subscriptions loaded from and saved to `path`
+    pub fn with_persistence(path: impl AsRef<Path>) -> Self {
+        Self {
+            state: Persisted::with_persistence(path),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Read the time from `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Current Unix seconds on the subscriptions' clock
+    pub fn now(&self) -> u64 {
+        self.clock.now_secs()
+    }
+
+    fn update<T>(&self, f: impl FnOnce(&mut SubscriptionState) -> T) -> Result<T, String> {
+        self.state.update(f)
+    }
+
+    pub fn state(&self) -> SubscriptionState {
+        self.state.get()
+    }
+
+    pub fn list(&self) -> Vec<Subscription> {
+        self.state().subscriptions
+    }
+
+    /// Add or replace the subscription for `subscription.publisher_id`.
+    /// Only content published from now on is reported.
+    pub fn subscribe(&self, mut subscription: Subscription) -> Result<Subscription, String> {
+        subscription
+            .publisher_id
+            .parse::<PeerId>()
+            .map_err(|e| format!("Invalid publisher peer ID: {}", e))?;
+        let now = self.now();
+        self.update(|state| {
+            match state
+                .subscriptions
+                .iter_mut()
+                .find(|s| s.publisher_id == subscription.publisher_id)
+            {
+                Some(existing) => {
+                    subscription.subscribed_at = existing.subscribed_at;
+                    subscription.last_seen_at = existing.last_seen_at;
+                    *existing = subscription.clone();
+                }
+                None => {
+                    subscription.subscribed_at = now;
+                    subscription.last_seen_at = now;
+                    state.subscriptions.push(subscription.clone());
+                }
+            }
+            subscription
+        })
+    }
+
+    pub fn unsubscribe(&self, publisher_id: &str) -> Result<bool, String> {
+        self.update(|state| {
+            let before = state.subscriptions.len();
+            state
+                .subscriptions
+                .retain(|s| s.publisher_id != publisher_id);
+            state.subscriptions.len() != before
+        })
+    }
+
+    pub fn set_limits(&self, limits: SubscriptionLimits) -> Result<(), String> {
+        self.update(|state| state.limits = limits)
+    }
+
+    /// Entries of `feed` not handled yet, oldest first
+    pub fn new_entries(&self, publisher_id: &str, feed: &PublisherFeed) -> Vec<FeedEntry> {
+        let last_seen_at = self
+            .state()
+            .subscriptions
+            .iter()
+            .find(|s| s.publisher_id == publisher_id)
+            .map(|s| s.last_seen_at);
+        let Some(last_seen_at) = last_seen_at else {
+            return Vec::new();
+        };
+        let mut entries: Vec<FeedEntry> = feed
+            .entries
+            .iter()
+            .filter(|e| e.published_at > last_seen_at)
+            .cloned()
+            .collect();
+        entries.sort_by_key(|e| e.published_at);
+        entries
+    }
+
+    pub fn mark_seen(&self, publisher_id: &str, published_at: u64) -> Result<(), String> {
+        self.update(|state| {
+            if let Some(sub) = state
+                .subscriptions
+                .iter_mut()
+                .find(|s| s.publisher_id == publisher_id)
+            {
+                sub.last_seen_at = sub.last_seen_at.max(published_at);
+            }
+        })
+    }
+
+    /// Decide whether `entry` from `publisher_id` is auto-downloaded and, if
+    /// so, where to, and count it against today's budget. `published` is the
+    /// file as found in the DHT; without it nothing is downloaded.
+    pub fn claim_auto_download(
+        &self,
+        publisher_id: &str,
+        entry: &FeedEntry,
+        published: Option<&PublishedFile>,
+        rules: &NamingRules,
+        now: u64,
+    ) -> Result<AutoDownload, String> {
+        self.update(|state| {
+            let Some(sub) = state
+                .subscriptions
+                .iter()
+                .find(|s| s.publisher_id == publisher_id)
+            else {
+                return AutoDownload::Skip("Not subscribed".to_string());
+            };
+            if !sub.auto_download {
+                return AutoDownload::Skip("Auto-download is off".to_string());
+            }
+            let Some(dir) = sub.download_dir.clone() else {
+                return AutoDownload::Skip("No download folder set".to_string());
+            };
+            let Some(file) = published else {
+                return AutoDownload::Skip("File metadata not found".to_string());
+            };
+            if sub.max_file_size.is_some_and(|max| file.file_size > max) {
+                return AutoDownload::Skip("Larger than the size limit".to_string());
+            }
+            if file.price > sub.max_price {
+                return AutoDownload::Skip("Price above the limit".to_string());
+            }
+            let path =
+                match naming::resolve(Path::new(&dir), &file.file_name, &entry.file_hash, rules) {
+                    Ok(OutputTarget::Write(path)) => path,
+                    Ok(OutputTarget::Skip(path)) => {
+                        return AutoDownload::Skip(format!("{} already exists", path.display()))
+                    }
+                    Err(e) => return AutoDownload::Skip(e),
+                };
+
+            let today = now / SECS_PER_DAY;
+            if state.usage.day != today {
+                state.usage = DailyUsage {
+                    day: today,
+                    ..Default::default()
+                };
+            }
+            let limits = &state.limits;
+            if limits
+                .daily_bytes
+                .is_some_and(|max| state.usage.bytes + file.file_size > max)
+            {
+                return AutoDownload::Skip("Daily download budget used up".to_string());
+            }
+            if limits
+                .daily_spend
+                .is_some_and(|max| state.usage.spent + file.price > max)
+            {
+                return AutoDownload::Skip("Daily spending budget used up".to_string());
+            }
+            state.usage.bytes += file.file_size;
+            state.usage.spent += file.price;
+            AutoDownload::Start(path)
+        })
+    }
+}
+
+impl Default for Subscriptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persisted::TempConfig;
+
+    fn entry(hash: &str, size: u64, price: f64, published_at: u64) -> FeedEntry {
+        FeedEntry {
+            file_hash: hash.to_string(),
+            file_name: format!("{}.bin", hash),
+            file_size: size,
+            price,
+            published_at,
+        }
+    }
+
+    #[test]
+    fn existing_files_follow_the_collision_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("show.mp3"), b"old").unwrap();
+        let subs = Subscriptions::new();
+        let peer = PeerId::random().to_string();
+        subs.subscribe(Subscription {
+            publisher_id: peer.clone(),
+            label: None,
+            auto_download: true,
+            download_dir: Some(dir.path().to_string_lossy().into_owned()),
+            max_file_size: None,
+            max_price: 0.0,
+            subscribed_at: 0,
+            last_seen_at: 0,
+        })
+        .unwrap();
+        let file = PublishedFile {
+            file_name: "show.mp3".into(),
+            file_size: 3,
+            price: 0.0,
+        };
+        let listed = entry("abc", 3, 0.0, 0);
+
+        let rules = NamingRules::default();
+        assert_eq!(
+            subs.claim_auto_download(&peer, &listed, Some(&file), &rules, 0)
+                .unwrap(),
+            AutoDownload::Start(dir.path().join("show (1).mp3"))
+        );
+        let rules = NamingRules {
+            collision: naming::CollisionPolicy::Skip,
+            ..NamingRules::default()
+        };
+        assert!(matches!(
+            subs.claim_auto_download(&peer, &listed, Some(&file), &rules, 0)
+                .unwrap(),
+            AutoDownload::Skip(_)
+        ));
+        assert_eq!(subs.state().usage.bytes, 3);
+    }
+
+    #[test]
+    fn filters_new_entries_and_enforces_budgets() {
+        let config = TempConfig::new();
+        let subs = Subscriptions::with_persistence(&config.path);
+        let peer = PeerId::random().to_string();
+        let sub = subs
+            .subscribe(Subscription {
+                publisher_id: peer.clone(),
+                label: Some("Radio".into()),
+                auto_download: true,
+                download_dir: Some("/downloads".into()),
+                max_file_size: Some(100),
+                max_price: 0.5,
+                subscribed_at: 0,
+                last_seen_at: 0,
+            })
+            .unwrap();
+        let start = sub.last_seen_at;
+        assert_eq!(
+            Subscriptions::with_persistence(&config.path).list(),
+            vec![sub.clone()]
+        );
+
+        let feed = PublisherFeed {
+            publisher: peer.clone(),
+            public_key: String::new(),
+            seq: 1,
+            entries: vec![
+                entry("new", 10, 0.0, start + 5),
+                entry("old", 10, 0.0, start),
+            ],
+            signature: String::new(),
+        };
+        let fresh = subs.new_entries(&peer, &feed);
+        assert_eq!(fresh.len(), 1);
+        subs.mark_seen(&peer, start + 5).unwrap();
+        assert!(subs.new_entries(&peer, &feed).is_empty());
+        assert!(subs.new_entries("stranger", &feed).is_empty());
+
+        subs.set_limits(SubscriptionLimits {
+            daily_bytes: Some(150),
+            daily_spend: Some(0.6),
+        })
+        .unwrap();
+        let day = 10 * SECS_PER_DAY;
+        let rules = NamingRules::default();
+        let claim = |hash: &str, size, price, now| {
+            let file = PublishedFile {
+                file_name: format!("{}.bin", hash),
+                file_size: size,
+                price,
+            };
+            // The feed claims a tiny free file; only the metadata counts
+            let listed = entry(hash, 1, 0.0, 0);
+            subs.claim_auto_download(&peer, &listed, Some(&file), &rules, now)
+                .unwrap()
+        };
+        assert_eq!(
+            claim("a", 90, 0.4, day),
+            AutoDownload::Start(PathBuf::from("/downloads/a.bin"))
+        );
+        assert!(matches!(
+            subs.claim_auto_download(&peer, &entry("x", 1, 0.0, 0), None, &rules, day)
+                .unwrap(),
+            AutoDownload::Skip(_)
+        ));
+        assert!(matches!(claim("b", 200, 0.0, day), AutoDownload::Skip(_)));
+        assert!(matches!(claim("c", 10, 0.9, day), AutoDownload::Skip(_)));
+        // 90 + 70 bytes exceeds the daily budget, 0.4 + 0.4 the spend budget
+        assert!(matches!(claim("d", 70, 0.0, day), AutoDownload::Skip(_)));
+        assert!(matches!(claim("e", 10, 0.4, day), AutoDownload::Skip(_)));
+        // Budgets reset the next day
+        assert!(matches!(
+            claim("d", 70, 0.0, day + SECS_PER_DAY),
+            AutoDownload::Start(_)
+        ));
+
+        assert!(subs.unsubscribe(&peer).unwrap());
+        assert!(subs.list().is_empty());
+    }
+}
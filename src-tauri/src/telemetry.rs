@@ -0,0 +1,335 @@
+// Opt-in anonymous network telemetry
+//
+// When the user enables it, the node periodically posts a small report to the
+// configured project endpoint so maintainers can gauge overall network health.
+// Telemetry is off by default and nothing is sent unless both `enabled` and
+// `endpoint` are set.
+//
+// Reports are deliberately coarse: a random per-install ID (not the peer ID or
+// wallet), the app version and OS, NAT status, whether a relay is available,
+// and the peer count rounded into a bucket. Timestamps are truncated to the
+// hour. The last report sent is kept so the UI can show exactly what left the
+// machine, along with the exact time it was sent, which stays local and is
+// what the next report is scheduled from.
+
+use crate::dht::NatReachabilityState;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+const REPORT_SCHEMA_VERSION: u32 = 1;
+const SUBMIT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often the reporter wakes to check whether a report is due
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+const MIN_INTERVAL_MINUTES: u64 = 15;
+const HOUR_SECS: u64 = 60 * 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TelemetryConfig {
+    pub enabled: bool,
+    /// Project endpoint reports are POSTed to as JSON
+    pub endpoint: Option<String>,
+    /// Minutes between reports (at least 15)
+    pub interval_minutes: u64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: None,
+            interval_minutes: 60,
+        }
+    }
+}
+
+/// Node state the report is built from, gathered by the caller
+#[derive(Debug, Clone, Default)]
+pub struct TelemetrySample {
+    pub peer_count: Option<usize>,
+    pub reachability: Option<NatReachabilityState>,
+    pub relay_available: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TelemetryReport {
+    pub schema_version: u32,
+    /// Random ID generated once per install; not linked to the peer ID
+    pub install_id: String,
+    pub app_version: String,
+    pub os: String,
+    pub nat_status: Option<NatReachabilityState>,
+    pub relay_available: Option<bool>,
+    /// Peer count rounded into "0", "1-5", "6-20", "21-100" or "100+"
+    pub peer_count_bucket: Option<String>,
+    /// Unix timestamp (seconds), truncated to the hour
+    pub reported_at: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct PersistedTelemetry {
+    config: TelemetryConfig,
+    install_id: Option<String>,
+    last_report: Option<TelemetryReport>,
+    /// When `last_report` was sent, Unix seconds; never leaves the machine
+    last_sent_at: Option<u64>,
+}
+
+pub struct TelemetryReporter {
+    state: Mutex<PersistedTelemetry>,
+    persist_path: Option<PathBuf>,
+}
+
+pub fn peer_count_bucket(count: usize) -> &'static str {
+    match count {
+        0 => "0",
+        1..=5 => "1-5",
+        6..=20 => "6-20",
+        21..=100 => "21-100",
+        _ => "100+",
+    }
+}
+
+impl TelemetryReporter {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(PersistedTelemetry::default()),
+            persist_path: None,
+        }
+    }
+
+    /// Reporter whose settings, install ID and last report are kept at `path`
+    pub fn with_persistence(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let state = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|raw| serde_json::from_str::<PersistedTelemetry>(&raw).ok())
+            .unwrap_or_default();
+        Self {
+            state: Mutex::new(state),
+            persist_path: Some(path),
+        }
+    }
+
+    pub fn config(&self) -> TelemetryConfig {
+        self.state
+            .lock()
+            .map(|s| s.config.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn set_config(&self, mut config: TelemetryConfig) -> Result<(), String> {
+        config.interval_minutes = config.interval_minutes.max(MIN_INTERVAL_MINUTES);
+        if let Some(endpoint) = &config.endpoint {
+            if !endpoint.starts_with("https://") && !endpoint.starts_with("http://") {
+                return Err(format!("Invalid telemetry endpoint: {}", endpoint));
+            }
+        }
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| "Telemetry state lock poisoned".to_string())?;
+        state.config = config;
+        self.save(&state)
+    }
+
+    pub fn last_report(&self) -> Option<TelemetryReport> {
+        self.state.lock().ok().and_then(|s| s.last_report.clone())
+    }
+
+    /// The report that would be sent for `sample` at `now` (Unix seconds)
+    pub fn build_report(&self, sample: &TelemetrySample, now: u64) -> TelemetryReport {
+        let install_id = self.install_id();
+        TelemetryReport {
+            schema_version: REPORT_SCHEMA_VERSION,
+            install_id,
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            os: std::env::consts::OS.to_string(),
+            nat_status: sample.reachability,
+            relay_available: sample.relay_available,
+            peer_count_bucket: sample.peer_count.map(|c| peer_count_bucket(c).to_string()),
+            reported_at: now - now % HOUR_SECS,
+        }
+    }
+
+    /// Whether a report should be sent at `now` given the current settings
+    pub fn is_due(&self, now: u64) -> bool {
+        let Ok(state) = self.state.lock() else {
+            return false;
+        };
+        if !state.config.enabled || state.config.endpoint.is_none() {
+            return false;
+        }
+        let interval_secs = state.config.interval_minutes.max(MIN_INTERVAL_MINUTES) * 60;
+        // Reports saved before the send time was kept only have the hour
+        let last_sent = state
+            .last_sent_at
+            .or_else(|| state.last_report.as_ref().map(|last| last.reported_at));
+        last_sent.map_or(true, |last| now.saturating_sub(last) >= interval_secs)
+    }
+
+    /// POST `report` to the configured endpoint and remember it, sent at
+    /// `now`, on success
+    pub async fn submit(&self, report: TelemetryReport, now: u64) -> Result<(), String> {
+        let endpoint = self
+            .config()
+            .endpoint
+            .ok_or_else(|| "No telemetry endpoint configured".to_string())?;
+        let client = reqwest::Client::builder()
+            .timeout(SUBMIT_TIMEOUT)
+            .build()
+            .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+        let response = client
+            .post(&endpoint)
+            .json(&report)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to send telemetry report: {}", e))?;
+        if !response.status().is_success() {
+            return Err(format!(
+                "Telemetry endpoint returned status {}",
+                response.status()
+            ));
+        }
+
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| "Telemetry state lock poisoned".to_string())?;
+        self.record_sent(&mut state, report, now)
+    }
+
+    fn record_sent(
+        &self,
+        state: &mut PersistedTelemetry,
+        report: TelemetryReport,
+        now: u64,
+    ) -> Result<(), String> {
+        state.last_report = Some(report);
+        state.last_sent_at = Some(now);
+        self.save(state)
+    }
+
+    /// Check every minute and send a report when one is due. Does nothing
+    /// while telemetry is disabled.
+    pub fn spawn_reporter<F, Fut>(self: Arc<Self>, sampler: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = TelemetrySample> + Send,
+    {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                if !self.is_due(now) {
+                    continue;
+                }
+                let report = self.build_report(&sampler().await, now);
+                match self.submit(report, now).await {
+                    Ok(()) => debug!("Telemetry report sent"),
+                    Err(e) => warn!("{}", e),
+                }
+            }
+        });
+    }
+
+    fn install_id(&self) -> String {
+        let Ok(mut state) = self.state.lock() else {
+            return uuid::Uuid::new_v4().to_string();
+        };
+        if let Some(id) = &state.install_id {
+            return id.clone();
+        }
+        let id = uuid::Uuid::new_v4().to_string();
+        state.install_id = Some(id.clone());
+        if let Err(e) = self.save(&state) {
+            warn!("{}", e);
+        }
+        id
+    }
+
+    fn save(&self, state: &PersistedTelemetry) -> Result<(), String> {
+        let Some(path) = &self.persist_path else {
+            return Ok(());
+        };
+        let json = serde_json::to_string_pretty(state)
+            .map_err(|e| format!("Failed to serialize telemetry settings: {}", e))?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create config directory: {}", e))?;
+        }
+        std::fs::write(path, json).map_err(|e| format!("Failed to save telemetry settings: {}", e))
+    }
+}
+
+impl Default for TelemetryReporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_are_coarse_and_only_due_when_enabled() {
+        let reporter = TelemetryReporter::new();
+        let now = 1_700_000_123;
+        assert!(!reporter.is_due(now), "telemetry must be off by default");
+
+        let sample = TelemetrySample {
+            peer_count: Some(12),
+            reachability: Some(NatReachabilityState::Private),
+            relay_available: Some(true),
+        };
+        let report = reporter.build_report(&sample, now);
+        assert_eq!(report.peer_count_bucket.as_deref(), Some("6-20"));
+        assert_eq!(report.reported_at % HOUR_SECS, 0);
+        assert_eq!(reporter.build_report(&sample, now).install_id, report.install_id);
+
+        reporter
+            .set_config(TelemetryConfig {
+                enabled: true,
+                endpoint: None,
+                interval_minutes: 60,
+            })
+            .unwrap();
+        assert!(!reporter.is_due(now), "no endpoint, nothing to send");
+
+        reporter
+            .set_config(TelemetryConfig {
+                enabled: true,
+                endpoint: Some("https://telemetry.invalid/report".to_string()),
+                interval_minutes: 1,
+            })
+            .unwrap();
+        assert_eq!(reporter.config().interval_minutes, MIN_INTERVAL_MINUTES);
+        assert!(reporter.is_due(now));
+
+        // Due a full interval after the send, not after the hour it fell in
+        {
+            let mut state = reporter.state.lock().unwrap();
+            reporter.record_sent(&mut state, report, now).unwrap();
+        }
+        let interval = MIN_INTERVAL_MINUTES * 60;
+        assert!(!reporter.is_due(now + interval - 1));
+        assert!(reporter.is_due(now + interval));
+        assert!(reporter
+            .set_config(TelemetryConfig {
+                endpoint: Some("ftp://nope".to_string()),
+                ..TelemetryConfig::default()
+            })
+            .is_err());
+    }
+}
@@ -63,6 +63,12 @@ pub enum TransferEvent {
     
     /// Speed/bandwidth update (more frequent than progress updates)
     SpeedUpdate(SpeedUpdateEvent),
+
+    /// Throughput stayed below the configured floor for the grace period
+    SlowTransfer(SlowTransferEvent),
+
+    /// No bytes moved for the inactivity timeout; recovery is being attempted
+    Stalled(TransferStalledEvent),
 }
 
 /// Event when a transfer is added to the download queue
@@ -240,6 +246,40 @@ pub struct SpeedUpdateEvent {
     pub timestamp: u64,
 }
 
+/// Event when a transfer's throughput stays below the slow-transfer floor
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SlowTransferEvent {
+    pub transfer_id: String,
+    pub file_hash: String,
+    /// Average throughput over the grace window
+    pub throughput_bps: f64,
+    pub min_throughput_bps: u64,
+    pub slow_for_seconds: u64,
+    /// Slowest source, if one could be singled out
+    pub source_id: Option<String>,
+    /// Chunks moved from the slow source to other providers (0 when no
+    /// alternative source was available or auto-switching is off)
+    pub rerouted_chunks: u32,
+    pub detected_at: u64,
+}
+
+/// Event when no bytes have moved for the inactivity timeout
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferStalledEvent {
+    pub transfer_id: String,
+    pub file_hash: String,
+    pub idle_seconds: u64,
+    /// 1-based recovery attempt started for this stall
+    pub recovery_attempt: u32,
+    /// The transfer fails with a timeout after this many attempts
+    pub max_recovery_attempts: u32,
+    pub downloaded_bytes: u64,
+    pub total_bytes: u64,
+    pub detected_at: u64,
+}
+
 // ============================================================================
 // Supporting Types
 // ============================================================================
@@ -334,6 +374,8 @@ pub enum ErrorCategory {
     Authentication,
     NoSources,
     RateLimit,
+    /// No data received within the inactivity timeout
+    Timeout,
     Unknown,
 }
 
@@ -370,9 +412,12 @@ impl TransferEventBus {
             TransferEvent::Failed(_) => "failed",
             TransferEvent::Canceled(_) => "canceled",
             TransferEvent::SpeedUpdate(_) => "speed_update",
+            TransferEvent::SlowTransfer(_) => "slow_transfer",
+            TransferEvent::Stalled(_) => "stalled",
         };
 
         debug!("Emitting transfer event: {}", event_type);
+        crate::event_bus::global().publish_transfer(&event);
 
         // Emit to specific typed channel
         let typed_channel = format!("transfer:{}", event_type);
@@ -451,6 +496,16 @@ impl TransferEventBus {
         self.emit(TransferEvent::SpeedUpdate(event));
     }
 
+    /// Helper to emit slow transfer event
+    pub fn emit_slow_transfer(&self, event: SlowTransferEvent) {
+        self.emit(TransferEvent::SlowTransfer(event));
+    }
+
+    /// Helper to emit stalled event
+    pub fn emit_stalled(&self, event: TransferStalledEvent) {
+        self.emit(TransferEvent::Stalled(event));
+    }
+
     // =========================================================================
     // Analytics Integration
     // =========================================================================
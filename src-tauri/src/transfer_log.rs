@@ -0,0 +1,870 @@
+// Persistent Transfer Event Log
+//
+// Terminal transfer events (completed uploads/downloads, failures with their
+// reasons, cancellations) are appended to an embedded SQLite database so they
+// survive restarts and can be queried later ("what happened last night?").
+// Entries can be filtered by time, outcome, file and the peer that served
+// the most bytes of a download.
+//
+// The log is fed from the unified event bus, so every service that publishes
+// there is covered without further wiring. Progress and other intermediate
+// events are intentionally not stored. A transfer may be reported by more
+// than one service (the file transfer service always publishes its own
+// events, the GUI's transfer bus adds richer ones), so it keeps one row per
+// outcome that later reports only fill in. Failure and cancel events don't
+// name a peer, so downloads take it from the sources they started with.
+
+use crate::error_codes::ServiceError;
+use crate::event_bus::{self, EventEnvelope, EventPayload};
+use crate::file_transfer::progress;
+use crate::file_transfer::FileTransferEvent;
+use crate::transfer_events::TransferEvent;
+use directories::ProjectDirs;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Upper bound for a single page of results
+pub const MAX_PAGE_SIZE: u32 = 500;
+
+const DEFAULT_PAGE_SIZE: u32 = 50;
+
+/// Started downloads whose end the recorder keeps waiting for; more are
+/// only left behind when their end was missed
+const MAX_STARTED_DOWNLOADS: usize = 4096;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferDirection {
+    Upload,
+    Download,
+}
+
+impl TransferDirection {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TransferDirection::Upload => "upload",
+            TransferDirection::Download => "download",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "upload" => Some(TransferDirection::Upload),
+            "download" => Some(TransferDirection::Download),
+            _ => None,
+        }
+    }
+}
+
+impl From<progress::TransferDirection> for TransferDirection {
+    fn from(direction: progress::TransferDirection) -> Self {
+        match direction {
+            progress::TransferDirection::Upload => TransferDirection::Upload,
+            progress::TransferDirection::Download => TransferDirection::Download,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferOutcome {
+    Completed,
+    Failed,
+    Canceled,
+}
+
+impl TransferOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TransferOutcome::Completed => "completed",
+            TransferOutcome::Failed => "failed",
+            TransferOutcome::Canceled => "canceled",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "completed" => Some(TransferOutcome::Completed),
+            "failed" => Some(TransferOutcome::Failed),
+            "canceled" => Some(TransferOutcome::Canceled),
+            _ => None,
+        }
+    }
+}
+
+/// One row of the transfer log
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferLogEntry {
+    /// Row ID assigned by the database (0 before insertion)
+    #[serde(default)]
+    pub id: i64,
+    pub transfer_id: Option<String>,
+    pub file_hash: Option<String>,
+    pub file_name: Option<String>,
+    pub direction: TransferDirection,
+    pub outcome: TransferOutcome,
+    /// Human readable failure reason
+    pub error: Option<String>,
+    /// Source that provided the most bytes, when known
+    #[serde(default)]
+    pub peer: Option<String>,
+    pub bytes: u64,
+    pub duration_ms: Option<u64>,
+    /// Unix timestamp in milliseconds
+    pub occurred_at: u64,
+}
+
+/// Filters for `TransferLog::query`; all fields are optional
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TransferLogQuery {
+    pub direction: Option<TransferDirection>,
+    pub outcome: Option<TransferOutcome>,
+    pub file_hash: Option<String>,
+    pub peer: Option<String>,
+    /// Inclusive lower bound, Unix ms
+    pub since: Option<u64>,
+    /// Exclusive upper bound, Unix ms
+    pub until: Option<u64>,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+/// A page of results, newest first
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferLogPage {
+    pub entries: Vec<TransferLogEntry>,
+    /// Number of rows matching the filters, ignoring pagination
+    pub total: u64,
+    pub limit: u32,
+    pub offset: u32,
+}
+
+pub struct TransferLog {
+    conn: Mutex<Connection>,
+}
+
+impl TransferLog {
+    /// Open (or create) the log at `path`
+    pub fn open(path: &Path) -> Result<Self, String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create transfer log directory: {}", e))?;
+        }
+        let conn = Connection::open(path)
+            .map_err(|e| format!("Failed to open transfer log: {}", e))?;
+        Self::with_connection(conn)
+    }
+
+    /// Open the log in the application data directory
+    pub fn open_default() -> Result<Self, String> {
+        let proj_dirs = ProjectDirs::from("com", "chiral-network", "chiral-network")
+            .ok_or("Failed to get project directories")?;
+        Self::open(&proj_dirs.data_dir().join("transfer_log.sqlite3"))
+    }
+
+    pub fn open_in_memory() -> Result<Self, String> {
+        let conn = Connection::open_in_memory()
+            .map_err(|e| format!("Failed to open transfer log: {}", e))?;
+        Self::with_connection(conn)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self, String> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS transfer_events (
+                 id          INTEGER PRIMARY KEY AUTOINCREMENT,
+                 transfer_id TEXT,
+                 file_hash   TEXT,
+                 file_name   TEXT,
+                 direction   TEXT NOT NULL,
+                 outcome     TEXT NOT NULL,
+                 error       TEXT,
+                 bytes       INTEGER NOT NULL DEFAULT 0,
+                 duration_ms INTEGER,
+                 occurred_at INTEGER NOT NULL,
+                 peer        TEXT
+             );
+             CREATE INDEX IF NOT EXISTS idx_transfer_events_occurred_at
+                 ON transfer_events (occurred_at);
+             CREATE INDEX IF NOT EXISTS idx_transfer_events_file_hash
+                 ON transfer_events (file_hash);
+             CREATE INDEX IF NOT EXISTS idx_transfer_events_transfer_id
+                 ON transfer_events (transfer_id);",
+        )
+        .map_err(|e| format!("Failed to initialize transfer log schema: {}", e))?;
+        // Logs created before peers were recorded lack the column
+        let has_peer = conn
+            .prepare("SELECT peer FROM transfer_events LIMIT 0")
+            .is_ok();
+        if !has_peer {
+            conn.execute("ALTER TABLE transfer_events ADD COLUMN peer TEXT", [])
+                .map_err(|e| format!("Failed to migrate transfer log schema: {}", e))?;
+        }
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_transfer_events_peer ON transfer_events (peer)",
+            [],
+        )
+        .map_err(|e| format!("Failed to initialize transfer log schema: {}", e))?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Append an entry and return its row ID. An entry for a transfer and
+    /// outcome already logged is merged into that row instead: columns it
+    /// left empty are filled in and the larger byte count is kept.
+    pub fn record(&self, entry: &TransferLogEntry) -> Result<i64, String> {
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "Transfer log lock poisoned".to_string())?;
+        if let Some(transfer_id) = &entry.transfer_id {
+            let existing: Option<i64> = conn
+                .query_row(
+                    "SELECT id FROM transfer_events
+                     WHERE transfer_id = ?1 AND direction = ?2 AND outcome = ?3",
+                    params![
+                        transfer_id,
+                        entry.direction.as_str(),
+                        entry.outcome.as_str()
+                    ],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(|e| format!("Failed to read transfer log entry: {}", e))?;
+            if let Some(id) = existing {
+                conn.execute(
+                    "UPDATE transfer_events SET
+                         file_hash = COALESCE(file_hash, ?2),
+                         file_name = COALESCE(file_name, ?3),
+                         error = COALESCE(error, ?4),
+                         bytes = MAX(bytes, ?5),
+                         duration_ms = COALESCE(duration_ms, ?6),
+                         peer = COALESCE(peer, ?7)
+                     WHERE id = ?1",
+                    params![
+                        id,
+                        entry.file_hash,
+                        entry.file_name,
+                        entry.error,
+                        entry.bytes as i64,
+                        entry.duration_ms.map(|d| d as i64),
+                        entry.peer,
+                    ],
+                )
+                .map_err(|e| format!("Failed to write transfer log entry: {}", e))?;
+                return Ok(id);
+            }
+        }
+        conn.execute(
+            "INSERT INTO transfer_events
+                 (transfer_id, file_hash, file_name, direction, outcome, error, bytes, duration_ms, occurred_at, peer)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                entry.transfer_id,
+                entry.file_hash,
+                entry.file_name,
+                entry.direction.as_str(),
+                entry.outcome.as_str(),
+                entry.error,
+                entry.bytes as i64,
+                entry.duration_ms.map(|d| d as i64),
+                entry.occurred_at as i64,
+                entry.peer,
+            ],
+        )
+        .map_err(|e| format!("Failed to write transfer log entry: {}", e))?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// Filtered, paginated query, newest entries first
+    pub fn query(&self, query: &TransferLogQuery) -> Result<TransferLogPage, String> {
+        let mut clauses: Vec<&str> = Vec::new();
+        let mut values: Vec<rusqlite::types::Value> = Vec::new();
+
+        if let Some(direction) = query.direction {
+            clauses.push("direction = ?");
+            values.push(direction.as_str().to_string().into());
+        }
+        if let Some(outcome) = query.outcome {
+            clauses.push("outcome = ?");
+            values.push(outcome.as_str().to_string().into());
+        }
+        if let Some(file_hash) = &query.file_hash {
+            clauses.push("file_hash = ?");
+            values.push(file_hash.clone().into());
+        }
+        if let Some(peer) = &query.peer {
+            clauses.push("peer = ?");
+            values.push(peer.clone().into());
+        }
+        if let Some(since) = query.since {
+            clauses.push("occurred_at >= ?");
+            values.push((since as i64).into());
+        }
+        if let Some(until) = query.until {
+            clauses.push("occurred_at < ?");
+            values.push((until as i64).into());
+        }
+
+        let where_sql = if clauses.is_empty() {
+            String::new()
+        } else {
+            format!(" WHERE {}", clauses.join(" AND "))
+        };
+        let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+        let offset = query.offset.unwrap_or(0);
+
+        let conn = self
+            .conn
+            .lock()
+            .map_err(|_| "Transfer log lock poisoned".to_string())?;
+
+        let total: i64 = conn
+            .query_row(
+                &format!("SELECT COUNT(*) FROM transfer_events{}", where_sql),
+                params_from_iter(values.iter()),
+                |row| row.get(0),
+            )
+            .map_err(|e| format!("Failed to count transfer log entries: {}", e))?;
+
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT id, transfer_id, file_hash, file_name, direction, outcome, error, bytes, duration_ms, occurred_at, peer
+                 FROM transfer_events{}
+                 ORDER BY occurred_at DESC, id DESC
+                 LIMIT {} OFFSET {}",
+                where_sql, limit, offset
+            ))
+            .map_err(|e| format!("Failed to prepare transfer log query: {}", e))?;
+
+        let entries = stmt
+            .query_map(params_from_iter(values.iter()), |row| {
+                let direction: String = row.get(4)?;
+                let outcome: String = row.get(5)?;
+                Ok(TransferLogEntry {
+                    id: row.get(0)?,
+                    transfer_id: row.get(1)?,
+                    file_hash: row.get(2)?,
+                    file_name: row.get(3)?,
+                    direction: TransferDirection::parse(&direction)
+                        .unwrap_or(TransferDirection::Download),
+                    outcome: TransferOutcome::parse(&outcome).unwrap_or(TransferOutcome::Failed),
+                    error: row.get(6)?,
+                    peer: row.get(10)?,
+                    bytes: row.get::<_, i64>(7)? as u64,
+                    duration_ms: row.get::<_, Option<i64>>(8)?.map(|d| d as u64),
+                    occurred_at: row.get::<_, i64>(9)? as u64,
+                })
+            })
+            .map_err(|e| format!("Failed to query transfer log: {}", e))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Failed to read transfer log row: {}", e))?;
+
+        Ok(TransferLogPage {
+            entries,
+            total: total as u64,
+            limit,
+            offset,
+        })
+    }
+}
+
+/// Map a bus envelope to a log entry if it represents a terminal transfer event
+pub fn entry_from_envelope(envelope: &EventEnvelope) -> Option<TransferLogEntry> {
+    let occurred_at = envelope.timestamp;
+    match &envelope.payload {
+        EventPayload::Transfer(TransferEvent::Completed(e)) => Some(TransferLogEntry {
+            id: 0,
+            transfer_id: Some(e.transfer_id.clone()),
+            file_hash: Some(e.file_hash.clone()),
+            file_name: Some(base_name(&e.file_name)),
+            direction: TransferDirection::Download,
+            outcome: TransferOutcome::Completed,
+            error: None,
+            peer: e
+                .sources_used
+                .iter()
+                .max_by_key(|source| source.bytes_provided)
+                .map(|source| source.source_id.clone()),
+            bytes: e.file_size,
+            duration_ms: Some(e.duration_seconds.saturating_mul(1000)),
+            occurred_at,
+        }),
+        EventPayload::Transfer(TransferEvent::Failed(e)) => Some(TransferLogEntry {
+            id: 0,
+            transfer_id: Some(e.transfer_id.clone()),
+            file_hash: Some(e.file_hash.clone()),
+            file_name: None,
+            direction: TransferDirection::Download,
+            outcome: TransferOutcome::Failed,
+            error: Some(
+                envelope
+                    .error
+                    .as_ref()
+                    .map_or_else(|| e.error.clone(), |err| err.to_string()),
+            ),
+            peer: None,
+            bytes: e.downloaded_bytes,
+            duration_ms: None,
+            occurred_at,
+        }),
+        EventPayload::Transfer(TransferEvent::Canceled(e)) => Some(TransferLogEntry {
+            id: 0,
+            transfer_id: Some(e.transfer_id.clone()),
+            file_hash: None,
+            file_name: None,
+            direction: TransferDirection::Download,
+            outcome: TransferOutcome::Canceled,
+            error: None,
+            peer: None,
+            bytes: e.downloaded_bytes,
+            duration_ms: None,
+            occurred_at,
+        }),
+        EventPayload::FileTransfer(FileTransferEvent::FileDownloaded {
+            transfer_id,
+            file_path,
+        }) => Some(TransferLogEntry {
+            id: 0,
+            transfer_id: Some(transfer_id.clone()),
+            file_hash: None,
+            file_name: Some(base_name(file_path)),
+            direction: TransferDirection::Download,
+            outcome: TransferOutcome::Completed,
+            error: None,
+            peer: None,
+            bytes: std::fs::metadata(file_path).map_or(0, |m| m.len()),
+            duration_ms: None,
+            occurred_at,
+        }),
+        EventPayload::FileTransfer(FileTransferEvent::FileNotFound {
+            transfer_id,
+            file_hash,
+        }) => Some(TransferLogEntry {
+            id: 0,
+            transfer_id: Some(transfer_id.clone()),
+            file_hash: Some(file_hash.clone()),
+            file_name: None,
+            direction: TransferDirection::Download,
+            outcome: TransferOutcome::Failed,
+            error: envelope.error.as_ref().map(|err| err.to_string()),
+            peer: None,
+            bytes: 0,
+            duration_ms: None,
+            occurred_at,
+        }),
+        EventPayload::FileTransfer(FileTransferEvent::Cancelled { transfer_id }) => {
+            Some(TransferLogEntry {
+                id: 0,
+                transfer_id: Some(transfer_id.clone()),
+                file_hash: None,
+                file_name: None,
+                direction: TransferDirection::Download,
+                outcome: TransferOutcome::Canceled,
+                error: None,
+                peer: None,
+                bytes: 0,
+                duration_ms: None,
+                occurred_at,
+            })
+        }
+        EventPayload::FileTransfer(FileTransferEvent::FileUploaded {
+            transfer_id,
+            file_hash,
+            file_name,
+            file_size,
+        }) => Some(TransferLogEntry {
+            id: 0,
+            transfer_id: Some(transfer_id.clone()),
+            file_hash: Some(file_hash.clone()),
+            file_name: Some(file_name.clone()),
+            direction: TransferDirection::Upload,
+            outcome: TransferOutcome::Completed,
+            error: None,
+            peer: None,
+            bytes: *file_size,
+            duration_ms: None,
+            occurred_at,
+        }),
+        EventPayload::FileTransfer(FileTransferEvent::Error {
+            transfer_id,
+            direction,
+            code,
+            message,
+        }) => Some(TransferLogEntry {
+            id: 0,
+            transfer_id: Some(transfer_id.clone()),
+            file_hash: None,
+            file_name: None,
+            direction: (*direction).into(),
+            outcome: TransferOutcome::Failed,
+            error: Some(ServiceError::new(*code, message.clone()).to_string()),
+            peer: None,
+            bytes: 0,
+            duration_ms: None,
+            occurred_at,
+        }),
+        _ => None,
+    }
+}
+
+/// Last component of `path`, as file names are shown
+fn base_name(path: &str) -> String {
+    Path::new(path).file_name().map_or_else(
+        || path.to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    )
+}
+
+/// The first source each running download was started with
+#[derive(Default)]
+struct StartedSources {
+    peers: HashMap<String, String>,
+}
+
+impl StartedSources {
+    /// Map `envelope` like `entry_from_envelope`, naming the peer a failed or
+    /// canceled download started with when its event doesn't
+    fn entry(&mut self, envelope: &EventEnvelope) -> Option<TransferLogEntry> {
+        if let EventPayload::Transfer(TransferEvent::Started(e)) = &envelope.payload {
+            // Downloads without providers start from local storage
+            let peer = e
+                .selected_sources
+                .first()
+                .filter(|id| *id != "local-storage");
+            if let Some(peer) = peer {
+                if self.peers.len() >= MAX_STARTED_DOWNLOADS {
+                    self.peers.clear();
+                }
+                self.peers.insert(e.transfer_id.clone(), peer.clone());
+            }
+            return None;
+        }
+        let mut entry = entry_from_envelope(envelope)?;
+        if let (TransferDirection::Download, Some(transfer_id)) =
+            (entry.direction, &entry.transfer_id)
+        {
+            let started_with = self.peers.remove(transfer_id);
+            if entry.outcome != TransferOutcome::Completed && entry.peer.is_none() {
+                entry.peer = started_with;
+            }
+        }
+        Some(entry)
+    }
+}
+
+/// Record terminal events from the unified event bus into `log` until the bus closes
+pub async fn run_recorder(log: Arc<TransferLog>) {
+    let mut started = StartedSources::default();
+    event_bus::record_events(
+        "Transfer log",
+        move |envelope| started.entry(envelope),
+        move |entry| log.record(entry),
+    )
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(direction: TransferDirection, outcome: TransferOutcome, at: u64) -> TransferLogEntry {
+        TransferLogEntry {
+            id: 0,
+            transfer_id: Some(format!("t-{}", at)),
+            file_hash: Some(format!("hash-{}", at % 2)),
+            file_name: None,
+            direction,
+            outcome,
+            error: matches!(outcome, TransferOutcome::Failed).then(|| "timeout".to_string()),
+            peer: Some(format!("peer-{}", at % 3)),
+            bytes: 10,
+            duration_ms: Some(5),
+            occurred_at: at,
+        }
+    }
+
+    #[test]
+    fn query_filters_and_paginates_newest_first() {
+        let log = TransferLog::open_in_memory().unwrap();
+        for at in 1..=10 {
+            let outcome = if at % 3 == 0 {
+                TransferOutcome::Failed
+            } else {
+                TransferOutcome::Completed
+            };
+            log.record(&entry(TransferDirection::Download, outcome, at)).unwrap();
+        }
+        log.record(&entry(TransferDirection::Upload, TransferOutcome::Completed, 11))
+            .unwrap();
+
+        let failed = log
+            .query(&TransferLogQuery {
+                outcome: Some(TransferOutcome::Failed),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(failed.total, 3);
+        assert_eq!(
+            failed.entries.iter().map(|e| e.occurred_at).collect::<Vec<_>>(),
+            vec![9, 6, 3]
+        );
+        assert_eq!(failed.entries[0].error.as_deref(), Some("timeout"));
+
+        let page = log
+            .query(&TransferLogQuery {
+                direction: Some(TransferDirection::Download),
+                since: Some(2),
+                limit: Some(3),
+                offset: Some(3),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(page.total, 9);
+        assert_eq!(
+            page.entries.iter().map(|e| e.occurred_at).collect::<Vec<_>>(),
+            vec![7, 6, 5]
+        );
+
+        let from_peer = log
+            .query(&TransferLogQuery {
+                peer: Some("peer-1".to_string()),
+                until: Some(10),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(
+            from_peer
+                .entries
+                .iter()
+                .map(|e| e.occurred_at)
+                .collect::<Vec<_>>(),
+            vec![7, 4, 1]
+        );
+    }
+
+    #[test]
+    fn upload_events_carry_their_direction_and_size() {
+        let envelope = |payload: FileTransferEvent| EventEnvelope {
+            id: 1,
+            source: event_bus::EventSource::FileTransfer,
+            severity: event_bus::EventSeverity::Info,
+            correlation_id: None,
+            timestamp: 5,
+            error: None,
+            payload: EventPayload::FileTransfer(payload),
+        };
+        let uploaded = entry_from_envelope(&envelope(FileTransferEvent::FileUploaded {
+            transfer_id: "t-1".into(),
+            file_hash: "hash".into(),
+            file_name: "a.bin".into(),
+            file_size: 42,
+        }))
+        .unwrap();
+        assert_eq!(uploaded.direction, TransferDirection::Upload);
+        assert_eq!(uploaded.bytes, 42);
+
+        // The direction comes from the event, not from the wording of the message
+        let failed = entry_from_envelope(&envelope(FileTransferEvent::Error {
+            transfer_id: "t-2".into(),
+            direction: progress::TransferDirection::Upload,
+            code: crate::error_codes::ErrorCode::WriteFailed,
+            message: "Folder upload failed: disk full".into(),
+        }))
+        .unwrap();
+        assert_eq!(failed.direction, TransferDirection::Upload);
+        assert_eq!(failed.outcome, TransferOutcome::Failed);
+    }
+
+    #[test]
+    fn a_transfer_reported_by_both_buses_is_logged_once() {
+        use crate::transfer_events::{
+            ErrorCategory, SourceSummary, SourceType, TransferCompletedEvent, TransferFailedEvent,
+        };
+
+        let envelope = |payload: EventPayload| EventEnvelope {
+            id: 1,
+            source: event_bus::EventSource::FileTransfer,
+            severity: event_bus::EventSeverity::Info,
+            correlation_id: None,
+            timestamp: 5,
+            error: None,
+            payload,
+        };
+        let source = |id: &str, bytes| SourceSummary {
+            source_id: id.into(),
+            source_type: SourceType::P2p,
+            chunks_provided: 1,
+            bytes_provided: bytes,
+            average_speed_bps: 0.0,
+            connection_duration_seconds: 1,
+        };
+        let downloaded = envelope(EventPayload::FileTransfer(
+            FileTransferEvent::FileDownloaded {
+                transfer_id: "t-1".into(),
+                file_path: "/missing/out/a.bin".into(),
+            },
+        ));
+        let completed = envelope(EventPayload::Transfer(TransferEvent::Completed(
+            TransferCompletedEvent {
+                transfer_id: "t-1".into(),
+                file_hash: "hash-1".into(),
+                file_name: "/missing/out/a.bin".into(),
+                file_size: 42,
+                output_path: "/missing/out/a.bin".into(),
+                completed_at: 5,
+                duration_seconds: 2,
+                average_speed_bps: 0.0,
+                total_chunks: 2,
+                sources_used: vec![source("peer-a", 30), source("peer-b", 12)],
+            },
+        )));
+        let failed = envelope(EventPayload::Transfer(TransferEvent::Failed(
+            TransferFailedEvent {
+                transfer_id: "t-2".into(),
+                file_hash: "hash-2".into(),
+                failed_at: 5,
+                error: "no seeders".into(),
+                error_category: ErrorCategory::NoSources,
+                downloaded_bytes: 7,
+                total_bytes: 10,
+                retry_possible: true,
+            },
+        )));
+        let error = envelope(EventPayload::FileTransfer(FileTransferEvent::Error {
+            transfer_id: "t-2".into(),
+            direction: progress::TransferDirection::Download,
+            code: crate::error_codes::ErrorCode::WriteFailed,
+            message: "no seeders".into(),
+        }));
+
+        let log = TransferLog::open_in_memory().unwrap();
+        // Either report may arrive first
+        for envelope in [&downloaded, &completed, &failed, &error] {
+            log.record(&entry_from_envelope(envelope).unwrap()).unwrap();
+        }
+        let page = log.query(&TransferLogQuery::default()).unwrap();
+        assert_eq!(page.total, 2);
+        let row = |id: &str| {
+            page.entries
+                .iter()
+                .find(|e| e.transfer_id.as_deref() == Some(id))
+                .unwrap()
+        };
+        let done = row("t-1");
+        assert_eq!(done.outcome, TransferOutcome::Completed);
+        assert_eq!(done.file_hash.as_deref(), Some("hash-1"));
+        assert_eq!(done.file_name.as_deref(), Some("a.bin"));
+        assert_eq!((done.peer.as_deref(), done.bytes), (Some("peer-a"), 42));
+        assert_eq!(done.duration_ms, Some(2000));
+        let failed = row("t-2");
+        assert_eq!(failed.outcome, TransferOutcome::Failed);
+        assert_eq!(failed.file_hash.as_deref(), Some("hash-2"));
+        assert_eq!(
+            (failed.error.as_deref(), failed.bytes),
+            (Some("no seeders"), 7)
+        );
+    }
+
+    #[test]
+    fn failed_and_canceled_downloads_name_the_peer_they_started_with() {
+        use crate::transfer_events::TransferStartedEvent;
+
+        let envelope = |payload: EventPayload| EventEnvelope {
+            id: 1,
+            source: event_bus::EventSource::FileTransfer,
+            severity: event_bus::EventSeverity::Info,
+            correlation_id: None,
+            timestamp: 5,
+            error: None,
+            payload,
+        };
+        let started = |transfer_id: &str, sources: &[&str]| {
+            envelope(EventPayload::Transfer(TransferEvent::Started(
+                TransferStartedEvent {
+                    transfer_id: transfer_id.into(),
+                    file_hash: "hash".into(),
+                    file_name: "a.bin".into(),
+                    file_size: 0,
+                    total_chunks: 0,
+                    chunk_size: 0,
+                    started_at: 5,
+                    available_sources: Vec::new(),
+                    selected_sources: sources.iter().map(|s| s.to_string()).collect(),
+                },
+            )))
+        };
+        let failed = |transfer_id: &str| {
+            envelope(EventPayload::FileTransfer(FileTransferEvent::Error {
+                transfer_id: transfer_id.into(),
+                direction: progress::TransferDirection::Download,
+                code: crate::error_codes::ErrorCode::Timeout,
+                message: "timed out".into(),
+            }))
+        };
+        let canceled = |transfer_id: &str| {
+            envelope(EventPayload::FileTransfer(FileTransferEvent::Cancelled {
+                transfer_id: transfer_id.into(),
+            }))
+        };
+
+        let mut sources = StartedSources::default();
+        assert!(sources
+            .entry(&started("t-1", &["peer-a", "peer-b"]))
+            .is_none());
+        sources.entry(&started("t-2", &["peer-c"]));
+        sources.entry(&started("t-3", &["local-storage"]));
+
+        assert_eq!(
+            sources.entry(&failed("t-1")).unwrap().peer.as_deref(),
+            Some("peer-a")
+        );
+        assert_eq!(
+            sources.entry(&canceled("t-2")).unwrap().peer.as_deref(),
+            Some("peer-c")
+        );
+        assert_eq!(sources.entry(&failed("t-3")).unwrap().peer, None);
+        // A download is forgotten once it ends
+        assert!(sources.peers.is_empty());
+    }
+
+    #[test]
+    fn logs_without_a_peer_column_are_migrated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("transfer_log.sqlite3");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE transfer_events (
+                 id          INTEGER PRIMARY KEY AUTOINCREMENT,
+                 transfer_id TEXT,
+                 file_hash   TEXT,
+                 file_name   TEXT,
+                 direction   TEXT NOT NULL,
+                 outcome     TEXT NOT NULL,
+                 error       TEXT,
+                 bytes       INTEGER NOT NULL DEFAULT 0,
+                 duration_ms INTEGER,
+                 occurred_at INTEGER NOT NULL
+             );
+             INSERT INTO transfer_events (direction, outcome, occurred_at)
+                 VALUES ('download', 'completed', 1);",
+        )
+        .unwrap();
+        drop(conn);
+
+        let log = TransferLog::open(&path).unwrap();
+        let upgraded = entry(TransferDirection::Download, TransferOutcome::Completed, 2);
+        log.record(&upgraded).unwrap();
+        let page = log.query(&TransferLogQuery::default()).unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(page.entries[0].peer.as_deref(), Some("peer-2"));
+        assert_eq!(page.entries[1].peer, None);
+    }
+}
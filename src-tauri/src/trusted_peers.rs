@@ -0,0 +1,341 @@
+// Trusted peers ("friends")
+//
+// Peer IDs the user trusts get three privileges, each of which can be turned
+// off: they are served first when upload slots are scarce, they can download
+// without the payment checkpoints if the user allows it, and they are picked
+// ahead of other P2P sources when downloading. Upload slots are shared by all
+// WebRTC uploads; a slot that frees up goes to a waiting trusted peer before
+// anyone else.
+//
+// The list and its settings are persisted together.
+
+use crate::runtime_env::{Clock, SystemClock};
+use directories::ProjectDirs;
+use libp2p::PeerId;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TrustedPeer {
+    pub peer_id: String,
+    pub label: Option<String>,
+    pub added_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TrustedPeerSettings {
+    /// Concurrent WebRTC uploads
+    pub upload_slots: usize,
+    /// Serve trusted peers before others waiting for a slot
+    pub priority_upload: bool,
+    /// Skip payment checkpoints when serving trusted peers
+    pub bypass_payment: bool,
+    /// Prefer trusted peers as download sources
+    pub prefer_as_sources: bool,
+}
+
+impl Default for TrustedPeerSettings {
+    fn default() -> Self {
+        Self {
+            upload_slots: 4,
+            priority_upload: true,
+            bypass_payment: false,
+            prefer_as_sources: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct TrustedPeerState {
+    peers: Vec<TrustedPeer>,
+    settings: TrustedPeerSettings,
+}
+
+#[derive(Default)]
+struct SlotState {
+    in_use: usize,
+    /// Trusted peers currently waiting for a slot
+    waiting_trusted: usize,
+}
+
+pub struct TrustedPeers {
+    state: Mutex<TrustedPeerState>,
+    persist_path: Option<PathBuf>,
+    slots: Mutex<SlotState>,
+    slot_freed: Notify,
+    clock: Arc<dyn Clock>,
+}
+
+/// An upload slot, released on drop
+pub struct UploadSlot<'a> {
+    owner: &'a TrustedPeers,
+}
+
+/// Counts a trusted peer as waiting until it gets a slot or gives up
+struct WaitingTrusted<'a> {
+    owner: &'a TrustedPeers,
+}
+
+static GLOBAL_TRUSTED_PEERS: Lazy<TrustedPeers> = Lazy::new(default_list);
+
+fn default_list() -> TrustedPeers {
+    match ProjectDirs::from("com", "chiral-network", "chiral-network") {
+        Some(dirs) => TrustedPeers::with_persistence(dirs.config_dir().join("trusted_peers.json")),
+        None => TrustedPeers::new(),
+    }
+}
+
+pub fn global() -> &'static TrustedPeers {
+    &GLOBAL_TRUSTED_PEERS
+}
+
+impl TrustedPeers {
+    pub fn new() -> Self {
+        Self::from_state(TrustedPeerState::default(), None)
+    }
+
+    /// A list loaded from and saved to `path`
+    pub fn with_persistence(path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref().to_path_buf();
+        let state = std::fs::read_to_string(&path)
+            .ok()
+            .and_then(|raw| serde_json::from_str::<TrustedPeerState>(&raw).ok())
+            .unwrap_or_default();
+        Self::from_state(state, Some(path))
+    }
+
+    /// Read the time from `clock` instead of the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn from_state(state: TrustedPeerState, persist_path: Option<PathBuf>) -> Self {
+        Self {
+            state: Mutex::new(state),
+            persist_path,
+            slots: Mutex::new(SlotState::default()),
+            slot_freed: Notify::new(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    fn update<T>(&self, f: impl FnOnce(&mut TrustedPeerState) -> T) -> Result<T, String> {
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| "Trusted peer list lock poisoned".to_string())?;
+        let result = f(&mut state);
+        if let Some(path) = &self.persist_path {
+            let json = serde_json::to_string_pretty(&*state)
+                .map_err(|e| format!("Failed to serialize trusted peers: {}", e))?;
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| format!("Failed to create config directory: {}", e))?;
+            }
+            std::fs::write(path, json)
+                .map_err(|e| format!("Failed to save trusted peers: {}", e))?;
+        }
+        Ok(result)
+    }
+
+    pub fn list(&self) -> Vec<TrustedPeer> {
+        self.state
+            .lock()
+            .map(|s| s.peers.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn settings(&self) -> TrustedPeerSettings {
+        self.state
+            .lock()
+            .map(|s| s.settings.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn set_settings(&self, settings: TrustedPeerSettings) -> Result<(), String> {
+        if settings.upload_slots == 0 {
+            return Err("At least one upload slot is required".to_string());
+        }
+        self.update(|state| state.settings = settings)?;
+        // More slots may be available now
+        self.slot_freed.notify_waiters();
+        Ok(())
+    }
+
+    /// Add `peer_id` or update its label
+    pub fn add(&self, peer_id: &str, label: Option<String>) -> Result<TrustedPeer, String> {
+        peer_id
+            .parse::<PeerId>()
+            .map_err(|e| format!("Invalid peer ID: {}", e))?;
+        self.update(
+            |state| match state.peers.iter_mut().find(|p| p.peer_id == peer_id) {
+                Some(existing) => {
+                    existing.label = label;
+                    existing.clone()
+                }
+                None => {
+                    let peer = TrustedPeer {
+                        peer_id: peer_id.to_string(),
+                        label,
+                        added_at: self.clock.now_secs(),
+                    };
+                    state.peers.push(peer.clone());
+                    peer
+                }
+            },
+        )
+    }
+
+    pub fn remove(&self, peer_id: &str) -> Result<bool, String> {
+        self.update(|state| {
+            let before = state.peers.len();
+            state.peers.retain(|p| p.peer_id != peer_id);
+            state.peers.len() != before
+        })
+    }
+
+    pub fn is_trusted(&self, peer_id: &str) -> bool {
+        self.state
+            .lock()
+            .map(|s| s.peers.iter().any(|p| p.peer_id == peer_id))
+            .unwrap_or(false)
+    }
+
+    /// Whether uploads to `peer_id` skip the payment checkpoints
+    pub fn bypasses_payment(&self, peer_id: &str) -> bool {
+        self.settings().bypass_payment && self.is_trusted(peer_id)
+    }
+
+    /// Whether `peer_id` should be picked ahead of other P2P sources
+    pub fn preferred_source(&self, peer_id: &str) -> bool {
+        self.settings().prefer_as_sources && self.is_trusted(peer_id)
+    }
+
+    fn try_take_slot(&self, trusted: bool) -> bool {
+        let limit = self.settings().upload_slots;
+        let mut slots = self.slots.lock().unwrap_or_else(|e| e.into_inner());
+        let queued_ahead = !trusted && slots.waiting_trusted > 0;
+        if slots.in_use < limit && !queued_ahead {
+            slots.in_use += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Wait for an upload slot for `peer_id`. With priority uploads on,
+    /// trusted peers take freed slots before other waiting peers.
+    pub async fn acquire_upload_slot(&self, peer_id: &str) -> UploadSlot<'_> {
+        let trusted = self.settings().priority_upload && self.is_trusted(peer_id);
+        let mut waiting: Option<WaitingTrusted<'_>> = None;
+        loop {
+            // Created before checking so a release in between is not missed
+            let freed = self.slot_freed.notified();
+            if self.try_take_slot(trusted) {
+                drop(waiting);
+                return UploadSlot { owner: self };
+            }
+            if trusted && waiting.is_none() {
+                self.slots
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .waiting_trusted += 1;
+                waiting = Some(WaitingTrusted { owner: self });
+            }
+            freed.await;
+        }
+    }
+
+    /// Slots in use and the configured limit
+    pub fn upload_slot_usage(&self) -> (usize, usize) {
+        let in_use = self.slots.lock().map(|s| s.in_use).unwrap_or(0);
+        (in_use, self.settings().upload_slots)
+    }
+}
+
+impl Default for TrustedPeers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for UploadSlot<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut slots) = self.owner.slots.lock() {
+            slots.in_use = slots.in_use.saturating_sub(1);
+        }
+        self.owner.slot_freed.notify_waiters();
+    }
+}
+
+impl Drop for WaitingTrusted<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut slots) = self.owner.slots.lock() {
+            slots.waiting_trusted = slots.waiting_trusted.saturating_sub(1);
+        }
+        // Regular peers held back for this one may proceed
+        self.owner.slot_freed.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn trusted_peers_take_freed_slots_first() {
+        let peers = Arc::new(TrustedPeers::new());
+        let friend = PeerId::random().to_string();
+        let stranger = PeerId::random().to_string();
+        peers.add(&friend, Some("Alice".into())).unwrap();
+        assert!(peers.add("not-a-peer", None).is_err());
+        assert!(peers.is_trusted(&friend));
+        assert!(!peers.bypasses_payment(&friend));
+        assert!(peers.preferred_source(&friend));
+        peers
+            .set_settings(TrustedPeerSettings {
+                upload_slots: 1,
+                bypass_payment: true,
+                ..Default::default()
+            })
+            .unwrap();
+        assert!(peers.bypasses_payment(&friend));
+        assert!(!peers.bypasses_payment(&stranger));
+
+        let held = peers.acquire_upload_slot(&stranger).await;
+        assert_eq!(peers.upload_slot_usage(), (1, 1));
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let waiter = |peer: String| {
+            let peers = peers.clone();
+            let order = order.clone();
+            tokio::spawn(async move {
+                let _slot = peers.acquire_upload_slot(&peer).await;
+                order.lock().unwrap().push(peer);
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            })
+        };
+        // The stranger queues first, the friend still goes first
+        let second = waiter(stranger.clone());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let first = waiter(friend.clone());
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        drop(held);
+        first.await.unwrap();
+        second.await.unwrap();
+        assert_eq!(*order.lock().unwrap(), vec![friend.clone(), stranger]);
+        assert_eq!(peers.upload_slot_usage(), (0, 1));
+
+        assert!(peers.remove(&friend).unwrap());
+        assert!(!peers.is_trusted(&friend));
+    }
+}
@@ -1,11 +1,16 @@
 use crate::connection_retry::{ConnectionManager, ConnectionState, RetryConfig, WebRtcRetryContext, };
 use crate::encryption::{decrypt_aes_key, encrypt_aes_key, EncryptedAesKeyBundle, FileEncryption};
+use crate::file_transfer::buffers::chunk_buffers;
 use crate::file_transfer::FileTransferService;
 use crate::keystore::Keystore;
 use crate::bandwidth::BandwidthController;
 use crate::manager::{ChunkInfo, FileManifest};
 use crate::multi_source_download::MultiSourceDownloadService;
 use crate::payment_checkpoint::PaymentCheckpointService;
+use crate::transfer_events::{
+    current_timestamp_ms, ErrorCategory, SourceSummary, SourceType, TransferCompletedEvent,
+    TransferEvent, TransferFailedEvent,
+};
 use aes_gcm::aead::Aead;
 use aes_gcm::{AeadCore, KeyInit};
 use serde::{Deserialize, Serialize};
@@ -1361,9 +1366,10 @@ impl WebRTCService {
         
         let has_file = stored_files
             .iter()
-            .any(|(hash, _)| hash == &request.file_hash);
+            .any(|file| file.entry.file_hash == request.file_hash);
 
         info!("📂 File {} found: {}", request.file_hash, has_file);
+        crate::demand_stats::global().record(&request.file_hash, Some(peer_id), has_file);
 
         if has_file {
             // Spawn file transfer as a separate task so the message handler
@@ -1393,6 +1399,14 @@ impl WebRTCService {
                 {
                     Ok(_) => {
                         info!("✅ File transfer completed successfully for {} to peer {}", request.file_hash, peer_id);
+                        crate::activity_feed::publish(
+                            crate::activity_feed::ActivityEvent::PeerServed {
+                                peer_id: peer_id.clone(),
+                                file_hash: request.file_hash.clone(),
+                                file_name: request.file_name.clone(),
+                                bytes: request.file_size,
+                            },
+                        );
                     }
                     Err(e) => {
                         error!("❌ File transfer failed for {} to peer {}: {}", request.file_hash, peer_id, e);
@@ -1729,12 +1743,11 @@ impl WebRTCService {
                             .unwrap_or_default();
                         let has_file = stored_files
                             .iter()
-                            .any(|(hash, _)| hash == &request.file_hash);
+                            .any(|file| file.entry.file_hash == request.file_hash);
 
                         if has_file {
-                            // Get file data
-                            if let Some(file_data) = file_transfer_service
-                                .get_file_data(&request.file_hash)
+                            if let Some(file_size) = file_transfer_service
+                                .stored_file_size(&request.file_hash)
                                 .await
                             {
                                 // Get metadata
@@ -1779,27 +1792,24 @@ impl WebRTCService {
                                     None
                                 };
 
-                                // Calculate chunks
-                                let mut chunks = Vec::new();
-                                let total_chunks =
-                                    ((file_data.len() as f64) / CHUNK_SIZE as f64).ceil() as u32;
-                                for chunk_index in 0..total_chunks {
-                                    let start = (chunk_index as usize) * CHUNK_SIZE;
-                                    let end = (start + CHUNK_SIZE).min(file_data.len());
-                                    let chunk_data = &file_data[start..end];
-                                    let chunk_hash = Self::calculate_chunk_checksum(chunk_data);
-                                    chunks.push(ChunkInfo {
-                                        index: chunk_index,
-                                        hash: chunk_hash.clone(),
-                                        size: (end - start),
-                                        encrypted_hash: chunk_hash,
-                                        encrypted_size: (end - start),
-                                    });
-                                }
+                                let chunks = match Self::chunk_infos(
+                                    file_transfer_service,
+                                    &request.file_hash,
+                                    file_size,
+                                )
+                                .await
+                                {
+                                    Ok(chunks) => chunks,
+                                    Err(e) => {
+                                        error!("Failed to build manifest response: {}", e);
+                                        return;
+                                    }
+                                };
                                 let manifest = FileManifest {
                                     merkle_root: request.file_hash.clone(),
                                     chunks,
                                     encrypted_key_bundle,
+                                    content_info: None,
                                 };
 
                                 let manifest_json = serde_json::to_string(&manifest).unwrap();
@@ -1933,12 +1943,12 @@ impl WebRTCService {
             sleep(Duration::from_millis(50)).await;
         }
         
-        // Get file data from local storage
-        let file_data = match file_transfer_service
-            .get_file_data(&request.file_hash)
+        // Chunks are read from storage one at a time as they are sent
+        let file_size = match file_transfer_service
+            .stored_file_size(&request.file_hash)
             .await
         {
-            Some(data) => data,
+            Some(size) => size,
             None => {
                 let _ = event_tx
                     .send(WebRTCEvent::TransferFailed {
@@ -1952,20 +1962,29 @@ impl WebRTCService {
         };
 
         // Calculate total chunks
-        let total_chunks = ((file_data.len() as f64) / CHUNK_SIZE as f64).ceil() as u32;
+        let total_chunks = file_size.div_ceil(CHUNK_SIZE as u64) as u32;
 
         info!(
             "Starting real file transfer of {} ({} bytes, {} chunks) to peer {}",
-            request.file_name,
-            file_data.len(),
-            total_chunks,
-            peer_id
+            request.file_name, file_size, total_chunks, peer_id
         );
 
+        // Wait for an upload slot; trusted peers are served first
+        let trusted_peers = crate::trusted_peers::global();
+        let _upload_slot = trusted_peers.acquire_upload_slot(peer_id).await;
+
+        // Trusted peers may be exempt from payment checkpoints
+        let no_checkpoint: Option<Arc<PaymentCheckpointService>> = None;
+        let payment_checkpoint = if trusted_peers.bypasses_payment(peer_id) {
+            info!("Serving trusted peer {} without payment checkpoints", peer_id);
+            &no_checkpoint
+        } else {
+            payment_checkpoint
+        };
+
         // Initialize payment checkpoint session if service available
         if let Some(checkpoint_service) = payment_checkpoint {
             let session_id = format!("{}_{}", request.file_hash, peer_id);
-            let file_size = file_data.len() as u64;
 
             checkpoint_service.init_session(
                 session_id.clone(),
@@ -1995,7 +2014,7 @@ impl WebRTCService {
                 let transfer = ActiveTransfer {
                     file_hash: request.file_hash.clone(),
                     file_name: request.file_name.clone(),
-                    file_size: file_data.len() as u64,
+                    file_size,
                     total_chunks,
                     chunks_sent: 0,
                     bytes_sent: 0,
@@ -2154,9 +2173,24 @@ impl WebRTCService {
                 info!("🔓 FLOW_CONTROL_PASSED: chunk {} for peer {}", chunk_index, peer_id);
             }
 
-            let start = (chunk_index as usize) * CHUNK_SIZE;
-            let end = (start + CHUNK_SIZE).min(file_data.len());
-            let chunk_data: Vec<u8> = file_data[start..end].to_vec();
+            let chunk_data = match file_transfer_service
+                .read_file_chunk(&request.file_hash, chunk_index as u64, CHUNK_SIZE)
+                .await
+                .and_then(|chunk| chunk.ok_or_else(|| "File data not available".to_string()))
+            {
+                Ok(data) => data,
+                Err(e) => {
+                    let error = format!("Failed to read chunk {}: {}", chunk_index, e);
+                    let _ = event_tx
+                        .send(WebRTCEvent::TransferFailed {
+                            peer_id: peer_id.to_string(),
+                            file_hash: request.file_hash.clone(),
+                            error: error.clone(),
+                        })
+                        .await;
+                    return Err(error);
+                }
+            };
 
             let (final_chunk_data, encrypted_key_bundle) =
                 if let Some(ref recipient_key) = request.recipient_public_key {
@@ -2229,11 +2263,16 @@ impl WebRTCService {
                     .await;
                 return Err(format!("Transfer aborted: {}", e));
             }
+            file_transfer_service.record_served(
+                &request.file_hash,
+                peer_id,
+                chunk.data.len() as u64,
+            );
 
             // Update payment checkpoint progress after sending chunk
             if let Some(checkpoint_service) = payment_checkpoint {
                 let session_id = format!("{}_{}", request.file_hash, peer_id);
-                let bytes_transferred = ((chunk_index as u64 + 1) * CHUNK_SIZE as u64).min(file_data.len() as u64);
+                let bytes_transferred = ((chunk_index as u64 + 1) * CHUNK_SIZE as u64).min(file_size);
 
                 checkpoint_service
                     .update_progress(&session_id, bytes_transferred)
@@ -2316,7 +2355,7 @@ impl WebRTCService {
             if let Some(connection) = conns.get_mut(peer_id) {
                 if let Some(transfer) = connection.active_transfers.get_mut(&request.file_hash) {
                     transfer.chunks_sent = total_chunks;
-                    transfer.bytes_sent = file_data.len() as u64;
+                    transfer.bytes_sent = file_size;
                 }
             }
         }
@@ -2519,6 +2558,92 @@ impl WebRTCService {
     // Compute final size without concatenating into a giant Vec<u8>.
     let file_size: usize = sorted_chunks.iter().map(|c| c.data.len()).sum();
 
+    let output_path = match Self::write_assembled_file(
+        file_hash,
+        &file_name,
+        file_size as u64,
+        &sorted_chunks,
+        app_handle,
+    )
+    .await
+    {
+        Ok(output_path) => output_path,
+        Err((error_category, error)) => {
+            error!("{}", error);
+            crate::event_bus::global().publish_transfer(&TransferEvent::Failed(
+                TransferFailedEvent {
+                    transfer_id: crate::file_transfer::new_transfer_id(),
+                    file_hash: file_hash.to_string(),
+                    failed_at: current_timestamp_ms(),
+                    error,
+                    error_category,
+                    downloaded_bytes: file_size as u64,
+                    total_bytes: file_size as u64,
+                    retry_possible: true,
+                },
+            ));
+            return;
+        }
+    };
+
+    // NOTE: We do NOT call store_file_data here because:
+    // 1. That function is for uploading/seeding files, not downloads
+    // 2. It creates hash-named files + .meta files in storage
+    // 3. The frontend handles saving the file with proper name via webrtc_download_complete event
+
+    // Emit event to frontend with final output path (no huge byte array over IPC).
+    if let Some(app_handle) = app_handle {
+        if let Err(e) = app_handle.emit("webrtc_download_complete", serde_json::json!({
+            "fileHash": file_hash,
+            "fileName": file_name,
+            "fileSize": file_size,
+            "outputPath": output_path.to_string_lossy().to_string(),
+        })) {
+            error!("Failed to emit webrtc_download_complete event: {}", e);
+        }
+    }
+
+    // Reported on the bus too, so the transfer log and activity feed see
+    // WebRTC downloads like any other
+    crate::event_bus::global().publish_transfer(&TransferEvent::Completed(TransferCompletedEvent {
+        transfer_id: crate::file_transfer::new_transfer_id(),
+        file_hash: file_hash.to_string(),
+        file_name: file_name.clone(),
+        file_size: file_size as u64,
+        output_path: output_path.to_string_lossy().to_string(),
+        completed_at: current_timestamp_ms(),
+        duration_seconds: 0,
+        average_speed_bps: 0.0,
+        total_chunks: sorted_chunks.len() as u32,
+        sources_used: vec![SourceSummary {
+            source_id: peer_id.to_string(),
+            source_type: SourceType::WebRtc,
+            chunks_provided: sorted_chunks.len() as u32,
+            bytes_provided: file_size as u64,
+            average_speed_bps: 0.0,
+            connection_duration_seconds: 0,
+        }],
+    }));
+
+    // Mapping cleanup happens via take_requested_download_output_path().
+
+    let _ = event_tx
+        .send(WebRTCEvent::TransferCompleted {
+            peer_id: peer_id.to_string(),
+            file_hash: file_hash.to_string(),
+        })
+        .await;
+}
+
+    /// Write verified chunks to the requested output path, or the download
+    /// directory, through the staging area; returns where the file ended up
+    async fn write_assembled_file(
+    file_hash: &str,
+    file_name: &str,
+    file_size: u64,
+    sorted_chunks: &[&FileChunk],
+    app_handle: Option<&tauri::AppHandle>,
+    ) -> Result<std::path::PathBuf, (ErrorCategory, String)> {
     // Choose output path:
     // - If the caller (GUI / E2E API) requested a specific output_path, honor it.
     // - Otherwise, fall back to the configured download directory from settings.
@@ -2530,7 +2655,7 @@ impl WebRTCService {
     let output_path: std::path::PathBuf = if let Some(p) = requested_output_path {
         // If the requested path is an existing directory, write the file inside it.
         if p.exists() && p.is_dir() {
-            p.join(&file_name)
+            p.join(file_name)
         } else {
             p
         }
@@ -2539,18 +2664,22 @@ impl WebRTCService {
         let storage_path = match crate::download_paths::get_download_directory_opt(app_handle) {
             Ok(p) => p,
             Err(e) => {
-                error!("Failed to resolve download directory: {}", e);
-                return;
+                return Err((
+                    ErrorCategory::Filesystem,
+                    format!("Failed to resolve download directory: {}", e),
+                ));
             }
         };
 
         // Ensure directory exists
         if let Err(e) = crate::download_paths::ensure_directory_exists(&storage_path).await {
-            error!("Failed to ensure download directory exists ({}): {}", storage_path, e);
-            return;
+            return Err((
+                ErrorCategory::Filesystem,
+                format!("Failed to ensure download directory exists ({}): {}", storage_path, e),
+            ));
         }
 
-        std::path::Path::new(&storage_path).join(&file_name)
+        std::path::Path::new(&storage_path).join(file_name)
     };
 
     // High-signal diagnostic: shows whether we honored a requested output path or fell back.
@@ -2564,59 +2693,95 @@ impl WebRTCService {
     if let Some(parent) = output_path.parent() {
         let parent_str = parent.to_string_lossy().to_string();
         if let Err(e) = crate::download_paths::ensure_directory_exists(&parent_str).await {
-            error!("Failed to ensure output directory exists ({}): {}", parent_str, e);
-            return;
+            return Err((
+                ErrorCategory::Filesystem,
+                format!("Failed to ensure output directory exists ({}): {}", parent_str, e),
+            ));
         }
     }
 
-    // Stream chunks to disk in order (avoid IPC + JSON serialization of raw bytes).
+    // Stream chunks in order into the staging area (avoid IPC + JSON serialization of raw
+    // bytes); the file only appears at output_path once it has been verified.
     use tokio::io::AsyncWriteExt;
-    let file = match tokio::fs::File::create(&output_path).await {
+    let staged = match crate::download_staging::global().stage(file_hash, file_size) {
+        Ok(staged) => staged,
+        Err(e) => {
+            return Err((
+                ErrorCategory::Filesystem,
+                format!("Failed to stage download {}: {}", file_hash, e),
+            ));
+        }
+    };
+    let file = match tokio::fs::File::create(staged.path()).await {
         Ok(f) => f,
         Err(e) => {
-            error!("Failed to create output file {:?}: {}", output_path, e);
-            return;
+            return Err((
+                ErrorCategory::Filesystem,
+                format!("Failed to create staged file {:?}: {}", staged.path(), e),
+            ));
         }
     };
     let mut writer = tokio::io::BufWriter::with_capacity(1024 * 1024, file); // 1MB buffer
-    for chunk in &sorted_chunks {
+    for chunk in sorted_chunks {
         if let Err(e) = writer.write_all(&chunk.data).await {
-            error!("Failed to write chunk {} to {:?}: {}", chunk.chunk_index, output_path, e);
-            return;
+            return Err((
+                ErrorCategory::Filesystem,
+                format!("Failed to write chunk {} to {:?}: {}", chunk.chunk_index, staged.path(), e),
+            ));
         }
     }
     if let Err(e) = writer.flush().await {
-        error!("Failed to flush output file {:?}: {}", output_path, e);
-        return;
+        return Err((
+            ErrorCategory::Filesystem,
+            format!("Failed to flush staged file {:?}: {}", staged.path(), e),
+        ));
     }
+    drop(writer);
 
-    // NOTE: We do NOT call store_file_data here because:
-    // 1. That function is for uploading/seeding files, not downloads
-    // 2. It creates hash-named files + .meta files in storage
-    // 3. The frontend handles saving the file with proper name via webrtc_download_complete event
-
-    // Emit event to frontend with final output path (no huge byte array over IPC).
-    if let Some(app_handle) = app_handle {
-        if let Err(e) = app_handle.emit("webrtc_download_complete", serde_json::json!({
-            "fileHash": file_hash,
-            "fileName": file_name,
-            "fileSize": file_size,
-            "outputPath": output_path.to_string_lossy().to_string(),
-        })) {
-            error!("Failed to emit webrtc_download_complete event: {}", e);
-        }
+    // The file is requested by the merkle root it was published under
+    let verification = crate::download_staging::Verification {
+        expected_size: Some(file_size),
+        published_id: Some(file_hash.to_string()),
+        ..Default::default()
+    };
+    if let Err(e) = staged.commit(&output_path, &verification).await {
+        return Err((
+            ErrorCategory::Verification,
+            format!("Failed to finalize download {}: {}", file_hash, e),
+        ));
     }
 
-    // Mapping cleanup happens via take_requested_download_output_path().
-
-    let _ = event_tx
-        .send(WebRTCEvent::TransferCompleted {
-            peer_id: peer_id.to_string(),
-            file_hash: file_hash.to_string(),
-        })
-        .await;
+    Ok(output_path)
 }
 
+    /// Manifest entries for a stored file, hashing one chunk at a time
+    async fn chunk_infos(
+        file_transfer_service: &Arc<FileTransferService>,
+        file_hash: &str,
+        file_size: u64,
+    ) -> Result<Vec<ChunkInfo>, String> {
+        let total_chunks = file_size.div_ceil(CHUNK_SIZE as u64) as u32;
+        let mut chunks = Vec::with_capacity(total_chunks as usize);
+        let mut data = chunk_buffers().take();
+        for index in 0..total_chunks {
+            let stored = file_transfer_service
+                .read_file_chunk_into(file_hash, index as u64, CHUNK_SIZE, &mut data)
+                .await?;
+            if !stored {
+                return Err(format!("Chunk {} of {} is not stored", index, file_hash));
+            }
+            let hash = Self::calculate_chunk_checksum(&data);
+            chunks.push(ChunkInfo {
+                index,
+                hash: hash.clone(),
+                size: data.len(),
+                encrypted_hash: hash,
+                encrypted_size: data.len(),
+            });
+        }
+        Ok(chunks)
+    }
+
     fn calculate_chunk_checksum(data: &[u8]) -> String {
         let mut hasher = Sha256::default();
         hasher.update(data);
@@ -0,0 +1,100 @@
+// blob_store_concurrency_test.rs
+// Many tasks storing, reading and changing different blobs at once
+//
+// - Every task owns a few blobs: it writes them, reads them back whole and
+//   in ranges, pins and unpins them and rewrites one
+// - Listing tasks walk the index the whole time
+// - Afterwards every blob must be intact, indexed once and survive reopening
+
+use chiral_network::blob_store::BlobStore;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::tempdir;
+
+const WRITERS: usize = 24;
+const BLOBS_PER_WRITER: usize = 4;
+const LISTERS: usize = 4;
+const BLOB_LEN: usize = 64 * 1024;
+
+fn key(writer: usize, blob: usize) -> String {
+    format!("stress{:02}x{}", writer, blob)
+}
+
+fn contents(writer: usize, blob: usize, version: u8) -> Vec<u8> {
+    (0..BLOB_LEN)
+        .map(|i| (i as u8) ^ (writer as u8) ^ (blob as u8).wrapping_mul(7) ^ version)
+        .collect()
+}
+
+async fn writer(store: Arc<BlobStore>, writer: usize) {
+    for blob in 0..BLOBS_PER_WRITER {
+        let key = key(writer, blob);
+        let data = contents(writer, blob, 0);
+        store.put(&key, &data).await.unwrap();
+        assert_eq!(store.get(&key).await.unwrap().as_deref(), Some(&data[..]));
+        for offset in [0, 1000, BLOB_LEN - 10] {
+            let range = store.read_range(&key, offset as u64, 10).await.unwrap();
+            assert_eq!(range.as_deref(), Some(&data[offset..offset + 10]));
+        }
+        store.set_pinned(&key, true).await.unwrap();
+        tokio::task::yield_now().await;
+        store.set_pinned(&key, blob % 2 == 0).await.unwrap();
+    }
+    // Rewriting keeps the pin
+    let rewritten = contents(writer, 0, 1);
+    store.put(&key(writer, 0), &rewritten).await.unwrap();
+}
+
+async fn lister(store: Arc<BlobStore>, done: Arc<AtomicBool>) {
+    let most = WRITERS * BLOBS_PER_WRITER;
+    while !done.load(Ordering::Relaxed) {
+        assert!(store.list().await.len() <= most);
+        assert!(store.total_bytes().await <= (most * BLOB_LEN) as u64);
+        tokio::task::yield_now().await;
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn concurrent_writers_and_readers_keep_the_index_consistent() {
+    let dir = tempdir().unwrap();
+    let store = Arc::new(BlobStore::open(dir.path()).await.unwrap());
+    let done = Arc::new(AtomicBool::new(false));
+
+    let listers: Vec<_> = (0..LISTERS)
+        .map(|_| tokio::spawn(lister(store.clone(), done.clone())))
+        .collect();
+    let writers: Vec<_> = (0..WRITERS)
+        .map(|n| tokio::spawn(writer(store.clone(), n)))
+        .collect();
+
+    let finished = async {
+        for task in writers {
+            task.await.unwrap();
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(60), finished)
+        .await
+        .expect("writers finished");
+    done.store(true, Ordering::Relaxed);
+    for task in listers {
+        task.await.unwrap();
+    }
+
+    let blobs = store.list().await;
+    assert_eq!(blobs.len(), WRITERS * BLOBS_PER_WRITER);
+    for writer in 0..WRITERS {
+        for blob in 0..BLOBS_PER_WRITER {
+            let version = if blob == 0 { 1 } else { 0 };
+            let expected = contents(writer, blob, version);
+            let key = key(writer, blob);
+            assert_eq!(store.get(&key).await.unwrap(), Some(expected));
+            assert_eq!(store.entry(&key).await.unwrap().pinned, blob % 2 == 0);
+        }
+    }
+
+    drop(store);
+    let reopened = BlobStore::open(dir.path()).await.unwrap();
+    assert_eq!(reopened.list().await.len(), WRITERS * BLOBS_PER_WRITER);
+    assert!(reopened.entry(&key(0, 0)).await.unwrap().pinned);
+}
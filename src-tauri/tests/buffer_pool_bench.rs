@@ -0,0 +1,142 @@
+// buffer_pool_bench.rs
+// Chunk reads with a fresh vector each time against pooled buffers
+//
+// Several tasks read every chunk of the same stored blobs at once, the way
+// a node serving one popular file to many peers does. Run with
+//
+//     cargo test --release --test buffer_pool_bench -- --ignored --nocapture
+
+use chiral_network::blob_store::BlobStore;
+use chiral_network::file_transfer::buffers::{chunk_buffers, BufferPool};
+use chiral_network::file_transfer::chunking::CHUNK_SIZE;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tempfile::tempdir;
+
+const BLOBS: usize = 4;
+const CHUNKS_PER_BLOB: usize = 32;
+const TASKS: usize = 16;
+const ROUNDS: usize = 4;
+
+async fn stored_blobs(blobs: &BlobStore) -> Vec<String> {
+    let mut hashes = Vec::new();
+    for blob in 0..BLOBS {
+        let data: Vec<u8> = (0..CHUNK_SIZE * CHUNKS_PER_BLOB)
+            .map(|i| (i * 31 + blob) as u8)
+            .collect();
+        let hash = blake3::hash(&data).to_hex().to_string();
+        blobs.put(&hash, &data).await.unwrap();
+        hashes.push(hash);
+    }
+    hashes
+}
+
+async fn read_fresh(blobs: Arc<BlobStore>, hashes: Arc<Vec<String>>) -> u64 {
+    let mut checksum = 0u64;
+    for _ in 0..ROUNDS {
+        for hash in hashes.iter() {
+            for chunk in 0..CHUNKS_PER_BLOB {
+                let offset = (chunk * CHUNK_SIZE) as u64;
+                let data = blobs.read_range(hash, offset, CHUNK_SIZE).await;
+                checksum += data.unwrap().unwrap()[0] as u64;
+            }
+        }
+    }
+    checksum
+}
+
+async fn read_pooled(blobs: Arc<BlobStore>, hashes: Arc<Vec<String>>) -> u64 {
+    let mut checksum = 0u64;
+    for _ in 0..ROUNDS {
+        for hash in hashes.iter() {
+            for chunk in 0..CHUNKS_PER_BLOB {
+                let offset = (chunk * CHUNK_SIZE) as u64;
+                let mut buf = chunk_buffers().take();
+                let read = blobs.read_range_into(hash, offset, CHUNK_SIZE, &mut buf);
+                assert!(read.await.unwrap());
+                checksum += buf[0] as u64;
+            }
+        }
+    }
+    checksum
+}
+
+async fn timed<F, Fut>(
+    read: F,
+    blobs: &Arc<BlobStore>,
+    hashes: &Arc<Vec<String>>,
+) -> (Duration, u64)
+where
+    F: Fn(Arc<BlobStore>, Arc<Vec<String>>) -> Fut,
+    Fut: std::future::Future<Output = u64> + Send + 'static,
+{
+    let started = Instant::now();
+    let tasks: Vec<_> = (0..TASKS)
+        .map(|_| tokio::spawn(read(blobs.clone(), hashes.clone())))
+        .collect();
+    let mut checksum = 0;
+    for task in tasks {
+        checksum += task.await.unwrap();
+    }
+    (started.elapsed(), checksum)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+#[ignore = "benchmark; run with --release --ignored --nocapture"]
+async fn pooled_chunk_reads_against_fresh_vectors() {
+    let dir = tempdir().unwrap();
+    let blobs = Arc::new(BlobStore::open(dir.path()).await.unwrap());
+    let hashes = Arc::new(stored_blobs(&blobs).await);
+    let chunks = TASKS * ROUNDS * BLOBS * CHUNKS_PER_BLOB;
+    let megabytes = (chunks * CHUNK_SIZE) as f64 / (1024.0 * 1024.0);
+
+    // Warm the page cache so both runs read from memory
+    timed(read_fresh, &blobs, &hashes).await;
+
+    let (fresh, fresh_sum) = timed(read_fresh, &blobs, &hashes).await;
+    let (pooled, pooled_sum) = timed(read_pooled, &blobs, &hashes).await;
+    assert_eq!(fresh_sum, pooled_sum);
+
+    for (label, elapsed) in [("fresh vectors", fresh), ("pooled buffers", pooled)] {
+        println!(
+            "{:<15} {:>8.1} ms  {:>8.1} MiB/s",
+            label,
+            elapsed.as_secs_f64() * 1000.0,
+            megabytes / elapsed.as_secs_f64()
+        );
+    }
+    let stats = chunk_buffers().stats();
+    println!(
+        "{} chunk reads, {} buffers allocated, {} reused",
+        chunks, stats.allocated, stats.reused
+    );
+    assert!(stats.allocated <= TASKS as u64 + 1);
+}
+
+#[test]
+#[ignore = "benchmark; run with --release --ignored --nocapture"]
+fn take_and_return_against_allocation() {
+    const ITERATIONS: usize = 100_000;
+    let pool = BufferPool::new(CHUNK_SIZE, 4);
+
+    let started = Instant::now();
+    for i in 0..ITERATIONS {
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        buf[i % CHUNK_SIZE] = 1;
+        std::hint::black_box(&buf);
+    }
+    let fresh = started.elapsed();
+
+    let started = Instant::now();
+    for i in 0..ITERATIONS {
+        let mut buf = pool.take();
+        buf.resize(CHUNK_SIZE, 0);
+        buf[i % CHUNK_SIZE] = 1;
+        std::hint::black_box(&buf);
+    }
+    let pooled = started.elapsed();
+
+    println!("fresh vectors  {:?} per chunk", fresh / ITERATIONS as u32);
+    println!("pooled buffers {:?} per chunk", pooled / ITERATIONS as u32);
+    assert_eq!(pool.stats().allocated, 1);
+}
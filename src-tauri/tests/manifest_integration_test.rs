@@ -74,6 +74,7 @@ fn test_file_metadata_with_manifest_serialization() {
             },
         ],
         encrypted_key_bundle: None,
+        content_info: None,
     };
 
     let manifest_json = serde_json::to_string(&manifest).unwrap();
@@ -152,6 +153,7 @@ fn test_chunk_hash_extraction_workflow() {
             },
         ],
         encrypted_key_bundle: None,
+        content_info: None,
     };
 
     // Store in metadata (upload to DHT)
@@ -285,6 +287,7 @@ fn test_manifest_with_variable_chunk_sizes() {
             },
         ],
         encrypted_key_bundle: None,
+        content_info: None,
     };
 
     let manifest_json = serde_json::to_string(&manifest).unwrap();
@@ -333,6 +336,7 @@ fn test_manifest_json_round_trip_integrity() {
         merkle_root: "integrity_test_root".to_string(),
         chunks,
         encrypted_key_bundle: None,
+        content_info: None,
     };
 
     // JSON round-trip
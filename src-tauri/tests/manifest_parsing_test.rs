@@ -34,6 +34,7 @@ fn create_test_manifest() -> FileManifest {
         merkle_root: "test_merkle_root".to_string(),
         chunks,
         encrypted_key_bundle: None,
+        content_info: None,
     }
 }
 
@@ -109,6 +110,7 @@ fn test_manifest_parsing_with_missing_chunks() {
             },
         ],
         encrypted_key_bundle: None,
+        content_info: None,
     };
 
     let manifest_json = serde_json::to_string(&manifest).unwrap();
@@ -0,0 +1,421 @@
+// swarm_simulation_test.rs
+// Multi-node scenarios on the in-process simulation harness
+//
+// - Swarm download: leechers exchange chunk-have announcements through the
+//   real `ChunkSwarm` and fetch from each other while the only seeder is slow,
+//   links drop messages and some leechers crash and restart
+// - Relay failover: real libp2p relays on the in-memory transport; a client
+//   picks one through the relay registry and `RelaySelector`, gets denied,
+//   and its reservation bookkeeping and scores move it to the backup relay
+
+use chiral_network::dht::chunk_have::{ChunkSwarm, HaveAnnouncement};
+use chiral_network::dht::reservations::{ReservationManager, ReservationStatus};
+use chiral_network::relay_registry::RelayRegistry;
+use chiral_network::relay_selector::BestHealth;
+use chiral_network::simulation::{
+    Fault, LinkProfile, NodeBehavior, NodeId, SimContext, Simulation,
+};
+use futures::StreamExt;
+use libp2p::core::muxing::StreamMuxerBox;
+use libp2p::core::transport::{Boxed, ListenerId, MemoryTransport};
+use libp2p::core::upgrade::Version;
+use libp2p::identity::Keypair;
+use libp2p::swarm::SwarmEvent;
+use libp2p::{noise, relay, yamux, Multiaddr, PeerId, Swarm, SwarmBuilder, Transport};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const FILE: &str = "sim-file";
+const CHUNKS: u32 = 32;
+const MAX_IN_FLIGHT: usize = 4;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(2);
+const ANNOUNCE_TIMER: u64 = 0;
+const FETCH_TIMER: u64 = 1;
+
+enum SwarmMsg {
+    HaveRequest(HaveAnnouncement),
+    HaveResponse(HaveAnnouncement),
+    Fetch(u32),
+    Chunk(u32, bool),
+}
+
+/// Stable peer IDs so runs are reproducible
+fn peer_ids(count: usize) -> Vec<PeerId> {
+    (0..count)
+        .map(|i| {
+            Keypair::ed25519_from_bytes([i as u8 + 1; 32])
+                .unwrap()
+                .public()
+                .to_peer_id()
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct SwarmOutcome {
+    completed_at: HashMap<NodeId, Duration>,
+    served: HashMap<NodeId, u32>,
+}
+
+struct SwarmNode {
+    peers: Arc<Vec<PeerId>>,
+    seeder: NodeId,
+    swarm: ChunkSwarm,
+    have: BTreeSet<u32>,
+    /// Chunk -> (holder asked, when)
+    in_flight: HashMap<u32, (NodeId, Duration)>,
+    attempts: HashMap<u32, usize>,
+    outcome: Arc<Mutex<SwarmOutcome>>,
+}
+
+impl SwarmNode {
+    fn node_of(&self, peer: &PeerId) -> Option<NodeId> {
+        self.peers.iter().position(|p| p == peer)
+    }
+
+    fn is_seeder(&self, ctx: &SimContext<'_, SwarmMsg>) -> bool {
+        ctx.node() == self.seeder
+    }
+
+    fn request_chunks(&mut self, ctx: &mut SimContext<'_, SwarmMsg>) {
+        let now = ctx.now();
+        self.in_flight
+            .retain(|_, (_, sent)| now.saturating_sub(*sent) < FETCH_TIMEOUT);
+        // Start at a different chunk on every node to spread the load
+        let offset = ctx.node() as u32 * 7;
+        for i in 0..CHUNKS {
+            if self.in_flight.len() >= MAX_IN_FLIGHT {
+                break;
+            }
+            let chunk = (offset + i) % CHUNKS;
+            if self.have.contains(&chunk) || self.in_flight.contains_key(&chunk) {
+                continue;
+            }
+            let holders = self.swarm.holders(FILE, chunk);
+            if holders.is_empty() {
+                continue;
+            }
+            // Rotate through holders on retries so a dead one is skipped
+            let attempt = self.attempts.get(&chunk).copied().unwrap_or(0);
+            let Some(holder) = self.node_of(&holders[attempt % holders.len()]) else {
+                continue;
+            };
+            *self.attempts.entry(chunk).or_default() += 1;
+            self.in_flight.insert(chunk, (holder, now));
+            ctx.send(holder, SwarmMsg::Fetch(chunk));
+        }
+    }
+}
+
+impl NodeBehavior<SwarmMsg> for SwarmNode {
+    fn on_start(&mut self, ctx: &mut SimContext<'_, SwarmMsg>) {
+        if self.is_seeder(ctx) {
+            return;
+        }
+        let have: Vec<u32> = self.have.iter().copied().collect();
+        let seeder = self.peers[self.seeder];
+        self.swarm.join(FILE, CHUNKS, &have, &[seeder]);
+        self.in_flight.clear();
+        ctx.set_timer(Duration::ZERO, ANNOUNCE_TIMER);
+        ctx.set_timer(Duration::from_millis(100), FETCH_TIMER);
+    }
+
+    fn on_message(&mut self, ctx: &mut SimContext<'_, SwarmMsg>, from: NodeId, message: SwarmMsg) {
+        let me = self.peers[ctx.node()];
+        let from_peer = self.peers[from];
+        match message {
+            SwarmMsg::HaveRequest(announcement) => {
+                let seeding = self.is_seeder(ctx);
+                self.swarm.record(from_peer, &announcement, &me);
+                let reply = self.swarm.announcement(FILE, &from_peer, seeding);
+                ctx.send(from, SwarmMsg::HaveResponse(reply));
+            }
+            SwarmMsg::HaveResponse(announcement) => {
+                if self.swarm.is_member(FILE) {
+                    self.swarm.record(from_peer, &announcement, &me);
+                }
+            }
+            SwarmMsg::Fetch(chunk) => {
+                let served = self.is_seeder(ctx) || self.swarm.has_local_chunk(FILE, chunk);
+                if served {
+                    let mut outcome = self.outcome.lock().unwrap();
+                    *outcome.served.entry(ctx.node()).or_default() += 1;
+                }
+                ctx.send(from, SwarmMsg::Chunk(chunk, served));
+            }
+            SwarmMsg::Chunk(chunk, ok) => {
+                self.in_flight.remove(&chunk);
+                if ok && self.have.insert(chunk) {
+                    self.swarm.add_local_chunk(FILE, chunk);
+                    if self.have.len() as u32 == CHUNKS {
+                        let mut outcome = self.outcome.lock().unwrap();
+                        outcome.completed_at.entry(ctx.node()).or_insert(ctx.now());
+                    }
+                }
+                self.request_chunks(ctx);
+            }
+        }
+    }
+
+    fn on_timer(&mut self, ctx: &mut SimContext<'_, SwarmMsg>, tag: u64) {
+        match tag {
+            ANNOUNCE_TIMER => {
+                let mut due = self.swarm.due_announcements();
+                due.sort_by_key(|(peer, _)| *peer);
+                for (peer, announcement) in due {
+                    if let Some(node) = self.node_of(&peer) {
+                        ctx.send(node, SwarmMsg::HaveRequest(announcement));
+                    }
+                }
+                ctx.set_timer(Duration::from_secs(1), ANNOUNCE_TIMER);
+            }
+            _ => {
+                self.request_chunks(ctx);
+                ctx.set_timer(Duration::from_millis(250), FETCH_TIMER);
+            }
+        }
+    }
+}
+
+fn run_swarm(seed: u64, leechers: usize) -> (SwarmOutcome, bool) {
+    let peers = Arc::new(peer_ids(leechers + 1));
+    let outcome = Arc::new(Mutex::new(SwarmOutcome::default()));
+    let link = LinkProfile::new(Duration::from_millis(30))
+        .with_jitter(Duration::from_millis(20))
+        .with_loss(0.02);
+    let mut sim = Simulation::new(seed).with_default_link(link);
+    for _ in 0..=leechers {
+        sim.add_node(SwarmNode {
+            peers: peers.clone(),
+            seeder: 0,
+            swarm: ChunkSwarm::new(),
+            have: BTreeSet::new(),
+            in_flight: HashMap::new(),
+            attempts: HashMap::new(),
+            outcome: outcome.clone(),
+        });
+    }
+    sim.schedule(Duration::ZERO, Fault::Slow(0, Duration::from_millis(400)));
+    sim.churn(
+        &[1, 2],
+        Duration::from_secs(2),
+        Duration::from_secs(20),
+        Duration::from_secs(6),
+        Duration::from_secs(2),
+    );
+
+    let done = sim.run_while(Duration::from_secs(300), |_| {
+        outcome.lock().unwrap().completed_at.len() == leechers
+    });
+    let outcome = std::mem::take(&mut *outcome.lock().unwrap());
+    (outcome, done)
+}
+
+#[test]
+fn swarm_download_completes_with_slow_seeder_loss_and_churn() {
+    let leechers = 6;
+    let (outcome, done) = run_swarm(42, leechers);
+    assert!(
+        done,
+        "only {} leechers finished",
+        outcome.completed_at.len()
+    );
+
+    // Leechers served each other, so the slow seeder sent far from every chunk
+    let from_seeder = outcome.served.get(&0).copied().unwrap_or(0);
+    let from_leechers: u32 = outcome
+        .served
+        .iter()
+        .filter(|(node, _)| **node != 0)
+        .map(|(_, served)| served)
+        .sum();
+    assert!(from_leechers > 0);
+    assert!(from_seeder < CHUNKS * leechers as u32);
+
+    // Same seed, same run
+    let (again, _) = run_swarm(42, leechers);
+    assert_eq!(again.completed_at, outcome.completed_at);
+    assert_eq!(again.served, outcome.served);
+}
+
+/// Relay server on the in-memory transport granting up to
+/// `max_reservations`, run in the background. Returns its peer ID and
+/// listen address.
+async fn spawn_relay(max_reservations: usize) -> (PeerId, Multiaddr) {
+    let mut relay = SwarmBuilder::with_new_identity()
+        .with_tokio()
+        .with_other_transport(memory_transport)
+        .unwrap()
+        .with_behaviour(|key| {
+            relay::Behaviour::new(
+                key.public().to_peer_id(),
+                relay::Config {
+                    max_reservations,
+                    ..Default::default()
+                },
+            )
+        })
+        .unwrap()
+        .with_swarm_config(|config| config.with_idle_connection_timeout(Duration::from_secs(30)))
+        .build();
+    relay.listen_on("/memory/0".parse().unwrap()).unwrap();
+    let address = loop {
+        if let SwarmEvent::NewListenAddr { address, .. } = relay.select_next_some().await {
+            break address;
+        }
+    };
+    // Reservations hand out the relay's external addresses
+    relay.add_external_address(address.clone());
+    let peer_id = *relay.local_peer_id();
+    tokio::spawn(async move {
+        loop {
+            relay.select_next_some().await;
+        }
+    });
+    (peer_id, address)
+}
+
+fn memory_transport(key: &Keypair) -> Result<Boxed<(PeerId, StreamMuxerBox)>, noise::Error> {
+    Ok(MemoryTransport::default()
+        .upgrade(Version::V1)
+        .authenticate(noise::Config::new(key)?)
+        .multiplex(yamux::Config::default())
+        .map(|(peer, muxer), _| (peer, StreamMuxerBox::new(muxer)))
+        .boxed())
+}
+
+fn relay_client() -> Swarm<relay::client::Behaviour> {
+    SwarmBuilder::with_new_identity()
+        .with_tokio()
+        .with_other_transport(memory_transport)
+        .unwrap()
+        .with_relay_client(noise::Config::new, yamux::Config::default)
+        .unwrap()
+        .with_behaviour(|_, relay_client| relay_client)
+        .unwrap()
+        .with_swarm_config(|config| config.with_idle_connection_timeout(Duration::from_secs(30)))
+        .build()
+}
+
+enum ReservationOutcome {
+    Accepted {
+        relay: PeerId,
+        renewal: bool,
+    },
+    Closed {
+        listener: ListenerId,
+        error: Option<String>,
+    },
+}
+
+async fn reservation_outcome(client: &mut Swarm<relay::client::Behaviour>) -> ReservationOutcome {
+    let outcome = async {
+        loop {
+            match client.select_next_some().await {
+                SwarmEvent::Behaviour(relay::client::Event::ReservationReqAccepted {
+                    relay_peer_id,
+                    renewal,
+                    ..
+                }) => {
+                    return ReservationOutcome::Accepted {
+                        relay: relay_peer_id,
+                        renewal,
+                    }
+                }
+                SwarmEvent::ListenerClosed {
+                    listener_id,
+                    reason,
+                    ..
+                } => {
+                    return ReservationOutcome::Closed {
+                        listener: listener_id,
+                        error: reason.err().map(|e| e.to_string()),
+                    }
+                }
+                _ => {}
+            }
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(10), outcome)
+        .await
+        .expect("no answer to the reservation request")
+}
+
+/// Pick a relay from the registry and ask it for a reservation, tracking the
+/// request the way the DHT swarm loop does
+async fn reserve_selected(
+    client: &mut Swarm<relay::client::Behaviour>,
+    registry: &RelayRegistry,
+    reservations: &ReservationManager,
+    now: u64,
+) -> (PeerId, ReservationOutcome) {
+    let selected = registry.select_relays(1, &BestHealth).await;
+    let relay = selected.first().expect("no relay selected");
+    let dial = RelayRegistry::dial_address(relay).unwrap();
+    let circuit: Multiaddr = format!("{}/p2p-circuit", dial).parse().unwrap();
+    let relay_peer: PeerId = relay.peer_id.parse().unwrap();
+    let listener = client.listen_on(circuit.clone()).unwrap();
+    registry
+        .record_reservation(&reservations.requested(relay_peer, &circuit, listener, now))
+        .await;
+    (relay_peer, reservation_outcome(client).await)
+}
+
+#[tokio::test]
+async fn client_fails_over_to_backup_relay() {
+    // The better scored relay is full and denies every reservation
+    let (primary, primary_addr) = spawn_relay(0).await;
+    let (backup, backup_addr) = spawn_relay(16).await;
+    let now = 1_000;
+    let registry = RelayRegistry::new();
+    for (relay, address, score) in [(primary, &primary_addr, 4.0), (backup, &backup_addr, 3.0)] {
+        let peer_id = relay.to_string();
+        registry
+            .record_event(&peer_id, "RelayReservationAccepted", score, now)
+            .await;
+        registry
+            .note_addresses(&peer_id, &[address.to_string()], now)
+            .await;
+    }
+    let reservations = ReservationManager::new();
+    let mut client = relay_client();
+
+    let (asked, outcome) = reserve_selected(&mut client, &registry, &reservations, now).await;
+    assert_eq!(asked, primary);
+    let ReservationOutcome::Closed { listener, error } = outcome else {
+        panic!("a full relay granted a reservation");
+    };
+    let failed = error.is_some();
+    let state = reservations.listener_closed(listener, error, now).unwrap();
+    assert_eq!(state.status, ReservationStatus::Failed);
+    assert!(state.retry_at.is_some());
+    registry.record_reservation(&state).await;
+    if failed {
+        registry
+            .record_usage(&primary.to_string(), 0, 0, 1, now)
+            .await;
+    }
+
+    // The failure costs the primary its lead
+    let (asked, outcome) = reserve_selected(&mut client, &registry, &reservations, now).await;
+    assert_eq!(asked, backup);
+    let ReservationOutcome::Accepted { relay, renewal } = outcome else {
+        panic!("the backup relay denied the reservation");
+    };
+    assert_eq!(relay, backup);
+    let (state, superseded) = reservations.accepted(relay, renewal, now);
+    assert_eq!(superseded, None);
+    registry.record_reservation(&state).await;
+
+    let snapshot = reservations.snapshot();
+    assert_eq!(snapshot[0].relay_peer_id, backup.to_string());
+    assert_eq!(snapshot[0].status, ReservationStatus::Active);
+    assert_eq!(snapshot[1].relay_peer_id, primary.to_string());
+    assert_eq!(snapshot[1].status, ReservationStatus::Failed);
+    let recorded = registry.get(&backup.to_string()).await.unwrap();
+    assert_eq!(
+        recorded.reservation.map(|r| r.status),
+        Some(ReservationStatus::Active)
+    );
+}
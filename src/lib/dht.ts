@@ -28,6 +28,8 @@ export interface DhtConfig {
   cacheSizeMb?: number;
   enableAutorelay?: boolean;
   preferredRelays?: string[];
+  // How known relays are picked when no preferred relays are given
+  relaySelection?: "best_health" | "lowest_latency" | "random_weighted" | "region_affinity";
   enableRelayServer?: boolean;
   enableUpnp?: boolean;
   relayServerAlias?: string; // Public alias for relay server (appears in logs and bootstrap)
@@ -189,6 +191,9 @@ export class DhtService {
       if (config?.preferredRelays && config.preferredRelays.length > 0) {
         payload.preferredRelays = config.preferredRelays;
       }
+      if (config?.relaySelection) {
+        payload.relaySelection = config.relaySelection;
+      }
       if (typeof config?.enableRelayServer === "boolean") {
         payload.enableRelayServer = config.enableRelayServer;
       }
@@ -6,11 +6,11 @@ import { showToast } from '$lib/toast';
 type AttemptStatus = 'retrying' | 'success' | 'failed';
 
 type DownloadAttemptPayload = {
-  file_hash: string;
+  fileHash: string;
   attempt: number;
-  max_attempts: number;
+  maxAttempts: number;
   status: AttemptStatus;
-  duration_ms: number;
+  durationMs: number;
   timestamp: number;
 };
 
@@ -29,7 +29,7 @@ function summarizeHash(hash: string): string {
 }
 
 function handleAttempt(payload: DownloadAttemptPayload) {
-  const formattedHash = summarizeHash(payload.file_hash);
+  const formattedHash = summarizeHash(payload.fileHash);
 
   switch (payload.status) {
     case 'retrying':
@@ -38,7 +38,7 @@ function handleAttempt(payload: DownloadAttemptPayload) {
           values: {
             hash: formattedHash,
             attempt: payload.attempt,
-            max: payload.max_attempts
+            max: payload.maxAttempts
           }
         }),
         'info'
@@ -51,7 +51,7 @@ function handleAttempt(payload: DownloadAttemptPayload) {
             values: {
               hash: formattedHash,
               retries: payload.attempt - 1,
-              duration: Math.round(payload.duration_ms)
+              duration: Math.round(payload.durationMs)
             }
           }),
           'success'
@@ -79,7 +79,7 @@ export async function initDownloadTelemetry() {
   if (unlisten) return;
 
   try {
-    unlisten = await listen<DownloadAttemptPayload>('download_attempt', (event) => {
+    unlisten = await listen<DownloadAttemptPayload>('file_transfer:download_attempt', (event) => {
       const payload = event.payload;
       if (!payload) return;
       handleAttempt(payload);
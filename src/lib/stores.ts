@@ -695,6 +695,10 @@ export interface AppSettings {
   maxLogSizeMB: number; // Maximum size of a single log file in MB
   pricePerMb: number; // Price per MB in Chiral (e.g., 0.001)
   customBootstrapNodes: string[]; // Custom bootstrap nodes for DHT (leave empty to use defaults)
+  relayPruneIntervalMins: number; // Minutes between passes pruning stale relays
+  relayMaxAgeDays: number; // Relays unheard of for this many days are pruned
+  downloadAttemptRetention: { maxEntries: number; maxAgeSecs: number | null }; // Download attempt history kept
+  maxConcurrentTransfers: number; // Downloads run at once, the rest are queued
   selectedProtocol: "WebRTC" | "BitTorrent" | "ED2K" | "FTP"; // Protocol selected for file uploads
 }
 
@@ -751,6 +755,10 @@ export const settings = writable<AppSettings>({
   maxLogSizeMB: 10, // 10 MB per log file by default
   pricePerMb: 0.001, // Default price: 0.001, until ability to set pricePerMb is there, then change to 0.001 Chiral per MB
   customBootstrapNodes: [], // Empty by default - use hardcoded bootstrap nodes
+  relayPruneIntervalMins: 60, // Prune stale relays hourly
+  relayMaxAgeDays: 30, // Forget relays unheard of for a month
+  downloadAttemptRetention: { maxEntries: 20, maxAgeSecs: null }, // Last 20 attempts, no age limit
+  maxConcurrentTransfers: 3, // Three downloads at a time
   selectedProtocol: "WebRTC", // Default to WebRTC
 });
 